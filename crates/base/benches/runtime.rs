@@ -0,0 +1,402 @@
+use base::worker_ctx::WorkerContext;
+use bytes::Bytes;
+use criterion::measurement::{Measurement, ValueFormatter};
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use deno_core::futures::future::join_all;
+use hyper::{Body, Method, Request};
+use sb_worker_context::essentials::{
+    BackpressureOpts, EdgeContextInitOpts, EdgeContextOpts, EdgeUserRuntimeOpts, OutboundOpts,
+};
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+use tokio::runtime::Runtime;
+
+// Counts the allocations made from rust by every thread of the bench, the
+// workers' included. The ones v8 makes for its heap aren't seen.
+struct CountingAllocator;
+
+static ALLOCATIONS: AtomicU64 = AtomicU64::new(0);
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        System.alloc(layout)
+    }
+
+    unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        System.alloc_zeroed(layout)
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        System.realloc(ptr, layout, new_size)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+}
+
+#[global_allocator]
+static GLOBAL: CountingAllocator = CountingAllocator;
+
+// Measures the allocations made during the samples instead of their time.
+struct Allocations;
+
+impl Measurement for Allocations {
+    type Intermediate = u64;
+    type Value = u64;
+
+    fn start(&self) -> u64 {
+        ALLOCATIONS.load(Ordering::Relaxed)
+    }
+
+    fn end(&self, start: u64) -> u64 {
+        ALLOCATIONS.load(Ordering::Relaxed) - start
+    }
+
+    fn add(&self, v1: &u64, v2: &u64) -> u64 {
+        v1 + v2
+    }
+
+    fn zero(&self) -> u64 {
+        0
+    }
+
+    fn to_f64(&self, value: &u64) -> f64 {
+        *value as f64
+    }
+
+    fn formatter(&self) -> &dyn ValueFormatter {
+        &AllocationsFormatter
+    }
+}
+
+struct AllocationsFormatter;
+
+impl ValueFormatter for AllocationsFormatter {
+    fn scale_values(&self, _typical_value: f64, _values: &mut [f64]) -> &'static str {
+        "allocs"
+    }
+
+    fn scale_throughputs(
+        &self,
+        _typical_value: f64,
+        throughput: &Throughput,
+        values: &mut [f64],
+    ) -> &'static str {
+        match throughput {
+            Throughput::Elements(requests) => {
+                for value in values {
+                    *value /= *requests as f64;
+                }
+                "allocs/req"
+            }
+            _ => "allocs",
+        }
+    }
+
+    fn scale_for_machines(&self, _values: &mut [f64]) -> &'static str {
+        "allocs"
+    }
+}
+
+fn service_path(name: &str) -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("benches/services")
+        .join(name)
+}
+
+// A service made of `modules` modules importing each other as a binary tree,
+// each one exporting a function calling the ones of the modules it imports.
+// Written to the target directory, the same every time.
+fn module_graph_service(modules: usize) -> PathBuf {
+    let dir = Path::new(env!("CARGO_TARGET_TMPDIR")).join(format!("module_graph_{}", modules));
+    fs::create_dir_all(&dir).unwrap();
+    for i in 0..modules {
+        let children: Vec<usize> = [2 * i + 1, 2 * i + 2]
+            .into_iter()
+            .filter(|child| *child < modules)
+            .collect();
+        let mut source = String::new();
+        for child in &children {
+            source.push_str(&format!(
+                "import {{ f{child} }} from \"./mod_{child}.ts\";\n"
+            ));
+        }
+        let calls: String = children
+            .iter()
+            .map(|child| format!(" + f{child}(x)"))
+            .collect();
+        source.push_str(&format!(
+            "\nexport function f{i}(x: number): number {{\n  return x{calls};\n}}\n"
+        ));
+        fs::write(dir.join(format!("mod_{}.ts", i)), source).unwrap();
+    }
+    fs::write(
+        dir.join("index.ts"),
+        "import { f0 } from \"./mod_0.ts\";\n\nDeno.serve(() => new Response(String(f0(1))));\n",
+    )
+    .unwrap();
+    dir
+}
+
+fn worker_opts(service_path: PathBuf) -> EdgeContextInitOpts {
+    EdgeContextInitOpts {
+        service_path,
+        no_module_cache: false,
+        offline: false,
+        import_map_path: None,
+        auth_tokens: None,
+        env_vars: HashMap::new(),
+        fetch_interceptor: None,
+        outbound: OutboundOpts::default(),
+        extensions: None,
+        unstable_features: vec![],
+        conf: EdgeContextOpts::UserWorker(EdgeUserRuntimeOpts {
+            id: "bench".to_string(),
+            // outlives the samples of a benchmark
+            worker_timeout_ms: 10 * 60 * 1000,
+            ..Default::default()
+        }),
+    }
+}
+
+// The time it takes to boot a worker for the service, until it can take
+// requests. The worker is stopped before the next one is booted.
+async fn boot(service_path: &Path) -> Duration {
+    let start = Instant::now();
+    let mut worker = WorkerContext::new(worker_opts(service_path.to_path_buf()))
+        .await
+        .unwrap();
+    let elapsed = start.elapsed();
+
+    let exit = worker.take_exit_signal();
+    drop(worker);
+    if let Some(exit) = exit {
+        let _ = tokio::time::timeout(Duration::from_secs(5), exit).await;
+    }
+    elapsed
+}
+
+async fn request(worker: &WorkerContext, req: Request<Body>) -> Bytes {
+    let res = worker.send_request(req).await.unwrap();
+    assert!(res.status().is_success());
+    hyper::body::to_bytes(res.into_body()).await.unwrap()
+}
+
+fn get(uri: &str) -> Request<Body> {
+    Request::builder().uri(uri).body(Body::empty()).unwrap()
+}
+
+fn worker_spawn(c: &mut Criterion) {
+    let rt = Runtime::new().unwrap();
+    let hello = service_path("hello");
+
+    let mut group = c.benchmark_group("worker_spawn");
+    group.sample_size(20);
+    group.bench_function("hello", |b| {
+        b.iter_custom(|iters| {
+            rt.block_on(async {
+                let mut total = Duration::ZERO;
+                for _ in 0..iters {
+                    total += boot(&hello).await;
+                }
+                total
+            })
+        })
+    });
+    group.finish();
+}
+
+// the modules are transpiled on the first boot, the samples measure the boots
+// reading them from the emit cache
+fn module_load(c: &mut Criterion) {
+    let rt = Runtime::new().unwrap();
+
+    let mut group = c.benchmark_group("module_load");
+    group.sample_size(10);
+    for modules in [10, 100, 500] {
+        let service = module_graph_service(modules);
+        group.throughput(Throughput::Elements(modules as u64));
+        group.bench_with_input(
+            BenchmarkId::from_parameter(modules),
+            &service,
+            |b, service| {
+                b.iter_custom(|iters| {
+                    rt.block_on(async {
+                        let mut total = Duration::ZERO;
+                        for _ in 0..iters {
+                            total += boot(service).await;
+                        }
+                        total
+                    })
+                })
+            },
+        );
+    }
+    group.finish();
+}
+
+fn request_round_trip(c: &mut Criterion) {
+    let rt = Runtime::new().unwrap();
+    let worker = rt
+        .block_on(WorkerContext::new(worker_opts(service_path("hello"))))
+        .unwrap();
+
+    let mut group = c.benchmark_group("request_round_trip");
+    group.bench_function("sequential", |b| {
+        b.iter(|| rt.block_on(request(&worker, get("http://localhost/"))))
+    });
+    // multiplexed on the connection to the worker
+    for concurrency in [16, 64] {
+        group.throughput(Throughput::Elements(concurrency));
+        group.bench_with_input(
+            BenchmarkId::new("concurrent", concurrency),
+            &concurrency,
+            |b, concurrency| {
+                b.iter(|| {
+                    rt.block_on(join_all(
+                        (0..*concurrency).map(|_| request(&worker, get("http://localhost/"))),
+                    ))
+                })
+            },
+        );
+    }
+    group.finish();
+}
+
+// the worker takes 4 requests at a time off its connection, the others wait
+// for capacity in the bridge
+fn request_backpressure(c: &mut Criterion) {
+    let rt = Runtime::new().unwrap();
+    let mut opts = worker_opts(service_path("hello"));
+    if let EdgeContextOpts::UserWorker(conf) = &mut opts.conf {
+        conf.backpressure = Some(BackpressureOpts {
+            max_in_flight: Some(4),
+            ..Default::default()
+        });
+    }
+    let worker = rt.block_on(WorkerContext::new(opts)).unwrap();
+
+    let mut group = c.benchmark_group("request_backpressure");
+    for concurrency in [16, 64] {
+        group.throughput(Throughput::Elements(concurrency));
+        group.bench_with_input(
+            BenchmarkId::new("concurrent", concurrency),
+            &concurrency,
+            |b, concurrency| {
+                b.iter(|| {
+                    rt.block_on(join_all(
+                        (0..*concurrency).map(|_| request(&worker, get("http://localhost/"))),
+                    ))
+                })
+            },
+        );
+    }
+    group.finish();
+}
+
+// a request with the headers of a browser, the cookies split in a few headers
+fn browser_get(uri: &str) -> Request<Body> {
+    let mut req = Request::builder()
+        .uri(uri)
+        .header("accept", "text/html,application/xhtml+xml,*/*;q=0.8")
+        .header("accept-encoding", "gzip, deflate, br")
+        .header("accept-language", "en-US,en;q=0.5")
+        .header("cache-control", "no-cache")
+        .header(
+            "user-agent",
+            "Mozilla/5.0 (X11; Linux x86_64; rv:109.0) Firefox/112.0",
+        )
+        .header("x-request-id", "bench")
+        .header("x-forwarded-for", "203.0.113.7");
+    for cookie in ["session=abc123", "theme=dark", "consent=1"] {
+        req = req.header("cookie", cookie);
+    }
+    req.body(Body::empty()).unwrap()
+}
+
+// the allocations of the whole round trip, the client's and the bridge's
+// included, per request
+fn request_allocations(c: &mut Criterion<Allocations>) {
+    let rt = Runtime::new().unwrap();
+    let worker = rt
+        .block_on(WorkerContext::new(worker_opts(service_path("hello"))))
+        .unwrap();
+
+    let mut group = c.benchmark_group("request_allocations");
+    group.throughput(Throughput::Elements(1));
+    group.bench_function("sequential", |b| {
+        b.iter(|| rt.block_on(request(&worker, browser_get("http://localhost/"))))
+    });
+    for concurrency in [16, 64] {
+        group.throughput(Throughput::Elements(concurrency));
+        group.bench_with_input(
+            BenchmarkId::new("concurrent", concurrency),
+            &concurrency,
+            |b, concurrency| {
+                b.iter(|| {
+                    rt.block_on(join_all(
+                        (0..*concurrency)
+                            .map(|_| request(&worker, browser_get("http://localhost/"))),
+                    ))
+                })
+            },
+        );
+    }
+    group.finish();
+}
+
+fn streaming_throughput(c: &mut Criterion) {
+    let rt = Runtime::new().unwrap();
+    let worker = rt
+        .block_on(WorkerContext::new(worker_opts(service_path("stream"))))
+        .unwrap();
+
+    let mut group = c.benchmark_group("streaming");
+    for bytes in [64 * 1024, 1024 * 1024, 16 * 1024 * 1024] {
+        group.throughput(Throughput::Bytes(bytes as u64));
+        group.bench_with_input(BenchmarkId::new("download", bytes), &bytes, |b, bytes| {
+            let uri = format!("http://localhost/?bytes={}", bytes);
+            b.iter(|| {
+                let body = rt.block_on(request(&worker, get(&uri)));
+                assert_eq!(body.len(), *bytes);
+            })
+        });
+
+        let payload = Bytes::from(vec![0u8; bytes]);
+        group.bench_with_input(BenchmarkId::new("echo", bytes), &payload, |b, payload| {
+            b.iter(|| {
+                let req = Request::builder()
+                    .method(Method::POST)
+                    .uri("http://localhost/")
+                    .body(Body::from(payload.clone()))
+                    .unwrap();
+                let body = rt.block_on(request(&worker, req));
+                assert_eq!(body.len(), payload.len());
+            })
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(
+    benches,
+    worker_spawn,
+    module_load,
+    request_round_trip,
+    request_backpressure,
+    streaming_throughput
+);
+criterion_group!(
+    name = allocations;
+    config = Criterion::default().with_measurement(Allocations);
+    targets = request_allocations
+);
+criterion_main!(benches, allocations);
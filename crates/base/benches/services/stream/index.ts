@@ -0,0 +1,23 @@
+// sends back `?bytes=N` bytes in 64KiB chunks, or the body of POST requests
+const chunk = new Uint8Array(64 * 1024);
+
+Deno.serve((req: Request) => {
+  if (req.method === "POST") {
+    return new Response(req.body);
+  }
+
+  const bytes = Number(new URL(req.url).searchParams.get("bytes") ?? 0);
+  let sent = 0;
+  const body = new ReadableStream<Uint8Array>({
+    pull(controller) {
+      if (sent >= bytes) {
+        controller.close();
+        return;
+      }
+      const size = Math.min(chunk.length, bytes - sent);
+      sent += size;
+      controller.enqueue(chunk.subarray(0, size));
+    },
+  });
+  return new Response(body);
+});
@@ -12,13 +12,44 @@ mod supabase_startup_snapshot {
     use deno_core::Extension;
     use deno_core::ExtensionFileSource;
     use deno_core::ModuleCode;
+    #[cfg(feature = "ai")]
+    use sb_ai::sb_ai;
+    #[cfg(feature = "cache")]
+    use sb_cache::sb_cache;
+    use sb_core::billing::sb_core_billing;
+    use sb_core::blob::sb_core_blob;
+    use sb_core::compression::sb_core_compression;
+    use sb_core::deadline::sb_core_deadline;
+    use sb_core::event_loop::sb_core_event_loop;
+    use sb_core::fetch_breaker::sb_core_fetch_breaker;
+    use sb_core::fetch_intercept::sb_core_fetch_intercept;
     use sb_core::http_start::sb_core_http;
+    use sb_core::lazy_tls::sb_core_lazy_tls;
+    use sb_core::logs::sb_core_logs;
     use sb_core::net::sb_core_net;
+    use sb_core::net_usage::sb_core_net_usage;
+    use sb_core::no_net::sb_core_no_net;
+    use sb_core::open_sockets::sb_core_open_sockets;
     use sb_core::permissions::sb_core_permissions;
+    use sb_core::router::sb_core_router;
     use sb_core::runtime::sb_core_runtime;
-    use sb_core::sb_core_main_js;
+    use sb_core::streams::sb_core_streams;
+    use sb_core::uncaught_errors::sb_core_uncaught_errors;
+    use sb_core::{sb_core_edge_runtime_apis, sb_core_main_js, sb_core_main_worker_js};
     use sb_env::sb_env;
-    use sb_workers::sb_user_workers;
+    #[cfg(feature = "jwt")]
+    use sb_jwt::sb_jwt;
+    #[cfg(feature = "mail")]
+    use sb_mail::sb_mail;
+    #[cfg(feature = "postgres")]
+    use sb_postgres::sb_postgres;
+    #[cfg(feature = "queue")]
+    use sb_queue::sb_queue;
+    #[cfg(feature = "rate_limit")]
+    use sb_rate_limit::sb_rate_limit;
+    #[cfg(feature = "storage")]
+    use sb_storage::sb_storage;
+    use sb_workers::{sb_service_bindings, sb_user_workers};
     use std::path::Path;
 
     fn transpile_ts_for_snapshotting(
@@ -125,9 +156,12 @@ mod supabase_startup_snapshot {
         }
     }
 
-    pub fn create_runtime_snapshot(snapshot_path: PathBuf) {
+    // must match `edge_runtime::runtime_extensions`, the main worker's
+    // snapshot has the extensions driving the pool on top and the one of user
+    // workers without the net capability has sb_core_no_net for deno_net
+    pub fn create_runtime_snapshot(snapshot_path: PathBuf, main_worker: bool, net: bool) {
         let user_agent = String::from("supabase");
-        let extensions: Vec<Extension> = vec![
+        let mut extensions: Vec<Extension> = vec![
             sb_core_permissions::init_ops_and_esm(),
             deno_webidl::deno_webidl::init_ops_and_esm(),
             deno_console::deno_console::init_ops_and_esm(),
@@ -142,18 +176,65 @@ mod supabase_startup_snapshot {
                 ..Default::default()
             }),
             deno_websocket::deno_websocket::init_ops_and_esm::<Permissions>(user_agent, None, None),
-            // TODO: support providing a custom seed for crypto
+            // the seed is put in the op state when a worker boots, see `crypto_seed`
             deno_crypto::deno_crypto::init_ops_and_esm(None),
-            deno_net::deno_net::init_ops_and_esm::<Permissions>(None, false, None),
+            if net {
+                deno_net::deno_net::init_ops_and_esm::<Permissions>(None, false, None)
+            } else {
+                sb_core_no_net::init_ops_and_esm()
+            },
             deno_tls::deno_tls::init_ops_and_esm(),
             deno_http::deno_http::init_ops_and_esm(),
+            sb_core_streams::init_ops_and_esm(),
             sb_env::init_ops_and_esm(),
-            sb_user_workers::init_ops_and_esm(),
+            sb_service_bindings::init_ops_and_esm(),
+            sb_core_edge_runtime_apis::init_ops_and_esm(),
+        ];
+        // see `runtime_extensions`
+        #[cfg(feature = "postgres")]
+        extensions.push(sb_postgres::init_ops_and_esm());
+        #[cfg(feature = "mail")]
+        extensions.push(sb_mail::init_ops_and_esm());
+        #[cfg(feature = "ai")]
+        extensions.push(sb_ai::init_ops_and_esm());
+        #[cfg(feature = "jwt")]
+        extensions.push(sb_jwt::init_ops_and_esm());
+        #[cfg(feature = "storage")]
+        extensions.push(sb_storage::init_ops_and_esm());
+        #[cfg(feature = "queue")]
+        extensions.push(sb_queue::init_ops_and_esm());
+        #[cfg(feature = "rate_limit")]
+        extensions.push(sb_rate_limit::init_ops_and_esm());
+        #[cfg(feature = "cache")]
+        extensions.push(sb_cache::init_ops_and_esm());
+        extensions.extend([
             sb_core_main_js::init_ops_and_esm(),
             sb_core_net::init_ops_and_esm(),
             sb_core_http::init_ops_and_esm(),
             sb_core_runtime::init_ops_and_esm(None),
-        ];
+            sb_core_event_loop::init_ops_and_esm(),
+            sb_core_uncaught_errors::init_ops_and_esm(),
+            sb_core_logs::init_ops_and_esm(),
+            sb_core_fetch_intercept::init_ops_and_esm(),
+            sb_core_net_usage::init_ops_and_esm(),
+            sb_core_compression::init_ops_and_esm(),
+            sb_core_blob::init_ops_and_esm(),
+            sb_core_fetch_breaker::init_ops_and_esm(),
+            sb_core_open_sockets::init_ops_and_esm(),
+            sb_core_billing::init_ops_and_esm(),
+            sb_core_deadline::init_ops_and_esm(),
+        ]);
+        if main_worker {
+            extensions.extend([
+                sb_user_workers::init_ops_and_esm(),
+                sb_core_router::init_ops_and_esm(),
+                sb_core_main_worker_js::init_ops_and_esm(),
+            ]);
+        } else {
+            extensions.push(sb_core_lazy_tls::init_ops_and_esm(
+                deno_tls::create_default_root_cert_store,
+            ));
+        }
 
         create_snapshot(CreateSnapshotOptions {
             cargo_manifest_dir: env!("CARGO_MANIFEST_DIR"),
@@ -172,8 +253,19 @@ fn main() {
 
     let o = PathBuf::from(env::var_os("OUT_DIR").unwrap());
 
-    // Main snapshot
-    let runtime_snapshot_path = o.join("RUNTIME_SNAPSHOT.bin");
-
-    supabase_startup_snapshot::create_runtime_snapshot(runtime_snapshot_path)
+    supabase_startup_snapshot::create_runtime_snapshot(
+        o.join("MAIN_RUNTIME_SNAPSHOT.bin"),
+        true,
+        true,
+    );
+    supabase_startup_snapshot::create_runtime_snapshot(
+        o.join("USER_RUNTIME_SNAPSHOT.bin"),
+        false,
+        true,
+    );
+    supabase_startup_snapshot::create_runtime_snapshot(
+        o.join("USER_NO_NET_RUNTIME_SNAPSHOT.bin"),
+        false,
+        false,
+    );
 }
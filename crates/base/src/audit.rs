@@ -0,0 +1,308 @@
+use anyhow::{Context, Error};
+use bytes::Bytes;
+use deno_core::futures::Stream;
+use hyper::header::HeaderMap;
+use hyper::{Body, Request, Response};
+use regex::Regex;
+use sb_worker_context::essentials::AuditOpts;
+use sb_worker_context::events::{
+    AuditEvent, CapturedBody, WorkerEventWithMetadata, WorkerEvents, WorkerEventsTx,
+};
+use sb_worker_context::worker_id::WorkerId;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context as TaskContext, Poll};
+
+const REDACTED: &str = "[redacted]";
+
+// Picks the requests of a user worker to record.
+#[derive(Debug)]
+pub struct Auditor {
+    sample_rate: f64,
+    // grows by the sample rate on each request, one is recorded when it's past 1
+    sampled: f64,
+    context: Arc<AuditContext>,
+}
+
+#[derive(Debug)]
+struct AuditContext {
+    worker_id: WorkerId,
+    max_body_bytes: usize,
+    redact_headers: Vec<Regex>,
+    events_tx: WorkerEventsTx,
+}
+
+impl AuditContext {
+    fn headers(&self, headers: &HeaderMap) -> Vec<(String, String)> {
+        headers
+            .iter()
+            .map(|(name, value)| {
+                let name = name.as_str().to_string();
+                let value = if self.redact_headers.iter().any(|re| re.is_match(&name)) {
+                    REDACTED.to_string()
+                } else {
+                    String::from_utf8_lossy(value.as_bytes()).to_string()
+                };
+                (name, value)
+            })
+            .collect()
+    }
+}
+
+// A request being recorded, until its response body is streamed.
+pub struct AuditRecording {
+    context: Arc<AuditContext>,
+    event: AuditEvent,
+    request_body: Arc<Mutex<CapturedBody>>,
+}
+
+impl Auditor {
+    pub fn new(
+        worker_id: WorkerId,
+        opts: &AuditOpts,
+        events_tx: WorkerEventsTx,
+    ) -> Result<Self, Error> {
+        let redact_headers = opts
+            .redact_headers
+            .iter()
+            .map(|pattern| {
+                Regex::new(pattern).with_context(|| format!("invalid header pattern {}", pattern))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        Ok(Self {
+            sample_rate: opts.sample_rate.clamp(0.0, 1.0),
+            sampled: 0.0,
+            context: Arc::new(AuditContext {
+                worker_id,
+                max_body_bytes: opts.max_body_bytes,
+                redact_headers,
+                events_tx,
+            }),
+        })
+    }
+
+    // spreads the recorded requests evenly, eg: one in four at 0.25
+    pub fn sample(&mut self) -> bool {
+        self.sampled += self.sample_rate;
+        if self.sampled >= 1.0 {
+            self.sampled -= 1.0;
+            return true;
+        }
+        false
+    }
+
+    // copies the request's body as it's streamed to the worker
+    pub fn record(&self, req: Request<Body>) -> (Request<Body>, AuditRecording) {
+        let event = AuditEvent {
+            request_id: req
+                .headers()
+                .get("x-request-id")
+                .and_then(|value| value.to_str().ok())
+                .map(str::to_string),
+            method: req.method().to_string(),
+            url: req.uri().to_string(),
+            request_headers: self.context.headers(req.headers()),
+            request_body: CapturedBody::default(),
+            status: 0,
+            response_headers: vec![],
+            response_body: CapturedBody::default(),
+        };
+
+        let request_body = Arc::new(Mutex::new(CapturedBody::default()));
+        let (parts, body) = req.into_parts();
+        let body = Body::wrap_stream(TeeBody {
+            inner: body,
+            capture: request_body.clone(),
+            max_bytes: self.context.max_body_bytes,
+            on_done: None,
+        });
+
+        let recording = AuditRecording {
+            context: self.context.clone(),
+            event,
+            request_body,
+        };
+        (Request::from_parts(parts, body), recording)
+    }
+}
+
+impl AuditRecording {
+    // copies the response's body as it's streamed to the client, the event is
+    // sent once it's done (or dropped)
+    pub fn finish(self, res: Response<Body>) -> Response<Body> {
+        let AuditRecording {
+            context,
+            mut event,
+            request_body,
+        } = self;
+        event.status = res.status().as_u16();
+        event.response_headers = context.headers(res.headers());
+
+        let response_body = Arc::new(Mutex::new(CapturedBody::default()));
+        let captured = response_body.clone();
+        let max_bytes = context.max_body_bytes;
+        let on_done = Box::new(move || {
+            event.request_body = request_body.lock().unwrap().clone();
+            event.response_body = captured.lock().unwrap().clone();
+            let _ = context.events_tx.send(WorkerEventWithMetadata {
+                worker_id: context.worker_id.clone(),
+                event: WorkerEvents::Audit(event),
+            });
+        });
+
+        let (parts, body) = res.into_parts();
+        let body = Body::wrap_stream(TeeBody {
+            inner: body,
+            capture: response_body,
+            max_bytes,
+            on_done: Some(on_done),
+        });
+        Response::from_parts(parts, body)
+    }
+}
+
+// Passes a body through, keeping a copy of its first bytes.
+struct TeeBody {
+    inner: Body,
+    capture: Arc<Mutex<CapturedBody>>,
+    max_bytes: usize,
+    on_done: Option<Box<dyn FnOnce() + Send>>,
+}
+
+impl Stream for TeeBody {
+    type Item = Result<Bytes, hyper::Error>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut TaskContext<'_>) -> Poll<Option<Self::Item>> {
+        let poll = Pin::new(&mut self.inner).poll_next(cx);
+        match &poll {
+            Poll::Ready(Some(Ok(chunk))) => {
+                let mut capture = self.capture.lock().unwrap();
+                capture.size += chunk.len() as u64;
+                let room = self.max_bytes.saturating_sub(capture.data.len());
+                capture
+                    .data
+                    .extend_from_slice(&chunk[..room.min(chunk.len())]);
+            }
+            Poll::Ready(_) => {
+                if let Some(on_done) = self.on_done.take() {
+                    on_done();
+                }
+            }
+            Poll::Pending => {}
+        }
+        poll
+    }
+}
+
+impl Drop for TeeBody {
+    fn drop(&mut self) {
+        if let Some(on_done) = self.on_done.take() {
+            on_done();
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use deno_core::futures::StreamExt;
+    use tokio::sync::mpsc;
+
+    fn new_auditor(opts: AuditOpts) -> (Auditor, mpsc::UnboundedReceiver<WorkerEventWithMetadata>) {
+        let (tx, rx) = mpsc::unbounded_channel();
+        (
+            Auditor::new(
+                WorkerId::new("worker".to_string(), "hello", None),
+                &opts,
+                tx,
+            )
+            .unwrap(),
+            rx,
+        )
+    }
+
+    #[test]
+    fn test_sample() {
+        let (mut auditor, _rx) = new_auditor(AuditOpts {
+            sample_rate: 0.25,
+            ..Default::default()
+        });
+        let sampled = (0..100).filter(|_| auditor.sample()).count();
+        assert_eq!(sampled, 25);
+
+        let (mut auditor, _rx) = new_auditor(AuditOpts {
+            sample_rate: 0.0,
+            ..Default::default()
+        });
+        assert!(!(0..100).any(|_| auditor.sample()));
+    }
+
+    #[test]
+    fn test_invalid_pattern() {
+        let (tx, _rx) = mpsc::unbounded_channel();
+        let opts = AuditOpts {
+            redact_headers: vec!["(".to_string()],
+            ..Default::default()
+        };
+        assert!(Auditor::new(
+            WorkerId::new("worker".to_string(), "hello", None),
+            &opts,
+            tx
+        )
+        .is_err());
+    }
+
+    #[tokio::test]
+    async fn test_record() {
+        let (auditor, mut rx) = new_auditor(AuditOpts {
+            max_body_bytes: 4,
+            redact_headers: vec!["^authorization$".to_string(), "token".to_string()],
+            ..Default::default()
+        });
+
+        let req = Request::post("http://localhost/users")
+            .header("authorization", "Bearer secret")
+            .header("x-refresh-token", "secret")
+            .header("content-type", "text/plain")
+            .body(Body::from("hello world"))
+            .unwrap();
+        let (req, recording) = auditor.record(req);
+        let body = hyper::body::to_bytes(req.into_body()).await.unwrap();
+        assert_eq!(body, "hello world");
+
+        let res = Response::builder()
+            .status(201)
+            .header("set-cookie", "session=1")
+            .body(Body::from("ok"))
+            .unwrap();
+        let res = recording.finish(res);
+        let mut body = res.into_body();
+        while body.next().await.is_some() {}
+
+        let WorkerEvents::Audit(event) = rx.recv().await.unwrap().event else {
+            panic!("expected an audit event");
+        };
+        assert_eq!(event.method, "POST");
+        assert_eq!(event.url, "http://localhost/users");
+        assert_eq!(
+            event.request_headers,
+            vec![
+                ("authorization".to_string(), REDACTED.to_string()),
+                ("x-refresh-token".to_string(), REDACTED.to_string()),
+                ("content-type".to_string(), "text/plain".to_string()),
+            ]
+        );
+        assert_eq!(event.request_body.data, b"hell");
+        assert_eq!(event.request_body.size, 11);
+        assert!(event.request_body.truncated());
+        assert_eq!(event.status, 201);
+        // not one of the patterns given
+        assert_eq!(
+            event.response_headers,
+            vec![("set-cookie".to_string(), "session=1".to_string())]
+        );
+        assert_eq!(event.response_body.data, b"ok");
+        assert!(!event.response_body.truncated());
+    }
+}
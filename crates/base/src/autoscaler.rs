@@ -0,0 +1,233 @@
+use sb_worker_context::essentials::AutoscaleOpts;
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+use uuid::Uuid;
+
+// number of recent response times the p95 latency is computed over
+const LATENCY_WINDOW: usize = 100;
+
+// Load of one of the isolates serving a user worker.
+#[derive(Debug, Clone)]
+pub struct ReplicaLoad {
+    pub id: Uuid,
+    // requests sent to the isolate that haven't been answered yet
+    pub in_flight: usize,
+    // requests the isolate handles at once, unlimited if unset
+    pub concurrency: Option<usize>,
+    pub idle_since: Option<Instant>,
+}
+
+impl ReplicaLoad {
+    // requests waiting for a slot of the isolate, none wait without a limit
+    fn queued(&self) -> usize {
+        self.concurrency
+            .map_or(0, |max| self.in_flight.saturating_sub(max.max(1)))
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub enum ScaleDecision {
+    Hold,
+    // number of isolates to spawn
+    Up(usize),
+    // isolate to stop
+    Down(Uuid),
+}
+
+// Decides when to spawn or stop isolates for a user worker, based on the
+// number of queued requests and the recent response times.
+#[derive(Debug)]
+pub struct Autoscaler {
+    opts: AutoscaleOpts,
+    latencies_ms: VecDeque<u64>,
+    booting: usize,
+}
+
+impl Autoscaler {
+    pub fn new(mut opts: AutoscaleOpts) -> Self {
+        opts.min_workers = opts.min_workers.max(1);
+        opts.max_workers = opts.max_workers.max(opts.min_workers);
+        Self {
+            opts,
+            latencies_ms: VecDeque::with_capacity(LATENCY_WINDOW),
+            booting: 0,
+        }
+    }
+
+    pub fn record_latency(&mut self, latency_ms: u64) {
+        if self.latencies_ms.len() == LATENCY_WINDOW {
+            self.latencies_ms.pop_front();
+        }
+        self.latencies_ms.push_back(latency_ms);
+    }
+
+    pub fn p95_latency_ms(&self) -> Option<u64> {
+        if self.latencies_ms.is_empty() {
+            return None;
+        }
+
+        let mut sorted: Vec<u64> = self.latencies_ms.iter().copied().collect();
+        sorted.sort_unstable();
+        let rank = (sorted.len() * 95 + 99) / 100;
+        Some(sorted[rank.saturating_sub(1)])
+    }
+
+    pub fn boot_started(&mut self, count: usize) {
+        self.booting += count;
+        // the samples were taken with fewer isolates, don't let them trigger another spawn
+        self.latencies_ms.clear();
+    }
+
+    pub fn boot_finished(&mut self) {
+        self.booting = self.booting.saturating_sub(1);
+    }
+
+    pub fn decide(&self, replicas: &[ReplicaLoad], now: Instant) -> ScaleDecision {
+        let total = replicas.len() + self.booting;
+        if total < self.opts.min_workers {
+            return ScaleDecision::Up(self.opts.min_workers - total);
+        }
+
+        let queue_depth: usize = replicas.iter().map(ReplicaLoad::queued).sum();
+        let slow = match (self.opts.max_p95_latency_ms, self.p95_latency_ms()) {
+            (Some(max), Some(p95)) => p95 > max,
+            _ => false,
+        };
+        let overloaded = queue_depth > self.opts.max_queue_depth || slow;
+
+        if overloaded {
+            // one isolate at a time, the next decision accounts for it once it's up
+            if self.booting == 0 && total < self.opts.max_workers {
+                return ScaleDecision::Up(1);
+            }
+            return ScaleDecision::Hold;
+        }
+
+        if replicas.len() <= self.opts.min_workers {
+            return ScaleDecision::Hold;
+        }
+
+        let idle_timeout = Duration::from_millis(self.opts.idle_timeout_ms);
+        replicas
+            .iter()
+            .filter(|r| r.in_flight == 0)
+            .filter_map(|r| Some((r.id, r.idle_since?)))
+            .filter(|(_, idle_since)| now.saturating_duration_since(*idle_since) >= idle_timeout)
+            .min_by_key(|(_, idle_since)| *idle_since)
+            .map(|(id, _)| ScaleDecision::Down(id))
+            .unwrap_or(ScaleDecision::Hold)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn replica(in_flight: usize, idle_since: Option<Instant>) -> ReplicaLoad {
+        ReplicaLoad {
+            id: Uuid::new_v4(),
+            in_flight,
+            concurrency: Some(1),
+            idle_since,
+        }
+    }
+
+    fn opts() -> AutoscaleOpts {
+        AutoscaleOpts {
+            min_workers: 1,
+            max_workers: 3,
+            max_queue_depth: 2,
+            max_p95_latency_ms: Some(500),
+            idle_timeout_ms: 1000,
+        }
+    }
+
+    #[test]
+    fn test_scale_up_on_queue_depth() {
+        let mut scaler = Autoscaler::new(opts());
+        let now = Instant::now();
+
+        assert_eq!(scaler.decide(&[], now), ScaleDecision::Up(1));
+        assert_eq!(scaler.decide(&[replica(3, None)], now), ScaleDecision::Hold);
+        assert_eq!(
+            scaler.decide(&[replica(4, None)], now),
+            ScaleDecision::Up(1)
+        );
+
+        // wait for the new isolate before spawning another one
+        scaler.boot_started(1);
+        assert_eq!(scaler.decide(&[replica(8, None)], now), ScaleDecision::Hold);
+        scaler.boot_finished();
+
+        let full = [replica(4, None), replica(4, None), replica(4, None)];
+        assert_eq!(scaler.decide(&full, now), ScaleDecision::Hold);
+    }
+
+    #[test]
+    fn test_queue_depth_against_concurrency() {
+        let scaler = Autoscaler::new(opts());
+        let now = Instant::now();
+        let with_concurrency = |in_flight, concurrency| ReplicaLoad {
+            concurrency,
+            ..replica(in_flight, None)
+        };
+
+        // 4 requests handled at once, 2 waiting for a slot
+        assert_eq!(
+            scaler.decide(&[with_concurrency(6, Some(4))], now),
+            ScaleDecision::Hold
+        );
+        assert_eq!(
+            scaler.decide(&[with_concurrency(7, Some(4))], now),
+            ScaleDecision::Up(1)
+        );
+        // without a limit, nothing waits
+        assert_eq!(
+            scaler.decide(&[with_concurrency(100, None)], now),
+            ScaleDecision::Hold
+        );
+    }
+
+    #[test]
+    fn test_scale_up_on_latency() {
+        let mut scaler = Autoscaler::new(opts());
+        let now = Instant::now();
+
+        for _ in 0..90 {
+            scaler.record_latency(100);
+        }
+        for _ in 0..10 {
+            scaler.record_latency(900);
+        }
+        assert_eq!(scaler.p95_latency_ms(), Some(900));
+        assert_eq!(
+            scaler.decide(&[replica(1, None)], now),
+            ScaleDecision::Up(1)
+        );
+
+        scaler.boot_started(1);
+        assert_eq!(scaler.p95_latency_ms(), None);
+    }
+
+    #[test]
+    fn test_scale_down_when_idle() {
+        let scaler = Autoscaler::new(opts());
+        let now = Instant::now();
+        let long_ago = now - Duration::from_secs(5);
+
+        let busy = replica(1, None);
+        let idle = replica(0, Some(long_ago));
+        let recently_idle = replica(0, Some(now));
+        assert_eq!(
+            scaler.decide(&[busy.clone(), idle.clone(), recently_idle.clone()], now),
+            ScaleDecision::Down(idle.id)
+        );
+        assert_eq!(
+            scaler.decide(&[busy, recently_idle], now),
+            ScaleDecision::Hold
+        );
+
+        // never below the minimum
+        assert_eq!(scaler.decide(&[idle], now), ScaleDecision::Hold);
+    }
+}
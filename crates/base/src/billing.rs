@@ -0,0 +1,395 @@
+use anyhow::{bail, Context, Error};
+use bytes::Bytes;
+use deno_core::futures::Stream;
+use deno_core::serde_json;
+use hyper::{Body, Response};
+use log::{error, warn};
+use once_cell::sync::OnceCell;
+use sb_worker_context::billing::InvocationUsages;
+use sb_worker_context::events::{
+    BillingEvent, WorkerEventWithMetadata, WorkerEvents, WorkerEventsTx,
+};
+use sb_worker_context::worker_id::WorkerId;
+use serde::Serialize;
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::path::PathBuf;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context as TaskContext, Poll};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use tokio::sync::mpsc;
+
+static BILLING: OnceCell<Billing> = OnceCell::new();
+
+// records waiting to be written, the ones past it are dropped
+const SINK_QUEUE_SIZE: usize = 4096;
+// records sent at once to an http sink
+const MAX_BATCH: usize = 100;
+// how long records wait for others to be sent along with them
+const BATCH_INTERVAL: Duration = Duration::from_secs(1);
+const HTTP_ATTEMPTS: usize = 3;
+const HTTP_RETRY_BACKOFF: Duration = Duration::from_secs(1);
+
+// Where the billing records are written to, besides the events channel.
+#[derive(Debug, Clone, PartialEq)]
+pub enum BillingSink {
+    // one JSON record per line, appended
+    File(PathBuf),
+    // batches of JSON records, one per line, POSTed as `application/x-ndjson`
+    Http(String),
+}
+
+impl std::str::FromStr for BillingSink {
+    type Err = Error;
+
+    // file:<path> or http(s)://<url>
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if let Some(path) = s.strip_prefix("file:") {
+            if path.is_empty() {
+                bail!("the billing sink {:?} has no path", s);
+            }
+            return Ok(BillingSink::File(PathBuf::from(path)));
+        }
+        if s.starts_with("http://") || s.starts_with("https://") {
+            url::Url::parse(s).with_context(|| format!("invalid billing sink {:?}", s))?;
+            return Ok(BillingSink::Http(s.to_string()));
+        }
+        bail!(
+            "invalid billing sink {:?}, expected file:<path> or http(s)://<url>",
+            s
+        );
+    }
+}
+
+// a billing event with the worker it's of, as written to the sinks
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct BillingRecord<'a> {
+    worker_id: &'a str,
+    #[serde(flatten)]
+    event: &'a BillingEvent,
+}
+
+#[derive(Debug)]
+struct Billing {
+    sink_tx: Option<mpsc::Sender<String>>,
+}
+
+// Bills each request handled by a user worker, with a `Billing` event and
+// a record written to `sink`. Must be called before the server starts.
+pub fn init_billing(sink: Option<BillingSink>) -> Result<(), Error> {
+    let sink_tx = match sink {
+        Some(sink) => Some(start_sink(sink)?),
+        None => None,
+    };
+    if BILLING.set(Billing { sink_tx }).is_err() {
+        bail!("billing is already set up");
+    }
+    Ok(())
+}
+
+pub(crate) fn billing_enabled() -> bool {
+    BILLING.get().is_some()
+}
+
+fn start_sink(sink: BillingSink) -> Result<mpsc::Sender<String>, Error> {
+    if let BillingSink::File(path) = &sink {
+        // fail on start, instead of on the first record
+        OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .with_context(|| format!("failed to open the billing file {}", path.display()))?;
+    }
+
+    let (tx, rx) = mpsc::channel(SINK_QUEUE_SIZE);
+    std::thread::Builder::new()
+        .name("billing-sink".to_string())
+        .spawn(move || {
+            let rt = tokio::runtime::Builder::new_current_thread()
+                .enable_all()
+                .build()
+                .unwrap();
+            rt.block_on(write_records(sink, rx));
+        })?;
+    Ok(tx)
+}
+
+async fn write_records(sink: BillingSink, mut rx: mpsc::Receiver<String>) {
+    let client = reqwest::Client::new();
+    while let Some(record) = rx.recv().await {
+        let mut batch = vec![record];
+        let deadline = tokio::time::Instant::now() + BATCH_INTERVAL;
+        while batch.len() < MAX_BATCH {
+            match tokio::time::timeout_at(deadline, rx.recv()).await {
+                Ok(Some(record)) => batch.push(record),
+                _ => break,
+            }
+        }
+
+        let mut lines = batch.join("\n");
+        lines.push('\n');
+        match &sink {
+            BillingSink::File(path) => {
+                let written = OpenOptions::new()
+                    .create(true)
+                    .append(true)
+                    .open(path)
+                    .and_then(|mut file| file.write_all(lines.as_bytes()));
+                if let Err(err) = written {
+                    error!(
+                        "failed to write {} billing records to {}: {}",
+                        batch.len(),
+                        path.display(),
+                        err
+                    );
+                }
+            }
+            BillingSink::Http(url) => post_records(&client, url, lines, batch.len()).await,
+        }
+    }
+}
+
+async fn post_records(client: &reqwest::Client, url: &str, lines: String, count: usize) {
+    let mut last_err = String::new();
+    for attempt in 0..HTTP_ATTEMPTS {
+        if attempt > 0 {
+            tokio::time::sleep(HTTP_RETRY_BACKOFF).await;
+        }
+        let res = client
+            .post(url)
+            .header("content-type", "application/x-ndjson")
+            .body(lines.clone())
+            .send()
+            .await;
+        match res {
+            Ok(res) if res.status().is_success() => return,
+            Ok(res) => last_err = format!("status {}", res.status()),
+            Err(err) => last_err = err.to_string(),
+        }
+    }
+    error!(
+        "failed to send {} billing records to {}: {}",
+        count, url, last_err
+    );
+}
+
+// Bills the requests of a user worker, with what its isolates reported
+// spending on them.
+#[derive(Debug)]
+pub(crate) struct BillingMeter {
+    worker_id: WorkerId,
+    service: String,
+    deployment: Option<String>,
+    memory_mb: u64,
+    usages: Arc<InvocationUsages>,
+    events_tx: WorkerEventsTx,
+}
+
+impl BillingMeter {
+    pub(crate) fn new(
+        worker_id: WorkerId,
+        service: String,
+        deployment: Option<String>,
+        memory_mb: u64,
+        usages: Arc<InvocationUsages>,
+        events_tx: WorkerEventsTx,
+    ) -> Self {
+        Self {
+            worker_id,
+            service,
+            deployment,
+            memory_mb,
+            usages,
+            events_tx,
+        }
+    }
+
+    // counts the bytes of the response as it's streamed to the client, the
+    // request is billed once it's done (or dropped)
+    pub(crate) fn finish(
+        self: Arc<Self>,
+        request_id: Option<String>,
+        started_at: SystemTime,
+        start: Instant,
+        res: Response<Body>,
+    ) -> Response<Body> {
+        let status = res.status().as_u16();
+        let on_done = Box::new(move |egress_bytes: u64| {
+            let duration = start.elapsed();
+            let usage = request_id
+                .as_deref()
+                .and_then(|id| self.usages.take(id))
+                .unwrap_or_default();
+            let event = BillingEvent {
+                request_id,
+                service: self.service.clone(),
+                deployment: self.deployment.clone(),
+                started_at: started_at
+                    .duration_since(UNIX_EPOCH)
+                    .unwrap_or_default()
+                    .as_millis() as u64,
+                duration_ms: duration.as_millis() as u64,
+                cpu_ms: usage.cpu_ms,
+                memory_mb: self.memory_mb,
+                memory_mb_s: self.memory_mb as f64 * duration.as_secs_f64(),
+                peak_memory_bytes: usage.peak_heap_bytes,
+                egress_bytes,
+                status,
+            };
+            self.bill(event);
+        });
+
+        let (parts, body) = res.into_parts();
+        let body = Body::wrap_stream(CountedBody {
+            inner: body,
+            bytes: 0,
+            on_done: Some(on_done),
+        });
+        Response::from_parts(parts, body)
+    }
+
+    fn bill(&self, event: BillingEvent) {
+        let sink_tx = BILLING.get().and_then(|billing| billing.sink_tx.as_ref());
+        if let Some(sink_tx) = sink_tx {
+            let record = BillingRecord {
+                worker_id: &self.worker_id.id,
+                event: &event,
+            };
+            match serde_json::to_string(&record) {
+                Ok(line) => {
+                    if sink_tx.try_send(line).is_err() {
+                        warn!(
+                            "[{}] billing record dropped, the sink is behind",
+                            self.worker_id
+                        );
+                    }
+                }
+                Err(err) => error!("failed to serialize a billing record: {}", err),
+            }
+        }
+        let _ = self.events_tx.send(WorkerEventWithMetadata {
+            worker_id: self.worker_id.clone(),
+            event: WorkerEvents::Billing(event),
+        });
+    }
+}
+
+// Passes a body through, counting its bytes.
+struct CountedBody {
+    inner: Body,
+    bytes: u64,
+    on_done: Option<Box<dyn FnOnce(u64) + Send>>,
+}
+
+impl Stream for CountedBody {
+    type Item = Result<Bytes, hyper::Error>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut TaskContext<'_>) -> Poll<Option<Self::Item>> {
+        let poll = Pin::new(&mut self.inner).poll_next(cx);
+        match &poll {
+            Poll::Ready(Some(Ok(chunk))) => self.bytes += chunk.len() as u64,
+            Poll::Ready(_) => {
+                if let Some(on_done) = self.on_done.take() {
+                    on_done(self.bytes);
+                }
+            }
+            Poll::Pending => {}
+        }
+        poll
+    }
+}
+
+impl Drop for CountedBody {
+    fn drop(&mut self) {
+        if let Some(on_done) = self.on_done.take() {
+            on_done(self.bytes);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use deno_core::futures::StreamExt;
+    use sb_worker_context::billing::IsolateUsage;
+
+    #[test]
+    fn test_parse_sink() {
+        assert_eq!(
+            "file:/var/log/billing.jsonl"
+                .parse::<BillingSink>()
+                .unwrap(),
+            BillingSink::File(PathBuf::from("/var/log/billing.jsonl"))
+        );
+        assert_eq!(
+            "https://metering.internal/records"
+                .parse::<BillingSink>()
+                .unwrap(),
+            BillingSink::Http("https://metering.internal/records".to_string())
+        );
+        assert!("file:".parse::<BillingSink>().is_err());
+        assert!("kafka://localhost".parse::<BillingSink>().is_err());
+    }
+
+    #[tokio::test]
+    async fn test_bill_request() {
+        let (tx, mut rx) = mpsc::unbounded_channel();
+        let usages = Arc::new(InvocationUsages::default());
+        usages.insert(
+            "req-1".to_string(),
+            IsolateUsage {
+                cpu_ms: 3.5,
+                peak_heap_bytes: 4096,
+            },
+        );
+        let meter = Arc::new(BillingMeter::new(
+            WorkerId::new("worker".to_string(), "./functions/hello", Some("v2")),
+            "./functions/hello".to_string(),
+            Some("v2".to_string()),
+            150,
+            usages.clone(),
+            tx,
+        ));
+
+        let res = Response::builder()
+            .status(201)
+            .body(Body::from("hello world"))
+            .unwrap();
+        let res = meter.finish(
+            Some("req-1".to_string()),
+            SystemTime::now(),
+            Instant::now(),
+            res,
+        );
+        let mut body = res.into_body();
+        while body.next().await.is_some() {}
+
+        let event = rx.recv().await.unwrap();
+        assert_eq!(event.worker_id.id, "worker");
+        assert_eq!(event.worker_id.label, "functions/hello@v2");
+        let WorkerEvents::Billing(event) = event.event else {
+            panic!("expected a billing event");
+        };
+        assert_eq!(event.request_id.as_deref(), Some("req-1"));
+        assert_eq!(event.service, "./functions/hello");
+        assert_eq!(event.deployment.as_deref(), Some("v2"));
+        assert_eq!(event.cpu_ms, 3.5);
+        assert_eq!(event.peak_memory_bytes, 4096);
+        assert_eq!(event.memory_mb, 150);
+        assert_eq!(event.egress_bytes, 11);
+        assert_eq!(event.status, 201);
+        // taken by the meter
+        assert!(usages.take("req-1").is_none());
+
+        let record = serde_json::to_value(BillingRecord {
+            worker_id: "worker",
+            event: &event,
+        })
+        .unwrap();
+        assert_eq!(record["workerId"], "worker");
+        assert_eq!(record["egressBytes"], 11);
+        assert_eq!(record["memoryMbS"], event.memory_mb_s);
+    }
+}
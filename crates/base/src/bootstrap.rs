@@ -0,0 +1,138 @@
+use deno_core::serde_json;
+use sb_worker_context::essentials::{FetchPolicyOpts, UnstableFeature};
+use serde::Serialize;
+
+// Bumped when a field is renamed, removed or changes meaning. bootstrap.js
+// refuses options of another version instead of misreading them.
+pub const BOOTSTRAP_OPTIONS_VERSION: u32 = 1;
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum WorkerKind {
+    Main,
+    User,
+}
+
+// The extensions an isolate is built with, each kind starts from a snapshot
+// of its own.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum IsolateKind {
+    Main,
+    User,
+    // in strict mode, the user workers whose service's manifest doesn't ask
+    // for the net capability, deno_net is replaced by `sb_core_no_net`
+    UserWithoutNet,
+}
+
+impl IsolateKind {
+    pub fn worker_kind(self) -> WorkerKind {
+        match self {
+            IsolateKind::Main => WorkerKind::Main,
+            IsolateKind::User | IsolateKind::UserWithoutNet => WorkerKind::User,
+        }
+    }
+}
+
+// Runtime behaviour turned on per worker.
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BootstrapFeatures {
+    // interval of the heartbeats the event loop watchdog listens to
+    pub event_loop_heartbeat_ms: Option<u64>,
+    pub terminate_on_unhandled_rejection: bool,
+    // console output goes through `op_forward_log`, to be forwarded or limited
+    pub forward_logs: bool,
+    pub intercept_fetch: bool,
+    // bytes a fetch response body can have, unlimited if unset
+    pub max_fetch_response_bytes: Option<u64>,
+    // timeouts and retries of fetch calls, none if unset
+    pub fetch_policy: Option<FetchPolicyOpts>,
+    // count the bytes fetch, Deno.connect and WebSocket send and receive
+    pub count_net_usage: bool,
+    // count and cap the sockets the worker opens, see `SocketUsage`
+    pub count_open_sockets: bool,
+    // fail fast on hosts whose circuit is open, see `FetchBreakers`
+    pub fetch_breaker: bool,
+    // private and secret keys can't be exported, see `allow_key_export`
+    pub restrict_key_export: bool,
+    // report what each request costs the isolate, see `InvocationMeter`
+    pub meter_invocations: bool,
+    // unstable APIs the worker can use
+    pub unstable: Vec<UnstableFeature>,
+}
+
+// Everything bootstrap.js is started with, see `parseBootstrapOptions`.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BootstrapOptions {
+    pub version: u32,
+    pub target: String,
+    // keep full stack traces
+    pub debug: bool,
+    // default locale of Intl and `navigator.language`, BCP 47 tag (eg: en-US)
+    pub locale: Option<String>,
+    // default time zone of Intl and of the locale methods of Date, IANA name
+    // (eg: Europe/Paris)
+    pub timezone: Option<String>,
+    pub worker_kind: WorkerKind,
+    // added to the fetch requests that don't set them
+    pub default_headers: Vec<(String, String)>,
+    pub features: BootstrapFeatures,
+}
+
+impl BootstrapOptions {
+    pub fn new(worker_kind: WorkerKind) -> Self {
+        Self {
+            version: BOOTSTRAP_OPTIONS_VERSION,
+            target: env!("TARGET").to_string(),
+            debug: log::log_enabled!(log::Level::Debug),
+            locale: None,
+            timezone: None,
+            worker_kind,
+            default_headers: vec![],
+            features: BootstrapFeatures::default(),
+        }
+    }
+
+    pub fn as_script(&self) -> String {
+        format!(
+            "globalThis.bootstrapSBEdge({})",
+            serde_json::to_string(self).unwrap()
+        )
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_bootstrap_script() {
+        let mut opts = BootstrapOptions::new(WorkerKind::User);
+        opts.debug = false;
+        opts.locale = Some("en-US".to_string());
+        opts.features.event_loop_heartbeat_ms = Some(50);
+        opts.features.unstable = vec![UnstableFeature::Kv];
+        opts.default_headers = vec![("x-deployment-id".to_string(), "d1".to_string())];
+
+        let script = opts.as_script();
+        let json = script
+            .strip_prefix("globalThis.bootstrapSBEdge(")
+            .and_then(|s| s.strip_suffix(')'))
+            .unwrap();
+        let value: serde_json::Value = serde_json::from_str(json).unwrap();
+
+        assert_eq!(value["version"], BOOTSTRAP_OPTIONS_VERSION);
+        assert_eq!(value["workerKind"], "user");
+        assert_eq!(value["locale"], "en-US");
+        assert_eq!(value["timezone"], serde_json::Value::Null);
+        assert_eq!(
+            value["defaultHeaders"],
+            serde_json::json!([["x-deployment-id", "d1"]])
+        );
+        assert_eq!(value["features"]["eventLoopHeartbeatMs"], 50);
+        assert_eq!(value["features"]["interceptFetch"], false);
+        assert_eq!(value["features"]["countNetUsage"], false);
+        assert_eq!(value["features"]["unstable"], serde_json::json!(["kv"]));
+    }
+}
@@ -0,0 +1,262 @@
+use anyhow::{Context, Error};
+use bytes::{Bytes, BytesMut};
+use deno_core::futures::stream::{self, StreamExt};
+use hyper::body::HttpBody;
+use hyper::header::{
+    HeaderMap, HeaderName, HeaderValue, AUTHORIZATION, COOKIE, ORIGIN, SET_COOKIE,
+};
+use hyper::{Body, Method, Request, Response, StatusCode, Version};
+use sb_worker_context::essentials::CoalesceOpts;
+use std::collections::HashMap;
+use tokio::sync::oneshot;
+
+type ResponseTx = oneshot::Sender<Response<Body>>;
+
+// Identical requests have the same path, query and origin, and the same
+// values of the vary headers.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct CoalesceKey {
+    uri: String,
+    vary: Vec<Option<HeaderValue>>,
+}
+
+pub enum Admission {
+    // the request goes to the worker, its response is shared under the key
+    Send(Request<Body>, ResponseTx, Option<CoalesceKey>),
+    // an identical request is being served
+    Waiting,
+}
+
+// Holds the requests waiting for an identical one sent to a user worker.
+#[derive(Debug)]
+pub struct Coalescer {
+    vary_headers: Vec<HeaderName>,
+    max_body_bytes: usize,
+    waiting: HashMap<CoalesceKey, Vec<(Request<Body>, ResponseTx)>>,
+}
+
+impl Coalescer {
+    pub fn new(opts: &CoalesceOpts) -> Result<Self, Error> {
+        let vary_headers = opts
+            .vary_headers
+            .iter()
+            .map(|name| {
+                HeaderName::try_from(name.as_str())
+                    .with_context(|| format!("invalid vary header {}", name))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        Ok(Self {
+            vary_headers,
+            max_body_bytes: opts.max_body_bytes,
+            waiting: HashMap::new(),
+        })
+    }
+
+    pub fn max_body_bytes(&self) -> usize {
+        self.max_body_bytes
+    }
+
+    // only GETs without a body are coalesced, and never ones with credentials,
+    // whatever the vary headers: a response could be handed to another user
+    fn key(&self, req: &Request<Body>) -> Option<CoalesceKey> {
+        if req.method() != Method::GET || !req.body().is_end_stream() {
+            return None;
+        }
+        if req.headers().contains_key(AUTHORIZATION) || req.headers().contains_key(COOKIE) {
+            return None;
+        }
+        let uri = req
+            .uri()
+            .path_and_query()
+            .map_or("/", |path| path.as_str())
+            .to_string();
+        // the CORS headers of the response depend on the origin
+        let vary = std::iter::once(&ORIGIN)
+            .chain(&self.vary_headers)
+            .map(|name| req.headers().get(name).cloned())
+            .collect();
+        Some(CoalesceKey { uri, vary })
+    }
+
+    pub fn admit(&mut self, req: Request<Body>, tx: ResponseTx) -> Admission {
+        let Some(key) = self.key(&req) else {
+            return Admission::Send(req, tx, None);
+        };
+        match self.waiting.get_mut(&key) {
+            Some(waiting) => {
+                waiting.push((req, tx));
+                Admission::Waiting
+            }
+            None => {
+                self.waiting.insert(key.clone(), vec![]);
+                Admission::Send(req, tx, Some(key))
+            }
+        }
+    }
+
+    // the requests that waited for the one sent under `key`
+    pub fn finish(&mut self, key: &CoalesceKey) -> Vec<(Request<Body>, ResponseTx)> {
+        self.waiting.remove(key).unwrap_or_default()
+    }
+}
+
+// A response read in full, handed out to the requests that waited for it.
+#[derive(Debug)]
+pub struct SharedResponse {
+    status: StatusCode,
+    version: Version,
+    headers: HeaderMap,
+    body: Bytes,
+}
+
+impl SharedResponse {
+    pub fn to_response(&self) -> Response<Body> {
+        let mut res = Response::new(Body::from(self.body.clone()));
+        *res.status_mut() = self.status;
+        *res.version_mut() = self.version;
+        *res.headers_mut() = self.headers.clone();
+        res
+    }
+}
+
+// Reads the response of a coalesced request, and returns it along with a
+// copy to share. Responses setting cookies, larger than `max_body_bytes` or
+// failing to be read aren't shared.
+pub async fn share(
+    res: Response<Body>,
+    max_body_bytes: usize,
+) -> (Response<Body>, Option<SharedResponse>) {
+    if res.headers().contains_key(SET_COOKIE) {
+        return (res, None);
+    }
+
+    let (parts, mut body) = res.into_parts();
+    let mut buf = BytesMut::new();
+    loop {
+        match body.data().await {
+            None => break,
+            Some(Ok(chunk)) if buf.len() + chunk.len() <= max_body_bytes => {
+                buf.extend_from_slice(&chunk);
+            }
+            // stream what was read so far and the rest
+            Some(chunk) => {
+                let read = stream::iter([Ok(buf.freeze()), chunk]).chain(body);
+                return (Response::from_parts(parts, Body::wrap_stream(read)), None);
+            }
+        }
+    }
+
+    let shared = SharedResponse {
+        status: parts.status,
+        version: parts.version,
+        headers: parts.headers,
+        body: buf.freeze(),
+    };
+    (shared.to_response(), Some(shared))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn request(method: Method, uri: &str, accept: &'static str) -> Request<Body> {
+        Request::builder()
+            .method(method)
+            .uri(uri)
+            .header("accept", accept)
+            .body(Body::empty())
+            .unwrap()
+    }
+
+    #[test]
+    fn test_admit() {
+        let mut coalescer = Coalescer::new(&CoalesceOpts::default()).unwrap();
+        let mut admit = |req| {
+            let (tx, _rx) = oneshot::channel();
+            match coalescer.admit(req, tx) {
+                Admission::Send(_, _, key) => Some(key),
+                Admission::Waiting => None,
+            }
+        };
+
+        let first = admit(request(Method::GET, "http://localhost/a?x=1", "*/*"));
+        let Some(Some(key)) = first else {
+            panic!("the first request should be sent");
+        };
+        assert!(admit(request(Method::GET, "http://localhost/a?x=1", "*/*")).is_none());
+
+        // other paths, queries and vary headers are sent
+        for req in [
+            request(Method::GET, "http://localhost/a?x=2", "*/*"),
+            request(Method::GET, "http://localhost/a?x=1", "text/html"),
+        ] {
+            assert!(matches!(admit(req), Some(Some(_))));
+        }
+        // as are other methods, without being coalesced
+        assert!(matches!(
+            admit(request(Method::POST, "http://localhost/a?x=1", "*/*")),
+            Some(None)
+        ));
+
+        assert_eq!(coalescer.finish(&key).len(), 1);
+        assert!(coalescer.finish(&key).is_empty());
+    }
+
+    #[test]
+    fn test_credentials_not_coalesced() {
+        // even when the vary headers don't include them
+        for vary_headers in [
+            vec![],
+            vec!["authorization".to_string(), "cookie".to_string()],
+        ] {
+            let mut coalescer = Coalescer::new(&CoalesceOpts {
+                vary_headers,
+                ..Default::default()
+            })
+            .unwrap();
+            for (name, value) in [("authorization", "Bearer a"), ("cookie", "session=a")] {
+                for _ in 0..2 {
+                    let req = Request::get("http://localhost/a")
+                        .header(name, value)
+                        .body(Body::empty())
+                        .unwrap();
+                    let (tx, _rx) = oneshot::channel();
+                    assert!(matches!(
+                        coalescer.admit(req, tx),
+                        Admission::Send(_, _, None)
+                    ));
+                }
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_share() {
+        let res = Response::builder()
+            .header("content-type", "text/plain")
+            .body(Body::from("hello"))
+            .unwrap();
+        let (res, shared) = share(res, 16).await;
+        let shared = shared.unwrap().to_response();
+        assert_eq!(shared.headers().get("content-type").unwrap(), "text/plain");
+        for res in [res, shared] {
+            let body = hyper::body::to_bytes(res.into_body()).await.unwrap();
+            assert_eq!(body, "hello");
+        }
+
+        // larger ones are still streamed in full
+        let chunks: Vec<Result<_, std::io::Error>> = vec![Ok("hello "), Ok("world")];
+        let res = Response::new(Body::wrap_stream(stream::iter(chunks)));
+        let (res, shared) = share(res, 8).await;
+        assert!(shared.is_none());
+        let body = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        assert_eq!(body, "hello world");
+
+        let res = Response::builder()
+            .header(SET_COOKIE, "session=1")
+            .body(Body::from("hello"))
+            .unwrap();
+        assert!(share(res, 16).await.1.is_none());
+    }
+}
@@ -1,20 +1,164 @@
-use crate::server::Server;
-use anyhow::Error;
+use crate::proxy::TrustedProxies;
+use crate::server::{KeepAliveOpts, Server};
+use crate::service_source::{self, ServiceSourceResolver};
+use crate::utils::files::collect_files;
+use crate::worker_ctx::UserWorkerPoolOpts;
+use anyhow::{anyhow, bail, Error};
+use deno_ast::{EmitOptions, MediaType, ParseParams, SourceTextInfo};
+use deno_core::serde_json::{self, json};
+use module_fetcher::cache::DenoDir;
+use std::fs;
+use std::path::Path;
+use url::Url;
+
+const SERVE_MAIN_TEMPLATE: &str = include_str!("serve_main.ts");
 
 pub async fn start_server(
     ip: &str,
     port: u16,
     main_service_path: String,
-    import_map_path: Option<String>,
-    no_module_cache: bool,
+    pool_opts: UserWorkerPoolOpts,
+    keep_alive: KeepAliveOpts,
+    trusted_proxies: TrustedProxies,
 ) -> Result<(), Error> {
     let mut server = Server::new(
         ip,
         port,
         main_service_path,
-        import_map_path,
-        no_module_cache,
+        pool_opts,
+        keep_alive,
+        trusted_proxies,
     )
     .await?;
     server.listen().await
 }
+
+// Serves every service in `functions_dir` under `/<service name>`, using a
+// generated main service.
+pub async fn serve_functions(
+    ip: &str,
+    port: u16,
+    functions_dir: &Path,
+    pool_opts: UserWorkerPoolOpts,
+    keep_alive: KeepAliveOpts,
+    trusted_proxies: TrustedProxies,
+    type_check: bool,
+) -> Result<(), Error> {
+    if !functions_dir.is_dir() {
+        bail!("functions directory does not exist {:?}", functions_dir);
+    }
+
+    let functions_dir = fs::canonicalize(functions_dir)?;
+    let main_service_path = std::env::temp_dir()
+        .join("edge-runtime")
+        .join(format!("serve-main-{}", std::process::id()));
+    fs::create_dir_all(&main_service_path)?;
+    fs::write(
+        main_service_path.join("index.ts"),
+        SERVE_MAIN_TEMPLATE
+            .replace(
+                "__FUNCTIONS_DIR__",
+                &serde_json::to_string(&functions_dir.to_string_lossy())?,
+            )
+            .replace("__TYPE_CHECK__", &type_check.to_string()),
+    )?;
+
+    start_server(
+        ip,
+        port,
+        main_service_path.to_string_lossy().to_string(),
+        pool_opts,
+        keep_alive,
+        trusted_proxies,
+    )
+    .await
+}
+
+fn is_source_file(path: &Path) -> bool {
+    matches!(
+        MediaType::from(path),
+        MediaType::JavaScript
+            | MediaType::Jsx
+            | MediaType::Mjs
+            | MediaType::TypeScript
+            | MediaType::Mts
+            | MediaType::Tsx
+    )
+}
+
+// Parses and transpiles every module of a service without running it.
+// Returns the diagnostics found, an empty list means the service is valid.
+pub fn check_service(service_path: &Path) -> Result<Vec<String>, Error> {
+    if !service_path.join("index.ts").exists() {
+        bail!("service entrypoint not found in {:?}", service_path);
+    }
+
+    let files = collect_files(service_path, &is_source_file)?;
+
+    let mut diagnostics = vec![];
+    for file in files {
+        let specifier = Url::from_file_path(fs::canonicalize(&file)?)
+            .map_err(|_| anyhow!("invalid module path {:?}", file))?;
+        let parsed = deno_ast::parse_module(ParseParams {
+            specifier: specifier.to_string(),
+            text_info: SourceTextInfo::from_string(fs::read_to_string(&file)?),
+            media_type: MediaType::from(file.as_path()),
+            capture_tokens: false,
+            scope_analysis: false,
+            maybe_syntax: None,
+        });
+
+        let result = parsed.map_err(|err| err.to_string()).and_then(|parsed| {
+            parsed
+                .transpile(&EmitOptions::default())
+                .map_err(|err| err.to_string())
+        });
+        if let Err(err) = result {
+            diagnostics.push(err);
+        }
+    }
+
+    Ok(diagnostics)
+}
+
+// Resolved configuration the server would start with.
+pub fn inspect_config(
+    main_service_path: &str,
+    import_map_path: Option<&str>,
+    no_module_cache: bool,
+    trusted_keys: &[String],
+) -> Result<serde_json::Value, Error> {
+    let main_path = Path::new(main_service_path);
+    let is_remote = service_source::is_remote(main_path);
+    let resolved_main_path = if is_remote {
+        main_service_path.to_string()
+    } else {
+        fs::canonicalize(main_path)
+            .unwrap_or_else(|_| main_path.to_path_buf())
+            .to_string_lossy()
+            .to_string()
+    };
+
+    let module_cache_dir = DenoDir::new(None)?.deps_folder_path();
+
+    Ok(json!({
+        "mainService": {
+            "path": resolved_main_path,
+            "remote": is_remote,
+            "exists": is_remote || main_path.join("index.ts").exists(),
+        },
+        "importMap": import_map_path.map(|path| json!({
+            "path": path,
+            "exists": Path::new(path).exists(),
+        })),
+        "moduleCache": {
+            "enabled": !no_module_cache,
+            "path": module_cache_dir,
+        },
+        "serviceSources": {
+            "cachePath": ServiceSourceResolver::default_cache_dir()?,
+            "trustedKeys": trusted_keys.len(),
+            "requireSignatures": !trusted_keys.is_empty(),
+        },
+    }))
+}
@@ -0,0 +1,111 @@
+use anyhow::{bail, Context, Error};
+use chrono::{NaiveDate, Utc};
+use sb_worker_context::essentials::CompatFlag;
+
+const DATE_FORMAT: &str = "%Y-%m-%d";
+
+// Behaviour changes, with the name services turn them on (`<name>`) or off
+// (`no_<name>`) with, and the compatibility date they're on from. Changes are
+// only appended here, a date is never moved back.
+const COMPAT_FLAGS: &[(CompatFlag, &str, &str)] = &[
+    (
+        CompatFlag::UnhandledRejectionTerminates,
+        "unhandled_rejection_terminates",
+        "2026-09-01",
+    ),
+    (
+        CompatFlag::RestrictKeyExport,
+        "restrict_key_export",
+        "2026-10-01",
+    ),
+];
+
+fn parse_date(date: &str) -> Result<NaiveDate, Error> {
+    NaiveDate::parse_from_str(date, DATE_FORMAT)
+        .with_context(|| format!("invalid compatibility date {:?}, expected YYYY-MM-DD", date))
+}
+
+// The behaviour changes of a service, the ones on at its compatibility date
+// (none without one) and the ones it turns on or off explicitly. A date in
+// the future is refused, it would turn on the changes made until then.
+pub fn compat_flags(date: Option<&str>, overrides: &[String]) -> Result<Vec<CompatFlag>, Error> {
+    let date = date.map(parse_date).transpose()?;
+    if let Some(date) = date {
+        if date > Utc::now().naive_utc().date() {
+            bail!(
+                "the compatibility date {} is in the future",
+                date.format(DATE_FORMAT)
+            );
+        }
+    }
+
+    let mut flags = vec![];
+    for &(flag, name, on_from) in COMPAT_FLAGS {
+        let on_from = parse_date(on_from)?;
+        let mut on = date.map_or(false, |date| date >= on_from);
+        for value in overrides {
+            if value == name {
+                on = true;
+            } else if value.strip_prefix("no_") == Some(name) {
+                on = false;
+            }
+        }
+        if on {
+            flags.push(flag);
+        }
+    }
+
+    for value in overrides {
+        let name = value.strip_prefix("no_").unwrap_or(value);
+        if !COMPAT_FLAGS.iter().any(|(_, known, _)| *known == name) {
+            bail!("unknown compatibility flag {:?}", value);
+        }
+    }
+    Ok(flags)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_compat_flags() {
+        assert_eq!(compat_flags(None, &[]).unwrap(), vec![]);
+        assert_eq!(
+            compat_flags(Some("2026-09-15"), &[]).unwrap(),
+            vec![CompatFlag::UnhandledRejectionTerminates]
+        );
+        assert_eq!(
+            compat_flags(Some("2026-10-01"), &[]).unwrap(),
+            vec![
+                CompatFlag::UnhandledRejectionTerminates,
+                CompatFlag::RestrictKeyExport
+            ]
+        );
+
+        // turned on before their date, or off after it
+        assert_eq!(
+            compat_flags(
+                Some("2026-10-01"),
+                &[
+                    "no_unhandled_rejection_terminates".to_string(),
+                    "restrict_key_export".to_string()
+                ]
+            )
+            .unwrap(),
+            vec![CompatFlag::RestrictKeyExport]
+        );
+        assert_eq!(
+            compat_flags(None, &["restrict_key_export".to_string()]).unwrap(),
+            vec![CompatFlag::RestrictKeyExport]
+        );
+    }
+
+    #[test]
+    fn test_invalid_compat_flags() {
+        assert!(compat_flags(Some("2026-13-01"), &[]).is_err());
+        assert!(compat_flags(Some("next year"), &[]).is_err());
+        assert!(compat_flags(Some("2999-01-01"), &[]).is_err());
+        assert!(compat_flags(None, &["streaming_everything".to_string()]).is_err());
+    }
+}
@@ -0,0 +1,278 @@
+use anyhow::{bail, Error};
+use hyper::header::{
+    HeaderMap, HeaderValue, ACCESS_CONTROL_ALLOW_CREDENTIALS, ACCESS_CONTROL_ALLOW_HEADERS,
+    ACCESS_CONTROL_ALLOW_METHODS, ACCESS_CONTROL_ALLOW_ORIGIN, ACCESS_CONTROL_EXPOSE_HEADERS,
+    ACCESS_CONTROL_MAX_AGE, ACCESS_CONTROL_REQUEST_HEADERS, ACCESS_CONTROL_REQUEST_METHOD, ORIGIN,
+    VARY,
+};
+use hyper::{Body, Method, Request, Response, StatusCode};
+use serde::Deserialize;
+
+// The origins allowed to call a service from a browser, answered by the
+// runtime so its workers don't have to.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct CorsConfig {
+    // eg: https://app.example.com, `*` for any
+    pub origins: Vec<String>,
+    pub methods: Vec<String>,
+    // request headers the browser can send, the ones it asks for if empty
+    pub headers: Vec<String>,
+    // response headers the browser's scripts can read
+    pub expose_headers: Vec<String>,
+    // how long preflight responses are cached, in seconds
+    pub max_age: Option<u64>,
+    // cookies and authorization headers, not with `*` as the origins
+    pub credentials: bool,
+}
+
+impl Default for CorsConfig {
+    fn default() -> Self {
+        Self {
+            origins: vec![],
+            methods: ["GET", "HEAD", "PUT", "PATCH", "POST", "DELETE"]
+                .iter()
+                .map(|method| method.to_string())
+                .collect(),
+            headers: vec![],
+            expose_headers: vec![],
+            max_age: None,
+            credentials: false,
+        }
+    }
+}
+
+// an OPTIONS request sent by a browser before the actual one
+pub fn is_preflight(req: &Request<Body>) -> bool {
+    req.method() == Method::OPTIONS
+        && req.headers().contains_key(ORIGIN)
+        && req.headers().contains_key(ACCESS_CONTROL_REQUEST_METHOD)
+}
+
+impl CorsConfig {
+    pub(crate) fn validate(&self) -> Result<(), Error> {
+        // any site could make requests with the user's cookies
+        if self.credentials && self.origins.iter().any(|origin| origin == "*") {
+            bail!("cors credentials can't be allowed for any origin (`*`)");
+        }
+        for method in &self.methods {
+            if Method::from_bytes(method.as_bytes()).is_err() {
+                bail!("invalid cors method {}", method);
+            }
+        }
+        for value in [&self.methods, &self.headers, &self.expose_headers] {
+            if HeaderValue::from_str(&value.join(", ")).is_err() {
+                bail!("invalid cors headers {:?}", value);
+            }
+        }
+        Ok(())
+    }
+
+    fn allow_origin(&self, origin: &HeaderValue) -> Option<HeaderValue> {
+        if self.origins.iter().any(|allowed| allowed == "*") {
+            return Some(HeaderValue::from_static("*"));
+        }
+        let origin_str = origin.to_str().ok()?;
+        self.origins
+            .iter()
+            .any(|allowed| {
+                allowed
+                    .trim_end_matches('/')
+                    .eq_ignore_ascii_case(origin_str)
+            })
+            .then(|| origin.clone())
+    }
+
+    fn allows_method(&self, method: &str) -> bool {
+        self.methods
+            .iter()
+            .any(|allowed| allowed.eq_ignore_ascii_case(method))
+    }
+
+    // headers of the origin's access to a response
+    fn set_origin(&self, allow_origin: HeaderValue, headers: &mut HeaderMap) {
+        // responses differ by origin, caches must keep them apart
+        if allow_origin != "*" {
+            headers.append(VARY, HeaderValue::from_static("origin"));
+        }
+        headers.insert(ACCESS_CONTROL_ALLOW_ORIGIN, allow_origin);
+        if self.credentials {
+            headers.insert(
+                ACCESS_CONTROL_ALLOW_CREDENTIALS,
+                HeaderValue::from_static("true"),
+            );
+        }
+    }
+
+    // The response to a preflight request. It doesn't allow anything when the
+    // origin or the method isn't allowed, which the browser reports as a
+    // CORS error.
+    pub fn preflight(&self, req_headers: &HeaderMap) -> Response<Body> {
+        let mut res = Response::builder()
+            .status(StatusCode::NO_CONTENT)
+            .body(Body::empty())
+            .unwrap();
+        let headers = res.headers_mut();
+
+        let allow_origin = req_headers
+            .get(ORIGIN)
+            .and_then(|origin| self.allow_origin(origin));
+        let method_allowed = req_headers
+            .get(ACCESS_CONTROL_REQUEST_METHOD)
+            .and_then(|method| method.to_str().ok())
+            .map_or(false, |method| self.allows_method(method));
+        let (Some(allow_origin), true) = (allow_origin, method_allowed) else {
+            headers.insert(VARY, HeaderValue::from_static("origin"));
+            return res;
+        };
+
+        self.set_origin(allow_origin, headers);
+        if let Ok(methods) = HeaderValue::from_str(&self.methods.join(", ")) {
+            headers.insert(ACCESS_CONTROL_ALLOW_METHODS, methods);
+        }
+        let allow_headers = if self.headers.is_empty() {
+            req_headers.get(ACCESS_CONTROL_REQUEST_HEADERS).cloned()
+        } else {
+            HeaderValue::from_str(&self.headers.join(", ")).ok()
+        };
+        if let Some(allow_headers) = allow_headers {
+            headers.insert(ACCESS_CONTROL_ALLOW_HEADERS, allow_headers);
+        }
+        if let Some(max_age) = self.max_age {
+            headers.insert(ACCESS_CONTROL_MAX_AGE, HeaderValue::from(max_age));
+        }
+        res
+    }
+
+    // adds the CORS headers to the response of a request from `origin`
+    pub fn apply(&self, origin: Option<&HeaderValue>, headers: &mut HeaderMap) {
+        let Some(allow_origin) = origin.and_then(|origin| self.allow_origin(origin)) else {
+            return;
+        };
+        self.set_origin(allow_origin, headers);
+        if !self.expose_headers.is_empty() {
+            if let Ok(expose) = HeaderValue::from_str(&self.expose_headers.join(", ")) {
+                headers.insert(ACCESS_CONTROL_EXPOSE_HEADERS, expose);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn config() -> CorsConfig {
+        CorsConfig {
+            origins: vec!["https://app.example.com".to_string()],
+            methods: vec!["GET".to_string(), "POST".to_string()],
+            max_age: Some(600),
+            expose_headers: vec!["x-request-id".to_string()],
+            ..Default::default()
+        }
+    }
+
+    fn preflight_request(origin: &'static str, method: &'static str) -> Request<Body> {
+        Request::options("http://localhost/hello")
+            .header(ORIGIN, origin)
+            .header(ACCESS_CONTROL_REQUEST_METHOD, method)
+            .header(ACCESS_CONTROL_REQUEST_HEADERS, "content-type")
+            .body(Body::empty())
+            .unwrap()
+    }
+
+    #[test]
+    fn test_preflight() {
+        let req = preflight_request("https://app.example.com", "POST");
+        assert!(is_preflight(&req));
+
+        let res = config().preflight(req.headers());
+        assert_eq!(res.status(), StatusCode::NO_CONTENT);
+        let headers = res.headers();
+        assert_eq!(
+            headers.get(ACCESS_CONTROL_ALLOW_ORIGIN).unwrap(),
+            "https://app.example.com"
+        );
+        assert_eq!(
+            headers.get(ACCESS_CONTROL_ALLOW_METHODS).unwrap(),
+            "GET, POST"
+        );
+        assert_eq!(
+            headers.get(ACCESS_CONTROL_ALLOW_HEADERS).unwrap(),
+            "content-type"
+        );
+        assert_eq!(headers.get(ACCESS_CONTROL_MAX_AGE).unwrap(), "600");
+        assert_eq!(headers.get(VARY).unwrap(), "origin");
+
+        // other origins and methods aren't allowed anything
+        for req in [
+            preflight_request("https://evil.example.com", "POST"),
+            preflight_request("https://app.example.com", "DELETE"),
+        ] {
+            let res = config().preflight(req.headers());
+            assert!(res.headers().get(ACCESS_CONTROL_ALLOW_ORIGIN).is_none());
+        }
+
+        let req = Request::options("http://localhost/hello")
+            .body(Body::empty())
+            .unwrap();
+        assert!(!is_preflight(&req));
+    }
+
+    #[test]
+    fn test_apply() {
+        let origin = HeaderValue::from_static("https://app.example.com");
+        let mut headers = HeaderMap::new();
+        config().apply(Some(&origin), &mut headers);
+        assert_eq!(headers.get(ACCESS_CONTROL_ALLOW_ORIGIN).unwrap(), origin);
+        assert_eq!(
+            headers.get(ACCESS_CONTROL_EXPOSE_HEADERS).unwrap(),
+            "x-request-id"
+        );
+
+        // same origin requests don't send one
+        let mut headers = HeaderMap::new();
+        config().apply(None, &mut headers);
+        assert!(headers.is_empty());
+
+        let any = CorsConfig {
+            origins: vec!["*".to_string()],
+            ..Default::default()
+        };
+        let mut headers = HeaderMap::new();
+        any.apply(Some(&origin), &mut headers);
+        assert_eq!(headers.get(ACCESS_CONTROL_ALLOW_ORIGIN).unwrap(), "*");
+        assert!(headers.get(VARY).is_none());
+
+        let credentials = CorsConfig {
+            credentials: true,
+            ..config()
+        };
+        let mut headers = HeaderMap::new();
+        credentials.apply(Some(&origin), &mut headers);
+        assert_eq!(headers.get(ACCESS_CONTROL_ALLOW_ORIGIN).unwrap(), origin);
+        assert_eq!(
+            headers.get(ACCESS_CONTROL_ALLOW_CREDENTIALS).unwrap(),
+            "true"
+        );
+    }
+
+    #[test]
+    fn test_validate() {
+        assert!(config().validate().is_ok());
+
+        // credentials must name the origins they're sent to
+        let any = CorsConfig {
+            origins: vec!["*".to_string()],
+            credentials: true,
+            ..Default::default()
+        };
+        assert!(any.validate().is_err());
+
+        let methods = CorsConfig {
+            methods: vec!["GET POST".to_string()],
+            ..config()
+        };
+        assert!(methods.validate().is_err());
+    }
+}
@@ -0,0 +1,273 @@
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct DeploymentVersion {
+    pub version: String,
+    pub service_path: PathBuf,
+    // share of the traffic routed to this version, relative to the other active versions
+    pub weight: u32,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum DeploymentRoutingRule {
+    // split traffic between versions based on their weights
+    Weighted,
+    // use the version named by the given request header, falling back to weights
+    Header(String),
+}
+
+#[derive(Debug)]
+struct ServiceDeployments {
+    active: Vec<DeploymentVersion>,
+    // versions that no longer receive traffic, but still have live workers
+    draining: Vec<DeploymentVersion>,
+    rule: DeploymentRoutingRule,
+    live_workers: HashMap<String, usize>,
+    routed: u64,
+}
+
+impl ServiceDeployments {
+    fn drain(&mut self, version: DeploymentVersion) {
+        if self
+            .live_workers
+            .get(&version.version)
+            .copied()
+            .unwrap_or(0)
+            > 0
+        {
+            self.draining.push(version);
+        }
+    }
+
+    fn pick_weighted(&mut self) -> Option<DeploymentVersion> {
+        let total: u64 = self.active.iter().map(|v| v.weight as u64).sum();
+        if total == 0 {
+            return self.active.first().cloned();
+        }
+
+        let mut slot = self.routed % total;
+        self.routed = self.routed.wrapping_add(1);
+
+        for version in &self.active {
+            if slot < version.weight as u64 {
+                return Some(version.clone());
+            }
+            slot -= version.weight as u64;
+        }
+
+        None
+    }
+}
+
+// Routes user worker creation for a named service between its deployed
+// versions. Cloning the router shares the underlying routing table, so
+// embedders can shift traffic while the pool is running.
+#[derive(Debug, Clone, Default)]
+pub struct DeploymentRouter {
+    services: Arc<Mutex<HashMap<String, ServiceDeployments>>>,
+}
+
+impl DeploymentRouter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // Sets the versions that receive traffic for a service. Previously active
+    // versions that are left out are drained.
+    pub fn deploy(
+        &self,
+        service_name: &str,
+        versions: Vec<DeploymentVersion>,
+        rule: DeploymentRoutingRule,
+    ) {
+        let mut services = self.services.lock().unwrap();
+        let service =
+            services
+                .entry(service_name.to_string())
+                .or_insert_with(|| ServiceDeployments {
+                    active: vec![],
+                    draining: vec![],
+                    rule: rule.clone(),
+                    live_workers: HashMap::new(),
+                    routed: 0,
+                });
+
+        let previous = std::mem::replace(&mut service.active, versions);
+        for version in previous {
+            if !service.active.iter().any(|v| v.version == version.version) {
+                service.drain(version);
+            }
+        }
+        service
+            .draining
+            .retain(|d| !service.active.iter().any(|v| v.version == d.version));
+        service.rule = rule;
+    }
+
+    pub fn set_weight(&self, service_name: &str, version: &str, weight: u32) -> bool {
+        let mut services = self.services.lock().unwrap();
+        let Some(service) = services.get_mut(service_name) else {
+            return false;
+        };
+
+        match service.active.iter_mut().find(|v| v.version == version) {
+            Some(v) => {
+                v.weight = weight;
+                true
+            }
+            None => false,
+        }
+    }
+
+    // Routes all traffic to the given version and drains the others.
+    pub fn cutover(&self, service_name: &str, version: &str) -> bool {
+        let mut services = self.services.lock().unwrap();
+        let Some(service) = services.get_mut(service_name) else {
+            return false;
+        };
+
+        if !service.active.iter().any(|v| v.version == version) {
+            return false;
+        }
+
+        let (kept, drained): (Vec<_>, Vec<_>) = std::mem::take(&mut service.active)
+            .into_iter()
+            .partition(|v| v.version == version);
+        service.active = kept;
+        for v in drained {
+            service.drain(v);
+        }
+
+        true
+    }
+
+    pub fn route(
+        &self,
+        service_name: &str,
+        headers: &HashMap<String, String>,
+    ) -> Option<DeploymentVersion> {
+        let mut services = self.services.lock().unwrap();
+        let service = services.get_mut(service_name)?;
+
+        if let DeploymentRoutingRule::Header(name) = &service.rule {
+            let requested = headers.get(&name.to_lowercase());
+            if let Some(v) = service
+                .active
+                .iter()
+                .find(|v| Some(&v.version) == requested)
+            {
+                return Some(v.clone());
+            }
+        }
+
+        service.pick_weighted()
+    }
+
+    pub fn draining_versions(&self, service_name: &str) -> Vec<String> {
+        let services = self.services.lock().unwrap();
+        services
+            .get(service_name)
+            .map(|s| s.draining.iter().map(|v| v.version.clone()).collect())
+            .unwrap_or_default()
+    }
+
+    pub fn worker_started(&self, service_name: &str, version: &str) {
+        let mut services = self.services.lock().unwrap();
+        if let Some(service) = services.get_mut(service_name) {
+            *service.live_workers.entry(version.to_string()).or_insert(0) += 1;
+        }
+    }
+
+    // Forgets a draining version once its last worker exits.
+    pub fn worker_exited(&self, service_name: &str, version: &str) {
+        let mut services = self.services.lock().unwrap();
+        let Some(service) = services.get_mut(service_name) else {
+            return;
+        };
+
+        let remaining = match service.live_workers.get_mut(version) {
+            Some(count) => {
+                *count = count.saturating_sub(1);
+                *count
+            }
+            None => return,
+        };
+
+        if remaining == 0 {
+            service.live_workers.remove(version);
+            service.draining.retain(|v| v.version != version);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn version(name: &str, weight: u32) -> DeploymentVersion {
+        DeploymentVersion {
+            version: name.to_string(),
+            service_path: PathBuf::from(format!("./services/{}", name)),
+            weight,
+        }
+    }
+
+    #[test]
+    fn test_weighted_routing() {
+        let router = DeploymentRouter::new();
+        router.deploy(
+            "hello",
+            vec![version("blue", 3), version("green", 1)],
+            DeploymentRoutingRule::Weighted,
+        );
+
+        let routed: Vec<String> = (0..8)
+            .map(|_| router.route("hello", &HashMap::new()).unwrap().version)
+            .collect();
+        assert_eq!(routed.iter().filter(|v| *v == "blue").count(), 6);
+        assert_eq!(routed.iter().filter(|v| *v == "green").count(), 2);
+        assert!(router.route("unknown", &HashMap::new()).is_none());
+    }
+
+    #[test]
+    fn test_header_routing() {
+        let router = DeploymentRouter::new();
+        router.deploy(
+            "hello",
+            vec![version("blue", 1), version("green", 0)],
+            DeploymentRoutingRule::Header("X-Deployment-Version".to_string()),
+        );
+
+        let headers = HashMap::from([("x-deployment-version".to_string(), "green".to_string())]);
+        assert_eq!(router.route("hello", &headers).unwrap().version, "green");
+        assert_eq!(
+            router.route("hello", &HashMap::new()).unwrap().version,
+            "blue"
+        );
+    }
+
+    #[test]
+    fn test_cutover_drains_old_versions() {
+        let router = DeploymentRouter::new();
+        router.deploy(
+            "hello",
+            vec![version("blue", 1), version("green", 1)],
+            DeploymentRoutingRule::Weighted,
+        );
+        router.worker_started("hello", "blue");
+
+        assert!(router.cutover("hello", "green"));
+        assert_eq!(router.draining_versions("hello"), vec!["blue".to_string()]);
+        for _ in 0..4 {
+            assert_eq!(
+                router.route("hello", &HashMap::new()).unwrap().version,
+                "green"
+            );
+        }
+
+        router.worker_exited("hello", "blue");
+        assert!(router.draining_versions("hello").is_empty());
+    }
+}
@@ -1,66 +1,164 @@
 use crate::utils::units::{bytes_to_display, human_elapsed, mib_to_bytes};
 
+use crate::bootstrap::{BootstrapFeatures, BootstrapOptions, IsolateKind, WorkerKind};
+use crate::js_worker::code_cache::{
+    code_cache_enabled, prime_modules, update_code_cache, CodeCache, LoadedModule,
+};
+use crate::js_worker::import_map::{load_import_map, load_service_import_map};
 use crate::js_worker::module_loader;
-use anyhow::{bail, Error};
-use deno_core::error::AnyError;
+use crate::monitor::monitor_isolate;
+use crate::runtime_info::runtime_info;
+use crate::watchdog::{heartbeat_interval_ms, EventLoopWatchdog, StackCaptureCtx};
+use crate::worker_handles::{register_worker_handle, WorkerHandle};
+use anyhow::{anyhow, bail, Error};
+use deno_core::error::{AnyError, JsError};
+use deno_core::futures::channel::oneshot as futures_oneshot;
+use deno_core::futures::future::poll_fn;
+use deno_core::futures::FutureExt;
 use deno_core::url::Url;
+use deno_core::CompiledWasmModuleStore;
+use deno_core::Extension;
 use deno_core::JsRuntime;
+use deno_core::ModuleLoader;
 use deno_core::ModuleSpecifier;
+use deno_core::ResolutionKind;
 use deno_core::RuntimeOptions;
 use deno_core::{located_script_name, serde_v8};
-use import_map::{parse_from_json, ImportMap, ImportMapDiagnostic};
+use deno_tls::rustls::RootCertStore;
 use log::{debug, error, warn};
+use once_cell::sync::{Lazy, OnceCell};
 use serde::de::DeserializeOwned;
 use std::collections::HashMap;
-use std::fs;
 use std::panic;
-use std::path::Path;
 use std::rc::Rc;
-use std::thread;
-use std::time::Duration;
+use std::sync::Arc;
+use std::task::Poll;
+use std::time::{Duration, Instant};
 use tokio::net::UnixStream;
 use tokio::sync::mpsc;
 use tokio::sync::oneshot;
+use tokio::sync::watch;
 
 use crate::snapshot;
 use module_loader::DefaultModuleLoader;
-use sb_core::http_start::sb_core_http;
+#[cfg(feature = "ai")]
+use sb_ai::backend::ai_backend_configured;
+#[cfg(feature = "ai")]
+use sb_ai::{sb_ai, AiWorkerState};
+#[cfg(feature = "cache")]
+use sb_cache::{sb_cache, CacheWorkerState};
+use sb_core::billing::{sb_core_billing, InvocationMeter};
+use sb_core::blob::{sb_core_blob, BlobSpillState};
+use sb_core::compression::sb_core_compression;
+use sb_core::deadline::{sb_core_deadline, wait_for_deadline, WorkerDeadline};
+use sb_core::event_loop::{sb_core_event_loop, EventLoopHeartbeat};
+use sb_core::fetch_breaker::sb_core_fetch_breaker;
+use sb_core::fetch_intercept::{sb_core_fetch_intercept, FetchInterceptorState};
+use sb_core::http_start::{sb_core_http, HttpBackpressure, HttpBufPool};
+use sb_core::lazy_tls::sb_core_lazy_tls;
+use sb_core::log_sinks::WorkerLogSinks;
+use sb_core::logs::{sb_core_logs, LogForwarder, LogLimiter};
 use sb_core::net::sb_core_net;
+use sb_core::net_usage::{sb_core_net_usage, NetUsageState};
+use sb_core::no_net::sb_core_no_net;
+use sb_core::open_sockets::{sb_core_open_sockets, SocketUsageState};
+use sb_core::permission_prompt::permission_prompter;
 use sb_core::permissions::{sb_core_permissions, Permissions};
-use sb_core::runtime::sb_core_runtime;
-use sb_core::sb_core_main_js;
+use sb_core::router::sb_core_router;
+use sb_core::runtime::{sb_core_runtime, RuntimeInfo};
+use sb_core::streams::sb_core_streams;
+use sb_core::uncaught_errors::{sb_core_uncaught_errors, UncaughtErrorKind, UncaughtErrorReporter};
+use sb_core::{sb_core_edge_runtime_apis, sb_core_main_js, sb_core_main_worker_js};
 use sb_env::sb_env as sb_env_op;
+#[cfg(feature = "jwt")]
+use sb_jwt::keys::jwt_keys_configured;
+#[cfg(feature = "jwt")]
+use sb_jwt::{sb_jwt, JwtWorkerState};
+#[cfg(feature = "mail")]
+use sb_mail::mailer::mailer_configured;
+#[cfg(feature = "mail")]
+use sb_mail::{sb_mail, MailWorkerState};
+#[cfg(feature = "postgres")]
+use sb_postgres::{sb_postgres, PgWorkerState};
+#[cfg(feature = "queue")]
+use sb_queue::{sb_queue, QueueWorkerState};
+#[cfg(feature = "rate_limit")]
+use sb_rate_limit::{sb_rate_limit, RateLimitWorkerState};
+#[cfg(feature = "storage")]
+use sb_storage::store::storage_configured;
+#[cfg(feature = "storage")]
+use sb_storage::{sb_storage, StorageWorkerState};
 use sb_worker_context::essentials::{
-    EdgeContextInitOpts, EdgeContextOpts, EdgeUserRuntimeOpts, UserWorkerMsgs,
+    Capability, ClientCertOpts, CompatFlag, EdgeContextInitOpts, EdgeContextOpts,
+    EdgeUserRuntimeOpts, OutboundOpts, ServiceBindings, UserWorkerMsgs,
 };
-use sb_workers::sb_user_workers;
-
-fn load_import_map(maybe_path: Option<String>) -> Result<Option<ImportMap>, Error> {
-    if let Some(path_str) = maybe_path {
-        let path = Path::new(&path_str);
-        let json_str = fs::read_to_string(path)?;
-
-        let abs_path = std::env::current_dir().map(|p| p.join(path))?;
-        let base_url = Url::from_directory_path(abs_path.parent().unwrap()).unwrap();
-        let result = parse_from_json(&base_url, json_str.as_str())?;
-        print_import_map_diagnostics(&result.diagnostics);
-        Ok(Some(result.import_map))
-    } else {
-        Ok(None)
+use sb_worker_context::extensions::WorkerExtensionsState;
+use sb_worker_context::fetch_breaker::FetchBreakers;
+use sb_worker_context::routes::RouteTable;
+use sb_worker_context::worker_id::WorkerId;
+use sb_workers::{sb_service_bindings, sb_user_workers, BindingDepthsState};
+
+fn report_uncaught_exception(js_runtime: &mut JsRuntime, worker_id: &WorkerId, err: &Error) {
+    error!("[{}] uncaught exception in worker: {}", worker_id, err);
+
+    let (kind, message, stack) = match err.downcast_ref::<JsError>() {
+        Some(js_error) => {
+            let kind = if js_error
+                .exception_message
+                .starts_with("Uncaught (in promise)")
+            {
+                UncaughtErrorKind::UnhandledRejection
+            } else {
+                UncaughtErrorKind::Exception
+            };
+            (
+                kind,
+                js_error.exception_message.clone(),
+                js_error.stack.clone(),
+            )
+        }
+        None => (UncaughtErrorKind::Exception, err.to_string(), None),
+    };
+
+    let op_state_rc = js_runtime.op_state();
+    let op_state = op_state_rc.borrow();
+    if let Some(reporter) = op_state.try_borrow::<UncaughtErrorReporter>() {
+        reporter.report(op_state.borrow::<HttpBufPool>(), kind, message, stack);
     }
 }
 
-fn print_import_map_diagnostics(diagnostics: &[ImportMapDiagnostic]) {
-    if !diagnostics.is_empty() {
-        warn!(
-            "Import map diagnostics:\n{}",
-            diagnostics
-                .iter()
-                .map(|d| format!("  - {d}"))
-                .collect::<Vec<_>>()
-                .join("\n")
-        );
+// shared by all the isolates, so compiled wasm modules can be passed between them
+static COMPILED_WASM_MODULE_STORE: Lazy<CompiledWasmModuleStore> =
+    Lazy::new(CompiledWasmModuleStore::default);
+
+// Note: this will load Mozilla's CAs (we may also need to support system certs).
+// Built once per process, workers get a copy as extensions take it by value
+// (in user workers only on their first request or TLS connection, see
+// `sb_core_lazy_tls`).
+static ROOT_CERT_STORE: Lazy<RootCertStore> = Lazy::new(deno_tls::create_default_root_cert_store);
+
+static STRICT_MODE: OnceCell<()> = OnceCell::new();
+
+// In strict mode, user workers are built without the extensions of the
+// capabilities their service's manifest doesn't ask for. Must be called
+// before the first worker is created.
+pub fn init_strict_mode() -> Result<(), Error> {
+    if STRICT_MODE.set(()).is_err() {
+        bail!("strict mode is already on");
     }
+    Ok(())
+}
+
+pub fn strict_mode() -> bool {
+    STRICT_MODE.get().is_some()
+}
+
+// The modules a worker loaded while booting, their code caches are made once
+// it's serving.
+struct PendingCodeCache {
+    code_cache: CodeCache,
+    loaded: Vec<LoadedModule>,
+    module_loader: Rc<DefaultModuleLoader>,
 }
 
 pub struct EdgeRuntime {
@@ -70,6 +168,13 @@ pub struct EdgeRuntime {
     pub env_vars: HashMap<String, String>,
     pub conf: EdgeContextOpts,
     pub curr_user_opts: EdgeUserRuntimeOpts,
+    // what the logs of the worker are prefixed with
+    pub worker_id: WorkerId,
+    // the service name, or path, of the worker
+    service: String,
+    boot_notifier: Option<oneshot::Sender<Result<(), Error>>>,
+    warmup_modules: Vec<ModuleSpecifier>,
+    module_loader: Rc<DefaultModuleLoader>,
 }
 
 #[derive(Debug, PartialEq)]
@@ -78,7 +183,225 @@ pub enum EdgeCallResult {
     TimeOut,
     ModuleEvaluationTimedOut,
     HeapLimitReached,
+    EventLoopBlocked,
+    UncaughtException,
     Completed,
+    // with `WorkerHandle::terminate`
+    Terminated,
+}
+
+// fetch only parses the client certificate on its first request, check it
+// when the worker boots instead
+fn load_client_cert(cert: &ClientCertOpts) -> Result<(), Error> {
+    let certs = deno_tls::load_certs(&mut cert.cert_chain.as_bytes())
+        .map_err(|err| anyhow!("invalid client certificate chain: {}", err))?;
+    if certs.is_empty() {
+        bail!("invalid client certificate chain: no certificate found");
+    }
+    let keys = deno_tls::load_private_keys(cert.private_key.as_bytes())
+        .map_err(|err| anyhow!("invalid client certificate key: {}", err))?;
+    if keys.is_empty() {
+        bail!("invalid client certificate key: no private key found");
+    }
+    Ok(())
+}
+
+// the js of the extensions is in the snapshot, it's only loaded with `with_esm`
+macro_rules! init_ext {
+    ($with_esm:expr, $($ext:ident)::+ $(<$ty:ty>)? ($($arg:expr),*)) => {
+        if $with_esm {
+            $($ext)::+::init_ops_and_esm$(::<$ty>)?($($arg),*)
+        } else {
+            $($ext)::+::init_ops$(::<$ty>)?($($arg),*)
+        }
+    };
+}
+
+const DEFAULT_USER_AGENT: &str = "supabase-edge-runtime";
+
+// Extensions the isolates of `isolate_kind` are made of. The ones driving the
+// pool are only in the main worker's, rather than absent from its op state,
+// and deno_net isn't in the ones of `IsolateKind::UserWithoutNet`.
+// Embedders building their own snapshots start from these, with `with_esm`.
+// `root_cert_store` is only given to fetch, websocket and net in the main
+// worker's, user workers copy the default store on their first request or TLS
+// connection instead. deno_fetch builds its http client when its state is
+// made, and without a store deno_tls builds the default one there, so user
+// workers give it an empty one.
+pub fn runtime_extensions(
+    with_esm: bool,
+    isolate_kind: IsolateKind,
+    main_module_url: Option<Url>,
+    root_cert_store: Option<RootCertStore>,
+    client_cert_chain_and_key: Option<(String, String)>,
+    user_agent: Option<String>,
+    crypto_seed: Option<u64>,
+) -> Vec<Extension> {
+    let user_agent = user_agent.unwrap_or_else(|| DEFAULT_USER_AGENT.to_string());
+    let worker_kind = isolate_kind.worker_kind();
+    let (fetch_root_cert_store, tls_root_cert_store) = match worker_kind {
+        WorkerKind::Main => (root_cert_store.clone(), root_cert_store),
+        WorkerKind::User => (Some(RootCertStore::empty()), None),
+    };
+
+    let mut extensions = vec![
+        init_ext!(with_esm, sb_core_permissions()),
+        init_ext!(with_esm, deno_webidl::deno_webidl()),
+        init_ext!(with_esm, deno_console::deno_console()),
+        init_ext!(with_esm, deno_url::deno_url()),
+        init_ext!(
+            with_esm,
+            deno_web::deno_web<Permissions>(deno_web::BlobStore::default(), None)
+        ),
+        init_ext!(
+            with_esm,
+            deno_fetch::deno_fetch<Permissions>(deno_fetch::Options {
+                user_agent: user_agent.clone(),
+                root_cert_store: fetch_root_cert_store,
+                client_cert_chain_and_key,
+                ..Default::default()
+            })
+        ),
+        init_ext!(
+            with_esm,
+            deno_websocket::deno_websocket<Permissions>(
+                user_agent,
+                tls_root_cert_store.clone(),
+                None
+            )
+        ),
+        init_ext!(with_esm, deno_crypto::deno_crypto(crypto_seed)),
+        match isolate_kind {
+            IsolateKind::UserWithoutNet => init_ext!(with_esm, sb_core_no_net()),
+            _ => init_ext!(
+                with_esm,
+                // unstable for Deno.listenDatagram, gated by the permissions
+                deno_net::deno_net<Permissions>(tls_root_cert_store, true, None)
+            ),
+        },
+        init_ext!(with_esm, deno_tls::deno_tls()),
+        init_ext!(with_esm, deno_http::deno_http()),
+        init_ext!(with_esm, sb_core_streams()),
+        init_ext!(with_esm, sb_env_op()),
+        init_ext!(with_esm, sb_service_bindings()),
+        init_ext!(with_esm, sb_core_edge_runtime_apis()),
+    ];
+    // the `EdgeRuntime` APIs the runtime is built with, by cargo feature
+    #[cfg(feature = "postgres")]
+    extensions.push(init_ext!(with_esm, sb_postgres()));
+    #[cfg(feature = "mail")]
+    extensions.push(init_ext!(with_esm, sb_mail()));
+    #[cfg(feature = "ai")]
+    extensions.push(init_ext!(with_esm, sb_ai()));
+    #[cfg(feature = "jwt")]
+    extensions.push(init_ext!(with_esm, sb_jwt()));
+    #[cfg(feature = "storage")]
+    extensions.push(init_ext!(with_esm, sb_storage()));
+    #[cfg(feature = "queue")]
+    extensions.push(init_ext!(with_esm, sb_queue()));
+    #[cfg(feature = "rate_limit")]
+    extensions.push(init_ext!(with_esm, sb_rate_limit()));
+    #[cfg(feature = "cache")]
+    extensions.push(init_ext!(with_esm, sb_cache()));
+    extensions.extend([
+        init_ext!(with_esm, sb_core_main_js()),
+        init_ext!(with_esm, sb_core_net()),
+        init_ext!(with_esm, sb_core_http()),
+        init_ext!(with_esm, sb_core_runtime(main_module_url)),
+        init_ext!(with_esm, sb_core_event_loop()),
+        init_ext!(with_esm, sb_core_uncaught_errors()),
+        init_ext!(with_esm, sb_core_logs()),
+        init_ext!(with_esm, sb_core_fetch_intercept()),
+        init_ext!(with_esm, sb_core_net_usage()),
+        init_ext!(with_esm, sb_core_compression()),
+        init_ext!(with_esm, sb_core_blob()),
+        init_ext!(with_esm, sb_core_fetch_breaker()),
+        init_ext!(with_esm, sb_core_open_sockets()),
+        init_ext!(with_esm, sb_core_billing()),
+        init_ext!(with_esm, sb_core_deadline()),
+    ]);
+    if worker_kind == WorkerKind::Main {
+        extensions.extend([
+            init_ext!(with_esm, sb_user_workers()),
+            init_ext!(with_esm, sb_core_router()),
+            init_ext!(with_esm, sb_core_main_worker_js()),
+        ]);
+    } else {
+        extensions.push(init_ext!(
+            with_esm,
+            sb_core_lazy_tls(|| ROOT_CERT_STORE.clone())
+        ));
+    }
+    extensions
+}
+
+// The names of the extensions of `runtime_extensions`, in the same order.
+// `sb_core_no_net` is listed under its own name.
+pub fn runtime_extension_names(isolate_kind: IsolateKind) -> Vec<&'static str> {
+    let worker_kind = isolate_kind.worker_kind();
+    let mut names = vec![
+        "sb_core_permissions",
+        "deno_webidl",
+        "deno_console",
+        "deno_url",
+        "deno_web",
+        "deno_fetch",
+        "deno_websocket",
+        "deno_crypto",
+        match isolate_kind {
+            IsolateKind::UserWithoutNet => "sb_core_no_net",
+            _ => "deno_net",
+        },
+        "deno_tls",
+        "deno_http",
+        "sb_core_streams",
+        "sb_env",
+        "sb_service_bindings",
+        "sb_core_edge_runtime_apis",
+    ];
+    #[cfg(feature = "postgres")]
+    names.push("sb_postgres");
+    #[cfg(feature = "mail")]
+    names.push("sb_mail");
+    #[cfg(feature = "ai")]
+    names.push("sb_ai");
+    #[cfg(feature = "jwt")]
+    names.push("sb_jwt");
+    #[cfg(feature = "storage")]
+    names.push("sb_storage");
+    #[cfg(feature = "queue")]
+    names.push("sb_queue");
+    #[cfg(feature = "rate_limit")]
+    names.push("sb_rate_limit");
+    #[cfg(feature = "cache")]
+    names.push("sb_cache");
+    names.extend([
+        "sb_core_main_js",
+        "sb_core_net",
+        "sb_core_http",
+        "sb_core_runtime",
+        "sb_core_event_loop",
+        "sb_core_uncaught_errors",
+        "sb_core_logs",
+        "sb_core_fetch_intercept",
+        "sb_core_net_usage",
+        "sb_core_compression",
+        "sb_core_blob",
+        "sb_core_fetch_breaker",
+        "sb_core_open_sockets",
+        "sb_core_billing",
+        "sb_core_deadline",
+    ]);
+    if worker_kind == WorkerKind::Main {
+        names.extend([
+            "sb_user_workers",
+            "sb_core_router",
+            "sb_core_main_worker_js",
+        ]);
+    } else {
+        names.push("sb_core_lazy_tls");
+    }
+    names
 }
 
 impl EdgeRuntime {
@@ -86,61 +409,136 @@ impl EdgeRuntime {
         let EdgeContextInitOpts {
             service_path,
             no_module_cache,
+            offline,
             import_map_path,
+            auth_tokens,
             env_vars,
+            fetch_interceptor,
+            outbound,
+            extensions: worker_extensions,
+            unstable_features,
             conf,
         } = opts;
 
-        let (is_user_runtime, user_rt_opts) = match conf.clone() {
-            EdgeContextOpts::UserWorker(conf) => (true, conf),
-            EdgeContextOpts::MainWorker(_conf) => (false, EdgeUserRuntimeOpts::default()),
+        let (is_user_runtime, user_rt_opts, warmup_specifiers) = match conf.clone() {
+            EdgeContextOpts::UserWorker(conf) => (true, conf, vec![]),
+            EdgeContextOpts::MainWorker(conf) => (
+                false,
+                EdgeUserRuntimeOpts::default(),
+                conf.warmup_specifiers,
+            ),
+        };
+        // shared by all the workers of the pool
+        let fetch_breakers = match &conf {
+            EdgeContextOpts::UserWorker(conf) => conf.fetch_breakers.clone(),
+            EdgeContextOpts::MainWorker(conf) => conf.fetch_breakers.clone(),
         };
 
-        let user_agent = "supabase-edge-runtime".to_string();
         let base_url =
             Url::from_directory_path(std::env::current_dir().map(|p| p.join(&service_path))?)
                 .unwrap();
         // TODO: check for other potential main paths (eg: index.js, index.tsx)
         let main_module_url = base_url.join("index.ts")?;
 
-        // Note: this will load Mozilla's CAs (we may also need to support system certs)
-        let root_cert_store = deno_tls::create_default_root_cert_store();
+        // what the worker's database pools, rate limits and logs are shared by
+        let service = user_rt_opts
+            .service_name
+            .clone()
+            .unwrap_or_else(|| service_path.to_string_lossy().to_string());
 
-        let extensions = vec![
-            sb_core_permissions::init_ops(),
-            deno_webidl::deno_webidl::init_ops(),
-            deno_console::deno_console::init_ops(),
-            deno_url::deno_url::init_ops(),
-            deno_web::deno_web::init_ops::<Permissions>(deno_web::BlobStore::default(), None),
-            deno_fetch::deno_fetch::init_ops::<Permissions>(deno_fetch::Options {
-                user_agent: user_agent.clone(),
-                root_cert_store: Some(root_cert_store.clone()),
-                ..Default::default()
-            }),
-            deno_websocket::deno_websocket::init_ops::<Permissions>(
-                user_agent,
-                Some(root_cert_store.clone()),
-                None,
-            ),
-            // TODO: support providing a custom seed for crypto
-            deno_crypto::deno_crypto::init_ops(None),
-            deno_net::deno_net::init_ops::<Permissions>(Some(root_cert_store), false, None),
-            deno_tls::deno_tls::init_ops(),
-            deno_http::deno_http::init_ops(),
-            sb_env_op::init_ops(),
-            sb_user_workers::init_ops(),
-            sb_core_main_js::init_ops(),
-            sb_core_net::init_ops(),
-            sb_core_http::init_ops(),
-            sb_core_runtime::init_ops(Some(main_module_url.clone())),
-        ];
-
-        let import_map = load_import_map(import_map_path)?;
-        let module_loader = DefaultModuleLoader::new(import_map, no_module_cache)?;
+        let worker_id = if is_user_runtime {
+            WorkerId::new(
+                user_rt_opts.id.clone(),
+                &service,
+                user_rt_opts.deployment.as_deref(),
+            )
+        } else {
+            WorkerId::main()
+        };
+
+        let client_cert_chain_and_key = match user_rt_opts.client_cert.clone() {
+            Some(cert) => {
+                load_client_cert(&cert)?;
+                Some((cert.cert_chain, cert.private_key))
+            }
+            None => None,
+        };
+
+        let user_permissions = if is_user_runtime {
+            let permissions = Permissions::user_worker(
+                user_rt_opts.net_allowlist.as_deref(),
+                user_rt_opts.allow_udp,
+            )?;
+            let permissions = match user_rt_opts.events_tx.clone() {
+                Some(events_tx) => permissions.with_denials_tx(worker_id.clone(), events_tx),
+                None => permissions,
+            };
+            Some(match permission_prompter() {
+                Some(prompter) => permissions.with_prompter(prompter),
+                None => permissions,
+            })
+        } else {
+            None
+        };
+
+        let without_net = user_rt_opts
+            .capabilities
+            .as_ref()
+            .filter(|_| is_user_runtime)
+            .map_or(false, |capabilities| {
+                !capabilities.contains(&Capability::Net)
+            });
+        let isolate_kind = match (is_user_runtime, without_net) {
+            (false, _) => IsolateKind::Main,
+            (true, false) => IsolateKind::User,
+            (true, true) => IsolateKind::UserWithoutNet,
+        };
+        let worker_kind = isolate_kind.worker_kind();
+        let startup_snapshot = snapshot::snapshot(isolate_kind);
+        let with_esm = startup_snapshot.is_none();
+        let mut extensions = runtime_extensions(
+            with_esm,
+            isolate_kind,
+            Some(main_module_url.clone()),
+            Some(ROOT_CERT_STORE.clone()),
+            client_cert_chain_and_key,
+            outbound.user_agent.clone(),
+            user_rt_opts.crypto_seed,
+        );
+        extensions.extend(snapshot::extra_extensions());
+        if let Some(worker_extensions) = &worker_extensions {
+            extensions.extend(worker_extensions.extensions(with_esm));
+        }
+
+        let import_map = if is_user_runtime {
+            load_service_import_map(
+                &service_path,
+                import_map_path.as_deref(),
+                user_rt_opts.base_import_map_path.as_deref(),
+            )?
+        } else {
+            load_import_map(import_map_path)?
+        };
+        let module_loader = Rc::new(DefaultModuleLoader::new(
+            import_map,
+            auth_tokens,
+            no_module_cache,
+            offline,
+        )?);
+
+        let warmup_modules = warmup_specifiers
+            .iter()
+            .filter_map(|specifier| {
+                module_loader
+                    .resolve(specifier, main_module_url.as_str(), ResolutionKind::Import)
+                    .map_err(|err| warn!("skipping warmup of {}: {}", specifier, err))
+                    .ok()
+            })
+            .collect();
 
         let mut js_runtime = JsRuntime::new(RuntimeOptions {
             extensions,
-            module_loader: Some(Rc::new(module_loader)),
+            module_loader: Some(module_loader.clone()),
             is_main: true,
             create_params: {
                 if is_user_runtime {
@@ -153,28 +551,202 @@ impl EdgeRuntime {
                 }
             },
             shared_array_buffer_store: None,
-            compiled_wasm_module_store: None,
-            startup_snapshot: Some(snapshot::snapshot()),
+            compiled_wasm_module_store: Some(COMPILED_WASM_MODULE_STORE.clone()),
+            startup_snapshot,
             ..Default::default()
         });
 
+        let forward_logs =
+            is_user_runtime && user_rt_opts.forward_logs && user_rt_opts.events_tx.is_some();
+        let log_sinks = WorkerLogSinks::new(service.clone());
+        let log_limiter = user_rt_opts
+            .logs
+            .as_ref()
+            .filter(|_| is_user_runtime)
+            .map(LogLimiter::new);
+        let invocation_usages = user_rt_opts
+            .invocation_usages
+            .clone()
+            .filter(|_| is_user_runtime);
+        let event_loop_heartbeat_ms = user_rt_opts
+            .event_loop_lag_threshold_ms
+            .filter(|_| is_user_runtime)
+            .map(heartbeat_interval_ms);
+
+        // the behaviour changes of the service's compatibility date
+        let compat_flags = &user_rt_opts.compat_flags;
+
         // Bootstrapping stage
-        let script = format!(
-            "globalThis.bootstrapSBEdge({}, {})",
-            deno_core::serde_json::json!({ "target": env!("TARGET") }),
-            is_user_runtime
-        );
+        let mut bootstrap_opts = BootstrapOptions::new(worker_kind);
+        bootstrap_opts.locale = user_rt_opts.locale.clone();
+        bootstrap_opts.timezone = user_rt_opts.timezone.clone();
+        bootstrap_opts.default_headers = outbound.default_headers.clone();
+        bootstrap_opts.features = BootstrapFeatures {
+            event_loop_heartbeat_ms,
+            terminate_on_unhandled_rejection: user_rt_opts.terminate_on_unhandled_rejection
+                || compat_flags.contains(&CompatFlag::UnhandledRejectionTerminates),
+            forward_logs: forward_logs || log_limiter.is_some() || log_sinks.is_some(),
+            intercept_fetch: fetch_interceptor.is_some(),
+            max_fetch_response_bytes: user_rt_opts
+                .max_fetch_response_bytes
+                .filter(|_| is_user_runtime),
+            fetch_policy: Some(user_rt_opts.fetch_policy.clone()).filter(|_| is_user_runtime),
+            count_net_usage: is_user_runtime,
+            count_open_sockets: is_user_runtime,
+            fetch_breaker: fetch_breakers.is_some(),
+            restrict_key_export: is_user_runtime
+                && (!user_rt_opts.allow_key_export
+                    || compat_flags.contains(&CompatFlag::RestrictKeyExport)),
+            meter_invocations: invocation_usages.is_some(),
+            unstable: unstable_features,
+        };
 
         js_runtime
-            .execute_script::<String>(located_script_name!(), script)
+            .execute_script::<String>(located_script_name!(), bootstrap_opts.as_script())
             .expect("Failed to execute bootstrap script");
 
+        let runtime_info = runtime_info(isolate_kind, &bootstrap_opts.features);
+
         {
             //run inside a closure, so op_state_rc is released
             let env_vars = env_vars.clone();
             let op_state_rc = js_runtime.op_state();
             let mut op_state = op_state_rc.borrow_mut();
             op_state.put::<sb_env::EnvVars>(env_vars);
+            op_state.put::<WorkerId>(worker_id.clone());
+            op_state.put::<RuntimeInfo>(runtime_info);
+
+            if let Some(permissions) = user_permissions {
+                op_state.put::<Permissions>(permissions);
+            }
+
+            // what the user workers it creates inherit
+            op_state.put::<OutboundOpts>(outbound);
+
+            if let Some(interceptor) = fetch_interceptor {
+                op_state.put::<FetchInterceptorState>(FetchInterceptorState(interceptor));
+            }
+
+            if let Some(breakers) = fetch_breakers {
+                op_state.put::<FetchBreakers>(breakers);
+            }
+
+            if let Some(worker_extensions) = worker_extensions {
+                worker_extensions.init_state(&mut op_state);
+                op_state.put::<WorkerExtensionsState>(WorkerExtensionsState(worker_extensions));
+            }
+
+            #[cfg(feature = "postgres")]
+            if let Some(postgres) = user_rt_opts.postgres.as_ref().filter(|_| is_user_runtime) {
+                op_state.put::<PgWorkerState>(PgWorkerState::new(
+                    service.clone(),
+                    postgres.pool_size,
+                    postgres.max_checkouts,
+                ));
+            }
+
+            #[cfg(feature = "mail")]
+            if mailer_configured() {
+                op_state.put::<MailWorkerState>(MailWorkerState::new(service.clone()));
+            }
+            // these fall back to backends in the process, they're always there
+            #[cfg(feature = "queue")]
+            op_state.put::<QueueWorkerState>(QueueWorkerState::new(service.clone()));
+            #[cfg(feature = "rate_limit")]
+            op_state.put::<RateLimitWorkerState>(RateLimitWorkerState::new(service.clone()));
+            #[cfg(feature = "cache")]
+            op_state.put::<CacheWorkerState>(CacheWorkerState::new(service.clone()));
+
+            if let Some(bindings) = user_rt_opts
+                .service_bindings
+                .clone()
+                .filter(|_| is_user_runtime)
+            {
+                op_state.put::<ServiceBindings>(bindings);
+                op_state.put::<BindingDepthsState>(BindingDepthsState(
+                    user_rt_opts.binding_depths.clone(),
+                ));
+            }
+
+            if is_user_runtime {
+                op_state.put::<NetUsageState>(NetUsageState(user_rt_opts.net_usage.clone()));
+                op_state.put::<SocketUsageState>(SocketUsageState::new(
+                    user_rt_opts.socket_usage.clone(),
+                    user_rt_opts.max_open_sockets,
+                ));
+            }
+
+            if let Some(blob_spill) = user_rt_opts.blob_spill.as_ref().filter(|_| is_user_runtime) {
+                op_state.put::<BlobSpillState>(BlobSpillState::new(blob_spill));
+            }
+
+            #[cfg(feature = "ai")]
+            if ai_backend_configured() {
+                if !is_user_runtime {
+                    op_state.put::<AiWorkerState>(AiWorkerState::unlimited());
+                } else if let Some(ai) = user_rt_opts.ai.as_ref() {
+                    op_state
+                        .put::<AiWorkerState>(AiWorkerState::new(ai.max_calls, ai.max_concurrent));
+                }
+            }
+
+            #[cfg(feature = "jwt")]
+            if jwt_keys_configured() {
+                if !is_user_runtime {
+                    op_state.put::<JwtWorkerState>(JwtWorkerState::unrestricted());
+                } else if let Some(jwt) = user_rt_opts.jwt.as_ref() {
+                    op_state.put::<JwtWorkerState>(JwtWorkerState::new(jwt.sign_keys.clone()));
+                }
+            }
+
+            // user workers only reach the objects under their prefix, by
+            // default the ones of their service
+            #[cfg(feature = "storage")]
+            if storage_configured() {
+                if !is_user_runtime {
+                    op_state
+                        .put::<StorageWorkerState>(StorageWorkerState::new(None, String::new()));
+                } else if let Some(storage) = user_rt_opts.storage.as_ref() {
+                    let mut prefix = storage.prefix.clone().unwrap_or_else(|| service.clone());
+                    if !prefix.is_empty() && !prefix.ends_with('/') {
+                        prefix.push('/');
+                    }
+                    op_state.put::<StorageWorkerState>(StorageWorkerState::new(
+                        storage.bucket.clone(),
+                        prefix,
+                    ));
+                }
+            }
+
+            if let Some(backpressure) = user_rt_opts
+                .backpressure
+                .as_ref()
+                .filter(|_| is_user_runtime)
+            {
+                let max_heap_bytes = backpressure.max_heap_usage_pct.map(|pct| {
+                    (mib_to_bytes(user_rt_opts.memory_limit_mb) * pct.min(100) as u64 / 100)
+                        as usize
+                });
+                op_state.put::<HttpBackpressure>(HttpBackpressure::new(
+                    backpressure.max_in_flight,
+                    max_heap_bytes,
+                ));
+            }
+
+            if forward_logs {
+                if let Some(events_tx) = user_rt_opts.events_tx.clone() {
+                    op_state.put::<LogForwarder>(LogForwarder::new(worker_id.clone(), events_tx));
+                }
+            }
+            if let Some(log_limiter) = log_limiter {
+                op_state.put::<LogLimiter>(log_limiter);
+            }
+            if let Some(log_sinks) = log_sinks {
+                op_state.put::<WorkerLogSinks>(log_sinks);
+            }
+            if let Some(usages) = invocation_usages {
+                op_state.put::<InvocationMeter>(InvocationMeter::new(usages));
+            }
         }
 
         Ok(Self {
@@ -184,9 +756,79 @@ impl EdgeRuntime {
             env_vars,
             conf,
             curr_user_opts: user_rt_opts,
+            worker_id,
+            service,
+            boot_notifier: None,
+            warmup_modules,
+            module_loader,
         })
     }
 
+    // The notifier receives the outcome of loading and evaluating the main
+    // module, so callers can tell a failed boot apart from a failed request.
+    pub fn with_boot_notifier(mut self, tx: oneshot::Sender<Result<(), Error>>) -> Self {
+        self.boot_notifier = Some(tx);
+        self
+    }
+
+    // Loads the modules without evaluating them. Their sources and emitted code
+    // end up in the module cache, shared with the user workers.
+    async fn warmup(js_runtime: &mut JsRuntime, modules: &[ModuleSpecifier]) {
+        for module in modules {
+            let start = std::time::Instant::now();
+            match js_runtime.load_side_module(module, None).await {
+                Ok(_) => debug!(
+                    "warmed up {} in {}",
+                    module,
+                    human_elapsed(start.elapsed().as_millis() as u64)
+                ),
+                Err(err) => warn!("failed to warm up {}: {}", module, err),
+            }
+        }
+    }
+
+    async fn boot_main_module(
+        js_runtime: &mut JsRuntime,
+        module_loader: &DefaultModuleLoader,
+        main_module_url: &ModuleSpecifier,
+    ) -> Result<futures_oneshot::Receiver<Result<(), Error>>, Error> {
+        let mod_id = js_runtime.load_main_module(main_module_url, None).await;
+
+        // offline, modules missing from the cache are loaded as empty modules
+        // and reported together, before any error they caused while linking
+        let missing_modules = module_loader.take_missing_modules();
+        if !missing_modules.is_empty() {
+            bail!(
+                "offline mode: {} remote module(s) are not in the module cache:\n{}",
+                missing_modules.len(),
+                missing_modules
+                    .iter()
+                    .map(|specifier| format!("  - {}", specifier))
+                    .collect::<Vec<_>>()
+                    .join("\n")
+            );
+        }
+
+        let mod_id = mod_id?;
+        let mut mod_result = js_runtime.mod_evaluate(mod_id);
+
+        // poll the event loop once, so errors thrown while synchronously
+        // evaluating the module are surfaced as boot failures
+        if let Poll::Ready(Err(err)) =
+            poll_fn(|cx| Poll::Ready(js_runtime.poll_event_loop(cx, false))).await
+        {
+            return Err(err);
+        }
+        if let Ok(Some(Err(err))) = mod_result.try_recv() {
+            return Err(err);
+        }
+
+        // serves `export default { fetch }`, if the module didn't start a server
+        js_runtime.execute_script(located_script_name!(), "globalThis.serveDefaultExport()")?;
+
+        Ok(mod_result)
+    }
+
     pub async fn run(
         mut self,
         stream: UnixStream,
@@ -207,20 +849,35 @@ impl EdgeRuntime {
             if !is_user_rt {
                 if let EdgeContextOpts::MainWorker(conf) = self.conf.clone() {
                     op_state.put::<mpsc::UnboundedSender<UserWorkerMsgs>>(conf.worker_pool_tx);
+                    op_state.put::<RouteTable>(conf.routes);
                 }
             }
         }
 
         let (halt_isolate_tx, mut halt_isolate_rx) = oneshot::channel::<EdgeCallResult>();
+        let (evaluated_tx, evaluated_rx) = watch::channel(false);
+        let (booted_tx, booted_rx) = watch::channel(false);
+        let mut handle_registration = None;
 
         if is_user_rt {
+            {
+                let op_state_rc = self.js_runtime.op_state();
+                let mut op_state = op_state_rc.borrow_mut();
+                op_state.put::<UncaughtErrorReporter>(UncaughtErrorReporter::new(
+                    self.worker_id.clone(),
+                    self.curr_user_opts.events_tx.clone(),
+                ));
+            }
+
             let (memory_limit_tx, memory_limit_rx) = mpsc::unbounded_channel::<u64>();
 
             // add a callback when a worker reaches its memory limit
             let memory_limit_mb = self.curr_user_opts.memory_limit_mb;
+            let worker_id = self.worker_id.clone();
             self.js_runtime.add_near_heap_limit_callback(move |cur, _| {
                 debug!(
-                    "Low memory alert triggered: {}",
+                    "[{}] Low memory alert triggered: {}",
+                    worker_id,
                     bytes_to_display(cur as u64),
                 );
 
@@ -229,34 +886,120 @@ impl EdgeRuntime {
                 (cur + memory_limit_mb as usize) << 20
             });
 
-            self.start_controller_thread(
-                self.curr_user_opts.worker_timeout_ms,
+            let maybe_watchdog = self
+                .curr_user_opts
+                .event_loop_lag_threshold_ms
+                .map(|lag_threshold_ms| self.create_event_loop_watchdog(lag_threshold_ms));
+
+            let (deadline_tx, deadline_rx) = watch::channel(
+                Instant::now() + Duration::from_millis(self.curr_user_opts.worker_timeout_ms),
+            );
+            let deadline_tx = Arc::new(deadline_tx);
+            if let Some(max_extension_ms) = self.curr_user_opts.max_wall_clock_extension_ms {
+                let op_state_rc = self.js_runtime.op_state();
+                op_state_rc
+                    .borrow_mut()
+                    .put::<WorkerDeadline>(WorkerDeadline::new(
+                        deadline_tx.clone(),
+                        Duration::from_millis(max_extension_ms),
+                    ));
+            }
+
+            // unregistered once the isolate is gone
+            let (terminate_tx, terminate_rx) = mpsc::unbounded_channel::<()>();
+            handle_registration = Some(register_worker_handle(WorkerHandle::new(
+                self.curr_user_opts.id.clone(),
+                self.service.clone(),
+                deadline_tx,
+                terminate_tx,
+                self.curr_user_opts.net_usage.clone(),
+                self.curr_user_opts.socket_usage.clone(),
+            )));
+
+            self.monitor_limits(
+                deadline_rx,
+                booted_rx,
+                evaluated_rx,
+                terminate_rx,
                 memory_limit_rx,
+                maybe_watchdog,
                 halt_isolate_tx,
             );
         }
 
+        let boot_notifier = self.boot_notifier.take();
         let mut js_runtime = self.js_runtime;
+        let warmup_modules = self.warmup_modules;
+        let module_loader = self.module_loader;
+        let worker_id = self.worker_id;
 
         let future = async move {
-            let mod_id = js_runtime
-                .load_main_module(&self.main_module_url, None)
-                .await?;
-            let mod_result = js_runtime.mod_evaluate(mod_id);
+            Self::warmup(&mut js_runtime, &warmup_modules).await;
+            for specifier in module_loader.take_missing_modules() {
+                warn!("skipped warmup of {}, not in the module cache", specifier);
+            }
+            module_loader.take_loaded_modules();
 
-            let result: Result<EdgeCallResult, Error> = tokio::select! {
-                _ = js_runtime.run_event_loop(false) => {
-                    debug!("Event loop has completed");
+            let code_cache = code_cache_enabled()
+                .then(|| CodeCache::new(&self.main_module_url))
+                .and_then(|code_cache| {
+                    code_cache
+                        .map_err(|err| warn!("[{}] code cache is off: {}", worker_id, err))
+                        .ok()
+                });
+            let primed = match &code_cache {
+                Some(code_cache) => {
+                    prime_modules(&mut js_runtime, &module_loader, code_cache).await
+                }
+                None => vec![],
+            };
+
+            let boot_result =
+                Self::boot_main_module(&mut js_runtime, &module_loader, &self.main_module_url)
+                    .await;
+            drop(primed);
+            module_loader.clear_primed();
+            // made once the worker served its first event loop turn
+            let code_cache_update = code_cache.map(|code_cache| PendingCodeCache {
+                code_cache,
+                loaded: module_loader.take_loaded_modules(),
+                module_loader: module_loader.clone(),
+            });
 
-                    if let Err(_err) = tokio::time::timeout(std::time::Duration::from_millis(self.curr_user_opts.worker_timeout_ms), mod_result).await {
-                        return Ok(EdgeCallResult::ModuleEvaluationTimedOut);
+            let mod_result = match boot_result {
+                Ok(mod_result) => {
+                    // the event loop is watched from now on
+                    let _ = booted_tx.send(true);
+                    if let Some(tx) = boot_notifier {
+                        let _ = tx.send(Ok(()));
+                    }
+                    mod_result
+                }
+                Err(err) => {
+                    let msg = err.to_string();
+                    if let Some(tx) = boot_notifier {
+                        let _ = tx.send(Err(err));
                     }
+                    bail!("worker failed to boot: {}", msg);
+                }
+            };
+
+            let result: Result<EdgeCallResult, Error> = tokio::select! {
+                event_loop_result = Self::evaluate_and_drain(&mut js_runtime, mod_result, code_cache_update, &evaluated_tx, &worker_id) => {
+                    debug!("[{}] Event loop has completed", worker_id);
 
-                    Ok(EdgeCallResult::Completed)
+                    match event_loop_result {
+                        Ok(true) => Ok(EdgeCallResult::Completed),
+                        Ok(false) => Ok(EdgeCallResult::ModuleEvaluationTimedOut),
+                        Err(err) => {
+                            report_uncaught_exception(&mut js_runtime, &worker_id, &err);
+                            Ok(EdgeCallResult::UncaughtException)
+                        }
+                    }
                 },
                 // TODO: Fix race condition
                 call_result = &mut halt_isolate_rx => {
-                    debug!("User Worker execution halted");
+                    debug!("[{}] User Worker execution halted", worker_id);
                     Ok(call_result.unwrap_or(EdgeCallResult::Unknown))
                 }
             };
@@ -266,6 +1009,7 @@ impl EdgeRuntime {
         };
 
         let res = future.await;
+        drop(handle_registration);
 
         if res.is_err() {
             println!("worker thread panicked {:?}", res.as_ref().err().unwrap());
@@ -275,38 +1019,171 @@ impl EdgeRuntime {
         res
     }
 
-    fn start_controller_thread(
+    // Drives the event loop until the main module has evaluated (its
+    // top-level awaits settled), then until the ops it left pending (eg:
+    // timers, streaming response bodies) are done. `false` if the event loop
+    // ran out of work before the module evaluated.
+    async fn evaluate_and_drain(
+        js_runtime: &mut JsRuntime,
+        mut mod_result: futures_oneshot::Receiver<Result<(), Error>>,
+        code_cache_update: Option<PendingCodeCache>,
+        evaluated_tx: &watch::Sender<bool>,
+        worker_id: &WorkerId,
+    ) -> Result<bool, Error> {
+        let evaluation = poll_fn(|cx| {
+            // the outcome may already have been taken while booting
+            if let Poll::Ready(result) = mod_result.poll_unpin(cx) {
+                return Poll::Ready(Ok(Some(result.unwrap_or(Ok(())))));
+            }
+            match js_runtime.poll_event_loop(cx, false) {
+                Poll::Ready(Ok(())) => Poll::Ready(Ok(mod_result.try_recv().ok().flatten())),
+                Poll::Ready(Err(err)) => Poll::Ready(Err(err)),
+                Poll::Pending => Poll::Pending,
+            }
+        })
+        .await?;
+
+        match evaluation {
+            Some(result) => result?,
+            None => return Ok(false),
+        }
+
+        // from now on, the worker is given its drain timeout when it's halted
+        let _ = evaluated_tx.send(true);
+        debug!("[{}] module evaluated, draining pending ops", worker_id);
+        if let Some(update) = code_cache_update {
+            // the requests already waiting are answered before the caches
+            // are made
+            let turn = poll_fn(|cx| Poll::Ready(js_runtime.poll_event_loop(cx, false))).await;
+            // written by a blocking thread, the worker doesn't wait for it
+            let _ = update_code_cache(
+                js_runtime,
+                &update.module_loader,
+                &update.code_cache,
+                update.loaded,
+            )
+            .await;
+            if let Poll::Ready(result) = turn {
+                result?;
+                return Ok(true);
+            }
+        }
+        js_runtime.run_event_loop(false).await?;
+        Ok(true)
+    }
+
+    fn create_event_loop_watchdog(&mut self, lag_threshold_ms: u64) -> EventLoopWatchdog {
+        let heartbeat = EventLoopHeartbeat::new();
+        let (stack_tx, stack_rx) = mpsc::unbounded_channel::<String>();
+
+        {
+            let op_state_rc = self.js_runtime.op_state();
+            let mut op_state = op_state_rc.borrow_mut();
+            op_state.put::<EventLoopHeartbeat>(heartbeat.clone());
+        }
+
+        let context = self.js_runtime.global_context();
+        let isolate = self.js_runtime.v8_isolate();
+        isolate.set_slot(StackCaptureCtx { context, stack_tx });
+
+        EventLoopWatchdog {
+            worker_id: self.worker_id.clone(),
+            heartbeat,
+            lag_threshold_ms,
+            isolate_handle: isolate.thread_safe_handle(),
+            stack_rx,
+            events_tx: self.curr_user_opts.events_tx.clone(),
+            blocked: false,
+        }
+    }
+
+    // terminates the isolate once it reaches one of its limits, watched from
+    // the monitor thread shared by every isolate
+    fn monitor_limits(
         &mut self,
-        worker_timeout_ms: u64,
+        // the wall clock limit, pushed back as the worker extends it
+        deadline_rx: watch::Receiver<Instant>,
+        // set once the worker booted, its event loop isn't watched before
+        // (loading its modules doesn't let the heartbeat timer run)
+        mut booted_rx: watch::Receiver<bool>,
+        // set once the main module evaluated, its pending ops are drained
+        evaluated_rx: watch::Receiver<bool>,
+        // `WorkerHandle::terminate` was called
+        mut terminate_rx: mpsc::UnboundedReceiver<()>,
         mut memory_limit_rx: mpsc::UnboundedReceiver<u64>,
-        halt_isolate_tx: oneshot::Sender<EdgeCallResult>,
+        mut maybe_watchdog: Option<EventLoopWatchdog>,
+        mut halt_isolate_tx: oneshot::Sender<EdgeCallResult>,
     ) {
         let thread_safe_handle = self.js_runtime.v8_isolate().thread_safe_handle();
+        let terminate_on_blocked_event_loop = self.curr_user_opts.terminate_on_blocked_event_loop;
+        let drain_timeout_ms = self.curr_user_opts.drain_timeout_ms;
+        let worker_id = self.worker_id.clone();
+        let started = Instant::now();
 
-        thread::spawn(move || {
-            let rt = tokio::runtime::Builder::new_current_thread()
-                .enable_all()
-                .build()
-                .unwrap();
+        monitor_isolate(async move {
+            let watchdog = async {
+                match maybe_watchdog.as_mut() {
+                    Some(watchdog) => {
+                        while !*booted_rx.borrow() {
+                            if booted_rx.changed().await.is_err() {
+                                std::future::pending::<()>().await;
+                            }
+                        }
+                        // the boot took what it took, count from now
+                        watchdog.heartbeat.beat();
+
+                        loop {
+                            let lag_ms = watchdog.wait_for_blocked_loop().await;
+                            watchdog.report_blocked_loop(lag_ms).await;
 
-            let future = async move {
-                tokio::select! {
-                    _ = tokio::time::sleep(Duration::from_millis(worker_timeout_ms)) => {
-                        debug!("max duration reached for the worker. terminating the worker. (duration {})", human_elapsed(worker_timeout_ms));
-                        thread_safe_handle.terminate_execution();
-                        EdgeCallResult::TimeOut
+                            if terminate_on_blocked_event_loop {
+                                break;
+                            }
+                        }
                     }
-                    Some(val) = memory_limit_rx.recv() => {
-                        error!("memory limit reached for the worker. terminating the worker. (used: {})", bytes_to_display(val));
-                        thread_safe_handle.terminate_execution();
-                        EdgeCallResult::HeapLimitReached
+                    None => std::future::pending::<()>().await,
+                }
+            };
+
+            let call = tokio::select! {
+                // the event loop completed on its own, nothing left to watch
+                _ = halt_isolate_tx.closed() => return,
+                _ = wait_for_deadline(deadline_rx) => {
+                    debug!("[{}] max duration reached for the worker. terminating the worker. (duration {})", worker_id, human_elapsed(started.elapsed().as_millis() as u64));
+
+                    if let Some(drain_timeout_ms) = drain_timeout_ms.filter(|_| *evaluated_rx.borrow()) {
+                        // the isolate is draining the ops the module left pending (eg:
+                        // streaming response bodies), let it finish unless the event
+                        // loop completes on its own and drops the halt receiver
+                        debug!("[{}] draining pending ops (up to {})", worker_id, human_elapsed(drain_timeout_ms));
+                        tokio::select! {
+                            _ = halt_isolate_tx.closed() => {}
+                            _ = tokio::time::sleep(Duration::from_millis(drain_timeout_ms)) => {}
+                        }
                     }
+
+                    thread_safe_handle.terminate_execution();
+                    EdgeCallResult::TimeOut
+                }
+                Some(()) = terminate_rx.recv() => {
+                    debug!("[{}] the worker was terminated through its handle", worker_id);
+                    thread_safe_handle.terminate_execution();
+                    EdgeCallResult::Terminated
+                }
+                Some(val) = memory_limit_rx.recv() => {
+                    error!("[{}] memory limit reached for the worker. terminating the worker. (used: {})", worker_id, bytes_to_display(val));
+                    thread_safe_handle.terminate_execution();
+                    EdgeCallResult::HeapLimitReached
+                }
+                _ = watchdog => {
+                    error!("[{}] event loop blocked for too long. terminating the worker.", worker_id);
+                    thread_safe_handle.terminate_execution();
+                    EdgeCallResult::EventLoopBlocked
                 }
             };
-            let call = rt.block_on(future);
 
             if halt_isolate_tx.send(call).is_err() {
-                error!("failed to send the halt execution signal");
+                error!("[{}] failed to send the halt execution signal", worker_id);
             }
         });
     }
@@ -329,13 +1206,25 @@ impl EdgeRuntime {
 
 #[cfg(test)]
 mod test {
-    use crate::edge_runtime::{EdgeCallResult, EdgeRuntime};
+    use crate::bootstrap::IsolateKind;
+    use crate::edge_runtime::{
+        load_client_cert, runtime_extension_names, runtime_extensions, EdgeCallResult, EdgeRuntime,
+    };
+    use crate::worker_handles::worker_handles;
+    use deno_core::futures::TryStreamExt;
+    use sb_core::streams::{add_readable, byte_stream, take_writable};
     use sb_worker_context::essentials::{
-        EdgeContextInitOpts, EdgeContextOpts, EdgeMainRuntimeOpts, EdgeUserRuntimeOpts,
-        UserWorkerMsgs,
+        BackpressureOpts, Capability, ClientCertOpts, CompatFlag, EdgeContextInitOpts,
+        EdgeContextOpts, EdgeMainRuntimeOpts, EdgeUserRuntimeOpts, OutboundOpts, UserWorkerMsgs,
     };
+    use sb_worker_context::events::WorkerEvents;
+    use sb_worker_context::extensions::{WorkerExtensions, WorkerExtensionsState};
+    use sb_worker_context::resolution::ResolutionDiagnostic;
+    use sb_worker_context::routes::RouteTable;
     use std::collections::HashMap;
     use std::path::PathBuf;
+    use std::sync::Arc;
+    use std::time::{Duration, Instant};
     use tokio::net::UnixStream;
     use tokio::sync::oneshot::{Receiver, Sender};
     use tokio::sync::{mpsc, oneshot};
@@ -350,13 +1239,24 @@ mod test {
         EdgeRuntime::new(EdgeContextInitOpts {
             service_path: path.unwrap_or(PathBuf::from("./examples/main")),
             no_module_cache: false,
+            offline: false,
             import_map_path: None,
+            auth_tokens: None,
             env_vars: env_vars.unwrap_or(Default::default()),
+            fetch_interceptor: None,
+            outbound: OutboundOpts::default(),
+            extensions: None,
+            unstable_features: vec![],
             conf: {
                 if let Some(uc) = user_conf {
                     uc
                 } else {
-                    EdgeContextOpts::MainWorker(EdgeMainRuntimeOpts { worker_pool_tx })
+                    EdgeContextOpts::MainWorker(EdgeMainRuntimeOpts {
+                        worker_pool_tx,
+                        warmup_specifiers: vec![],
+                        routes: RouteTable::default(),
+                        fetch_breakers: None,
+                    })
                 }
             },
         })
@@ -469,6 +1369,152 @@ mod test {
         assert!(user_serde_deno_env.unwrap().is_null());
     }
 
+    #[cfg(all(
+        feature = "postgres",
+        feature = "ai",
+        feature = "storage",
+        feature = "queue"
+    ))]
+    #[tokio::test]
+    async fn test_user_worker_globals() {
+        let mut user_rt = create_basic_user_runtime("./test_cases/deno_serve", 100, 1000);
+        let globals = user_rt
+            .js_runtime
+            .execute_script(
+                "<anon>",
+                "[typeof EdgeRuntime.postgres.connect, typeof EdgeRuntime.ai.embed, typeof EdgeRuntime.storage.signedUrl, typeof EdgeRuntime.queue.consume, typeof EdgeRuntime.services.get, typeof EdgeRuntime.userWorkers]",
+            )
+            .unwrap();
+        let globals = user_rt.to_value::<Vec<String>>(&globals).unwrap();
+        assert_eq!(
+            globals,
+            vec![
+                "function",
+                "function",
+                "function",
+                "function",
+                "function",
+                "undefined"
+            ]
+        );
+    }
+
+    // the ops driving the pool aren't in the isolates of user workers at all
+    #[tokio::test]
+    async fn test_supervisor_ops() {
+        let script = "[typeof Deno.core.ops.op_user_worker_create, typeof Deno.core.ops.op_router_register, typeof Deno.core.ops.op_service_binding_send]";
+        let mut main_rt = create_runtime(None, None, None);
+        let mut user_rt = create_runtime(
+            None,
+            None,
+            Some(EdgeContextOpts::UserWorker(Default::default())),
+        );
+
+        let main_ops = main_rt.js_runtime.execute_script("<anon>", script).unwrap();
+        let main_ops = main_rt.to_value::<Vec<String>>(&main_ops).unwrap();
+        assert_eq!(main_ops, vec!["function", "function", "function"]);
+
+        let user_ops = user_rt.js_runtime.execute_script("<anon>", script).unwrap();
+        let user_ops = user_rt.to_value::<Vec<String>>(&user_ops).unwrap();
+        assert_eq!(user_ops, vec!["undefined", "undefined", "function"]);
+    }
+
+    #[test]
+    fn test_invalid_client_cert() {
+        let err = load_client_cert(&ClientCertOpts {
+            cert_chain: "not a certificate".to_string(),
+            private_key: "not a key".to_string(),
+        })
+        .unwrap_err();
+        assert!(err
+            .to_string()
+            .starts_with("invalid client certificate chain"));
+    }
+
+    // deno_core ships v8 with the full ICU data, not only english
+    #[tokio::test]
+    async fn test_intl_locales() {
+        let mut user_rt = create_runtime(
+            None,
+            None,
+            Some(EdgeContextOpts::UserWorker(EdgeUserRuntimeOpts {
+                locale: Some("de-DE".to_string()),
+                timezone: Some("Asia/Tokyo".to_string()),
+                ..Default::default()
+            })),
+        );
+
+        let formatted = user_rt
+            .js_runtime
+            .execute_script(
+                "<anon>",
+                r#"
+            [
+                new Intl.NumberFormat("fr-FR").format(1234.5),
+                new Intl.DateTimeFormat("ja-JP", { month: "long", timeZone: "UTC" }).format(0),
+                ["z", "ä", "a"].sort((a, b) => a.localeCompare(b, "sv")).join(""),
+                // the worker's locale and time zone are the defaults
+                (1234.5).toLocaleString(),
+                new Date(0).toLocaleTimeString(undefined, { hour: "numeric" }),
+                navigator.language,
+            ]
+        "#,
+            )
+            .unwrap();
+        let formatted = user_rt.to_value::<Vec<String>>(&formatted).unwrap();
+        assert_eq!(
+            formatted,
+            vec!["1\u{202f}234,5", "1月", "azä", "1.234,5", "09 Uhr", "de-DE"]
+        );
+    }
+
+    // V8 has a single time zone per process, the worker's is applied to Date
+    #[tokio::test]
+    async fn test_date_time_zone() {
+        let mut user_rt = create_runtime(
+            None,
+            None,
+            Some(EdgeContextOpts::UserWorker(EdgeUserRuntimeOpts {
+                timezone: Some("Europe/Paris".to_string()),
+                ..Default::default()
+            })),
+        );
+
+        let dates = user_rt
+            .js_runtime
+            .execute_script(
+                "<anon>",
+                r#"
+            const summer = new Date(Date.UTC(2024, 6, 1, 12));
+            const midnight = new Date(0);
+            midnight.setHours(0);
+            [
+                new Date(0).getHours(),
+                summer.getHours(),
+                summer.getTimezoneOffset(),
+                new Date(2024, 0, 1, 9).getTime(),
+                midnight.getTime(),
+                summer.toString(),
+                new Date() instanceof Date,
+            ].map(String)
+        "#,
+            )
+            .unwrap();
+        let dates = user_rt.to_value::<Vec<String>>(&dates).unwrap();
+        assert_eq!(
+            dates,
+            vec![
+                "1",
+                "14",
+                "-120",
+                "1704096000000",
+                "-3600000",
+                "Mon Jul 01 2024 14:00:00 GMT+0200 (Central European Summer Time)",
+                "true",
+            ]
+        );
+    }
+
     fn create_basic_user_runtime(
         path: &str,
         memory_limit: u64,
@@ -481,16 +1527,299 @@ mod test {
                 memory_limit_mb: memory_limit,
                 worker_timeout_ms,
                 id: "".to_string(),
+                ..Default::default()
             })),
         )
     }
 
+    // sends a request to a user worker, returning the response body
+    async fn request_user_worker(path: &str, uri: &str) -> String {
+        request_runtime(create_basic_user_runtime(path, 100, 1000), uri).await
+    }
+
+    async fn request_runtime(user_rt: EdgeRuntime, uri: &str) -> String {
+        let (sender_stream, recv_stream) = UnixStream::pair().unwrap();
+        let (shutdown_tx, _shutdown_rx) = oneshot::channel::<()>();
+
+        let request = async {
+            let (mut request_sender, connection) =
+                hyper::client::conn::handshake(sender_stream).await.unwrap();
+            tokio::spawn(connection);
+
+            let req = hyper::Request::get(uri)
+                .header("host", "localhost")
+                .body(hyper::Body::empty())
+                .unwrap();
+            let res = request_sender.send_request(req).await.unwrap();
+            let body = hyper::body::to_bytes(res.into_body()).await.unwrap();
+            String::from_utf8(body.to_vec()).unwrap()
+        };
+
+        let (_, body) = tokio::join!(user_rt.run(recv_stream, shutdown_tx), request);
+        body
+    }
+
     #[tokio::test]
-    async fn test_timeout_infinite_promises() {
-        let user_rt = create_basic_user_runtime("./test_cases/infinite_promises", 100, 1000);
-        let (stream, shutdown, _receiver) = create_user_rt_params_to_run();
-        let data = user_rt.run(stream, shutdown).await.unwrap();
-        assert_eq!(data, EdgeCallResult::ModuleEvaluationTimedOut);
+    async fn test_deno_serve() {
+        let body = request_user_worker("./test_cases/deno_serve", "/hello").await;
+        assert_eq!(body, "hello from /hello");
+    }
+
+    #[tokio::test]
+    async fn test_net_allowlist() {
+        let (events_tx, mut events_rx) = mpsc::unbounded_channel();
+        let user_rt = create_runtime(
+            Some(PathBuf::from("./test_cases/net_allowlist")),
+            None,
+            Some(EdgeContextOpts::UserWorker(EdgeUserRuntimeOpts {
+                id: "tester".to_string(),
+                worker_timeout_ms: 1000,
+                net_allowlist: Some(vec!["*.internal:6379".to_string()]),
+                events_tx: Some(events_tx),
+                ..Default::default()
+            })),
+        );
+        let body = request_runtime(user_rt, "/").await;
+        assert_eq!(body, "PermissionDenied,PermissionDenied");
+
+        // the denials are reported to the embedder
+        let mut denials = vec![];
+        while let Ok(event) = events_rx.try_recv() {
+            assert_eq!(event.worker_id.id, "tester");
+            if let WorkerEvents::PermissionDenied(ev) = event.event {
+                denials.push((ev.api_name, ev.resource));
+            }
+        }
+        assert_eq!(denials.len(), 2);
+        assert!(denials[0].0.starts_with("Deno.connect"));
+        assert_eq!(denials[0].1, "redis.internal:6380");
+        assert!(denials[1].0.starts_with("Deno.listenDatagram"));
+        assert_eq!(denials[1].1, "udp");
+    }
+
+    #[tokio::test]
+    async fn test_crypto_seed() {
+        let seeded_rt = |crypto_seed| {
+            create_runtime(
+                Some(PathBuf::from("./test_cases/crypto_seed")),
+                None,
+                Some(EdgeContextOpts::UserWorker(EdgeUserRuntimeOpts {
+                    worker_timeout_ms: 1000,
+                    crypto_seed,
+                    ..Default::default()
+                })),
+            )
+        };
+        let first = request_runtime(seeded_rt(Some(42)), "/").await;
+        let second = request_runtime(seeded_rt(Some(42)), "/").await;
+        assert_eq!(first, second);
+
+        let unseeded = request_runtime(seeded_rt(None), "/").await;
+        assert_ne!(first, unseeded);
+    }
+
+    #[tokio::test]
+    async fn test_crypto_keys() {
+        let keys_rt = |allow_key_export| {
+            create_runtime(
+                Some(PathBuf::from("./test_cases/crypto_keys")),
+                None,
+                Some(EdgeContextOpts::UserWorker(EdgeUserRuntimeOpts {
+                    worker_timeout_ms: 1000,
+                    allow_key_export,
+                    ..Default::default()
+                })),
+            )
+        };
+        let body = request_runtime(keys_rt(true), "/").await;
+        assert_eq!(body, "true,true,32,none");
+
+        // public keys can still be exported
+        let body = request_runtime(keys_rt(false), "/").await;
+        assert_eq!(body, "true,true,32,InvalidAccessError");
+    }
+
+    // without the net capability, the socket ops are gone but the worker is
+    // still served
+    #[tokio::test]
+    async fn test_strict_capabilities() {
+        let user_rt = create_runtime(
+            Some(PathBuf::from("./test_cases/no_net")),
+            None,
+            Some(EdgeContextOpts::UserWorker(EdgeUserRuntimeOpts {
+                worker_timeout_ms: 1000,
+                capabilities: Some(vec![]),
+                ..Default::default()
+            })),
+        );
+        let body = request_runtime(user_rt, "/").await;
+        assert_eq!(body, "PermissionDenied,PermissionDenied");
+
+        let user_rt = create_runtime(
+            Some(PathBuf::from("./test_cases/no_net")),
+            None,
+            Some(EdgeContextOpts::UserWorker(EdgeUserRuntimeOpts {
+                worker_timeout_ms: 1000,
+                capabilities: Some(vec![Capability::Net]),
+                ..Default::default()
+            })),
+        );
+        let body = request_runtime(user_rt, "/").await;
+        assert!(body.starts_with("ConnectionRefused,"));
+    }
+
+    // deno_net's ops aren't in the isolates of workers without the net
+    // capability, fetch (and deno_fetch) stays, deno_http is built on it
+    #[tokio::test]
+    async fn test_strict_net_ops() {
+        let script = "[typeof Deno.core.ops.op_net_connect_tcp, typeof Deno.core.ops.op_net_connect_tls, typeof Deno.core.ops.op_dns_resolve, typeof Deno.connect, typeof fetch]";
+        let create = |capabilities| {
+            create_runtime(
+                None,
+                None,
+                Some(EdgeContextOpts::UserWorker(EdgeUserRuntimeOpts {
+                    capabilities: Some(capabilities),
+                    ..Default::default()
+                })),
+            )
+        };
+
+        let mut strict_rt = create(vec![]);
+        let strict_ops = strict_rt
+            .js_runtime
+            .execute_script("<anon>", script)
+            .unwrap();
+        let strict_ops = strict_rt.to_value::<Vec<String>>(&strict_ops).unwrap();
+        assert_eq!(
+            strict_ops,
+            vec![
+                "undefined",
+                "undefined",
+                "undefined",
+                "function",
+                "function"
+            ]
+        );
+
+        let mut net_rt = create(vec![Capability::Net]);
+        let net_ops = net_rt.js_runtime.execute_script("<anon>", script).unwrap();
+        let net_ops = net_rt.to_value::<Vec<String>>(&net_ops).unwrap();
+        assert_eq!(
+            net_ops,
+            vec!["function", "function", "function", "function", "function"]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_default_export_fetch() {
+        let body = request_user_worker("./test_cases/default_export", "/hello").await;
+        assert_eq!(body, "hello from /hello");
+    }
+
+    #[tokio::test]
+    async fn test_concurrent_requests() {
+        let user_rt = create_basic_user_runtime("./test_cases/concurrent_requests", 100, 1000);
+        let (sender_stream, recv_stream) = UnixStream::pair().unwrap();
+        let (shutdown_tx, _shutdown_rx) = oneshot::channel::<()>();
+
+        let requests = async {
+            let (mut request_sender, connection) = hyper::client::conn::Builder::new()
+                .http2_only(true)
+                .handshake(sender_stream)
+                .await
+                .unwrap();
+            tokio::spawn(connection);
+
+            let mut send = |uri: &str| {
+                let req = hyper::Request::get(format!("http://localhost{}", uri))
+                    .body(hyper::Body::empty())
+                    .unwrap();
+                let res = request_sender.send_request(req);
+                async move {
+                    let body = hyper::body::to_bytes(res.await.unwrap().into_body());
+                    String::from_utf8(body.await.unwrap().to_vec()).unwrap()
+                }
+            };
+
+            // the first request is only answered once the second one reached the worker
+            let waited = send("/wait");
+            let released = send("/release");
+            tokio::join!(waited, released)
+        };
+
+        let (_, (waited, released)) = tokio::join!(user_rt.run(recv_stream, shutdown_tx), requests);
+        assert_eq!(waited, "released");
+        assert_eq!(released, "ok");
+    }
+
+    // sends 2 requests at once to the `slow_response` worker, which answers
+    // each 200ms after it's taken, returning how long both took
+    async fn two_slow_requests(backpressure: Option<BackpressureOpts>) -> Duration {
+        let user_rt = create_runtime(
+            Some(PathBuf::from("./test_cases/slow_response")),
+            None,
+            Some(EdgeContextOpts::UserWorker(EdgeUserRuntimeOpts {
+                memory_limit_mb: 100,
+                worker_timeout_ms: 2000,
+                backpressure,
+                ..Default::default()
+            })),
+        );
+        let (sender_stream, recv_stream) = UnixStream::pair().unwrap();
+        let (shutdown_tx, _shutdown_rx) = oneshot::channel::<()>();
+
+        let requests = async {
+            let (mut request_sender, connection) = hyper::client::conn::Builder::new()
+                .http2_only(true)
+                .handshake(sender_stream)
+                .await
+                .unwrap();
+            tokio::spawn(connection);
+
+            let mut send = || {
+                let req = hyper::Request::get("http://localhost/")
+                    .body(hyper::Body::empty())
+                    .unwrap();
+                let res = request_sender.send_request(req);
+                async move {
+                    let body = hyper::body::to_bytes(res.await.unwrap().into_body());
+                    String::from_utf8(body.await.unwrap().to_vec()).unwrap()
+                }
+            };
+
+            let start = Instant::now();
+            let (first, second) = (send(), send());
+            let bodies = tokio::join!(first, second);
+            (bodies, start.elapsed())
+        };
+
+        let (_, (bodies, elapsed)) = tokio::join!(user_rt.run(recv_stream, shutdown_tx), requests);
+        assert_eq!(bodies, ("ok".to_string(), "ok".to_string()));
+        elapsed
+    }
+
+    #[tokio::test]
+    async fn test_backpressure() {
+        // the second request stays in the connection until the first one is
+        // answered, rather than being rejected
+        let elapsed = two_slow_requests(Some(BackpressureOpts {
+            max_in_flight: Some(1),
+            ..Default::default()
+        }))
+        .await;
+        assert!(elapsed >= Duration::from_millis(400));
+
+        // both are handled at once otherwise
+        let elapsed = two_slow_requests(None).await;
+        assert!(elapsed < Duration::from_millis(400));
+    }
+
+    #[tokio::test]
+    async fn test_timeout_infinite_promises() {
+        let user_rt = create_basic_user_runtime("./test_cases/infinite_promises", 100, 1000);
+        let (stream, shutdown, _receiver) = create_user_rt_params_to_run();
+        let data = user_rt.run(stream, shutdown).await.unwrap();
+        assert_eq!(data, EdgeCallResult::ModuleEvaluationTimedOut);
     }
 
     #[tokio::test]
@@ -527,6 +1856,335 @@ mod test {
         assert_eq!(data, EdgeCallResult::Completed);
     }
 
+    // runs the worker, returning how it ended and what it logged
+    async fn run_draining_user_runtime(
+        worker_timeout_ms: u64,
+        drain_timeout_ms: Option<u64>,
+    ) -> (EdgeCallResult, Vec<String>) {
+        let (events_tx, mut events_rx) = mpsc::unbounded_channel();
+        let user_rt = create_runtime(
+            Some(PathBuf::from("./test_cases/drain_pending_ops")),
+            None,
+            Some(EdgeContextOpts::UserWorker(EdgeUserRuntimeOpts {
+                worker_timeout_ms,
+                drain_timeout_ms,
+                forward_logs: true,
+                events_tx: Some(events_tx),
+                ..Default::default()
+            })),
+        );
+        let (stream, shutdown, _receiver) = create_user_rt_params_to_run();
+        let data = user_rt.run(stream, shutdown).await.unwrap();
+
+        let mut logs = vec![];
+        while let Ok(event) = events_rx.try_recv() {
+            if let WorkerEvents::Log(ev) = event.event {
+                logs.push(ev.msg);
+            }
+        }
+        (data, logs)
+    }
+
+    #[tokio::test]
+    async fn test_drain_pending_ops() {
+        // the timer fires after the module evaluated, within the drain timeout
+        let (data, logs) = run_draining_user_runtime(100, Some(1000)).await;
+        assert_eq!(data, EdgeCallResult::Completed);
+        assert!(logs.iter().any(|msg| msg.contains("timer fired")));
+
+        // without one, the worker is cut off at its wall clock limit
+        let (data, logs) = run_draining_user_runtime(100, None).await;
+        assert_eq!(data, EdgeCallResult::TimeOut);
+        assert!(!logs.iter().any(|msg| msg.contains("timer fired")));
+    }
+
+    fn create_extensible_user_runtime(max_extension_ms: Option<u64>) -> EdgeRuntime {
+        create_runtime(
+            Some(PathBuf::from("./test_cases/extend_deadline")),
+            None,
+            Some(EdgeContextOpts::UserWorker(EdgeUserRuntimeOpts {
+                worker_timeout_ms: 1000,
+                max_wall_clock_extension_ms: max_extension_ms,
+                ..Default::default()
+            })),
+        )
+    }
+
+    #[tokio::test]
+    async fn test_extend_deadline() {
+        let user_rt = create_extensible_user_runtime(Some(1000));
+        let (stream, shutdown, _receiver) = create_user_rt_params_to_run();
+        let data = user_rt.run(stream, shutdown).await.unwrap();
+        assert_eq!(data, EdgeCallResult::Completed);
+
+        // only 200ms of the 1000ms asked for are granted
+        let user_rt = create_extensible_user_runtime(Some(200));
+        let (stream, shutdown, _receiver) = create_user_rt_params_to_run();
+        let data = user_rt.run(stream, shutdown).await.unwrap();
+        assert_eq!(data, EdgeCallResult::TimeOut);
+
+        // not allowed to
+        let user_rt = create_extensible_user_runtime(None);
+        let (stream, shutdown, _receiver) = create_user_rt_params_to_run();
+        assert!(user_rt.run(stream, shutdown).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_runtime_info() {
+        let user_rt = create_runtime(
+            Some(PathBuf::from("./test_cases/runtime_info")),
+            None,
+            Some(EdgeContextOpts::UserWorker(Default::default())),
+        );
+        let (stream, shutdown, _receiver) = create_user_rt_params_to_run();
+        let data = user_rt.run(stream, shutdown).await.unwrap();
+        assert_eq!(data, EdgeCallResult::Completed);
+    }
+
+    #[tokio::test]
+    async fn test_worker_handle() {
+        let user_rt = create_runtime(
+            Some(PathBuf::from("./test_cases/resolve_promise_after_timeout")),
+            None,
+            Some(EdgeContextOpts::UserWorker(EdgeUserRuntimeOpts {
+                id: "supervised".to_string(),
+                worker_timeout_ms: 10000,
+                ..Default::default()
+            })),
+        );
+        let (stream, shutdown, _receiver) = create_user_rt_params_to_run();
+
+        let supervisor = async {
+            let handle = loop {
+                if let Some(handle) = worker_handles("supervised").pop() {
+                    break handle;
+                }
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            };
+            let stats = handle.stats();
+            assert_eq!(stats.worker_id, "supervised");
+            assert!(stats.deadline_in > Duration::from_secs(5));
+            assert!(stats.deadline_in <= Duration::from_secs(10));
+
+            handle.extend_deadline(Duration::from_secs(5));
+            assert!(handle.stats().deadline_in > Duration::from_secs(10));
+            handle.terminate();
+        };
+
+        let (data, _) = tokio::join!(user_rt.run(stream, shutdown), supervisor);
+        assert_eq!(data.unwrap(), EdgeCallResult::Terminated);
+        // unregistered with the isolate
+        assert!(worker_handles("supervised").is_empty());
+    }
+
+    #[test]
+    fn test_runtime_extension_names() {
+        for isolate_kind in [
+            IsolateKind::Main,
+            IsolateKind::User,
+            IsolateKind::UserWithoutNet,
+        ] {
+            let extensions = runtime_extensions(false, isolate_kind, None, None, None, None, None);
+            assert_eq!(
+                runtime_extension_names(isolate_kind).len(),
+                extensions.len()
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn test_worker_id() {
+        let user_rt = create_runtime(
+            Some(PathBuf::from("./test_cases/resolve_promise_after_timeout")),
+            None,
+            Some(EdgeContextOpts::UserWorker(EdgeUserRuntimeOpts {
+                id: "1b4e28ba-2fa1-11d2-883f-0016d3cca427".to_string(),
+                service_name: Some("hello".to_string()),
+                deployment: Some("v2".to_string()),
+                ..Default::default()
+            })),
+        );
+        assert_eq!(user_rt.worker_id.label, "hello@v2");
+        assert_eq!(user_rt.worker_id.to_string(), "hello@v2 (1b4e28ba)");
+
+        // named after the path of the service, without a service name
+        let user_rt = create_runtime(
+            Some(PathBuf::from("./test_cases/resolve_promise_after_timeout")),
+            None,
+            Some(EdgeContextOpts::UserWorker(EdgeUserRuntimeOpts {
+                id: "1b4e28ba-2fa1-11d2-883f-0016d3cca427".to_string(),
+                ..Default::default()
+            })),
+        );
+        assert_eq!(
+            user_rt.worker_id.label,
+            "test_cases/resolve_promise_after_timeout"
+        );
+
+        let main_rt = create_runtime(None, None, None);
+        assert_eq!(main_rt.worker_id.to_string(), "main");
+    }
+
+    #[tokio::test]
+    async fn test_blocked_event_loop() {
+        let user_rt = create_runtime(
+            Some(PathBuf::from("./test_cases/blocked_event_loop")),
+            None,
+            Some(EdgeContextOpts::UserWorker(EdgeUserRuntimeOpts {
+                worker_timeout_ms: 5000,
+                event_loop_lag_threshold_ms: Some(200),
+                terminate_on_blocked_event_loop: true,
+                ..Default::default()
+            })),
+        );
+        let (stream, shutdown, _receiver) = create_user_rt_params_to_run();
+        let data = user_rt.run(stream, shutdown).await.unwrap();
+        assert_eq!(data, EdgeCallResult::EventLoopBlocked);
+    }
+
+    #[tokio::test]
+    async fn test_slow_boot_is_not_a_blocked_event_loop() {
+        // evaluating the module takes longer than the threshold
+        let user_rt = create_runtime(
+            Some(PathBuf::from("./test_cases/slow_boot")),
+            None,
+            Some(EdgeContextOpts::UserWorker(EdgeUserRuntimeOpts {
+                worker_timeout_ms: 5000,
+                event_loop_lag_threshold_ms: Some(200),
+                terminate_on_blocked_event_loop: true,
+                ..Default::default()
+            })),
+        );
+        let (stream, shutdown, _receiver) = create_user_rt_params_to_run();
+        let data = user_rt.run(stream, shutdown).await.unwrap();
+        assert_eq!(data, EdgeCallResult::Completed);
+    }
+
+    #[tokio::test]
+    async fn test_unhandled_rejection_terminates_worker() {
+        let user_rt = create_runtime(
+            Some(PathBuf::from("./test_cases/unhandled_rejection")),
+            None,
+            Some(EdgeContextOpts::UserWorker(EdgeUserRuntimeOpts {
+                terminate_on_unhandled_rejection: true,
+                ..Default::default()
+            })),
+        );
+        let (stream, shutdown, _receiver) = create_user_rt_params_to_run();
+        let data = user_rt.run(stream, shutdown).await.unwrap();
+        assert_eq!(data, EdgeCallResult::UncaughtException);
+
+        // with the compatibility date of the service
+        let user_rt = create_runtime(
+            Some(PathBuf::from("./test_cases/unhandled_rejection")),
+            None,
+            Some(EdgeContextOpts::UserWorker(EdgeUserRuntimeOpts {
+                compat_flags: vec![CompatFlag::UnhandledRejectionTerminates],
+                ..Default::default()
+            })),
+        );
+        let (stream, shutdown, _receiver) = create_user_rt_params_to_run();
+        let data = user_rt.run(stream, shutdown).await.unwrap();
+        assert_eq!(data, EdgeCallResult::UncaughtException);
+    }
+
+    #[tokio::test]
+    async fn test_boot_failure_is_notified() {
+        let (boot_tx, boot_rx) = oneshot::channel();
+        let user_rt = create_basic_user_runtime("./test_cases/boot_error", 100, 1000)
+            .with_boot_notifier(boot_tx);
+        let (stream, shutdown, _receiver) = create_user_rt_params_to_run();
+        assert!(user_rt.run(stream, shutdown).await.is_err());
+        assert!(boot_rx.await.unwrap().is_err());
+    }
+
+    #[tokio::test]
+    async fn test_warmup_modules() {
+        let (worker_pool_tx, _) = mpsc::unbounded_channel::<UserWorkerMsgs>();
+        let mut runtime = EdgeRuntime::new(EdgeContextInitOpts {
+            service_path: PathBuf::from("./test_cases/resolution_error"),
+            no_module_cache: false,
+            offline: false,
+            import_map_path: None,
+            auth_tokens: None,
+            env_vars: HashMap::new(),
+            fetch_interceptor: None,
+            outbound: OutboundOpts::default(),
+            extensions: None,
+            unstable_features: vec![],
+            conf: EdgeContextOpts::MainWorker(EdgeMainRuntimeOpts {
+                worker_pool_tx,
+                // specifiers that can't be resolved are skipped
+                warmup_specifiers: vec!["./index.ts".to_string(), "missing".to_string()],
+                routes: RouteTable::default(),
+                fetch_breakers: None,
+            }),
+        })
+        .unwrap();
+
+        assert_eq!(runtime.warmup_modules.len(), 1);
+        assert!(runtime.warmup_modules[0]
+            .as_str()
+            .ends_with("/resolution_error/index.ts"));
+
+        // failing to load a module doesn't fail the boot
+        let modules = runtime.warmup_modules.clone();
+        EdgeRuntime::warmup(&mut runtime.js_runtime, &modules).await;
+    }
+
+    #[tokio::test]
+    async fn test_resolution_failure_diagnostic() {
+        let (boot_tx, boot_rx) = oneshot::channel();
+        let user_rt = create_basic_user_runtime("./test_cases/resolution_error", 100, 1000)
+            .with_boot_notifier(boot_tx);
+        let (stream, shutdown, _receiver) = create_user_rt_params_to_run();
+        assert!(user_rt.run(stream, shutdown).await.is_err());
+
+        let err = boot_rx.await.unwrap().unwrap_err();
+        let diagnostic = err
+            .chain()
+            .find_map(|e| e.downcast_ref::<ResolutionDiagnostic>())
+            .unwrap();
+        assert_eq!(diagnostic.specifier, "sdt/fmt/colors.ts");
+        assert_eq!(diagnostic.suggestions, vec!["std/".to_string()]);
+        assert_eq!(diagnostic.chain.len(), 2);
+        assert!(diagnostic.chain[0].ends_with("/resolution_error/greet.ts"));
+        assert!(diagnostic.chain[1].ends_with("/resolution_error/index.ts"));
+    }
+
+    #[tokio::test]
+    async fn test_offline_missing_modules() {
+        let (boot_tx, boot_rx) = oneshot::channel();
+        let user_rt = EdgeRuntime::new(EdgeContextInitOpts {
+            service_path: PathBuf::from("./test_cases/offline"),
+            no_module_cache: false,
+            offline: true,
+            import_map_path: None,
+            auth_tokens: None,
+            env_vars: HashMap::new(),
+            fetch_interceptor: None,
+            outbound: OutboundOpts::default(),
+            extensions: None,
+            unstable_features: vec![],
+            conf: EdgeContextOpts::UserWorker(EdgeUserRuntimeOpts {
+                memory_limit_mb: 100,
+                worker_timeout_ms: 1000,
+                id: "".to_string(),
+                ..Default::default()
+            }),
+        })
+        .unwrap()
+        .with_boot_notifier(boot_tx);
+        let (stream, shutdown, _receiver) = create_user_rt_params_to_run();
+        assert!(user_rt.run(stream, shutdown).await.is_err());
+
+        // every missing module is reported, not only the first one
+        let err = boot_rx.await.unwrap().unwrap_err().to_string();
+        assert!(err.starts_with("offline mode: 2 remote module(s)"));
+        assert!(err.contains("https://example.invalid/greet.ts"));
+        assert!(err.contains("https://example.invalid/colors.ts"));
+    }
+
     #[tokio::test]
     async fn test_heap_limits_reached() {
         let user_rt = create_basic_user_runtime("./test_cases/heap_limit", 5, 1000);
@@ -534,4 +2192,164 @@ mod test {
         let data = user_rt.run(stream, shutdown).await.unwrap();
         assert_eq!(data, EdgeCallResult::HeapLimitReached);
     }
+
+    #[derive(Debug)]
+    struct BillingExtensions;
+
+    struct BillingPlan(String);
+
+    #[deno_core::op]
+    fn op_billing_plan(state: &mut deno_core::OpState) -> String {
+        state.borrow::<BillingPlan>().0.clone()
+    }
+
+    deno_core::extension!(billing, ops = [op_billing_plan]);
+
+    impl WorkerExtensions for BillingExtensions {
+        fn extensions(&self, _with_esm: bool) -> Vec<deno_core::Extension> {
+            vec![billing::init_ops()]
+        }
+
+        fn init_state(&self, state: &mut deno_core::OpState) {
+            state.put(BillingPlan("pro".to_string()));
+        }
+    }
+
+    #[tokio::test]
+    async fn test_worker_extensions() {
+        let (worker_pool_tx, _) = mpsc::unbounded_channel::<UserWorkerMsgs>();
+        let mut runtime = EdgeRuntime::new(EdgeContextInitOpts {
+            service_path: PathBuf::from("./examples/main"),
+            no_module_cache: false,
+            offline: false,
+            import_map_path: None,
+            auth_tokens: None,
+            env_vars: HashMap::new(),
+            fetch_interceptor: None,
+            outbound: OutboundOpts::default(),
+            extensions: Some(Arc::new(BillingExtensions)),
+            unstable_features: vec![],
+            conf: EdgeContextOpts::MainWorker(EdgeMainRuntimeOpts {
+                worker_pool_tx,
+                warmup_specifiers: vec![],
+                routes: RouteTable::default(),
+                fetch_breakers: None,
+            }),
+        })
+        .unwrap();
+
+        let plan = runtime
+            .js_runtime
+            .execute_script("<anon>", "Deno.core.ops.op_billing_plan()")
+            .unwrap();
+        let plan = runtime
+            .to_value::<deno_core::serde_json::Value>(&plan)
+            .unwrap();
+        assert_eq!(plan.as_str(), Some("pro"));
+
+        // passed on to the user workers it creates
+        let op_state = runtime.js_runtime.op_state();
+        assert!(op_state
+            .borrow()
+            .try_borrow::<WorkerExtensionsState>()
+            .is_some());
+    }
+
+    #[derive(Debug)]
+    struct StreamExtensions;
+
+    // "1,2,3" from a task, as a stream read from JS
+    #[deno_core::op]
+    fn op_test_stream_numbers(state: &mut deno_core::OpState) -> deno_core::ResourceId {
+        let (sender, stream) = byte_stream();
+        let chunks = ["1,", "2,", "3"]
+            .into_iter()
+            .map(|chunk| Ok::<_, deno_core::error::AnyError>(bytes::Bytes::from(chunk)));
+        tokio::spawn(sender.pipe(deno_core::futures::stream::iter(chunks)));
+        add_readable(state, stream)
+    }
+
+    // what JS writes to the stream `rid`
+    #[deno_core::op]
+    async fn op_test_stream_collect(
+        state: std::rc::Rc<std::cell::RefCell<deno_core::OpState>>,
+        rid: deno_core::ResourceId,
+    ) -> Result<String, deno_core::error::AnyError> {
+        let stream = take_writable(&mut state.borrow_mut(), rid)?;
+        let chunks: Vec<_> = stream.try_collect().await?;
+        Ok(String::from_utf8(chunks.concat())?)
+    }
+
+    deno_core::extension!(
+        test_streams,
+        ops = [op_test_stream_numbers, op_test_stream_collect]
+    );
+
+    impl WorkerExtensions for StreamExtensions {
+        fn extensions(&self, _with_esm: bool) -> Vec<deno_core::Extension> {
+            vec![test_streams::init_ops()]
+        }
+    }
+
+    // through the resources and JS of an extension's ops, the streams
+    // themselves are tested in sb_core
+    #[tokio::test]
+    async fn test_byte_streams() {
+        let (worker_pool_tx, _) = mpsc::unbounded_channel::<UserWorkerMsgs>();
+        let mut runtime = EdgeRuntime::new(EdgeContextInitOpts {
+            service_path: PathBuf::from("./examples/main"),
+            no_module_cache: false,
+            offline: false,
+            import_map_path: None,
+            auth_tokens: None,
+            env_vars: HashMap::new(),
+            fetch_interceptor: None,
+            outbound: OutboundOpts::default(),
+            extensions: Some(Arc::new(StreamExtensions)),
+            unstable_features: vec![],
+            conf: EdgeContextOpts::MainWorker(EdgeMainRuntimeOpts {
+                worker_pool_tx,
+                warmup_specifiers: vec![],
+                routes: RouteTable::default(),
+                fetch_breakers: None,
+            }),
+        })
+        .unwrap();
+
+        // read in reads smaller than the chunks, then written back
+        runtime
+            .js_runtime
+            .execute_script(
+                "<anon>",
+                r#"(async () => {
+                    const core = Deno.core;
+                    const rid = core.ops.op_test_stream_numbers();
+                    const buf = new Uint8Array(1);
+                    let read = "";
+                    for (let n; (n = await core.read(rid, buf)) > 0;) {
+                        read += core.decode(buf.subarray(0, n));
+                    }
+                    core.close(rid);
+
+                    const sink = core.ops.op_stream_writable_new();
+                    const collected = core.opAsync("op_test_stream_collect", sink);
+                    for (const chunk of ["a", "bc", "def"]) {
+                        await core.opAsync("op_stream_write", sink, core.encode(chunk));
+                    }
+                    await core.opAsync("op_stream_write_end", sink, null);
+                    globalThis.streamed = [read, await collected];
+                })()"#,
+            )
+            .unwrap();
+        runtime.js_runtime.run_event_loop(false).await.unwrap();
+
+        let streamed = runtime
+            .js_runtime
+            .execute_script("<anon>", "globalThis.streamed")
+            .unwrap();
+        let streamed = runtime
+            .to_value::<deno_core::serde_json::Value>(&streamed)
+            .unwrap();
+        assert_eq!(streamed, deno_core::serde_json::json!(["1,2,3", "abcdef"]));
+    }
 }
@@ -0,0 +1,208 @@
+use anyhow::{bail, Context, Error};
+use hyper::header::{HeaderMap, HeaderName, HeaderValue};
+use maxminddb::{geoip2, MaxMindDBError, Reader};
+use once_cell::sync::OnceCell;
+use std::net::IpAddr;
+use std::path::Path;
+
+// set by the server on every request when the client is found in the
+// databases, the names are URL encoded
+pub const COUNTRY_HEADER: &str = "x-edge-runtime-country";
+pub const REGION_HEADER: &str = "x-edge-runtime-region";
+pub const CITY_HEADER: &str = "x-edge-runtime-city";
+pub const ASN_HEADER: &str = "x-edge-runtime-asn";
+pub const AS_ORG_HEADER: &str = "x-edge-runtime-as-org";
+
+const GEO_HEADERS: [&str; 5] = [
+    COUNTRY_HEADER,
+    REGION_HEADER,
+    CITY_HEADER,
+    ASN_HEADER,
+    AS_ORG_HEADER,
+];
+
+static GEOIP: OnceCell<GeoIp> = OnceCell::new();
+
+// Where the client of a request is, as far as the databases know.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct GeoInfo {
+    // ISO 3166-1 (eg: "DE")
+    pub country: Option<String>,
+    // ISO 3166-2 subdivision, without the country (eg: "BE")
+    pub region: Option<String>,
+    // in English
+    pub city: Option<String>,
+    pub asn: Option<u32>,
+    pub as_org: Option<String>,
+}
+
+// MaxMind databases (or compatible ones), a City or Country database for the
+// location and an ASN database for the network.
+pub struct GeoIp {
+    location: Option<Reader<Vec<u8>>>,
+    asn: Option<Reader<Vec<u8>>>,
+}
+
+impl GeoIp {
+    pub fn open(paths: &[impl AsRef<Path>]) -> Result<Self, Error> {
+        let mut geoip = Self {
+            location: None,
+            asn: None,
+        };
+        for path in paths {
+            let path = path.as_ref();
+            let reader = Reader::open_readfile(path)
+                .with_context(|| format!("can't open the geoip database {}", path.display()))?;
+            let kind = reader.metadata.database_type.clone();
+            let slot = if kind.contains("ASN") {
+                &mut geoip.asn
+            } else if kind.contains("City") || kind.contains("Country") {
+                &mut geoip.location
+            } else {
+                bail!(
+                    "{} is a {} database, expected a City, Country or ASN one",
+                    path.display(),
+                    kind
+                );
+            };
+            if slot.replace(reader).is_some() {
+                bail!("more than one {} database is given", kind);
+            }
+        }
+        Ok(geoip)
+    }
+
+    // nothing is known of private and unlisted addresses
+    pub fn lookup(&self, ip: IpAddr) -> GeoInfo {
+        let mut info = GeoInfo::default();
+        if let Some(reader) = &self.location {
+            // a Country database has the fields of a City one it has
+            if let Some(city) = found(reader.lookup::<geoip2::City>(ip)) {
+                info.country = city
+                    .country
+                    .and_then(|country| country.iso_code)
+                    .map(String::from);
+                info.region = city
+                    .subdivisions
+                    .and_then(|subdivisions| subdivisions.into_iter().next())
+                    .and_then(|subdivision| subdivision.iso_code)
+                    .map(String::from);
+                info.city = city
+                    .city
+                    .and_then(|city| city.names)
+                    .and_then(|names| names.get("en").map(|name| name.to_string()));
+            }
+        }
+        if let Some(reader) = &self.asn {
+            if let Some(asn) = found(reader.lookup::<geoip2::Asn>(ip)) {
+                info.asn = asn.autonomous_system_number;
+                info.as_org = asn.autonomous_system_organization.map(String::from);
+            }
+        }
+        info
+    }
+}
+
+fn found<T>(res: Result<T, MaxMindDBError>) -> Option<T> {
+    match res {
+        Ok(record) => Some(record),
+        Err(MaxMindDBError::AddressNotFoundError(_)) => None,
+        Err(err) => {
+            log::debug!("geoip lookup failed: {}", err);
+            None
+        }
+    }
+}
+
+// Enriches the requests with where their client is. Must be called before
+// the server starts.
+pub fn init_geoip(paths: &[impl AsRef<Path>]) -> Result<(), Error> {
+    let geoip = GeoIp::open(paths)?;
+    if GEOIP.set(geoip).is_err() {
+        bail!("the geoip databases are already opened");
+    }
+    Ok(())
+}
+
+// Sets the location headers of a request from `client_ip`. The ones it came
+// with are dropped, so workers can't be fooled by spoofed ones, even when
+// there's no database to look the client up in.
+pub(crate) fn apply_geo_headers(client_ip: IpAddr, headers: &mut HeaderMap) {
+    for name in GEO_HEADERS {
+        headers.remove(name);
+    }
+    if let Some(geoip) = GEOIP.get() {
+        set_geo_headers(&geoip.lookup(client_ip), headers);
+    }
+}
+
+fn set_geo_headers(info: &GeoInfo, headers: &mut HeaderMap) {
+    let encode = |value: &str| -> String {
+        url::form_urlencoded::byte_serialize(value.as_bytes()).collect()
+    };
+    let values: [(&str, Option<String>); 5] = [
+        (COUNTRY_HEADER, info.country.clone()),
+        (REGION_HEADER, info.region.clone()),
+        (CITY_HEADER, info.city.as_deref().map(encode)),
+        (ASN_HEADER, info.asn.map(|asn| asn.to_string())),
+        (AS_ORG_HEADER, info.as_org.as_deref().map(encode)),
+    ];
+    for (name, value) in values {
+        if let Some(value) = value.and_then(|value| HeaderValue::from_str(&value).ok()) {
+            headers.insert(HeaderName::from_static(name), value);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_geo_headers() {
+        let mut headers = HeaderMap::new();
+        headers.insert(COUNTRY_HEADER, HeaderValue::from_static("XX"));
+        headers.insert(ASN_HEADER, HeaderValue::from_static("1"));
+        apply_geo_headers("203.0.113.7".parse().unwrap(), &mut headers);
+
+        let info = GeoInfo {
+            country: Some("DE".to_string()),
+            region: Some("BE".to_string()),
+            city: Some("Köln".to_string()),
+            asn: None,
+            as_org: None,
+        };
+        set_geo_headers(&info, &mut headers);
+        assert_eq!(headers.get(COUNTRY_HEADER).unwrap(), "DE");
+        assert_eq!(headers.get(REGION_HEADER).unwrap(), "BE");
+        assert_eq!(headers.get(CITY_HEADER).unwrap(), "K%C3%B6ln");
+        // unknown, the client's own was dropped
+        assert!(headers.get(ASN_HEADER).is_none());
+        assert!(headers.get(AS_ORG_HEADER).is_none());
+    }
+
+    #[test]
+    fn test_spoofed_headers_without_database() {
+        // the tests never open the databases
+        assert!(GEOIP.get().is_none());
+
+        let mut headers = HeaderMap::new();
+        for name in GEO_HEADERS {
+            headers.insert(name, HeaderValue::from_static("XX"));
+        }
+        headers.insert(hyper::header::ACCEPT, HeaderValue::from_static("*/*"));
+        apply_geo_headers("203.0.113.7".parse().unwrap(), &mut headers);
+        assert_eq!(headers.len(), 1);
+        assert_eq!(headers.get(hyper::header::ACCEPT).unwrap(), "*/*");
+    }
+
+    #[test]
+    fn test_missing_database() {
+        assert!(GeoIp::open(&["./test_cases/missing.mmdb"]).is_err());
+        let geoip = GeoIp::open(&[] as &[&str]).unwrap();
+        assert_eq!(
+            geoip.lookup("203.0.113.7".parse().unwrap()),
+            GeoInfo::default()
+        );
+    }
+}
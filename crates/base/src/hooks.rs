@@ -0,0 +1,326 @@
+use crate::worker_ctx::error_response;
+use anyhow::{bail, Context, Error};
+use async_trait::async_trait;
+use deno_core::serde_json;
+use hyper::header::{HeaderMap, HeaderName, HeaderValue, CONTENT_TYPE};
+use hyper::{Body, Method, Request, Response, Uri};
+use log::warn;
+use once_cell::sync::OnceCell;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::fmt::Debug;
+use std::sync::Arc;
+use std::time::Duration;
+
+// how long the server waits on a `--request-hook` before failing the request
+const HTTP_HOOK_TIMEOUT: Duration = Duration::from_secs(5);
+
+static REQUEST_HOOKS: OnceCell<Vec<Arc<dyn RequestHook>>> = OnceCell::new();
+
+// What a hook does with a request before the user worker gets it.
+#[derive(Debug)]
+pub enum HookAction {
+    // on to the next hook, and the worker
+    Continue,
+    // answered without the worker, the hooks after this one don't run
+    Respond(Response<Body>),
+}
+
+// The request a user worker was sent, as the hooks left it.
+#[derive(Debug, Clone)]
+pub struct HookRequest {
+    pub method: Method,
+    pub uri: Uri,
+    pub headers: HeaderMap,
+}
+
+// Runs around the requests the main worker sends to user workers, eg: an
+// auth gateway or an A/B split. Requests coalesced by a worker share the
+// response its hooks left.
+#[async_trait]
+pub trait RequestHook: Send + Sync + Debug {
+    // can rewrite the path and headers of a request for a worker of
+    // `service`, or answer it. Failing answers it with a 502.
+    async fn before(&self, _service: &str, _req: &mut Request<Body>) -> Result<HookAction, Error> {
+        Ok(HookAction::Continue)
+    }
+
+    // can change the response of the worker, which is kept as is on failure
+    async fn after(
+        &self,
+        _service: &str,
+        _req: &HookRequest,
+        _res: &mut Response<Body>,
+    ) -> Result<(), Error> {
+        Ok(())
+    }
+}
+
+// Must be called before the server starts. Hooks run in order before the
+// worker, and in reverse order after it.
+pub fn init_request_hooks(hooks: Vec<Arc<dyn RequestHook>>) -> Result<(), Error> {
+    if REQUEST_HOOKS.set(hooks).is_err() {
+        bail!("the request hooks are already set");
+    }
+    Ok(())
+}
+
+pub(crate) fn request_hooks() -> &'static [Arc<dyn RequestHook>] {
+    REQUEST_HOOKS.get().map(Vec::as_slice).unwrap_or_default()
+}
+
+// `Some` if one of the hooks answered the request
+pub(crate) async fn run_before_hooks(
+    hooks: &[Arc<dyn RequestHook>],
+    service: &str,
+    req: &mut Request<Body>,
+) -> Option<Response<Body>> {
+    for hook in hooks {
+        match hook.before(service, req).await {
+            Ok(HookAction::Continue) => {}
+            Ok(HookAction::Respond(res)) => return Some(res),
+            Err(err) => {
+                warn!("a request hook of {} failed: {:?}", service, err);
+                return Some(error_response(502, "the request hook failed"));
+            }
+        }
+    }
+    None
+}
+
+pub(crate) async fn run_after_hooks(
+    hooks: &[Arc<dyn RequestHook>],
+    service: &str,
+    req: &HookRequest,
+    res: &mut Response<Body>,
+) {
+    for hook in hooks.iter().rev() {
+        if let Err(err) = hook.after(service, req, res).await {
+            warn!("a response hook of {} failed: {:?}", service, err);
+        }
+    }
+}
+
+// What a `--request-hook` answers, see `HttpRequestHook`.
+#[derive(Debug, Deserialize, PartialEq)]
+#[serde(tag = "action", rename_all = "camelCase")]
+enum HttpHookAction {
+    Continue {
+        // the path (and query) the worker gets instead
+        path: Option<String>,
+        // set on the request, replacing the ones it has
+        #[serde(default)]
+        headers: HashMap<String, String>,
+    },
+    Respond {
+        status: u16,
+        #[serde(default)]
+        headers: HashMap<String, String>,
+        #[serde(default)]
+        body: String,
+    },
+}
+
+// A middleware service called over HTTP before each request, with its
+// service, method, path and headers as JSON. It answers with
+// `{ "action": "continue", "path": ..., "headers": {...} }` to rewrite the
+// request, or `{ "action": "respond", "status": ..., "headers": {...},
+// "body": ... }` to answer it.
+#[derive(Debug)]
+pub struct HttpRequestHook {
+    url: String,
+    client: reqwest::Client,
+}
+
+impl HttpRequestHook {
+    pub fn new(url: &str) -> Result<Self, Error> {
+        if !url.starts_with("http://") && !url.starts_with("https://") {
+            bail!("invalid request hook {:?}, expected an http(s) url", url);
+        }
+        let client = reqwest::Client::builder()
+            .timeout(HTTP_HOOK_TIMEOUT)
+            .build()?;
+        Ok(Self {
+            url: url.to_string(),
+            client,
+        })
+    }
+}
+
+fn header_map(headers: &HashMap<String, String>) -> Result<HeaderMap, Error> {
+    let mut map = HeaderMap::new();
+    for (name, value) in headers {
+        map.insert(
+            HeaderName::from_bytes(name.as_bytes())
+                .with_context(|| format!("invalid header name {:?}", name))?,
+            HeaderValue::from_str(value)
+                .with_context(|| format!("invalid value of header {}", name))?,
+        );
+    }
+    Ok(map)
+}
+
+fn apply_http_hook_action(
+    action: HttpHookAction,
+    req: &mut Request<Body>,
+) -> Result<HookAction, Error> {
+    match action {
+        HttpHookAction::Continue { path, headers } => {
+            if let Some(path) = path {
+                if !path.starts_with('/') {
+                    bail!("the rewritten path {:?} must start with /", path);
+                }
+                let mut parts = req.uri().clone().into_parts();
+                parts.path_and_query = Some(path.parse()?);
+                *req.uri_mut() = Uri::from_parts(parts)?;
+            }
+            req.headers_mut().extend(header_map(&headers)?);
+            Ok(HookAction::Continue)
+        }
+        HttpHookAction::Respond {
+            status,
+            headers,
+            body,
+        } => {
+            let mut res = Response::builder().status(status).body(Body::from(body))?;
+            *res.headers_mut() = header_map(&headers)?;
+            Ok(HookAction::Respond(res))
+        }
+    }
+}
+
+#[async_trait]
+impl RequestHook for HttpRequestHook {
+    async fn before(&self, service: &str, req: &mut Request<Body>) -> Result<HookAction, Error> {
+        let headers: Vec<(&str, &str)> = req
+            .headers()
+            .iter()
+            .filter_map(|(name, value)| Some((name.as_str(), value.to_str().ok()?)))
+            .collect();
+        let body = serde_json::json!({
+            "service": service,
+            "method": req.method().as_str(),
+            "path": req.uri().path_and_query().map_or("/", |p| p.as_str()),
+            "headers": headers,
+        });
+        let res = self
+            .client
+            .post(&self.url)
+            .header(CONTENT_TYPE, "application/json")
+            .body(body.to_string())
+            .send()
+            .await?
+            .error_for_status()?;
+        let action: HttpHookAction = serde_json::from_slice(&res.bytes().await?)?;
+        apply_http_hook_action(action, req)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    // answers the requests without an `authorization` header
+    #[derive(Debug)]
+    struct AuthGateway;
+
+    #[async_trait]
+    impl RequestHook for AuthGateway {
+        async fn before(
+            &self,
+            _service: &str,
+            req: &mut Request<Body>,
+        ) -> Result<HookAction, Error> {
+            if req.headers().contains_key("authorization") {
+                return Ok(HookAction::Continue);
+            }
+            Ok(HookAction::Respond(error_response(401, "unauthorized")))
+        }
+    }
+
+    // puts half of the users on /b, and tags the response with the variant
+    #[derive(Debug)]
+    struct Split;
+
+    #[async_trait]
+    impl RequestHook for Split {
+        async fn before(
+            &self,
+            _service: &str,
+            req: &mut Request<Body>,
+        ) -> Result<HookAction, Error> {
+            if req.headers().get("authorization").unwrap() == "odd" {
+                *req.uri_mut() = "/b".parse()?;
+            }
+            Ok(HookAction::Continue)
+        }
+
+        async fn after(
+            &self,
+            _service: &str,
+            req: &HookRequest,
+            res: &mut Response<Body>,
+        ) -> Result<(), Error> {
+            let variant = HeaderValue::from_str(req.uri.path())?;
+            res.headers_mut().insert("x-variant", variant);
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_request_hooks() {
+        let hooks: Vec<Arc<dyn RequestHook>> = vec![Arc::new(AuthGateway), Arc::new(Split)];
+
+        let mut req = Request::get("/a").body(Body::empty()).unwrap();
+        let res = run_before_hooks(&hooks, "hello", &mut req).await.unwrap();
+        assert_eq!(res.status(), 401);
+
+        let mut req = Request::get("/a")
+            .header("authorization", "odd")
+            .body(Body::empty())
+            .unwrap();
+        assert!(run_before_hooks(&hooks, "hello", &mut req).await.is_none());
+        assert_eq!(req.uri().path(), "/b");
+
+        let head = HookRequest {
+            method: req.method().clone(),
+            uri: req.uri().clone(),
+            headers: req.headers().clone(),
+        };
+        let mut res = Response::new(Body::empty());
+        run_after_hooks(&hooks, "hello", &head, &mut res).await;
+        assert_eq!(res.headers().get("x-variant").unwrap(), "/b");
+    }
+
+    #[test]
+    fn test_http_hook_actions() {
+        let mut req = Request::get("http://localhost/hello?a=1")
+            .body(Body::empty())
+            .unwrap();
+        let action: HttpHookAction = serde_json::from_str(
+            r#"{ "action": "continue", "path": "/hello-v2?a=1", "headers": { "x-user-id": "42" } }"#,
+        )
+        .unwrap();
+        assert!(matches!(
+            apply_http_hook_action(action, &mut req).unwrap(),
+            HookAction::Continue
+        ));
+        assert_eq!(req.uri().to_string(), "http://localhost/hello-v2?a=1");
+        assert_eq!(req.headers().get("x-user-id").unwrap(), "42");
+
+        let action: HttpHookAction = serde_json::from_str(
+            r#"{ "action": "respond", "status": 302, "headers": { "location": "/login" } }"#,
+        )
+        .unwrap();
+        let HookAction::Respond(res) = apply_http_hook_action(action, &mut req).unwrap() else {
+            panic!("the request should be answered");
+        };
+        assert_eq!(res.status(), 302);
+        assert_eq!(res.headers().get("location").unwrap(), "/login");
+
+        let action: HttpHookAction =
+            serde_json::from_str(r#"{ "action": "continue", "path": "hello" }"#).unwrap();
+        assert!(apply_http_hook_action(action, &mut req).is_err());
+        assert!(HttpRequestHook::new("ftp://hooks").is_err());
+    }
+}
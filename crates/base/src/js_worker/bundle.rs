@@ -0,0 +1,301 @@
+use crate::js_worker::import_attributes::{
+    rewrite_import_attributes, text_module_source, text_module_target,
+};
+use crate::js_worker::module_graph::{service_module_graph, ModuleGraph};
+use crate::js_worker::module_loader::DefaultModuleLoader;
+use anyhow::{anyhow, bail, Context, Error};
+use deno_ast::swc::ast;
+use deno_ast::swc::bundler::{
+    Bundler, Config, Hook, Load, ModuleData, ModuleRecord, ModuleType, Resolve,
+};
+use deno_ast::swc::codegen::text_writer::JsWriter;
+use deno_ast::swc::codegen::Emitter;
+use deno_ast::swc::common::{FileName, FilePathMapping, Globals, Mark, SourceMap, Span, GLOBALS};
+use deno_ast::swc::parser::lexer::Lexer;
+use deno_ast::swc::parser::{Parser, StringInput};
+use deno_ast::swc::transforms::resolver;
+use deno_ast::swc::visit::VisitMutWith;
+use deno_ast::{EmitOptions, MediaType, ParseParams, SourceTextInfo};
+use deno_core::serde_json;
+use std::collections::HashMap;
+use std::path::Path;
+use std::rc::Rc;
+use url::Url;
+
+// Loads the modules of the graph, transpiled to javascript beforehand.
+struct BundleLoader<'a> {
+    cm: Rc<SourceMap>,
+    sources: &'a HashMap<String, String>,
+}
+
+impl Load for BundleLoader<'_> {
+    fn load(&self, file_name: &FileName) -> Result<ModuleData, Error> {
+        let FileName::Url(specifier) = file_name else {
+            bail!("unexpected module {}", file_name);
+        };
+        let source = self
+            .sources
+            .get(specifier.as_str())
+            .ok_or_else(|| anyhow!("module {} not found", specifier))?;
+
+        let fm = self
+            .cm
+            .new_source_file(FileName::Url(specifier.clone()), source.clone());
+        let lexer = Lexer::new(
+            deno_ast::get_syntax(MediaType::JavaScript),
+            deno_ast::ES_VERSION,
+            StringInput::from(&*fm),
+            None,
+        );
+        let mut module = Parser::new_from(lexer)
+            .parse_module()
+            .map_err(|err| anyhow!("failed to parse {}: {:?}", specifier, err.into_kind()))?;
+        module.visit_mut_with(&mut resolver(Mark::new(), Mark::new(), false));
+        Ok(ModuleData {
+            fm,
+            module,
+            helpers: Default::default(),
+        })
+    }
+}
+
+// Resolves the imports the way the graph did.
+struct BundleResolver<'a> {
+    graph: &'a ModuleGraph,
+}
+
+impl Resolve for BundleResolver<'_> {
+    fn resolve(&self, referrer: &FileName, specifier: &str) -> Result<FileName, Error> {
+        let FileName::Url(referrer) = referrer else {
+            bail!("unexpected module {}", referrer);
+        };
+        let resolved = self
+            .graph
+            .modules
+            .get(referrer.as_str())
+            .and_then(|module| {
+                module
+                    .imports
+                    .iter()
+                    .find(|import| import.specifier == specifier)
+            })
+            .and_then(|import| import.resolved.as_deref())
+            .ok_or_else(|| anyhow!("can't resolve {} from {}", specifier, referrer))?;
+        Ok(FileName::Url(Url::parse(resolved)?))
+    }
+}
+
+// `import.meta` of the bundled modules: their own url, and `main` for the
+// entrypoint only.
+struct BundleHook;
+
+impl Hook for BundleHook {
+    fn get_import_meta_props(
+        &self,
+        span: Span,
+        module_record: &ModuleRecord,
+    ) -> Result<Vec<ast::KeyValueProp>, Error> {
+        let main = if module_record.is_entry {
+            ast::Expr::Member(ast::MemberExpr {
+                span,
+                obj: Box::new(ast::Expr::MetaProp(ast::MetaPropExpr {
+                    span,
+                    kind: ast::MetaPropKind::ImportMeta,
+                })),
+                prop: ast::MemberProp::Ident(ast::Ident::new("main".into(), span)),
+            })
+        } else {
+            ast::Expr::Lit(ast::Lit::Bool(ast::Bool { span, value: false }))
+        };
+        Ok(vec![
+            ast::KeyValueProp {
+                key: ast::PropName::Ident(ast::Ident::new("url".into(), span)),
+                value: Box::new(ast::Expr::Lit(ast::Lit::Str(ast::Str {
+                    span,
+                    value: module_record.file_name.to_string().into(),
+                    raw: None,
+                }))),
+            },
+            ast::KeyValueProp {
+                key: ast::PropName::Ident(ast::Ident::new("main".into(), span)),
+                value: Box::new(main),
+            },
+        ])
+    }
+}
+
+// The source of a module as the bundler loads it: text and json modules
+// become javascript ones exporting their contents, the others are
+// transpiled.
+fn bundle_source(
+    specifier: &str,
+    source: &str,
+    media_type: MediaType,
+    is_text: bool,
+) -> Result<String, Error> {
+    if is_text {
+        return Ok(text_module_source(source));
+    }
+    match media_type {
+        MediaType::Json => Ok(format!(
+            "export default JSON.parse({});\n",
+            serde_json::to_string(source)?
+        )),
+        MediaType::Wasm => bail!("wasm modules can't be bundled: {}", specifier),
+        media_type => {
+            let parsed = deno_ast::parse_module(ParseParams {
+                specifier: specifier.to_string(),
+                text_info: SourceTextInfo::from_string(
+                    rewrite_import_attributes(source).into_owned(),
+                ),
+                media_type,
+                capture_tokens: false,
+                scope_analysis: false,
+                maybe_syntax: None,
+            })?;
+            let transpiled = parsed.transpile(&EmitOptions {
+                inline_source_map: false,
+                source_map: false,
+                ..Default::default()
+            })?;
+            Ok(transpiled.text)
+        }
+    }
+}
+
+fn emit_bundle(
+    graph: &ModuleGraph,
+    sources: &HashMap<String, String>,
+    external_modules: Vec<String>,
+) -> Result<String, Error> {
+    let cm = Rc::new(SourceMap::new(FilePathMapping::empty()));
+    let globals = Globals::new();
+    let loader = BundleLoader {
+        cm: cm.clone(),
+        sources,
+    };
+    let config = Config {
+        module: ModuleType::Es,
+        external_modules: external_modules.into_iter().map(Into::into).collect(),
+        ..Default::default()
+    };
+    let entry = FileName::Url(Url::parse(&graph.root)?);
+
+    let mut output = GLOBALS.set(&globals, || {
+        let mut bundler = Bundler::new(
+            &globals,
+            cm.clone(),
+            loader,
+            BundleResolver { graph },
+            config,
+            Box::new(BundleHook),
+        );
+        bundler.bundle(HashMap::from([("bundle".to_string(), entry)]))
+    })?;
+    let Some(bundle) = output.pop() else {
+        bail!("nothing to bundle");
+    };
+
+    let mut buf = vec![];
+    let mut emitter = Emitter {
+        cfg: Default::default(),
+        cm: cm.clone(),
+        comments: None,
+        wr: Box::new(JsWriter::new(cm, "\n", &mut buf, None)),
+    };
+    emitter.emit_module(&bundle.module)?;
+    drop(emitter);
+    Ok(String::from_utf8(buf)?)
+}
+
+// Bundles the service at `service_path` into a single ES module, its imports
+// resolved like its workers resolve them (see `service_module_graph`). The
+// modules are merged in the order they're evaluated in, and the code none of
+// them uses is dropped, so the worker compiles one module instead of its
+// whole graph on a cold start. `node:` and `npm:` imports are left as they
+// are, like dynamic imports, and wasm modules can't be bundled.
+pub async fn bundle_service_module(
+    service_path: &Path,
+    base_import_map_path: Option<&str>,
+    offline: bool,
+) -> Result<String, Error> {
+    let graph = service_module_graph(service_path, base_import_map_path, offline).await?;
+    let mut errors = vec![];
+    for (specifier, module) in &graph.modules {
+        if let Some(error) = &module.error {
+            errors.push(format!("  {}: {}", specifier, error));
+        }
+        for import in &module.imports {
+            if let Some(error) = &import.error {
+                errors.push(format!(
+                    "  {} from {}: {}",
+                    import.specifier, specifier, error
+                ));
+            }
+        }
+    }
+    if !errors.is_empty() {
+        bail!("failed to load the modules:\n{}", errors.join("\n"));
+    }
+
+    // fetched while the graph was built
+    let loader = DefaultModuleLoader::new(None, None, false, true)?;
+    let mut sources = HashMap::new();
+    for (specifier, module) in &graph.modules {
+        if module.local.is_none() {
+            continue;
+        }
+        let url = Url::parse(specifier)?;
+        let target = text_module_target(&url);
+        let file = loader
+            .fetch(target.as_ref().unwrap_or(&url))
+            .await
+            .with_context(|| format!("failed to load {}", specifier))?;
+        let source = bundle_source(specifier, &file.source, file.media_type, target.is_some())?;
+        sources.insert(specifier.clone(), source);
+    }
+
+    // the modules that weren't fetched (eg: `node:` and `npm:` ones), as
+    // they're imported
+    let external_modules = graph
+        .modules
+        .values()
+        .flat_map(|module| &module.imports)
+        .filter(|import| {
+            import.resolved.as_ref().map_or(false, |resolved| {
+                graph
+                    .modules
+                    .get(resolved)
+                    .map_or(false, |module| module.local.is_none())
+            })
+        })
+        .map(|import| import.specifier.clone())
+        .collect();
+
+    emit_bundle(&graph, &sources, external_modules)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_bundle_service_module() {
+        let service_path = Path::new("./test_cases/import_attributes");
+        let bundle = bundle_service_module(service_path, None, true)
+            .await
+            .unwrap();
+
+        // the json and text modules are inlined
+        assert!(bundle.contains("\\\"greeting\\\": \\\"hello\\\""));
+        assert!(bundle.contains("\"world\\n\""));
+        assert!(!bundle.contains("import "));
+        assert!(bundle.contains("Deno.listen"));
+
+        assert!(
+            bundle_service_module(Path::new("./test_cases/wasm_import"), None, true)
+                .await
+                .is_err()
+        );
+    }
+}
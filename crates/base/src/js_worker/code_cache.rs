@@ -0,0 +1,497 @@
+use crate::js_worker::module_loader::DefaultModuleLoader;
+use anyhow::{bail, Error};
+use deno_core::serde_json;
+use deno_core::v8;
+use deno_core::JsRuntime;
+use deno_core::ModuleLoader;
+use deno_core::ModuleSource;
+use deno_core::ModuleSpecifier;
+use deno_core::ModuleType;
+use log::{debug, warn};
+use module_fetcher::cache::{DenoDir, FastInsecureHasher};
+use once_cell::sync::OnceCell;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+use tokio::task::JoinHandle;
+
+static CODE_CACHE_DISABLED: OnceCell<()> = OnceCell::new();
+
+// the least recently used caches are removed past it
+const MAX_CODE_CACHE_SIZE: u64 = 256 * 1024 * 1024;
+
+// Workers compile their modules from source on every boot instead.
+pub fn disable_code_cache() -> Result<(), Error> {
+    if CODE_CACHE_DISABLED.set(()).is_err() {
+        bail!("the code cache is already off");
+    }
+    Ok(())
+}
+
+pub fn code_cache_enabled() -> bool {
+    CODE_CACHE_DISABLED.get().is_none()
+}
+
+// The key of a module's code cache. v8 only takes caches made by the same
+// version, with the same flags.
+pub(crate) fn source_hash(code: &[u8]) -> u64 {
+    FastInsecureHasher::new()
+        .write(code)
+        .write_u64(v8::script_compiler::cached_data_version_tag() as u64)
+        .finish()
+}
+
+// A module loaded while the worker booted.
+#[derive(Debug, Clone)]
+pub(crate) struct LoadedModule {
+    pub specifier: ModuleSpecifier,
+    pub source_hash: u64,
+}
+
+#[derive(Debug, Default, PartialEq, Serialize, Deserialize)]
+struct BootModules {
+    modules: Vec<String>,
+}
+
+// The code caches v8 made for the modules of the workers, kept on disk by
+// the hash of their source, and the modules each main module loaded on its
+// last boot.
+#[derive(Clone)]
+pub(crate) struct CodeCache {
+    dir: PathBuf,
+    boot_modules_path: PathBuf,
+    max_size: u64,
+}
+
+impl CodeCache {
+    pub(crate) fn new(main_module: &ModuleSpecifier) -> Result<Self, Error> {
+        Self::with_dir(&DenoDir::new(None)?.code_cache_folder_path(), main_module)
+    }
+
+    fn with_dir(dir: &Path, main_module: &ModuleSpecifier) -> Result<Self, Error> {
+        fs::create_dir_all(dir)?;
+        let main_module_hash = FastInsecureHasher::new()
+            .write_str(main_module.as_str())
+            .finish();
+        Ok(Self {
+            dir: dir.to_path_buf(),
+            boot_modules_path: dir.join(format!("{:016x}.modules.json", main_module_hash)),
+            max_size: MAX_CODE_CACHE_SIZE,
+        })
+    }
+
+    fn cache_path(&self, source_hash: u64) -> PathBuf {
+        self.dir.join(format!("{:016x}.bin", source_hash))
+    }
+
+    // a read counts as a use, the cache is kept over the ones not read since
+    fn get(&self, source_hash: u64) -> Option<Vec<u8>> {
+        let path = self.cache_path(source_hash);
+        let data = fs::read(&path).ok()?;
+        let _ = filetime::set_file_mtime(&path, filetime::FileTime::now());
+        Some(data)
+    }
+
+    fn remove(&self, source_hash: u64) {
+        let _ = fs::remove_file(self.cache_path(source_hash));
+    }
+
+    fn contains(&self, source_hash: u64) -> bool {
+        self.cache_path(source_hash).exists()
+    }
+
+    // written aside and renamed, workers of the service may read it meanwhile
+    fn set(&self, source_hash: u64, data: &[u8]) -> Result<(), Error> {
+        write_atomically(&self.cache_path(source_hash), data)
+    }
+
+    fn boot_modules(&self) -> Vec<ModuleSpecifier> {
+        fs::read(&self.boot_modules_path)
+            .ok()
+            .and_then(|bytes| serde_json::from_slice::<BootModules>(&bytes).ok())
+            .unwrap_or_default()
+            .modules
+            .iter()
+            .filter_map(|specifier| ModuleSpecifier::parse(specifier).ok())
+            .collect()
+    }
+
+    fn set_boot_modules(&self, modules: &[ModuleSpecifier]) -> Result<(), Error> {
+        let modules = BootModules {
+            modules: modules.iter().map(|m| m.to_string()).collect(),
+        };
+        let current = fs::read(&self.boot_modules_path)
+            .ok()
+            .and_then(|bytes| serde_json::from_slice::<BootModules>(&bytes).ok());
+        if current.as_ref() == Some(&modules) {
+            return Ok(());
+        }
+        write_atomically(&self.boot_modules_path, &serde_json::to_vec(&modules)?)
+    }
+
+    // Removes the least recently used caches until the ones left fit in the
+    // max size.
+    fn prune(&self) -> Result<(), Error> {
+        let mut caches: Vec<(SystemTime, u64, PathBuf)> = vec![];
+        for entry in fs::read_dir(&self.dir)? {
+            let entry = entry?;
+            let path = entry.path();
+            if path.extension().map_or(true, |ext| ext != "bin") {
+                continue;
+            }
+            let Ok(metadata) = entry.metadata() else {
+                continue;
+            };
+            let used = metadata.modified().unwrap_or(SystemTime::UNIX_EPOCH);
+            caches.push((used, metadata.len(), path));
+        }
+
+        let mut size: u64 = caches.iter().map(|(_, len, _)| len).sum();
+        if size <= self.max_size {
+            return Ok(());
+        }
+        caches.sort_by_key(|(used, _, _)| *used);
+        let mut removed = 0;
+        for (_, len, path) in caches {
+            if size <= self.max_size {
+                break;
+            }
+            // removed already by another worker
+            if fs::remove_file(&path).is_ok() {
+                removed += 1;
+            }
+            size -= len;
+        }
+        debug!("removed {} code cache(s) over the max size", removed);
+        Ok(())
+    }
+}
+
+fn write_atomically(path: &Path, data: &[u8]) -> Result<(), Error> {
+    let tmp = path.with_extension(format!("{}.tmp", uuid::Uuid::new_v4()));
+    fs::write(&tmp, data)?;
+    if let Err(err) = fs::rename(&tmp, path) {
+        let _ = fs::remove_file(&tmp);
+        return Err(err.into());
+    }
+    Ok(())
+}
+
+// the origin deno_core compiles modules with, the compilation cache only
+// answers lookups made with the same one
+fn module_origin<'a>(
+    scope: &mut v8::HandleScope<'a>,
+    resource_name: v8::Local<'a, v8::String>,
+) -> v8::ScriptOrigin<'a> {
+    let source_map_url = v8::String::empty(scope);
+    v8::ScriptOrigin::new(
+        scope,
+        resource_name.into(),
+        0,
+        0,
+        false,
+        123,
+        source_map_url.into(),
+        true,
+        false,
+        true,
+    )
+}
+
+fn compile_module<'s>(
+    scope: &mut v8::HandleScope<'s>,
+    source: &ModuleSource,
+    cached_data: Option<&[u8]>,
+) -> Option<(v8::Local<'s, v8::Module>, bool)> {
+    let name = v8::String::new(scope, &source.module_url_found)?;
+    let code = v8::String::new_from_utf8(scope, source.code.as_bytes(), v8::NewStringType::Normal)?;
+    let origin = module_origin(scope, name);
+    let (mut source, options) = match cached_data {
+        Some(data) => (
+            v8::script_compiler::Source::new_with_cached_data(
+                code,
+                Some(&origin),
+                v8::script_compiler::CachedData::new(data),
+            ),
+            v8::script_compiler::CompileOptions::ConsumeCodeCache,
+        ),
+        None => (
+            v8::script_compiler::Source::new(code, Some(&origin)),
+            v8::script_compiler::CompileOptions::NoCompileOptions,
+        ),
+    };
+    let tc_scope = &mut v8::TryCatch::new(scope);
+    let module = v8::script_compiler::compile_module2(
+        tc_scope,
+        &mut source,
+        options,
+        v8::script_compiler::NoCacheReason::NoReason,
+    )?;
+    // v8 compiled the module from source, the cache was made by another
+    // version or with other flags
+    let rejected = source
+        .get_cached_data()
+        .map_or(false, |data| data.rejected());
+    Some((module, rejected))
+}
+
+// Compiles the modules the worker loaded on its last boot from their code
+// caches, before the main module is loaded. v8 keeps what it deserializes in
+// the isolate's compilation cache, where deno_core's compilation of the same
+// source finds it instead of parsing it. The cache only holds the scripts
+// weakly, the returned modules keep them until the main module is loaded.
+pub(crate) async fn prime_modules(
+    js_runtime: &mut JsRuntime,
+    module_loader: &DefaultModuleLoader,
+    code_cache: &CodeCache,
+) -> Vec<v8::Global<v8::Module>> {
+    let mut modules = vec![];
+    for specifier in code_cache.boot_modules() {
+        let Ok(source) = module_loader.load(&specifier, None, false).await else {
+            continue;
+        };
+        // offline and no longer in the module cache, the boot reports it if
+        // the module is still imported
+        if !module_loader.take_missing_modules().is_empty() {
+            continue;
+        }
+        if matches!(source.module_type, ModuleType::JavaScript) {
+            let hash = source_hash(source.code.as_bytes());
+            if let Some(data) = code_cache.get(hash) {
+                let scope = &mut js_runtime.handle_scope();
+                match compile_module(scope, &source, Some(&data)) {
+                    // made again once the worker booted
+                    Some((_, true)) => {
+                        debug!("v8 rejected the code cache of {}", specifier);
+                        code_cache.remove(hash);
+                    }
+                    Some((module, false)) => modules.push(v8::Global::new(scope, module)),
+                    None => {}
+                }
+            }
+        }
+        // deno_core's load takes it, rather than loading it again
+        module_loader.keep_primed(specifier, source);
+    }
+    debug!("primed {} module(s) from the code cache", modules.len());
+    modules
+}
+
+// The code caches of the modules a worker loaded while booting, and the
+// modules to prime on its next boot.
+pub(crate) struct CodeCacheUpdate {
+    code_cache: CodeCache,
+    caches: Vec<(u64, Vec<u8>)>,
+    boot_modules: Vec<ModuleSpecifier>,
+}
+
+impl CodeCacheUpdate {
+    fn write(self) {
+        for (source_hash, data) in &self.caches {
+            if let Err(err) = self.code_cache.set(*source_hash, data) {
+                warn!("failed to write a code cache: {}", err);
+            }
+        }
+        if let Err(err) = self.code_cache.set_boot_modules(&self.boot_modules) {
+            warn!("failed to write the modules of the boot: {}", err);
+        }
+        if !self.caches.is_empty() {
+            if let Err(err) = self.code_cache.prune() {
+                warn!("failed to prune the code cache: {}", err);
+            }
+        }
+    }
+}
+
+// Makes the code caches of the modules the worker loaded while booting that
+// don't have one yet. Compiling them again is answered by the compilation
+// cache, with the scripts the worker evaluated, so the functions they ran are
+// in the caches too. The isolate serializes them, they're written to disk on
+// a blocking thread.
+pub(crate) async fn update_code_cache(
+    js_runtime: &mut JsRuntime,
+    module_loader: &DefaultModuleLoader,
+    code_cache: &CodeCache,
+    loaded: Vec<LoadedModule>,
+) -> JoinHandle<()> {
+    let mut caches = vec![];
+    for module in &loaded {
+        if code_cache.contains(module.source_hash) {
+            continue;
+        }
+        let Ok(source) = module_loader.load(&module.specifier, None, false).await else {
+            continue;
+        };
+        if source_hash(source.code.as_bytes()) != module.source_hash {
+            continue;
+        }
+        let scope = &mut js_runtime.handle_scope();
+        let Some((compiled, _)) = compile_module(scope, &source, None) else {
+            continue;
+        };
+        let Some(data) = compiled
+            .get_unbound_module_script(scope)
+            .create_code_cache()
+        else {
+            continue;
+        };
+        caches.push((module.source_hash, data.to_vec()));
+    }
+    // loaded again for their caches
+    module_loader.take_loaded_modules();
+
+    let update = CodeCacheUpdate {
+        code_cache: code_cache.clone(),
+        caches,
+        boot_modules: loaded.into_iter().map(|m| m.specifier).collect(),
+    };
+    tokio::task::spawn_blocking(move || update.write())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use deno_core::RuntimeOptions;
+    use std::rc::Rc;
+
+    fn temp_dir() -> PathBuf {
+        std::env::temp_dir().join(format!("sb-code-cache-{}", uuid::Uuid::new_v4()))
+    }
+
+    fn code_cache() -> CodeCache {
+        let main_module = ModuleSpecifier::parse("file:///service/index.ts").unwrap();
+        CodeCache::with_dir(&temp_dir(), &main_module).unwrap()
+    }
+
+    // boots the service the way workers do, returns how many modules were
+    // compiled from their caches
+    async fn boot(main_module: &ModuleSpecifier, code_cache: &CodeCache) -> usize {
+        let module_loader = Rc::new(DefaultModuleLoader::new(None, None, false, false).unwrap());
+        let mut js_runtime = JsRuntime::new(RuntimeOptions {
+            module_loader: Some(module_loader.clone()),
+            ..Default::default()
+        });
+
+        let primed = prime_modules(&mut js_runtime, &module_loader, code_cache).await;
+        let mod_id = js_runtime
+            .load_main_module(main_module, None)
+            .await
+            .unwrap();
+        let mod_result = js_runtime.mod_evaluate(mod_id);
+        js_runtime.run_event_loop(false).await.unwrap();
+        mod_result.await.unwrap().unwrap();
+        let primed = primed.len();
+        module_loader.clear_primed();
+
+        let loaded = module_loader.take_loaded_modules();
+        update_code_cache(&mut js_runtime, &module_loader, code_cache, loaded)
+            .await
+            .await
+            .unwrap();
+        primed
+    }
+
+    #[tokio::test]
+    async fn test_code_cache_across_boots() {
+        let service = temp_dir();
+        fs::create_dir_all(&service).unwrap();
+        fs::write(
+            service.join("index.js"),
+            "import { add } from \"./add.js\";\nglobalThis.sum = add(1, 2);\n",
+        )
+        .unwrap();
+        fs::write(
+            service.join("add.js"),
+            "export function add(a, b) {\n  return a + b;\n}\n",
+        )
+        .unwrap();
+        let main_module = ModuleSpecifier::from_file_path(service.join("index.js")).unwrap();
+        let code_cache = CodeCache::with_dir(&service.join("cache"), &main_module).unwrap();
+
+        assert_eq!(boot(&main_module, &code_cache).await, 0);
+        assert_eq!(code_cache.boot_modules().len(), 2);
+        assert_eq!(boot(&main_module, &code_cache).await, 2);
+
+        // only the changed module is compiled from source
+        fs::write(
+            service.join("add.js"),
+            "export function add(a, b) {\n  return b + a;\n}\n",
+        )
+        .unwrap();
+        assert_eq!(boot(&main_module, &code_cache).await, 1);
+        assert_eq!(boot(&main_module, &code_cache).await, 2);
+    }
+
+    #[tokio::test]
+    async fn test_code_cache_rejected() {
+        let service = temp_dir();
+        fs::create_dir_all(&service).unwrap();
+        let code = "globalThis.answer = 42;\n";
+        fs::write(service.join("index.js"), code).unwrap();
+        let main_module = ModuleSpecifier::from_file_path(service.join("index.js")).unwrap();
+        let code_cache = CodeCache::with_dir(&service.join("cache"), &main_module).unwrap();
+
+        assert_eq!(boot(&main_module, &code_cache).await, 0);
+        let hash = source_hash(code.as_bytes());
+        assert!(code_cache.contains(hash));
+
+        // v8 won't take it, it's made again for the next boot
+        code_cache.set(hash, b"not a code cache").unwrap();
+        assert_eq!(boot(&main_module, &code_cache).await, 0);
+        assert_ne!(code_cache.get(hash).unwrap(), b"not a code cache");
+        assert_eq!(boot(&main_module, &code_cache).await, 1);
+    }
+
+    #[test]
+    fn test_code_cache_max_size() {
+        let mut cache = code_cache();
+        cache.max_size = 20;
+        let now = SystemTime::now();
+        for (i, hash) in [1u64, 2, 3].into_iter().enumerate() {
+            cache.set(hash, &[0; 8]).unwrap();
+            let used = filetime::FileTime::from_system_time(
+                now - std::time::Duration::from_secs(60 * (3 - i as u64)),
+            );
+            filetime::set_file_mtime(cache.cache_path(hash), used).unwrap();
+        }
+
+        // read since the others were written
+        assert!(cache.get(1).is_some());
+        cache.prune().unwrap();
+        assert!(cache.contains(1));
+        assert!(!cache.contains(2));
+        assert!(cache.contains(3));
+    }
+
+    #[test]
+    fn test_code_cache_by_source_hash() {
+        let cache = code_cache();
+        let hash = source_hash(b"export const a = 1;");
+        assert_ne!(hash, source_hash(b"export const a = 2;"));
+
+        assert!(!cache.contains(hash));
+        assert_eq!(cache.get(hash), None);
+        cache.set(hash, b"data").unwrap();
+        assert!(cache.contains(hash));
+        assert_eq!(cache.get(hash), Some(b"data".to_vec()));
+    }
+
+    #[test]
+    fn test_code_cache_boot_modules() {
+        let cache = code_cache();
+        assert!(cache.boot_modules().is_empty());
+
+        let modules = vec![
+            ModuleSpecifier::parse("file:///service/index.ts").unwrap(),
+            ModuleSpecifier::parse("https://deno.land/std/http/server.ts").unwrap(),
+        ];
+        cache.set_boot_modules(&modules).unwrap();
+        assert_eq!(cache.boot_modules(), modules);
+
+        // each main module has its own list
+        let other_main = ModuleSpecifier::parse("file:///other/index.ts").unwrap();
+        let other = CodeCache::with_dir(&cache.dir, &other_main).unwrap();
+        assert!(other.boot_modules().is_empty());
+    }
+}
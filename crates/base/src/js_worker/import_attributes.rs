@@ -0,0 +1,147 @@
+use deno_core::serde_json;
+use deno_core::ModuleSpecifier;
+use once_cell::sync::Lazy;
+use regex::{Captures, Regex};
+use std::borrow::Cow;
+
+// Query parameter marking modules imported with `type: "text"`. The loader
+// never sees import attributes, so the type travels with the specifier.
+pub(crate) const TEXT_TYPE_PARAM: &str = "__edge_type=text";
+
+// import x from "./a.json" with { type: "json" }
+static STATIC_IMPORT_RE: Lazy<Regex> = Lazy::new(|| {
+    Regex::new(
+        r#"(?P<from>\bfrom\s*)(?P<quote>["'])(?P<specifier>[^"'\n]+)["']\s*(?:with|assert)\s*\{\s*type\s*:\s*["'](?P<type>json|text)["']\s*,?\s*\}"#,
+    )
+    .unwrap()
+});
+
+// import("./a.json", { with: { type: "json" } })
+static DYNAMIC_IMPORT_RE: Lazy<Regex> = Lazy::new(|| {
+    Regex::new(
+        r#"\bimport\(\s*(?P<quote>["'])(?P<specifier>[^"'\n]+)["']\s*,\s*\{\s*(?:with|assert)\s*:\s*\{\s*type\s*:\s*["'](?P<type>json|text)["']\s*,?\s*\}\s*,?\s*\}\s*\)"#,
+    )
+    .unwrap()
+});
+
+fn text_specifier(specifier: &str) -> String {
+    let separator = if specifier.contains('?') { '&' } else { '?' };
+    format!("{}{}{}", specifier, separator, TEXT_TYPE_PARAM)
+}
+
+fn rewrite_static_imports(source: &str) -> Cow<str> {
+    STATIC_IMPORT_RE.replace_all(source, |caps: &Captures| {
+        let quote = &caps["quote"];
+        let specifier = &caps["specifier"];
+        match &caps["type"] {
+            "text" => format!(
+                "{}{}{}{}",
+                &caps["from"],
+                quote,
+                text_specifier(specifier),
+                quote
+            ),
+            _ => format!(
+                "{}{}{}{} assert {{ type: \"json\" }}",
+                &caps["from"], quote, specifier, quote
+            ),
+        }
+    })
+}
+
+fn rewrite_dynamic_imports(source: &str) -> Cow<str> {
+    DYNAMIC_IMPORT_RE.replace_all(source, |caps: &Captures| {
+        let quote = &caps["quote"];
+        let specifier = &caps["specifier"];
+        match &caps["type"] {
+            "text" => format!("import({}{}{})", quote, text_specifier(specifier), quote),
+            _ => format!(
+                "import({}{}{}, {{ assert: {{ type: \"json\" }} }})",
+                quote, specifier, quote
+            ),
+        }
+    })
+}
+
+// Rewrites import attributes to what the runtime understands: `with` becomes
+// `assert` for json modules, and text modules are imported through a marked
+// specifier without attributes.
+pub fn rewrite_import_attributes(source: &str) -> Cow<str> {
+    match rewrite_static_imports(source) {
+        Cow::Borrowed(source) => rewrite_dynamic_imports(source),
+        Cow::Owned(source) => Cow::Owned(rewrite_dynamic_imports(&source).into_owned()),
+    }
+}
+
+// The file to load for a module imported with `type: "text"`, `None` for other modules.
+pub fn text_module_target(specifier: &ModuleSpecifier) -> Option<ModuleSpecifier> {
+    let query = specifier.query()?;
+    if !query.split('&').any(|param| param == TEXT_TYPE_PARAM) {
+        return None;
+    }
+
+    let query: Vec<&str> = query
+        .split('&')
+        .filter(|param| *param != TEXT_TYPE_PARAM)
+        .collect();
+    let mut target = specifier.clone();
+    target.set_query((!query.is_empty()).then(|| query.join("&")).as_deref());
+    Some(target)
+}
+
+// Module exporting the contents of a text file as its default export.
+pub fn text_module_source(text: &str) -> String {
+    format!(
+        "export default {};\n",
+        serde_json::to_string(text).unwrap_or_else(|_| "\"\"".to_string())
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::tester::EdgeRuntimeTester;
+    use hyper::{Body, Request};
+
+    #[test]
+    fn test_rewrite_import_attributes() {
+        let source = r#"import config from "./config.json" with { type: "json" };
+import query from './query.sql' with { type: "text" };
+const data = await import("./data.json", { with: { type: "json" } });
+const readme = await import("./README.md?raw", { with: { type: "text" } });
+import { serve } from "https://deno.land/std@0.131.0/http/server.ts";
+"#;
+
+        assert_eq!(
+            rewrite_import_attributes(source),
+            r#"import config from "./config.json" assert { type: "json" };
+import query from './query.sql?__edge_type=text';
+const data = await import("./data.json", { assert: { type: "json" } });
+const readme = await import("./README.md?raw&__edge_type=text");
+import { serve } from "https://deno.land/std@0.131.0/http/server.ts";
+"#
+        );
+
+        let specifier =
+            ModuleSpecifier::parse("https://example.com/README.md?raw&__edge_type=text").unwrap();
+        assert_eq!(
+            text_module_target(&specifier).unwrap().as_str(),
+            "https://example.com/README.md?raw"
+        );
+        let specifier = ModuleSpecifier::parse("file:///app/query.sql").unwrap();
+        assert!(text_module_target(&specifier).is_none());
+    }
+
+    #[tokio::test]
+    async fn test_import_json_and_text() {
+        let mut tester = EdgeRuntimeTester::new("./test_cases/import_attributes")
+            .await
+            .unwrap();
+
+        let req = Request::get("http://localhost/")
+            .body(Body::empty())
+            .unwrap();
+        let res = tester.request(req).await.unwrap();
+        assert_eq!(res.text().unwrap(), "hello world");
+    }
+}
@@ -0,0 +1,271 @@
+use anyhow::{bail, Error};
+use deno_core::serde_json::{Map, Value};
+use import_map::{parse_from_json, ImportMap, ImportMapDiagnostic};
+use log::warn;
+use once_cell::sync::Lazy;
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::SystemTime;
+use url::Url;
+
+// import map picked up from the service directory when none is given
+pub(crate) const SERVICE_IMPORT_MAP_NAME: &str = "import_map.json";
+
+struct CachedImportMap {
+    // modification times of the files the map was built from
+    modified: Vec<Option<SystemTime>>,
+    import_map: Arc<ImportMap>,
+}
+
+// parsed import maps by the files they were built from, shared by all workers
+static IMPORT_MAP_CACHE: Lazy<Mutex<HashMap<Vec<PathBuf>, CachedImportMap>>> =
+    Lazy::new(Default::default);
+
+// Returns the map built from `paths` by an earlier worker, as long as none of
+// the files changed since, or builds it.
+fn cached_import_map(
+    paths: &[PathBuf],
+    build: impl FnOnce() -> Result<ImportMap, Error>,
+) -> Result<Arc<ImportMap>, Error> {
+    let modified: Vec<Option<SystemTime>> = paths
+        .iter()
+        .map(|path| fs::metadata(path).and_then(|m| m.modified()).ok())
+        .collect();
+
+    if let Some(cached) = IMPORT_MAP_CACHE.lock().unwrap().get(paths) {
+        if cached.modified == modified {
+            return Ok(cached.import_map.clone());
+        }
+    }
+
+    let import_map = Arc::new(build()?);
+    IMPORT_MAP_CACHE.lock().unwrap().insert(
+        paths.to_vec(),
+        CachedImportMap {
+            modified,
+            import_map: import_map.clone(),
+        },
+    );
+    Ok(import_map)
+}
+
+fn print_import_map_diagnostics(diagnostics: &[ImportMapDiagnostic]) {
+    if !diagnostics.is_empty() {
+        warn!(
+            "Import map diagnostics:\n{}",
+            diagnostics
+                .iter()
+                .map(|d| format!("  - {d}"))
+                .collect::<Vec<_>>()
+                .join("\n")
+        );
+    }
+}
+
+// Url of the directory holding the import map, its relative addresses are resolved against it.
+fn import_map_base_url(path: &Path) -> Result<Url, Error> {
+    let abs_path = std::env::current_dir().map(|p| p.join(path))?;
+    let Some(dir) = abs_path.parent() else {
+        bail!("invalid import map path {:?}", path);
+    };
+    Url::from_directory_path(dir).map_err(|_| anyhow::anyhow!("invalid import map path {:?}", path))
+}
+
+pub fn load_import_map(maybe_path: Option<String>) -> Result<Option<Arc<ImportMap>>, Error> {
+    if let Some(path_str) = maybe_path {
+        let path = PathBuf::from(path_str);
+        let import_map = cached_import_map(&[path.clone()], || {
+            let json_str = fs::read_to_string(&path)?;
+            let base_url = import_map_base_url(&path)?;
+            let result = parse_from_json(&base_url, json_str.as_str())?;
+            print_import_map_diagnostics(&result.diagnostics);
+            Ok(result.import_map)
+        })?;
+        Ok(Some(import_map))
+    } else {
+        Ok(None)
+    }
+}
+
+pub(crate) fn is_relative(specifier: &str) -> bool {
+    specifier.starts_with("./") || specifier.starts_with("../") || specifier.starts_with('/')
+}
+
+fn resolve_relative(specifier: &str, base_url: &Url) -> String {
+    if !is_relative(specifier) {
+        return specifier.to_string();
+    }
+
+    base_url
+        .join(specifier)
+        .map(|url| url.to_string())
+        .unwrap_or_else(|_| specifier.to_string())
+}
+
+// Rewrites the relative keys and addresses of a specifier map to absolute urls,
+// so maps loaded from different directories can be merged.
+fn resolve_specifier_map(map: &Map<String, Value>, base_url: &Url) -> Map<String, Value> {
+    map.iter()
+        .map(|(key, value)| {
+            let value = match value {
+                Value::String(address) => Value::String(resolve_relative(address, base_url)),
+                value => value.clone(),
+            };
+            (resolve_relative(key, base_url), value)
+        })
+        .collect()
+}
+
+fn read_import_map(path: &Path) -> Result<(Map<String, Value>, Map<String, Value>), Error> {
+    let json_str = fs::read_to_string(path)
+        .map_err(|err| anyhow::anyhow!("failed to read import map {:?}: {}", path, err))?;
+    let value: Value = deno_core::serde_json::from_str(&json_str)?;
+    let base_url = import_map_base_url(path)?;
+
+    let imports = match value.get("imports") {
+        Some(Value::Object(imports)) => resolve_specifier_map(imports, &base_url),
+        Some(_) => bail!("\"imports\" of import map {:?} must be an object", path),
+        None => Map::new(),
+    };
+
+    let mut scopes = Map::new();
+    match value.get("scopes") {
+        Some(Value::Object(value)) => {
+            for (scope, scope_imports) in value {
+                let Value::Object(scope_imports) = scope_imports else {
+                    bail!(
+                        "scope {:?} of import map {:?} must be an object",
+                        scope,
+                        path
+                    );
+                };
+                scopes.insert(
+                    resolve_relative(scope, &base_url),
+                    Value::Object(resolve_specifier_map(scope_imports, &base_url)),
+                );
+            }
+        }
+        Some(_) => bail!("\"scopes\" of import map {:?} must be an object", path),
+        None => {}
+    }
+
+    Ok((imports, scopes))
+}
+
+// Import map of the service itself: the given path (relative to the service
+// directory) or an `import_map.json` found in it.
+fn service_import_map_path(service_path: &Path, maybe_path: Option<&str>) -> Option<PathBuf> {
+    match maybe_path {
+        Some(path) => Some(service_path.join(path)),
+        None => {
+            let path = service_path.join(SERVICE_IMPORT_MAP_NAME);
+            path.exists().then_some(path)
+        }
+    }
+}
+
+// Merges the import map of a service on top of the global one. Entries of the
+// service map take precedence, both for `imports` and within each scope, so
+// shared aliases can be pinned to other versions per service.
+pub fn load_service_import_map(
+    service_path: &Path,
+    maybe_path: Option<&str>,
+    maybe_base_path: Option<&str>,
+) -> Result<Option<Arc<ImportMap>>, Error> {
+    let service_map_path = service_import_map_path(service_path, maybe_path);
+    let map_paths: Vec<PathBuf> = maybe_base_path
+        .map(PathBuf::from)
+        .into_iter()
+        .chain(service_map_path)
+        .collect();
+    let Some(last_path) = map_paths.last() else {
+        return Ok(None);
+    };
+    let import_map = cached_import_map(&map_paths, || merge_import_maps(&map_paths, last_path))?;
+    Ok(Some(import_map))
+}
+
+fn merge_import_maps(map_paths: &[PathBuf], last_path: &Path) -> Result<ImportMap, Error> {
+    let base_url = import_map_base_url(last_path)?;
+
+    let mut imports = Map::new();
+    let mut scopes = Map::new();
+    for path in map_paths {
+        let (map_imports, map_scopes) = read_import_map(path)?;
+        imports.extend(map_imports);
+        for (scope, scope_imports) in map_scopes {
+            let Value::Object(scope_imports) = scope_imports else {
+                continue;
+            };
+            match scopes.get_mut(&scope) {
+                Some(Value::Object(existing)) => existing.extend(scope_imports),
+                _ => {
+                    scopes.insert(scope, Value::Object(scope_imports));
+                }
+            }
+        }
+    }
+
+    let mut merged = Map::new();
+    merged.insert("imports".to_string(), Value::Object(imports));
+    merged.insert("scopes".to_string(), Value::Object(scopes));
+
+    let result = parse_from_json(&base_url, &Value::Object(merged).to_string())?;
+    print_import_map_diagnostics(&result.diagnostics);
+    Ok(result.import_map)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_service_import_map_overrides_global_map() {
+        let global_map_path = "./test_cases/import_maps/global_import_map.json";
+        let service_path = Path::new("./test_cases/import_maps/service");
+
+        let import_map = load_service_import_map(service_path, None, Some(global_map_path))
+            .unwrap()
+            .unwrap();
+        let referrer =
+            Url::from_file_path(fs::canonicalize(service_path.join("index.ts")).unwrap()).unwrap();
+
+        // shared alias from the global map
+        let shared = import_map.resolve("shared/greet.ts", &referrer).unwrap();
+        assert!(shared
+            .as_str()
+            .ends_with("/test_cases/import_maps/shared/greet.ts"));
+
+        // pinned by the service map
+        let std = import_map.resolve("std/", &referrer).unwrap();
+        assert_eq!(std.as_str(), "https://deno.land/std@0.150.0/");
+
+        // relative to the service directory
+        let local = import_map.resolve("local", &referrer).unwrap();
+        assert!(local
+            .as_str()
+            .ends_with("/test_cases/import_maps/service/lib/local.ts"));
+    }
+
+    #[test]
+    fn test_import_maps_are_shared() {
+        let service_path = Path::new("./test_cases/import_maps/service");
+        let global_map_path = "./test_cases/import_maps/global_import_map.json";
+
+        let first = load_service_import_map(service_path, None, Some(global_map_path))
+            .unwrap()
+            .unwrap();
+        let second = load_service_import_map(service_path, None, Some(global_map_path))
+            .unwrap()
+            .unwrap();
+        assert!(Arc::ptr_eq(&first, &second));
+
+        // a different set of files is another map
+        let service_only = load_service_import_map(service_path, None, None)
+            .unwrap()
+            .unwrap();
+        assert!(!Arc::ptr_eq(&first, &service_only));
+    }
+}
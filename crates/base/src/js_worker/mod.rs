@@ -1 +1,9 @@
+pub mod bundle;
+pub mod code_cache;
+pub mod import_attributes;
+pub mod import_map;
+pub mod module_graph;
 pub mod module_loader;
+pub mod resolution;
+pub mod vendor;
+pub mod wasm;
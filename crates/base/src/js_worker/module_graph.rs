@@ -0,0 +1,240 @@
+use crate::js_worker::import_attributes::{rewrite_import_attributes, text_module_target};
+use crate::js_worker::import_map::load_service_import_map;
+use crate::js_worker::module_loader::DefaultModuleLoader;
+use anyhow::{bail, Error};
+use deno_ast::MediaType;
+use deno_core::ModuleSpecifier;
+use deno_graph::ModuleAnalyzer;
+use module_fetcher::util::checksum;
+use serde::Serialize;
+use std::borrow::Cow;
+use std::collections::{BTreeMap, BTreeSet, VecDeque};
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use url::Url;
+
+// An import of a module, as written and as the loader resolves it.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct GraphImport {
+    pub specifier: String,
+    // `None` if it can't be resolved, see `error`
+    pub resolved: Option<String>,
+    pub error: Option<String>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct GraphModule {
+    // where a remote module was redirected to, if it was
+    pub redirected: Option<String>,
+    // the file, or its copy in the module cache
+    pub local: Option<PathBuf>,
+    pub media_type: Option<String>,
+    // sha256 of the local file
+    pub hash: Option<String>,
+    pub imports: Vec<GraphImport>,
+    // set if it can't be loaded or parsed
+    pub error: Option<String>,
+}
+
+// The modules a service loads, by the url they resolve to, as the worker's
+// loader finds them (with the same import maps and module cache). Sorted, so
+// the graphs of two nodes or two runs can be diffed.
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct ModuleGraph {
+    pub root: String,
+    pub modules: BTreeMap<String, GraphModule>,
+}
+
+impl fmt::Display for ModuleGraph {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (specifier, module) in &self.modules {
+            writeln!(f, "{}", specifier)?;
+            if let Some(redirected) = &module.redirected {
+                writeln!(f, "  redirected: {}", redirected)?;
+            }
+            if let Some(local) = &module.local {
+                writeln!(f, "  local: {}", local.display())?;
+            }
+            if let Some(hash) = &module.hash {
+                writeln!(f, "  sha256: {}", hash)?;
+            }
+            if let Some(error) = &module.error {
+                writeln!(f, "  error: {}", error)?;
+            }
+            for import in &module.imports {
+                match (&import.resolved, &import.error) {
+                    (Some(resolved), _) => writeln!(f, "  {} -> {}", import.specifier, resolved)?,
+                    (None, Some(error)) => {
+                        writeln!(f, "  {} -> error: {}", import.specifier, error)?
+                    }
+                    (None, None) => writeln!(f, "  {}", import.specifier)?,
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+fn resolve_import(loader: &DefaultModuleLoader, specifier: &str, referrer: &str) -> GraphImport {
+    match loader.resolve_specifier(specifier, referrer) {
+        Ok(resolved) => GraphImport {
+            specifier: specifier.to_string(),
+            resolved: Some(resolved.to_string()),
+            error: None,
+        },
+        Err(err) => GraphImport {
+            specifier: specifier.to_string(),
+            resolved: None,
+            error: Some(err.to_string()),
+        },
+    }
+}
+
+async fn load_module(
+    loader: &DefaultModuleLoader,
+    analyzer: &dyn ModuleAnalyzer,
+    specifier: &ModuleSpecifier,
+) -> GraphModule {
+    let mut module = GraphModule::default();
+    // node builtins and npm packages aren't fetched by the loader
+    if !matches!(specifier.scheme(), "file" | "http" | "https" | "data") {
+        return module;
+    }
+
+    let target = text_module_target(specifier);
+    let file = match loader.fetch(target.as_ref().unwrap_or(specifier)).await {
+        Ok(file) => file,
+        Err(err) => {
+            module.error = Some(err.to_string());
+            return module;
+        }
+    };
+    if file.specifier.as_str() != target.as_ref().unwrap_or(specifier).as_str() {
+        module.redirected = Some(file.specifier.to_string());
+    }
+    module.media_type = Some(file.media_type.to_string());
+    module.hash = std::fs::read(&file.local)
+        .ok()
+        .map(|bytes| checksum::gen(&[&bytes]));
+    module.local = Some(file.local.clone());
+
+    // text, json and wasm modules import nothing
+    if target.is_some() || matches!(file.media_type, MediaType::Json | MediaType::Wasm) {
+        return module;
+    }
+    let source: Arc<str> = match rewrite_import_attributes(&file.source) {
+        Cow::Borrowed(_) => file.source.clone(),
+        Cow::Owned(source) => source.into(),
+    };
+    match analyzer.analyze(&file.specifier, source, file.media_type) {
+        Ok(info) => {
+            let imports: BTreeSet<GraphImport> = info
+                .dependencies
+                .iter()
+                .map(|dependency| {
+                    resolve_import(loader, &dependency.specifier, file.specifier.as_str())
+                })
+                .collect();
+            module.imports = imports.into_iter().collect();
+        }
+        Err(err) => module.error = Some(err.to_string()),
+    }
+    module
+}
+
+// The module graph of the service at `service_path`, from its entrypoint,
+// with its import map merged on top of the server's one like its workers.
+// Only the imports of literal specifiers are followed. Remote modules are
+// fetched into the module cache, unless `offline`.
+pub async fn service_module_graph(
+    service_path: &Path,
+    base_import_map_path: Option<&str>,
+    offline: bool,
+) -> Result<ModuleGraph, Error> {
+    if !service_path.join("index.ts").exists() {
+        bail!("service entrypoint not found in {:?}", service_path);
+    }
+    // the url the worker loads it from
+    let base_url = Url::from_directory_path(std::env::current_dir()?.join(service_path))
+        .map_err(|_| anyhow::anyhow!("invalid service path {:?}", service_path))?;
+    let root = base_url.join("index.ts")?;
+
+    let import_map = load_service_import_map(service_path, None, base_import_map_path)?;
+    let loader = DefaultModuleLoader::new(import_map, None, false, offline)?;
+    let analyzer = loader.analyzer();
+
+    let mut graph = ModuleGraph {
+        root: root.to_string(),
+        modules: BTreeMap::new(),
+    };
+    let mut pending = VecDeque::from([root]);
+    while let Some(specifier) = pending.pop_front() {
+        if graph.modules.contains_key(specifier.as_str()) {
+            continue;
+        }
+        let module = load_module(&loader, analyzer.as_ref(), &specifier).await;
+        pending.extend(
+            module
+                .imports
+                .iter()
+                .filter_map(|import| import.resolved.as_deref())
+                .filter_map(|resolved| Url::parse(resolved).ok()),
+        );
+        graph.modules.insert(specifier.to_string(), module);
+    }
+    Ok(graph)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_service_module_graph() {
+        let service_path = Path::new("./test_cases/import_attributes");
+        let graph = service_module_graph(service_path, None, true)
+            .await
+            .unwrap();
+        let dir =
+            Url::from_directory_path(std::env::current_dir().unwrap().join(service_path)).unwrap();
+
+        let root = dir.join("index.ts").unwrap();
+        assert_eq!(graph.root, root.as_str());
+        let specifiers: Vec<&str> = graph.modules.keys().map(|s| s.as_str()).collect();
+        assert_eq!(
+            specifiers,
+            vec![
+                dir.join("config.json").unwrap().as_str(),
+                dir.join("index.ts").unwrap().as_str(),
+                dir.join("name.txt?__edge_type=text").unwrap().as_str(),
+            ]
+        );
+
+        let index = &graph.modules[root.as_str()];
+        assert_eq!(index.media_type.as_deref(), Some("TypeScript"));
+        assert_eq!(index.imports.len(), 2);
+        assert_eq!(index.imports[0].specifier, "./config.json");
+        assert_eq!(
+            index.imports[0].resolved.as_deref(),
+            Some(dir.join("config.json").unwrap().as_str())
+        );
+        let source = std::fs::read(service_path.join("index.ts")).unwrap();
+        assert_eq!(index.hash, Some(checksum::gen(&[&source])));
+
+        // the same graph every time
+        let again = service_module_graph(service_path, None, true)
+            .await
+            .unwrap();
+        assert_eq!(graph, again);
+        assert!(graph.to_string().starts_with(dir.as_str()));
+
+        assert!(
+            service_module_graph(Path::new("./test_cases/missing"), None, true)
+                .await
+                .is_err()
+        );
+    }
+}
@@ -1,7 +1,13 @@
+use crate::js_worker::code_cache::{code_cache_enabled, source_hash, LoadedModule};
+use crate::js_worker::import_attributes::{
+    rewrite_import_attributes, text_module_source, text_module_target,
+};
+use crate::js_worker::resolution::{import_map_keys, suggest_specifiers};
+use crate::js_worker::wasm::{parse_wasm_module, wasm_module_source};
 use anyhow::{bail, Error};
 use deno_ast::EmitOptions;
 use deno_ast::MediaType;
-use deno_core::error::AnyError;
+use deno_core::error::{get_custom_error_class, AnyError};
 use deno_core::futures::FutureExt;
 use deno_core::ModuleLoader;
 use deno_core::ModuleSource;
@@ -10,14 +16,22 @@ use deno_core::ModuleSpecifier;
 use deno_core::ModuleType;
 use deno_core::ResolutionKind;
 use import_map::ImportMap;
+use module_fetcher::auth_tokens::AuthTokens;
 use module_fetcher::cache::{
     caches, DenoDir, EmitCache, FastInsecureHasher, HttpCache, ParsedSourceCache,
 };
 use module_fetcher::emit::emit_parsed_source;
-use module_fetcher::file_fetcher::{CacheSetting, FileFetcher};
+use module_fetcher::file_fetcher::{CacheSetting, File, FileFetcher};
 use module_fetcher::http_util::HttpClient;
+use once_cell::sync::OnceCell;
+use sb_worker_context::resolution::ResolutionDiagnostic;
+use std::borrow::Cow;
+use std::cell::RefCell;
+use std::collections::HashMap;
 use std::path::Path;
 use std::pin::Pin;
+use std::rc::Rc;
+use std::sync::Arc;
 use url::Url;
 
 fn get_module_type(media_type: MediaType) -> Result<ModuleType, Error> {
@@ -38,10 +52,17 @@ fn get_module_type(media_type: MediaType) -> Result<ModuleType, Error> {
     Ok(module_type)
 }
 
+// one client (and tls config) for the process, it's cheap to clone
+static HTTP_CLIENT: OnceCell<HttpClient> = OnceCell::new();
+
 fn make_http_client() -> Result<HttpClient, AnyError> {
-    let root_cert_store = None;
-    let unsafely_ignore_certificate_errors = None;
-    HttpClient::new(root_cert_store, unsafely_ignore_certificate_errors)
+    HTTP_CLIENT
+        .get_or_try_init(|| {
+            let root_cert_store = None;
+            let unsafely_ignore_certificate_errors = None;
+            HttpClient::new(root_cert_store, unsafely_ignore_certificate_errors)
+        })
+        .cloned()
 }
 
 pub struct DefaultModuleLoader {
@@ -49,17 +70,34 @@ pub struct DefaultModuleLoader {
     permissions: module_fetcher::permissions::Permissions,
     emit_cache: EmitCache,
     parsed_source_cache: ParsedSourceCache,
-    maybe_import_map: Option<ImportMap>,
+    maybe_import_map: Option<Arc<ImportMap>>,
+    import_map_keys: Vec<String>,
+    // resolved module -> module that first imported it, to report import chains
+    referrers: RefCell<HashMap<String, String>>,
+    // remote modules missing from the cache while offline
+    missing_modules: Rc<RefCell<Vec<String>>>,
+    // the modules loaded since the last call to `take_loaded_modules`, when
+    // the code cache is on
+    loaded_modules: Option<Rc<RefCell<Vec<LoadedModule>>>>,
+    // loaded ahead by the code cache, handed to deno_core when it asks
+    primed_modules: RefCell<HashMap<ModuleSpecifier, ModuleSource>>,
 }
 
 impl DefaultModuleLoader {
-    pub fn new(maybe_import_map: Option<ImportMap>, no_cache: bool) -> Result<Self, AnyError> {
+    pub fn new(
+        maybe_import_map: Option<Arc<ImportMap>>,
+        maybe_auth_tokens: Option<String>,
+        no_cache: bool,
+        offline: bool,
+    ) -> Result<Self, AnyError> {
         // Note: we are reusing Deno dependency cache path
         let deno_dir = DenoDir::new(None)?;
         let deps_cache_location = deno_dir.deps_folder_path();
 
         let http_cache = HttpCache::new(&deps_cache_location);
-        let cache_setting = if no_cache {
+        let cache_setting = if offline {
+            CacheSetting::Only
+        } else if no_cache {
             CacheSetting::ReloadAll
         } else {
             CacheSetting::Use
@@ -67,34 +105,85 @@ impl DefaultModuleLoader {
         let allow_remote = true;
         let http_client = make_http_client()?;
         let blob_store = deno_web::BlobStore::default();
-        let file_fetcher = FileFetcher::new(
+        let mut file_fetcher = FileFetcher::new(
             http_cache,
             cache_setting,
             allow_remote,
             http_client,
             blob_store,
         );
+        if maybe_auth_tokens.is_some() {
+            file_fetcher.add_auth_tokens(AuthTokens::new(maybe_auth_tokens));
+        }
         let permissions = module_fetcher::permissions::Permissions::default();
         let emit_cache = EmitCache::new(deno_dir.gen_cache.clone());
         let caches_def = caches::Caches::default();
         let parsed_source_cache = ParsedSourceCache::new(caches_def.dep_analysis_db(&deno_dir));
 
+        let import_map_keys = maybe_import_map
+            .as_deref()
+            .map(import_map_keys)
+            .unwrap_or_default();
+
         Ok(Self {
             file_fetcher,
             permissions,
             emit_cache,
             parsed_source_cache,
             maybe_import_map,
+            import_map_keys,
+            referrers: RefCell::new(HashMap::new()),
+            missing_modules: Rc::new(RefCell::new(vec![])),
+            loaded_modules: code_cache_enabled().then(|| Rc::new(RefCell::new(vec![]))),
+            primed_modules: RefCell::new(HashMap::new()),
         })
     }
-}
 
-impl ModuleLoader for DefaultModuleLoader {
-    fn resolve(
+    // Remote modules that were not in the cache while offline, since the last call.
+    // They are loaded as empty modules, so the whole module graph is walked and
+    // every missing module can be reported at once.
+    pub fn take_missing_modules(&self) -> Vec<String> {
+        std::mem::take(&mut *self.missing_modules.borrow_mut())
+    }
+
+    pub(crate) fn take_loaded_modules(&self) -> Vec<LoadedModule> {
+        self.loaded_modules
+            .as_ref()
+            .map(|loaded| std::mem::take(&mut *loaded.borrow_mut()))
+            .unwrap_or_default()
+    }
+
+    pub(crate) fn keep_primed(&self, specifier: ModuleSpecifier, source: ModuleSource) {
+        self.primed_modules.borrow_mut().insert(specifier, source);
+    }
+
+    // The primed modules deno_core didn't ask for are no longer imported,
+    // they aren't counted as loaded.
+    pub(crate) fn clear_primed(&self) {
+        let unused = std::mem::take(&mut *self.primed_modules.borrow_mut());
+        if let Some(loaded) = &self.loaded_modules {
+            loaded
+                .borrow_mut()
+                .retain(|module| !unused.contains_key(&module.specifier));
+        }
+    }
+
+    // fetched (or read from the cache) the way the modules of the worker are
+    pub(crate) async fn fetch(&self, specifier: &ModuleSpecifier) -> Result<File, AnyError> {
+        self.file_fetcher
+            .fetch(specifier, self.permissions.clone())
+            .await
+    }
+
+    // finds the imports of the modules the loader fetched
+    pub(crate) fn analyzer(&self) -> Box<dyn deno_graph::ModuleAnalyzer> {
+        self.parsed_source_cache.as_analyzer()
+    }
+
+    pub(crate) fn resolve_specifier(
         &self,
         specifier: &str,
         referrer: &str,
-        _kind: ResolutionKind,
     ) -> Result<ModuleSpecifier, Error> {
         if let Some(import_map) = &self.maybe_import_map {
             let referrer_relative = Path::new(referrer).is_relative();
@@ -116,6 +205,55 @@ impl ModuleLoader for DefaultModuleLoader {
         }
     }
 
+    fn resolution_diagnostic(
+        &self,
+        specifier: &str,
+        referrer: &str,
+        err: Error,
+    ) -> ResolutionDiagnostic {
+        let referrers = self.referrers.borrow();
+        let mut chain = vec![referrer.to_string()];
+        let mut current = referrers.get(referrer);
+        while let Some(module) = current {
+            // guard against import cycles
+            if chain.contains(module) {
+                break;
+            }
+            chain.push(module.clone());
+            current = referrers.get(module);
+        }
+
+        ResolutionDiagnostic {
+            specifier: specifier.to_string(),
+            referrer: referrer.to_string(),
+            reason: err.to_string(),
+            suggestions: suggest_specifiers(specifier, &self.import_map_keys),
+            chain,
+        }
+    }
+}
+
+impl ModuleLoader for DefaultModuleLoader {
+    fn resolve(
+        &self,
+        specifier: &str,
+        referrer: &str,
+        kind: ResolutionKind,
+    ) -> Result<ModuleSpecifier, Error> {
+        match self.resolve_specifier(specifier, referrer) {
+            Ok(resolved) => {
+                if !matches!(kind, ResolutionKind::MainModule) {
+                    self.referrers
+                        .borrow_mut()
+                        .entry(resolved.to_string())
+                        .or_insert_with(|| referrer.to_string());
+                }
+                Ok(resolved)
+            }
+            Err(err) => Err(self.resolution_diagnostic(specifier, referrer, err).into()),
+        }
+    }
+
     // TODO: implement prepare_load method
     fn load(
         &self,
@@ -123,11 +261,18 @@ impl ModuleLoader for DefaultModuleLoader {
         _maybe_referrer: Option<ModuleSpecifier>,
         _is_dyn_import: bool,
     ) -> Pin<Box<ModuleSourceFuture>> {
+        if let Some(source) = self.primed_modules.borrow_mut().remove(module_specifier) {
+            return async move { Ok(source) }.boxed_local();
+        }
+
         let file_fetcher = self.file_fetcher.clone();
         let permissions = self.permissions.clone();
         let module_specifier = module_specifier.clone();
+        let loaded_specifier = module_specifier.clone();
         let emit_cache = self.emit_cache.clone();
         let parsed_source_cache = self.parsed_source_cache.clone();
+        let missing_modules = self.missing_modules.clone();
+        let loaded_modules = self.loaded_modules.clone();
         let emit_options = EmitOptions {
             inline_source_map: true,
             inline_sources: true,
@@ -139,19 +284,64 @@ impl ModuleLoader for DefaultModuleLoader {
             .finish();
 
         async move {
-            let fetched_file = file_fetcher.fetch(&module_specifier, permissions).await?;
+            // modules imported with `type: "text"` export the file's contents
+            if let Some(target) = text_module_target(&module_specifier) {
+                let fetched_file = file_fetcher.fetch(&target, permissions).await?;
+                return Ok(ModuleSource {
+                    code: text_module_source(&fetched_file.source).into(),
+                    module_type: ModuleType::JavaScript,
+                    module_url_specified: module_specifier.to_string(),
+                    module_url_found: module_specifier.to_string(),
+                });
+            }
+
+            let fetched_file = match file_fetcher.fetch(&module_specifier, permissions).await {
+                Ok(fetched_file) => fetched_file,
+                Err(err) if get_custom_error_class(&err) == Some("NotCached") => {
+                    missing_modules
+                        .borrow_mut()
+                        .push(module_specifier.to_string());
+                    return Ok(ModuleSource {
+                        code: "export {};".into(),
+                        module_type: ModuleType::JavaScript,
+                        module_url_specified: module_specifier.to_string(),
+                        module_url_found: module_specifier.to_string(),
+                    });
+                }
+                Err(err) => return Err(err),
+            };
+
+            // the fetched source is decoded as text, read the binary from the local copy
+            if fetched_file.media_type == MediaType::Wasm {
+                let bytes = std::fs::read(&fetched_file.local)?;
+                let info = parse_wasm_module(&bytes)?;
+                return Ok(ModuleSource {
+                    code: wasm_module_source(&bytes, &info)?.into(),
+                    module_type: ModuleType::JavaScript,
+                    module_url_specified: module_specifier.to_string(),
+                    module_url_found: fetched_file.specifier.to_string(),
+                });
+            }
+
             let module_type = get_module_type(fetched_file.media_type)?;
 
-            let code = fetched_file.source;
-            let code = emit_parsed_source(
-                &emit_cache,
-                &parsed_source_cache,
-                &module_specifier,
-                fetched_file.media_type,
-                &code,
-                &emit_options,
-                emit_config_hash,
-            )?;
+            let code = if matches!(module_type, ModuleType::Json) {
+                fetched_file.source.to_string().into()
+            } else {
+                let code: Arc<str> = match rewrite_import_attributes(&fetched_file.source) {
+                    Cow::Borrowed(_) => fetched_file.source.clone(),
+                    Cow::Owned(code) => code.into(),
+                };
+                emit_parsed_source(
+                    &emit_cache,
+                    &parsed_source_cache,
+                    &module_specifier,
+                    fetched_file.media_type,
+                    &code,
+                    &emit_options,
+                    emit_config_hash,
+                )?
+            };
 
             let module = ModuleSource {
                 code,
@@ -161,6 +351,17 @@ impl ModuleLoader for DefaultModuleLoader {
             };
             Ok(module)
         }
+        .map(move |result: Result<ModuleSource, AnyError>| {
+            if let (Some(loaded_modules), Ok(source)) = (loaded_modules, &result) {
+                if matches!(source.module_type, ModuleType::JavaScript) {
+                    loaded_modules.borrow_mut().push(LoadedModule {
+                        specifier: loaded_specifier,
+                        source_hash: source_hash(source.code.as_bytes()),
+                    });
+                }
+            }
+            result
+        })
         .boxed_local()
     }
 }
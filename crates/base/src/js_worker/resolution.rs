@@ -0,0 +1,104 @@
+use deno_core::serde_json::{self, Value};
+use import_map::ImportMap;
+
+const MAX_SUGGESTIONS: usize = 3;
+
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut prev: Vec<usize> = (0..=b.len()).collect();
+    let mut curr = vec![0; b.len() + 1];
+
+    for (i, ca) in a.chars().enumerate() {
+        curr[0] = i + 1;
+        for (j, cb) in b.iter().enumerate() {
+            let cost = usize::from(ca != *cb);
+            curr[j + 1] = (prev[j] + cost).min(prev[j + 1] + 1).min(curr[j] + 1);
+        }
+        std::mem::swap(&mut prev, &mut curr);
+    }
+
+    prev[b.len()]
+}
+
+// Bare specifiers an import map can resolve, including the ones of its scopes.
+pub fn import_map_keys(import_map: &ImportMap) -> Vec<String> {
+    let Ok(value) = serde_json::from_str::<Value>(&import_map.to_json()) else {
+        return vec![];
+    };
+
+    let mut keys: Vec<String> = vec![];
+    let mut add_keys = |map: Option<&Value>| {
+        if let Some(Value::Object(map)) = map {
+            for key in map.keys() {
+                if !keys.contains(key) {
+                    keys.push(key.clone());
+                }
+            }
+        }
+    };
+
+    add_keys(value.get("imports"));
+    if let Some(Value::Object(scopes)) = value.get("scopes") {
+        for scope in scopes.values() {
+            add_keys(Some(scope));
+        }
+    }
+
+    keys.retain(|key| !key.contains("://") && !key.starts_with("file:"));
+    keys
+}
+
+// Import map keys that look like what the specifier was meant to be. Keys for
+// packages (eg: `std/`) are compared with the same number of leading path
+// segments of the specifier.
+pub fn suggest_specifiers(specifier: &str, keys: &[String]) -> Vec<String> {
+    let mut scored: Vec<(usize, &String)> = keys
+        .iter()
+        .filter_map(|key| {
+            let candidate = if key.ends_with('/') {
+                let segments = key.matches('/').count();
+                match specifier.match_indices('/').nth(segments - 1) {
+                    Some((idx, _)) => &specifier[..=idx],
+                    None => specifier,
+                }
+            } else {
+                specifier
+            };
+
+            let distance = edit_distance(candidate, key);
+            let threshold = (key.chars().count() / 3).max(2);
+            (distance <= threshold).then_some((distance, key))
+        })
+        .collect();
+
+    scored.sort_by(|a, b| a.0.cmp(&b.0).then_with(|| a.1.cmp(b.1)));
+    scored
+        .into_iter()
+        .take(MAX_SUGGESTIONS)
+        .map(|(_, key)| key.clone())
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_suggest_specifiers() {
+        let keys = vec![
+            "std/".to_string(),
+            "@supabase/supabase-js".to_string(),
+            "postgres".to_string(),
+        ];
+
+        assert_eq!(
+            suggest_specifiers("sdt/http/server.ts", &keys),
+            vec!["std/".to_string()]
+        );
+        assert_eq!(
+            suggest_specifiers("@supabase/supabse-js", &keys),
+            vec!["@supabase/supabase-js".to_string()]
+        );
+        assert!(suggest_specifiers("lodash", &keys).is_empty());
+    }
+}
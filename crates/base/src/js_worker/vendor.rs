@@ -0,0 +1,351 @@
+use crate::js_worker::import_attributes::{text_module_target, TEXT_TYPE_PARAM};
+use crate::js_worker::import_map::{is_relative, SERVICE_IMPORT_MAP_NAME};
+use crate::js_worker::module_graph::service_module_graph;
+use crate::js_worker::module_loader::DefaultModuleLoader;
+use anyhow::{anyhow, bail, Context, Error};
+use deno_ast::MediaType;
+use deno_core::serde_json::{self, Map, Value};
+use module_fetcher::util::checksum;
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use url::Url;
+
+// where the remote modules are copied to, in the service directory
+const VENDOR_DIR_NAME: &str = "vendor";
+
+#[derive(Debug, Clone, Default)]
+pub struct VendorReport {
+    // the remote modules copied, as they're imported
+    pub modules: Vec<String>,
+    pub import_map_path: PathBuf,
+}
+
+fn is_remote(specifier: &str) -> bool {
+    specifier.starts_with("http://") || specifier.starts_with("https://")
+}
+
+// `<host>[_<port>]`, the directory of a host's modules
+fn host_dir(specifier: &Url) -> String {
+    let host = specifier.host_str().unwrap_or_default();
+    match specifier.port() {
+        Some(port) => format!("{}_{}", host, port),
+        None => host.to_string(),
+    }
+}
+
+// the extension the loader infers the media type of a local file from
+fn extension(media_type: MediaType) -> Option<&'static str> {
+    match media_type {
+        MediaType::JavaScript => Some(".js"),
+        MediaType::Mjs => Some(".mjs"),
+        MediaType::Cjs => Some(".cjs"),
+        MediaType::Jsx => Some(".jsx"),
+        MediaType::TypeScript => Some(".ts"),
+        MediaType::Mts => Some(".mts"),
+        MediaType::Cts => Some(".cts"),
+        MediaType::Dts => Some(".d.ts"),
+        MediaType::Tsx => Some(".tsx"),
+        MediaType::Json => Some(".json"),
+        MediaType::Wasm => Some(".wasm"),
+        _ => None,
+    }
+}
+
+// The path of a remote module in the vendor directory, as a relative url:
+// `<host dir>/<path>`. The query of a url is hashed into the file name, and
+// a module served with another media type than its extension says (eg: from
+// esm.sh) gets the right extension added, as it's loaded as a local file.
+fn vendor_path(specifier: &Url, media_type: Option<MediaType>) -> String {
+    let mut path = format!("{}{}", host_dir(specifier), specifier.path());
+    if path.ends_with('/') {
+        path.push_str("index");
+    }
+    if let Some(query) = specifier.query() {
+        path = format!("{}_{}", path, &checksum::gen(&[query.as_bytes()])[..8]);
+    }
+    if let Some(ext) = media_type.and_then(extension) {
+        let inferred = Url::parse("file:///")
+            .and_then(|root| root.join(&path))
+            .map(|local| MediaType::from_specifier(&local));
+        if inferred.ok() != media_type {
+            path.push_str(ext);
+        }
+    }
+    path
+}
+
+// Where the imports of the vendored modules lead to.
+#[derive(Debug, Default)]
+struct VendoredModules {
+    // the origins of the modules, eg: "https://deno.land/" -> "./vendor/deno.land/"
+    hosts: BTreeMap<String, String>,
+    // the modules not at the path their url maps to through their origin's
+    // entry (redirected, renamed or with a query)
+    modules: BTreeMap<String, String>,
+}
+
+impl VendoredModules {
+    fn add(&mut self, specifier: &Url, requested: &Url, path: &str) {
+        let dir = host_dir(requested);
+        self.hosts.insert(
+            format!("{}/", requested.origin().ascii_serialization()),
+            format!("./{}/{}/", VENDOR_DIR_NAME, dir),
+        );
+        if path != format!("{}{}", dir, requested.path()) {
+            let mut address = format!("./{}/{}", VENDOR_DIR_NAME, path);
+            if specifier != requested {
+                // a text module
+                address = format!("{}?{}", address, TEXT_TYPE_PARAM);
+            }
+            self.modules.insert(specifier.to_string(), address);
+        }
+    }
+
+    // the address of a remote module or directory once vendored, `None` for
+    // the others
+    fn rewrite(&self, address: &str) -> Option<String> {
+        if let Some(vendored) = self.modules.get(address) {
+            return Some(vendored.clone());
+        }
+        self.hosts.iter().find_map(|(origin, dir)| {
+            let rest = address.strip_prefix(origin.as_str())?;
+            Some(format!("{}{}", dir, rest))
+        })
+    }
+
+    fn rewrite_specifier_map(&self, map: &Map<String, Value>) -> Map<String, Value> {
+        map.iter()
+            .map(|(key, value)| {
+                let value = match value.as_str().and_then(|address| self.rewrite(address)) {
+                    Some(address) => Value::String(address),
+                    None => value.clone(),
+                };
+                (key.clone(), value)
+            })
+            .collect()
+    }
+}
+
+fn read_json_object(path: &Path) -> Result<Map<String, Value>, Error> {
+    let json = fs::read_to_string(path)
+        .with_context(|| format!("failed to read import map {:?}", path))?;
+    match serde_json::from_str(&json)? {
+        Value::Object(map) => Ok(map),
+        _ => bail!("import map {:?} must be an object", path),
+    }
+}
+
+fn object(map: &Map<String, Value>, key: &str) -> Map<String, Value> {
+    match map.get(key) {
+        Some(Value::Object(object)) => object.clone(),
+        _ => Map::new(),
+    }
+}
+
+// Points the remote addresses of the service's import map at the vendored
+// modules, and adds the entries leading the imports of the remote modules to
+// them.
+fn vendor_import_map(
+    service_map_path: &Path,
+    base_map_path: Option<&str>,
+    vendored: &VendoredModules,
+) -> Result<(), Error> {
+    let mut map = if service_map_path.exists() {
+        read_json_object(service_map_path)?
+    } else {
+        Map::new()
+    };
+    let mut imports = vendored.rewrite_specifier_map(&object(&map, "imports"));
+    let mut scopes: Map<String, Value> = object(&map, "scopes")
+        .iter()
+        .map(|(scope, scope_imports)| {
+            let scope = vendored.rewrite(scope).unwrap_or_else(|| scope.clone());
+            let scope_imports = match scope_imports {
+                Value::Object(scope_imports) => {
+                    Value::Object(vendored.rewrite_specifier_map(scope_imports))
+                }
+                value => value.clone(),
+            };
+            (scope, scope_imports)
+        })
+        .collect();
+
+    // the service's entries take precedence over the server's, which would
+    // lead to remote modules otherwise. Its relative ones aren't relative to
+    // the service directory.
+    if let Some(base_map_path) = base_map_path {
+        let base_map = read_json_object(Path::new(base_map_path))?;
+        for (key, value) in object(&base_map, "imports") {
+            let Some(address) = value.as_str().and_then(|address| vendored.rewrite(address)) else {
+                continue;
+            };
+            if !is_relative(&key) && !imports.contains_key(&key) {
+                imports.insert(key, Value::String(address));
+            }
+        }
+    }
+
+    // absolute imports of the remote modules, and root relative ones (eg:
+    // "/v135/react@18.2.0/es2022/react.mjs" from esm.sh), which are resolved
+    // against the vendored module
+    for (origin, dir) in vendored.hosts.iter().chain(&vendored.modules) {
+        imports
+            .entry(origin.clone())
+            .or_insert_with(|| Value::String(dir.clone()));
+    }
+    for dir in vendored.hosts.values() {
+        let mut root = Map::new();
+        root.insert("/".to_string(), Value::String(dir.clone()));
+        scopes
+            .entry(dir.clone())
+            .or_insert_with(|| Value::Object(root));
+    }
+
+    map.insert("imports".to_string(), Value::Object(imports));
+    map.insert("scopes".to_string(), Value::Object(scopes));
+    fs::write(
+        service_map_path,
+        format!("{}\n", serde_json::to_string_pretty(&Value::Object(map))?),
+    )
+    .with_context(|| format!("failed to write import map {:?}", service_map_path))?;
+    Ok(())
+}
+
+// Copies the remote modules the service at `service_path` imports into its
+// `vendor` directory, and points its import map at them, so it loads with no
+// network access (eg: on air-gapped nodes). Modules are fetched the way its
+// workers fetch them, with the server's import map at `base_import_map_path`,
+// whose entries leading to remote modules are copied to the service's.
+// Running it again vendors the modules imported since.
+pub async fn vendor_service(
+    service_path: &Path,
+    base_import_map_path: Option<&str>,
+) -> Result<VendorReport, Error> {
+    let graph = service_module_graph(service_path, base_import_map_path, false).await?;
+    let remote: Vec<&String> = graph
+        .modules
+        .keys()
+        .filter(|specifier| is_remote(specifier))
+        .collect();
+    let failed: Vec<String> = remote
+        .iter()
+        .filter_map(|specifier| {
+            let error = graph.modules[*specifier].error.as_ref()?;
+            Some(format!("  {}: {}", specifier, error))
+        })
+        .collect();
+    if !failed.is_empty() {
+        bail!("failed to fetch the modules:\n{}", failed.join("\n"));
+    }
+
+    let vendor_dir = std::env::current_dir()?
+        .join(service_path)
+        .join(VENDOR_DIR_NAME);
+    let vendor_dir_url = Url::from_directory_path(&vendor_dir)
+        .map_err(|_| anyhow!("invalid service path {:?}", service_path))?;
+    // they're in the module cache now
+    let loader = DefaultModuleLoader::new(None, None, false, true)?;
+
+    let mut vendored = VendoredModules::default();
+    for specifier in &remote {
+        let specifier = Url::parse(specifier)?;
+        let target = text_module_target(&specifier);
+        let requested = target.clone().unwrap_or_else(|| specifier.clone());
+        let file = loader.fetch(&requested).await?;
+
+        // text modules are read as they are
+        let path = vendor_path(&file.specifier, target.is_none().then_some(file.media_type));
+        let local = vendor_dir_url
+            .join(&path)?
+            .to_file_path()
+            .map_err(|_| anyhow!("invalid vendor path {}", path))?;
+        if let Some(dir) = local.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        fs::copy(&file.local, &local)
+            .with_context(|| format!("failed to copy {} to {:?}", specifier, local))?;
+        vendored.add(&specifier, &requested, &path);
+    }
+
+    let import_map_path = service_path.join(SERVICE_IMPORT_MAP_NAME);
+    if !remote.is_empty() {
+        vendor_import_map(&import_map_path, base_import_map_path, &vendored)?;
+    }
+    Ok(VendorReport {
+        modules: remote.into_iter().cloned().collect(),
+        import_map_path,
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_vendor_path() {
+        let url = |url: &str| Url::parse(url).unwrap();
+
+        assert_eq!(
+            vendor_path(
+                &url("https://deno.land/std@0.150.0/http/server.ts"),
+                Some(MediaType::TypeScript)
+            ),
+            "deno.land/std@0.150.0/http/server.ts"
+        );
+        assert_eq!(
+            vendor_path(&url("http://localhost:8000/mod.d.ts"), Some(MediaType::Dts)),
+            "localhost_8000/mod.d.ts"
+        );
+        // served as javascript
+        assert_eq!(
+            vendor_path(
+                &url("https://esm.sh/react@18.2.0"),
+                Some(MediaType::JavaScript)
+            ),
+            "esm.sh/react@18.2.0.js"
+        );
+        let with_query = vendor_path(
+            &url("https://esm.sh/react@18.2.0/es2022/react.mjs?target=deno"),
+            Some(MediaType::Mjs),
+        );
+        assert!(with_query.starts_with("esm.sh/react@18.2.0/es2022/react.mjs_"));
+        assert!(with_query.ends_with(".mjs"));
+        assert_eq!(
+            vendor_path(&url("https://example.com/docs/"), None),
+            "example.com/docs/index"
+        );
+        assert_eq!(
+            vendor_path(&url("https://example.com/name.txt"), None),
+            "example.com/name.txt"
+        );
+    }
+
+    #[test]
+    fn test_vendored_addresses() {
+        let url = |url: &str| Url::parse(url).unwrap();
+        let mut vendored = VendoredModules::default();
+        let server = url("https://deno.land/std@0.150.0/http/server.ts");
+        vendored.add(&server, &server, "deno.land/std@0.150.0/http/server.ts");
+        let react = url("https://esm.sh/react@18.2.0");
+        vendored.add(&react, &react, "esm.sh/react@18.2.0.js");
+        let text = url("http://localhost:8000/name.txt?__edge_type=text");
+        let target = url("http://localhost:8000/name.txt");
+        vendored.add(&text, &target, "localhost_8000/name.txt");
+
+        assert_eq!(
+            vendored.rewrite("https://deno.land/std@0.150.0/"),
+            Some("./vendor/deno.land/std@0.150.0/".to_string())
+        );
+        assert_eq!(
+            vendored.rewrite("https://esm.sh/react@18.2.0"),
+            Some("./vendor/esm.sh/react@18.2.0.js".to_string())
+        );
+        assert_eq!(
+            vendored.rewrite("http://localhost:8000/name.txt?__edge_type=text"),
+            Some("./vendor/localhost_8000/name.txt?__edge_type=text".to_string())
+        );
+        assert_eq!(vendored.rewrite("https://cdn.skypack.dev/react"), None);
+        assert_eq!(vendored.rewrite("./lib/local.ts"), None);
+    }
+}
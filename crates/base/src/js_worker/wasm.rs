@@ -0,0 +1,234 @@
+use anyhow::{bail, Error};
+use deno_core::serde_json;
+use std::fmt::Write;
+
+const WASM_MAGIC: &[u8] = b"\0asm";
+const IMPORT_SECTION_ID: u8 = 2;
+const EXPORT_SECTION_ID: u8 = 7;
+
+// What a wasm module imports and exports, needed to expose it as an ES module.
+#[derive(Debug, Default, PartialEq)]
+pub struct WasmModuleInfo {
+    // module names of the imports, in order of appearance
+    pub imports: Vec<String>,
+    pub exports: Vec<String>,
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn is_empty(&self) -> bool {
+        self.pos >= self.bytes.len()
+    }
+
+    fn byte(&mut self) -> Result<u8, Error> {
+        let Some(byte) = self.bytes.get(self.pos) else {
+            bail!("unexpected end of wasm module");
+        };
+        self.pos += 1;
+        Ok(*byte)
+    }
+
+    fn bytes(&mut self, len: usize) -> Result<&'a [u8], Error> {
+        let end = self.pos + len;
+        if end > self.bytes.len() {
+            bail!("unexpected end of wasm module");
+        }
+        let bytes = &self.bytes[self.pos..end];
+        self.pos = end;
+        Ok(bytes)
+    }
+
+    // unsigned LEB128
+    fn u32(&mut self) -> Result<u32, Error> {
+        let mut result = 0u32;
+        let mut shift = 0;
+        loop {
+            let byte = self.byte()?;
+            if shift >= 32 {
+                bail!("invalid integer in wasm module");
+            }
+            result |= ((byte & 0x7f) as u32) << shift;
+            if byte & 0x80 == 0 {
+                return Ok(result);
+            }
+            shift += 7;
+        }
+    }
+
+    fn name(&mut self) -> Result<String, Error> {
+        let len = self.u32()? as usize;
+        Ok(String::from_utf8(self.bytes(len)?.to_vec())?)
+    }
+
+    fn limits(&mut self) -> Result<(), Error> {
+        let flags = self.byte()?;
+        self.u32()?;
+        if flags & 0x01 != 0 {
+            self.u32()?;
+        }
+        Ok(())
+    }
+}
+
+// Reads the import and export sections of a wasm binary.
+pub fn parse_wasm_module(bytes: &[u8]) -> Result<WasmModuleInfo, Error> {
+    if bytes.len() < 8 || &bytes[..4] != WASM_MAGIC {
+        bail!("not a wasm module");
+    }
+
+    let mut info = WasmModuleInfo::default();
+    let mut reader = Reader {
+        bytes: &bytes[8..],
+        pos: 0,
+    };
+
+    while !reader.is_empty() {
+        let id = reader.byte()?;
+        let size = reader.u32()? as usize;
+        let mut section = Reader {
+            bytes: reader.bytes(size)?,
+            pos: 0,
+        };
+
+        match id {
+            IMPORT_SECTION_ID => {
+                for _ in 0..section.u32()? {
+                    let module = section.name()?;
+                    let _field = section.name()?;
+                    match section.byte()? {
+                        // function: type index
+                        0x00 => {
+                            section.u32()?;
+                        }
+                        // table: element type and limits
+                        0x01 => {
+                            section.byte()?;
+                            section.limits()?;
+                        }
+                        // memory
+                        0x02 => section.limits()?,
+                        // global: value type and mutability
+                        0x03 => {
+                            section.bytes(2)?;
+                        }
+                        kind => bail!("unknown import kind {} in wasm module", kind),
+                    }
+                    if !info.imports.contains(&module) {
+                        info.imports.push(module);
+                    }
+                }
+            }
+            EXPORT_SECTION_ID => {
+                for _ in 0..section.u32()? {
+                    let name = section.name()?;
+                    // kind and index
+                    section.byte()?;
+                    section.u32()?;
+                    info.exports.push(name);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    Ok(info)
+}
+
+// JavaScript module that instantiates the wasm module and re-exports its
+// exports. The imports of the wasm module are imported like any other module,
+// relative to it. Compilation goes through `WebAssembly.compileStreaming`.
+pub fn wasm_module_source(bytes: &[u8], info: &WasmModuleInfo) -> Result<String, Error> {
+    let mut source = String::new();
+
+    for (i, module) in info.imports.iter().enumerate() {
+        writeln!(
+            source,
+            "import * as __wasm_import_{} from {};",
+            i,
+            serde_json::to_string(module)?
+        )?;
+    }
+
+    writeln!(
+        source,
+        "const __wasm_bytes = Uint8Array.from(atob(\"{}\"), (c) => c.charCodeAt(0));",
+        base64::encode(bytes)
+    )?;
+    writeln!(
+        source,
+        "const __wasm_module = await WebAssembly.compileStreaming(new Response(__wasm_bytes, {{ headers: {{ \"content-type\": \"application/wasm\" }} }}));"
+    )?;
+
+    let imports = info
+        .imports
+        .iter()
+        .enumerate()
+        .map(|(i, module)| {
+            Ok(format!(
+                "{}: __wasm_import_{}",
+                serde_json::to_string(module)?,
+                i
+            ))
+        })
+        .collect::<Result<Vec<_>, Error>>()?;
+    writeln!(
+        source,
+        "const __wasm_instance = await WebAssembly.instantiate(__wasm_module, {{ {} }});",
+        imports.join(", ")
+    )?;
+
+    for (i, name) in info.exports.iter().enumerate() {
+        let name = serde_json::to_string(name)?;
+        writeln!(
+            source,
+            "const __wasm_export_{} = __wasm_instance.exports[{}];",
+            i, name
+        )?;
+        writeln!(source, "export {{ __wasm_export_{} as {} }};", i, name)?;
+    }
+
+    Ok(source)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::tester::EdgeRuntimeTester;
+    use hyper::{Body, Request};
+
+    #[test]
+    fn test_parse_wasm_module() {
+        let bytes = std::fs::read("./test_cases/wasm_import/add.wasm").unwrap();
+        let info = parse_wasm_module(&bytes).unwrap();
+        assert_eq!(
+            info,
+            WasmModuleInfo {
+                imports: vec!["./env.js".to_string()],
+                exports: vec!["add".to_string()],
+            }
+        );
+
+        let source = wasm_module_source(&bytes, &info).unwrap();
+        assert!(source.starts_with("import * as __wasm_import_0 from \"./env.js\";\n"));
+        assert!(source.contains("export { __wasm_export_0 as \"add\" };"));
+
+        assert!(parse_wasm_module(b"export {}").is_err());
+    }
+
+    #[tokio::test]
+    async fn test_import_wasm_module() {
+        let mut tester = EdgeRuntimeTester::new("./test_cases/wasm_import")
+            .await
+            .unwrap();
+
+        let req = Request::get("http://localhost/")
+            .body(Body::empty())
+            .unwrap();
+        let res = tester.request(req).await.unwrap();
+        assert_eq!(res.text().unwrap(), "103");
+    }
+}
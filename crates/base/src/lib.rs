@@ -1,7 +1,35 @@
+pub mod audit;
+pub mod autoscaler;
+pub mod billing;
+pub mod bootstrap;
+pub mod coalesce;
 pub mod commands;
+pub mod compat;
+pub mod cors;
+pub mod deployments;
 pub mod edge_runtime;
+pub mod geoip;
+pub mod hooks;
 pub mod js_worker;
+pub mod maintenance;
+pub mod manifest;
+pub mod metrics;
+pub mod monitor;
+pub mod proxy;
+pub mod rate_limit;
+pub mod reload;
+pub mod runtime_info;
+pub mod scheduler;
 pub mod server;
+pub mod service_source;
 pub mod snapshot;
+pub mod sticky;
+pub mod test_runner;
+pub mod tester;
+pub mod type_check;
 pub mod utils;
+pub mod waf;
+pub mod watchdog;
 pub mod worker_ctx;
+pub mod worker_handles;
+pub mod worker_threads;
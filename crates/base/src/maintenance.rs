@@ -0,0 +1,128 @@
+use deno_core::serde_json;
+use hyper::header::{HeaderValue, CONTENT_TYPE, RETRY_AFTER};
+use hyper::{Body, Response};
+use once_cell::sync::Lazy;
+use std::path::Path;
+use std::sync::RwLock;
+
+static MAINTENANCE: Lazy<RwLock<MaintenanceOpts>> = Lazy::new(Default::default);
+
+// What the server answers instead of the workers, during incidents and deploy
+// freezes.
+#[derive(Debug, Clone, PartialEq)]
+pub struct MaintenanceOpts {
+    // every request but the ones to `/_internal/*` is answered
+    pub enabled: bool,
+    // the requests to their workers are answered, by name or directory name
+    pub disabled_services: Vec<String>,
+    pub status: u16,
+    // JSON bodies are sent as such, the others as text
+    pub body: Option<String>,
+    pub retry_after_secs: Option<u64>,
+}
+
+impl Default for MaintenanceOpts {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            disabled_services: vec![],
+            status: 503,
+            body: None,
+            retry_after_secs: None,
+        }
+    }
+}
+
+impl MaintenanceOpts {
+    // `service` is the name of the service, or its path
+    fn is_disabled(&self, service: &str) -> bool {
+        let dir_name = Path::new(service)
+            .file_name()
+            .and_then(|name| name.to_str());
+        self.disabled_services
+            .iter()
+            .any(|disabled| disabled == service || Some(disabled.as_str()) == dir_name)
+    }
+
+    fn response(&self) -> Response<Body> {
+        let (content_type, body) = match &self.body {
+            Some(body) if serde_json::from_str::<serde_json::Value>(body).is_ok() => {
+                ("application/json", body.clone())
+            }
+            Some(body) => ("text/plain; charset=utf-8", body.clone()),
+            None => (
+                "application/json",
+                serde_json::json!({ "msg": "under maintenance" }).to_string(),
+            ),
+        };
+        let mut res = Response::builder()
+            .status(self.status)
+            .header(CONTENT_TYPE, content_type)
+            .body(Body::from(body))
+            .unwrap();
+        if let Some(secs) = self.retry_after_secs {
+            res.headers_mut()
+                .insert(RETRY_AFTER, HeaderValue::from(secs));
+        }
+        res
+    }
+}
+
+// Turns maintenance mode on or off, and disables services. Called at startup
+// and when the config is reloaded, embedders can call it at any time.
+pub fn set_maintenance(opts: MaintenanceOpts) {
+    *MAINTENANCE.write().unwrap() = opts;
+}
+
+// The answer to a request, to `service` if it's known, while the server is
+// under maintenance or the service is disabled.
+pub(crate) fn maintenance_response(service: Option<&str>) -> Option<Response<Body>> {
+    let opts = MAINTENANCE.read().unwrap();
+    let disabled = service.map_or(false, |service| opts.is_disabled(service));
+    (opts.enabled || disabled).then(|| opts.response())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_disabled_services() {
+        let opts = MaintenanceOpts {
+            disabled_services: vec!["hello".to_string()],
+            ..Default::default()
+        };
+        assert!(opts.is_disabled("hello"));
+        assert!(opts.is_disabled("./examples/hello"));
+        assert!(!opts.is_disabled("hello-world"));
+        assert!(!opts.is_disabled("./hello/world"));
+    }
+
+    #[tokio::test]
+    async fn test_maintenance_response() {
+        let opts = MaintenanceOpts {
+            enabled: true,
+            status: 503,
+            body: Some("back soon".to_string()),
+            retry_after_secs: Some(120),
+            ..Default::default()
+        };
+        let res = opts.response();
+        assert_eq!(res.status(), 503);
+        assert_eq!(res.headers().get(RETRY_AFTER).unwrap(), "120");
+        assert_eq!(
+            res.headers().get(CONTENT_TYPE).unwrap(),
+            "text/plain; charset=utf-8"
+        );
+        let body = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        assert_eq!(&body[..], b"back soon");
+
+        let res = MaintenanceOpts {
+            body: Some(r#"{ "error": "frozen" }"#.to_string()),
+            ..Default::default()
+        }
+        .response();
+        assert_eq!(res.headers().get(CONTENT_TYPE).unwrap(), "application/json");
+        assert!(res.headers().get(RETRY_AFTER).is_none());
+    }
+}
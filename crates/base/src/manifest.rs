@@ -0,0 +1,417 @@
+use crate::compat;
+use crate::cors::{self, CorsConfig};
+use crate::proxy::{IpNetwork, CLIENT_IP_HEADER};
+use crate::worker_ctx::error_response;
+use anyhow::{bail, Context, Error};
+use deno_core::serde_json;
+use hyper::header::{HeaderMap, HeaderName, HeaderValue, ALLOW, ORIGIN};
+use hyper::{Body, Method, Request, Response};
+use sb_worker_context::essentials::{Capability, CompatFlag};
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::fs;
+use std::net::IpAddr;
+use std::path::Path;
+
+// settings of a service, picked up from its directory
+pub const SERVICE_MANIFEST_NAME: &str = "edge-runtime.json";
+
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct ServiceManifest {
+    pub headers: HeaderRules,
+    // answered by the runtime, preflight requests don't boot a worker
+    pub cors: Option<CorsConfig>,
+    // the methods its functions handle, the others are answered with a 405
+    // without booting a worker
+    pub methods: Option<Vec<String>>,
+    // the clients its functions serve, the others are answered with a 403
+    // without booting a worker
+    pub client_ips: IpRules,
+    // what its workers can do in strict mode, see `Capability`
+    pub capabilities: Vec<Capability>,
+    // YYYY-MM-DD, its workers get the behaviour changes made until then
+    pub compatibility_date: Option<String>,
+    // behaviour changes turned on (`<flag>`) or off (`no_<flag>`) regardless
+    // of the date, see `compat::compat_flags`
+    pub compatibility_flags: Vec<String>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct HeaderRules {
+    // applied to the requests forwarded to the service's workers
+    pub request: HeaderFilter,
+    // applied to the responses of its workers
+    pub response: HeaderFilter,
+}
+
+// Addresses or networks (eg: 203.0.113.0/24) of the clients, as the server
+// derived them (see `TrustedProxies`).
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct IpRules {
+    // only these clients are served, if any are given
+    pub allow: Vec<IpNetwork>,
+    // refused, even if allowed
+    pub deny: Vec<IpNetwork>,
+}
+
+impl IpRules {
+    pub fn is_empty(&self) -> bool {
+        self.allow.is_empty() && self.deny.is_empty()
+    }
+
+    // a client the server couldn't tell is only refused by an allowlist
+    pub fn allows(&self, client_ip: Option<IpAddr>) -> bool {
+        let Some(ip) = client_ip else {
+            return self.allow.is_empty();
+        };
+        (self.allow.is_empty() || self.allow.iter().any(|network| network.contains(ip)))
+            && !self.deny.iter().any(|network| network.contains(ip))
+    }
+}
+
+// Header names are matched case insensitively, a trailing `*` matches any
+// suffix (eg: `x-internal-*`).
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct HeaderFilter {
+    // only these headers are kept, if any are given
+    pub allow: Vec<String>,
+    // removed, even if allowed
+    pub deny: Vec<String>,
+    // added, or replacing the ones already there (eg: strict-transport-security)
+    pub set: HashMap<String, String>,
+}
+
+fn matches(pattern: &str, name: &str) -> bool {
+    match pattern.strip_suffix('*') {
+        Some(prefix) => name
+            .get(..prefix.len())
+            .map_or(false, |start| start.eq_ignore_ascii_case(prefix)),
+        None => pattern.eq_ignore_ascii_case(name),
+    }
+}
+
+impl HeaderFilter {
+    pub fn is_empty(&self) -> bool {
+        self.allow.is_empty() && self.deny.is_empty() && self.set.is_empty()
+    }
+
+    fn validate(&self) -> Result<(), Error> {
+        for (name, value) in &self.set {
+            if HeaderName::try_from(name.as_str()).is_err()
+                || HeaderValue::try_from(value.as_str()).is_err()
+            {
+                bail!("invalid header {}", name);
+            }
+        }
+        Ok(())
+    }
+
+    pub fn apply(&self, headers: &mut HeaderMap) {
+        if self.is_empty() {
+            return;
+        }
+
+        let removed: Vec<HeaderName> = headers
+            .keys()
+            .filter(|name| {
+                let name = name.as_str();
+                (!self.allow.is_empty() && !self.allow.iter().any(|p| matches(p, name)))
+                    || self.deny.iter().any(|p| matches(p, name))
+            })
+            .cloned()
+            .collect();
+        for name in removed {
+            headers.remove(name);
+        }
+
+        for (name, value) in &self.set {
+            // checked when the manifest was loaded
+            if let (Ok(name), Ok(value)) = (
+                HeaderName::try_from(name.as_str()),
+                HeaderValue::try_from(value.as_str()),
+            ) {
+                headers.insert(name, value);
+            }
+        }
+    }
+}
+
+impl ServiceManifest {
+    // the manifest of the service, the default one if it has none
+    pub fn load(service_path: &Path) -> Result<Self, Error> {
+        let path = service_path.join(SERVICE_MANIFEST_NAME);
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+
+        let manifest: ServiceManifest = serde_json::from_slice(
+            &fs::read(&path).with_context(|| format!("failed to read {:?}", path))?,
+        )
+        .with_context(|| format!("invalid service manifest {:?}", path))?;
+        manifest
+            .headers
+            .request
+            .validate()
+            .and_then(|_| manifest.headers.response.validate())
+            .and_then(|_| manifest.cors.as_ref().map_or(Ok(()), CorsConfig::validate))
+            .and_then(|_| manifest.validate_methods())
+            .and_then(|_| manifest.compat_flags().map(|_| ()))
+            .with_context(|| format!("invalid service manifest {:?}", path))?;
+        Ok(manifest)
+    }
+
+    // the behaviour changes its workers get
+    pub fn compat_flags(&self) -> Result<Vec<CompatFlag>, Error> {
+        compat::compat_flags(
+            self.compatibility_date.as_deref(),
+            &self.compatibility_flags,
+        )
+    }
+
+    fn validate_methods(&self) -> Result<(), Error> {
+        for method in self.methods.iter().flatten() {
+            if Method::from_bytes(method.as_bytes()).is_err() {
+                bail!("invalid method {}", method);
+            }
+        }
+        Ok(())
+    }
+
+    fn allows_method(&self, method: &Method) -> bool {
+        let Some(methods) = &self.methods else {
+            return true;
+        };
+        // a GET handler answers HEAD requests too
+        methods.iter().any(|allowed| {
+            allowed.eq_ignore_ascii_case(method.as_str())
+                || (method == Method::HEAD && allowed.eq_ignore_ascii_case("GET"))
+        })
+    }
+
+    // The response the runtime gives to a request to the service, without
+    // booting a worker: clients it doesn't serve, CORS preflights and methods
+    // it doesn't handle. `None` if the request goes to a worker.
+    pub fn answer(&self, req: &Request<Body>) -> Option<Response<Body>> {
+        if !self.client_ips.is_empty() {
+            let client_ip = req
+                .headers()
+                .get(CLIENT_IP_HEADER)
+                .and_then(|value| value.to_str().ok())
+                .and_then(|value| value.parse().ok());
+            if !self.client_ips.allows(client_ip) {
+                return Some(error_response(403, "forbidden"));
+            }
+        }
+        if let Some(cors) = self.cors.as_ref().filter(|_| cors::is_preflight(req)) {
+            return Some(cors.preflight(req.headers()));
+        }
+        if self.allows_method(req.method()) {
+            return None;
+        }
+
+        let mut res = error_response(405, "method not allowed");
+        let methods = self.methods.as_deref().unwrap_or_default().join(", ");
+        if let Ok(allow) = HeaderValue::from_str(&methods) {
+            res.headers_mut().insert(ALLOW, allow);
+        }
+        // lets the browser's scripts read the error
+        if let Some(cors) = &self.cors {
+            cors.apply(req.headers().get(ORIGIN), res.headers_mut());
+        }
+        Some(res)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn headers(pairs: &[(&'static str, &'static str)]) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        for (name, value) in pairs {
+            headers.insert(*name, HeaderValue::from_static(value));
+        }
+        headers
+    }
+
+    #[test]
+    fn test_deny_and_set() {
+        let filter = HeaderFilter {
+            deny: vec!["x-internal-*".to_string(), "Cookie".to_string()],
+            set: HashMap::from([("x-frame-options".to_string(), "DENY".to_string())]),
+            ..Default::default()
+        };
+        let mut h = headers(&[
+            ("x-internal-auth", "secret"),
+            ("cookie", "a=1"),
+            ("content-type", "text/plain"),
+            ("x-frame-options", "SAMEORIGIN"),
+        ]);
+        filter.apply(&mut h);
+
+        assert!(h.get("x-internal-auth").is_none());
+        assert!(h.get("cookie").is_none());
+        assert_eq!(h.get("content-type").unwrap(), "text/plain");
+        assert_eq!(h.get("x-frame-options").unwrap(), "DENY");
+    }
+
+    #[test]
+    fn test_allow() {
+        let filter = HeaderFilter {
+            allow: vec!["content-*".to_string(), "accept".to_string()],
+            deny: vec!["content-encoding".to_string()],
+            ..Default::default()
+        };
+        let mut h = headers(&[
+            ("content-type", "text/plain"),
+            ("content-encoding", "gzip"),
+            ("accept", "*/*"),
+            ("authorization", "Bearer secret"),
+        ]);
+        filter.apply(&mut h);
+
+        let mut names: Vec<&str> = h.keys().map(|name| name.as_str()).collect();
+        names.sort();
+        assert_eq!(names, vec!["accept", "content-type"]);
+    }
+
+    #[test]
+    fn test_load() {
+        let manifest = ServiceManifest::load(Path::new("./test_cases/manifest")).unwrap();
+        assert_eq!(manifest.headers.request.deny, vec!["x-internal-*"]);
+        assert_eq!(
+            manifest
+                .headers
+                .response
+                .set
+                .get("strict-transport-security")
+                .unwrap(),
+            "max-age=63072000"
+        );
+        let cors = manifest.cors.unwrap();
+        assert_eq!(cors.origins, vec!["https://app.example.com"]);
+        assert_eq!(cors.max_age, Some(600));
+        assert_eq!(CorsConfig::default().methods, cors.methods);
+        assert_eq!(
+            manifest.methods,
+            Some(vec!["GET".to_string(), "POST".to_string()])
+        );
+
+        // services without one
+        let manifest = ServiceManifest::load(Path::new("./test_cases/tester")).unwrap();
+        assert_eq!(manifest, ServiceManifest::default());
+
+        // cookies for any origin
+        let err =
+            ServiceManifest::load(Path::new("./test_cases/manifest_cors_credentials")).unwrap_err();
+        assert!(format!("{:#}", err).contains("cors credentials"));
+    }
+
+    #[test]
+    fn test_capabilities() {
+        let manifest: ServiceManifest =
+            serde_json::from_str(r#"{ "capabilities": ["net"] }"#).unwrap();
+        assert_eq!(manifest.capabilities, vec![Capability::Net]);
+        assert!(ServiceManifest::default().capabilities.is_empty());
+        assert!(serde_json::from_str::<ServiceManifest>(r#"{ "capabilities": ["fs"] }"#).is_err());
+    }
+
+    #[test]
+    fn test_compatibility_date() {
+        let manifest: ServiceManifest = serde_json::from_str(
+            r#"{ "compatibilityDate": "2026-09-15", "compatibilityFlags": ["restrict_key_export"] }"#,
+        )
+        .unwrap();
+        assert_eq!(
+            manifest.compat_flags().unwrap(),
+            vec![
+                CompatFlag::UnhandledRejectionTerminates,
+                CompatFlag::RestrictKeyExport
+            ]
+        );
+        assert!(ServiceManifest::default()
+            .compat_flags()
+            .unwrap()
+            .is_empty());
+    }
+
+    #[test]
+    fn test_answer() {
+        let manifest = ServiceManifest::load(Path::new("./test_cases/manifest")).unwrap();
+        let request = |method: Method| {
+            Request::builder()
+                .method(method)
+                .uri("http://localhost/manifest")
+                .header(ORIGIN, "https://app.example.com")
+                .body(Body::empty())
+                .unwrap()
+        };
+
+        for method in [Method::GET, Method::HEAD, Method::POST] {
+            assert!(manifest.answer(&request(method)).is_none());
+        }
+
+        let res = manifest.answer(&request(Method::DELETE)).unwrap();
+        assert_eq!(res.status(), 405);
+        assert_eq!(res.headers().get(ALLOW).unwrap(), "GET, POST");
+        assert_eq!(
+            res.headers()
+                .get(hyper::header::ACCESS_CONTROL_ALLOW_ORIGIN)
+                .unwrap(),
+            "https://app.example.com"
+        );
+
+        // preflights are answered from the cors settings, whatever the methods
+        let mut preflight = request(Method::OPTIONS);
+        preflight.headers_mut().insert(
+            hyper::header::ACCESS_CONTROL_REQUEST_METHOD,
+            HeaderValue::from_static("POST"),
+        );
+        assert_eq!(manifest.answer(&preflight).unwrap().status(), 204);
+
+        // services without methods handle all of them
+        let manifest = ServiceManifest::default();
+        assert!(manifest.answer(&request(Method::DELETE)).is_none());
+    }
+
+    #[test]
+    fn test_client_ips() {
+        let manifest: ServiceManifest = serde_json::from_str(
+            r#"{ "clientIps": { "allow": ["203.0.113.0/24", "2001:db8::/32"], "deny": ["203.0.113.66"] } }"#,
+        )
+        .unwrap();
+        let request = |client_ip: Option<&'static str>| {
+            let mut req = Request::get("http://localhost/manifest")
+                .body(Body::empty())
+                .unwrap();
+            if let Some(ip) = client_ip {
+                req.headers_mut()
+                    .insert(CLIENT_IP_HEADER, HeaderValue::from_static(ip));
+            }
+            req
+        };
+
+        assert!(manifest.answer(&request(Some("203.0.113.7"))).is_none());
+        assert!(manifest.answer(&request(Some("2001:db8::1"))).is_none());
+        for client_ip in [Some("203.0.113.66"), Some("198.51.100.1"), None] {
+            let res = manifest.answer(&request(client_ip)).unwrap();
+            assert_eq!(res.status(), 403);
+        }
+
+        // a denylist alone serves the clients it doesn't list
+        let manifest: ServiceManifest =
+            serde_json::from_str(r#"{ "clientIps": { "deny": ["198.51.100.0/24"] } }"#).unwrap();
+        assert!(manifest.answer(&request(Some("203.0.113.7"))).is_none());
+        assert!(manifest.answer(&request(None)).is_none());
+        assert!(manifest.answer(&request(Some("198.51.100.1"))).is_some());
+
+        assert!(serde_json::from_str::<ServiceManifest>(
+            r#"{ "clientIps": { "allow": ["203.0.113.0/33"] } }"#
+        )
+        .is_err());
+    }
+}
@@ -0,0 +1,283 @@
+use anyhow::{bail, Context, Error};
+use once_cell::sync::OnceCell;
+use std::collections::HashMap;
+use std::fmt::Debug;
+use std::net::{SocketAddr, ToSocketAddrs, UdpSocket};
+use std::str::FromStr;
+use std::sync::Arc;
+
+static METRICS_EXPORTER: OnceCell<Arc<dyn MetricsExporter>> = OnceCell::new();
+static METRICS_ENDPOINT: OnceCell<()> = OnceCell::new();
+
+const DEFAULT_STATSD_PORT: u16 = 8125;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum MetricKind {
+    Counter,
+    Gauge,
+    // in ms
+    Timing,
+}
+
+#[derive(Debug)]
+pub struct Metric<'a> {
+    pub name: &'static str,
+    pub kind: MetricKind,
+    pub value: f64,
+    // eg: `service`, `deployment` and `worker`
+    pub tags: &'a [(&'static str, &'a str)],
+}
+
+// Pushes the metrics of the pool somewhere, for environments that don't
+// scrape `/_internal/metrics`. Called from the pool, so it mustn't block.
+pub trait MetricsExporter: Debug + Send + Sync {
+    fn export(&self, metric: &Metric);
+}
+
+// Exports the metrics of the pool with `exporter`. Must be called before
+// the server starts.
+pub fn init_metrics_exporter(exporter: Arc<dyn MetricsExporter>) -> Result<(), Error> {
+    if METRICS_EXPORTER.set(exporter).is_err() {
+        bail!("the metrics exporter is already set up");
+    }
+    Ok(())
+}
+
+// Serves the metrics of the server on `/_internal/metrics`, which tell the
+// hosts workers fetch from. Must be called before the server starts.
+pub fn init_metrics_endpoint() -> Result<(), Error> {
+    if METRICS_ENDPOINT.set(()).is_err() {
+        bail!("the metrics endpoint is already on");
+    }
+    Ok(())
+}
+
+pub(crate) fn metrics_endpoint_enabled() -> bool {
+    METRICS_ENDPOINT.get().is_some()
+}
+
+pub(crate) fn metrics_enabled() -> bool {
+    METRICS_EXPORTER.get().is_some()
+}
+
+pub(crate) fn record(
+    name: &'static str,
+    kind: MetricKind,
+    value: f64,
+    tags: &[(&'static str, &str)],
+) {
+    if let Some(exporter) = METRICS_EXPORTER.get() {
+        exporter.export(&Metric {
+            name,
+            kind,
+            value,
+            tags,
+        });
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum StatsdFlavor {
+    // tags are appended to the name, eg: `edge_runtime.requests.service.hello`
+    Statsd,
+    // tags are sent as dogstatsd tags, eg: `|#service:hello`
+    Dogstatsd,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct StatsdOpts {
+    pub addr: String,
+    pub flavor: StatsdFlavor,
+    pub prefix: String,
+    // renames the tags, those renamed to "" are left out, eg: `worker` to
+    // "" to drop the worker ids
+    pub tag_names: HashMap<String, String>,
+}
+
+impl FromStr for StatsdOpts {
+    type Err = Error;
+
+    // statsd://<host[:port]> or dogstatsd://<host[:port]>
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (flavor, addr) = if let Some(addr) = s.strip_prefix("statsd://") {
+            (StatsdFlavor::Statsd, addr)
+        } else if let Some(addr) = s.strip_prefix("dogstatsd://") {
+            (StatsdFlavor::Dogstatsd, addr)
+        } else {
+            bail!(
+                "invalid metrics exporter {:?}, expected statsd://<host> or dogstatsd://<host>",
+                s
+            );
+        };
+        let addr = addr.trim_end_matches('/');
+        let addr = match addr.rsplit_once(':') {
+            Some((_, port)) if port.parse::<u16>().is_ok() => addr.to_string(),
+            _ => format!("{}:{}", addr, DEFAULT_STATSD_PORT),
+        };
+
+        Ok(Self {
+            addr,
+            flavor,
+            prefix: "edge_runtime".to_string(),
+            tag_names: HashMap::new(),
+        })
+    }
+}
+
+// what statsd names and dogstatsd tags can have
+fn sanitize(value: &str, keep_dots: bool) -> String {
+    value
+        .chars()
+        .map(|c| match c {
+            'a'..='z' | 'A'..='Z' | '0'..='9' | '_' | '-' | '/' => c,
+            '.' if keep_dots => c,
+            _ => '_',
+        })
+        .collect()
+}
+
+// Sends the metrics over UDP as statsd or dogstatsd lines, one per datagram.
+// Lines are dropped while the agent can't take them.
+#[derive(Debug)]
+pub struct StatsdExporter {
+    socket: UdpSocket,
+    opts: StatsdOpts,
+}
+
+impl StatsdExporter {
+    pub fn new(opts: StatsdOpts) -> Result<Self, Error> {
+        let addr = opts
+            .addr
+            .to_socket_addrs()
+            .ok()
+            .and_then(|mut addrs| addrs.next())
+            .with_context(|| format!("failed to resolve the statsd agent {}", opts.addr))?;
+        let socket = match addr {
+            SocketAddr::V4(_) => UdpSocket::bind("0.0.0.0:0")?,
+            SocketAddr::V6(_) => UdpSocket::bind("[::]:0")?,
+        };
+        socket.connect(addr)?;
+        socket.set_nonblocking(true)?;
+        Ok(Self { socket, opts })
+    }
+
+    fn line(&self, metric: &Metric) -> String {
+        let tags = metric.tags.iter().filter_map(|(name, value)| {
+            let name = self
+                .opts
+                .tag_names
+                .get(*name)
+                .map(String::as_str)
+                .unwrap_or(name);
+            (!name.is_empty()).then_some((name, *value))
+        });
+
+        let mut name = match self.opts.prefix.as_str() {
+            "" => metric.name.to_string(),
+            prefix => format!("{}.{}", prefix, metric.name),
+        };
+        let mut dog_tags = vec![];
+        for (tag, value) in tags {
+            match self.opts.flavor {
+                StatsdFlavor::Statsd => {
+                    name.push_str(&format!(
+                        ".{}.{}",
+                        sanitize(tag, false),
+                        sanitize(value, false)
+                    ));
+                }
+                StatsdFlavor::Dogstatsd => {
+                    dog_tags.push(format!("{}:{}", sanitize(tag, true), sanitize(value, true)));
+                }
+            }
+        }
+
+        let kind = match metric.kind {
+            MetricKind::Counter => "c",
+            MetricKind::Gauge => "g",
+            MetricKind::Timing => "ms",
+        };
+        let mut line = format!("{}:{}|{}", name, metric.value, kind);
+        if !dog_tags.is_empty() {
+            line.push_str("|#");
+            line.push_str(&dog_tags.join(","));
+        }
+        line
+    }
+}
+
+impl MetricsExporter for StatsdExporter {
+    fn export(&self, metric: &Metric) {
+        let _ = self.socket.send(self.line(metric).as_bytes());
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::time::Duration;
+
+    fn request_metric<'a>(tags: &'a [(&'static str, &'a str)]) -> Metric<'a> {
+        Metric {
+            name: "request_duration_ms",
+            kind: MetricKind::Timing,
+            value: 12.0,
+            tags,
+        }
+    }
+
+    #[test]
+    fn test_statsd_lines() {
+        let tags = [
+            ("service", "./functions/hello"),
+            ("deployment", "v2"),
+            ("worker", "0b6f"),
+        ];
+
+        let mut opts: StatsdOpts = "dogstatsd://127.0.0.1".parse().unwrap();
+        assert_eq!(opts.addr, "127.0.0.1:8125");
+        opts.tag_names
+            .insert("service".to_string(), "function".to_string());
+        opts.tag_names.insert("worker".to_string(), "".to_string());
+        let exporter = StatsdExporter::new(opts).unwrap();
+        assert_eq!(
+            exporter.line(&request_metric(&tags)),
+            "edge_runtime.request_duration_ms:12|ms|#function:./functions/hello,deployment:v2"
+        );
+
+        let exporter = StatsdExporter::new("statsd://127.0.0.1:9125".parse().unwrap()).unwrap();
+        assert_eq!(
+            exporter.line(&request_metric(&tags[..1])),
+            "edge_runtime.request_duration_ms.service.__functions_hello:12|ms"
+        );
+
+        assert!("prometheus://127.0.0.1".parse::<StatsdOpts>().is_err());
+    }
+
+    #[test]
+    fn test_statsd_export() {
+        let agent = UdpSocket::bind("127.0.0.1:0").unwrap();
+        agent
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        let exporter = StatsdExporter::new(
+            format!("dogstatsd://{}", agent.local_addr().unwrap())
+                .parse()
+                .unwrap(),
+        )
+        .unwrap();
+
+        exporter.export(&Metric {
+            name: "worker_boots",
+            kind: MetricKind::Counter,
+            value: 1.0,
+            tags: &[("service", "hello")],
+        });
+        let mut buf = [0; 512];
+        let len = agent.recv(&mut buf).unwrap();
+        assert_eq!(
+            std::str::from_utf8(&buf[..len]).unwrap(),
+            "edge_runtime.worker_boots:1|c|#service:hello"
+        );
+    }
+}
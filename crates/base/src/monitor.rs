@@ -0,0 +1,60 @@
+use once_cell::sync::Lazy;
+use std::future::Future;
+use std::sync::mpsc;
+use std::thread;
+use tokio::runtime::Handle;
+
+static MONITOR: Lazy<Handle> = Lazy::new(start_monitor);
+
+// One thread watches the wall clock limits, memory alerts and event loops of
+// every isolate. Deadlines are timers of its runtime, kept in a single timer
+// wheel, instead of a thread and runtime sleeping for each isolate.
+fn start_monitor() -> Handle {
+    let (handle_tx, handle_rx) = mpsc::channel();
+    thread::Builder::new()
+        .name("worker-monitor".to_string())
+        .spawn(move || {
+            let rt = tokio::runtime::Builder::new_current_thread()
+                .enable_all()
+                .build()
+                .unwrap();
+            let _ = handle_tx.send(rt.handle().clone());
+            rt.block_on(std::future::pending::<()>());
+        })
+        .expect("failed to start the worker monitor thread");
+    handle_rx.recv().unwrap()
+}
+
+// Runs `monitor` on the monitor thread. It mustn't block, every isolate's
+// limits are enforced from there.
+pub fn monitor_isolate<F>(monitor: F)
+where
+    F: Future<Output = ()> + Send + 'static,
+{
+    MONITOR.spawn(monitor);
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use tokio::sync::oneshot;
+
+    #[tokio::test]
+    async fn test_monitors_share_a_thread() {
+        let mut threads = vec![];
+        for _ in 0..3 {
+            let (tx, rx) = oneshot::channel();
+            monitor_isolate(async move {
+                tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+                let _ = tx.send(thread::current().id());
+            });
+            threads.push(rx);
+        }
+
+        let first = threads.remove(0).await.unwrap();
+        assert_ne!(first, thread::current().id());
+        for rx in threads {
+            assert_eq!(rx.await.unwrap(), first);
+        }
+    }
+}
@@ -0,0 +1,271 @@
+use anyhow::{Context, Error};
+use hyper::header::{HeaderMap, HeaderName, HeaderValue};
+use serde::{Deserialize, Deserializer};
+use std::net::IpAddr;
+use std::str::FromStr;
+
+// set by the server on every request, with the client's address
+pub const CLIENT_IP_HEADER: &str = "x-edge-runtime-client-ip";
+
+const FORWARDED_FOR: &str = "x-forwarded-for";
+const FORWARDED: &str = "forwarded";
+const REAL_IP: &str = "x-real-ip";
+
+// An address or a network (eg: 10.0.0.0/8, fd00::/8).
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct IpNetwork {
+    addr: IpAddr,
+    prefix_len: u8,
+}
+
+impl FromStr for IpNetwork {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (addr, prefix_len) = match s.split_once('/') {
+            Some((addr, prefix_len)) => (addr, Some(prefix_len)),
+            None => (s, None),
+        };
+        let addr = IpAddr::from_str(addr).with_context(|| format!("invalid address {}", s))?;
+        let max_len = if addr.is_ipv4() { 32 } else { 128 };
+        let prefix_len = match prefix_len {
+            Some(len) => len
+                .parse::<u8>()
+                .ok()
+                .filter(|len| *len <= max_len)
+                .with_context(|| format!("invalid prefix length in {}", s))?,
+            None => max_len,
+        };
+        Ok(Self { addr, prefix_len })
+    }
+}
+
+impl<'de> Deserialize<'de> for IpNetwork {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        String::deserialize(deserializer)?
+            .parse()
+            .map_err(serde::de::Error::custom)
+    }
+}
+
+impl IpNetwork {
+    pub fn contains(&self, ip: IpAddr) -> bool {
+        match (self.addr, canonical(ip)) {
+            (IpAddr::V4(net), IpAddr::V4(ip)) => {
+                let mask = u32::MAX
+                    .checked_shl(32 - self.prefix_len as u32)
+                    .unwrap_or(0);
+                u32::from(net) & mask == u32::from(ip) & mask
+            }
+            (IpAddr::V6(net), IpAddr::V6(ip)) => {
+                let mask = u128::MAX
+                    .checked_shl(128 - self.prefix_len as u32)
+                    .unwrap_or(0);
+                u128::from(net) & mask == u128::from(ip) & mask
+            }
+            _ => false,
+        }
+    }
+}
+
+// IPv4 clients of a dual stack listener show up as ::ffff:a.b.c.d
+fn canonical(ip: IpAddr) -> IpAddr {
+    match ip {
+        IpAddr::V6(v6) => v6.to_ipv4_mapped().map_or(ip, IpAddr::V4),
+        ip => ip,
+    }
+}
+
+// The proxies in front of the server, whose `X-Forwarded-For` and
+// `Forwarded` headers are believed. Other peers are the clients themselves.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct TrustedProxies {
+    networks: Vec<IpNetwork>,
+}
+
+impl TrustedProxies {
+    pub fn new(networks: Vec<IpNetwork>) -> Self {
+        Self { networks }
+    }
+
+    pub fn parse(networks: &[String]) -> Result<Self, Error> {
+        let networks = networks
+            .iter()
+            .map(|network| network.parse())
+            .collect::<Result<Vec<IpNetwork>, Error>>()?;
+        Ok(Self::new(networks))
+    }
+
+    pub fn is_trusted(&self, ip: IpAddr) -> bool {
+        self.networks.iter().any(|network| network.contains(ip))
+    }
+
+    // The addresses the request went through before reaching `peer`, from the
+    // client to the last proxy.
+    fn forwarded_chain(headers: &HeaderMap) -> Vec<IpAddr> {
+        let forwarded_for: Vec<IpAddr> = headers
+            .get_all(FORWARDED_FOR)
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .flat_map(|value| value.split(','))
+            .filter_map(|ip| parse_node(ip.trim()))
+            .collect();
+        if !forwarded_for.is_empty() {
+            return forwarded_for;
+        }
+
+        // Forwarded: for=192.0.2.60;proto=http, for="[2001:db8::1]:4711"
+        headers
+            .get_all(FORWARDED)
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .flat_map(|value| value.split(','))
+            .filter_map(|element| {
+                element.split(';').find_map(|pair| {
+                    let (name, value) = pair.trim().split_once('=')?;
+                    name.eq_ignore_ascii_case("for")
+                        .then(|| parse_node(value.trim_matches('"')))
+                        .flatten()
+                })
+            })
+            .collect()
+    }
+
+    // The first address that isn't a trusted proxy, going back from `peer`.
+    pub fn client_ip(&self, peer: IpAddr, headers: &HeaderMap) -> IpAddr {
+        if !self.is_trusted(peer) {
+            return canonical(peer);
+        }
+
+        let chain = Self::forwarded_chain(headers);
+        chain
+            .iter()
+            .rev()
+            .find(|ip| !self.is_trusted(**ip))
+            // every hop is a proxy, the first one is as close to the client as it gets
+            .or_else(|| chain.first())
+            .copied()
+            .map_or(canonical(peer), canonical)
+    }
+
+    // Sets the client's address, and drops the forwarding headers of peers
+    // that aren't trusted, so workers can't be fooled by spoofed ones.
+    // Returns the client's address.
+    pub fn apply(&self, peer: IpAddr, headers: &mut HeaderMap) -> IpAddr {
+        let client_ip = self.client_ip(peer, headers);
+        let peer = canonical(peer);
+
+        // proxies append the address they got the request from
+        let mut forwarded_for = peer.to_string();
+        if self.is_trusted(peer) {
+            let chain: Vec<&str> = headers
+                .get_all(FORWARDED_FOR)
+                .iter()
+                .filter_map(|value| value.to_str().ok())
+                .collect();
+            if !chain.is_empty() {
+                forwarded_for = format!("{}, {}", chain.join(", "), forwarded_for);
+            }
+        } else {
+            headers.remove(FORWARDED);
+            headers.remove(REAL_IP);
+        }
+
+        if let Ok(value) = HeaderValue::from_str(&forwarded_for) {
+            headers.insert(HeaderName::from_static(FORWARDED_FOR), value);
+        }
+        if let Ok(value) = HeaderValue::from_str(&client_ip.to_string()) {
+            headers.insert(HeaderName::from_static(CLIENT_IP_HEADER), value);
+        }
+        client_ip
+    }
+}
+
+// an address of X-Forwarded-For or Forwarded, which may have a port
+fn parse_node(node: &str) -> Option<IpAddr> {
+    if let Ok(ip) = IpAddr::from_str(node) {
+        return Some(ip);
+    }
+    // [2001:db8::1]:4711
+    if let Some(rest) = node.strip_prefix('[') {
+        return rest.split(']').next()?.parse().ok();
+    }
+    // 192.0.2.60:4711
+    node.split_once(':')?.0.parse().ok()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn proxies() -> TrustedProxies {
+        TrustedProxies::parse(&["10.0.0.0/8".to_string(), "fd00::1".to_string()]).unwrap()
+    }
+
+    fn ip(s: &str) -> IpAddr {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn test_networks() {
+        let network: IpNetwork = "10.1.0.0/16".parse().unwrap();
+        assert!(network.contains(ip("10.1.2.3")));
+        assert!(network.contains(ip("::ffff:10.1.2.3")));
+        assert!(!network.contains(ip("10.2.0.1")));
+
+        let network: IpNetwork = "0.0.0.0/0".parse().unwrap();
+        assert!(network.contains(ip("203.0.113.7")));
+
+        assert!("10.0.0.0/33".parse::<IpNetwork>().is_err());
+        assert!("proxy.internal".parse::<IpNetwork>().is_err());
+    }
+
+    #[test]
+    fn test_untrusted_peer() {
+        let mut headers = HeaderMap::new();
+        headers.insert(FORWARDED_FOR, HeaderValue::from_static("1.2.3.4"));
+        headers.insert(FORWARDED, HeaderValue::from_static("for=1.2.3.4"));
+        headers.insert(CLIENT_IP_HEADER, HeaderValue::from_static("1.2.3.4"));
+
+        proxies().apply(ip("203.0.113.7"), &mut headers);
+        assert_eq!(headers.get(CLIENT_IP_HEADER).unwrap(), "203.0.113.7");
+        assert_eq!(headers.get(FORWARDED_FOR).unwrap(), "203.0.113.7");
+        assert!(headers.get(FORWARDED).is_none());
+    }
+
+    #[test]
+    fn test_trusted_peer() {
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            FORWARDED_FOR,
+            HeaderValue::from_static("1.2.3.4, 203.0.113.7, 10.0.0.2"),
+        );
+
+        proxies().apply(ip("10.0.0.1"), &mut headers);
+        // 1.2.3.4 was added by the client, the first proxy saw 203.0.113.7
+        assert_eq!(headers.get(CLIENT_IP_HEADER).unwrap(), "203.0.113.7");
+        assert_eq!(
+            headers.get(FORWARDED_FOR).unwrap(),
+            "1.2.3.4, 203.0.113.7, 10.0.0.2, 10.0.0.1"
+        );
+    }
+
+    #[test]
+    fn test_forwarded() {
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            FORWARDED,
+            HeaderValue::from_static(r#"for="[2001:db8::1]:4711";proto=https, for=10.0.0.2"#),
+        );
+        assert_eq!(
+            proxies().client_ip(ip("fd00::1"), &headers),
+            ip("2001:db8::1")
+        );
+
+        // without forwarding headers, the proxy is all there is
+        assert_eq!(
+            proxies().client_ip(ip("10.0.0.1"), &HeaderMap::new()),
+            ip("10.0.0.1")
+        );
+    }
+}
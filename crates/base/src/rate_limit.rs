@@ -0,0 +1,246 @@
+use crate::proxy::CLIENT_IP_HEADER;
+use anyhow::{bail, Error};
+use deno_core::serde_json;
+use std::collections::HashMap;
+use std::str::FromStr;
+use std::time::{Duration, Instant};
+
+// buckets kept before the full ones are dropped
+const MAX_IDLE_BUCKETS: usize = 10_000;
+
+// What a rate limit bucket is keyed by, besides the service.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RateLimitKey {
+    Service,
+    // client address the server derived (see `TrustedProxies`), or the first
+    // one of `x-forwarded-for` or `x-real-ip` of the request
+    Ip,
+    // `sub` claim of the bearer token, the token is not verified
+    JwtSubject,
+}
+
+impl FromStr for RateLimitKey {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "service" => Ok(RateLimitKey::Service),
+            "ip" => Ok(RateLimitKey::Ip),
+            "jwt-sub" => Ok(RateLimitKey::JwtSubject),
+            _ => bail!(
+                "unknown rate limit key {}, expected service, ip or jwt-sub",
+                s
+            ),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct RateLimitOpts {
+    pub requests_per_sec: f64,
+    // requests allowed at once, after being idle
+    pub burst: u32,
+    pub key: RateLimitKey,
+}
+
+#[derive(Debug)]
+struct TokenBucket {
+    tokens: f64,
+    updated_at: Instant,
+}
+
+// Token buckets limiting how often user workers are created for a service,
+// so a single noisy service can't take over the node.
+#[derive(Debug)]
+pub struct RateLimiter {
+    opts: RateLimitOpts,
+    buckets: HashMap<String, TokenBucket>,
+}
+
+fn caller_ip(headers: &HashMap<String, String>) -> Option<&str> {
+    headers
+        .get(CLIENT_IP_HEADER)
+        .map(|value| value.as_str())
+        .or_else(|| {
+            headers
+                .get("x-forwarded-for")
+                .and_then(|value| value.split(',').next())
+        })
+        .or_else(|| headers.get("x-real-ip").map(|value| value.as_str()))
+        .map(|ip| ip.trim())
+        .filter(|ip| !ip.is_empty())
+}
+
+fn jwt_subject(headers: &HashMap<String, String>) -> Option<String> {
+    let token = headers.get("authorization")?.strip_prefix("Bearer ")?;
+    let payload = token.split('.').nth(1)?;
+    let payload = base64::decode_config(payload, base64::URL_SAFE_NO_PAD).ok()?;
+    let claims: serde_json::Value = serde_json::from_slice(&payload).ok()?;
+    claims.get("sub")?.as_str().map(|sub| sub.to_string())
+}
+
+impl RateLimiter {
+    pub fn new(opts: RateLimitOpts) -> Self {
+        Self {
+            opts,
+            buckets: HashMap::new(),
+        }
+    }
+
+    // The buckets are kept unless they're keyed differently, with no more
+    // tokens than the new burst.
+    pub fn set_opts(&mut self, opts: RateLimitOpts) {
+        if opts.key != self.opts.key {
+            self.buckets.clear();
+        }
+        let capacity = opts.burst.max(1) as f64;
+        for bucket in self.buckets.values_mut() {
+            bucket.tokens = bucket.tokens.min(capacity);
+        }
+        self.opts = opts;
+    }
+
+    // Requests without the caller's ip or token share the service's bucket.
+    pub fn bucket_key(&self, service: &str, headers: &HashMap<String, String>) -> String {
+        let caller = match self.opts.key {
+            RateLimitKey::Service => None,
+            RateLimitKey::Ip => caller_ip(headers).map(|ip| ip.to_string()),
+            RateLimitKey::JwtSubject => jwt_subject(headers),
+        };
+
+        match caller {
+            Some(caller) => format!("{} ({})", service, caller),
+            None => service.to_string(),
+        }
+    }
+
+    // Takes a token from the bucket, or returns how long to wait for one.
+    pub fn check(&mut self, key: &str, now: Instant) -> Result<(), Duration> {
+        let capacity = self.opts.burst.max(1) as f64;
+        let rate = self.opts.requests_per_sec;
+
+        if self.buckets.len() >= MAX_IDLE_BUCKETS {
+            self.buckets.retain(|_, bucket| {
+                let elapsed = now.saturating_duration_since(bucket.updated_at);
+                bucket.tokens + elapsed.as_secs_f64() * rate < capacity
+            });
+        }
+
+        let bucket = self
+            .buckets
+            .entry(key.to_string())
+            .or_insert_with(|| TokenBucket {
+                tokens: capacity,
+                updated_at: now,
+            });
+
+        let elapsed = now.saturating_duration_since(bucket.updated_at);
+        bucket.tokens = (bucket.tokens + elapsed.as_secs_f64() * rate).min(capacity);
+        bucket.updated_at = now;
+
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            return Ok(());
+        }
+
+        if rate <= 0.0 {
+            return Err(Duration::MAX);
+        }
+        Err(Duration::from_secs_f64((1.0 - bucket.tokens) / rate))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_token_bucket() {
+        let mut limiter = RateLimiter::new(RateLimitOpts {
+            requests_per_sec: 2.0,
+            burst: 2,
+            key: RateLimitKey::Service,
+        });
+        let now = Instant::now();
+
+        assert!(limiter.check("hello", now).is_ok());
+        assert!(limiter.check("hello", now).is_ok());
+        assert_eq!(limiter.check("hello", now), Err(Duration::from_millis(500)));
+        // other services have their own bucket
+        assert!(limiter.check("world", now).is_ok());
+
+        assert!(limiter
+            .check("hello", now + Duration::from_millis(500))
+            .is_ok());
+        assert!(limiter
+            .check("hello", now + Duration::from_millis(500))
+            .is_err());
+    }
+
+    #[test]
+    fn test_set_opts() {
+        let mut limiter = RateLimiter::new(RateLimitOpts {
+            requests_per_sec: 1.0,
+            burst: 5,
+            key: RateLimitKey::Service,
+        });
+        let now = Instant::now();
+        assert!(limiter.check("hello", now).is_ok());
+
+        // the bucket is capped at the new burst
+        limiter.set_opts(RateLimitOpts {
+            requests_per_sec: 1.0,
+            burst: 1,
+            key: RateLimitKey::Service,
+        });
+        assert!(limiter.check("hello", now).is_ok());
+        assert!(limiter.check("hello", now).is_err());
+    }
+
+    #[test]
+    fn test_bucket_keys() {
+        let headers = HashMap::from([
+            (
+                "x-forwarded-for".to_string(),
+                "203.0.113.7, 10.0.0.1".to_string(),
+            ),
+            (
+                "authorization".to_string(),
+                // {"alg":"HS256"}.{"sub":"user-1"}.signature
+                "Bearer eyJhbGciOiJIUzI1NiJ9.eyJzdWIiOiJ1c2VyLTEifQ.c2ln".to_string(),
+            ),
+        ]);
+
+        let by = |key| {
+            RateLimiter::new(RateLimitOpts {
+                requests_per_sec: 1.0,
+                burst: 1,
+                key,
+            })
+        };
+        assert_eq!(
+            by(RateLimitKey::Service).bucket_key("hello", &headers),
+            "hello"
+        );
+        assert_eq!(
+            by(RateLimitKey::Ip).bucket_key("hello", &headers),
+            "hello (203.0.113.7)"
+        );
+        assert_eq!(
+            by(RateLimitKey::JwtSubject).bucket_key("hello", &headers),
+            "hello (user-1)"
+        );
+        assert_eq!(
+            by(RateLimitKey::JwtSubject).bucket_key("hello", &HashMap::new()),
+            "hello"
+        );
+        assert!("jwt".parse::<RateLimitKey>().is_err());
+
+        let mut headers = headers;
+        headers.insert(CLIENT_IP_HEADER.to_string(), "198.51.100.2".to_string());
+        assert_eq!(
+            by(RateLimitKey::Ip).bucket_key("hello", &headers),
+            "hello (198.51.100.2)"
+        );
+    }
+}
@@ -0,0 +1,50 @@
+use crate::maintenance::{set_maintenance, MaintenanceOpts};
+use crate::rate_limit::RateLimitOpts;
+use crate::scheduler::SchedulerOpts;
+use crate::waf::{set_waf_rules, WafRules};
+use anyhow::{bail, Error};
+use log::LevelFilter;
+use once_cell::sync::OnceCell;
+
+type TunablesLoader = Box<dyn Fn() -> Result<Tunables, Error> + Send + Sync>;
+
+static TUNABLES_LOADER: OnceCell<TunablesLoader> = OnceCell::new();
+
+// The settings that can change while the server runs. They apply to the
+// workers created after they're reloaded, the running ones keep theirs.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Tunables {
+    pub rate_limit: Option<RateLimitOpts>,
+    pub scheduler: SchedulerOpts,
+    pub log_level: LevelFilter,
+    // default time zone of the user workers, see `UserWorkerPoolOpts`
+    pub timezone: Option<String>,
+    pub waf_rules: WafRules,
+    pub maintenance: MaintenanceOpts,
+}
+
+impl Tunables {
+    // the settings of the process, the pool's are applied by the pool
+    pub(crate) fn apply_to_process(&self) {
+        log::set_max_level(self.log_level);
+        set_waf_rules(self.waf_rules.clone());
+        set_maintenance(self.maintenance.clone());
+    }
+}
+
+// Lets the server reload its tunables on SIGHUP, with what `loader` returns
+// (eg: read again from its config file). Must be called before the server
+// starts.
+pub fn init_config_reload(
+    loader: impl Fn() -> Result<Tunables, Error> + Send + Sync + 'static,
+) -> Result<(), Error> {
+    if TUNABLES_LOADER.set(Box::new(loader)).is_err() {
+        bail!("config reloading is already set up");
+    }
+    Ok(())
+}
+
+// `None` if the server can't reload its config
+pub(crate) fn load_tunables() -> Option<Result<Tunables, Error>> {
+    TUNABLES_LOADER.get().map(|loader| loader())
+}
@@ -0,0 +1,130 @@
+use crate::bootstrap::{BootstrapFeatures, IsolateKind};
+use crate::edge_runtime::runtime_extension_names;
+use anyhow::{bail, Error};
+use deno_core::serde_json;
+use hyper::header::CONTENT_TYPE;
+use hyper::{Body, Response};
+use once_cell::sync::OnceCell;
+use sb_core::runtime::RuntimeInfo;
+
+pub const RUNTIME_VERSION: &str = env!("CARGO_PKG_VERSION");
+
+static VERSION_ENDPOINT: OnceCell<()> = OnceCell::new();
+
+// Serves the runtime info of the server on `/_internal/version`. Must be
+// called before the server starts.
+pub fn init_version_endpoint() -> Result<(), Error> {
+    if VERSION_ENDPOINT.set(()).is_err() {
+        bail!("the version endpoint is already on");
+    }
+    Ok(())
+}
+
+pub(crate) fn version_endpoint_enabled() -> bool {
+    VERSION_ENDPOINT.get().is_some()
+}
+
+pub fn v8_version() -> &'static str {
+    deno_core::v8::V8::get_version()
+}
+
+// What the isolates of `isolate_kind` run on, with `features` turned on. The
+// ones of a worker are given to it by `EdgeRuntime.runtimeInfo()`.
+pub fn runtime_info(isolate_kind: IsolateKind, features: &BootstrapFeatures) -> RuntimeInfo {
+    RuntimeInfo {
+        version: RUNTIME_VERSION.to_string(),
+        v8_version: v8_version().to_string(),
+        target: env!("TARGET").to_string(),
+        extensions: runtime_extension_names(isolate_kind)
+            .into_iter()
+            .map(String::from)
+            .collect(),
+        features: enabled_features(features),
+        unstable: features
+            .unstable
+            .iter()
+            .filter_map(|feature| serde_json::to_value(feature).ok())
+            .filter_map(|value| value.as_str().map(String::from))
+            .collect(),
+    }
+}
+
+// the names of the features that aren't off or unset, as bootstrap.js gets
+// them (sorted)
+fn enabled_features(features: &BootstrapFeatures) -> Vec<String> {
+    let Ok(serde_json::Value::Object(fields)) = serde_json::to_value(features) else {
+        return vec![];
+    };
+    let mut enabled: Vec<String> = fields
+        .into_iter()
+        .filter(|(name, value)| {
+            name != "unstable"
+                && !matches!(
+                    value,
+                    serde_json::Value::Null | serde_json::Value::Bool(false)
+                )
+        })
+        .map(|(name, _)| name)
+        .collect();
+    enabled.sort();
+    enabled
+}
+
+// logged once the server starts
+pub fn startup_banner() -> String {
+    format!(
+        "edge-runtime {} (v8 {}, {})",
+        RUNTIME_VERSION,
+        v8_version(),
+        env!("TARGET")
+    )
+}
+
+// The runtime info of the main worker, with the features of no worker in
+// particular.
+pub(crate) fn version_response() -> Response<Body> {
+    let info = runtime_info(IsolateKind::Main, &BootstrapFeatures::default());
+    Response::builder()
+        .header(CONTENT_TYPE, "application/json")
+        .body(Body::from(serde_json::to_string(&info).unwrap()))
+        .unwrap()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use sb_worker_context::essentials::UnstableFeature;
+
+    #[test]
+    fn test_runtime_info() {
+        let features = BootstrapFeatures {
+            event_loop_heartbeat_ms: Some(50),
+            count_net_usage: true,
+            unstable: vec![UnstableFeature::Kv, UnstableFeature::Cron],
+            ..Default::default()
+        };
+        let info = runtime_info(IsolateKind::User, &features);
+
+        assert_eq!(info.version, RUNTIME_VERSION);
+        assert!(!info.v8_version.is_empty());
+        assert_eq!(info.target, env!("TARGET"));
+        assert_eq!(
+            info.features,
+            vec![
+                "countNetUsage".to_string(),
+                "eventLoopHeartbeatMs".to_string()
+            ]
+        );
+        assert_eq!(info.unstable, vec!["kv".to_string(), "cron".to_string()]);
+        assert!(info.extensions.contains(&"deno_fetch".to_string()));
+        // the pool is only driven from the main worker
+        assert!(!info.extensions.contains(&"sb_user_workers".to_string()));
+        assert!(runtime_info(IsolateKind::Main, &features)
+            .extensions
+            .contains(&"sb_user_workers".to_string()));
+        // deno_net is replaced
+        let info = runtime_info(IsolateKind::UserWithoutNet, &features);
+        assert!(!info.extensions.contains(&"deno_net".to_string()));
+        assert!(info.extensions.contains(&"sb_core_no_net".to_string()));
+    }
+}
@@ -0,0 +1,270 @@
+use sb_worker_context::essentials::WorkerPriority;
+use std::cmp::Ordering;
+use std::collections::BinaryHeap;
+use uuid::Uuid;
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct SchedulerOpts {
+    // user workers booting at the same time, unlimited if unset
+    pub max_concurrent_boots: Option<usize>,
+    // sum of the memory limits of the user workers, unlimited if unset
+    pub memory_budget_mb: Option<u64>,
+}
+
+// A user worker that's running, as seen by the scheduler.
+#[derive(Debug, Clone)]
+pub struct LiveWorker {
+    pub key: Uuid,
+    pub priority: WorkerPriority,
+    // memory reserved by all of its isolates
+    pub memory_mb: u64,
+}
+
+// What the queued workers need next.
+#[derive(Debug, PartialEq)]
+pub enum Admission<T> {
+    // a queued worker allowed to boot
+    Boot(T),
+    // workers to stop to make room for the one at the head of the queue, it's
+    // let through once their exit frees their memory (see `stopped`)
+    Preempt(Vec<Uuid>),
+}
+
+struct Queued<T> {
+    priority: WorkerPriority,
+    seq: u64,
+    memory_mb: u64,
+    payload: T,
+}
+
+// higher priorities first, then first in first out
+impl<T> Ord for Queued<T> {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.priority
+            .cmp(&other.priority)
+            .then_with(|| other.seq.cmp(&self.seq))
+    }
+}
+
+impl<T> PartialOrd for Queued<T> {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl<T> PartialEq for Queued<T> {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl<T> Eq for Queued<T> {}
+
+// Decides which user workers boot first when boot slots or memory run out.
+// Batch workers are preempted when memory is needed by a higher priority one.
+pub struct WorkerScheduler<T> {
+    opts: SchedulerOpts,
+    queue: BinaryHeap<Queued<T>>,
+    next_seq: u64,
+    booting: usize,
+    // memory of the booting and live workers
+    reserved_mb: u64,
+    // part of it held by preempted workers until they exit
+    stopping_mb: u64,
+}
+
+impl<T> WorkerScheduler<T> {
+    pub fn new(opts: SchedulerOpts) -> Self {
+        Self {
+            opts,
+            queue: BinaryHeap::new(),
+            next_seq: 0,
+            booting: 0,
+            reserved_mb: 0,
+            stopping_mb: 0,
+        }
+    }
+
+    // A lower budget doesn't stop the running workers, the queued ones wait
+    // until enough of them exit.
+    pub fn set_opts(&mut self, opts: SchedulerOpts) {
+        self.opts = opts;
+    }
+
+    pub fn push(&mut self, priority: WorkerPriority, memory_mb: u64, payload: T) {
+        self.queue.push(Queued {
+            priority,
+            seq: self.next_seq,
+            memory_mb,
+            payload,
+        });
+        self.next_seq += 1;
+    }
+
+    pub fn queued(&self) -> usize {
+        self.queue.len()
+    }
+
+    // Pops the next worker to boot, if there's a boot slot and enough memory
+    // for it, or else picks the batch workers to stop to get the memory. Lower
+    // priorities wait behind it until then.
+    pub fn next(&mut self, live: &[LiveWorker]) -> Option<Admission<T>> {
+        let head = self.queue.peek()?;
+        if let Some(max) = self.opts.max_concurrent_boots {
+            if self.booting >= max {
+                return None;
+            }
+        }
+
+        if let Some(budget) = self.opts.memory_budget_mb {
+            let over_mb = (self.reserved_mb + head.memory_mb).saturating_sub(budget);
+            if over_mb > 0 {
+                // the workers already stopping will free theirs
+                let mut missing_mb = over_mb.saturating_sub(self.stopping_mb);
+                if missing_mb == 0 || head.priority == WorkerPriority::Batch {
+                    return None;
+                }
+
+                // the largest batch workers first, so as few as possible are stopped
+                let mut candidates: Vec<&LiveWorker> = live
+                    .iter()
+                    .filter(|w| w.priority == WorkerPriority::Batch)
+                    .collect();
+                candidates.sort_by(|a, b| b.memory_mb.cmp(&a.memory_mb));
+
+                let mut preempted = vec![];
+                let mut freed_mb = 0;
+                for worker in candidates {
+                    if missing_mb == 0 {
+                        break;
+                    }
+                    preempted.push(worker.key);
+                    freed_mb += worker.memory_mb;
+                    missing_mb = missing_mb.saturating_sub(worker.memory_mb);
+                }
+                if missing_mb > 0 {
+                    return None;
+                }
+                self.stopping_mb += freed_mb;
+                return Some(Admission::Preempt(preempted));
+            }
+        }
+
+        let head = self.queue.pop()?;
+        self.booting += 1;
+        self.reserved_mb += head.memory_mb;
+        Some(Admission::Boot(head.payload))
+    }
+
+    pub fn boot_finished(&mut self) {
+        self.booting = self.booting.saturating_sub(1);
+    }
+
+    // Memory of isolates booted outside of the queue (eg: autoscaled ones).
+    pub fn reserve(&mut self, memory_mb: u64) {
+        self.reserved_mb += memory_mb;
+    }
+
+    pub fn release(&mut self, memory_mb: u64) {
+        self.reserved_mb = self.reserved_mb.saturating_sub(memory_mb);
+    }
+
+    // An isolate of a preempted worker exited.
+    pub fn stopped(&mut self, memory_mb: u64) {
+        self.stopping_mb = self.stopping_mb.saturating_sub(memory_mb);
+        self.release(memory_mb);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_higher_priorities_boot_first() {
+        let mut scheduler = WorkerScheduler::new(SchedulerOpts {
+            max_concurrent_boots: Some(1),
+            memory_budget_mb: None,
+        });
+        scheduler.push(WorkerPriority::Batch, 150, "batch");
+        scheduler.push(WorkerPriority::Normal, 150, "normal-1");
+        scheduler.push(WorkerPriority::System, 150, "system");
+        scheduler.push(WorkerPriority::Normal, 150, "normal-2");
+
+        let mut booted = vec![];
+        while let Some(Admission::Boot(payload)) = scheduler.next(&[]) {
+            booted.push(payload);
+            // a single boot slot
+            assert!(scheduler.next(&[]).is_none());
+            scheduler.boot_finished();
+        }
+        assert_eq!(booted, vec!["system", "normal-1", "normal-2", "batch"]);
+    }
+
+    #[test]
+    fn test_preempt_batch_workers() {
+        let mut scheduler = WorkerScheduler::new(SchedulerOpts {
+            max_concurrent_boots: None,
+            memory_budget_mb: Some(400),
+        });
+        let batch = LiveWorker {
+            key: Uuid::new_v4(),
+            priority: WorkerPriority::Batch,
+            memory_mb: 150,
+        };
+        let normal = LiveWorker {
+            key: Uuid::new_v4(),
+            priority: WorkerPriority::Normal,
+            memory_mb: 150,
+        };
+        scheduler.reserve(300);
+
+        // batch workers wait for memory
+        scheduler.push(WorkerPriority::Batch, 150, "batch");
+        assert!(scheduler.next(&[batch.clone(), normal.clone()]).is_none());
+        assert_eq!(scheduler.queued(), 1);
+
+        // higher priorities stop batch workers to get it
+        scheduler.push(WorkerPriority::High, 150, "high");
+        assert_eq!(
+            scheduler.next(&[batch.clone(), normal.clone()]),
+            Some(Admission::Preempt(vec![batch.key]))
+        );
+        // and boot once they exited, without stopping more meanwhile
+        let another_batch = LiveWorker {
+            key: Uuid::new_v4(),
+            ..batch.clone()
+        };
+        assert!(scheduler
+            .next(&[normal.clone(), another_batch.clone()])
+            .is_none());
+        scheduler.stopped(batch.memory_mb);
+        assert_eq!(
+            scheduler.next(&[normal.clone(), another_batch]),
+            Some(Admission::Boot("high"))
+        );
+
+        // and wait when there's nothing left to preempt
+        scheduler.push(WorkerPriority::System, 200, "system");
+        assert!(scheduler.next(&[normal]).is_none());
+    }
+
+    #[test]
+    fn test_set_opts() {
+        let mut scheduler = WorkerScheduler::new(SchedulerOpts {
+            max_concurrent_boots: Some(1),
+            memory_budget_mb: None,
+        });
+        scheduler.push(WorkerPriority::Normal, 100, "first");
+        scheduler.push(WorkerPriority::Normal, 100, "second");
+        assert_eq!(scheduler.next(&[]), Some(Admission::Boot("first")));
+        assert!(scheduler.next(&[]).is_none());
+
+        // more boot slots let the queued workers through
+        scheduler.set_opts(SchedulerOpts {
+            max_concurrent_boots: Some(2),
+            memory_budget_mb: None,
+        });
+        assert_eq!(scheduler.next(&[]), Some(Admission::Boot("second")));
+    }
+}
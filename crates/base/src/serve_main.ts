@@ -0,0 +1,61 @@
+import { serve } from "https://deno.land/std@0.131.0/http/server.ts"
+
+// placeholders are filled in by `edge-runtime serve`
+const functionsDir = __FUNCTIONS_DIR__;
+const typeCheck = __TYPE_CHECK__;
+
+serve(async (req: Request) => {
+  const url = new URL(req.url);
+  const service_name = url.pathname.split("/")[1];
+
+  if (!service_name || service_name === "") {
+    const error = { msg: "missing function name in request" }
+    return new Response(
+        JSON.stringify(error),
+        { status: 400, headers: { "Content-Type": "application/json" } },
+    )
+  }
+
+  const servicePath = `${functionsDir}/${service_name}`;
+  const envVarsObj = Deno.env.toObject();
+  const envVars = Object.keys(envVarsObj).map(k => [k, envVarsObj[k]]);
+  try {
+    // preflights, unknown functions and methods they don't handle are
+    // answered without booting a worker
+    const preflight = await EdgeRuntime.userWorkers.preflight(servicePath, req);
+    if (preflight) {
+      return preflight;
+    }
+
+    const worker = await EdgeRuntime.userWorkers.create({
+      servicePath,
+      serviceName: service_name,
+      // lets the server route and rate limit by caller
+      routingHeaders: [...req.headers.entries()],
+      typeCheck,
+      envVars
+    });
+    return worker.fetch(req);
+  } catch (e) {
+    console.error(e);
+    if (e.name === "RateLimitError") {
+      const error = { msg: e.message }
+      return new Response(
+          JSON.stringify(error),
+          {
+            status: 429,
+            headers: {
+              "Content-Type": "application/json",
+              "Retry-After": String(Math.ceil(e.retryAfterMs / 1000)),
+            },
+          },
+      )
+    }
+    // module resolution failures come with suggestions and the import chain
+    const error = { msg: e.toString(), diagnostic: e.diagnostic }
+    return new Response(
+        JSON.stringify(error),
+        { status: 500, headers: { "Content-Type": "application/json" } },
+    )
+  }
+})
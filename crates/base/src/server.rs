@@ -1,7 +1,22 @@
-use crate::worker_ctx::{WorkerContext, WorkerPool};
+use crate::deployments::DeploymentRouter;
+use crate::geoip::apply_geo_headers;
+use crate::maintenance::maintenance_response;
+use crate::metrics::metrics_endpoint_enabled;
+use crate::proxy::TrustedProxies;
+use crate::reload::load_tunables;
+use crate::runtime_info::{startup_banner, version_endpoint_enabled, version_response};
+use crate::waf::{waf, WafRequest, WafVerdict};
+use crate::worker_ctx::{
+    error_response, request_rolling_restart, UserWorkerPoolOpts, WorkerContext, WorkerPool,
+    BINDING_DEPTH_HEADER,
+};
 use anyhow::Error;
+use deno_core::serde_json;
+use hyper::header::{HeaderValue, CONNECTION, CONTENT_TYPE};
 use hyper::{server::conn::Http, service::Service, Body, Request, Response};
-use log::{debug, error, info};
+use log::{debug, error, info, warn};
+use sb_worker_context::fetch_breaker::FetchBreakers;
+use sb_worker_context::routes::RouteTable;
 use std::future::Future;
 use std::net::IpAddr;
 use std::net::Ipv4Addr;
@@ -9,18 +24,124 @@ use std::net::SocketAddr;
 use std::pin::Pin;
 use std::str;
 use std::str::FromStr;
-use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
 use std::task::Poll;
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncRead, AsyncWrite};
 use tokio::net::TcpListener;
-use tokio::sync::RwLock;
+use tokio::sync::{watch, RwLock};
+
+// how long open connections get to finish their requests once the server is stopped
+const SHUTDOWN_DRAIN_TIMEOUT: Duration = Duration::from_secs(10);
+
+// How connections to the server are reused between requests.
+#[derive(Debug, Clone)]
+pub struct KeepAliveOpts {
+    pub enabled: bool,
+    // connections without requests for this long are closed
+    pub idle_timeout_ms: Option<u64>,
+    // requests served on a connection before it's closed
+    pub max_requests: Option<usize>,
+}
+
+impl Default for KeepAliveOpts {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            idle_timeout_ms: None,
+            max_requests: None,
+        }
+    }
+}
+
+// Requests of a single connection.
+struct ConnState {
+    requests: AtomicUsize,
+    in_flight: AtomicUsize,
+    last_active: Mutex<Instant>,
+}
+
+impl ConnState {
+    fn new() -> Self {
+        Self {
+            requests: AtomicUsize::new(0),
+            in_flight: AtomicUsize::new(0),
+            last_active: Mutex::new(Instant::now()),
+        }
+    }
+
+    fn request_started(&self) -> usize {
+        self.in_flight.fetch_add(1, Ordering::Relaxed);
+        *self.last_active.lock().unwrap() = Instant::now();
+        self.requests.fetch_add(1, Ordering::Relaxed) + 1
+    }
+
+    fn request_done(&self) {
+        *self.last_active.lock().unwrap() = Instant::now();
+        self.in_flight.fetch_sub(1, Ordering::Relaxed);
+    }
+
+    // resolves once no request was sent on the connection for `timeout`
+    async fn idle(&self, timeout: Duration) {
+        loop {
+            let idle_since = *self.last_active.lock().unwrap();
+            let deadline = if self.in_flight.load(Ordering::Relaxed) > 0 {
+                Instant::now() + timeout
+            } else if idle_since.elapsed() >= timeout {
+                return;
+            } else {
+                idle_since + timeout
+            };
+            tokio::time::sleep_until(deadline.into()).await;
+        }
+    }
+}
+
+// The state of the server as JSON, for monitoring.
+fn metrics_response(fetch_breakers: Option<&FetchBreakers>) -> Response<Body> {
+    let metrics = serde_json::json!({
+        "fetchBreakers": fetch_breakers.map(FetchBreakers::snapshot).unwrap_or_default(),
+    });
+    Response::builder()
+        .header(CONTENT_TYPE, "application/json")
+        .body(Body::from(metrics.to_string()))
+        .unwrap()
+}
 
 struct WorkerService {
     worker_ctx: Arc<RwLock<WorkerContext>>,
+    routes: RouteTable,
+    fetch_breakers: Option<FetchBreakers>,
+    remote_addr: SocketAddr,
+    trusted_proxies: Arc<TrustedProxies>,
+    conn_state: Arc<ConnState>,
+    max_requests: Option<usize>,
+    shutdown_rx: watch::Receiver<bool>,
 }
 
 impl WorkerService {
-    fn new(worker_ctx: Arc<RwLock<WorkerContext>>) -> Self {
-        Self { worker_ctx }
+    #[allow(clippy::too_many_arguments)]
+    fn new(
+        worker_ctx: Arc<RwLock<WorkerContext>>,
+        routes: RouteTable,
+        fetch_breakers: Option<FetchBreakers>,
+        remote_addr: SocketAddr,
+        trusted_proxies: Arc<TrustedProxies>,
+        conn_state: Arc<ConnState>,
+        max_requests: Option<usize>,
+        shutdown_rx: watch::Receiver<bool>,
+    ) -> Self {
+        Self {
+            worker_ctx,
+            routes,
+            fetch_breakers,
+            remote_addr,
+            trusted_proxies,
+            conn_state,
+            max_requests,
+            shutdown_rx,
+        }
     }
 }
 
@@ -33,19 +154,66 @@ impl Service<Request<Body>> for WorkerService {
         Poll::Ready(Ok(()))
     }
 
-    fn call(&mut self, req: Request<Body>) -> Self::Future {
+    fn call(&mut self, mut req: Request<Body>) -> Self::Future {
+        // the main worker only sees the request, tell it where it came from,
+        // believing the forwarding headers of trusted proxies only
+        let client_ip = self
+            .trusted_proxies
+            .apply(self.remote_addr.ip(), req.headers_mut());
+        apply_geo_headers(client_ip, req.headers_mut());
+        // only the pool says how deep in service calls a request is
+        req.headers_mut().remove(BINDING_DEPTH_HEADER);
+
+        // tell the client to open a new connection for its next requests, once this one
+        // served its share of them or the server is stopping
+        let requests = self.conn_state.request_started();
+        let close =
+            self.max_requests.map_or(false, |max| requests >= max) || *self.shutdown_rx.borrow();
+
         // create a response in a future.
         let worker_ctx = self.worker_ctx.clone();
+        let routes = self.routes.clone();
+        let fetch_breakers = self.fetch_breakers.clone();
+        let conn_state = self.conn_state.clone();
         let fut = async move {
             let req_path = req.uri().path();
 
             // if the request is for the health endpoint return a 200 OK response
-            if req_path == "/_internal/health" {
-                return Ok(Response::new(Body::empty()));
-            }
+            let response = if req_path == "/_internal/health" {
+                Ok(Response::new(Body::empty()))
+            } else if req_path == "/_internal/metrics" && metrics_endpoint_enabled() {
+                Ok(metrics_response(fetch_breakers.as_ref()))
+            } else if req_path == "/_internal/version" && version_endpoint_enabled() {
+                Ok(version_response())
+            } else if let Some(res) = maintenance_response(None) {
+                Ok(res)
+            } else if let WafVerdict::Reject(status) = waf().check(
+                &WafRequest {
+                    method: req.method(),
+                    path: req_path,
+                    headers: req.headers(),
+                    client_ip,
+                },
+                Instant::now(),
+            ) {
+                // obviously malicious, the main worker doesn't see it
+                Ok(error_response(status, "request blocked"))
+            } else if !routes.may_match(req_path) {
+                // none of the routes of the main worker can match it
+                Ok(error_response(404, "route not found"))
+            } else {
+                // requests are multiplexed to the main worker, so they don't wait on each other
+                let worker_ctx = worker_ctx.read().await;
+                worker_ctx.send_request(req).await
+            };
+            conn_state.request_done();
 
-            let mut worker_ctx_writer = worker_ctx.write().await;
-            let response = worker_ctx_writer.send_request(req).await?;
+            let mut response = response?;
+            if close {
+                response
+                    .headers_mut()
+                    .insert(CONNECTION, HeaderValue::from_static("close"));
+            }
             Ok(response)
         };
 
@@ -54,10 +222,93 @@ impl Service<Request<Body>> for WorkerService {
     }
 }
 
+// Serves the requests of a connection until the client closes it, or it's
+// idle for longer than allowed, or the server is stopping.
+async fn serve_conn<I>(
+    conn: I,
+    service: WorkerService,
+    keep_alive: &KeepAliveOpts,
+    conn_state: &ConnState,
+    mut shutdown_rx: watch::Receiver<bool>,
+) where
+    I: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+{
+    let conn_fut = Http::new()
+        .http1_writev(true)
+        .http1_keep_alive(keep_alive.enabled)
+        .serve_connection(conn, service);
+    tokio::pin!(conn_fut);
+
+    let idle = async {
+        match keep_alive.idle_timeout_ms {
+            Some(ms) => conn_state.idle(Duration::from_millis(ms)).await,
+            None => std::future::pending().await,
+        }
+    };
+    tokio::pin!(idle);
+
+    // closing lets the requests in flight finish first
+    let mut closing = false;
+    loop {
+        tokio::select! {
+            res = conn_fut.as_mut() => {
+                if let Err(e) = res {
+                    error!("{:?}", e);
+                }
+                break;
+            }
+            _ = &mut idle, if !closing => {
+                conn_fut.as_mut().graceful_shutdown();
+                closing = true;
+            }
+            _ = shutdown_rx.changed(), if !closing => {
+                conn_fut.as_mut().graceful_shutdown();
+                closing = true;
+            }
+        }
+    }
+}
+
+// SIGHUP, to reload the config
+#[cfg(unix)]
+fn reload_signal() -> Result<tokio::signal::unix::Signal, Error> {
+    use tokio::signal::unix::{signal, SignalKind};
+    Ok(signal(SignalKind::hangup())?)
+}
+
+#[cfg(not(unix))]
+fn reload_signal() -> Result<NoSignal, Error> {
+    Ok(NoSignal)
+}
+
+// SIGUSR2, to restart the user workers
+#[cfg(unix)]
+fn restart_signal() -> Result<tokio::signal::unix::Signal, Error> {
+    use tokio::signal::unix::{signal, SignalKind};
+    Ok(signal(SignalKind::user_defined2())?)
+}
+
+#[cfg(not(unix))]
+fn restart_signal() -> Result<NoSignal, Error> {
+    Ok(NoSignal)
+}
+
+#[cfg(not(unix))]
+struct NoSignal;
+
+#[cfg(not(unix))]
+impl NoSignal {
+    async fn recv(&mut self) -> Option<()> {
+        std::future::pending().await
+    }
+}
+
 pub struct Server {
     ip: Ipv4Addr,
     port: u16,
     worker_pool: WorkerPool,
+    keep_alive: KeepAliveOpts,
+    trusted_proxies: Arc<TrustedProxies>,
 }
 
 impl Server {
@@ -65,48 +316,106 @@ impl Server {
         ip: &str,
         port: u16,
         main_service_path: String,
-        import_map_path: Option<String>,
-        no_module_cache: bool,
+        pool_opts: UserWorkerPoolOpts,
+        keep_alive: KeepAliveOpts,
+        trusted_proxies: TrustedProxies,
     ) -> Result<Self, Error> {
         // create a worker pool
-        let worker_pool =
-            WorkerPool::new(main_service_path, import_map_path, no_module_cache).await?;
+        let worker_pool = WorkerPool::new(main_service_path, pool_opts).await?;
 
         let ip = Ipv4Addr::from_str(ip)?;
         Ok(Self {
             ip,
             port,
             worker_pool,
+            keep_alive,
+            trusted_proxies: Arc::new(trusted_proxies),
         })
     }
 
+    pub fn deployments(&self) -> DeploymentRouter {
+        self.worker_pool.deployments.clone()
+    }
+
+    // a config that fails to load leaves the current settings in place
+    fn reload_config(&self) {
+        let tunables = match load_tunables() {
+            None => {
+                warn!("SIGHUP received, but there is no config to reload");
+                return;
+            }
+            Some(Err(err)) => {
+                error!("failed to reload the config: {:#}", err);
+                return;
+            }
+            Some(Ok(tunables)) => tunables,
+        };
+        tunables.apply_to_process();
+        let _ = self.worker_pool.tunables_tx.send(tunables);
+        info!("config reloaded");
+    }
+
+    // the pool replaces the isolates in the background, the server keeps
+    // serving requests
+    fn rolling_restart(&self) {
+        let restart_tx = self.worker_pool.restart_tx.clone();
+        tokio::spawn(async move {
+            match request_rolling_restart(&restart_tx).await {
+                Ok(report) if report.failed > 0 => warn!(
+                    "{} isolate(s) failed to restart, they keep running",
+                    report.failed
+                ),
+                Ok(_) => {}
+                Err(err) => error!("failed to restart the workers: {:#}", err),
+            }
+        });
+    }
+
     pub async fn listen(&mut self) -> Result<(), Error> {
         let addr = SocketAddr::new(IpAddr::V4(self.ip), self.port);
         let listener = TcpListener::bind(&addr).await?;
+        info!("{}", startup_banner());
         debug!("edge-runtime is listening on {:?}", listener.local_addr()?);
 
         let main_worker = &self.worker_pool.main_worker;
+        let routes = &self.worker_pool.routes;
+        let fetch_breakers = &self.worker_pool.fetch_breakers;
+        let (shutdown_tx, shutdown_rx) = watch::channel(false);
+        let mut reload_signal = reload_signal()?;
+        let mut restart_signal = restart_signal()?;
 
         loop {
             tokio::select! {
                 msg = listener.accept() => {
                     match msg {
-                       Ok((conn, _)) => {
+                       Ok((conn, remote_addr)) => {
                            let main_worker = main_worker.clone();
+                           let routes = routes.clone();
+                           let fetch_breakers = fetch_breakers.clone();
+                           let keep_alive = self.keep_alive.clone();
+                           let trusted_proxies = self.trusted_proxies.clone();
+                           let shutdown_rx = shutdown_rx.clone();
                            tokio::task::spawn(async move {
-                             let service = WorkerService::new(main_worker);
-
-                             let conn_fut = Http::new()
-                                .serve_connection(conn, service);
+                             let conn_state = Arc::new(ConnState::new());
+                             let service = WorkerService::new(
+                                 main_worker,
+                                 routes,
+                                 fetch_breakers,
+                                 remote_addr,
+                                 trusted_proxies,
+                                 conn_state.clone(),
+                                 keep_alive.max_requests,
+                                 shutdown_rx.clone(),
+                             );
 
-                             if let Err(e) = conn_fut.await {
-                                 error!("{:?}", e);
-                             }
+                             serve_conn(conn, service, &keep_alive, &conn_state, shutdown_rx).await;
                            });
                        }
                        Err(e) => error!("socket error: {}", e)
                     }
                 }
+                _ = reload_signal.recv() => self.reload_config(),
+                _ = restart_signal.recv() => self.rolling_restart(),
                 // wait for shutdown signal...
                 _ = tokio::signal::ctrl_c() => {
                     info!("shutdown signal received");
@@ -114,6 +423,132 @@ impl Server {
                 }
             }
         }
+
+        // stop accepting connections, and close the open ones once they're done
+        drop(listener);
+        drop(shutdown_rx);
+        let _ = shutdown_tx.send(true);
+        if tokio::time::timeout(SHUTDOWN_DRAIN_TIMEOUT, shutdown_tx.closed())
+            .await
+            .is_err()
+        {
+            warn!("connections still open after {:?}", SHUTDOWN_DRAIN_TIMEOUT);
+        }
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use hyper::client::conn::SendRequest;
+    use sb_worker_context::essentials::{
+        EdgeContextInitOpts, EdgeContextOpts, EdgeUserRuntimeOpts, OutboundOpts,
+    };
+    use std::collections::HashMap;
+    use std::path::PathBuf;
+    use tokio::task::JoinHandle;
+
+    async fn create_worker() -> Arc<RwLock<WorkerContext>> {
+        let worker = WorkerContext::new(EdgeContextInitOpts {
+            service_path: PathBuf::from("./test_cases/request_headers"),
+            no_module_cache: false,
+            offline: false,
+            import_map_path: None,
+            auth_tokens: None,
+            env_vars: HashMap::new(),
+            fetch_interceptor: None,
+            outbound: OutboundOpts::default(),
+            extensions: None,
+            unstable_features: vec![],
+            conf: EdgeContextOpts::UserWorker(EdgeUserRuntimeOpts {
+                worker_timeout_ms: 5000,
+                ..Default::default()
+            }),
+        })
+        .await
+        .unwrap();
+        Arc::new(RwLock::new(worker))
+    }
+
+    // serves one connection in the background, like `listen` does for the
+    // ones it accepts, returning the client's side of it and the task that
+    // ends once the server closed it
+    async fn connect(keep_alive: KeepAliveOpts) -> (SendRequest<Body>, JoinHandle<()>) {
+        let (client, server) = tokio::io::duplex(64 * 1024);
+        let worker = create_worker().await;
+        let server_task = tokio::spawn(async move {
+            let (_shutdown_tx, shutdown_rx) = watch::channel(false);
+            let conn_state = Arc::new(ConnState::new());
+            let service = WorkerService::new(
+                worker,
+                RouteTable::default(),
+                None,
+                SocketAddr::from(([127, 0, 0, 1], 4000)),
+                Arc::new(TrustedProxies::default()),
+                conn_state.clone(),
+                keep_alive.max_requests,
+                shutdown_rx.clone(),
+            );
+            serve_conn(server, service, &keep_alive, &conn_state, shutdown_rx).await;
+        });
+
+        let (request_sender, connection) = hyper::client::conn::handshake(client).await.unwrap();
+        tokio::spawn(connection);
+        (request_sender, server_task)
+    }
+
+    async fn get(request_sender: &mut SendRequest<Body>) -> Response<Body> {
+        request_sender.ready().await.unwrap();
+        let req = Request::get("http://localhost/")
+            .body(Body::empty())
+            .unwrap();
+        request_sender.send_request(req).await.unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_keep_alive_max_requests() {
+        let (mut request_sender, server_task) = connect(KeepAliveOpts {
+            max_requests: Some(2),
+            ..Default::default()
+        })
+        .await;
+
+        let res = get(&mut request_sender).await;
+        assert_eq!(res.status(), 200);
+        assert!(res.headers().get(CONNECTION).is_none());
+
+        // the last one it serves tells the client to reconnect
+        let res = get(&mut request_sender).await;
+        assert_eq!(res.status(), 200);
+        assert_eq!(res.headers().get(CONNECTION).unwrap(), "close");
+        hyper::body::to_bytes(res.into_body()).await.unwrap();
+
+        tokio::time::timeout(Duration::from_secs(1), server_task)
+            .await
+            .unwrap()
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_keep_alive_idle_timeout() {
+        let (mut request_sender, server_task) = connect(KeepAliveOpts {
+            idle_timeout_ms: Some(200),
+            ..Default::default()
+        })
+        .await;
+
+        let start = Instant::now();
+        let res = get(&mut request_sender).await;
+        assert_eq!(res.status(), 200);
+        assert!(res.headers().get(CONNECTION).is_none());
+        hyper::body::to_bytes(res.into_body()).await.unwrap();
+
+        // closed once nothing was sent on it for the timeout, not before
+        tokio::time::timeout(Duration::from_secs(2), server_task)
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(start.elapsed() >= Duration::from_millis(200));
+    }
+}
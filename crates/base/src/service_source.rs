@@ -0,0 +1,934 @@
+use crate::js_worker::bundle::bundle_service_module;
+use anyhow::{anyhow, bail, Context, Error};
+use bytes::Bytes;
+use deno_core::futures::future::BoxFuture;
+use deno_core::serde_json;
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use log::warn;
+use module_fetcher::cache::DenoDir;
+use module_fetcher::util::checksum;
+use ring::signature::{Ed25519KeyPair, KeyPair, UnparsedPublicKey, ED25519};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{SystemTime, UNIX_EPOCH};
+use tar::{Archive, Builder};
+use url::Url;
+use uuid::Uuid;
+
+// Downloads the contents of a remote service location.
+pub trait ServiceSourceProvider: Send + Sync {
+    fn fetch(&self, url: &Url) -> BoxFuture<'static, Result<Bytes, Error>>;
+}
+
+#[derive(Clone, Default)]
+pub struct HttpSourceProvider {
+    client: reqwest::Client,
+}
+
+impl ServiceSourceProvider for HttpSourceProvider {
+    fn fetch(&self, url: &Url) -> BoxFuture<'static, Result<Bytes, Error>> {
+        let client = self.client.clone();
+        let url = url.clone();
+        Box::pin(async move {
+            let res = client.get(url.clone()).send().await?;
+            if !res.status().is_success() {
+                bail!("unexpected response status from {}: {}", url, res.status());
+            }
+            Ok(res.bytes().await?)
+        })
+    }
+}
+
+// Reads `s3://<bucket>/<key>` locations over the S3 HTTP API. Requests are not
+// signed, so the bucket (or the gateway set as the endpoint) must allow reads
+// from the edge nodes.
+#[derive(Clone, Default)]
+pub struct S3SourceProvider {
+    http: HttpSourceProvider,
+    endpoint: Option<Url>,
+}
+
+impl S3SourceProvider {
+    pub fn with_endpoint(endpoint: Url) -> Self {
+        Self {
+            http: HttpSourceProvider::default(),
+            endpoint: Some(endpoint),
+        }
+    }
+
+    fn object_url(&self, url: &Url) -> Result<Url, Error> {
+        let bucket = url
+            .host_str()
+            .ok_or_else(|| anyhow!("missing bucket in service source {}", url))?;
+        let key = url.path().trim_start_matches('/');
+
+        let object_url = match &self.endpoint {
+            // path-style addressing for custom endpoints (eg: minio)
+            Some(endpoint) => endpoint.join(&format!("{}/{}", bucket, key))?,
+            None => Url::parse(&format!("https://{}.s3.amazonaws.com/{}", bucket, key))?,
+        };
+        Ok(object_url)
+    }
+}
+
+impl ServiceSourceProvider for S3SourceProvider {
+    fn fetch(&self, url: &Url) -> BoxFuture<'static, Result<Bytes, Error>> {
+        match self.object_url(url) {
+            Ok(object_url) => self.http.fetch(&object_url),
+            Err(err) => Box::pin(async move { Err(err) }),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct CachedSourceMeta {
+    url: String,
+    checksum: String,
+    fetched_at_ms: u64,
+    // trusted key (base64) the source's signature was verified against
+    #[serde(default)]
+    signed_by: Option<String>,
+    // the signature (base64) of the source, verified again when it's reused
+    #[serde(default)]
+    signature: Option<String>,
+}
+
+// the source as it was fetched, kept next to its unpacked copy
+const CACHED_SOURCE_NAME: &str = "source.data";
+
+// each version of a source is unpacked in a directory of its own, named
+// after its checksum, so a refresh doesn't change the files of the workers
+// booted from the previous one
+fn source_dir_name(checksum: &str) -> String {
+    format!("source-{}", checksum.to_lowercase())
+}
+
+fn is_source_dir_name(name: &str) -> bool {
+    // `source` is where copies were unpacked before they were versioned
+    name == "source" || name.starts_with("source-")
+}
+
+#[derive(Debug, Default)]
+struct Leases {
+    // workers booted from each copy
+    counts: HashMap<PathBuf, usize>,
+    // copies a newer version replaced, removed once they're released
+    stale: HashSet<PathBuf>,
+}
+
+// The copies of remote sources the workers boot from. A copy is only
+// removed once no worker uses it anymore.
+#[derive(Debug, Clone, Default)]
+struct SourceLeases(Arc<Mutex<Leases>>);
+
+impl SourceLeases {
+    fn acquire(&self, leases: &mut Leases, dir: &Path) -> SourceLease {
+        *leases.counts.entry(dir.to_path_buf()).or_default() += 1;
+        leases.stale.remove(dir);
+        SourceLease {
+            dir: dir.to_path_buf(),
+            leases: self.clone(),
+        }
+    }
+
+    // removes the copies next to `current` nobody uses, the others once
+    // they're released
+    fn collect(leases: &mut Leases, entry_dir: &Path, current: &Path) -> Result<(), Error> {
+        for entry in fs::read_dir(entry_dir)? {
+            let entry = entry?;
+            let path = entry.path();
+            let name = entry.file_name();
+            if path == current
+                || !entry.file_type()?.is_dir()
+                || !is_source_dir_name(&name.to_string_lossy())
+            {
+                continue;
+            }
+            if leases.counts.contains_key(&path) {
+                leases.stale.insert(path);
+            } else {
+                fs::remove_dir_all(&path)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+// Held by a worker for as long as it runs from a copy of a remote source.
+#[derive(Debug)]
+pub struct SourceLease {
+    dir: PathBuf,
+    leases: SourceLeases,
+}
+
+impl Drop for SourceLease {
+    fn drop(&mut self) {
+        let mut leases = self.leases.0.lock().unwrap();
+        let Some(count) = leases.counts.get_mut(&self.dir) else {
+            return;
+        };
+        *count -= 1;
+        if *count > 0 {
+            return;
+        }
+        leases.counts.remove(&self.dir);
+        if leases.stale.remove(&self.dir) {
+            if let Err(err) = fs::remove_dir_all(&self.dir) {
+                warn!("failed to remove the replaced copy {:?}: {}", self.dir, err);
+            }
+        }
+    }
+}
+
+// Where a service is booted from. Copies of remote sources are kept until
+// it's dropped.
+#[derive(Debug)]
+pub struct ResolvedSource {
+    pub path: PathBuf,
+    pub lease: Option<SourceLease>,
+}
+
+impl ResolvedSource {
+    fn local(path: &Path) -> Self {
+        Self {
+            path: path.to_path_buf(),
+            lease: None,
+        }
+    }
+}
+
+fn now_ms() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or(0)
+}
+
+fn remote_url(service_path: &Path) -> Option<Url> {
+    let url = Url::parse(service_path.to_str()?).ok()?;
+    // single letter schemes are windows drive letters
+    if url.scheme() == "file" || url.scheme().len() == 1 {
+        return None;
+    }
+    Some(url)
+}
+
+pub fn is_remote(service_path: &Path) -> bool {
+    remote_url(service_path).is_some()
+}
+
+fn signature_url(url: &Url) -> Url {
+    let mut signature_url = url.clone();
+    signature_url.set_path(&format!("{}.sig", url.path()));
+    signature_url
+}
+
+// Signatures are either the raw 64 bytes or their base64 encoding.
+fn decode_signature(data: &[u8]) -> Result<Vec<u8>, Error> {
+    if data.len() == 64 {
+        return Ok(data.to_vec());
+    }
+
+    let encoded = std::str::from_utf8(data)?.trim();
+    Ok(base64::decode(encoded)?)
+}
+
+// The files of a source by path, symlinks by their target. Only what was
+// unpacked from the source can be in its directory.
+#[derive(PartialEq, Eq, Default)]
+struct SourceFiles(BTreeMap<String, (bool, Vec<u8>)>);
+
+impl SourceFiles {
+    fn of_source(url: &Url, data: &[u8]) -> Result<Self, Error> {
+        let mut files = Self::default();
+        if !is_archive(url) {
+            files
+                .0
+                .insert("index.ts".to_string(), (false, data.to_vec()));
+            return Ok(files);
+        }
+
+        let mut archive = Archive::new(GzDecoder::new(data));
+        for entry in archive.entries()? {
+            let mut entry = entry?;
+            let path = normalize(&entry.path()?);
+            let kind = entry.header().entry_type();
+            if kind.is_symlink() {
+                let Some(target) = entry.link_name()? else {
+                    continue;
+                };
+                let target = target.to_string_lossy().as_bytes().to_vec();
+                files.0.insert(path, (true, target));
+            } else if kind.is_file() {
+                let mut data = vec![];
+                std::io::Read::read_to_end(&mut entry, &mut data)?;
+                files.0.insert(path, (false, data));
+            }
+        }
+        Ok(files)
+    }
+
+    fn of_dir(dir: &Path) -> Result<Self, Error> {
+        let mut files = Self::default();
+        let mut dirs = vec![dir.to_path_buf()];
+        while let Some(current) = dirs.pop() {
+            for entry in fs::read_dir(&current)? {
+                let entry = entry?;
+                let path = entry.path();
+                let kind = entry.file_type()?;
+                let name = normalize(path.strip_prefix(dir)?);
+                if kind.is_symlink() {
+                    let target = fs::read_link(&path)?;
+                    let target = target.to_string_lossy().as_bytes().to_vec();
+                    files.0.insert(name, (true, target));
+                } else if kind.is_dir() {
+                    dirs.push(path);
+                } else {
+                    files.0.insert(name, (false, fs::read(&path)?));
+                }
+            }
+        }
+        Ok(files)
+    }
+}
+
+fn normalize(path: &Path) -> String {
+    path.components()
+        .filter_map(|component| match component {
+            std::path::Component::Normal(part) => Some(part.to_string_lossy()),
+            _ => None,
+        })
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+fn is_archive(url: &Url) -> bool {
+    let path = url.path();
+    path.ends_with(".tar.gz") || path.ends_with(".tgz")
+}
+
+fn unpack_source(url: &Url, data: &[u8], output_dir: &Path) -> Result<(), Error> {
+    fs::create_dir_all(output_dir)?;
+
+    if is_archive(url) {
+        let mut archive = Archive::new(GzDecoder::new(data));
+        archive.set_overwrite(true);
+        archive.unpack(output_dir)?;
+    } else {
+        // a single module, booted as the service entrypoint
+        fs::write(output_dir.join("index.ts"), data)?;
+    }
+
+    Ok(())
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ServiceBundle {
+    pub path: PathBuf,
+    pub checksum: String,
+    pub signature_path: Option<PathBuf>,
+    // base64 public key to add to the trusted keys, when the bundle is signed
+    pub public_key: Option<String>,
+}
+
+// Packs a service directory into a `.tar.gz` archive that can be served from
+// a remote location. When a PKCS#8 ed25519 key is given, a detached signature
+// is written next to the archive (`<output>.sig`).
+pub fn bundle_service(
+    service_path: &Path,
+    output: &Path,
+    signing_key_pkcs8: Option<&[u8]>,
+) -> Result<ServiceBundle, Error> {
+    if !service_path.join("index.ts").exists() {
+        bail!("service entrypoint not found in {:?}", service_path);
+    }
+
+    let mut archive = Builder::new(GzEncoder::new(Vec::new(), Compression::default()));
+    archive.append_dir_all(".", service_path)?;
+    let data = archive.into_inner()?.finish()?;
+    write_bundle(output, &data, signing_key_pkcs8)
+}
+
+// Bundles a service into a single module (see `bundle_service_module`),
+// which is booted as the entrypoint of the service when served from a remote
+// location. It's signed like archives.
+pub async fn bundle_service_file(
+    service_path: &Path,
+    output: &Path,
+    base_import_map_path: Option<&str>,
+    signing_key_pkcs8: Option<&[u8]>,
+) -> Result<ServiceBundle, Error> {
+    let source = bundle_service_module(service_path, base_import_map_path, false).await?;
+    write_bundle(output, source.as_bytes(), signing_key_pkcs8)
+}
+
+fn write_bundle(
+    output: &Path,
+    data: &[u8],
+    signing_key_pkcs8: Option<&[u8]>,
+) -> Result<ServiceBundle, Error> {
+    fs::write(output, data)?;
+
+    let mut bundle = ServiceBundle {
+        path: output.to_path_buf(),
+        checksum: checksum::gen(&[data]),
+        signature_path: None,
+        public_key: None,
+    };
+
+    if let Some(pkcs8) = signing_key_pkcs8 {
+        let key_pair = Ed25519KeyPair::from_pkcs8(pkcs8)
+            .map_err(|err| anyhow!("invalid signing key: {}", err))?;
+        let signature_path = PathBuf::from(format!("{}.sig", output.display()));
+        fs::write(
+            &signature_path,
+            base64::encode(key_pair.sign(data).as_ref()),
+        )?;
+
+        bundle.signature_path = Some(signature_path);
+        bundle.public_key = Some(base64::encode(key_pair.public_key().as_ref()));
+    }
+
+    Ok(bundle)
+}
+
+fn create_private_dir(dir: &Path) -> Result<(), Error> {
+    fs::create_dir_all(dir)?;
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        fs::set_permissions(dir, fs::Permissions::from_mode(0o700))?;
+    }
+    Ok(())
+}
+
+// Resolves service paths pointing at remote locations (eg: `https://` or
+// `s3://`) to a local copy in the cache directory. Local paths are returned
+// as they are.
+#[derive(Clone)]
+pub struct ServiceSourceResolver {
+    cache_dir: PathBuf,
+    providers: HashMap<String, Arc<dyn ServiceSourceProvider>>,
+    // ed25519 public keys, a remote source must be signed by one of them when set
+    trusted_keys: Vec<Vec<u8>>,
+    // never fetch, only boot from the cached copies
+    offline: bool,
+    leases: SourceLeases,
+}
+
+impl ServiceSourceResolver {
+    pub fn new(cache_dir: PathBuf) -> Self {
+        let http: Arc<dyn ServiceSourceProvider> = Arc::new(HttpSourceProvider::default());
+        let mut providers = HashMap::new();
+        providers.insert("http".to_string(), http.clone());
+        providers.insert("https".to_string(), http);
+        providers.insert(
+            "s3".to_string(),
+            Arc::new(S3SourceProvider::default()) as Arc<dyn ServiceSourceProvider>,
+        );
+
+        Self {
+            cache_dir,
+            providers,
+            trusted_keys: vec![],
+            offline: false,
+            leases: SourceLeases::default(),
+        }
+    }
+
+    // Requires remote sources to come with a detached ed25519 signature
+    // (`<url>.sig`) made by one of the given base64 encoded public keys.
+    pub fn with_trusted_keys(mut self, keys: &[String]) -> Result<Self, Error> {
+        for key in keys {
+            let key = base64::decode(key.trim())
+                .with_context(|| format!("invalid trusted key: {}", key))?;
+            if key.len() != 32 {
+                bail!("invalid trusted key length, expected a 32 byte ed25519 public key");
+            }
+            self.trusted_keys.push(key);
+        }
+        Ok(self)
+    }
+
+    // the trusted key (base64) that made the signature
+    fn signed_by(&self, url: &Url, data: &[u8], signature: &[u8]) -> Result<String, Error> {
+        let signed_by = self.trusted_keys.iter().find(|key| {
+            UnparsedPublicKey::new(&ED25519, key)
+                .verify(data, signature)
+                .is_ok()
+        });
+
+        match signed_by {
+            Some(key) => Ok(base64::encode(key)),
+            None => bail!(
+                "service source {} is not signed by any of the trusted keys",
+                url
+            ),
+        }
+    }
+
+    // the trusted key and the signature (base64), none without trusted keys
+    async fn verify_signature(
+        &self,
+        provider: &Arc<dyn ServiceSourceProvider>,
+        url: &Url,
+        data: &[u8],
+    ) -> Result<Option<(String, String)>, Error> {
+        if self.trusted_keys.is_empty() {
+            return Ok(None);
+        }
+
+        let signature_url = signature_url(url);
+        let signature = provider
+            .fetch(&signature_url)
+            .await
+            .with_context(|| format!("failed to fetch signature {}", signature_url))?;
+        let signature = decode_signature(&signature)?;
+
+        let signed_by = self.signed_by(url, data, &signature)?;
+        Ok(Some((signed_by, base64::encode(signature))))
+    }
+
+    // The cache directory can be written by others than the runtime, so a
+    // copy is checked again before every reuse: the source kept next to it
+    // must still have its checksum and a valid signature, and the copy must
+    // be exactly what was unpacked from it.
+    fn verify_cached(
+        &self,
+        url: &Url,
+        meta: &CachedSourceMeta,
+        entry_dir: &Path,
+        source_dir: &Path,
+    ) -> Result<(), Error> {
+        let data = fs::read(entry_dir.join(CACHED_SOURCE_NAME))?;
+        if !checksum::gen(&[&data]).eq_ignore_ascii_case(&meta.checksum) {
+            bail!("the cached source doesn't match its checksum");
+        }
+        if !self.trusted_keys.is_empty() {
+            let Some(signature) = &meta.signature else {
+                bail!("the cached source isn't signed");
+            };
+            self.signed_by(url, &data, &base64::decode(signature)?)?;
+        }
+        if SourceFiles::of_source(url, &data)? != SourceFiles::of_dir(source_dir)? {
+            bail!("the cached copy doesn't match its source");
+        }
+        Ok(())
+    }
+
+    // Offline, a cached copy is used even when it is older than `max_age_ms`.
+    pub fn with_offline(mut self, offline: bool) -> Self {
+        self.offline = offline;
+        self
+    }
+
+    pub fn with_provider(mut self, scheme: &str, provider: Arc<dyn ServiceSourceProvider>) -> Self {
+        self.providers.insert(scheme.to_string(), provider);
+        self
+    }
+
+    // Next to the module cache, only accessible by the user running the
+    // runtime.
+    pub fn default_cache_dir() -> Result<PathBuf, Error> {
+        let cache_dir = DenoDir::new(None)?.services_folder_path();
+        create_private_dir(&cache_dir)?;
+        Ok(cache_dir)
+    }
+
+    // A cached copy is reused when it matches the expected checksum, or, when
+    // no checksum is given, until it is older than `max_age_ms` (forever if unset).
+    // The copy isn't removed while the result is kept.
+    pub async fn resolve(
+        &self,
+        service_path: &Path,
+        expected_checksum: Option<&str>,
+        max_age_ms: Option<u64>,
+    ) -> Result<ResolvedSource, Error> {
+        let Some(url) = remote_url(service_path) else {
+            return Ok(ResolvedSource::local(service_path));
+        };
+
+        let provider = self
+            .providers
+            .get(url.scheme())
+            .ok_or_else(|| anyhow!("unsupported service source: {}", url))?;
+
+        let entry_dir = self.cache_dir.join(checksum::gen(&[url.as_str()]));
+        let meta_path = entry_dir.join("meta.json");
+
+        let cached_meta = fs::read(&meta_path)
+            .ok()
+            .and_then(|data| serde_json::from_slice::<CachedSourceMeta>(&data).ok());
+        if let Some(meta) = cached_meta {
+            let is_fresh = match (expected_checksum, max_age_ms) {
+                (Some(expected), _) => meta.checksum.eq_ignore_ascii_case(expected),
+                (None, Some(_)) if self.offline => true,
+                (None, Some(max_age_ms)) => {
+                    now_ms().saturating_sub(meta.fetched_at_ms) < max_age_ms
+                }
+                (None, None) => true,
+            };
+            if is_fresh {
+                let source_dir = entry_dir.join(source_dir_name(&meta.checksum));
+                // taken before it's checked, so it can't be replaced meanwhile
+                let lease = {
+                    let mut leases = self.leases.0.lock().unwrap();
+                    self.leases.acquire(&mut leases, &source_dir)
+                };
+                let entry_dir = entry_dir.clone();
+                let resolver = self.clone();
+                let url = url.clone();
+                let verified = tokio::task::spawn_blocking(move || {
+                    resolver.verify_cached(&url, &meta, &entry_dir, &lease.dir)?;
+                    Ok::<_, Error>(lease)
+                })
+                .await?;
+                match verified {
+                    Ok(lease) => {
+                        return Ok(ResolvedSource {
+                            path: source_dir,
+                            lease: Some(lease),
+                        })
+                    }
+                    Err(err) => warn!("not reusing the cached copy of {}: {}", url, err),
+                }
+            }
+        }
+
+        if self.offline {
+            bail!(
+                "service source {} is not in the cache and the runtime is offline",
+                url
+            );
+        }
+
+        let data = provider
+            .fetch(&url)
+            .await
+            .with_context(|| format!("failed to fetch service source {}", url))?;
+
+        let actual_checksum = checksum::gen(&[&data]);
+        if let Some(expected) = expected_checksum {
+            if !actual_checksum.eq_ignore_ascii_case(expected) {
+                bail!(
+                    "checksum mismatch for service source {}\n\nExpected: {}\nActual: {}",
+                    url,
+                    expected,
+                    actual_checksum
+                );
+            }
+        }
+
+        // verify before anything is written to the cache, a worker must never
+        // boot from an unsigned source
+        let (signed_by, signature) = self.verify_signature(provider, &url, &data).await?.unzip();
+
+        let meta = CachedSourceMeta {
+            url: url.to_string(),
+            checksum: actual_checksum,
+            fetched_at_ms: now_ms(),
+            signed_by,
+            signature,
+        };
+
+        let leases = self.leases.clone();
+        tokio::task::spawn_blocking(move || -> Result<ResolvedSource, Error> {
+            // unpack aside first, so workers booting from the current copy
+            // are not affected by a failed download
+            let tmp_dir = entry_dir.join(format!("tmp-{}", Uuid::new_v4()));
+            if let Err(err) = unpack_source(&url, &data, &tmp_dir) {
+                let _ = fs::remove_dir_all(&tmp_dir);
+                return Err(err.context(format!("failed to unpack service source {}", url)));
+            }
+
+            let source_dir = entry_dir.join(source_dir_name(&meta.checksum));
+            let mut guard = leases.0.lock().unwrap();
+            // a copy of the same version workers use is kept as it is, one
+            // nobody uses didn't match its source
+            if source_dir.exists() && !guard.counts.contains_key(&source_dir) {
+                fs::remove_dir_all(&source_dir)?;
+            }
+            if source_dir.exists() {
+                let _ = fs::remove_dir_all(&tmp_dir);
+            } else if let Err(err) = fs::rename(&tmp_dir, &source_dir) {
+                let _ = fs::remove_dir_all(&tmp_dir);
+                return Err(err.into());
+            }
+            let lease = leases.acquire(&mut guard, &source_dir);
+
+            let tmp_path = entry_dir.join(format!("{}-{}", CACHED_SOURCE_NAME, Uuid::new_v4()));
+            fs::write(&tmp_path, &data)?;
+            fs::rename(&tmp_path, entry_dir.join(CACHED_SOURCE_NAME))?;
+            fs::write(&meta_path, serde_json::to_vec(&meta)?)?;
+
+            SourceLeases::collect(&mut guard, &entry_dir, &source_dir)?;
+            Ok(ResolvedSource {
+                path: source_dir,
+                lease: Some(lease),
+            })
+        })
+        .await?
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    struct StaticSourceProvider(&'static str);
+
+    impl ServiceSourceProvider for StaticSourceProvider {
+        fn fetch(&self, _url: &Url) -> BoxFuture<'static, Result<Bytes, Error>> {
+            let data = Bytes::from_static(self.0.as_bytes());
+            Box::pin(async move { Ok(data) })
+        }
+    }
+
+    #[tokio::test]
+    async fn test_resolve_remote_source() {
+        let cache_dir = std::env::temp_dir().join(format!("sb-sources-{}", Uuid::new_v4()));
+        let code = "Deno.serve(() => new Response('hello'));";
+        let resolver = ServiceSourceResolver::new(cache_dir.clone())
+            .with_provider("test", Arc::new(StaticSourceProvider(code)));
+
+        let local_path = PathBuf::from("./examples/hello");
+        assert_eq!(
+            resolver
+                .resolve(&local_path, None, None)
+                .await
+                .unwrap()
+                .path,
+            local_path
+        );
+
+        let remote_path = PathBuf::from("test://services/hello.ts");
+        let source_dir = resolver
+            .resolve(&remote_path, Some(&checksum::gen(&[code])), None)
+            .await
+            .unwrap()
+            .path;
+        assert_eq!(
+            fs::read_to_string(source_dir.join("index.ts")).unwrap(),
+            code
+        );
+
+        let err = resolver
+            .resolve(&remote_path, Some("deadbeef"), None)
+            .await
+            .unwrap_err();
+        assert!(err.to_string().contains("checksum mismatch"));
+
+        let _ = fs::remove_dir_all(cache_dir);
+    }
+
+    #[tokio::test]
+    async fn test_resolve_offline() {
+        let cache_dir = std::env::temp_dir().join(format!("sb-sources-{}", Uuid::new_v4()));
+        let code = "Deno.serve(() => new Response('hello'));";
+        let remote_path = PathBuf::from("test://services/hello.ts");
+
+        let offline = ServiceSourceResolver::new(cache_dir.clone())
+            .with_provider("test", Arc::new(StaticSourceProvider(code)))
+            .with_offline(true);
+        let err = offline.resolve(&remote_path, None, None).await.unwrap_err();
+        assert!(err.to_string().contains("runtime is offline"));
+
+        let source_dir = ServiceSourceResolver::new(cache_dir.clone())
+            .with_provider("test", Arc::new(StaticSourceProvider(code)))
+            .resolve(&remote_path, None, None)
+            .await
+            .unwrap()
+            .path;
+
+        // stale copies are still used offline
+        assert_eq!(
+            offline
+                .resolve(&remote_path, None, Some(0))
+                .await
+                .unwrap()
+                .path,
+            source_dir
+        );
+        assert!(offline
+            .resolve(&remote_path, Some("deadbeef"), None)
+            .await
+            .is_err());
+
+        let _ = fs::remove_dir_all(cache_dir);
+    }
+
+    struct SignedSourceProvider {
+        data: Bytes,
+        signature: Bytes,
+    }
+
+    impl ServiceSourceProvider for SignedSourceProvider {
+        fn fetch(&self, url: &Url) -> BoxFuture<'static, Result<Bytes, Error>> {
+            let data = if url.path().ends_with(".sig") {
+                self.signature.clone()
+            } else {
+                self.data.clone()
+            };
+            Box::pin(async move { Ok(data) })
+        }
+    }
+
+    #[tokio::test]
+    async fn test_signed_remote_source() {
+        use ring::rand::SystemRandom;
+
+        let rng = SystemRandom::new();
+        let pkcs8 = Ed25519KeyPair::generate_pkcs8(&rng).unwrap();
+        let key_pair = Ed25519KeyPair::from_pkcs8(pkcs8.as_ref()).unwrap();
+        let public_key = base64::encode(key_pair.public_key().as_ref());
+
+        let code = "Deno.serve(() => new Response('hello'));";
+        let signature = key_pair.sign(code.as_bytes());
+        let remote_path = PathBuf::from("test://services/hello.ts");
+
+        let cache_dir = std::env::temp_dir().join(format!("sb-sources-{}", Uuid::new_v4()));
+        let resolver = ServiceSourceResolver::new(cache_dir.clone())
+            .with_provider(
+                "test",
+                Arc::new(SignedSourceProvider {
+                    data: Bytes::from_static(code.as_bytes()),
+                    signature: Bytes::from(base64::encode(signature.as_ref())),
+                }),
+            )
+            .with_trusted_keys(&[public_key])
+            .unwrap();
+        assert!(resolver.resolve(&remote_path, None, None).await.is_ok());
+
+        let tampered = ServiceSourceResolver::new(cache_dir.clone())
+            .with_provider(
+                "test",
+                Arc::new(SignedSourceProvider {
+                    data: Bytes::from_static(b"Deno.exit(1);"),
+                    signature: Bytes::from(base64::encode(signature.as_ref())),
+                }),
+            )
+            .with_trusted_keys(&[base64::encode(key_pair.public_key().as_ref())])
+            .unwrap();
+        let err = tampered
+            .resolve(&remote_path, None, Some(0))
+            .await
+            .unwrap_err();
+        assert!(err
+            .to_string()
+            .contains("not signed by any of the trusted keys"));
+
+        let _ = fs::remove_dir_all(cache_dir);
+    }
+
+    #[tokio::test]
+    async fn test_tampered_cached_source() {
+        use ring::rand::SystemRandom;
+
+        let rng = SystemRandom::new();
+        let pkcs8 = Ed25519KeyPair::generate_pkcs8(&rng).unwrap();
+        let key_pair = Ed25519KeyPair::from_pkcs8(pkcs8.as_ref()).unwrap();
+
+        let code = "Deno.serve(() => new Response('hello'));";
+        let signature = key_pair.sign(code.as_bytes());
+        let remote_path = PathBuf::from("test://services/hello.ts");
+
+        let cache_dir = std::env::temp_dir().join(format!("sb-sources-{}", Uuid::new_v4()));
+        let resolver = ServiceSourceResolver::new(cache_dir.clone())
+            .with_provider(
+                "test",
+                Arc::new(SignedSourceProvider {
+                    data: Bytes::from_static(code.as_bytes()),
+                    signature: Bytes::from(base64::encode(signature.as_ref())),
+                }),
+            )
+            .with_trusted_keys(&[base64::encode(key_pair.public_key().as_ref())])
+            .unwrap();
+        let source_dir = resolver
+            .resolve(&remote_path, None, None)
+            .await
+            .unwrap()
+            .path;
+
+        // the copy is fetched again when it was changed
+        fs::write(source_dir.join("index.ts"), "Deno.exit(1);").unwrap();
+        fs::write(source_dir.join("extra.ts"), "").unwrap();
+        let source_dir = resolver
+            .resolve(&remote_path, None, None)
+            .await
+            .unwrap()
+            .path;
+        assert_eq!(
+            fs::read_to_string(source_dir.join("index.ts")).unwrap(),
+            code
+        );
+        assert!(!source_dir.join("extra.ts").exists());
+
+        // and never booted from offline
+        fs::write(source_dir.join("index.ts"), "Deno.exit(1);").unwrap();
+        let err = resolver
+            .clone()
+            .with_offline(true)
+            .resolve(&remote_path, None, None)
+            .await
+            .unwrap_err();
+        assert!(err.to_string().contains("runtime is offline"));
+
+        let _ = fs::remove_dir_all(cache_dir);
+    }
+
+    #[tokio::test]
+    async fn test_refresh_keeps_copies_in_use() {
+        let cache_dir = std::env::temp_dir().join(format!("sb-sources-{}", Uuid::new_v4()));
+        let remote_path = PathBuf::from("test://services/hello.ts");
+        let resolver = ServiceSourceResolver::new(cache_dir.clone());
+        let version = |code: &'static str| {
+            resolver
+                .clone()
+                .with_provider("test", Arc::new(StaticSourceProvider(code)))
+        };
+
+        let v1 = version("// v1")
+            .resolve(&remote_path, None, None)
+            .await
+            .unwrap();
+
+        // refreshed while a worker runs from the first version
+        let v2 = version("// v2")
+            .resolve(&remote_path, None, Some(0))
+            .await
+            .unwrap();
+        assert_ne!(v1.path, v2.path);
+        assert_eq!(
+            fs::read_to_string(v1.path.join("index.ts")).unwrap(),
+            "// v1"
+        );
+        assert_eq!(
+            fs::read_to_string(v2.path.join("index.ts")).unwrap(),
+            "// v2"
+        );
+
+        // it's removed once the worker is done with it
+        let v1_path = v1.path.clone();
+        drop(v1);
+        assert!(!v1_path.exists());
+
+        // and right away when nobody uses it
+        let v2_path = v2.path.clone();
+        drop(v2);
+        let v3 = version("// v3")
+            .resolve(&remote_path, None, Some(0))
+            .await
+            .unwrap();
+        assert!(!v2_path.exists());
+        assert!(v3.path.exists());
+
+        let _ = fs::remove_dir_all(cache_dir);
+    }
+}
@@ -1,8 +1,77 @@
-use deno_core::Snapshot;
+use crate::bootstrap::IsolateKind;
+use anyhow::{bail, Error};
+use deno_core::{Extension, Snapshot};
+use once_cell::sync::OnceCell;
 
-pub static CLI_SNAPSHOT: &[u8] = include_bytes!(concat!(env!("OUT_DIR"), "/RUNTIME_SNAPSHOT.bin"));
+pub static MAIN_SNAPSHOT: &[u8] =
+    include_bytes!(concat!(env!("OUT_DIR"), "/MAIN_RUNTIME_SNAPSHOT.bin"));
+pub static USER_SNAPSHOT: &[u8] =
+    include_bytes!(concat!(env!("OUT_DIR"), "/USER_RUNTIME_SNAPSHOT.bin"));
+pub static USER_NO_NET_SNAPSHOT: &[u8] = include_bytes!(concat!(
+    env!("OUT_DIR"),
+    "/USER_NO_NET_RUNTIME_SNAPSHOT.bin"
+));
 
-pub fn snapshot() -> Snapshot {
-    let data = CLI_SNAPSHOT;
-    Snapshot::Static(data)
+static STARTUP_SNAPSHOT: OnceCell<StartupSnapshot> = OnceCell::new();
+
+// What the isolates are started from.
+#[derive(Clone, Copy, Default)]
+pub enum StartupSnapshot {
+    // built with the runtime's extensions by build.rs, one per kind of isolate
+    #[default]
+    Builtin,
+    // the js of the extensions is evaluated on every boot instead, so it can be
+    // changed (or debugged) without rebuilding
+    Disabled,
+    // built by an embedder, see `CustomSnapshot`
+    Custom(CustomSnapshot),
+}
+
+// Snapshots built from `edge_runtime::runtime_extensions(true, kind, ..)` and
+// extra extensions, with `deno_core::snapshot_util::create_snapshot` (eg: in
+// the build script of the embedder).
+#[derive(Clone, Copy)]
+pub struct CustomSnapshot {
+    // with `IsolateKind::Main`
+    pub main: &'static [u8],
+    // with `IsolateKind::User`
+    pub user: &'static [u8],
+    // with `IsolateKind::UserWithoutNet`
+    pub user_without_net: &'static [u8],
+    // the extra extensions (`init_ops`), added to every isolate
+    pub extensions: fn() -> Vec<Extension>,
+}
+
+// Sets the snapshot isolates start from. Must be called before the first
+// worker is created.
+pub fn init_startup_snapshot(snapshot: StartupSnapshot) -> Result<(), Error> {
+    if STARTUP_SNAPSHOT.set(snapshot).is_err() {
+        bail!("the startup snapshot is already in use");
+    }
+    Ok(())
+}
+
+pub fn startup_snapshot() -> StartupSnapshot {
+    *STARTUP_SNAPSHOT.get_or_init(StartupSnapshot::default)
+}
+
+pub fn snapshot(isolate_kind: IsolateKind) -> Option<Snapshot> {
+    let data = match (startup_snapshot(), isolate_kind) {
+        (StartupSnapshot::Builtin, IsolateKind::Main) => MAIN_SNAPSHOT,
+        (StartupSnapshot::Builtin, IsolateKind::User) => USER_SNAPSHOT,
+        (StartupSnapshot::Builtin, IsolateKind::UserWithoutNet) => USER_NO_NET_SNAPSHOT,
+        (StartupSnapshot::Disabled, _) => return None,
+        (StartupSnapshot::Custom(custom), IsolateKind::Main) => custom.main,
+        (StartupSnapshot::Custom(custom), IsolateKind::User) => custom.user,
+        (StartupSnapshot::Custom(custom), IsolateKind::UserWithoutNet) => custom.user_without_net,
+    };
+    Some(Snapshot::Static(data))
+}
+
+// Extensions of the embedder, on top of the runtime's ones.
+pub fn extra_extensions() -> Vec<Extension> {
+    match startup_snapshot() {
+        StartupSnapshot::Custom(custom) => (custom.extensions)(),
+        _ => vec![],
+    }
 }
@@ -0,0 +1,85 @@
+// Runs the tests registered by a test module and responds with their results.
+// Placeholders are filled in by the test runner.
+const testModule = __TEST_MODULE__;
+const { fetchMocks, allowNet } = __TEST_CONFIG__;
+
+const tests = [];
+
+Deno.test = (nameOrOpts, optsOrFn, maybeFn) => {
+  if (typeof nameOrOpts === "function") {
+    tests.push({ name: nameOrOpts.name, fn: nameOrOpts });
+  } else if (typeof nameOrOpts === "string") {
+    if (typeof optsOrFn === "function") {
+      tests.push({ name: nameOrOpts, fn: optsOrFn });
+    } else {
+      tests.push({ ...optsOrFn, name: nameOrOpts, fn: maybeFn });
+    }
+  } else {
+    tests.push(nameOrOpts);
+  }
+};
+
+const originalFetch = globalThis.fetch;
+globalThis.fetch = (input, init) => {
+  const url = input instanceof Request ? input.url : String(input);
+  const mock = fetchMocks.find((m) => url.startsWith(m.urlPrefix));
+  if (mock) {
+    return Promise.resolve(
+      new Response(mock.body, { status: mock.status, headers: mock.headers }),
+    );
+  }
+  if (!allowNet) {
+    return Promise.reject(new TypeError(`fetch to ${url} is not mocked`));
+  }
+  return originalFetch(input, init);
+};
+
+async function runTests() {
+  try {
+    await import(testModule);
+  } catch (e) {
+    return { error: e?.stack ?? String(e), cases: [] };
+  }
+
+  const only = tests.filter((t) => t.only);
+  const selected = only.length > 0 ? only : tests;
+  const cases = [];
+
+  for (const t of tests) {
+    if (t.ignore || !selected.includes(t)) {
+      cases.push({ name: t.name, outcome: "ignored", durationMs: 0 });
+      continue;
+    }
+
+    const start = Date.now();
+    try {
+      await t.fn({ name: t.name });
+      cases.push({ name: t.name, outcome: "passed", durationMs: Date.now() - start });
+    } catch (e) {
+      cases.push({
+        name: t.name,
+        outcome: "failed",
+        durationMs: Date.now() - start,
+        error: e?.stack ?? String(e),
+      });
+    }
+  }
+
+  return { error: null, cases };
+}
+
+// serve a single request, so the worker exits once the results are sent
+const listener = Deno.listen({ port: 9999 });
+const conn = await listener.accept();
+const httpConn = Deno.serveHttp(conn);
+const requestEvent = await httpConn.nextRequest();
+if (requestEvent) {
+  const results = await runTests();
+  await requestEvent.respondWith(
+    new Response(JSON.stringify(results), {
+      headers: { "Content-Type": "application/json" },
+    }),
+  );
+}
+httpConn.close();
+listener.close();
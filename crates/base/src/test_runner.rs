@@ -0,0 +1,396 @@
+use crate::utils::files::collect_files;
+use crate::worker_ctx::WorkerContext;
+use anyhow::{anyhow, bail, Error};
+use deno_core::serde_json::{self, json};
+use hyper::{Body, Request};
+use sb_worker_context::essentials::{
+    EdgeContextInitOpts, EdgeContextOpts, EdgeUserRuntimeOpts, OutboundOpts,
+};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fmt::Write;
+use std::fs;
+use std::path::{Path, PathBuf};
+use url::Url;
+use uuid::Uuid;
+
+const TEST_HARNESS_TEMPLATE: &str = include_str!("test_harness.ts");
+
+// Response returned to `fetch` calls made by tests, for urls starting with `url_prefix`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct FetchMock {
+    pub url_prefix: String,
+    #[serde(default = "default_mock_status")]
+    pub status: u16,
+    #[serde(default)]
+    pub body: String,
+    #[serde(default)]
+    pub headers: Vec<(String, String)>,
+}
+
+fn default_mock_status() -> u16 {
+    200
+}
+
+#[derive(Debug, Clone)]
+pub struct TestRunnerOpts {
+    // the only env vars visible to the tests
+    pub env_vars: HashMap<String, String>,
+    pub fetch_mocks: Vec<FetchMock>,
+    // let fetch calls that aren't mocked reach the network
+    pub allow_net: bool,
+    pub import_map_path: Option<String>,
+    pub worker_timeout_ms: u64,
+}
+
+impl Default for TestRunnerOpts {
+    fn default() -> TestRunnerOpts {
+        TestRunnerOpts {
+            env_vars: HashMap::new(),
+            fetch_mocks: vec![],
+            allow_net: false,
+            import_map_path: None,
+            worker_timeout_ms: 30000,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TestOutcome {
+    Passed,
+    Failed,
+    Ignored,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TestCaseResult {
+    pub name: String,
+    pub outcome: TestOutcome,
+    pub duration_ms: u64,
+    pub error: Option<String>,
+}
+
+#[derive(Debug, Clone)]
+pub struct TestFileResult {
+    pub file: PathBuf,
+    pub cases: Vec<TestCaseResult>,
+}
+
+impl TestFileResult {
+    pub fn failed(&self) -> usize {
+        self.cases
+            .iter()
+            .filter(|c| c.outcome == TestOutcome::Failed)
+            .count()
+    }
+}
+
+#[derive(Deserialize)]
+struct HarnessResponse {
+    error: Option<String>,
+    cases: Vec<TestCaseResult>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TestReportFormat {
+    Pretty,
+    Tap,
+    JUnit,
+}
+
+fn is_test_file(path: &Path) -> bool {
+    let Some(name) = path.file_name().map(|n| n.to_string_lossy()) else {
+        return false;
+    };
+
+    ["ts", "tsx", "js", "jsx", "mjs", "mts"].iter().any(|ext| {
+        name.ends_with(&format!("_test.{}", ext)) || name.ends_with(&format!(".test.{}", ext))
+    })
+}
+
+// Finds the test files (`*_test.ts`, `*.test.ts`, ...) of a service.
+pub fn discover_tests(service_path: &Path) -> Result<Vec<PathBuf>, Error> {
+    collect_files(service_path, &is_test_file)
+}
+
+// Runs the tests of a single file in its own user worker.
+pub async fn run_test_file(file: &Path, opts: &TestRunnerOpts) -> Result<TestFileResult, Error> {
+    let module_url = Url::from_file_path(fs::canonicalize(file)?)
+        .map_err(|_| anyhow!("invalid test file path {:?}", file))?;
+
+    let harness_dir = std::env::temp_dir()
+        .join("edge-runtime")
+        .join(format!("test-{}", Uuid::new_v4()));
+    fs::create_dir_all(&harness_dir)?;
+    fs::write(
+        harness_dir.join("index.ts"),
+        TEST_HARNESS_TEMPLATE
+            .replace(
+                "__TEST_MODULE__",
+                &serde_json::to_string(module_url.as_str())?,
+            )
+            .replace(
+                "__TEST_CONFIG__",
+                &json!({
+                    "fetchMocks": opts.fetch_mocks,
+                    "allowNet": opts.allow_net,
+                })
+                .to_string(),
+            ),
+    )?;
+
+    let result = async {
+        let mut worker = WorkerContext::new(EdgeContextInitOpts {
+            service_path: harness_dir.clone(),
+            no_module_cache: false,
+            offline: false,
+            import_map_path: opts.import_map_path.clone(),
+            auth_tokens: None,
+            env_vars: opts.env_vars.clone(),
+            fetch_interceptor: None,
+            outbound: OutboundOpts::default(),
+            extensions: None,
+            unstable_features: vec![],
+            conf: EdgeContextOpts::UserWorker(EdgeUserRuntimeOpts {
+                worker_timeout_ms: opts.worker_timeout_ms,
+                id: format!("test:{}", file.display()),
+                ..Default::default()
+            }),
+        })
+        .await?;
+
+        let req = Request::builder()
+            .uri("http://localhost/")
+            .body(Body::empty())?;
+        let res = worker.send_request(req).await?;
+        let body = hyper::body::to_bytes(res.into_body()).await?;
+        let res: HarnessResponse = serde_json::from_slice(&body)?;
+
+        let mut cases = res.cases;
+        if let Some(error) = res.error {
+            // the module failed to load, report it as a failing test
+            cases.push(TestCaseResult {
+                name: "<module>".to_string(),
+                outcome: TestOutcome::Failed,
+                duration_ms: 0,
+                error: Some(error),
+            });
+        }
+
+        Ok::<_, Error>(TestFileResult {
+            file: file.to_path_buf(),
+            cases,
+        })
+    }
+    .await;
+
+    let _ = fs::remove_dir_all(&harness_dir);
+    result
+}
+
+pub async fn run_tests(
+    service_path: &Path,
+    opts: &TestRunnerOpts,
+) -> Result<Vec<TestFileResult>, Error> {
+    let files = discover_tests(service_path)?;
+    if files.is_empty() {
+        bail!("no test files found in {:?}", service_path);
+    }
+
+    let mut results = vec![];
+    for file in files {
+        results.push(run_test_file(&file, opts).await?);
+    }
+    Ok(results)
+}
+
+fn escape_xml(value: &str) -> String {
+    value
+        .replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&apos;")
+}
+
+pub fn format_report(results: &[TestFileResult], format: TestReportFormat) -> String {
+    let mut out = String::new();
+
+    match format {
+        TestReportFormat::Pretty => {
+            for result in results {
+                let _ = writeln!(
+                    out,
+                    "running {} tests from {}",
+                    result.cases.len(),
+                    result.file.display()
+                );
+                for case in &result.cases {
+                    let status = match case.outcome {
+                        TestOutcome::Passed => "ok",
+                        TestOutcome::Failed => "FAILED",
+                        TestOutcome::Ignored => "ignored",
+                    };
+                    let _ = writeln!(out, "{} ... {} ({}ms)", case.name, status, case.duration_ms);
+                    if let Some(error) = &case.error {
+                        let _ = writeln!(out, "{}", error);
+                    }
+                }
+            }
+
+            let count = |outcome: TestOutcome| -> usize {
+                results
+                    .iter()
+                    .flat_map(|r| r.cases.iter())
+                    .filter(|c| c.outcome == outcome)
+                    .count()
+            };
+            let failed = count(TestOutcome::Failed);
+            let _ = writeln!(
+                out,
+                "\n{} | {} passed | {} failed | {} ignored",
+                if failed == 0 { "ok" } else { "FAILED" },
+                count(TestOutcome::Passed),
+                failed,
+                count(TestOutcome::Ignored)
+            );
+        }
+        TestReportFormat::Tap => {
+            let total: usize = results.iter().map(|r| r.cases.len()).sum();
+            let _ = writeln!(out, "TAP version 13");
+            let _ = writeln!(out, "1..{}", total);
+
+            let mut n = 0;
+            for result in results {
+                for case in &result.cases {
+                    n += 1;
+                    let name = format!("{} > {}", result.file.display(), case.name);
+                    match case.outcome {
+                        TestOutcome::Passed => {
+                            let _ = writeln!(out, "ok {} - {}", n, name);
+                        }
+                        TestOutcome::Ignored => {
+                            let _ = writeln!(out, "ok {} - {} # SKIP", n, name);
+                        }
+                        TestOutcome::Failed => {
+                            let _ = writeln!(out, "not ok {} - {}", n, name);
+                            let _ = writeln!(out, "  ---");
+                            let message = case.error.as_deref().unwrap_or_default();
+                            let _ = writeln!(out, "  message: |");
+                            for line in message.lines() {
+                                let _ = writeln!(out, "    {}", line);
+                            }
+                            let _ = writeln!(out, "  ...");
+                        }
+                    }
+                }
+            }
+        }
+        TestReportFormat::JUnit => {
+            let total: usize = results.iter().map(|r| r.cases.len()).sum();
+            let failed: usize = results.iter().map(|r| r.failed()).sum();
+            let _ = writeln!(out, r#"<?xml version="1.0" encoding="UTF-8"?>"#);
+            let _ = writeln!(
+                out,
+                r#"<testsuites tests="{}" failures="{}">"#,
+                total, failed
+            );
+
+            for result in results {
+                let file = escape_xml(&result.file.display().to_string());
+                let skipped = result
+                    .cases
+                    .iter()
+                    .filter(|c| c.outcome == TestOutcome::Ignored)
+                    .count();
+                let time: u64 = result.cases.iter().map(|c| c.duration_ms).sum();
+                let _ = writeln!(
+                    out,
+                    r#"  <testsuite name="{}" tests="{}" failures="{}" skipped="{}" time="{:.3}">"#,
+                    file,
+                    result.cases.len(),
+                    result.failed(),
+                    skipped,
+                    time as f64 / 1000.0
+                );
+
+                for case in &result.cases {
+                    let _ = write!(
+                        out,
+                        r#"    <testcase name="{}" classname="{}" time="{:.3}""#,
+                        escape_xml(&case.name),
+                        file,
+                        case.duration_ms as f64 / 1000.0
+                    );
+                    match case.outcome {
+                        TestOutcome::Passed => {
+                            let _ = writeln!(out, "/>");
+                        }
+                        TestOutcome::Ignored => {
+                            let _ = writeln!(out, "><skipped/></testcase>");
+                        }
+                        TestOutcome::Failed => {
+                            let error = escape_xml(case.error.as_deref().unwrap_or_default());
+                            let message = error.lines().next().unwrap_or_default().to_string();
+                            let _ = writeln!(
+                                out,
+                                r#"><failure message="{}">{}</failure></testcase>"#,
+                                message, error
+                            );
+                        }
+                    }
+                }
+                let _ = writeln!(out, "  </testsuite>");
+            }
+            let _ = writeln!(out, "</testsuites>");
+        }
+    }
+
+    out
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_run_tests() {
+        let opts = TestRunnerOpts {
+            env_vars: HashMap::from([("GREETING".to_string(), "hello".to_string())]),
+            fetch_mocks: vec![FetchMock {
+                url_prefix: "https://api.example.com/".to_string(),
+                status: 200,
+                body: "mocked".to_string(),
+                headers: vec![],
+            }],
+            ..Default::default()
+        };
+
+        let results = run_tests(Path::new("./test_cases/test_runner"), &opts)
+            .await
+            .unwrap();
+        assert_eq!(results.len(), 1);
+
+        let outcomes: Vec<(&str, TestOutcome)> = results[0]
+            .cases
+            .iter()
+            .map(|c| (c.name.as_str(), c.outcome))
+            .collect();
+        assert_eq!(
+            outcomes,
+            vec![
+                ("reads injected env", TestOutcome::Passed),
+                ("uses mocked fetch", TestOutcome::Passed),
+                ("fails", TestOutcome::Failed),
+                ("is ignored", TestOutcome::Ignored),
+            ]
+        );
+
+        let report = format_report(&results, TestReportFormat::Tap);
+        assert!(report.starts_with("TAP version 13\n1..4\n"));
+        assert!(report.contains("not ok 3 - "));
+    }
+}
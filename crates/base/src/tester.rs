@@ -0,0 +1,896 @@
+use crate::worker_ctx::WorkerContext;
+use anyhow::{bail, Error};
+use bytes::Bytes;
+use deno_core::serde_json;
+use hyper::{Body, HeaderMap, Request, StatusCode};
+use sb_worker_context::essentials::{
+    EdgeContextInitOpts, EdgeContextOpts, EdgeUserRuntimeOpts, OutboundOpts,
+};
+use sb_worker_context::events::{LogEvent, WorkerEventWithMetadata, WorkerEvents};
+use serde::de::DeserializeOwned;
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+use tokio::sync::mpsc;
+
+#[derive(Debug)]
+pub struct TesterResponse {
+    pub status: StatusCode,
+    pub headers: HeaderMap,
+    pub body: Bytes,
+    pub duration: Duration,
+}
+
+impl TesterResponse {
+    pub fn text(&self) -> Result<String, Error> {
+        Ok(String::from_utf8(self.body.to_vec())?)
+    }
+
+    pub fn json<T: DeserializeOwned>(&self) -> Result<T, Error> {
+        Ok(serde_json::from_slice(&self.body)?)
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct TesterMetrics {
+    pub requests: u64,
+    // responses with a 5xx status
+    pub failed_requests: u64,
+    pub total_duration: Duration,
+    pub max_duration: Duration,
+}
+
+// Boots a service in-process and sends requests straight to its worker,
+// without going through the server or the worker pool. The console output
+// and the events of the worker are captured, so tests can assert on them.
+pub struct EdgeRuntimeTester {
+    worker: WorkerContext,
+    events_rx: mpsc::UnboundedReceiver<WorkerEventWithMetadata>,
+    events: Vec<WorkerEventWithMetadata>,
+    metrics: TesterMetrics,
+}
+
+impl EdgeRuntimeTester {
+    pub async fn new(service_path: impl Into<PathBuf>) -> Result<Self, Error> {
+        Self::with_opts(EdgeContextInitOpts {
+            service_path: service_path.into(),
+            no_module_cache: false,
+            offline: false,
+            import_map_path: None,
+            auth_tokens: None,
+            env_vars: HashMap::new(),
+            fetch_interceptor: None,
+            outbound: OutboundOpts::default(),
+            extensions: None,
+            unstable_features: vec![],
+            conf: EdgeContextOpts::UserWorker(EdgeUserRuntimeOpts {
+                id: "tester".to_string(),
+                ..Default::default()
+            }),
+        })
+        .await
+    }
+
+    pub async fn with_opts(mut opts: EdgeContextInitOpts) -> Result<Self, Error> {
+        let (events_tx, events_rx) = mpsc::unbounded_channel::<WorkerEventWithMetadata>();
+        match &mut opts.conf {
+            EdgeContextOpts::UserWorker(user_opts) => {
+                user_opts.events_tx = Some(events_tx);
+                user_opts.forward_logs = true;
+            }
+            EdgeContextOpts::MainWorker(_) => bail!("only user workers can be tested"),
+        }
+
+        let worker = WorkerContext::new(opts).await?;
+        Ok(Self {
+            worker,
+            events_rx,
+            events: vec![],
+            metrics: TesterMetrics::default(),
+        })
+    }
+
+    pub async fn request(&mut self, req: Request<Body>) -> Result<TesterResponse, Error> {
+        let start = Instant::now();
+        let res = self.worker.send_request(req).await?;
+        let (parts, body) = res.into_parts();
+        let body = hyper::body::to_bytes(body).await?;
+        let duration = start.elapsed();
+
+        self.metrics.requests += 1;
+        if parts.status.is_server_error() {
+            self.metrics.failed_requests += 1;
+        }
+        self.metrics.total_duration += duration;
+        self.metrics.max_duration = self.metrics.max_duration.max(duration);
+
+        Ok(TesterResponse {
+            status: parts.status,
+            headers: parts.headers,
+            body,
+            duration,
+        })
+    }
+
+    // All the events emitted by the worker so far.
+    pub fn events(&mut self) -> &[WorkerEventWithMetadata] {
+        while let Ok(event) = self.events_rx.try_recv() {
+            self.events.push(event);
+        }
+        &self.events
+    }
+
+    pub fn logs(&mut self) -> Vec<LogEvent> {
+        self.events()
+            .iter()
+            .filter_map(|e| match &e.event {
+                WorkerEvents::Log(log) => Some(log.clone()),
+                _ => None,
+            })
+            .collect()
+    }
+
+    pub fn metrics(&self) -> &TesterMetrics {
+        &self.metrics
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use sb_worker_context::essentials::{
+        BlobSpillOpts, FetchBreakerOpts, FetchPolicyOpts, LogOpts,
+    };
+    use sb_worker_context::events::LogLevel;
+    use sb_worker_context::fetch::{MockFetchLayer, StubResponse};
+    use sb_worker_context::fetch_breaker::{BreakerState, FetchBreakers};
+    use sb_worker_context::net_usage::{NetUsage, Traffic};
+    use sb_worker_context::open_sockets::{OpenSockets, SocketUsage};
+    use std::sync::Arc;
+
+    #[tokio::test]
+    async fn test_request_and_capture_logs() {
+        let mut tester = EdgeRuntimeTester::new("./test_cases/tester").await.unwrap();
+
+        let req = Request::get("http://localhost/hello")
+            .body(Body::empty())
+            .unwrap();
+        let res = tester.request(req).await.unwrap();
+        assert_eq!(res.status, StatusCode::OK);
+        assert_eq!(res.text().unwrap(), "hello");
+
+        let logs = tester.logs();
+        assert_eq!(logs.len(), 1);
+        assert_eq!(logs[0].msg, "handling /hello");
+        assert_eq!(tester.metrics().requests, 1);
+    }
+
+    #[tokio::test]
+    async fn test_fetch_interception() {
+        let layer = Arc::new(MockFetchLayer::new().stub(
+            "https://api.example.com/",
+            StubResponse {
+                status: 201,
+                headers: vec![],
+                body: Some("stubbed".to_string()),
+            },
+        ));
+
+        let mut tester = EdgeRuntimeTester::with_opts(EdgeContextInitOpts {
+            service_path: "./test_cases/fetch_interception".into(),
+            no_module_cache: false,
+            offline: false,
+            import_map_path: None,
+            auth_tokens: None,
+            env_vars: HashMap::new(),
+            fetch_interceptor: Some(layer.clone()),
+            outbound: OutboundOpts::default(),
+            extensions: None,
+            unstable_features: vec![],
+            conf: EdgeContextOpts::UserWorker(EdgeUserRuntimeOpts {
+                id: "tester".to_string(),
+                ..Default::default()
+            }),
+        })
+        .await
+        .unwrap();
+
+        let req = Request::get("http://localhost/")
+            .body(Body::empty())
+            .unwrap();
+        let res = tester.request(req).await.unwrap();
+        assert_eq!(res.status, StatusCode::CREATED);
+        assert_eq!(res.text().unwrap(), "stubbed");
+
+        let recorded = layer.recorded();
+        assert_eq!(recorded.len(), 1);
+        assert_eq!(recorded[0].method, "POST");
+        assert_eq!(recorded[0].url, "https://api.example.com/users");
+        assert_eq!(recorded[0].body.as_deref(), Some("ping"));
+    }
+
+    #[tokio::test]
+    async fn test_fetch_response_size_limit() {
+        let stub = |body: &str| StubResponse {
+            status: 200,
+            headers: vec![],
+            body: Some(body.to_string()),
+        };
+        let layer = Arc::new(
+            MockFetchLayer::new()
+                .stub("https://api.example.com/small", stub("ok"))
+                .stub("https://api.example.com/large", stub(&"x".repeat(100))),
+        );
+
+        let mut tester = EdgeRuntimeTester::with_opts(EdgeContextInitOpts {
+            service_path: "./test_cases/fetch_size_limit".into(),
+            no_module_cache: false,
+            offline: false,
+            import_map_path: None,
+            auth_tokens: None,
+            env_vars: HashMap::new(),
+            fetch_interceptor: Some(layer),
+            outbound: OutboundOpts::default(),
+            extensions: None,
+            unstable_features: vec![],
+            conf: EdgeContextOpts::UserWorker(EdgeUserRuntimeOpts {
+                id: "tester".to_string(),
+                max_fetch_response_bytes: Some(10),
+                ..Default::default()
+            }),
+        })
+        .await
+        .unwrap();
+
+        let req = Request::get("http://localhost/small")
+            .body(Body::empty())
+            .unwrap();
+        let res = tester.request(req).await.unwrap();
+        assert_eq!(res.text().unwrap(), "ok");
+
+        let req = Request::get("http://localhost/large")
+            .body(Body::empty())
+            .unwrap();
+        let res = tester.request(req).await.unwrap();
+        assert_eq!(res.status, StatusCode::INTERNAL_SERVER_ERROR);
+        assert_eq!(res.text().unwrap(), "RangeError");
+    }
+
+    #[tokio::test]
+    async fn test_net_usage() {
+        let layer = Arc::new(MockFetchLayer::new().stub(
+            "https://api.example.com/",
+            StubResponse {
+                status: 200,
+                headers: vec![],
+                body: Some("pong!".to_string()),
+            },
+        ));
+        let net_usage = Arc::new(NetUsage::default());
+
+        let mut tester = EdgeRuntimeTester::with_opts(EdgeContextInitOpts {
+            service_path: "./test_cases/net_usage".into(),
+            no_module_cache: false,
+            offline: false,
+            import_map_path: None,
+            auth_tokens: None,
+            env_vars: HashMap::new(),
+            fetch_interceptor: Some(layer),
+            outbound: OutboundOpts::default(),
+            extensions: None,
+            unstable_features: vec![],
+            conf: EdgeContextOpts::UserWorker(EdgeUserRuntimeOpts {
+                id: "tester".to_string(),
+                net_usage: net_usage.clone(),
+                ..Default::default()
+            }),
+        })
+        .await
+        .unwrap();
+
+        for _ in 0..2 {
+            let req = Request::get("http://localhost/")
+                .body(Body::empty())
+                .unwrap();
+            let res = tester.request(req).await.unwrap();
+            assert_eq!(res.text().unwrap(), "pong!");
+        }
+
+        let usage = net_usage.snapshot();
+        assert_eq!(
+            usage.fetch,
+            Traffic {
+                sent: 8,
+                received: 10
+            }
+        );
+        assert_eq!(usage.net, Traffic::default());
+        assert_eq!(usage.total(), usage.fetch);
+    }
+
+    #[tokio::test]
+    async fn test_compression_streams() {
+        let mut tester = EdgeRuntimeTester::new("./test_cases/compression")
+            .await
+            .unwrap();
+
+        let req = Request::get("http://localhost/")
+            .body(Body::empty())
+            .unwrap();
+        let res = tester.request(req).await.unwrap();
+        let results: serde_json::Value = res.json().unwrap();
+        for format in ["gzip", "deflate", "deflate-raw", "br"] {
+            assert_eq!(results[format]["ok"], true, "{} round trip", format);
+            // the text repeats itself, it can't be compressed this poorly
+            assert!(results[format]["compressed"].as_u64().unwrap() < 200);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_blob_spill() {
+        let mut tester = EdgeRuntimeTester::with_opts(EdgeContextInitOpts {
+            service_path: "./test_cases/blob_spill".into(),
+            no_module_cache: false,
+            offline: false,
+            import_map_path: None,
+            auth_tokens: None,
+            env_vars: HashMap::new(),
+            fetch_interceptor: None,
+            outbound: OutboundOpts::default(),
+            extensions: None,
+            unstable_features: vec![],
+            conf: EdgeContextOpts::UserWorker(EdgeUserRuntimeOpts {
+                id: "tester".to_string(),
+                blob_spill: Some(BlobSpillOpts {
+                    threshold_bytes: 1024 * 1024,
+                    max_bytes: 3 * 1024 * 1024,
+                }),
+                ..Default::default()
+            }),
+        })
+        .await
+        .unwrap();
+
+        let req = Request::get("http://localhost/")
+            .body(Body::empty())
+            .unwrap();
+        let res = tester.request(req).await.unwrap();
+        let results: serde_json::Value = res.json().unwrap();
+        assert_eq!(results["text"], "aaa");
+        assert_eq!(results["size"], 2 * 1024 * 1024);
+        assert_eq!(results["small"], "hello");
+        // a second large blob would go past the cap
+        assert_eq!(results["overCap"], "RangeError");
+    }
+
+    #[tokio::test]
+    async fn test_multipart() {
+        let mut tester = EdgeRuntimeTester::new("./test_cases/multipart")
+            .await
+            .unwrap();
+        let body = [
+            "preamble\r\n",
+            "--XyZ\r\n",
+            "Content-Disposition: form-data; name=\"title\"\r\n\r\n",
+            "hello --XY\r\nworld\r\n",
+            "--XyZ\r\n",
+            "Content-Disposition: form-data; name=\"upload\"; filename=\"a.txt\"\r\n",
+            "Content-Type: text/csv\r\n\r\n",
+            "a,b,c\r\n",
+            "--XyZ--\r\n",
+        ]
+        .concat();
+        let request = |uri: &str| {
+            Request::post(uri)
+                .header("content-type", "multipart/form-data; boundary=XyZ")
+                .body(Body::from(body.clone()))
+                .unwrap()
+        };
+
+        let res = tester.request(request("http://localhost/")).await.unwrap();
+        assert_eq!(res.status, StatusCode::OK);
+        assert_eq!(
+            res.json::<serde_json::Value>().unwrap(),
+            serde_json::json!([
+                { "name": "title", "value": "hello --XY\r\nworld" },
+                { "name": "upload", "filename": "a.txt", "type": "text/csv" },
+            ])
+        );
+
+        let res = tester
+            .request(request("http://localhost/?maxPartBytes=8"))
+            .await
+            .unwrap();
+        assert_eq!(res.status, StatusCode::PAYLOAD_TOO_LARGE);
+        assert_eq!(res.text().unwrap(), "RangeError");
+    }
+
+    #[tokio::test]
+    async fn test_stream_transforms() {
+        use flate2::write::GzEncoder;
+        use flate2::Compression;
+        use hyper::service::{make_service_fn, service_fn};
+        use std::convert::Infallible;
+        use std::io::Write;
+
+        // multi-byte characters, some of them cut across chunks
+        let text = "héllo wörld ✓ ".repeat(10_000);
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(text.as_bytes()).unwrap();
+        let gzipped = Bytes::from(encoder.finish().unwrap());
+
+        let make_svc = make_service_fn(move |_| {
+            let gzipped = gzipped.clone();
+            async move {
+                Ok::<_, Infallible>(service_fn(move |_| {
+                    let gzipped = gzipped.clone();
+                    async move {
+                        let chunks: Vec<Result<Bytes, Infallible>> = gzipped
+                            .chunks(1000)
+                            .map(|chunk| Ok(Bytes::copy_from_slice(chunk)))
+                            .collect();
+                        Ok::<_, Infallible>(hyper::Response::new(Body::wrap_stream(
+                            deno_core::futures::stream::iter(chunks),
+                        )))
+                    }
+                }))
+            }
+        });
+        let upstream = hyper::Server::bind(&([127, 0, 0, 1], 0).into()).serve(make_svc);
+        let upstream_addr = upstream.local_addr();
+        tokio::spawn(upstream);
+
+        let mut tester = EdgeRuntimeTester::new("./test_cases/stream_transforms")
+            .await
+            .unwrap();
+        let req = Request::get(format!(
+            "http://localhost/?upstream=http://{}/",
+            upstream_addr
+        ))
+        .body(Body::empty())
+        .unwrap();
+        let res = tester.request(req).await.unwrap();
+        assert_eq!(res.status, StatusCode::OK);
+        assert_eq!(res.text().unwrap(), text);
+    }
+
+    #[tokio::test]
+    async fn test_fetch_breaker() {
+        use hyper::service::{make_service_fn, service_fn};
+        use std::convert::Infallible;
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        let calls = Arc::new(AtomicUsize::new(0));
+        let upstream_calls = calls.clone();
+        let make_svc = make_service_fn(move |_| {
+            let calls = upstream_calls.clone();
+            async move {
+                Ok::<_, Infallible>(service_fn(move |_| {
+                    calls.fetch_add(1, Ordering::SeqCst);
+                    async move {
+                        let mut res = hyper::Response::new(Body::empty());
+                        *res.status_mut() = StatusCode::SERVICE_UNAVAILABLE;
+                        Ok::<_, Infallible>(res)
+                    }
+                }))
+            }
+        });
+        let upstream = hyper::Server::bind(&([127, 0, 0, 1], 0).into()).serve(make_svc);
+        let upstream_addr = upstream.local_addr();
+        tokio::spawn(upstream);
+
+        let breakers = FetchBreakers::new(FetchBreakerOpts {
+            failure_threshold: 2,
+            cooldown_ms: 60000,
+        });
+        let mut tester = EdgeRuntimeTester::with_opts(EdgeContextInitOpts {
+            service_path: "./test_cases/fetch_breaker".into(),
+            no_module_cache: false,
+            offline: false,
+            import_map_path: None,
+            auth_tokens: None,
+            env_vars: HashMap::new(),
+            fetch_interceptor: None,
+            outbound: OutboundOpts::default(),
+            extensions: None,
+            unstable_features: vec![],
+            conf: EdgeContextOpts::UserWorker(EdgeUserRuntimeOpts {
+                id: "tester".to_string(),
+                fetch_breakers: Some(breakers.clone()),
+                ..Default::default()
+            }),
+        })
+        .await
+        .unwrap();
+
+        let req = Request::get(format!(
+            "http://localhost/?upstream=http://{}/&calls=3",
+            upstream_addr
+        ))
+        .body(Body::empty())
+        .unwrap();
+        let res = tester.request(req).await.unwrap();
+        let results: serde_json::Value = res.json().unwrap();
+        assert_eq!(results[0]["status"], 503);
+        assert_eq!(results[1]["status"], 503);
+        // the third call doesn't reach the upstream
+        assert_eq!(results[2]["error"], "TypeError");
+        assert!(results[2]["message"].as_str().unwrap().contains("circuit"));
+        assert_eq!(calls.load(Ordering::SeqCst), 2);
+
+        let snapshot = breakers.snapshot();
+        assert_eq!(snapshot.len(), 1);
+        assert_eq!(snapshot[0].host, upstream_addr.to_string());
+        assert_eq!(snapshot[0].state, BreakerState::Open);
+        assert_eq!(snapshot[0].consecutive_failures, 2);
+    }
+
+    #[tokio::test]
+    async fn test_fetch_policy() {
+        use hyper::service::{make_service_fn, service_fn};
+        use std::convert::Infallible;
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        // `/flaky` fails twice before answering, `/hang` never answers
+        let calls = Arc::new(AtomicUsize::new(0));
+        let upstream_calls = calls.clone();
+        let make_svc = make_service_fn(move |_| {
+            let calls = upstream_calls.clone();
+            async move {
+                Ok::<_, Infallible>(service_fn(move |req: Request<Body>| {
+                    let call = calls.fetch_add(1, Ordering::SeqCst);
+                    async move {
+                        if req.uri().path() == "/hang" {
+                            tokio::time::sleep(Duration::from_secs(60)).await;
+                        }
+                        let mut res = hyper::Response::new(Body::from("ok"));
+                        if call < 2 {
+                            *res.status_mut() = StatusCode::SERVICE_UNAVAILABLE;
+                        }
+                        Ok::<_, Infallible>(res)
+                    }
+                }))
+            }
+        });
+        let upstream = hyper::Server::bind(&([127, 0, 0, 1], 0).into()).serve(make_svc);
+        let upstream_addr = upstream.local_addr();
+        tokio::spawn(upstream);
+
+        let mut tester = EdgeRuntimeTester::with_opts(EdgeContextInitOpts {
+            service_path: "./test_cases/fetch_breaker".into(),
+            no_module_cache: false,
+            offline: false,
+            import_map_path: None,
+            auth_tokens: None,
+            env_vars: HashMap::new(),
+            fetch_interceptor: None,
+            outbound: OutboundOpts::default(),
+            extensions: None,
+            unstable_features: vec![],
+            conf: EdgeContextOpts::UserWorker(EdgeUserRuntimeOpts {
+                id: "tester".to_string(),
+                fetch_policy: FetchPolicyOpts {
+                    connect_timeout_ms: Some(200),
+                    read_timeout_ms: Some(200),
+                    retries: 2,
+                    retry_backoff_ms: 10,
+                },
+                ..Default::default()
+            }),
+        })
+        .await
+        .unwrap();
+
+        let req = Request::get(format!(
+            "http://localhost/?upstream=http://{}/flaky&calls=1",
+            upstream_addr
+        ))
+        .body(Body::empty())
+        .unwrap();
+        let res = tester.request(req).await.unwrap();
+        let results: serde_json::Value = res.json().unwrap();
+        assert_eq!(results[0]["status"], 200);
+        assert_eq!(calls.load(Ordering::SeqCst), 3);
+
+        let req = Request::get(format!(
+            "http://localhost/?upstream=http://{}/hang&calls=1",
+            upstream_addr
+        ))
+        .body(Body::empty())
+        .unwrap();
+        let res = tester.request(req).await.unwrap();
+        let results: serde_json::Value = res.json().unwrap();
+        assert_eq!(results[0]["error"], "TimeoutError");
+        // the timed out call was sent again twice
+        assert_eq!(calls.load(Ordering::SeqCst), 6);
+    }
+
+    #[cfg(feature = "jwt")]
+    #[tokio::test]
+    async fn test_jwt_verify_with_cached_jwks() {
+        use hyper::service::{make_service_fn, service_fn};
+        use ring::rand::SystemRandom;
+        use ring::signature::{Ed25519KeyPair, KeyPair};
+        use std::convert::Infallible;
+        use std::sync::atomic::{AtomicUsize, Ordering};
+        use std::time::{SystemTime, UNIX_EPOCH};
+
+        let pkcs8 = Ed25519KeyPair::generate_pkcs8(&SystemRandom::new()).unwrap();
+        let pair = Ed25519KeyPair::from_pkcs8(pkcs8.as_ref()).unwrap();
+        let b64 = |bytes: &[u8]| base64::encode_config(bytes, base64::URL_SAFE_NO_PAD);
+        let jwks = serde_json::json!({
+            "keys": [{
+                "kty": "OKP",
+                "crv": "Ed25519",
+                "kid": "k1",
+                "alg": "EdDSA",
+                "x": b64(pair.public_key().as_ref()),
+            }]
+        })
+        .to_string();
+        let token = |claims: serde_json::Value| {
+            let signed = format!(
+                "{}.{}",
+                b64(br#"{"alg":"EdDSA","kid":"k1"}"#),
+                b64(claims.to_string().as_bytes())
+            );
+            let signature = pair.sign(signed.as_bytes());
+            format!("{}.{}", signed, b64(signature.as_ref()))
+        };
+
+        let fetches = Arc::new(AtomicUsize::new(0));
+        let server_fetches = fetches.clone();
+        let make_svc = make_service_fn(move |_| {
+            let fetches = server_fetches.clone();
+            let jwks = jwks.clone();
+            async move {
+                Ok::<_, Infallible>(service_fn(move |_req: Request<Body>| {
+                    fetches.fetch_add(1, Ordering::SeqCst);
+                    let jwks = jwks.clone();
+                    async move { Ok::<_, Infallible>(hyper::Response::new(Body::from(jwks))) }
+                }))
+            }
+        });
+        let server = hyper::Server::bind(&([127, 0, 0, 1], 0).into()).serve(make_svc);
+        let jwks_url = format!("http://{}/.well-known/jwks.json", server.local_addr());
+        tokio::spawn(server);
+
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs();
+        let valid = token(serde_json::json!({ "sub": "user-1", "exp": now + 3600 }));
+        let expired = token(serde_json::json!({ "sub": "user-1", "exp": now - 3600 }));
+        async fn verify(
+            tester: &mut EdgeRuntimeTester,
+            jwks_url: &str,
+            token: &str,
+        ) -> serde_json::Value {
+            let req = Request::get(format!("http://localhost/?jwks={}", jwks_url))
+                .header("authorization", format!("Bearer {}", token))
+                .body(Body::empty())
+                .unwrap();
+            tester.request(req).await.unwrap().json().unwrap()
+        }
+
+        // the key set is fetched once for both workers
+        let mut first = EdgeRuntimeTester::new("./test_cases/jwt").await.unwrap();
+        let mut second = EdgeRuntimeTester::new("./test_cases/jwt").await.unwrap();
+        for tester in [&mut first, &mut second] {
+            assert_eq!(
+                verify(tester, &jwks_url, &valid).await,
+                serde_json::json!({ "kid": "k1", "sub": "user-1" })
+            );
+        }
+        assert_eq!(fetches.load(Ordering::SeqCst), 1);
+
+        let body = verify(&mut first, &jwks_url, &expired).await;
+        assert_eq!(body["error"], "InvalidData");
+    }
+
+    // the hits of a key are counted across the workers of the service
+    #[cfg(feature = "rate_limit")]
+    #[tokio::test]
+    async fn test_rate_limit() {
+        let key = uuid::Uuid::new_v4().to_string();
+        let url = format!("http://localhost/?key={}", key);
+        let mut first = EdgeRuntimeTester::new("./test_cases/rate_limit")
+            .await
+            .unwrap();
+        let mut second = EdgeRuntimeTester::new("./test_cases/rate_limit")
+            .await
+            .unwrap();
+
+        let mut results = vec![];
+        for tester in [&mut first, &mut second, &mut first] {
+            let req = Request::get(&url).body(Body::empty()).unwrap();
+            let res: serde_json::Value = tester.request(req).await.unwrap().json().unwrap();
+            results.push((res["allowed"].clone(), res["remaining"].clone()));
+        }
+        assert_eq!(
+            results,
+            vec![
+                (true.into(), 1.into()),
+                (true.into(), 0.into()),
+                (false.into(), 0.into())
+            ]
+        );
+    }
+
+    // entries are seen by every worker of the service, and outlive them
+    #[cfg(feature = "cache")]
+    #[tokio::test]
+    async fn test_cache() {
+        let key = uuid::Uuid::new_v4().to_string();
+        let mut first = EdgeRuntimeTester::new("./test_cases/cache").await.unwrap();
+
+        let url = format!("http://localhost/?key={}&value=on", key);
+        let req = Request::get(&url).body(Body::empty()).unwrap();
+        let res: serde_json::Value = first.request(req).await.unwrap().json().unwrap();
+        assert_eq!(res, serde_json::json!({ "value": "on" }));
+        drop(first);
+
+        let mut second = EdgeRuntimeTester::new("./test_cases/cache").await.unwrap();
+        let url = format!("http://localhost/?key={}", key);
+        let req = Request::get(&url).body(Body::empty()).unwrap();
+        let res: serde_json::Value = second.request(req).await.unwrap().json().unwrap();
+        assert_eq!(res, serde_json::json!({ "value": "on" }));
+    }
+
+    #[tokio::test]
+    async fn test_max_open_sockets() {
+        let upstream = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = upstream.local_addr().unwrap().port();
+        tokio::spawn(async move {
+            let mut conns = vec![];
+            while let Ok((conn, _)) = upstream.accept().await {
+                conns.push(conn);
+            }
+        });
+
+        let socket_usage = Arc::new(SocketUsage::default());
+        let mut tester = EdgeRuntimeTester::with_opts(EdgeContextInitOpts {
+            service_path: "./test_cases/open_sockets".into(),
+            no_module_cache: false,
+            offline: false,
+            import_map_path: None,
+            auth_tokens: None,
+            env_vars: HashMap::new(),
+            fetch_interceptor: None,
+            outbound: OutboundOpts::default(),
+            extensions: None,
+            unstable_features: vec![],
+            conf: EdgeContextOpts::UserWorker(EdgeUserRuntimeOpts {
+                id: "tester".to_string(),
+                max_open_sockets: Some(2),
+                socket_usage: socket_usage.clone(),
+                ..Default::default()
+            }),
+        })
+        .await
+        .unwrap();
+
+        let req = Request::get(format!("http://localhost/?port={}&attempts=3", port))
+            .body(Body::empty())
+            .unwrap();
+        let res = tester.request(req).await.unwrap();
+        let results: serde_json::Value = res.json().unwrap();
+        assert_eq!(results[0]["ok"], true);
+        assert_eq!(results[1]["ok"], true);
+        assert_eq!(results[2]["error"], "QuotaExceededError");
+        assert_eq!(results[3]["ok"], true);
+
+        // the connections the first request left open still count
+        let req = Request::get(format!("http://localhost/?port={}&attempts=0", port))
+            .body(Body::empty())
+            .unwrap();
+        let res = tester.request(req).await.unwrap();
+        let results: serde_json::Value = res.json().unwrap();
+        assert_eq!(results[0]["error"], "QuotaExceededError");
+        assert_eq!(
+            socket_usage.snapshot(),
+            OpenSockets {
+                connections: 2,
+                ..Default::default()
+            }
+        );
+    }
+
+    #[tokio::test]
+    async fn test_log_limits() {
+        let mut tester = EdgeRuntimeTester::with_opts(EdgeContextInitOpts {
+            service_path: "./test_cases/log_limits".into(),
+            no_module_cache: false,
+            offline: false,
+            import_map_path: None,
+            auth_tokens: None,
+            env_vars: HashMap::new(),
+            fetch_interceptor: None,
+            outbound: OutboundOpts::default(),
+            extensions: None,
+            unstable_features: vec![],
+            conf: EdgeContextOpts::UserWorker(EdgeUserRuntimeOpts {
+                id: "tester".to_string(),
+                logs: Some(LogOpts {
+                    level: LogLevel::Info,
+                    rate_per_sec: 1.0,
+                    burst: 3,
+                }),
+                ..Default::default()
+            }),
+        })
+        .await
+        .unwrap();
+
+        let req = Request::get("http://localhost/burst")
+            .body(Body::empty())
+            .unwrap();
+        tester.request(req).await.unwrap();
+        // refills a token
+        tokio::time::sleep(Duration::from_millis(1100)).await;
+        let req = Request::get("http://localhost/after")
+            .body(Body::empty())
+            .unwrap();
+        tester.request(req).await.unwrap();
+
+        let msgs: Vec<String> = tester.logs().into_iter().map(|log| log.msg).collect();
+        assert_eq!(
+            msgs,
+            vec![
+                "line 0",
+                "line 1",
+                "line 2",
+                "[7 messages dropped]",
+                "after"
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_request_headers() {
+        let mut tester = EdgeRuntimeTester::new("./test_cases/request_headers")
+            .await
+            .unwrap();
+
+        for _ in 0..2 {
+            let req = Request::get("http://localhost/")
+                .header("x-a", "1")
+                .header("cookie", "a=1")
+                .header("x-b", "2")
+                .header("cookie", "b=2")
+                .body(Body::empty())
+                .unwrap();
+            let res = tester.request(req).await.unwrap();
+            let headers: Vec<(String, String)> = res.json().unwrap();
+            assert!(headers.contains(&("cookie".to_string(), "a=1; b=2".to_string())));
+            assert!(headers.contains(&("x-a".to_string(), "1".to_string())));
+            assert!(headers.contains(&("x-b".to_string(), "2".to_string())));
+        }
+    }
+
+    #[tokio::test]
+    async fn test_unhandled_rejection_request_ids() {
+        let mut tester = EdgeRuntimeTester::new("./test_cases/uncaught_errors")
+            .await
+            .unwrap();
+
+        let req = Request::get("http://localhost/")
+            .header("x-request-id", "req-1")
+            .body(Body::empty())
+            .unwrap();
+        let res = tester.request(req).await.unwrap();
+        assert_eq!(res.text().unwrap(), "ok");
+
+        let rejections: Vec<_> = tester
+            .events()
+            .iter()
+            .filter_map(|e| match &e.event {
+                WorkerEvents::UnhandledRejection(ev) => Some(ev.clone()),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(rejections.len(), 1);
+        assert!(rejections[0].message.contains("boom"));
+        assert_eq!(rejections[0].request_ids, vec!["req-1".to_string()]);
+    }
+}
@@ -0,0 +1,151 @@
+use anyhow::{bail, Context, Error};
+use std::fmt;
+use std::path::{Path, PathBuf};
+use tokio::process::Command;
+
+// Type checking is delegated to the deno CLI, as the runtime doesn't ship
+// with a TypeScript compiler. The binary can be set with `DENO_BIN`.
+fn deno_bin() -> PathBuf {
+    std::env::var_os("DENO_BIN")
+        .map(PathBuf::from)
+        .unwrap_or_else(|| PathBuf::from("deno"))
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct TypeDiagnostic {
+    pub code: String,
+    pub message: String,
+    pub file: Option<String>,
+    pub line: Option<u32>,
+    pub column: Option<u32>,
+}
+
+impl fmt::Display for TypeDiagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match (&self.file, self.line, self.column) {
+            (Some(file), Some(line), Some(column)) => write!(
+                f,
+                "{}:{}:{} - {} {}",
+                file, line, column, self.code, self.message
+            ),
+            _ => write!(f, "{} {}", self.code, self.message),
+        }
+    }
+}
+
+// Parses the diagnostics printed by `deno check`, eg:
+//
+// TS2322 [ERROR]: Type 'number' is not assignable to type 'string'.
+// const x: string = 1;
+//       ^
+//     at file:///services/hello/index.ts:1:7
+fn parse_diagnostics(output: &str) -> Vec<TypeDiagnostic> {
+    let mut diagnostics: Vec<TypeDiagnostic> = vec![];
+
+    for line in output.lines() {
+        let line = line.trim();
+        let line = line.strip_prefix("error: ").unwrap_or(line);
+
+        if let Some((code, message)) = line.split_once(" [ERROR]: ") {
+            if code.starts_with("TS") {
+                diagnostics.push(TypeDiagnostic {
+                    code: code.to_string(),
+                    message: message.to_string(),
+                    file: None,
+                    line: None,
+                    column: None,
+                });
+            }
+            continue;
+        }
+
+        let Some(location) = line.strip_prefix("at ") else {
+            continue;
+        };
+        let Some(diagnostic) = diagnostics.last_mut() else {
+            continue;
+        };
+        if diagnostic.file.is_some() {
+            continue;
+        }
+
+        let mut parts = location.rsplitn(3, ':');
+        let column = parts.next().and_then(|c| c.parse().ok());
+        let line = parts.next().and_then(|l| l.parse().ok());
+        if let (Some(file), Some(line), Some(column)) = (parts.next(), line, column) {
+            diagnostic.file = Some(file.to_string());
+            diagnostic.line = Some(line);
+            diagnostic.column = Some(column);
+        }
+    }
+
+    diagnostics
+}
+
+// Type checks the entrypoint of a service and the modules it imports.
+// Returns the diagnostics found, an empty list means the service type checks.
+pub async fn type_check_service(
+    service_path: &Path,
+    import_map_path: Option<&str>,
+) -> Result<Vec<TypeDiagnostic>, Error> {
+    let entrypoint = service_path.join("index.ts");
+    if !entrypoint.exists() {
+        bail!("service entrypoint not found in {:?}", service_path);
+    }
+
+    let mut cmd = Command::new(deno_bin());
+    cmd.arg("check").arg("--quiet").env("NO_COLOR", "1");
+    if let Some(import_map_path) = import_map_path {
+        cmd.arg("--import-map").arg(import_map_path);
+    }
+    cmd.arg(&entrypoint);
+
+    let output = cmd
+        .output()
+        .await
+        .context("failed to run the type checker, is deno installed?")?;
+    if output.status.success() {
+        return Ok(vec![]);
+    }
+
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    let diagnostics = parse_diagnostics(&stderr);
+    if diagnostics.is_empty() {
+        // eg: a module that couldn't be resolved
+        bail!("type checking {:?} failed: {}", entrypoint, stderr.trim());
+    }
+
+    Ok(diagnostics)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse_diagnostics() {
+        let output = r#"
+error: TS2322 [ERROR]: Type 'number' is not assignable to type 'string'.
+const x: string = 1;
+      ^
+    at file:///services/hello/index.ts:1:7
+
+TS2304 [ERROR]: Cannot find name 'foo'.
+foo();
+~~~
+    at file:///services/hello/utils.ts:12:3
+
+Found 2 errors.
+"#;
+
+        let diagnostics = parse_diagnostics(output);
+        assert_eq!(diagnostics.len(), 2);
+        assert_eq!(
+            diagnostics[0].to_string(),
+            "file:///services/hello/index.ts:1:7 - TS2322 Type 'number' is not assignable to type 'string'."
+        );
+        assert_eq!(diagnostics[1].code, "TS2304");
+        assert_eq!(diagnostics[1].line, Some(12));
+        assert_eq!(diagnostics[1].column, Some(3));
+    }
+}
@@ -0,0 +1,36 @@
+use anyhow::Error;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+// Recursively collects the files in `dir` matching `filter`, skipping hidden
+// entries and `node_modules`. The result is sorted.
+pub fn collect_files(dir: &Path, filter: &dyn Fn(&Path) -> bool) -> Result<Vec<PathBuf>, Error> {
+    let mut files = vec![];
+    collect_files_into(dir, filter, &mut files)?;
+    files.sort();
+    Ok(files)
+}
+
+fn collect_files_into(
+    dir: &Path,
+    filter: &dyn Fn(&Path) -> bool,
+    files: &mut Vec<PathBuf>,
+) -> Result<(), Error> {
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        let is_hidden = path
+            .file_name()
+            .map(|name| name.to_string_lossy().starts_with('.'))
+            .unwrap_or(false);
+        if is_hidden || path.ends_with("node_modules") {
+            continue;
+        }
+
+        if path.is_dir() {
+            collect_files_into(&path, filter, files)?;
+        } else if filter(&path) {
+            files.push(path);
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,356 @@
+use crate::proxy::IpNetwork;
+use crate::rate_limit::{RateLimitKey, RateLimitOpts, RateLimiter};
+use anyhow::{bail, Context, Error};
+use deno_core::serde_json;
+use hyper::header::{HeaderMap, CONTENT_LENGTH, TRANSFER_ENCODING};
+use hyper::Method;
+use log::debug;
+use once_cell::sync::Lazy;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::path::Path;
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::Instant;
+
+// the rules the server filters requests with, swapped when they're reloaded
+static WAF: Lazy<RwLock<Arc<Waf>>> = Lazy::new(Default::default);
+
+// The rules of a `--waf-rules` file, eg:
+// { "rules": [{ "paths": ["/wp-admin*"], "action": "deny" }] }
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct WafRules {
+    #[serde(default)]
+    pub rules: Vec<WafRule>,
+}
+
+impl WafRules {
+    pub fn load(path: &Path) -> Result<Self, Error> {
+        let rules = std::fs::read(path)
+            .with_context(|| format!("can't read the waf rules {}", path.display()))?;
+        Self::parse(&rules).with_context(|| format!("invalid waf rules {}", path.display()))
+    }
+
+    pub fn parse(rules: &[u8]) -> Result<Self, Error> {
+        let rules: Self = serde_json::from_slice(rules)?;
+        for rule in &rules.rules {
+            rule.validate()?;
+        }
+        Ok(rules)
+    }
+}
+
+// A request matches a rule when it matches all of its predicates, the ones
+// left out match any request.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct WafRule {
+    // shown in the logs when it rejects a request
+    pub name: Option<String>,
+    // any of them, case insensitive
+    #[serde(default)]
+    pub methods: Vec<String>,
+    // any of them, `*` matches any characters (eg: "/wp-*")
+    #[serde(default)]
+    pub paths: Vec<String>,
+    // all of them, the values are patterns like the paths, case insensitive
+    #[serde(default)]
+    pub headers: HashMap<String, String>,
+    // any of them, the client's address the server derived
+    #[serde(default)]
+    pub ips: Vec<IpNetwork>,
+    // requests declaring a larger body, and chunked ones whose size is unknown
+    pub body_larger_than: Option<u64>,
+    #[serde(flatten)]
+    pub action: WafAction,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(tag = "action", rename_all = "snake_case")]
+pub enum WafAction {
+    // let the request through, the rules after this one aren't checked
+    Allow,
+    Deny {
+        #[serde(default = "forbidden")]
+        status: u16,
+    },
+    // a token bucket per client address, the requests over it get a 429
+    // and the others go on to the next rules
+    RateLimit {
+        requests_per_sec: f64,
+        burst: u32,
+    },
+}
+
+fn forbidden() -> u16 {
+    403
+}
+
+impl WafRule {
+    fn validate(&self) -> Result<(), Error> {
+        for method in &self.methods {
+            Method::from_bytes(method.as_bytes())
+                .with_context(|| format!("invalid method {:?}", method))?;
+        }
+        match self.action {
+            WafAction::Deny { status } if !(400..600).contains(&status) => {
+                bail!("a deny rule must answer with a 4xx or 5xx status")
+            }
+            WafAction::RateLimit {
+                requests_per_sec, ..
+            } if requests_per_sec.is_nan() || requests_per_sec < 0.0 => {
+                bail!("the requests per second of a rate limit can't be negative")
+            }
+            _ => Ok(()),
+        }
+    }
+
+    fn matches(&self, req: &WafRequest) -> bool {
+        (self.methods.is_empty()
+            || self
+                .methods
+                .iter()
+                .any(|method| method.eq_ignore_ascii_case(req.method.as_str())))
+            && (self.paths.is_empty() || self.paths.iter().any(|path| glob(path, req.path)))
+            && self.headers.iter().all(|(name, pattern)| {
+                req.headers
+                    .get_all(name.as_str())
+                    .iter()
+                    .filter_map(|value| value.to_str().ok())
+                    .any(|value| glob(&pattern.to_lowercase(), &value.to_lowercase()))
+            })
+            && (self.ips.is_empty() || self.ips.iter().any(|ip| ip.contains(req.client_ip)))
+            && self.body_larger_than.map_or(true, |max| {
+                body_size(req.headers).map_or(true, |size| size > max)
+            })
+    }
+}
+
+// `None` when the size isn't known before the body is read
+fn body_size(headers: &HeaderMap) -> Option<u64> {
+    if headers.contains_key(TRANSFER_ENCODING) {
+        return None;
+    }
+    headers
+        .get(CONTENT_LENGTH)
+        .map_or(Some(0), |value| value.to_str().ok()?.parse().ok())
+}
+
+// `*` matches any characters, the others themselves
+fn glob(pattern: &str, value: &str) -> bool {
+    let mut parts = pattern.split('*');
+    let first = parts.next().unwrap_or_default();
+    let Some(mut rest) = value.strip_prefix(first) else {
+        return false;
+    };
+    let mut parts: Vec<&str> = parts.collect();
+    let Some(last) = parts.pop() else {
+        // no `*`
+        return rest.is_empty();
+    };
+    for part in parts {
+        match rest.find(part) {
+            Some(at) => rest = &rest[at + part.len()..],
+            None => return false,
+        }
+    }
+    rest.len() >= last.len() && rest.ends_with(last)
+}
+
+pub(crate) struct WafRequest<'a> {
+    pub method: &'a Method,
+    pub path: &'a str,
+    pub headers: &'a HeaderMap,
+    pub client_ip: IpAddr,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum WafVerdict {
+    Pass,
+    Reject(u16),
+}
+
+// The rules and the buckets of their rate limits, which start full again
+// when the rules are reloaded.
+#[derive(Default)]
+pub(crate) struct Waf {
+    rules: Vec<WafRule>,
+    limiters: Vec<Option<Mutex<RateLimiter>>>,
+}
+
+impl Waf {
+    pub fn new(rules: WafRules) -> Self {
+        let limiters = rules
+            .rules
+            .iter()
+            .map(|rule| match rule.action {
+                WafAction::RateLimit {
+                    requests_per_sec,
+                    burst,
+                } => Some(Mutex::new(RateLimiter::new(RateLimitOpts {
+                    requests_per_sec,
+                    burst,
+                    key: RateLimitKey::Ip,
+                }))),
+                _ => None,
+            })
+            .collect();
+        Self {
+            rules: rules.rules,
+            limiters,
+        }
+    }
+
+    // the first rule that allows or rejects the request decides
+    pub fn check(&self, req: &WafRequest, now: Instant) -> WafVerdict {
+        for (rule, limiter) in self.rules.iter().zip(&self.limiters) {
+            if !rule.matches(req) {
+                continue;
+            }
+            let status = match (&rule.action, limiter) {
+                (WafAction::Allow, _) => return WafVerdict::Pass,
+                (WafAction::Deny { status }, _) => *status,
+                (WafAction::RateLimit { .. }, Some(limiter)) => {
+                    let key = req.client_ip.to_string();
+                    if limiter.lock().unwrap().check(&key, now).is_ok() {
+                        continue;
+                    }
+                    429
+                }
+                (WafAction::RateLimit { .. }, None) => continue,
+            };
+            debug!(
+                "waf rule {} rejected {} {} from {}",
+                rule.name.as_deref().unwrap_or("(unnamed)"),
+                req.method,
+                req.path,
+                req.client_ip
+            );
+            return WafVerdict::Reject(status);
+        }
+        WafVerdict::Pass
+    }
+}
+
+// Replaces the rules the requests are filtered with, before they reach the
+// main worker. Called at startup and when the config is reloaded.
+pub fn set_waf_rules(rules: WafRules) {
+    *WAF.write().unwrap() = Arc::new(Waf::new(rules));
+}
+
+pub(crate) fn waf() -> Arc<Waf> {
+    WAF.read().unwrap().clone()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn check(waf: &Waf, method: Method, path: &str, headers: &HeaderMap, ip: &str) -> WafVerdict {
+        let req = WafRequest {
+            method: &method,
+            path,
+            headers,
+            client_ip: ip.parse().unwrap(),
+        };
+        waf.check(&req, Instant::now())
+    }
+
+    #[test]
+    fn test_glob() {
+        assert!(glob("/wp-*", "/wp-admin"));
+        assert!(glob("*sqlmap*", "sqlmap/1.7"));
+        assert!(glob("/a/*/c", "/a/b/c"));
+        assert!(glob("*.php", "/index.php"));
+        assert!(!glob("*.php", "/index.phps"));
+        assert!(!glob("/a", "/ab"));
+        assert!(!glob("a*a", "a"));
+        assert!(glob("*", ""));
+    }
+
+    #[test]
+    fn test_waf_rules() {
+        let rules = WafRules::parse(
+            br#"{ "rules": [
+                { "name": "office", "ips": ["10.0.0.0/8"], "action": "allow" },
+                { "name": "scanners", "headers": { "user-agent": "*SQLMap*" }, "action": "deny" },
+                { "paths": ["/wp-*", "*.php"], "action": "deny", "status": 404 },
+                { "methods": ["post"], "body_larger_than": 1024, "action": "deny", "status": 413 },
+                { "paths": ["/login"], "action": "rate_limit", "requests_per_sec": 0, "burst": 1 }
+            ] }"#,
+        )
+        .unwrap();
+        let waf = Waf::new(rules);
+        let client = "203.0.113.7";
+
+        let mut headers = HeaderMap::new();
+        assert_eq!(
+            check(&waf, Method::GET, "/hello", &headers, client),
+            WafVerdict::Pass
+        );
+        assert_eq!(
+            check(&waf, Method::GET, "/wp-login.php", &headers, client),
+            WafVerdict::Reject(404)
+        );
+        // allowed before the other rules are checked
+        assert_eq!(
+            check(&waf, Method::GET, "/wp-login.php", &headers, "10.1.2.3"),
+            WafVerdict::Pass
+        );
+
+        headers.insert("user-agent", "sqlmap/1.7".parse().unwrap());
+        assert_eq!(
+            check(&waf, Method::GET, "/hello", &headers, client),
+            WafVerdict::Reject(403)
+        );
+
+        let mut headers = HeaderMap::new();
+        headers.insert(CONTENT_LENGTH, "100".parse().unwrap());
+        assert_eq!(
+            check(&waf, Method::POST, "/hello", &headers, client),
+            WafVerdict::Pass
+        );
+        headers.insert(CONTENT_LENGTH, "2048".parse().unwrap());
+        assert_eq!(
+            check(&waf, Method::POST, "/hello", &headers, client),
+            WafVerdict::Reject(413)
+        );
+        let mut headers = HeaderMap::new();
+        headers.insert(TRANSFER_ENCODING, "chunked".parse().unwrap());
+        assert_eq!(
+            check(&waf, Method::POST, "/hello", &headers, client),
+            WafVerdict::Reject(413)
+        );
+
+        let headers = HeaderMap::new();
+        assert_eq!(
+            check(&waf, Method::POST, "/login", &headers, client),
+            WafVerdict::Pass
+        );
+        assert_eq!(
+            check(&waf, Method::POST, "/login", &headers, client),
+            WafVerdict::Reject(429)
+        );
+        // other clients have their own bucket
+        assert_eq!(
+            check(&waf, Method::POST, "/login", &headers, "203.0.113.8"),
+            WafVerdict::Pass
+        );
+    }
+
+    #[test]
+    fn test_invalid_waf_rules() {
+        assert!(WafRules::parse(br#"{ "rules": [{ "action": "block" }] }"#).is_err());
+        assert!(WafRules::parse(br#"{ "rules": [{ "action": "deny", "status": 200 }] }"#).is_err());
+        assert!(
+            WafRules::parse(br#"{ "rules": [{ "ips": ["10.0.0.0/33"], "action": "deny" }] }"#)
+                .is_err()
+        );
+        assert!(
+            WafRules::parse(br#"{ "rules": [{ "methods": ["GE T"], "action": "deny" }] }"#)
+                .is_err()
+        );
+        assert!(WafRules::parse(br#"{ "rule": [] }"#).is_err());
+        assert_eq!(WafRules::parse(b"{}").unwrap(), WafRules::default());
+    }
+}
@@ -0,0 +1,134 @@
+use crate::utils::units::human_elapsed;
+use deno_core::v8;
+use log::warn;
+use sb_core::event_loop::EventLoopHeartbeat;
+use sb_worker_context::events::{
+    EventLoopBlockedEvent, WorkerEventWithMetadata, WorkerEvents, WorkerEventsTx,
+};
+use sb_worker_context::worker_id::WorkerId;
+use std::ffi::c_void;
+use std::time::Duration;
+use tokio::sync::mpsc;
+
+// max number of JS frames captured when the event loop is blocked
+const MAX_STACK_FRAMES: usize = 16;
+// minimum interval at which the JS side reports a heartbeat
+const MIN_HEARTBEAT_INTERVAL_MS: u64 = 10;
+
+pub fn heartbeat_interval_ms(lag_threshold_ms: u64) -> u64 {
+    std::cmp::max(lag_threshold_ms / 2, MIN_HEARTBEAT_INTERVAL_MS)
+}
+
+// Stored in an isolate slot, so the interrupt callback can enter the main
+// context and report the captured stack back to the controller thread.
+pub struct StackCaptureCtx {
+    pub context: v8::Global<v8::Context>,
+    pub stack_tx: mpsc::UnboundedSender<String>,
+}
+
+extern "C" fn capture_js_stack(isolate: &mut v8::Isolate, _data: *mut c_void) {
+    let (context, stack_tx) = match isolate.get_slot::<StackCaptureCtx>() {
+        Some(ctx) => (ctx.context.clone(), ctx.stack_tx.clone()),
+        None => return,
+    };
+
+    let scope = &mut v8::HandleScope::new(isolate);
+    let context = v8::Local::new(scope, &context);
+    let scope = &mut v8::ContextScope::new(scope, context);
+
+    let mut frames = vec![];
+    if let Some(stack) = v8::StackTrace::current_stack_trace(scope, MAX_STACK_FRAMES) {
+        for i in 0..stack.get_frame_count() {
+            let frame = match stack.get_frame(scope, i) {
+                Some(frame) => frame,
+                None => continue,
+            };
+            let function_name = frame
+                .get_function_name(scope)
+                .map(|name| name.to_rust_string_lossy(scope))
+                .filter(|name| !name.is_empty())
+                .unwrap_or_else(|| "<anonymous>".to_string());
+            let script_name = frame
+                .get_script_name(scope)
+                .map(|name| name.to_rust_string_lossy(scope))
+                .unwrap_or_else(|| "<unknown>".to_string());
+            frames.push(format!(
+                "    at {} ({}:{}:{})",
+                function_name,
+                script_name,
+                frame.get_line_number(),
+                frame.get_column()
+            ));
+        }
+    }
+
+    let _ = stack_tx.send(frames.join("\n"));
+}
+
+pub struct EventLoopWatchdog {
+    pub worker_id: WorkerId,
+    pub heartbeat: EventLoopHeartbeat,
+    pub lag_threshold_ms: u64,
+    pub isolate_handle: v8::IsolateHandle,
+    pub stack_rx: mpsc::UnboundedReceiver<String>,
+    pub events_tx: Option<WorkerEventsTx>,
+    pub blocked: bool,
+}
+
+impl EventLoopWatchdog {
+    // Resolves with the observed lag once the event loop is blocked for
+    // longer than the configured threshold.
+    pub async fn wait_for_blocked_loop(&mut self) -> u64 {
+        let interval_ms = heartbeat_interval_ms(self.lag_threshold_ms);
+        let mut interval = tokio::time::interval(Duration::from_millis(interval_ms));
+
+        loop {
+            interval.tick().await;
+
+            let lag_ms =
+                (self.heartbeat.since_last_beat().as_millis() as u64).saturating_sub(interval_ms);
+            if lag_ms < self.lag_threshold_ms {
+                self.blocked = false;
+                continue;
+            }
+
+            // report only once for each blocked period
+            if !self.blocked {
+                self.blocked = true;
+                return lag_ms;
+            }
+        }
+    }
+
+    pub async fn report_blocked_loop(&mut self, lag_ms: u64) {
+        let js_stack = if self
+            .isolate_handle
+            .request_interrupt(capture_js_stack, std::ptr::null_mut())
+        {
+            // the interrupt only runs while JS is executing; give up if it doesn't in time
+            tokio::time::timeout(
+                Duration::from_millis(self.lag_threshold_ms),
+                self.stack_rx.recv(),
+            )
+            .await
+            .ok()
+            .flatten()
+        } else {
+            None
+        };
+
+        warn!(
+            "[{}] event loop blocked for {}\n{}",
+            self.worker_id,
+            human_elapsed(lag_ms),
+            js_stack.as_deref().unwrap_or("    <stack unavailable>")
+        );
+
+        if let Some(events_tx) = &self.events_tx {
+            let _ = events_tx.send(WorkerEventWithMetadata {
+                worker_id: self.worker_id.clone(),
+                event: WorkerEvents::EventLoopBlocked(EventLoopBlockedEvent { lag_ms, js_stack }),
+            });
+        }
+    }
+}
@@ -1,23 +1,52 @@
-use crate::edge_runtime::EdgeRuntime;
-use anyhow::{bail, Error};
+use crate::audit::Auditor;
+use crate::autoscaler::{Autoscaler, ReplicaLoad, ScaleDecision};
+use crate::billing::{billing_enabled, BillingMeter};
+use crate::coalesce::{self, Admission, CoalesceKey, Coalescer, SharedResponse};
+use crate::deployments::DeploymentRouter;
+use crate::edge_runtime::{strict_mode, EdgeRuntime};
+use crate::hooks::{request_hooks, run_after_hooks, run_before_hooks, HookRequest};
+use crate::maintenance::maintenance_response;
+use crate::manifest::ServiceManifest;
+use crate::metrics::{self, metrics_enabled, MetricKind};
+use crate::rate_limit::{RateLimitOpts, RateLimiter};
+use crate::reload::Tunables;
+use crate::scheduler::{self, LiveWorker, SchedulerOpts, WorkerScheduler};
+use crate::service_source::{ServiceSourceResolver, SourceLease};
+use crate::sticky::StickySessions;
+use crate::type_check::type_check_service;
+use crate::utils::units::human_elapsed;
+use crate::worker_threads::spawn_isolate;
+use anyhow::{anyhow, bail, Error};
+use hyper::header::{HeaderName, HeaderValue};
 use hyper::{Body, Request, Response};
-use log::error;
+use log::{debug, error, info, warn};
+use sb_worker_context::billing::InvocationUsages;
+use sb_worker_context::binding_depth::{BindingDepth, BindingDepths};
 use sb_worker_context::essentials::{
     CreateUserWorkerResult, EdgeContextInitOpts, EdgeContextOpts, EdgeMainRuntimeOpts,
-    UserWorkerMsgs,
+    OutboundOpts, UserWorkerMsgs, WorkerPriority,
 };
-use std::collections::HashMap;
+use sb_worker_context::events::{LogLevel, WorkerEventWithMetadata, WorkerEvents, WorkerEventsTx};
+use sb_worker_context::fetch_breaker::FetchBreakers;
+use sb_worker_context::net_usage::{NetUsage, NetUsageSnapshot};
+use sb_worker_context::open_sockets::{OpenSockets, SocketUsage};
+use sb_worker_context::rate_limit::RateLimited;
+use sb_worker_context::routes::RouteTable;
+use sb_worker_context::worker_id::WorkerId;
+use std::collections::{HashMap, VecDeque};
+use std::future::Future;
 use std::path::Path;
 use std::sync::Arc;
-use std::thread;
+use std::time::{Duration, Instant, SystemTime};
 use tokio::net::UnixStream;
-use tokio::sync::RwLock;
-use tokio::sync::{mpsc, oneshot};
+use tokio::sync::{mpsc, oneshot, Mutex, RwLock, Semaphore};
 use uuid::Uuid;
 
 #[derive(Debug)]
 pub struct WorkerContext {
-    request_sender: hyper::client::conn::SendRequest<Body>,
+    // only held while a request is handed to the connection
+    request_sender: Mutex<hyper::client::conn::SendRequest<Body>>,
+    exit_rx: Option<oneshot::Receiver<()>>,
 }
 
 impl WorkerContext {
@@ -30,16 +59,18 @@ impl WorkerContext {
 
         // create a unix socket pair
         let (sender_stream, recv_stream) = UnixStream::pair()?;
+        let (boot_tx, boot_rx) = oneshot::channel::<Result<(), Error>>();
+        let (exit_tx, exit_rx) = oneshot::channel::<()>();
 
-        let _handle: thread::JoinHandle<Result<(), Error>> = thread::spawn(move || {
-            let runtime = tokio::runtime::Builder::new_current_thread()
-                .enable_all()
-                .build()
-                .unwrap();
-            let local = tokio::task::LocalSet::new();
-
-            let _handle: Result<(), Error> = local.block_on(&runtime, async {
-                let worker = EdgeRuntime::new(conf)?;
+        spawn_isolate(move || async move {
+            let _handle: Result<(), Error> = async {
+                let worker = match EdgeRuntime::new(conf) {
+                    Ok(worker) => worker.with_boot_notifier(boot_tx),
+                    Err(err) => {
+                        let _ = boot_tx.send(Err(err));
+                        return Ok(());
+                    }
+                };
 
                 // start the worker
                 let (shutdown_tx, shutdown_rx) = oneshot::channel::<()>();
@@ -49,13 +80,18 @@ impl WorkerContext {
                 let _ = shutdown_rx.await;
 
                 Ok(())
-            });
+            }
+            .await;
 
-            Ok(())
+            let _ = exit_tx.send(());
         });
 
-        // send the HTTP request to the worker over Unix stream
-        let (request_sender, connection) = hyper::client::conn::handshake(sender_stream).await?;
+        // send the HTTP requests to the worker over Unix stream. http/2, so they're
+        // multiplexed and the worker handles them concurrently on its event loop
+        let (request_sender, connection) = hyper::client::conn::Builder::new()
+            .http2_only(true)
+            .handshake(sender_stream)
+            .await?;
 
         // spawn a task to poll the connection and drive the HTTP state
         tokio::spawn(async move {
@@ -64,81 +100,2006 @@ impl WorkerContext {
             }
         });
 
-        Ok(Self { request_sender })
+        match boot_rx.await {
+            Ok(Ok(())) => {}
+            Ok(Err(err)) => return Err(err.context("worker failed to boot")),
+            Err(_) => bail!("worker exited before it finished booting"),
+        }
+
+        Ok(Self {
+            request_sender: Mutex::new(request_sender),
+            exit_rx: Some(exit_rx),
+        })
+    }
+
+    // Resolves once the worker's thread has finished. Can only be taken once.
+    pub fn take_exit_signal(&mut self) -> Option<oneshot::Receiver<()>> {
+        self.exit_rx.take()
+    }
+
+    pub async fn send_request(&self, req: Request<Body>) -> Result<Response<Body>, hyper::Error> {
+        let response = {
+            let mut request_sender = self.request_sender.lock().await;
+            request_sender.ready().await?;
+            request_sender.send_request(req)
+        };
+        response.await
+    }
+}
+
+fn log_worker_event(event: &WorkerEventWithMetadata) {
+    match &event.event {
+        WorkerEvents::EventLoopBlocked(ev) => debug!(
+            "[{}] event loop blocked for {}ms",
+            event.worker_id, ev.lag_ms
+        ),
+        WorkerEvents::UncaughtException(ev) | WorkerEvents::UnhandledRejection(ev) => warn!(
+            "[{}] {} (request ids: {})\n{}",
+            event.worker_id,
+            ev.message,
+            if ev.request_ids.is_empty() {
+                "none".to_string()
+            } else {
+                ev.request_ids.join(", ")
+            },
+            ev.stack.as_deref().unwrap_or_default()
+        ),
+        WorkerEvents::Log(ev) => {
+            let level = match ev.level {
+                LogLevel::Debug => log::Level::Debug,
+                LogLevel::Info => log::Level::Info,
+                LogLevel::Warning => log::Level::Warn,
+                LogLevel::Error => log::Level::Error,
+            };
+            log::log!(level, "[{}] {}", event.worker_id, ev.msg);
+        }
+        WorkerEvents::Audit(ev) => debug!(
+            "[{}] audited {} {} ({})",
+            event.worker_id, ev.method, ev.url, ev.status
+        ),
+        WorkerEvents::PermissionDenied(ev) => warn!(
+            "[{}] {} was denied access to {}",
+            event.worker_id, ev.api_name, ev.resource
+        ),
+        WorkerEvents::Billing(ev) => debug!(
+            "[{}] billed {:.1} cpu ms and {:.1} MB-s ({})",
+            event.worker_id, ev.cpu_ms, ev.memory_mb_s, ev.status
+        ),
+        WorkerEvents::NetUsage(ev) => {
+            let total = ev.total();
+            debug!(
+                "[{}] sent {} bytes, received {} bytes",
+                event.worker_id, total.sent, total.received
+            );
+        }
+    }
+}
+
+// the id the pool tags every request to a user worker with
+const REQUEST_ID_HEADER: &str = "x-request-id";
+
+// how deep in service calls the request is, set by the pool from the depth
+// the calling isolate gave it. Public requests are at depth 0, without it
+pub(crate) const BINDING_DEPTH_HEADER: &str = "x-edge-runtime-binding-depth";
+
+pub(crate) fn error_response(status: u16, msg: &str) -> Response<Body> {
+    Response::builder()
+        .status(status)
+        .body(Body::from(
+            deno_core::serde_json::json!({ "msg": msg }).to_string(),
+        ))
+        .unwrap()
+}
+
+// answers with a 504 if the worker doesn't respond within `request_timeout_ms`
+async fn with_request_timeout(
+    request_timeout_ms: Option<u64>,
+    fut: impl Future<Output = Response<Body>>,
+) -> Response<Body> {
+    match request_timeout_ms {
+        Some(ms) => tokio::time::timeout(Duration::from_millis(ms), fut)
+            .await
+            .unwrap_or_else(|_| {
+                error_response(
+                    504,
+                    "Request timed out while waiting for the worker to respond.",
+                )
+            }),
+        None => fut.await,
+    }
+}
+
+async fn send_user_worker_request(
+    worker: Arc<RwLock<WorkerContext>>,
+    concurrency: Option<Arc<Semaphore>>,
+    req: Request<Body>,
+    request_timeout_ms: Option<u64>,
+) -> Response<Body> {
+    // waits for one of the requests already sent to the isolate to be
+    // answered, the request only times out once it's sent
+    let _permit = match &concurrency {
+        Some(concurrency) => concurrency.clone().acquire_owned().await.ok(),
+        None => None,
+    };
+    let fut = async move {
+        let worker = worker.read().await;
+        // TODO: Json format
+        worker.send_request(req).await.unwrap_or_else(|_e| {
+            error_response(
+                408,
+                "Request could not be processed by the server because it timed out or an error was thrown.",
+            )
+        })
+    };
+
+    with_request_timeout(request_timeout_ms, fut).await
+}
+
+async fn create_user_worker(
+    opts: EdgeContextInitOpts,
+    boot_retries: u32,
+    boot_retry_backoff_ms: u64,
+) -> Result<WorkerContext, Error> {
+    let mut attempt = 0;
+    loop {
+        match WorkerContext::new(opts.clone()).await {
+            Ok(worker) => return Ok(worker),
+            Err(err) if attempt < boot_retries => {
+                // exponential backoff between boot attempts
+                let backoff_ms = boot_retry_backoff_ms.saturating_mul(1 << attempt.min(16));
+                attempt += 1;
+                warn!(
+                    "user worker failed to boot, retrying in {} ({}/{}): {:?}",
+                    human_elapsed(backoff_ms),
+                    attempt,
+                    boot_retries,
+                    err
+                );
+                tokio::time::sleep(Duration::from_millis(backoff_ms)).await;
+            }
+            Err(err) => return Err(err),
+        }
+    }
+}
+
+// how often the load of autoscaled workers is checked, besides on every request
+const AUTOSCALE_INTERVAL: Duration = Duration::from_secs(1);
+// how often the gauges of the pool are exported, with a metrics exporter
+const METRICS_INTERVAL: Duration = Duration::from_secs(10);
+
+// One of the isolates serving a user worker.
+struct UserWorkerReplica {
+    id: Uuid,
+    worker: Arc<RwLock<WorkerContext>>,
+    // requests the isolate handles at once, unlimited if unset
+    concurrency: Option<Arc<Semaphore>>,
+    in_flight: usize,
+    idle_since: Option<Instant>,
+}
+
+// Wraps a booted worker into a replica, reporting it to the pool once it exits.
+fn watch_replica(
+    key: Uuid,
+    mut worker: WorkerContext,
+    max_concurrent_requests: Option<usize>,
+    lifecycle_tx: &mpsc::UnboundedSender<UserWorkerLifecycle>,
+) -> UserWorkerReplica {
+    let id = Uuid::new_v4();
+    if let Some(exit_signal) = worker.take_exit_signal() {
+        let lifecycle_tx = lifecycle_tx.clone();
+        tokio::spawn(async move {
+            let _ = exit_signal.await;
+            let _ = lifecycle_tx.send(UserWorkerLifecycle::Exited(key, id));
+        });
+    }
+
+    UserWorkerReplica {
+        id,
+        worker: Arc::new(RwLock::new(worker)),
+        concurrency: max_concurrent_requests.map(|max| Arc::new(Semaphore::new(max.max(1)))),
+        in_flight: 0,
+        idle_since: Some(Instant::now()),
+    }
+}
+
+// the settings of the manifest its isolates are built with
+fn apply_manifest(opts: &mut EdgeContextInitOpts, manifest: &ServiceManifest) -> Result<(), Error> {
+    if let EdgeContextOpts::UserWorker(user_opts) = &mut opts.conf {
+        user_opts.compat_flags = manifest.compat_flags()?;
+        if strict_mode() {
+            user_opts.capabilities = Some(manifest.capabilities.clone());
+        }
+    }
+    Ok(())
+}
+
+// An isolate booted to replace one of a worker's in a rolling restart, with
+// the options and manifest it was booted with.
+struct Replacement {
+    replica: UserWorkerReplica,
+    opts: EdgeContextInitOpts,
+    manifest: ServiceManifest,
+}
+
+// Boots more isolates for a worker, from the options its first isolate
+// booted with (after its source was fetched and type checked).
+struct ReplicaSpawner {
+    worker_id: WorkerId,
+    opts: EdgeContextInitOpts,
+    boot_retries: u32,
+    boot_retry_backoff_ms: u64,
+    max_concurrent_requests: Option<usize>,
+}
+
+impl ReplicaSpawner {
+    // another isolate for an autoscaled worker
+    fn spawn(&self, key: Uuid, lifecycle_tx: mpsc::UnboundedSender<UserWorkerLifecycle>) {
+        let worker_id = self.worker_id.clone();
+        let opts = self.opts.clone();
+        let boot_retries = self.boot_retries;
+        let boot_retry_backoff_ms = self.boot_retry_backoff_ms;
+        let max_concurrent_requests = self.max_concurrent_requests;
+        tokio::spawn(async move {
+            let replica = match create_user_worker(opts, boot_retries, boot_retry_backoff_ms).await
+            {
+                Ok(worker) => Some(watch_replica(
+                    key,
+                    worker,
+                    max_concurrent_requests,
+                    &lifecycle_tx,
+                )),
+                Err(err) => {
+                    warn!("[{}] failed to boot another isolate: {:?}", worker_id, err);
+                    None
+                }
+            };
+            let _ = lifecycle_tx.send(UserWorkerLifecycle::ReplicaBooted(key, replica));
+        });
+    }
+
+    // An isolate replacing `replica_id`, which picks up the changes made to
+    // the service's code and manifest since the worker booted.
+    fn restart(
+        &self,
+        key: Uuid,
+        replica_id: Uuid,
+        lifecycle_tx: mpsc::UnboundedSender<UserWorkerLifecycle>,
+    ) {
+        let worker_id = self.worker_id.clone();
+        let mut opts = self.opts.clone();
+        let boot_retries = self.boot_retries;
+        let boot_retry_backoff_ms = self.boot_retry_backoff_ms;
+        let max_concurrent_requests = self.max_concurrent_requests;
+        tokio::spawn(async move {
+            let replacement = async {
+                let manifest = ServiceManifest::load(&opts.service_path)?;
+                apply_manifest(&mut opts, &manifest)?;
+                let worker =
+                    create_user_worker(opts.clone(), boot_retries, boot_retry_backoff_ms).await?;
+                Ok::<_, Error>(Replacement {
+                    replica: watch_replica(key, worker, max_concurrent_requests, &lifecycle_tx),
+                    opts,
+                    manifest,
+                })
+            }
+            .await;
+            let replacement = match replacement {
+                Ok(replacement) => Some(replacement),
+                Err(err) => {
+                    warn!(
+                        "[{}] failed to boot the isolate replacing {}: {:?}",
+                        worker_id, replica_id, err
+                    );
+                    None
+                }
+            };
+            let _ = lifecycle_tx.send(UserWorkerLifecycle::Restarted(key, replica_id, replacement));
+        });
+    }
+}
+
+// How a rolling restart of the user workers went.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct RestartReport {
+    // isolates replaced
+    pub restarted: usize,
+    // isolates whose replacement failed to boot, they're kept
+    pub failed: usize,
+}
+
+// The isolates a rolling restart has yet to replace, one at a time.
+struct RollingRestart {
+    // worker key and replica id
+    pending: VecDeque<(Uuid, Uuid)>,
+    report: RestartReport,
+    waiters: Vec<oneshot::Sender<RestartReport>>,
+}
+
+struct UserWorkerProfile {
+    // what its logs are prefixed with
+    worker_id: WorkerId,
+    // what its metrics are tagged with
+    service: String,
+    replicas: Vec<UserWorkerReplica>,
+    request_timeout_ms: Option<u64>,
+    // (service name, version) the worker was routed to
+    deployment: Option<(String, String)>,
+    // boots the other isolates of the worker, and their replacements
+    spawner: ReplicaSpawner,
+    // set when the worker autoscales
+    autoscaler: Option<Autoscaler>,
+    priority: WorkerPriority,
+    // memory limit of each of its isolates
+    memory_mb: u64,
+    net_usage: Arc<NetUsage>,
+    socket_usage: Arc<SocketUsage>,
+    // the depths of the service calls its isolates are handling
+    binding_depths: Arc<BindingDepths>,
+    // copies the requests picked for audit logs to the events channel
+    audit: Option<Auditor>,
+    // set when invocations are billed
+    billing: Option<Arc<BillingMeter>>,
+    // identical GETs waiting for the one sent to the worker
+    coalescer: Option<Coalescer>,
+    // isolates the sessions of the worker are pinned to
+    sticky: Option<StickySessions>,
+    // the service's manifest
+    manifest: Arc<ServiceManifest>,
+    // keeps the copy of the remote source its isolates boot from
+    source: Option<SourceLease>,
+}
+
+impl UserWorkerProfile {
+    // the tags of the metrics of the worker
+    fn metric_tags(&self, key: Uuid) -> Vec<(&'static str, String)> {
+        let mut tags = vec![("service", self.service.clone())];
+        if let Some((_, version)) = &self.deployment {
+            tags.push(("deployment", version.clone()));
+        }
+        tags.push(("worker", key.to_string()));
+        tags
+    }
+}
+
+fn as_tags(tags: &[(&'static str, String)]) -> Vec<(&'static str, &str)> {
+    tags.iter()
+        .map(|(name, value)| (*name, value.as_str()))
+        .collect()
+}
+
+type PendingUserWorker = (
+    EdgeContextInitOpts,
+    oneshot::Sender<Result<CreateUserWorkerResult, Error>>,
+);
+
+enum UserWorkerLifecycle {
+    Booted(
+        Uuid,
+        UserWorkerProfile,
+        oneshot::Sender<Result<CreateUserWorkerResult, Error>>,
+    ),
+    // memory reserved for a worker that failed to boot
+    BootFailed(u64),
+    // an extra isolate of an autoscaled worker booted, `None` if it failed to
+    ReplicaBooted(Uuid, Option<UserWorkerReplica>),
+    // worker key and replica id
+    Exited(Uuid, Uuid),
+    // worker key, replica id and response time in ms
+    RequestDone(Uuid, Uuid, u64),
+    // a coalesced request was answered, with the response to share if it
+    // can be
+    Coalesced(Uuid, CoalesceKey, Option<SharedResponse>),
+    // the request hooks let a request through to the worker
+    Hooked(Uuid, Request<Body>, oneshot::Sender<Response<Body>>),
+    // worker key, id of the replica being replaced, and its replacement if
+    // it booted
+    Restarted(Uuid, Uuid, Option<Replacement>),
+}
+
+// Keeps track of the user workers and routes the requests sent by the main
+// worker to them.
+struct UserWorkerPool {
+    user_workers: HashMap<Uuid, UserWorkerProfile>,
+    worker_events_tx: WorkerEventsTx,
+    lifecycle_tx: mpsc::UnboundedSender<UserWorkerLifecycle>,
+    deployments: DeploymentRouter,
+    sources: ServiceSourceResolver,
+    // import map of the server, user workers inherit it
+    base_import_map_path: Option<String>,
+    // auth tokens of the server, used after the ones of the user worker
+    auth_tokens: Option<String>,
+    // the server runs offline, so do its user workers
+    offline: bool,
+    // used by the user workers that aren't given one
+    timezone: Option<String>,
+    // limits how often user workers are created, per service
+    rate_limiter: Option<RateLimiter>,
+    // orders the boots by priority when boot slots or memory run out
+    scheduler: WorkerScheduler<PendingUserWorker>,
+    // isolates of the preempted workers, by id, with the memory they hold
+    // until they exit
+    stopping: HashMap<Uuid, u64>,
+    // shared with the user workers, and the main worker
+    fetch_breakers: Option<FetchBreakers>,
+    // set while the isolates are replaced one at a time
+    restart: Option<RollingRestart>,
+}
+
+impl UserWorkerPool {
+    fn new(
+        opts: &UserWorkerPoolOpts,
+        worker_events_tx: WorkerEventsTx,
+        lifecycle_tx: mpsc::UnboundedSender<UserWorkerLifecycle>,
+        deployments: DeploymentRouter,
+        sources: ServiceSourceResolver,
+        fetch_breakers: Option<FetchBreakers>,
+    ) -> Self {
+        Self {
+            user_workers: HashMap::new(),
+            worker_events_tx,
+            lifecycle_tx,
+            deployments,
+            sources,
+            base_import_map_path: opts.import_map_path.clone(),
+            auth_tokens: opts.auth_tokens.clone(),
+            offline: opts.offline,
+            timezone: opts.timezone.clone(),
+            rate_limiter: opts.rate_limit.clone().map(RateLimiter::new),
+            scheduler: WorkerScheduler::new(opts.scheduler.clone()),
+            stopping: HashMap::new(),
+            fetch_breakers,
+            restart: None,
+        }
+    }
+
+    fn create(
+        &mut self,
+        worker_options: EdgeContextInitOpts,
+        tx: oneshot::Sender<Result<CreateUserWorkerResult, Error>>,
+    ) {
+        // refuse before anything is spawned for the worker
+        if let (Some(limiter), EdgeContextOpts::UserWorker(user_opts)) =
+            (&mut self.rate_limiter, &worker_options.conf)
+        {
+            let service = user_opts
+                .service_name
+                .clone()
+                .unwrap_or_else(|| worker_options.service_path.to_string_lossy().to_string());
+            let bucket_key = limiter.bucket_key(&service, &user_opts.routing_headers);
+            if let Err(retry_after) = limiter.check(&bucket_key, Instant::now()) {
+                let _ = tx.send(Err(RateLimited {
+                    key: bucket_key,
+                    retry_after_ms: u64::try_from(retry_after.as_millis()).unwrap_or(u64::MAX),
+                }
+                .into()));
+                return;
+            }
+        }
+
+        let (priority, memory_mb) = match &worker_options.conf {
+            EdgeContextOpts::UserWorker(user_opts) => {
+                (user_opts.priority, user_opts.memory_limit_mb)
+            }
+            EdgeContextOpts::MainWorker(_) => (WorkerPriority::default(), 0),
+        };
+        self.scheduler
+            .push(priority, memory_mb, (worker_options, tx));
+        self.schedule();
+    }
+
+    // the reloaded settings apply to the workers created from now on
+    fn retune(&mut self, tunables: Tunables) {
+        match (&mut self.rate_limiter, tunables.rate_limit) {
+            (Some(limiter), Some(opts)) => limiter.set_opts(opts),
+            (limiter, opts) => *limiter = opts.map(RateLimiter::new),
+        }
+        self.scheduler.set_opts(tunables.scheduler);
+        self.timezone = tunables.timezone;
+        // more boot slots or memory may let queued workers boot
+        self.schedule();
+    }
+
+    // Boots the queued workers the scheduler lets through, and stops the ones
+    // preempted to make room for them. The worker they made room for boots
+    // once they exited.
+    fn schedule(&mut self) {
+        loop {
+            let live: Vec<LiveWorker> = self
+                .user_workers
+                .iter()
+                .map(|(key, profile)| LiveWorker {
+                    key: *key,
+                    priority: profile.priority,
+                    memory_mb: profile.memory_mb * profile.replicas.len() as u64,
+                })
+                .collect();
+            match self.scheduler.next(&live) {
+                Some(scheduler::Admission::Boot((worker_options, tx))) => {
+                    self.boot(worker_options, tx);
+                }
+                Some(scheduler::Admission::Preempt(keys)) => {
+                    for key in keys {
+                        self.preempt(key);
+                    }
+                }
+                None => break,
+            }
+        }
+
+        if self.scheduler.queued() > 0 {
+            debug!("{} user worker(s) waiting to boot", self.scheduler.queued());
+        }
+    }
+
+    fn preempt(&mut self, key: Uuid) {
+        let Some(profile) = self.user_workers.remove(&key) else {
+            return;
+        };
+
+        warn!(
+            "[{}] stopping batch worker to free memory",
+            profile.worker_id
+        );
+        self.report_net_usage(&profile);
+        Self::record_exit(key, &profile);
+        if let Some((service_name, version)) = profile.deployment {
+            self.deployments.worker_exited(&service_name, &version);
+        }
+        // dropping the profile closes the connections to its isolates, their
+        // memory is only released once they exited
+        for replica in &profile.replicas {
+            self.stopping.insert(replica.id, profile.memory_mb);
+        }
     }
 
-    pub async fn send_request(
+    fn boot(
         &mut self,
+        mut worker_options: EdgeContextInitOpts,
+        tx: oneshot::Sender<Result<CreateUserWorkerResult, Error>>,
+    ) {
+        let key = Uuid::new_v4();
+        worker_options.auth_tokens = match (worker_options.auth_tokens.take(), &self.auth_tokens) {
+            (Some(tokens), Some(server_tokens)) => Some(format!("{};{}", tokens, server_tokens)),
+            (tokens, server_tokens) => tokens.or_else(|| server_tokens.clone()),
+        };
+        worker_options.offline |= self.offline;
+        let mut request_timeout_ms = None;
+        let mut boot_retries = 0;
+        let mut boot_retry_backoff_ms = 0;
+        let mut service_checksum = None;
+        let mut source_max_age_ms = None;
+        let mut type_check = false;
+        let mut autoscale = None;
+        let mut priority = WorkerPriority::default();
+        let mut memory_mb = 0;
+        let mut max_concurrent_requests = None;
+        let mut deployment = None;
+        let mut net_usage = Arc::default();
+        let mut socket_usage = Arc::default();
+        let mut binding_depths = Arc::default();
+        let mut audit = None;
+        let mut billing = None;
+        let mut coalescer = None;
+        let mut sticky = None;
+        let mut service = worker_options.service_path.to_string_lossy().to_string();
+        let mut worker_id = WorkerId::new(key.to_string(), &service, None);
+        if let EdgeContextOpts::UserWorker(user_opts) = &mut worker_options.conf {
+            user_opts.id = key.to_string();
+            if let Some(service_name) = &user_opts.service_name {
+                service = service_name.clone();
+            }
+            if user_opts.events_tx.is_none() {
+                user_opts.events_tx = Some(self.worker_events_tx.clone());
+            }
+            if user_opts.base_import_map_path.is_none() {
+                user_opts.base_import_map_path = self.base_import_map_path.clone();
+            }
+            if user_opts.timezone.is_none() {
+                user_opts.timezone = self.timezone.clone();
+            }
+            user_opts.fetch_breakers = self.fetch_breakers.clone();
+            request_timeout_ms = user_opts.request_timeout_ms;
+            boot_retries = user_opts.boot_retries;
+            boot_retry_backoff_ms = user_opts.boot_retry_backoff_ms;
+            service_checksum = user_opts.service_checksum.clone();
+            source_max_age_ms = user_opts.source_max_age_ms;
+            type_check = user_opts.type_check;
+            autoscale = user_opts.autoscale.clone();
+            priority = user_opts.priority;
+            memory_mb = user_opts.memory_limit_mb;
+            max_concurrent_requests = user_opts.max_concurrent_requests;
+            net_usage = user_opts.net_usage.clone();
+            socket_usage = user_opts.socket_usage.clone();
+            binding_depths = user_opts.binding_depths.clone();
+
+            // pick one of the deployed versions of the service, if the embedder registered any
+            let version = user_opts.service_name.as_ref().and_then(|service_name| {
+                self.deployments
+                    .route(service_name, &user_opts.routing_headers)
+            });
+            user_opts.deployment = version.as_ref().map(|version| version.version.clone());
+            worker_id = WorkerId::new(key.to_string(), &service, user_opts.deployment.as_deref());
+
+            if let Some(audit_opts) = &user_opts.audit {
+                let events_tx = user_opts
+                    .events_tx
+                    .clone()
+                    .unwrap_or_else(|| self.worker_events_tx.clone());
+                match Auditor::new(worker_id.clone(), audit_opts, events_tx) {
+                    Ok(auditor) => audit = Some(auditor),
+                    Err(err) => {
+                        let _ = tx.send(Err(err));
+                        let _ = self
+                            .lifecycle_tx
+                            .send(UserWorkerLifecycle::BootFailed(memory_mb));
+                        return;
+                    }
+                }
+            }
+
+            if let Some(coalesce_opts) = &user_opts.coalesce {
+                match Coalescer::new(coalesce_opts) {
+                    Ok(c) => coalescer = Some(c),
+                    Err(err) => {
+                        let _ = tx.send(Err(err));
+                        let _ = self
+                            .lifecycle_tx
+                            .send(UserWorkerLifecycle::BootFailed(memory_mb));
+                        return;
+                    }
+                }
+            }
+
+            if let Some(sticky_opts) = &user_opts.sticky {
+                match StickySessions::new(sticky_opts) {
+                    Ok(s) => sticky = Some(s),
+                    Err(err) => {
+                        let _ = tx.send(Err(err));
+                        let _ = self
+                            .lifecycle_tx
+                            .send(UserWorkerLifecycle::BootFailed(memory_mb));
+                        return;
+                    }
+                }
+            }
+
+            if let (Some(service_name), Some(version)) = (&user_opts.service_name, version) {
+                worker_options.service_path = version.service_path;
+                self.deployments
+                    .worker_started(service_name, &version.version);
+                deployment = Some((service_name.clone(), version.version));
+            }
+
+            if billing_enabled() {
+                let usages = Arc::new(InvocationUsages::default());
+                user_opts.invocation_usages = Some(usages.clone());
+                let events_tx = user_opts
+                    .events_tx
+                    .clone()
+                    .unwrap_or_else(|| self.worker_events_tx.clone());
+                billing = Some(Arc::new(BillingMeter::new(
+                    worker_id.clone(),
+                    service.clone(),
+                    deployment.as_ref().map(|(_, version)| version.clone()),
+                    memory_mb,
+                    usages,
+                    events_tx,
+                )));
+            }
+        }
+
+        // boot the worker in the background, so the pool can keep serving
+        // requests while the worker's modules are loaded
+        let lifecycle_tx = self.lifecycle_tx.clone();
+        let deployments = self.deployments.clone();
+        let sources = self.sources.clone();
+        tokio::spawn(async move {
+            let user_worker_ctx = async move {
+                // fetch remote services into the local cache before booting from it
+                let source = sources
+                    .resolve(
+                        &worker_options.service_path,
+                        service_checksum.as_deref(),
+                        source_max_age_ms,
+                    )
+                    .await?;
+                worker_options.service_path = source.path;
+                let manifest = ServiceManifest::load(&worker_options.service_path)?;
+                apply_manifest(&mut worker_options, &manifest)?;
+
+                if type_check {
+                    let diagnostics = type_check_service(
+                        &worker_options.service_path,
+                        worker_options.import_map_path.as_deref(),
+                    )
+                    .await?;
+                    if !diagnostics.is_empty() {
+                        let diagnostics: Vec<String> =
+                            diagnostics.iter().map(|d| d.to_string()).collect();
+                        bail!("service failed to type check:\n{}", diagnostics.join("\n"));
+                    }
+                }
+
+                let worker =
+                    create_user_worker(worker_options.clone(), boot_retries, boot_retry_backoff_ms)
+                        .await?;
+                Ok((worker, worker_options, manifest, source.lease))
+            }
+            .await;
+
+            match user_worker_ctx {
+                Ok((worker, worker_options, manifest, source)) => {
+                    let profile = UserWorkerProfile {
+                        worker_id: worker_id.clone(),
+                        service,
+                        replicas: vec![watch_replica(
+                            key,
+                            worker,
+                            max_concurrent_requests,
+                            &lifecycle_tx,
+                        )],
+                        request_timeout_ms,
+                        deployment,
+                        spawner: ReplicaSpawner {
+                            worker_id,
+                            opts: worker_options,
+                            boot_retries,
+                            boot_retry_backoff_ms,
+                            max_concurrent_requests,
+                        },
+                        autoscaler: autoscale.map(Autoscaler::new),
+                        priority,
+                        memory_mb,
+                        net_usage,
+                        socket_usage,
+                        binding_depths,
+                        audit,
+                        billing,
+                        coalescer,
+                        sticky,
+                        manifest: Arc::new(manifest),
+                        source,
+                    };
+                    let _ = lifecycle_tx.send(UserWorkerLifecycle::Booted(key, profile, tx));
+                }
+                Err(e) => {
+                    if let Some((service_name, version)) = deployment {
+                        deployments.worker_exited(&service_name, &version);
+                    }
+                    let _ = tx.send(Err(e));
+                    let _ = lifecycle_tx.send(UserWorkerLifecycle::BootFailed(memory_mb));
+                }
+            }
+        });
+    }
+
+    // The worker is gone once its last isolate exits.
+    fn remove_exited(&mut self, key: Uuid, replica_id: Uuid) {
+        if let Some(memory_mb) = self.stopping.remove(&replica_id) {
+            self.scheduler.stopped(memory_mb);
+            self.schedule();
+            return;
+        }
+        let Some(profile) = self.user_workers.get_mut(&key) else {
+            return;
+        };
+        let replicas = profile.replicas.len();
+        profile.replicas.retain(|r| r.id != replica_id);
+        if profile.replicas.len() < replicas {
+            self.scheduler.release(profile.memory_mb);
+        }
+        if let Some(sticky) = &mut profile.sticky {
+            sticky.evicted(replica_id);
+        }
+        if profile.replicas.is_empty() {
+            if let Some(profile) = self.user_workers.remove(&key) {
+                self.report_net_usage(&profile);
+                Self::record_exit(key, &profile);
+                if let Some((service_name, version)) = profile.deployment {
+                    self.deployments.worker_exited(&service_name, &version);
+                }
+            }
+        }
+
+        self.schedule();
+    }
+
+    // lets the embedder bill what the worker sent and received once it's gone
+    fn report_net_usage(&self, profile: &UserWorkerProfile) {
+        let _ = self.worker_events_tx.send(WorkerEventWithMetadata {
+            worker_id: profile.worker_id.clone(),
+            event: WorkerEvents::NetUsage(profile.net_usage.snapshot()),
+        });
+    }
+
+    fn record_exit(key: Uuid, profile: &UserWorkerProfile) {
+        if metrics_enabled() {
+            let tags = profile.metric_tags(key);
+            metrics::record("worker_exits", MetricKind::Counter, 1.0, &as_tags(&tags));
+        }
+    }
+
+    // the gauges of the pool, and of the isolates of each service
+    fn report_metrics(&self) {
+        let mut isolates: HashMap<(&str, Option<&str>), usize> = HashMap::new();
+        let mut in_flight = 0;
+        for profile in self.user_workers.values() {
+            let deployment = profile.deployment.as_ref().map(|(_, v)| v.as_str());
+            *isolates
+                .entry((profile.service.as_str(), deployment))
+                .or_default() += profile.replicas.len();
+            in_flight += profile.replicas.iter().map(|r| r.in_flight).sum::<usize>();
+        }
+
+        metrics::record(
+            "workers",
+            MetricKind::Gauge,
+            self.user_workers.len() as f64,
+            &[],
+        );
+        metrics::record(
+            "requests_in_flight",
+            MetricKind::Gauge,
+            in_flight as f64,
+            &[],
+        );
+        metrics::record(
+            "boots_queued",
+            MetricKind::Gauge,
+            self.scheduler.queued() as f64,
+            &[],
+        );
+        for ((service, deployment), count) in isolates {
+            let mut tags = vec![("service", service)];
+            if let Some(deployment) = deployment {
+                tags.push(("deployment", deployment));
+            }
+            metrics::record("isolates", MetricKind::Gauge, count as f64, &tags);
+        }
+    }
+
+    fn net_usage(&self, key: Uuid) -> Option<NetUsageSnapshot> {
+        self.user_workers
+            .get(&key)
+            .map(|profile| profile.net_usage.snapshot())
+    }
+
+    fn open_sockets(&self, key: Uuid) -> Option<OpenSockets> {
+        self.user_workers
+            .get(&key)
+            .map(|profile| profile.socket_usage.snapshot())
+    }
+
+    // Answers a request to the service without booting a worker: when it's
+    // disabled, with a 404 if there's no such service, and per its manifest
+    // (clients it doesn't serve, CORS preflights and methods it doesn't
+    // handle). `None` if it goes to a worker.
+    fn preflight(
+        &self,
+        service_path: String,
         req: Request<Body>,
-    ) -> Result<Response<Body>, hyper::Error> {
-        self.request_sender.send_request(req).await
+        tx: oneshot::Sender<Result<Option<Response<Body>>, Error>>,
+    ) {
+        if let Some(res) = maintenance_response(Some(&service_path)) {
+            let _ = tx.send(Ok(Some(res)));
+            return;
+        }
+        let sources = self.sources.clone();
+        tokio::spawn(async move {
+            let res = async {
+                let source = sources
+                    .resolve(Path::new(&service_path), None, None)
+                    .await?;
+                let service_path = &source.path;
+                // junk traffic and scanners asking for services that don't exist
+                if !service_path.join("index.ts").exists() {
+                    return Ok(Some(error_response(404, "function not found")));
+                }
+                let manifest = ServiceManifest::load(&service_path)?;
+                Ok::<_, Error>(manifest.answer(&req))
+            }
+            .await;
+            let _ = tx.send(res);
+        });
+    }
+
+    fn remove_failed(&mut self, memory_mb: u64) {
+        self.scheduler.boot_finished();
+        self.scheduler.release(memory_mb);
+        self.schedule();
+    }
+
+    fn add_booted(
+        &mut self,
+        key: Uuid,
+        profile: UserWorkerProfile,
+        tx: oneshot::Sender<Result<CreateUserWorkerResult, Error>>,
+    ) {
+        if metrics_enabled() {
+            let tags = profile.metric_tags(key);
+            metrics::record("worker_boots", MetricKind::Counter, 1.0, &as_tags(&tags));
+        }
+        self.user_workers.insert(key, profile);
+        let _ = tx.send(Ok(CreateUserWorkerResult { key }));
+        self.scheduler.boot_finished();
+        self.autoscale(key);
+        self.schedule();
+    }
+
+    fn add_replica(&mut self, key: Uuid, replica: Option<UserWorkerReplica>) {
+        // the worker exited in the meantime, dropping the replica stops it
+        let Some(profile) = self.user_workers.get_mut(&key) else {
+            return;
+        };
+
+        if let Some(autoscaler) = &mut profile.autoscaler {
+            autoscaler.boot_finished();
+        }
+        if let Some(replica) = replica {
+            self.scheduler.reserve(profile.memory_mb);
+            debug!(
+                "[{}] {} isolate(s) running",
+                profile.worker_id,
+                profile.replicas.len() + 1
+            );
+            profile.replicas.push(replica);
+        }
+    }
+
+    // Replaces the isolates of the workers one at a time, booting each one's
+    // replacement before it stops taking requests. Joins the restart that's
+    // already running, if any.
+    fn restart_all(&mut self, tx: oneshot::Sender<RestartReport>) {
+        if let Some(restart) = &mut self.restart {
+            restart.waiters.push(tx);
+            return;
+        }
+
+        let pending: VecDeque<(Uuid, Uuid)> = self
+            .user_workers
+            .iter()
+            .flat_map(|(key, profile)| profile.replicas.iter().map(|r| (*key, r.id)))
+            .collect();
+        info!("restarting {} isolate(s), one at a time", pending.len());
+        self.restart = Some(RollingRestart {
+            pending,
+            report: RestartReport::default(),
+            waiters: vec![tx],
+        });
+        self.restart_next();
+    }
+
+    fn restart_next(&mut self) {
+        let Some(restart) = &mut self.restart else {
+            return;
+        };
+        while let Some((key, replica_id)) = restart.pending.pop_front() {
+            // skipped if it exited since the restart started
+            let Some(profile) = self.user_workers.get(&key) else {
+                continue;
+            };
+            if profile.replicas.iter().any(|r| r.id == replica_id) {
+                profile
+                    .spawner
+                    .restart(key, replica_id, self.lifecycle_tx.clone());
+                return;
+            }
+        }
+
+        let Some(restart) = self.restart.take() else {
+            return;
+        };
+        info!(
+            "rolling restart done, {} isolate(s) restarted and {} failed",
+            restart.report.restarted, restart.report.failed
+        );
+        for tx in restart.waiters {
+            let _ = tx.send(restart.report.clone());
+        }
+    }
+
+    // Routes the requests to the replacement from now on. The ones sent to
+    // the old isolate hold it until they're answered.
+    fn replace_replica(&mut self, key: Uuid, replica_id: Uuid, replacement: Option<Replacement>) {
+        let replaced = match (self.user_workers.get_mut(&key), replacement) {
+            (Some(profile), Some(replacement)) => {
+                match profile.replicas.iter_mut().find(|r| r.id == replica_id) {
+                    Some(replica) => *replica = replacement.replica,
+                    None => {
+                        // the old one exited while its replacement booted
+                        self.scheduler.reserve(profile.memory_mb);
+                        profile.replicas.push(replacement.replica);
+                    }
+                }
+                if let Some(sticky) = &mut profile.sticky {
+                    sticky.evicted(replica_id);
+                }
+                profile.spawner.opts = replacement.opts;
+                profile.manifest = Arc::new(replacement.manifest);
+                debug!("[{}] replaced isolate {}", profile.worker_id, replica_id);
+                true
+            }
+            // or the worker is gone, dropping the replacement stops it
+            _ => false,
+        };
+
+        if let Some(restart) = &mut self.restart {
+            if replaced {
+                restart.report.restarted += 1;
+            } else {
+                restart.report.failed += 1;
+            }
+        }
+        self.restart_next();
+    }
+
+    fn request_done(&mut self, key: Uuid, replica_id: Uuid, latency_ms: u64) {
+        let Some(profile) = self.user_workers.get_mut(&key) else {
+            return;
+        };
+
+        if let Some(replica) = profile.replicas.iter_mut().find(|r| r.id == replica_id) {
+            replica.in_flight = replica.in_flight.saturating_sub(1);
+            if replica.in_flight == 0 {
+                replica.idle_since = Some(Instant::now());
+            }
+        }
+        if let Some(autoscaler) = &mut profile.autoscaler {
+            autoscaler.record_latency(latency_ms);
+        }
+    }
+
+    fn autoscale(&mut self, key: Uuid) {
+        let Some(profile) = self.user_workers.get_mut(&key) else {
+            return;
+        };
+        let Some(autoscaler) = &mut profile.autoscaler else {
+            return;
+        };
+
+        let loads: Vec<ReplicaLoad> = profile
+            .replicas
+            .iter()
+            .map(|r| ReplicaLoad {
+                id: r.id,
+                in_flight: r.in_flight,
+                concurrency: profile.spawner.max_concurrent_requests,
+                idle_since: r.idle_since,
+            })
+            .collect();
+
+        match autoscaler.decide(&loads, Instant::now()) {
+            ScaleDecision::Hold => {}
+            ScaleDecision::Up(count) => {
+                debug!("[{}] booting {} more isolate(s)", profile.worker_id, count);
+                autoscaler.boot_started(count);
+                for _ in 0..count {
+                    profile.spawner.spawn(key, self.lifecycle_tx.clone());
+                }
+            }
+            ScaleDecision::Down(replica_id) => {
+                debug!(
+                    "[{}] stopping idle isolate {}",
+                    profile.worker_id, replica_id
+                );
+                // dropping the worker closes its connection, which ends the isolate
+                profile.replicas.retain(|r| r.id != replica_id);
+                self.scheduler.release(profile.memory_mb);
+                if let Some(sticky) = &mut profile.sticky {
+                    sticky.evicted(replica_id);
+                }
+            }
+        }
+    }
+
+    fn autoscale_all(&mut self) {
+        let keys: Vec<Uuid> = self.user_workers.keys().copied().collect();
+        let now = Instant::now();
+        for key in keys {
+            self.autoscale(key);
+            if let Some(sticky) = self
+                .user_workers
+                .get_mut(&key)
+                .and_then(|profile| profile.sticky.as_mut())
+            {
+                sticky.prune(now);
+            }
+        }
+    }
+
+    // what happened to the workers booted and the requests sent by the pool
+    fn handle_lifecycle(&mut self, lifecycle: UserWorkerLifecycle) {
+        match lifecycle {
+            UserWorkerLifecycle::Booted(key, profile, tx) => {
+                self.add_booted(key, profile, tx);
+            }
+            UserWorkerLifecycle::BootFailed(memory_mb) => {
+                self.remove_failed(memory_mb);
+            }
+            UserWorkerLifecycle::ReplicaBooted(key, replica) => {
+                self.add_replica(key, replica);
+            }
+            UserWorkerLifecycle::Exited(key, replica_id) => {
+                self.remove_exited(key, replica_id);
+            }
+            UserWorkerLifecycle::RequestDone(key, replica_id, latency_ms) => {
+                self.request_done(key, replica_id, latency_ms);
+            }
+            UserWorkerLifecycle::Coalesced(key, coalesce_key, shared) => {
+                self.coalesced(key, coalesce_key, shared);
+            }
+            UserWorkerLifecycle::Hooked(key, req, tx) => {
+                self.admit(key, req, tx);
+            }
+            UserWorkerLifecycle::Restarted(key, replica_id, replacement) => {
+                self.replace_replica(key, replica_id, replacement);
+            }
+        }
+    }
+
+    fn send_request(&mut self, key: Uuid, req: Request<Body>, tx: oneshot::Sender<Response<Body>>) {
+        let hooks = request_hooks();
+        let service = self.user_workers.get(&key).map(|p| p.service.clone());
+        // the service was disabled after its worker booted
+        if let Some(res) = maintenance_response(service.as_deref()) {
+            let _ = tx.send(res);
+            return;
+        }
+        let (Some(service), false) = (service, hooks.is_empty()) else {
+            return self.admit(key, req, tx);
+        };
+
+        // the hooks may take a while, the pool doesn't wait on them
+        let lifecycle_tx = self.lifecycle_tx.clone();
+        tokio::spawn(async move {
+            let mut req = req;
+            if let Some(res) = run_before_hooks(hooks, &service, &mut req).await {
+                let _ = tx.send(res);
+                return;
+            }
+            let head = HookRequest {
+                method: req.method().clone(),
+                uri: req.uri().clone(),
+                headers: req.headers().clone(),
+            };
+            let (res_tx, res_rx) = oneshot::channel();
+            let _ = lifecycle_tx.send(UserWorkerLifecycle::Hooked(key, req, res_tx));
+            if let Ok(mut res) = res_rx.await {
+                run_after_hooks(hooks, &service, &head, &mut res).await;
+                let _ = tx.send(res);
+            }
+        });
+    }
+
+    fn admit(&mut self, key: Uuid, req: Request<Body>, tx: oneshot::Sender<Response<Body>>) {
+        // identical GETs wait for the one being served, if the worker coalesces them
+        let admission = match self
+            .user_workers
+            .get_mut(&key)
+            .and_then(|profile| profile.coalescer.as_mut())
+        {
+            Some(coalescer) => coalescer.admit(req, tx),
+            None => Admission::Send(req, tx, None),
+        };
+        if let Admission::Send(req, tx, coalesce_key) = admission {
+            self.dispatch(key, req, tx, coalesce_key);
+        }
+    }
+
+    // hands the response of a coalesced request out to the ones that waited
+    // for it, or sends them to the worker if it can't be shared
+    fn coalesced(&mut self, key: Uuid, coalesce_key: CoalesceKey, shared: Option<SharedResponse>) {
+        let Some(waiting) = self
+            .user_workers
+            .get_mut(&key)
+            .and_then(|profile| profile.coalescer.as_mut())
+            .map(|coalescer| coalescer.finish(&coalesce_key))
+        else {
+            return;
+        };
+        for (req, tx) in waiting {
+            match &shared {
+                Some(shared) => {
+                    let _ = tx.send(shared.to_response());
+                }
+                None => self.dispatch(key, req, tx, None),
+            }
+        }
+    }
+
+    fn dispatch(
+        &mut self,
+        key: Uuid,
+        mut req: Request<Body>,
+        tx: oneshot::Sender<Response<Body>>,
+        coalesce_key: Option<CoalesceKey>,
+    ) {
+        let manifest = self
+            .user_workers
+            .get(&key)
+            .map(|profile| profile.manifest.clone())
+            .unwrap_or_default();
+        manifest.headers.request.apply(req.headers_mut());
+        let origin = req.headers().get(hyper::header::ORIGIN).cloned();
+
+        // tag the request, the errors the worker reports while handling it
+        // carry its id. Clients can't pick it, the one they sent is moved to
+        // `x-client-request-id`
+        let client_request_id_header = HeaderName::from_static("x-client-request-id");
+        req.headers_mut().remove(&client_request_id_header);
+        if let Some(client_request_id) = req.headers_mut().remove(REQUEST_ID_HEADER) {
+            req.headers_mut()
+                .insert(client_request_id_header, client_request_id);
+        }
+        let request_id = Uuid::new_v4().to_string();
+        req.headers_mut().insert(
+            REQUEST_ID_HEADER,
+            HeaderValue::from_str(&request_id).unwrap(),
+        );
+
+        // a service call goes as deep as the calling isolate said, the
+        // worker's isolates count their own calls from there
+        req.headers_mut().remove(BINDING_DEPTH_HEADER);
+        let binding_depths = match req.extensions().get::<BindingDepth>() {
+            Some(BindingDepth(depth)) => {
+                req.headers_mut()
+                    .insert(BINDING_DEPTH_HEADER, HeaderValue::from(*depth));
+                self.user_workers.get(&key).map(|profile| {
+                    profile.binding_depths.insert(request_id.clone(), *depth);
+                    profile.binding_depths.clone()
+                })
+            }
+            None => None,
+        };
+
+        // the isolate the request's session is pinned to, or else the one of
+        // the worker with the fewest pending requests
+        let Some((replica_id, worker, concurrency, request_timeout_ms)) =
+            self.user_workers.get_mut(&key).and_then(|profile| {
+                let pinned = profile.sticky.as_mut().and_then(|sticky| {
+                    let session = sticky.key(&req)?;
+                    let loads: Vec<(Uuid, usize)> = profile
+                        .replicas
+                        .iter()
+                        .map(|r| (r.id, r.in_flight))
+                        .collect();
+                    sticky.pick(session, &loads, Instant::now())
+                });
+                let replica = match pinned {
+                    Some(id) => profile.replicas.iter_mut().find(|r| r.id == id)?,
+                    None => profile.replicas.iter_mut().min_by_key(|r| r.in_flight)?,
+                };
+                replica.in_flight += 1;
+                replica.idle_since = None;
+                Some((
+                    replica.id,
+                    replica.worker.clone(),
+                    replica.concurrency.clone(),
+                    profile.request_timeout_ms,
+                ))
+            })
+        else {
+            let _ = tx.send(error_response(
+                503,
+                "Worker is no longer available, it may have exited.",
+            ));
+            if let Some(coalesce_key) = coalesce_key {
+                self.coalesced(key, coalesce_key, None);
+            }
+            return;
+        };
+
+        let max_shared_bytes = self
+            .user_workers
+            .get(&key)
+            .and_then(|profile| profile.coalescer.as_ref())
+            .map_or(0, |coalescer| coalescer.max_body_bytes());
+
+        let metric_tags = self
+            .user_workers
+            .get(&key)
+            .filter(|_| metrics_enabled())
+            .map(|profile| profile.metric_tags(key));
+
+        let billing = self
+            .user_workers
+            .get(&key)
+            .and_then(|profile| profile.billing.clone());
+        let recording = match self
+            .user_workers
+            .get_mut(&key)
+            .and_then(|profile| profile.audit.as_mut())
+        {
+            Some(auditor) if auditor.sample() => {
+                let (tee, recording) = auditor.record(req);
+                req = tee;
+                Some(recording)
+            }
+            _ => None,
+        };
+
+        // don't hold up the pool while the worker handles the request
+        let lifecycle_tx = self.lifecycle_tx.clone();
+        tokio::spawn(async move {
+            let started_at = SystemTime::now();
+            let start = Instant::now();
+            let mut res =
+                send_user_worker_request(worker, concurrency, req, request_timeout_ms).await;
+            if let Some(binding_depths) = binding_depths {
+                binding_depths.remove(&request_id);
+            }
+            manifest.headers.response.apply(res.headers_mut());
+            if let Some(cors) = &manifest.cors {
+                cors.apply(origin.as_ref(), res.headers_mut());
+            }
+            let res = match recording {
+                Some(recording) => recording.finish(res),
+                None => res,
+            };
+            let res = match billing {
+                Some(billing) => billing.finish(Some(request_id), started_at, start, res),
+                None => res,
+            };
+            let latency_ms = start.elapsed().as_millis() as u64;
+            if let Some(mut tags) = metric_tags {
+                tags.push(("status", res.status().as_u16().to_string()));
+                let tags = as_tags(&tags);
+                metrics::record("requests", MetricKind::Counter, 1.0, &tags);
+                metrics::record(
+                    "request_duration_ms",
+                    MetricKind::Timing,
+                    latency_ms as f64,
+                    &tags,
+                );
+            }
+            let _ = lifecycle_tx.send(UserWorkerLifecycle::RequestDone(
+                key, replica_id, latency_ms,
+            ));
+            let res = match coalesce_key {
+                Some(coalesce_key) => {
+                    let (res, shared) = coalesce::share(res, max_shared_bytes).await;
+                    let _ = lifecycle_tx.send(UserWorkerLifecycle::Coalesced(
+                        key,
+                        coalesce_key,
+                        shared,
+                    ));
+                    res
+                }
+                None => res,
+            };
+            let _ = tx.send(res);
+        });
+
+        self.autoscale(key);
     }
 }
 
+// The settings of the main worker and of the pool of user workers.
+#[derive(Debug, Clone, Default)]
+pub struct UserWorkerPoolOpts {
+    // inherited by the user workers
+    pub import_map_path: Option<String>,
+    // used after the ones of the user workers
+    pub auth_tokens: Option<String>,
+    pub no_module_cache: bool,
+    // the server runs offline, so do its user workers
+    pub offline: bool,
+    // Default time zone of the user workers, for `Intl` and the
+    // `toLocale*String` methods. It can be reloaded, unlike the process's
+    // (`TZ`), which the `Date` getters use.
+    pub timezone: Option<String>,
+    // keys remote services must be signed with
+    pub trusted_keys: Vec<String>,
+    // modules the main worker loads as it boots
+    pub warmup_specifiers: Vec<String>,
+    // limits how often user workers are created, per service
+    pub rate_limit: Option<RateLimitOpts>,
+    pub scheduler: SchedulerOpts,
+    pub outbound: OutboundOpts,
+}
+
 pub struct WorkerPool {
     pub main_worker: Arc<RwLock<WorkerContext>>,
+    // shared with the pool, lets embedders roll out new versions of a service
+    pub deployments: DeploymentRouter,
+    // registered by the main worker, lets the server answer unrouted paths
+    pub routes: RouteTable,
+    // per host, set when the fetch calls of workers are broken
+    pub fetch_breakers: Option<FetchBreakers>,
+    // reloaded settings for the pool
+    pub tunables_tx: mpsc::UnboundedSender<Tunables>,
+    // asks the pool for a rolling restart, see `rolling_restart`
+    pub restart_tx: mpsc::UnboundedSender<oneshot::Sender<RestartReport>>,
 }
 
 impl WorkerPool {
-    pub async fn new(
-        main_path: String,
-        import_map_path: Option<String>,
-        no_module_cache: bool,
-    ) -> Result<Self, Error> {
+    pub async fn new(main_path: String, opts: UserWorkerPoolOpts) -> Result<Self, Error> {
         let (user_worker_msgs_tx, mut user_worker_msgs_rx) =
             mpsc::unbounded_channel::<UserWorkerMsgs>();
 
-        let main_path = Path::new(&main_path);
+        let sources = ServiceSourceResolver::new(ServiceSourceResolver::default_cache_dir()?)
+            .with_trusted_keys(&opts.trusted_keys)?
+            .with_offline(opts.offline);
+        let main_source = sources.resolve(Path::new(&main_path), None, None).await?;
 
+        let routes = RouteTable::default();
+        let fetch_breakers = opts.outbound.fetch_breaker.clone().map(FetchBreakers::new);
         let main_worker_ctx = WorkerContext::new(EdgeContextInitOpts {
-            service_path: main_path.to_path_buf(),
-            import_map_path,
-            no_module_cache,
+            service_path: main_source.path.clone(),
+            import_map_path: opts.import_map_path.clone(),
+            auth_tokens: opts.auth_tokens.clone(),
+            no_module_cache: opts.no_module_cache,
+            offline: opts.offline,
             conf: EdgeContextOpts::MainWorker(EdgeMainRuntimeOpts {
                 worker_pool_tx: user_worker_msgs_tx,
+                warmup_specifiers: opts.warmup_specifiers.clone(),
+                routes: routes.clone(),
+                fetch_breakers: fetch_breakers.clone(),
             }),
             env_vars: std::env::vars().collect(),
+            fetch_interceptor: None,
+            outbound: opts.outbound.clone(),
+            extensions: None,
+            unstable_features: vec![],
         })
         .await?;
 
+        let (worker_events_tx, mut worker_events_rx) =
+            mpsc::unbounded_channel::<WorkerEventWithMetadata>();
+        tokio::spawn(async move {
+            while let Some(event) = worker_events_rx.recv().await {
+                log_worker_event(&event);
+            }
+        });
+
         let main_worker = Arc::new(RwLock::new(main_worker_ctx));
+        let deployments = DeploymentRouter::new();
+        let pool_deployments = deployments.clone();
+        let pool_fetch_breakers = fetch_breakers.clone();
+        let (tunables_tx, mut tunables_rx) = mpsc::unbounded_channel::<Tunables>();
+        let (restart_tx, mut restart_rx) =
+            mpsc::unbounded_channel::<oneshot::Sender<RestartReport>>();
         tokio::spawn(async move {
-            let mut user_workers: HashMap<Uuid, Arc<RwLock<WorkerContext>>> = HashMap::new();
+            // the main worker runs from it as long as the pool does
+            let _main_source = main_source;
+            let (lifecycle_tx, mut lifecycle_rx) = mpsc::unbounded_channel::<UserWorkerLifecycle>();
+            let mut user_worker_pool = UserWorkerPool::new(
+                &opts,
+                worker_events_tx,
+                lifecycle_tx,
+                pool_deployments,
+                sources,
+                pool_fetch_breakers,
+            );
 
+            let mut autoscale_interval = tokio::time::interval(AUTOSCALE_INTERVAL);
+            let mut metrics_interval = tokio::time::interval(METRICS_INTERVAL);
             loop {
-                match user_worker_msgs_rx.recv().await {
-                    None => break,
-                    Some(UserWorkerMsgs::Create(worker_options, tx)) => {
-                        let key = Uuid::new_v4();
-                        let user_worker_ctx = WorkerContext::new(worker_options).await;
-
-                        match user_worker_ctx {
-                            Ok(v) => {
-                                user_workers.insert(key, Arc::new(RwLock::new(v)));
-
-                                let _ = tx.send(Ok(CreateUserWorkerResult { key }));
-                            }
-                            Err(e) => {
-                                let _ = tx.send(Err(e));
-                            }
+                tokio::select! {
+                    msg = user_worker_msgs_rx.recv() => match msg {
+                        None => break,
+                        Some(UserWorkerMsgs::Create(worker_options, tx)) => {
+                            user_worker_pool.create(worker_options, tx);
+                        }
+                        Some(UserWorkerMsgs::SendRequest(key, req, tx)) => {
+                            user_worker_pool.send_request(key, req, tx);
+                        }
+                        Some(UserWorkerMsgs::NetUsage(key, tx)) => {
+                            let _ = tx.send(user_worker_pool.net_usage(key));
+                        }
+                        Some(UserWorkerMsgs::OpenSockets(key, tx)) => {
+                            let _ = tx.send(user_worker_pool.open_sockets(key));
                         }
+                        Some(UserWorkerMsgs::Preflight(service_path, req, tx)) => {
+                            user_worker_pool.preflight(service_path, req, tx);
+                        }
+                    },
+                    Some(lifecycle) = lifecycle_rx.recv() => {
+                        user_worker_pool.handle_lifecycle(lifecycle);
+                    }
+                    Some(tunables) = tunables_rx.recv() => {
+                        user_worker_pool.retune(tunables);
+                    }
+                    Some(tx) = restart_rx.recv() => {
+                        user_worker_pool.restart_all(tx);
                     }
-                    Some(UserWorkerMsgs::SendRequest(key, req, tx)) => {
-                        // TODO: handle errors
-                        let worker = user_workers.get(&key).unwrap();
-                        let mut worker = worker.write().await;
-                        // TODO: Json format
-                        // TODO: Ability to attach hook
-                        let res = worker.send_request(req).await.unwrap_or_else(|_e| Response::builder().status(408).body(Body::from(deno_core::serde_json::json!({
-                            "msg": "Request could not be processed by the server because it timed out or an error was thrown."
-                        }).to_string())).unwrap());
-
-                        let _ = tx.send(res);
+                    _ = autoscale_interval.tick() => {
+                        user_worker_pool.autoscale_all();
+                    }
+                    _ = metrics_interval.tick(), if metrics_enabled() => {
+                        user_worker_pool.report_metrics();
                     }
                 }
             }
         });
 
-        Ok(Self { main_worker })
+        Ok(Self {
+            main_worker,
+            deployments,
+            routes,
+            fetch_breakers,
+            tunables_tx,
+            restart_tx,
+        })
+    }
+
+    // Replaces the isolates of the user workers one at a time (the
+    // replacement boots, takes the new requests, and the old isolate stops
+    // once it answered the ones it had), so changes to their code and
+    // manifests are picked up without a gap in the traffic. Resolves once
+    // they're all replaced.
+    pub async fn rolling_restart(&self) -> Result<RestartReport, Error> {
+        request_rolling_restart(&self.restart_tx).await
+    }
+}
+
+pub(crate) async fn request_rolling_restart(
+    restart_tx: &mpsc::UnboundedSender<oneshot::Sender<RestartReport>>,
+) -> Result<RestartReport, Error> {
+    let (tx, rx) = oneshot::channel();
+    restart_tx
+        .send(tx)
+        .map_err(|_| anyhow!("the worker pool is gone"))?;
+    Ok(rx.await?)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use hyper::StatusCode;
+    use sb_worker_context::essentials::{EdgeUserRuntimeOpts, ServiceBindings};
+    use std::path::PathBuf;
+
+    // handles what happens to the pool's workers, like its loop does, until
+    // `rx` resolves
+    async fn run_pool_until<T>(
+        pool: &mut UserWorkerPool,
+        lifecycle_rx: &mut mpsc::UnboundedReceiver<UserWorkerLifecycle>,
+        mut rx: oneshot::Receiver<T>,
+    ) -> T {
+        loop {
+            tokio::select! {
+                Some(lifecycle) = lifecycle_rx.recv() => pool.handle_lifecycle(lifecycle),
+                res = &mut rx => return res.unwrap(),
+            }
+        }
+    }
+
+    // like `run_pool_until`, also sending the requests workers make to the
+    // services bound to them, their keys are first looked up in `keys`
+    async fn run_bound_pool_until<T>(
+        pool: &mut UserWorkerPool,
+        lifecycle_rx: &mut mpsc::UnboundedReceiver<UserWorkerLifecycle>,
+        msgs_rx: &mut mpsc::UnboundedReceiver<UserWorkerMsgs>,
+        keys: &HashMap<Uuid, Uuid>,
+        mut rx: oneshot::Receiver<T>,
+    ) -> T {
+        loop {
+            tokio::select! {
+                Some(lifecycle) = lifecycle_rx.recv() => pool.handle_lifecycle(lifecycle),
+                Some(UserWorkerMsgs::SendRequest(key, req, tx)) = msgs_rx.recv() => {
+                    pool.send_request(*keys.get(&key).unwrap_or(&key), req, tx);
+                }
+                res = &mut rx => return res.unwrap(),
+            }
+        }
+    }
+
+    fn create_pool() -> (
+        UserWorkerPool,
+        mpsc::UnboundedReceiver<UserWorkerLifecycle>,
+        PathBuf,
+    ) {
+        let cache_dir = std::env::temp_dir().join(format!("sb-sources-{}", Uuid::new_v4()));
+        let (events_tx, _events_rx) = mpsc::unbounded_channel::<WorkerEventWithMetadata>();
+        let (lifecycle_tx, lifecycle_rx) = mpsc::unbounded_channel::<UserWorkerLifecycle>();
+        let pool = UserWorkerPool::new(
+            &UserWorkerPoolOpts::default(),
+            events_tx,
+            lifecycle_tx,
+            DeploymentRouter::new(),
+            ServiceSourceResolver::new(cache_dir.clone()),
+            None,
+        );
+        (pool, lifecycle_rx, cache_dir)
+    }
+
+    fn user_worker_opts(service_path: &str, user_opts: EdgeUserRuntimeOpts) -> EdgeContextInitOpts {
+        EdgeContextInitOpts {
+            service_path: PathBuf::from(service_path),
+            no_module_cache: false,
+            offline: false,
+            import_map_path: None,
+            auth_tokens: None,
+            env_vars: HashMap::new(),
+            fetch_interceptor: None,
+            outbound: OutboundOpts::default(),
+            extensions: None,
+            unstable_features: vec![],
+            conf: EdgeContextOpts::UserWorker(user_opts),
+        }
+    }
+
+    // boots a user worker through the pool, returning its key
+    async fn create_user_worker_in(
+        pool: &mut UserWorkerPool,
+        lifecycle_rx: &mut mpsc::UnboundedReceiver<UserWorkerLifecycle>,
+        opts: EdgeContextInitOpts,
+    ) -> Result<Uuid, Error> {
+        let (tx, rx) = oneshot::channel();
+        pool.create(opts, tx);
+        Ok(run_pool_until(pool, lifecycle_rx, rx).await?.key)
+    }
+
+    // the headers the `request_headers` worker saw
+    async fn echoed_headers(res: Response<Body>) -> HashMap<String, String> {
+        let body = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        let headers: Vec<(String, String)> = deno_core::serde_json::from_slice(&body).unwrap();
+        headers.into_iter().collect()
+    }
+
+    #[tokio::test]
+    async fn test_request_ids() {
+        let (mut pool, mut lifecycle_rx, cache_dir) = create_pool();
+        let key = create_user_worker_in(
+            &mut pool,
+            &mut lifecycle_rx,
+            user_worker_opts(
+                "./test_cases/request_headers",
+                EdgeUserRuntimeOpts {
+                    worker_timeout_ms: 5000,
+                    ..Default::default()
+                },
+            ),
+        )
+        .await
+        .unwrap();
+
+        // both sent before either is answered, with the same forged id
+        let mut responses = vec![];
+        for _ in 0..2 {
+            let req = Request::get("http://localhost/")
+                .header("x-request-id", "forged")
+                .header("x-client-request-id", "also-forged")
+                .body(Body::empty())
+                .unwrap();
+            let (tx, rx) = oneshot::channel();
+            pool.send_request(key, req, tx);
+            responses.push(rx);
+        }
+        let mut request_ids = vec![];
+        for rx in responses {
+            let res = run_pool_until(&mut pool, &mut lifecycle_rx, rx).await;
+            let headers = echoed_headers(res).await;
+            assert_eq!(headers["x-client-request-id"], "forged");
+            request_ids.push(headers["x-request-id"].clone());
+        }
+
+        assert!(request_ids.iter().all(|id| id != "forged"));
+        assert!(request_ids.iter().all(|id| Uuid::parse_str(id).is_ok()));
+        assert_ne!(request_ids[0], request_ids[1]);
+
+        let _ = std::fs::remove_dir_all(cache_dir);
+    }
+
+    #[tokio::test]
+    async fn test_request_timeout_after_queue() {
+        let (mut pool, mut lifecycle_rx, cache_dir) = create_pool();
+        let key = create_user_worker_in(
+            &mut pool,
+            &mut lifecycle_rx,
+            user_worker_opts(
+                "./test_cases/slow_response",
+                EdgeUserRuntimeOpts {
+                    worker_timeout_ms: 5000,
+                    request_timeout_ms: Some(300),
+                    max_concurrent_requests: Some(1),
+                    ..Default::default()
+                },
+            ),
+        )
+        .await
+        .unwrap();
+
+        // the second one waits 200ms for the first one's slot, and is
+        // answered 200ms after it's sent
+        let start = Instant::now();
+        let mut responses = vec![];
+        for _ in 0..2 {
+            let req = Request::get("http://localhost/")
+                .body(Body::empty())
+                .unwrap();
+            let (tx, rx) = oneshot::channel();
+            pool.send_request(key, req, tx);
+            responses.push(rx);
+        }
+        for rx in responses {
+            let res = run_pool_until(&mut pool, &mut lifecycle_rx, rx).await;
+            assert_eq!(res.status(), StatusCode::OK);
+        }
+        assert!(start.elapsed() >= Duration::from_millis(400));
+
+        let _ = std::fs::remove_dir_all(cache_dir);
+    }
+
+    #[tokio::test]
+    async fn test_request_timeout() {
+        let (mut pool, mut lifecycle_rx, cache_dir) = create_pool();
+        let key = create_user_worker_in(
+            &mut pool,
+            &mut lifecycle_rx,
+            user_worker_opts(
+                "./test_cases/never_responds",
+                EdgeUserRuntimeOpts {
+                    worker_timeout_ms: 2000,
+                    request_timeout_ms: Some(200),
+                    max_concurrent_requests: Some(1),
+                    ..Default::default()
+                },
+            ),
+        )
+        .await
+        .unwrap();
+
+        let start = Instant::now();
+        let req = Request::get("http://localhost/")
+            .body(Body::empty())
+            .unwrap();
+        let (tx, rx) = oneshot::channel();
+        pool.send_request(key, req, tx);
+        let res = run_pool_until(&mut pool, &mut lifecycle_rx, rx).await;
+        assert_eq!(res.status(), StatusCode::GATEWAY_TIMEOUT);
+        assert!(start.elapsed() >= Duration::from_millis(200));
+        assert!(start.elapsed() < Duration::from_millis(2000));
+
+        // the request's slot is freed, but the isolate outlives it
+        while let Ok(lifecycle) = lifecycle_rx.try_recv() {
+            pool.handle_lifecycle(lifecycle);
+        }
+        let replica = &pool.user_workers[&key].replicas[0];
+        assert_eq!(replica.in_flight, 0);
+        assert_eq!(replica.concurrency.as_ref().unwrap().available_permits(), 1);
+
+        // and is removed from the pool once it's past the worker timeout
+        tokio::time::timeout(Duration::from_secs(10), async {
+            while pool.user_workers.contains_key(&key) {
+                let lifecycle = lifecycle_rx.recv().await.unwrap();
+                pool.handle_lifecycle(lifecycle);
+            }
+        })
+        .await
+        .unwrap();
+
+        let _ = std::fs::remove_dir_all(cache_dir);
+    }
+
+    // a `service_bindings` worker, calling the one bound to `name` at `key`
+    fn bound_worker_opts(
+        msgs_tx: &mpsc::UnboundedSender<UserWorkerMsgs>,
+        name: &str,
+        key: Uuid,
+    ) -> EdgeContextInitOpts {
+        user_worker_opts(
+            "./test_cases/service_bindings",
+            EdgeUserRuntimeOpts {
+                worker_timeout_ms: 5000,
+                service_bindings: Some(ServiceBindings {
+                    worker_pool_tx: msgs_tx.clone(),
+                    workers: HashMap::from([(name.to_string(), key)]),
+                }),
+                ..Default::default()
+            },
+        )
+    }
+
+    #[tokio::test]
+    async fn test_service_binding_loop() {
+        let (mut pool, mut lifecycle_rx, cache_dir) = create_pool();
+        let (msgs_tx, mut msgs_rx) = mpsc::unbounded_channel::<UserWorkerMsgs>();
+
+        // each worker is bound to the other one, through keys that only
+        // exist once both are created
+        let (to_a, to_b) = (Uuid::new_v4(), Uuid::new_v4());
+        let a = create_user_worker_in(
+            &mut pool,
+            &mut lifecycle_rx,
+            bound_worker_opts(&msgs_tx, "next", to_b),
+        )
+        .await
+        .unwrap();
+        let b = create_user_worker_in(
+            &mut pool,
+            &mut lifecycle_rx,
+            bound_worker_opts(&msgs_tx, "next", to_a),
+        )
+        .await
+        .unwrap();
+        let keys = HashMap::from([(to_a, a), (to_b, b)]);
+
+        // A -> B -> A -> ... until A is called 16 levels deep
+        let req = Request::get("http://localhost/next")
+            .body(Body::empty())
+            .unwrap();
+        let (tx, rx) = oneshot::channel();
+        pool.send_request(a, req, tx);
+        let res = run_bound_pool_until(&mut pool, &mut lifecycle_rx, &mut msgs_rx, &keys, rx).await;
+        assert_eq!(res.status(), StatusCode::INTERNAL_SERVER_ERROR);
+        let body = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        assert_eq!(&body[..], b"RangeError at depth 16");
+
+        // the depth a client claims is ignored
+        let req = Request::get("http://localhost/next")
+            .header(BINDING_DEPTH_HEADER, "1000")
+            .body(Body::empty())
+            .unwrap();
+        let (tx, rx) = oneshot::channel();
+        pool.send_request(a, req, tx);
+        let res = run_bound_pool_until(&mut pool, &mut lifecycle_rx, &mut msgs_rx, &keys, rx).await;
+        let body = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        assert_eq!(&body[..], b"RangeError at depth 16");
+
+        let _ = std::fs::remove_dir_all(cache_dir);
+    }
+
+    #[tokio::test]
+    async fn test_service_binding_missing() {
+        let (mut pool, mut lifecycle_rx, cache_dir) = create_pool();
+        let (msgs_tx, mut msgs_rx) = mpsc::unbounded_channel::<UserWorkerMsgs>();
+
+        // bound to a worker that isn't in the pool (anymore)
+        let key = create_user_worker_in(
+            &mut pool,
+            &mut lifecycle_rx,
+            bound_worker_opts(&msgs_tx, "gone", Uuid::new_v4()),
+        )
+        .await
+        .unwrap();
+        let keys = HashMap::new();
+
+        let req = Request::get("http://localhost/gone")
+            .body(Body::empty())
+            .unwrap();
+        let (tx, rx) = oneshot::channel();
+        pool.send_request(key, req, tx);
+        let res = run_bound_pool_until(&mut pool, &mut lifecycle_rx, &mut msgs_rx, &keys, rx).await;
+        assert_eq!(res.status(), StatusCode::SERVICE_UNAVAILABLE);
+
+        // and to nothing at all
+        let req = Request::get("http://localhost/unbound")
+            .body(Body::empty())
+            .unwrap();
+        let (tx, rx) = oneshot::channel();
+        pool.send_request(key, req, tx);
+        let res = run_bound_pool_until(&mut pool, &mut lifecycle_rx, &mut msgs_rx, &keys, rx).await;
+        assert_eq!(res.status(), StatusCode::INTERNAL_SERVER_ERROR);
+        let body = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        assert_eq!(&body[..], b"TypeError at depth 0");
+
+        let _ = std::fs::remove_dir_all(cache_dir);
+    }
+
+    // a service answering `body`, in a directory of its own so it can be
+    // changed while its worker runs
+    fn write_service(dir: &Path, body: &str) {
+        std::fs::create_dir_all(dir).unwrap();
+        std::fs::write(
+            dir.join("index.ts"),
+            format!("Deno.serve(() => new Response({:?}));", body),
+        )
+        .unwrap();
+    }
+
+    async fn get_body(
+        pool: &mut UserWorkerPool,
+        lifecycle_rx: &mut mpsc::UnboundedReceiver<UserWorkerLifecycle>,
+        key: Uuid,
+    ) -> String {
+        let req = Request::get("http://localhost/")
+            .body(Body::empty())
+            .unwrap();
+        let (tx, rx) = oneshot::channel();
+        pool.send_request(key, req, tx);
+        let res = run_pool_until(pool, lifecycle_rx, rx).await;
+        assert_eq!(res.status(), StatusCode::OK);
+        let body = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        String::from_utf8(body.to_vec()).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_rolling_restart() {
+        let (mut pool, mut lifecycle_rx, cache_dir) = create_pool();
+        let service_dir = std::env::temp_dir().join(format!("sb-service-{}", Uuid::new_v4()));
+        write_service(&service_dir, "v1");
+        let key = create_user_worker_in(
+            &mut pool,
+            &mut lifecycle_rx,
+            user_worker_opts(
+                service_dir.to_str().unwrap(),
+                EdgeUserRuntimeOpts {
+                    worker_timeout_ms: 10000,
+                    ..Default::default()
+                },
+            ),
+        )
+        .await
+        .unwrap();
+        assert_eq!(get_body(&mut pool, &mut lifecycle_rx, key).await, "v1");
+
+        // requests keep being answered while the isolate is replaced
+        write_service(&service_dir, "v2");
+        let (tx, mut restart_rx) = oneshot::channel();
+        pool.restart_all(tx);
+        let report = loop {
+            let body = get_body(&mut pool, &mut lifecycle_rx, key).await;
+            assert!(body == "v1" || body == "v2");
+            if let Ok(report) = restart_rx.try_recv() {
+                break report;
+            }
+        };
+        assert_eq!(
+            report,
+            RestartReport {
+                restarted: 1,
+                failed: 0
+            }
+        );
+        assert_eq!(get_body(&mut pool, &mut lifecycle_rx, key).await, "v2");
+        assert_eq!(pool.user_workers[&key].replicas.len(), 1);
+
+        let _ = std::fs::remove_dir_all(service_dir);
+        let _ = std::fs::remove_dir_all(cache_dir);
+    }
+
+    #[tokio::test]
+    async fn test_rolling_restart_failed() {
+        let (mut pool, mut lifecycle_rx, cache_dir) = create_pool();
+        let service_dir = std::env::temp_dir().join(format!("sb-service-{}", Uuid::new_v4()));
+        write_service(&service_dir, "v1");
+        let key = create_user_worker_in(
+            &mut pool,
+            &mut lifecycle_rx,
+            user_worker_opts(
+                service_dir.to_str().unwrap(),
+                EdgeUserRuntimeOpts {
+                    worker_timeout_ms: 10000,
+                    ..Default::default()
+                },
+            ),
+        )
+        .await
+        .unwrap();
+        let replica_id = pool.user_workers[&key].replicas[0].id;
+
+        // the replacement can't boot, the old isolate keeps serving
+        std::fs::write(service_dir.join("index.ts"), "throw new Error(\"broken\");").unwrap();
+        let (tx, rx) = oneshot::channel();
+        pool.restart_all(tx);
+        let report = run_pool_until(&mut pool, &mut lifecycle_rx, rx).await;
+        assert_eq!(
+            report,
+            RestartReport {
+                restarted: 0,
+                failed: 1
+            }
+        );
+        assert_eq!(pool.user_workers[&key].replicas[0].id, replica_id);
+        assert_eq!(get_body(&mut pool, &mut lifecycle_rx, key).await, "v1");
+
+        let _ = std::fs::remove_dir_all(service_dir);
+        let _ = std::fs::remove_dir_all(cache_dir);
+    }
+
+    #[tokio::test]
+    async fn test_request_timeout_response() {
+        let start = Instant::now();
+        let res = with_request_timeout(Some(200), std::future::pending()).await;
+        assert_eq!(res.status(), StatusCode::GATEWAY_TIMEOUT);
+        assert!(start.elapsed() >= Duration::from_millis(200));
+    }
+
+    #[tokio::test]
+    async fn test_request_within_timeout() {
+        let res = with_request_timeout(Some(200), async { Response::new(Body::from("ok")) }).await;
+        assert_eq!(res.status(), StatusCode::OK);
     }
 }
@@ -0,0 +1,164 @@
+use once_cell::sync::Lazy;
+use sb_worker_context::net_usage::{NetUsage, NetUsageSnapshot};
+use sb_worker_context::open_sockets::{OpenSockets, SocketUsage};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::sync::{mpsc, watch};
+use uuid::Uuid;
+
+// by worker id, an autoscaled worker has a handle per isolate
+static WORKER_HANDLES: Lazy<Mutex<HashMap<String, Vec<WorkerHandle>>>> =
+    Lazy::new(Default::default);
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct WorkerStats {
+    pub worker_id: String,
+    pub service: String,
+    pub uptime: Duration,
+    // until the worker reaches its wall clock limit
+    pub deadline_in: Duration,
+    pub net_usage: NetUsageSnapshot,
+    pub open_sockets: OpenSockets,
+}
+
+#[derive(Debug)]
+struct HandleInner {
+    worker_id: String,
+    isolate_id: Uuid,
+    service: String,
+    started: Instant,
+    deadline_tx: Arc<watch::Sender<Instant>>,
+    terminate_tx: mpsc::UnboundedSender<()>,
+    net_usage: Arc<NetUsage>,
+    socket_usage: Arc<SocketUsage>,
+}
+
+// An isolate of a running user worker, for embedders supervising the
+// workers themselves. Cheap to clone, and safe to keep once the isolate is
+// gone (its calls then do nothing).
+#[derive(Debug, Clone)]
+pub struct WorkerHandle {
+    inner: Arc<HandleInner>,
+}
+
+impl WorkerHandle {
+    pub(crate) fn new(
+        worker_id: String,
+        service: String,
+        deadline_tx: Arc<watch::Sender<Instant>>,
+        terminate_tx: mpsc::UnboundedSender<()>,
+        net_usage: Arc<NetUsage>,
+        socket_usage: Arc<SocketUsage>,
+    ) -> Self {
+        Self {
+            inner: Arc::new(HandleInner {
+                worker_id,
+                isolate_id: Uuid::new_v4(),
+                service,
+                started: Instant::now(),
+                deadline_tx,
+                terminate_tx,
+                net_usage,
+                socket_usage,
+            }),
+        }
+    }
+
+    pub fn worker_id(&self) -> &str {
+        &self.inner.worker_id
+    }
+
+    pub fn isolate_id(&self) -> Uuid {
+        self.inner.isolate_id
+    }
+
+    pub fn service(&self) -> &str {
+        &self.inner.service
+    }
+
+    // the worker's `NetUsage` and `SocketUsage` are shared by its isolates
+    pub fn stats(&self) -> WorkerStats {
+        WorkerStats {
+            worker_id: self.inner.worker_id.clone(),
+            service: self.inner.service.clone(),
+            uptime: self.inner.started.elapsed(),
+            deadline_in: self.deadline().saturating_duration_since(Instant::now()),
+            net_usage: self.inner.net_usage.snapshot(),
+            open_sockets: self.inner.socket_usage.snapshot(),
+        }
+    }
+
+    // terminates the isolate as if it had reached one of its limits, its
+    // run ends with `EdgeCallResult::Terminated`
+    pub fn terminate(&self) {
+        let _ = self.inner.terminate_tx.send(());
+    }
+
+    // when the isolate reaches its wall clock limit
+    pub fn deadline(&self) -> Instant {
+        *self.inner.deadline_tx.borrow()
+    }
+
+    // moves the wall clock limit, earlier or later, regardless of the
+    // `max_wall_clock_extension_ms` the worker itself is held to
+    pub fn set_deadline(&self, at: Instant) {
+        self.inner
+            .deadline_tx
+            .send_modify(|deadline| *deadline = at);
+    }
+
+    pub fn extend_deadline(&self, by: Duration) -> Instant {
+        self.inner
+            .deadline_tx
+            .send_modify(|deadline| *deadline += by);
+        self.deadline()
+    }
+}
+
+// Unregisters the handle once the isolate is gone.
+pub(crate) struct WorkerHandleRegistration(WorkerHandle);
+
+impl Drop for WorkerHandleRegistration {
+    fn drop(&mut self) {
+        let mut handles = WORKER_HANDLES.lock().unwrap();
+        let worker_id = self.0.worker_id();
+        if let Some(isolates) = handles.get_mut(worker_id) {
+            isolates.retain(|h| h.isolate_id() != self.0.isolate_id());
+            if isolates.is_empty() {
+                handles.remove(worker_id);
+            }
+        }
+    }
+}
+
+pub(crate) fn register_worker_handle(handle: WorkerHandle) -> WorkerHandleRegistration {
+    WORKER_HANDLES
+        .lock()
+        .unwrap()
+        .entry(handle.worker_id().to_string())
+        .or_default()
+        .push(handle.clone());
+    WorkerHandleRegistration(handle)
+}
+
+// the handles of the running isolates of a worker, empty once it's gone
+pub fn worker_handles(worker_id: &str) -> Vec<WorkerHandle> {
+    WORKER_HANDLES
+        .lock()
+        .unwrap()
+        .get(worker_id)
+        .cloned()
+        .unwrap_or_default()
+}
+
+// the handles of every running isolate of the user workers
+pub fn all_worker_handles() -> Vec<WorkerHandle> {
+    WORKER_HANDLES
+        .lock()
+        .unwrap()
+        .values()
+        .flatten()
+        .cloned()
+        .collect()
+}
@@ -0,0 +1,161 @@
+use anyhow::{bail, Error};
+use log::{debug, error};
+use once_cell::sync::OnceCell;
+use std::future::Future;
+use std::panic::{catch_unwind, AssertUnwindSafe};
+use std::pin::Pin;
+use std::sync::{mpsc, Arc, Mutex};
+use std::thread;
+
+type IsolateJob = Box<dyn FnOnce() -> Pin<Box<dyn Future<Output = ()>>> + Send>;
+
+static POOL: OnceCell<IsolateThreadPool> = OnceCell::new();
+
+fn default_size() -> usize {
+    thread::available_parallelism()
+        .map(|n| n.get())
+        .unwrap_or(1)
+}
+
+// Sets the number of isolate threads kept around. Must be called before the
+// first worker is created.
+pub fn init_isolate_threads(size: usize) -> Result<(), Error> {
+    if POOL.set(IsolateThreadPool::new(size)).is_err() {
+        bail!("the isolate thread pool is already running");
+    }
+    Ok(())
+}
+
+// Runs a future on a thread of its own, with its own runtime and LocalSet,
+// like every isolate (main and user workers) does.
+pub fn spawn_isolate<F, Fut>(f: F)
+where
+    F: FnOnce() -> Fut + Send + 'static,
+    Fut: Future<Output = ()> + 'static,
+{
+    POOL.get_or_init(|| IsolateThreadPool::new(default_size()))
+        .spawn(Box::new(move || Box::pin(f())));
+}
+
+struct PoolState {
+    // threads waiting for an isolate
+    idle: Vec<mpsc::Sender<IsolateJob>>,
+    threads: usize,
+}
+
+// Threads an isolate gets to itself while it runs. Up to `size` of them are
+// kept once their isolate exits and reused for the next ones, isolates
+// started while all of them are busy get a thread that exits with them.
+#[derive(Clone)]
+pub struct IsolateThreadPool {
+    size: usize,
+    state: Arc<Mutex<PoolState>>,
+}
+
+fn run_job(job: IsolateJob) -> bool {
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .unwrap();
+    run_job_on(&runtime, job)
+}
+
+fn run_job_on(runtime: &tokio::runtime::Runtime, job: IsolateJob) -> bool {
+    // a LocalSet per isolate, so tasks it left behind are dropped with it
+    let local = tokio::task::LocalSet::new();
+    catch_unwind(AssertUnwindSafe(|| local.block_on(runtime, job()))).is_ok()
+}
+
+impl IsolateThreadPool {
+    pub fn new(size: usize) -> Self {
+        Self {
+            size: size.max(1),
+            state: Arc::new(Mutex::new(PoolState {
+                idle: vec![],
+                threads: 0,
+            })),
+        }
+    }
+
+    pub fn spawn(&self, mut job: IsolateJob) {
+        let mut state = self.state.lock().unwrap();
+        while let Some(tx) = state.idle.pop() {
+            match tx.send(job) {
+                Ok(()) => return,
+                // the thread exited, try the next one
+                Err(mpsc::SendError(returned)) => job = returned,
+            }
+        }
+
+        if state.threads >= self.size {
+            drop(state);
+            debug!("all isolate threads are busy, starting a temporary one");
+            thread::spawn(move || {
+                if !run_job(job) {
+                    error!("isolate thread panicked");
+                }
+            });
+            return;
+        }
+
+        state.threads += 1;
+        drop(state);
+
+        let (tx, rx) = mpsc::channel::<IsolateJob>();
+        let _ = tx.send(job);
+        let pool = self.clone();
+        thread::spawn(move || {
+            let runtime = tokio::runtime::Builder::new_current_thread()
+                .enable_all()
+                .build()
+                .unwrap();
+
+            while let Ok(job) = rx.recv() {
+                if !run_job_on(&runtime, job) {
+                    error!("isolate thread panicked");
+                    break;
+                }
+                pool.state.lock().unwrap().idle.push(tx.clone());
+            }
+
+            pool.state.lock().unwrap().threads -= 1;
+        });
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::time::Duration;
+
+    fn thread_id(pool: &IsolateThreadPool, wait: Option<mpsc::Receiver<()>>) -> thread::ThreadId {
+        let (tx, rx) = mpsc::channel();
+        pool.spawn(Box::new(move || {
+            Box::pin(async move {
+                let _ = tx.send(thread::current().id());
+                if let Some(wait) = wait {
+                    let _ = wait.recv();
+                }
+            })
+        }));
+        rx.recv_timeout(Duration::from_secs(5)).unwrap()
+    }
+
+    #[test]
+    fn test_isolate_threads_are_reused() {
+        let pool = IsolateThreadPool::new(1);
+
+        let first = thread_id(&pool, None);
+        // give the thread time to go back to the pool
+        thread::sleep(Duration::from_millis(50));
+        assert_eq!(thread_id(&pool, None), first);
+
+        // a busy pool doesn't make isolates wait for each other
+        let (release_tx, release_rx) = mpsc::channel();
+        thread::sleep(Duration::from_millis(50));
+        let busy = thread_id(&pool, Some(release_rx));
+        let other = thread_id(&pool, None);
+        assert_ne!(busy, other);
+        let _ = release_tx.send(());
+    }
+}
@@ -0,0 +1,28 @@
+const MiB = 1024 * 1024;
+
+const listener = Deno.listen({ port: 9999 });
+
+for await (const conn of listener) {
+  const httpConn = Deno.serveHttp(conn);
+  for await (const { respondWith } of httpConn) {
+    // spilled to disk
+    const large = new Blob([new Uint8Array(2 * MiB).fill(97)]);
+    const small = new Blob(["hello"]);
+
+    let overCap = null;
+    try {
+      new Blob([new Uint8Array(2 * MiB)]);
+    } catch (err) {
+      overCap = err.name;
+    }
+
+    const text = await large.slice(0, 3).text();
+    const size = (await large.arrayBuffer()).byteLength;
+    respondWith(Response.json({
+      text,
+      size,
+      small: await small.text(),
+      overCap,
+    }));
+  }
+}
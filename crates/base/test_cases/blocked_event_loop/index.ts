@@ -0,0 +1,5 @@
+// blocks the event loop once the worker booted
+setTimeout(() => {
+  while (true) {
+  }
+}, 100);
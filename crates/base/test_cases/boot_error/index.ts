@@ -0,0 +1,2 @@
+// @ts-ignore
+throw new Error("failed to boot");
@@ -0,0 +1,8 @@
+Deno.serve((req) => {
+  const params = new URL(req.url).searchParams;
+  const key = params.get("key")!;
+  if (params.has("value")) {
+    EdgeRuntime.cache.set(key, { value: params.get("value") });
+  }
+  return Response.json(EdgeRuntime.cache.get(key) ?? null);
+});
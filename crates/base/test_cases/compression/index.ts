@@ -0,0 +1,24 @@
+const text = "hello world, ".repeat(100);
+
+async function roundTrip(format: string) {
+  const compressed = await new Response(
+    new Blob([text]).stream().pipeThrough(new CompressionStream(format)),
+  ).arrayBuffer();
+  const decompressed = await new Response(
+    new Blob([compressed]).stream().pipeThrough(new DecompressionStream(format)),
+  ).text();
+  return { compressed: compressed.byteLength, ok: decompressed === text };
+}
+
+const listener = Deno.listen({ port: 9999 });
+
+for await (const conn of listener) {
+  const httpConn = Deno.serveHttp(conn);
+  for await (const { respondWith } of httpConn) {
+    const results: Record<string, unknown> = {};
+    for (const format of ["gzip", "deflate", "deflate-raw", "br"]) {
+      results[format] = await roundTrip(format);
+    }
+    respondWith(Response.json(results));
+  }
+}
@@ -0,0 +1,11 @@
+let release: () => void;
+const released = new Promise<void>((resolve) => release = resolve);
+
+Deno.serve(async (req: Request) => {
+  if (new URL(req.url).pathname === "/release") {
+    release();
+    return new Response("ok");
+  }
+  await released;
+  return new Response("released");
+});
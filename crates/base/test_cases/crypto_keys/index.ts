@@ -0,0 +1,28 @@
+async function errorName(fn: () => Promise<unknown>) {
+  try {
+    await fn();
+    return "none";
+  } catch (err) {
+    return err.name;
+  }
+}
+
+Deno.serve(async () => {
+  // webhook style signature
+  const signing = await crypto.subtle.generateKey({ name: "Ed25519" }, true, ["sign", "verify"]);
+  const payload = new TextEncoder().encode("payload");
+  const signature = await crypto.subtle.sign({ name: "Ed25519" }, signing.privateKey, payload);
+  const verified = await crypto.subtle.verify({ name: "Ed25519" }, signing.publicKey, signature, payload);
+
+  // both sides derive the same secret
+  const alice = await crypto.subtle.generateKey({ name: "X25519" }, false, ["deriveBits"]);
+  const bob = await crypto.subtle.generateKey({ name: "X25519" }, false, ["deriveBits"]);
+  const aliceBits = new Uint8Array(await crypto.subtle.deriveBits({ name: "X25519", public: bob.publicKey }, alice.privateKey, 256));
+  const bobBits = new Uint8Array(await crypto.subtle.deriveBits({ name: "X25519", public: alice.publicKey }, bob.privateKey, 256));
+  const agreed = aliceBits.every((byte, i) => byte === bobBits[i]);
+
+  const publicKey = new Uint8Array(await crypto.subtle.exportKey("raw", signing.publicKey));
+  const exportPrivate = await errorName(() => crypto.subtle.exportKey("pkcs8", signing.privateKey));
+
+  return new Response([verified, agreed, publicKey.byteLength, exportPrivate].join(","));
+});
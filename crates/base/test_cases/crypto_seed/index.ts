@@ -0,0 +1,4 @@
+Deno.serve(() => {
+  const bytes = crypto.getRandomValues(new Uint8Array(8));
+  return new Response(`${bytes.join(",")} ${crypto.randomUUID()}`);
+});
@@ -0,0 +1,5 @@
+export default {
+  fetch(req: Request) {
+    return new Response(`hello from ${new URL(req.url).pathname}`);
+  },
+};
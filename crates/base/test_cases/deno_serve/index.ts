@@ -0,0 +1,3 @@
+Deno.serve((req: Request) => {
+  return new Response(`hello from ${new URL(req.url).pathname}`);
+});
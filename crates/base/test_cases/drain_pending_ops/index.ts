@@ -0,0 +1,2 @@
+// nothing awaits the timer, the module evaluates before it fires
+setTimeout(() => console.log("timer fired"), 300);
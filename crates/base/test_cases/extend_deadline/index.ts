@@ -0,0 +1,5 @@
+// asks for 1000ms more than the 1000ms the worker is given
+// @ts-ignore
+EdgeRuntime.extendDeadline(1000);
+// @ts-ignore
+await new Promise(r => setTimeout(r, 1500));
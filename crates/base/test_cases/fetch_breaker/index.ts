@@ -0,0 +1,23 @@
+// calls the upstream server a few times, and reports how each call went
+const listener = Deno.listen({ port: 9999 });
+
+for await (const conn of listener) {
+  const httpConn = Deno.serveHttp(conn);
+  for await (const { request, respondWith } of httpConn) {
+    const { searchParams } = new URL(request.url);
+    const upstream = searchParams.get("upstream")!;
+    const calls = Number(searchParams.get("calls"));
+
+    const results = [];
+    for (let i = 0; i < calls; i++) {
+      try {
+        const res = await fetch(upstream);
+        await res.body?.cancel();
+        results.push({ status: res.status });
+      } catch (err) {
+        results.push({ error: err.name, message: err.message });
+      }
+    }
+    respondWith(Response.json(results));
+  }
+}
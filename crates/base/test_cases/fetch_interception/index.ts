@@ -0,0 +1,12 @@
+const listener = Deno.listen({ port: 9999 });
+
+for await (const conn of listener) {
+  const httpConn = Deno.serveHttp(conn);
+  for await (const { respondWith } of httpConn) {
+    const res = await fetch("https://api.example.com/users", {
+      method: "POST",
+      body: "ping",
+    });
+    respondWith(new Response(await res.text(), { status: res.status }));
+  }
+}
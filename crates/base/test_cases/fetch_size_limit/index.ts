@@ -0,0 +1,15 @@
+const listener = Deno.listen({ port: 9999 });
+
+for await (const conn of listener) {
+  const httpConn = Deno.serveHttp(conn);
+  for await (const { request, respondWith } of httpConn) {
+    const { pathname } = new URL(request.url);
+    const res = await fetch(`https://api.example.com${pathname}`);
+    try {
+      const body = await res.text();
+      respondWith(new Response(body));
+    } catch (err) {
+      respondWith(new Response(err.name, { status: 500 }));
+    }
+  }
+}
@@ -0,0 +1,11 @@
+import config from "./config.json" with { type: "json" };
+import name from "./name.txt" with { type: "text" };
+
+const listener = Deno.listen({ port: 9999 });
+
+for await (const conn of listener) {
+  const httpConn = Deno.serveHttp(conn);
+  for await (const { respondWith } of httpConn) {
+    respondWith(new Response(`${config.greeting} ${name.trim()}`));
+  }
+}
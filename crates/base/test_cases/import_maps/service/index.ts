@@ -0,0 +1,11 @@
+import { greet } from "shared/greet.ts";
+import { local } from "local";
+
+const listener = Deno.listen({ port: 9999 });
+
+for await (const conn of listener) {
+  const httpConn = Deno.serveHttp(conn);
+  for await (const { respondWith } of httpConn) {
+    respondWith(new Response(`${greet("world")} ${local}`));
+  }
+}
@@ -0,0 +1,10 @@
+Deno.serve(async (req) => {
+  const jwksUrl = new URL(req.url).searchParams.get("jwks")!;
+  const token = req.headers.get("authorization")!.replace("Bearer ", "");
+  try {
+    const { header, payload } = await EdgeRuntime.jwt.verify(token, { jwksUrl });
+    return Response.json({ kid: header.kid, sub: payload.sub });
+  } catch (err) {
+    return Response.json({ error: err.name });
+  }
+});
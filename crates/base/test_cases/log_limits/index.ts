@@ -0,0 +1,12 @@
+Deno.serve((req) => {
+  const { pathname } = new URL(req.url);
+  if (pathname === "/burst") {
+    console.debug("below the level");
+    for (let i = 0; i < 10; i++) {
+      console.log(`line ${i}`);
+    }
+  } else {
+    console.log("after");
+  }
+  return new Response("ok");
+});
@@ -0,0 +1,26 @@
+const listener = Deno.listen({ port: 9999 });
+
+for await (const conn of listener) {
+  const httpConn = Deno.serveHttp(conn);
+  for await (const { request, respondWith } of httpConn) {
+    const maxPartBytes = new URL(request.url).searchParams.get("maxPartBytes");
+    const parts = [];
+    try {
+      for await (
+        const part of EdgeRuntime.multipart(request, {
+          maxPartBytes: maxPartBytes ? Number(maxPartBytes) : undefined,
+        })
+      ) {
+        if (part.filename === null) {
+          parts.push({ name: part.name, value: await part.text() });
+        } else {
+          // skipped, without being read
+          parts.push({ name: part.name, filename: part.filename, type: part.contentType });
+        }
+      }
+      respondWith(Response.json(parts));
+    } catch (err) {
+      respondWith(new Response(err.name, { status: 413 }));
+    }
+  }
+}
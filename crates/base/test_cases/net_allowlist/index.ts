@@ -0,0 +1,18 @@
+async function errorName(fn: () => Promise<unknown> | unknown) {
+  try {
+    await fn();
+    return "none";
+  } catch (err) {
+    return err.name;
+  }
+}
+
+Deno.serve(async () => {
+  const errors = [
+    // only port 6379 of the internal hosts is allowed
+    await errorName(() => Deno.connect({ hostname: "redis.internal", port: 6380 })),
+    // UDP sockets weren't allowed
+    await errorName(() => Deno.listenDatagram({ hostname: "127.0.0.1", port: 0, transport: "udp" })),
+  ];
+  return new Response(errors.join(","));
+});
@@ -0,0 +1,2 @@
+// never answers, the requests to it time out
+Deno.serve(() => new Promise<Response>(() => {}));
@@ -0,0 +1,17 @@
+async function errorName(fn: () => Promise<unknown> | unknown) {
+  try {
+    await fn();
+    return "none";
+  } catch (err) {
+    return err.name;
+  }
+}
+
+Deno.serve(async () => {
+  const errors = [
+    // nothing listens on port 1
+    await errorName(() => Deno.connect({ hostname: "127.0.0.1", port: 1 })),
+    await errorName(() => Deno.resolveDns("localhost", "A")),
+  ];
+  return new Response(errors.join(","));
+});
@@ -0,0 +1,4 @@
+import { greet } from "https://example.invalid/greet.ts";
+import { colors } from "https://example.invalid/colors.ts";
+
+Deno.serve(() => new Response(colors.bold(greet("world"))));
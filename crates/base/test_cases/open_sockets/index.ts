@@ -0,0 +1,35 @@
+// opens connections to the upstream server until it can't, and reports how
+// each attempt went
+const listener = Deno.listen({ port: 9999 });
+
+for await (const conn of listener) {
+  const httpConn = Deno.serveHttp(conn);
+  for await (const { request, respondWith } of httpConn) {
+    const { searchParams } = new URL(request.url);
+    const port = Number(searchParams.get("port"));
+    const attempts = Number(searchParams.get("attempts"));
+
+    const conns = [];
+    const results = [];
+    for (let i = 0; i < attempts; i++) {
+      try {
+        conns.push(await Deno.connect({ hostname: "127.0.0.1", port }));
+        results.push({ ok: true });
+      } catch (err) {
+        results.push({ error: err.name });
+      }
+    }
+
+    // a closed connection makes room for another
+    conns.pop()?.close();
+    try {
+      conns.push(await Deno.connect({ hostname: "127.0.0.1", port }));
+      results.push({ ok: true });
+    } catch (err) {
+      results.push({ error: err.name });
+    }
+
+    // left open, as a leaky function would
+    respondWith(Response.json(results));
+  }
+}
@@ -0,0 +1,4 @@
+Deno.serve(async (req) => {
+  const key = new URL(req.url).searchParams.get("key")!;
+  return Response.json(await EdgeRuntime.rateLimit(key, 2, 60_000));
+});
@@ -0,0 +1 @@
+Deno.serve((req) => Response.json([...req.headers]));
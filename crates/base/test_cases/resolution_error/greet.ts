@@ -0,0 +1,3 @@
+import { bold } from "sdt/fmt/colors.ts";
+
+export const greet = (name: string) => bold(`hello ${name}`);
@@ -0,0 +1,10 @@
+// fails to boot unless the worker is told what it runs on
+// @ts-ignore
+const info = EdgeRuntime.runtimeInfo();
+if (!info.version || !info.v8Version || !info.extensions.includes("deno_fetch")) {
+    throw new Error(`unexpected runtime info: ${JSON.stringify(info)}`);
+}
+// user workers count the bytes they send and receive
+if (!info.features.includes("countNetUsage")) {
+    throw new Error(`unexpected features: ${info.features}`);
+}
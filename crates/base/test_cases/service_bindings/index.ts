@@ -0,0 +1,13 @@
+// calls the service bound to the first segment of the path with a request of
+// its own, none of the headers it got are passed on
+Deno.serve(async (req) => {
+  const name = new URL(req.url).pathname.split("/")[1];
+  try {
+    return await EdgeRuntime.services.get(name).fetch(req.url, {
+      method: req.method,
+    });
+  } catch (e) {
+    const depth = req.headers.get("x-edge-runtime-binding-depth") ?? "0";
+    return new Response(`${e.name} at depth ${depth}`, { status: 500 });
+  }
+});
@@ -0,0 +1,5 @@
+// takes a while to evaluate, then leaves the event loop free
+const start = Date.now();
+while (Date.now() - start < 600) {
+}
+await new Promise((resolve) => setTimeout(resolve, 500));
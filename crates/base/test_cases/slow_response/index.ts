@@ -0,0 +1,5 @@
+Deno.serve(() =>
+  new Promise<Response>((resolve) =>
+    setTimeout(() => resolve(new Response("ok")), 200)
+  )
+);
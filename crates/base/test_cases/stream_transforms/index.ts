@@ -0,0 +1,23 @@
+// decodes what the upstream server sends, gzipped, as the response
+const listener = Deno.listen({ port: 9999 });
+
+for await (const conn of listener) {
+  const httpConn = Deno.serveHttp(conn);
+  for await (const { request, respondWith } of httpConn) {
+    const { searchParams } = new URL(request.url);
+    const upstream = await fetch(searchParams.get("upstream")!);
+    const text = upstream.body!
+      .pipeThrough(new DecompressionStream("gzip"))
+      .pipeThrough(new TextDecoderStream());
+
+    let body = "";
+    for await (const chunk of text) {
+      body += chunk;
+    }
+    // and compresses it again, in a form the tester can check
+    const compressed = new Response(body).body!
+      .pipeThrough(new CompressionStream("deflate"))
+      .pipeThrough(new DecompressionStream("deflate"));
+    respondWith(new Response(compressed));
+  }
+}
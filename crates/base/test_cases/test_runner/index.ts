@@ -0,0 +1,4 @@
+export async function greet(): Promise<string> {
+  const res = await fetch("https://api.example.com/greeting");
+  return `${Deno.env.get("GREETING")} ${await res.text()}`;
+}
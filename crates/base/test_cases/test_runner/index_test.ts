@@ -0,0 +1,20 @@
+import { greet } from "./index.ts";
+
+Deno.test("reads injected env", () => {
+  if (Deno.env.get("GREETING") !== "hello") {
+    throw new Error("env var was not injected");
+  }
+});
+
+Deno.test("uses mocked fetch", async () => {
+  const greeting = await greet();
+  if (greeting !== "hello mocked") {
+    throw new Error(`unexpected greeting: ${greeting}`);
+  }
+});
+
+Deno.test("fails", () => {
+  throw new Error("expected failure");
+});
+
+Deno.test({ name: "is ignored", ignore: true, fn: () => {} });
@@ -0,0 +1,9 @@
+const listener = Deno.listen({ port: 9999 });
+
+for await (const conn of listener) {
+  const httpConn = Deno.serveHttp(conn);
+  for await (const { request, respondWith } of httpConn) {
+    console.log(`handling ${new URL(request.url).pathname}`);
+    respondWith(new Response("hello", { status: 200 }));
+  }
+}
@@ -0,0 +1,11 @@
+const listener = Deno.listen({ port: 9999 });
+
+for await (const conn of listener) {
+  const httpConn = Deno.serveHttp(conn);
+  for await (const { respondWith } of httpConn) {
+    // nothing handles it, while the request is in flight
+    Promise.reject(new Error("boom"));
+    await new Promise((resolve) => setTimeout(resolve, 50));
+    respondWith(new Response("ok"));
+  }
+}
@@ -0,0 +1,2 @@
+// @ts-ignore
+Promise.reject(new Error("unhandled"));
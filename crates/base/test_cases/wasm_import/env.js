@@ -0,0 +1 @@
+export const offset = (x) => x + 100;
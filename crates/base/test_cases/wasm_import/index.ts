@@ -0,0 +1,10 @@
+import { add } from "./add.wasm";
+
+const listener = Deno.listen({ port: 9999 });
+
+for await (const conn of listener) {
+  const httpConn = Deno.serveHttp(conn);
+  for await (const { respondWith } of httpConn) {
+    respondWith(new Response(String(add(1, 2))));
+  }
+}
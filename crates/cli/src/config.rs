@@ -0,0 +1,453 @@
+use anyhow::{bail, Context, Error};
+use clap::{ArgAction, Command};
+use serde_json::Value;
+use std::collections::BTreeMap;
+use std::ffi::OsStr;
+use std::path::Path;
+
+// env vars overriding the keys of the config file, eg: `server.port` is
+// overridden by EDGE_RUNTIME_SERVER_PORT
+const ENV_PREFIX: &str = "EDGE_RUNTIME_";
+
+// A key of the config file and the flag of `start` and `serve` it sets. A
+// negated key turns the flag on when it's false, eg: `server.keep_alive`.
+struct ConfigKey {
+    key: &'static str,
+    arg: &'static str,
+    negated: bool,
+}
+
+const fn key(key: &'static str, arg: &'static str) -> ConfigKey {
+    ConfigKey {
+        key,
+        arg,
+        negated: false,
+    }
+}
+
+const fn negated(key: &'static str, arg: &'static str) -> ConfigKey {
+    ConfigKey {
+        key,
+        arg,
+        negated: true,
+    }
+}
+
+const CONFIG_KEYS: &[ConfigKey] = &[
+    key("server.ip", "ip"),
+    key("server.port", "port"),
+    key("server.main_service", "main-service"),
+    key("server.functions_dir", "dir"),
+    negated("server.keep_alive", "no-keep-alive"),
+    key("server.keep_alive_timeout_ms", "keep-alive-timeout-ms"),
+    key("server.keep_alive_max_requests", "keep-alive-max-requests"),
+    key("server.trusted_proxies", "trusted-proxy"),
+    key("server.expose_version", "expose-version"),
+    key("server.expose_metrics", "expose-metrics"),
+    key("server.geoip_dbs", "geoip-db"),
+    key("server.request_hooks", "request-hook"),
+    key("server.waf_rules", "waf-rules"),
+    key("maintenance.enabled", "maintenance"),
+    key("maintenance.disabled_services", "disable-service"),
+    key("maintenance.status", "maintenance-status"),
+    key("maintenance.body", "maintenance-body"),
+    key("maintenance.retry_after_secs", "maintenance-retry-after"),
+    key("server.log_level", "log-level"),
+    key("logs.dir", "log-dir"),
+    key("logs.max_size_mb", "log-max-size-mb"),
+    key("logs.rotate", "log-rotate"),
+    key("logs.keep", "log-keep"),
+    key("logs.sinks", "log-sink"),
+    key("pool.worker_threads", "worker-threads"),
+    key("pool.max_concurrent_boots", "max-concurrent-boots"),
+    key("pool.memory_budget_mb", "memory-budget-mb"),
+    key("pool.rate_limit", "rate-limit"),
+    key("pool.rate_limit_burst", "rate-limit-burst"),
+    key("pool.rate_limit_by", "rate-limit-by"),
+    negated("pool.snapshot", "no-snapshot"),
+    key("limits.strict", "strict"),
+    key("limits.timezone", "timezone"),
+    key("limits.mail_rate_limit", "mail-rate-limit"),
+    key("limits.fetch_breaker_threshold", "fetch-breaker-threshold"),
+    key(
+        "limits.fetch_breaker_cooldown_ms",
+        "fetch-breaker-cooldown-ms",
+    ),
+    key("cache.dir", "cache-dir"),
+    negated("cache.module_cache", "disable-module-cache"),
+    negated("cache.code_cache", "disable-code-cache"),
+    key("cache.offline", "offline"),
+    key("cache.import_map", "import-map"),
+    key("cache.warmup", "warmup"),
+    key("cache.trusted_keys", "trusted-key"),
+    key("cache.auth_tokens", "auth-token"),
+    key("outbound.user_agent", "user-agent"),
+    key("outbound.headers", "outbound-header"),
+    key("services.smtp_url", "smtp-url"),
+    key("services.mail_from", "mail-from"),
+    key("services.ai_backend", "ai-backend"),
+    key("services.jwt_keys", "jwt-key"),
+    key("services.storage_endpoint", "storage-endpoint"),
+    key("services.storage_region", "storage-region"),
+    key("services.storage_bucket", "storage-bucket"),
+    key("services.storage_path_style", "storage-path-style"),
+    key("services.queue_backend", "queue-backend"),
+    key("services.rate_limit_store", "rate-limit-store"),
+    key("services.cache_size_mb", "cache-size-mb"),
+    key("services.cache_max_ttl", "cache-max-ttl"),
+    key("metrics.exporter", "metrics-exporter"),
+    key("metrics.prefix", "metrics-prefix"),
+    key("metrics.tags", "metrics-tag"),
+    key("billing.enabled", "billing"),
+    key("billing.sink", "billing-sink"),
+];
+
+fn env_var(key: &str) -> String {
+    format!("{}{}", ENV_PREFIX, key.replace('.', "_").to_uppercase())
+}
+
+// the values of a key of the config file, a list for the repeatable flags
+fn config_values(key: &str, value: &Value) -> Result<Vec<String>, Error> {
+    match value {
+        Value::String(s) => Ok(vec![s.clone()]),
+        Value::Number(n) => Ok(vec![n.to_string()]),
+        Value::Bool(b) => Ok(vec![b.to_string()]),
+        Value::Array(items) => items
+            .iter()
+            .map(|item| match item {
+                Value::Array(_) | Value::Object(_) | Value::Null => {
+                    bail!("`{}` must be a list of strings or numbers", key)
+                }
+                item => Ok(config_values(key, item)?.remove(0)),
+            })
+            .collect(),
+        Value::Null => Ok(vec![]),
+        Value::Object(_) => bail!("`{}` must be a value, not a table", key),
+    }
+}
+
+// the keys of the file, with their path, eg: `server.port`
+fn flatten(prefix: &str, value: &Value, keys: &mut BTreeMap<String, Value>) {
+    match value {
+        Value::Object(map) => {
+            for (name, value) in map {
+                let key = match prefix {
+                    "" => name.clone(),
+                    _ => format!("{}.{}", prefix, name),
+                };
+                flatten(&key, value, keys);
+            }
+        }
+        value => {
+            keys.insert(prefix.to_string(), value.clone());
+        }
+    }
+}
+
+// where a value comes from, for errors
+enum Origin {
+    File,
+    Env(String),
+}
+
+// The settings of the server read from a TOML or YAML file, eg:
+//
+//     [server]
+//     port = 8080
+//     [pool]
+//     memory_budget_mb = 4096
+//
+// and from the EDGE_RUNTIME_* env vars, which override it. Both are used as
+// the defaults of the command line flags, which override them.
+pub struct RuntimeConfig {
+    // set keys, by their position in CONFIG_KEYS
+    values: BTreeMap<usize, (Vec<String>, Origin)>,
+    path: Option<String>,
+}
+
+impl RuntimeConfig {
+    pub fn load(path: Option<&Path>) -> Result<Self, Error> {
+        Self::load_with_env(path, |name| std::env::var(name).ok())
+    }
+
+    fn load_with_env(
+        path: Option<&Path>,
+        env: impl Fn(&str) -> Option<String>,
+    ) -> Result<Self, Error> {
+        let mut values = BTreeMap::new();
+
+        if let Some(path) = path {
+            let text = std::fs::read_to_string(path)
+                .with_context(|| format!("failed to read the config file {}", path.display()))?;
+            let parsed: Value = match path.extension().and_then(OsStr::to_str) {
+                Some("yaml") | Some("yml") => serde_yaml::from_str(&text)
+                    .with_context(|| format!("invalid YAML in {}", path.display()))?,
+                _ => toml::from_str(&text)
+                    .with_context(|| format!("invalid TOML in {}", path.display()))?,
+            };
+
+            let mut keys = BTreeMap::new();
+            flatten("", &parsed, &mut keys);
+            for (name, value) in keys {
+                let Some(index) = CONFIG_KEYS.iter().position(|k| k.key == name) else {
+                    bail!("unknown key `{}` in {}", name, path.display());
+                };
+                let values_of = config_values(&name, &value)
+                    .with_context(|| format!("invalid config file {}", path.display()))?;
+                values.insert(index, (values_of, Origin::File));
+            }
+        }
+
+        for (index, config_key) in CONFIG_KEYS.iter().enumerate() {
+            let name = env_var(config_key.key);
+            if let Some(value) = env(&name) {
+                // split in `apply`, once it's known whether the flag takes a list
+                values.insert(index, (vec![value], Origin::Env(name)));
+            }
+        }
+
+        Ok(Self {
+            values,
+            path: path.map(|path| path.display().to_string()),
+        })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.values.is_empty()
+    }
+
+    // Sets the configured values as the defaults of the flags of `subcommand`.
+    // They're checked as the flags are, the errors name the key they're from.
+    // Keys of the flags `subcommand` doesn't have are left out.
+    pub fn apply(&self, cli: Command, subcommand: &str) -> Result<Command, Error> {
+        let Some(cmd) = cli.find_subcommand(subcommand).cloned() else {
+            return Ok(cli);
+        };
+
+        let mut defaults = vec![];
+        for (index, (values, origin)) in &self.values {
+            let config_key = &CONFIG_KEYS[*index];
+            let Some(arg) = cmd
+                .get_arguments()
+                .find(|arg| arg.get_id() == config_key.arg)
+            else {
+                continue;
+            };
+            let from = match origin {
+                Origin::File => format!("in {}", self.path.as_deref().unwrap_or_default()),
+                Origin::Env(name) => format!("from {}", name),
+            };
+
+            let repeatable = matches!(arg.get_action(), ArgAction::Append);
+            let mut values = match origin {
+                // lists are comma separated
+                Origin::Env(_) if repeatable => {
+                    values[0].split(',').map(|v| v.trim().to_string()).collect()
+                }
+                _ => values.clone(),
+            };
+            if config_key.negated {
+                values = match values.as_slice() {
+                    [value] if value == "true" => vec!["false".to_string()],
+                    [value] if value == "false" => vec!["true".to_string()],
+                    _ => bail!("`{}` {} must be true or false", config_key.key, from),
+                };
+            }
+            if values.len() > 1 && !repeatable {
+                bail!("`{}` {} takes a single value", config_key.key, from);
+            }
+            for value in &values {
+                if let Err(err) =
+                    arg.get_value_parser()
+                        .parse_ref(&cmd, Some(arg), OsStr::new(value))
+                {
+                    let err = err.to_string();
+                    let reason = err.lines().next().unwrap_or_default();
+                    bail!(
+                        "invalid `{}` {}: {}",
+                        config_key.key,
+                        from,
+                        reason.trim_start_matches("error: ")
+                    );
+                }
+            }
+            defaults.push((config_key.arg, values));
+        }
+
+        Ok(cli.mut_subcommand(subcommand, |mut cmd| {
+            for (arg, values) in defaults {
+                cmd = cmd.mut_arg(arg, |arg| arg.default_values(values));
+            }
+            cmd
+        }))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use clap::{arg, value_parser, ArgMatches};
+    use std::collections::HashMap;
+    use std::path::PathBuf;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    static CONFIG_FILES: AtomicUsize = AtomicUsize::new(0);
+
+    fn cli() -> Command {
+        Command::new("edge-runtime").subcommand(
+            Command::new("start")
+                .arg(arg!(--port <PORT>).value_parser(value_parser!(u16)))
+                .arg(arg!(--"main-service" <DIR>))
+                .arg(arg!(--"trusted-proxy" <CIDR>).action(ArgAction::Append))
+                .arg(arg!(--"no-keep-alive").action(ArgAction::SetTrue)),
+        )
+    }
+
+    // writes `text` to a config file named `name`, in a directory of its own
+    fn config_file(name: &str, text: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!(
+            "edge-runtime-config-{}-{}",
+            std::process::id(),
+            CONFIG_FILES.fetch_add(1, Ordering::Relaxed)
+        ));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join(name);
+        std::fs::write(&path, text).unwrap();
+        path
+    }
+
+    fn load(path: Option<&Path>, env: &[(&str, &str)]) -> Result<RuntimeConfig, Error> {
+        let env: HashMap<String, String> = env
+            .iter()
+            .map(|(name, value)| (name.to_string(), value.to_string()))
+            .collect();
+        RuntimeConfig::load_with_env(path, |name| env.get(name).cloned())
+    }
+
+    fn start_matches(config: &RuntimeConfig, args: &[&str]) -> ArgMatches {
+        let matches = config
+            .apply(cli(), "start")
+            .unwrap()
+            .try_get_matches_from(["edge-runtime", "start"].iter().chain(args))
+            .unwrap();
+        matches.subcommand_matches("start").unwrap().clone()
+    }
+
+    fn proxies(matches: &ArgMatches) -> Vec<String> {
+        matches
+            .get_many::<String>("trusted-proxy")
+            .unwrap()
+            .cloned()
+            .collect()
+    }
+
+    #[test]
+    fn test_load_toml_and_yaml() {
+        let path = config_file(
+            "config.toml",
+            "[server]\nport = 8080\ntrusted_proxies = [\"10.0.0.0/8\", \"172.16.0.0/12\"]\nkeep_alive = false\n",
+        );
+        let config = load(Some(&path), &[]).unwrap();
+        let matches = start_matches(&config, &[]);
+        assert_eq!(matches.get_one::<u16>("port"), Some(&8080));
+        assert_eq!(proxies(&matches), vec!["10.0.0.0/8", "172.16.0.0/12"]);
+        assert!(matches.get_flag("no-keep-alive"));
+
+        let path = config_file("config.yaml", "server:\n  port: 9000\n");
+        let config = load(Some(&path), &[]).unwrap();
+        let matches = start_matches(&config, &[]);
+        assert_eq!(matches.get_one::<u16>("port"), Some(&9000));
+    }
+
+    #[test]
+    fn test_precedence() {
+        let path = config_file("config.toml", "[server]\nport = 8080\n");
+        let config = load(Some(&path), &[("EDGE_RUNTIME_SERVER_PORT", "8081")]).unwrap();
+        let matches = start_matches(&config, &[]);
+        assert_eq!(matches.get_one::<u16>("port"), Some(&8081));
+
+        // the flags have the last word
+        let matches = start_matches(&config, &["--port", "8082"]);
+        assert_eq!(matches.get_one::<u16>("port"), Some(&8082));
+    }
+
+    #[test]
+    fn test_env_lists() {
+        // only the values of repeatable flags are split
+        let config = load(
+            None,
+            &[
+                (
+                    "EDGE_RUNTIME_SERVER_TRUSTED_PROXIES",
+                    "10.0.0.0/8, 127.0.0.1",
+                ),
+                ("EDGE_RUNTIME_SERVER_MAIN_SERVICE", "./main,v2"),
+            ],
+        )
+        .unwrap();
+        let matches = start_matches(&config, &[]);
+        assert_eq!(proxies(&matches), vec!["10.0.0.0/8", "127.0.0.1"]);
+        assert_eq!(
+            matches.get_one::<String>("main-service").unwrap(),
+            "./main,v2"
+        );
+    }
+
+    #[test]
+    fn test_unknown_key() {
+        let path = config_file("config.toml", "[server]\nprot = 8080\n");
+        let err = load(Some(&path), &[]).err().unwrap().to_string();
+        assert!(err.contains("unknown key `server.prot`"), "{}", err);
+    }
+
+    #[test]
+    fn test_type_errors() {
+        let path = config_file("config.toml", "[server]\nport = \"eighty\"\n");
+        let config = load(Some(&path), &[]).unwrap();
+        let err = config.apply(cli(), "start").err().unwrap().to_string();
+        assert!(err.starts_with("invalid `server.port` in "), "{}", err);
+
+        let path = config_file("config.toml", "[server]\nport = 80\nkeep_alive = \"no\"\n");
+        let config = load(Some(&path), &[]).unwrap();
+        let err = config.apply(cli(), "start").err().unwrap().to_string();
+        assert!(err.contains("`server.keep_alive`"), "{}", err);
+        assert!(err.contains("must be true or false"), "{}", err);
+
+        let path = config_file("config.toml", "[server]\nport = [80, 81]\n");
+        let config = load(Some(&path), &[]).unwrap();
+        let err = config.apply(cli(), "start").err().unwrap().to_string();
+        assert!(err.contains("takes a single value"), "{}", err);
+
+        let path = config_file(
+            "config.toml",
+            "[server]\ntrusted_proxies = [[\"10.0.0.0/8\"]]\n",
+        );
+        let err = format!("{:#}", load(Some(&path), &[]).err().unwrap());
+        assert!(
+            err.contains("`server.trusted_proxies` must be a list of strings or numbers"),
+            "{}",
+            err
+        );
+    }
+
+    #[test]
+    fn test_env_errors() {
+        let config = load(None, &[("EDGE_RUNTIME_SERVER_PORT", "eighty")]).unwrap();
+        let err = config.apply(cli(), "start").err().unwrap().to_string();
+        assert!(
+            err.starts_with("invalid `server.port` from EDGE_RUNTIME_SERVER_PORT: "),
+            "{}",
+            err
+        );
+    }
+
+    #[test]
+    fn test_applied_again() {
+        // like on every reload, the command is built and configured again
+        let config = load(None, &[("EDGE_RUNTIME_SERVER_PORT", "8080")]).unwrap();
+        for _ in 0..3 {
+            let matches = start_matches(&config, &[]);
+            assert_eq!(matches.get_one::<u16>("port"), Some(&8080));
+        }
+    }
+}
@@ -41,15 +41,15 @@ impl log::Log for CliLogger {
     }
 }
 
-pub fn init(verbose: bool) {
-    let log_level = if verbose {
-        log::Level::Debug
+pub fn init(level: log::LevelFilter) {
+    // without RUST_LOG, only the max level filters the records, so it can be
+    // changed while the server runs
+    let cli_logger = CliLogger::new(log::Level::Trace);
+    let max_level = if std::env::var_os("RUST_LOG").is_some() {
+        cli_logger.filter()
     } else {
-        log::Level::Info
+        level
     };
-
-    let cli_logger = CliLogger::new(log_level);
-    let max_level = cli_logger.filter();
     let r = log::set_boxed_logger(Box::new(cli_logger));
     if r.is_ok() {
         log::set_max_level(max_level);
@@ -5,12 +5,26 @@ use log::debug;
 use log::error;
 use std::fmt;
 
-#[derive(Debug, Clone, PartialEq, Eq)]
+#[derive(Clone, PartialEq, Eq)]
 pub enum AuthTokenData {
     Bearer(String),
     Basic { username: String, password: String },
 }
 
+// Secrets are left out, so tokens can't end up in logs.
+impl fmt::Debug for AuthTokenData {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            AuthTokenData::Bearer(_) => write!(f, "Bearer(<redacted>)"),
+            AuthTokenData::Basic { username, .. } => f
+                .debug_struct("Basic")
+                .field("username", username)
+                .field("password", &"<redacted>")
+                .finish(),
+        }
+    }
+}
+
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct AuthToken {
     host: String,
@@ -72,6 +86,13 @@ impl AuthTokens {
         Self(tokens)
     }
 
+    /// Adds tokens that take precedence over the existing ones.
+    pub fn prepend(&mut self, tokens: AuthTokens) {
+        let mut tokens = tokens.0;
+        tokens.append(&mut self.0);
+        self.0 = tokens;
+    }
+
     /// Attempt to match the provided specifier to the tokens in the set.  The
     /// matching occurs from the right of the hostname plus port, irrespective of
     /// scheme.  For example `https://www.deno.land:8080/` would match a token
@@ -119,6 +140,27 @@ mod tests {
         assert_eq!(auth_tokens.get(&fixture), None);
     }
 
+    #[test]
+    fn test_auth_tokens_redacted() {
+        let auth_tokens =
+            AuthTokens::new(Some("abc123@deno.land;user:pass@example.com".to_string()));
+        let debug = format!("{auth_tokens:?}");
+        assert!(!debug.contains("abc123"));
+        assert!(!debug.contains("pass"));
+        assert!(debug.contains("example.com"));
+    }
+
+    #[test]
+    fn test_auth_tokens_prepend() {
+        let mut auth_tokens = AuthTokens::new(Some("abc123@deno.land".to_string()));
+        auth_tokens.prepend(AuthTokens::new(Some("def456@deno.land".to_string())));
+        let fixture = resolve_url("https://deno.land/x/mod.ts").unwrap();
+        assert_eq!(
+            auth_tokens.get(&fixture).unwrap().to_string(),
+            "Bearer def456"
+        );
+    }
+
     #[test]
     fn test_auth_tokens_multiple() {
         let auth_tokens = AuthTokens::new(Some("abc123@deno.land;def456@example.com".to_string()));
@@ -20,6 +20,8 @@ impl DenoDir {
     pub fn new(maybe_custom_root: Option<PathBuf>) -> std::io::Result<Self> {
         let root: PathBuf = if let Some(root) = maybe_custom_root {
             root
+        } else if let Some(root) = env::var_os("DENO_DIR") {
+            PathBuf::from(root)
         } else if let Some(cache_dir) = dirs::cache_dir() {
             // We use the OS cache dir because all files deno writes are cache files
             // Once that changes we need to start using different roots if DENO_DIR
@@ -93,6 +95,11 @@ impl DenoDir {
         self.root.join("deps")
     }
 
+    /// Path to the V8 code caches of the modules workers load.
+    pub fn code_cache_folder_path(&self) -> PathBuf {
+        self.root.join("v8_code_cache")
+    }
+
     /// Path to the origin data cache folder.
     pub fn origin_data_folder_path(&self) -> PathBuf {
         // TODO(@crowlKats): change to origin_data for 2.0
@@ -127,6 +134,11 @@ impl DenoDir {
     pub fn dl_folder_path(&self) -> PathBuf {
         self.root.join("dl")
     }
+
+    /// Folder used for the copies of remote services.
+    pub fn services_folder_path(&self) -> PathBuf {
+        self.root.join("services")
+    }
 }
 
 /// To avoid the poorly managed dirs crate
@@ -194,6 +194,12 @@ impl FileFetcher {
         }
     }
 
+    /// Adds tokens used to authenticate requests for remote modules, they take
+    /// precedence over the ones set with `DENO_AUTH_TOKENS`.
+    pub fn add_auth_tokens(&mut self, tokens: AuthTokens) {
+        self.auth_tokens.prepend(tokens);
+    }
+
     /// Sets the log level to use when outputting the download message.
     pub fn set_download_log_level(&mut self, level: log::Level) {
         self.download_log_level = level;
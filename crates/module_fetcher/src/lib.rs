@@ -1,5 +1,5 @@
 pub mod args;
-mod auth_tokens;
+pub mod auth_tokens;
 pub mod cache;
 pub mod emit;
 pub mod file_fetcher;
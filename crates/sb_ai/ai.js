@@ -0,0 +1,31 @@
+import { registerEdgeRuntimeApi } from "ext:sb_core_edge_runtime_apis/js/edge_runtime_apis.js";
+
+const core = globalThis.Deno.core;
+
+function inputs(input) {
+  return Array.isArray(input) ? input : [input];
+}
+
+// embeddings of a string (or of each string of an array), eg:
+// `await EdgeRuntime.ai.embed("gte-small", "hello")`
+async function embed(model, input) {
+  if (typeof model !== "string") {
+    throw new TypeError("A model name is required.");
+  }
+  const embeddings = await core.opAsync("op_ai_embed", model, inputs(input));
+  return Array.isArray(input) ? embeddings : embeddings[0];
+}
+
+// runs a model on a JSON input, what it takes and returns depends on the model
+function run(model, input) {
+  if (typeof model !== "string") {
+    throw new TypeError("A model name is required.");
+  }
+  return core.opAsync("op_ai_run", model, input ?? null);
+}
+
+const SUPABASE_AI = { embed, run };
+
+registerEdgeRuntimeApi("ai", SUPABASE_AI);
+
+export { SUPABASE_AI };
@@ -0,0 +1,55 @@
+use anyhow::{bail, Error};
+use async_trait::async_trait;
+use deno_core::serde_json::Value;
+use once_cell::sync::{Lazy, OnceCell};
+use std::fmt::Debug;
+use std::future::Future;
+use std::sync::Arc;
+
+static AI_BACKEND: OnceCell<Arc<dyn InferenceBackend>> = OnceCell::new();
+
+// Isolates run on runtimes of their own and stop with them, inference is
+// driven by this one so backends can keep clients and sessions across workers.
+static AI_RUNTIME: Lazy<tokio::runtime::Runtime> = Lazy::new(|| {
+    tokio::runtime::Builder::new_multi_thread()
+        .worker_threads(1)
+        .thread_name("sb-ai")
+        .enable_all()
+        .build()
+        .unwrap()
+});
+
+// Where `EdgeRuntime.ai` runs models, eg: a local onnxruntime or an inference
+// server.
+#[async_trait]
+pub trait InferenceBackend: Send + Sync + Debug {
+    // one embedding for each input
+    async fn embed(&self, model: &str, input: Vec<String>) -> Result<Vec<Vec<f32>>, Error>;
+
+    async fn run(&self, model: &str, input: Value) -> Result<Value, Error>;
+}
+
+// Enables `EdgeRuntime.ai`. Must be called before the first worker is created,
+// its functions throw otherwise.
+pub fn init_ai_backend(backend: Arc<dyn InferenceBackend>) -> Result<(), Error> {
+    if AI_BACKEND.set(backend).is_err() {
+        bail!("the inference backend is already configured");
+    }
+    Ok(())
+}
+
+pub(crate) fn ai_backend() -> Option<Arc<dyn InferenceBackend>> {
+    AI_BACKEND.get().cloned()
+}
+
+pub fn ai_backend_configured() -> bool {
+    AI_BACKEND.get().is_some()
+}
+
+pub(crate) async fn spawn<F, T>(fut: F) -> Result<T, Error>
+where
+    F: Future<Output = Result<T, Error>> + Send + 'static,
+    T: Send + 'static,
+{
+    AI_RUNTIME.spawn(fut).await?
+}
@@ -0,0 +1,82 @@
+use crate::backend::InferenceBackend;
+use anyhow::{bail, Error};
+use async_trait::async_trait;
+use deno_core::serde_json::{json, Value};
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
+
+// Forwards inference to a server:
+// `POST <url>/embed {model, input}` answers `{embeddings}` and
+// `POST <url>/run {model, input}` answers `{output}`.
+#[derive(Debug)]
+pub struct HttpBackend {
+    url: String,
+    api_key: Option<String>,
+    client: reqwest::Client,
+}
+
+#[derive(Deserialize)]
+struct EmbedResponse {
+    embeddings: Vec<Vec<f32>>,
+}
+
+#[derive(Deserialize)]
+struct RunResponse {
+    output: Value,
+}
+
+impl HttpBackend {
+    pub fn new(url: &str, api_key: Option<String>) -> Self {
+        Self {
+            url: url.trim_end_matches('/').to_string(),
+            api_key,
+            client: reqwest::Client::new(),
+        }
+    }
+
+    async fn post<T: DeserializeOwned>(&self, path: &str, body: Value) -> Result<T, Error> {
+        let mut req = self
+            .client
+            .post(format!("{}/{}", self.url, path))
+            .json(&body);
+        if let Some(api_key) = &self.api_key {
+            req = req.bearer_auth(api_key);
+        }
+
+        let res = req.send().await?;
+        let status = res.status();
+        if !status.is_success() {
+            bail!(
+                "inference server answered {}: {}",
+                status,
+                res.text().await.unwrap_or_default()
+            );
+        }
+        Ok(res.json().await?)
+    }
+}
+
+#[async_trait]
+impl InferenceBackend for HttpBackend {
+    async fn embed(&self, model: &str, input: Vec<String>) -> Result<Vec<Vec<f32>>, Error> {
+        let expected = input.len();
+        let res: EmbedResponse = self
+            .post("embed", json!({ "model": model, "input": input }))
+            .await?;
+        if res.embeddings.len() != expected {
+            bail!(
+                "inference server returned {} embeddings for {} inputs",
+                res.embeddings.len(),
+                expected
+            );
+        }
+        Ok(res.embeddings)
+    }
+
+    async fn run(&self, model: &str, input: Value) -> Result<Value, Error> {
+        let res: RunResponse = self
+            .post("run", json!({ "model": model, "input": input }))
+            .await?;
+        Ok(res.output)
+    }
+}
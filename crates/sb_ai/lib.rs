@@ -0,0 +1,95 @@
+pub mod backend;
+pub mod http;
+#[cfg(feature = "onnx")]
+pub mod onnx;
+
+use backend::{ai_backend, spawn, InferenceBackend};
+use deno_core::error::{custom_error, AnyError};
+use deno_core::op;
+use deno_core::serde_json::Value;
+use deno_core::OpState;
+use std::cell::RefCell;
+use std::rc::Rc;
+use std::sync::Arc;
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+
+deno_core::extension!(sb_ai, ops = [op_ai_embed, op_ai_run], esm = ["ai.js"]);
+
+// Inference quota of a worker, `EdgeRuntime.ai` throws without it.
+pub struct AiWorkerState {
+    // calls the worker can make in its lifetime, any if unset
+    max_calls: Option<u64>,
+    calls: u64,
+    // calls the worker can have running at once
+    concurrency: Arc<Semaphore>,
+}
+
+impl AiWorkerState {
+    pub fn new(max_calls: Option<u64>, max_concurrent: usize) -> Self {
+        Self {
+            max_calls,
+            calls: 0,
+            concurrency: Arc::new(Semaphore::new(max_concurrent.max(1))),
+        }
+    }
+
+    // the main worker isn't limited
+    pub fn unlimited() -> Self {
+        Self::new(None, Semaphore::MAX_PERMITS)
+    }
+}
+
+// takes one of the worker's calls, and waits for one of its concurrent slots
+async fn acquire(
+    state: &Rc<RefCell<OpState>>,
+) -> Result<(Arc<dyn InferenceBackend>, OwnedSemaphorePermit), AnyError> {
+    let Some(backend) = ai_backend() else {
+        return Err(custom_error(
+            "NotSupported",
+            "inference isn't configured on this server",
+        ));
+    };
+    let concurrency = {
+        let mut state = state.borrow_mut();
+        let Some(ai) = state.try_borrow_mut::<AiWorkerState>() else {
+            return Err(custom_error(
+                "PermissionDenied",
+                "inference is not enabled for this worker, create it with the `ai` option",
+            ));
+        };
+        if ai.max_calls.map_or(false, |max| ai.calls >= max) {
+            return Err(custom_error(
+                "DOMExceptionQuotaExceededError",
+                format!(
+                    "the worker made the {} inference calls it's allowed",
+                    ai.calls
+                ),
+            ));
+        }
+        ai.calls += 1;
+        ai.concurrency.clone()
+    };
+
+    let permit = concurrency.acquire_owned().await?;
+    Ok((backend, permit))
+}
+
+#[op]
+async fn op_ai_embed(
+    state: Rc<RefCell<OpState>>,
+    model: String,
+    input: Vec<String>,
+) -> Result<Vec<Vec<f32>>, AnyError> {
+    let (backend, _permit) = acquire(&state).await?;
+    spawn(async move { backend.embed(&model, input).await }).await
+}
+
+#[op]
+async fn op_ai_run(
+    state: Rc<RefCell<OpState>>,
+    model: String,
+    input: Value,
+) -> Result<Value, AnyError> {
+    let (backend, _permit) = acquire(&state).await?;
+    spawn(async move { backend.run(&model, input).await }).await
+}
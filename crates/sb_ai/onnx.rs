@@ -0,0 +1,183 @@
+use crate::backend::InferenceBackend;
+use anyhow::{anyhow, bail, Context, Error};
+use async_trait::async_trait;
+use deno_core::serde_json::{json, Map, Value};
+use log::debug;
+use ndarray::{Array2, ArrayD, Axis, IxDyn};
+use ort::{GraphOptimizationLevel, Session};
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use tokenizers::{PaddingParams, Tokenizer};
+
+// Runs models with onnxruntime, from `<dir>/<model>/model.onnx`. Models used
+// with `embed` also need their `tokenizer.json` next to it. The library is
+// loaded when the backend is created, from `ORT_DYLIB_PATH` or the default
+// search path.
+#[derive(Debug)]
+pub struct OnnxBackend {
+    dir: PathBuf,
+    // by model name, loaded on first use
+    models: Mutex<HashMap<String, Arc<OnnxModel>>>,
+}
+
+#[derive(Debug)]
+struct OnnxModel {
+    session: Session,
+    tokenizer: Option<Tokenizer>,
+}
+
+impl OnnxBackend {
+    pub fn new(dir: PathBuf) -> Result<Self, Error> {
+        if !dir.is_dir() {
+            bail!("model directory {} doesn't exist", dir.display());
+        }
+        ort::init().with_name("sb-ai").commit()?;
+
+        Ok(Self {
+            dir,
+            models: Mutex::new(HashMap::new()),
+        })
+    }
+
+    fn model(&self, name: &str) -> Result<Arc<OnnxModel>, Error> {
+        // models can't be loaded from outside the directory
+        if name.is_empty() || name.contains(['/', '\\']) || name.starts_with('.') {
+            bail!("invalid model name {:?}", name);
+        }
+        if let Some(model) = self.models.lock().unwrap().get(name) {
+            return Ok(model.clone());
+        }
+
+        let dir = self.dir.join(name);
+        let session = Session::builder()?
+            .with_optimization_level(GraphOptimizationLevel::Level3)?
+            .with_model_from_file(dir.join("model.onnx"))
+            .with_context(|| format!("failed to load model {}", name))?;
+        let tokenizer_path = dir.join("tokenizer.json");
+        let tokenizer = if tokenizer_path.exists() {
+            let mut tokenizer = Tokenizer::from_file(tokenizer_path).map_err(|err| anyhow!(err))?;
+            // inputs of a batch are padded to the longest one
+            tokenizer.with_padding(Some(PaddingParams::default()));
+            Some(tokenizer)
+        } else {
+            None
+        };
+        debug!("loaded model {}", name);
+
+        let model = Arc::new(OnnxModel { session, tokenizer });
+        self.models
+            .lock()
+            .unwrap()
+            .insert(name.to_string(), model.clone());
+        Ok(model)
+    }
+}
+
+impl OnnxModel {
+    // mean of the token embeddings, normalized
+    fn embed(&self, input: Vec<String>) -> Result<Vec<Vec<f32>>, Error> {
+        let Some(tokenizer) = &self.tokenizer else {
+            bail!("the model has no tokenizer.json, it can't be used to embed text");
+        };
+        let encodings = tokenizer
+            .encode_batch(input, true)
+            .map_err(|err| anyhow!(err))?;
+        let batch = encodings.len();
+        let len = encodings.first().map(|e| e.len()).unwrap_or(0);
+
+        let tensor = |values: Vec<i64>| -> Result<ort::Value, Error> {
+            let array = Array2::from_shape_vec((batch, len), values)?;
+            Ok(ort::Value::from_array(array)?)
+        };
+        let collect = |get: fn(&tokenizers::Encoding) -> &[u32]| -> Vec<i64> {
+            encodings
+                .iter()
+                .flat_map(|e| get(e).iter().map(|v| *v as i64))
+                .collect()
+        };
+        let input_ids = tensor(collect(|e| e.get_ids()))?;
+        let attention_mask = tensor(collect(|e| e.get_attention_mask()))?;
+        let token_type_ids = tensor(collect(|e| e.get_type_ids()))?;
+
+        let mut inputs = vec![input_ids, attention_mask];
+        // not every model takes them
+        if self.session.inputs.len() > 2 {
+            inputs.push(token_type_ids);
+        }
+        let outputs = self.session.run(inputs.as_slice())?;
+        // [batch, tokens, dims]
+        let hidden = outputs[0].extract_tensor::<f32>()?;
+        let hidden = hidden.view();
+
+        let mut embeddings = Vec::with_capacity(batch);
+        for (idx, tokens) in hidden.axis_iter(Axis(0)).enumerate() {
+            let mask = encodings[idx].get_attention_mask();
+            let mut sum = vec![0f32; tokens.shape()[1]];
+            let mut count = 0f32;
+            for (token, weight) in tokens.axis_iter(Axis(0)).zip(mask) {
+                if *weight == 0 {
+                    continue;
+                }
+                count += 1.0;
+                for (acc, value) in sum.iter_mut().zip(token.iter()) {
+                    *acc += value;
+                }
+            }
+            let mean: Vec<f32> = sum.iter().map(|v| v / count.max(1.0)).collect();
+            let norm = mean.iter().map(|v| v * v).sum::<f32>().sqrt().max(1e-12);
+            embeddings.push(mean.iter().map(|v| v / norm).collect());
+        }
+        Ok(embeddings)
+    }
+
+    // `input` and the output are objects of tensors by name, as
+    // `{ shape: number[], data: number[] }` of f32
+    fn run(&self, input: Value) -> Result<Value, Error> {
+        let Value::Object(tensors) = input else {
+            bail!("the input of an onnx model must be an object of tensors by name");
+        };
+
+        let mut inputs = Vec::with_capacity(self.session.inputs.len());
+        for expected in &self.session.inputs {
+            let Some(tensor) = tensors.get(&expected.name) else {
+                bail!("missing the model input {:?}", expected.name);
+            };
+            let shape: Vec<usize> = deno_core::serde_json::from_value(tensor["shape"].clone())
+                .with_context(|| format!("invalid shape of input {:?}", expected.name))?;
+            let data: Vec<f32> = deno_core::serde_json::from_value(tensor["data"].clone())
+                .with_context(|| format!("invalid data of input {:?}", expected.name))?;
+            let array = ArrayD::from_shape_vec(IxDyn(&shape), data)?;
+            inputs.push(ort::Value::from_array(array)?);
+        }
+        let outputs = self.session.run(inputs.as_slice())?;
+
+        let mut result = Map::new();
+        for output in &self.session.outputs {
+            let tensor = outputs[output.name.as_str()].extract_tensor::<f32>()?;
+            let view = tensor.view();
+            result.insert(
+                output.name.clone(),
+                json!({
+                    "shape": view.shape(),
+                    "data": view.iter().copied().collect::<Vec<f32>>(),
+                }),
+            );
+        }
+        Ok(Value::Object(result))
+    }
+}
+
+#[async_trait]
+impl InferenceBackend for OnnxBackend {
+    async fn embed(&self, model: &str, input: Vec<String>) -> Result<Vec<Vec<f32>>, Error> {
+        // loading a model reads it from disk, once
+        let model = tokio::task::block_in_place(|| self.model(model))?;
+        tokio::task::spawn_blocking(move || model.embed(input)).await?
+    }
+
+    async fn run(&self, model: &str, input: Value) -> Result<Value, Error> {
+        let model = tokio::task::block_in_place(|| self.model(model))?;
+        tokio::task::spawn_blocking(move || model.run(input)).await?
+    }
+}
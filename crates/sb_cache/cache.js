@@ -0,0 +1,56 @@
+import { registerEdgeRuntimeApi } from "ext:sb_core_edge_runtime_apis/js/edge_runtime_apis.js";
+
+const core = globalThis.Deno.core;
+
+function checkKey(key) {
+  if (typeof key !== "string") {
+    throw new TypeError("A cache key must be a string.");
+  }
+}
+
+// the value set for `key` by any worker of the service, undefined if it's
+// missing or expired
+function get(key) {
+  checkKey(key);
+  const value = core.ops.op_cache_get(key);
+  return value === null ? undefined : JSON.parse(value);
+}
+
+// keeps a JSON serializable `value` until `ttl` ms have passed (at most the
+// server's --cache-max-ttl, its default), or it's the least recently used
+// entry once the service's cache is full
+function set(key, value, options = {}) {
+  checkKey(key);
+  const json = JSON.stringify(value);
+  if (json === undefined) {
+    throw new TypeError("A cached value must be JSON serializable.");
+  }
+  const ttl = options.ttl ?? null;
+  if (ttl !== null && (!Number.isInteger(ttl) || ttl <= 0)) {
+    throw new TypeError("The ttl must be a positive integer.");
+  }
+  core.ops.op_cache_set(key, json, ttl);
+}
+
+// whether there was an entry for `key`
+function del(key) {
+  checkKey(key);
+  return core.ops.op_cache_delete(key);
+}
+
+// the cached value of `key`, or the one `fn` resolves with, which is cached
+async function getOrSet(key, fn, options = {}) {
+  const cached = get(key);
+  if (cached !== undefined) {
+    return cached;
+  }
+  const value = await fn();
+  set(key, value, options);
+  return value;
+}
+
+const SUPABASE_CACHE = { get, set, delete: del, getOrSet };
+
+registerEdgeRuntimeApi("cache", SUPABASE_CACHE);
+
+export { SUPABASE_CACHE };
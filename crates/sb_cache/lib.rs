@@ -0,0 +1,84 @@
+pub mod store;
+
+use deno_core::error::{type_error, AnyError};
+use deno_core::op;
+use deno_core::OpState;
+use std::time::{Duration, Instant};
+use store::{cache_opts, service_cache};
+
+const MAX_KEY_LEN: usize = 1024;
+
+deno_core::extension!(
+    sb_cache,
+    ops = [op_cache_get, op_cache_set, op_cache_delete],
+    esm = ["cache.js"]
+);
+
+// The service whose entries a worker uses, services can't see each other's.
+pub struct CacheWorkerState {
+    service: String,
+}
+
+impl CacheWorkerState {
+    pub fn new(service: String) -> Self {
+        Self { service }
+    }
+}
+
+fn service(state: &OpState) -> String {
+    state
+        .try_borrow::<CacheWorkerState>()
+        .map(|state| state.service.clone())
+        .unwrap_or_default()
+}
+
+fn validate_key(key: &str) -> Result<(), AnyError> {
+    if key.is_empty() || key.len() > MAX_KEY_LEN {
+        return Err(type_error(format!(
+            "a cache key must have 1 to {} bytes",
+            MAX_KEY_LEN
+        )));
+    }
+    Ok(())
+}
+
+// the entry as JSON, `None` if it's missing or expired
+#[op]
+fn op_cache_get(state: &mut OpState, key: String) -> Result<Option<String>, AnyError> {
+    validate_key(&key)?;
+    let cache = service_cache(&service(state));
+    let value = cache.lock().unwrap().get(&key, Instant::now());
+    Ok(value)
+}
+
+#[op]
+fn op_cache_set(
+    state: &mut OpState,
+    key: String,
+    value: String,
+    ttl_ms: Option<u64>,
+) -> Result<(), AnyError> {
+    validate_key(&key)?;
+    let max_ttl = cache_opts().max_ttl;
+    let ttl = match ttl_ms {
+        Some(0) => return Err(type_error("the ttl must be at least 1ms")),
+        Some(ms) => Duration::from_millis(ms).min(max_ttl),
+        None => max_ttl,
+    };
+
+    let cache = service_cache(&service(state));
+    cache
+        .lock()
+        .unwrap()
+        .set(&key, value, ttl, Instant::now())
+        .map_err(|err| type_error(err.to_string()))?;
+    Ok(())
+}
+
+#[op]
+fn op_cache_delete(state: &mut OpState, key: String) -> Result<bool, AnyError> {
+    validate_key(&key)?;
+    let cache = service_cache(&service(state));
+    let deleted = cache.lock().unwrap().delete(&key);
+    Ok(deleted)
+}
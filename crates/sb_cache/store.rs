@@ -0,0 +1,221 @@
+use anyhow::{bail, Error};
+use once_cell::sync::{Lazy, OnceCell};
+use std::collections::{BTreeMap, HashMap};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+static CACHE_OPTS: OnceCell<CacheOpts> = OnceCell::new();
+
+// by service, shared by its workers and kept when they're recycled
+static SERVICE_CACHES: Lazy<Mutex<HashMap<String, Arc<Mutex<ServiceCache>>>>> =
+    Lazy::new(Default::default);
+
+#[derive(Debug, Clone)]
+pub struct CacheOpts {
+    // the size of the keys and values of a service's entries, the least
+    // recently used ones are evicted past it
+    pub max_bytes: usize,
+    // entries expire after at most this long, and after it without a ttl
+    pub max_ttl: Duration,
+}
+
+impl Default for CacheOpts {
+    fn default() -> Self {
+        Self {
+            max_bytes: 16 * 1024 * 1024,
+            max_ttl: Duration::from_secs(60 * 60),
+        }
+    }
+}
+
+// Must be called before the first worker is created, the defaults are used
+// otherwise.
+pub fn init_cache(opts: CacheOpts) -> Result<(), Error> {
+    if opts.max_bytes == 0 || opts.max_ttl.is_zero() {
+        bail!("the cache size and ttl must be greater than 0");
+    }
+    if CACHE_OPTS.set(opts).is_err() {
+        bail!("the cache is already configured");
+    }
+    Ok(())
+}
+
+pub(crate) fn cache_opts() -> &'static CacheOpts {
+    CACHE_OPTS.get_or_init(CacheOpts::default)
+}
+
+pub(crate) fn service_cache(service: &str) -> Arc<Mutex<ServiceCache>> {
+    SERVICE_CACHES
+        .lock()
+        .unwrap()
+        .entry(service.to_string())
+        .or_insert_with(|| Arc::new(Mutex::new(ServiceCache::new(cache_opts().max_bytes))))
+        .clone()
+}
+
+#[derive(Debug)]
+struct Entry {
+    value: String,
+    expires_at: Instant,
+    // its key in `recency`
+    used: u64,
+}
+
+// The entries of a service, evicted by least recent use once they take more
+// than `max_bytes`.
+#[derive(Debug)]
+pub struct ServiceCache {
+    max_bytes: usize,
+    size: usize,
+    entries: HashMap<String, Entry>,
+    // the keys of the entries, by when they were last used
+    recency: BTreeMap<u64, String>,
+    uses: u64,
+}
+
+impl ServiceCache {
+    pub fn new(max_bytes: usize) -> Self {
+        Self {
+            max_bytes,
+            size: 0,
+            entries: HashMap::new(),
+            recency: BTreeMap::new(),
+            uses: 0,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    // the size of the entries, in bytes
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    fn next_use(&mut self) -> u64 {
+        self.uses += 1;
+        self.uses
+    }
+
+    pub fn get(&mut self, key: &str, now: Instant) -> Option<String> {
+        let entry = self.entries.get(key)?;
+        if entry.expires_at <= now {
+            self.delete(key);
+            return None;
+        }
+
+        let used = self.next_use();
+        let entry = self.entries.get_mut(key)?;
+        let key = self.recency.remove(&entry.used)?;
+        entry.used = used;
+        self.recency.insert(used, key);
+        Some(entry.value.clone())
+    }
+
+    // fails without changing the cache if the entry alone is over its size
+    pub fn set(
+        &mut self,
+        key: &str,
+        value: String,
+        ttl: Duration,
+        now: Instant,
+    ) -> Result<(), Error> {
+        let size = key.len() + value.len();
+        if size > self.max_bytes {
+            bail!(
+                "the entry takes {} bytes, more than the {} of the cache",
+                size,
+                self.max_bytes
+            );
+        }
+
+        self.delete(key);
+        if self.size + size > self.max_bytes {
+            self.evict_expired(now);
+        }
+        while self.size + size > self.max_bytes {
+            let Some((_, oldest)) = self.recency.pop_first() else {
+                break;
+            };
+            if let Some(entry) = self.entries.remove(&oldest) {
+                self.size -= oldest.len() + entry.value.len();
+            }
+        }
+
+        let used = self.next_use();
+        self.recency.insert(used, key.to_string());
+        self.entries.insert(
+            key.to_string(),
+            Entry {
+                value,
+                expires_at: now + ttl,
+                used,
+            },
+        );
+        self.size += size;
+        Ok(())
+    }
+
+    pub fn delete(&mut self, key: &str) -> bool {
+        let Some(entry) = self.entries.remove(key) else {
+            return false;
+        };
+        self.recency.remove(&entry.used);
+        self.size -= key.len() + entry.value.len();
+        true
+    }
+
+    fn evict_expired(&mut self, now: Instant) {
+        let expired: Vec<String> = self
+            .entries
+            .iter()
+            .filter(|(_, entry)| entry.expires_at <= now)
+            .map(|(key, _)| key.clone())
+            .collect();
+        for key in expired {
+            self.delete(&key);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_service_cache() {
+        let now = Instant::now();
+        let ttl = Duration::from_secs(60);
+        // room for 3 entries of 1 byte keys and 3 byte values
+        let mut cache = ServiceCache::new(12);
+
+        for key in ["a", "b", "c"] {
+            cache.set(key, "\"x\"".to_string(), ttl, now).unwrap();
+        }
+        assert_eq!(cache.size(), 12);
+
+        // "b" is now the least recently used, and evicted for "d"
+        assert!(cache.get("a", now).is_some());
+        cache.set("d", "\"x\"".to_string(), ttl, now).unwrap();
+        assert!(cache.get("b", now).is_none());
+        assert_eq!(cache.len(), 3);
+
+        // and "c" for "e", which expires before "a"
+        cache
+            .set("e", "\"x\"".to_string(), Duration::from_millis(1), now)
+            .unwrap();
+        let later = now + Duration::from_millis(10);
+        assert!(cache.get("e", later).is_none());
+        assert!(cache.get("a", later).is_some());
+
+        assert!(cache.set("f", "x".repeat(12), ttl, now).is_err());
+        assert!(cache.delete("a"));
+        assert!(!cache.delete("a"));
+        assert_eq!(cache.size(), 4);
+    }
+}
@@ -0,0 +1,106 @@
+use crate::http_start::HttpBufPool;
+use deno_core::op;
+use deno_core::v8;
+use deno_core::OpState;
+use sb_worker_context::billing::{InvocationUsages, IsolateUsage};
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Duration;
+
+// the cpu time of the calling thread, each isolate runs on a thread of its own
+#[cfg(unix)]
+fn thread_cpu_time() -> Duration {
+    let mut ts = libc::timespec {
+        tv_sec: 0,
+        tv_nsec: 0,
+    };
+    // SAFETY: `ts` is a valid timespec to write to
+    if unsafe { libc::clock_gettime(libc::CLOCK_THREAD_CPUTIME_ID, &mut ts) } != 0 {
+        return Duration::ZERO;
+    }
+    Duration::new(ts.tv_sec as u64, ts.tv_nsec as u32)
+}
+
+#[cfg(not(unix))]
+fn thread_cpu_time() -> Duration {
+    Duration::ZERO
+}
+
+// array buffers included
+fn heap_used(scope: &mut v8::HandleScope) -> u64 {
+    let mut stats = v8::HeapStatistics::default();
+    scope.get_heap_statistics(&mut stats);
+    (stats.used_heap_size() + stats.external_memory()) as u64
+}
+
+// Meters what the isolate spends on each request it handles, and reports it
+// to the worker's `InvocationUsages` once the request is answered. The cpu
+// time spent between two samples is split evenly between the requests in
+// flight, the heap is sampled as requests start and are answered.
+#[derive(Debug)]
+pub struct InvocationMeter {
+    usages: Arc<InvocationUsages>,
+    // by the slots of the requests in `HttpBufPool`
+    in_flight: HashMap<u32, IsolateUsage>,
+    // cpu time of the isolate's thread at the last sample
+    sampled_cpu: Duration,
+}
+
+impl InvocationMeter {
+    pub fn new(usages: Arc<InvocationUsages>) -> Self {
+        Self {
+            usages,
+            in_flight: HashMap::new(),
+            sampled_cpu: thread_cpu_time(),
+        }
+    }
+
+    fn sample(&mut self, scope: &mut v8::HandleScope) {
+        let now = thread_cpu_time();
+        let spent = now.saturating_sub(self.sampled_cpu);
+        self.sampled_cpu = now;
+        if self.in_flight.is_empty() {
+            return;
+        }
+
+        let heap = heap_used(scope);
+        let cpu_ms = spent.as_secs_f64() * 1000.0 / self.in_flight.len() as f64;
+        for usage in self.in_flight.values_mut() {
+            usage.cpu_ms += cpu_ms;
+            usage.peak_heap_bytes = usage.peak_heap_bytes.max(heap);
+        }
+    }
+}
+
+#[op(v8)]
+fn op_meter_request_started(scope: &mut v8::HandleScope, state: &mut OpState, slot: u32) {
+    if let Some(meter) = state.try_borrow_mut::<InvocationMeter>() {
+        meter.sample(scope);
+        let usage = IsolateUsage {
+            cpu_ms: 0.0,
+            peak_heap_bytes: heap_used(scope),
+        };
+        meter.in_flight.insert(slot, usage);
+    }
+}
+
+#[op(v8)]
+fn op_meter_request_responded(scope: &mut v8::HandleScope, state: &mut OpState, slot: u32) {
+    let Some(usage) = state.try_borrow_mut::<InvocationMeter>().and_then(|meter| {
+        meter.sample(scope);
+        meter.in_flight.remove(&slot)
+    }) else {
+        return;
+    };
+    // the usage outlives the slot, it's read by the pool
+    let request_id = state.borrow::<HttpBufPool>().request_id(slot).to_string();
+    state
+        .borrow_mut::<InvocationMeter>()
+        .usages
+        .insert(request_id, usage);
+}
+
+deno_core::extension!(
+    sb_core_billing,
+    ops = [op_meter_request_started, op_meter_request_responded]
+);
@@ -0,0 +1,123 @@
+use async_trait::async_trait;
+use deno_core::error::{range_error, AnyError};
+use deno_core::op;
+use deno_core::{OpState, ZeroCopyBuf};
+use deno_web::{BlobPart, BlobStore, InMemoryBlobPart};
+use sb_worker_context::essentials::BlobSpillOpts;
+use std::fs;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use tokio::sync::OnceCell;
+use uuid::Uuid;
+
+// Where the large blobs of an isolate go, rather than its memory (eg: the
+// files of a multipart upload). Removed along with the isolate.
+#[derive(Debug)]
+pub struct BlobSpillState {
+    dir: PathBuf,
+    threshold_bytes: usize,
+    max_bytes: u64,
+    // bytes on disk, given back as blobs are collected
+    used: Arc<AtomicU64>,
+}
+
+impl BlobSpillState {
+    pub fn new(opts: &BlobSpillOpts) -> Self {
+        Self {
+            dir: std::env::temp_dir()
+                .join("edge-runtime")
+                .join("blobs")
+                .join(Uuid::new_v4().to_string()),
+            threshold_bytes: opts.threshold_bytes,
+            max_bytes: opts.max_bytes,
+            used: Arc::default(),
+        }
+    }
+
+    fn spill(&self, data: &[u8]) -> Result<SpilledBlobPart, AnyError> {
+        let size = data.len() as u64;
+        let reserved = self
+            .used
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |used| {
+                (used + size <= self.max_bytes).then_some(used + size)
+            });
+        if reserved.is_err() {
+            return Err(range_error(format!(
+                "blobs can't take more than {} bytes of disk",
+                self.max_bytes
+            )));
+        }
+
+        let path = self.dir.join(Uuid::new_v4().to_string());
+        let written = fs::create_dir_all(&self.dir).and_then(|_| fs::write(&path, data));
+        if let Err(err) = written {
+            self.used.fetch_sub(size, Ordering::SeqCst);
+            return Err(err.into());
+        }
+
+        Ok(SpilledBlobPart {
+            path,
+            size: data.len(),
+            data: OnceCell::new(),
+            used: self.used.clone(),
+        })
+    }
+}
+
+impl Drop for BlobSpillState {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.dir);
+    }
+}
+
+#[derive(Debug)]
+struct SpilledBlobPart {
+    path: PathBuf,
+    size: usize,
+    // read back when the blob is read (eg: streamed to storage), not while
+    // it's only held
+    data: OnceCell<Vec<u8>>,
+    used: Arc<AtomicU64>,
+}
+
+#[async_trait]
+impl BlobPart for SpilledBlobPart {
+    async fn read(&self) -> Result<&[u8], AnyError> {
+        let data = self
+            .data
+            .get_or_try_init(|| tokio::fs::read(&self.path))
+            .await?;
+        Ok(data)
+    }
+
+    fn size(&self) -> usize {
+        self.size
+    }
+}
+
+impl Drop for SpilledBlobPart {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+        self.used.fetch_sub(self.size as u64, Ordering::SeqCst);
+    }
+}
+
+// `op_blob_create_part` of deno_web, spilling the parts past the threshold
+// when the isolate has a `BlobSpillState`
+#[op]
+fn op_blob_create_part_spilled(state: &mut OpState, data: ZeroCopyBuf) -> Result<Uuid, AnyError> {
+    let part: Arc<dyn BlobPart + Send + Sync> = match state.try_borrow::<BlobSpillState>() {
+        Some(spill) if data.len() >= spill.threshold_bytes => Arc::new(spill.spill(&data)?),
+        _ => Arc::new(InMemoryBlobPart::from(data.to_vec())),
+    };
+    Ok(state.borrow::<BlobStore>().insert_part(part))
+}
+
+deno_core::extension!(
+    sb_core_blob,
+    middleware = |op| match op.name {
+        "op_blob_create_part" => op_blob_create_part_spilled::decl(),
+        _ => op,
+    }
+);
@@ -0,0 +1,380 @@
+use deno_core::error::{type_error, AnyError};
+use deno_core::op;
+use deno_core::{
+    AsyncResult, BufView, CancelHandle, CancelTryFuture, OpState, RcRef, Resource, ResourceId,
+    ZeroCopyBuf,
+};
+use flate2::write::{
+    DeflateDecoder, DeflateEncoder, GzDecoder, GzEncoder, ZlibDecoder, ZlibEncoder,
+};
+use flate2::Compression;
+use std::borrow::Cow;
+use std::cell::RefCell;
+use std::io::Write;
+use std::rc::Rc;
+
+// buffer size and window of the brotli encoder, quality is traded for speed
+// as responses are usually compressed on the fly
+const BUFFER_SIZE: usize = 4096;
+const QUALITY: u32 = 5;
+const LG_WINDOW_SIZE: u32 = 22;
+
+// what a transformed resource reads from its source at once
+const READ_SIZE: usize = 64 * 1024;
+
+// collects what the encoder or decoder outputs, taken after each write
+#[derive(Default, Clone)]
+struct Output(Rc<RefCell<Vec<u8>>>);
+
+impl Output {
+    fn take(&self) -> ZeroCopyBuf {
+        std::mem::take(&mut *self.0.borrow_mut()).into()
+    }
+
+    fn take_up_to(&self, limit: usize) -> Vec<u8> {
+        let mut output = self.0.borrow_mut();
+        let len = limit.min(output.len());
+        output.drain(..len).collect()
+    }
+}
+
+impl Write for Output {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0.borrow_mut().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+// The formats of `CompressionStream` and `DecompressionStream`.
+enum Codec {
+    GzipEncoder(GzEncoder<Output>),
+    GzipDecoder(GzDecoder<Output>),
+    DeflateEncoder(ZlibEncoder<Output>),
+    DeflateDecoder(ZlibDecoder<Output>),
+    DeflateRawEncoder(DeflateEncoder<Output>),
+    DeflateRawDecoder(DeflateDecoder<Output>),
+    BrotliEncoder(brotli::CompressorWriter<Output>),
+    BrotliDecoder(brotli::DecompressorWriter<Output>),
+}
+
+impl Codec {
+    fn new(format: &str, is_decoder: bool, output: Output) -> Result<Self, AnyError> {
+        let level = Compression::default();
+        Ok(match (format, is_decoder) {
+            ("gzip", false) => Codec::GzipEncoder(GzEncoder::new(output, level)),
+            ("gzip", true) => Codec::GzipDecoder(GzDecoder::new(output)),
+            ("deflate", false) => Codec::DeflateEncoder(ZlibEncoder::new(output, level)),
+            ("deflate", true) => Codec::DeflateDecoder(ZlibDecoder::new(output)),
+            ("deflate-raw", false) => Codec::DeflateRawEncoder(DeflateEncoder::new(output, level)),
+            ("deflate-raw", true) => Codec::DeflateRawDecoder(DeflateDecoder::new(output)),
+            ("br", false) => Codec::BrotliEncoder(brotli::CompressorWriter::new(
+                output,
+                BUFFER_SIZE,
+                QUALITY,
+                LG_WINDOW_SIZE,
+            )),
+            ("br", true) => {
+                Codec::BrotliDecoder(brotli::DecompressorWriter::new(output, BUFFER_SIZE))
+            }
+            _ => {
+                return Err(type_error(format!(
+                    "unsupported compression format {}",
+                    format
+                )))
+            }
+        })
+    }
+
+    fn write(&mut self, input: &[u8]) -> Result<(), AnyError> {
+        let res = match self {
+            Codec::GzipEncoder(codec) => codec.write_all(input),
+            Codec::GzipDecoder(codec) => codec.write_all(input),
+            Codec::DeflateEncoder(codec) => codec.write_all(input),
+            Codec::DeflateDecoder(codec) => codec.write_all(input),
+            Codec::DeflateRawEncoder(codec) => codec.write_all(input),
+            Codec::DeflateRawDecoder(codec) => codec.write_all(input),
+            Codec::BrotliEncoder(codec) => codec.write_all(input),
+            Codec::BrotliDecoder(codec) => codec.write_all(input),
+        };
+        res.map_err(|err| type_error(format!("corrupt compressed stream: {}", err)))
+    }
+
+    // flushes the end of the stream
+    fn finish(self) -> Result<(), AnyError> {
+        let res = match self {
+            Codec::GzipEncoder(codec) => codec.finish().map(drop),
+            Codec::GzipDecoder(codec) => codec.finish().map(drop),
+            Codec::DeflateEncoder(codec) => codec.finish().map(drop),
+            Codec::DeflateDecoder(codec) => codec.finish().map(drop),
+            Codec::DeflateRawEncoder(codec) => codec.finish().map(drop),
+            Codec::DeflateRawDecoder(codec) => codec.finish().map(drop),
+            Codec::BrotliEncoder(codec) => {
+                drop(codec.into_inner());
+                Ok(())
+            }
+            Codec::BrotliDecoder(codec) => codec.into_inner().map(drop).map_err(|_| {
+                std::io::Error::new(std::io::ErrorKind::UnexpectedEof, "stream ended early")
+            }),
+        };
+        res.map_err(|err| type_error(format!("corrupt compressed stream: {}", err)))
+    }
+}
+
+// The "br" format of `CompressionStream` and `DecompressionStream`, the others
+// are handled by deno_web.
+struct BrotliResource {
+    codec: RefCell<Option<Codec>>,
+    output: Output,
+}
+
+impl Resource for BrotliResource {
+    fn name(&self) -> Cow<str> {
+        "brotli".into()
+    }
+}
+
+#[op]
+fn op_brotli_new(state: &mut OpState, is_decoder: bool) -> Result<ResourceId, AnyError> {
+    let output = Output::default();
+    let codec = Codec::new("br", is_decoder, output.clone())?;
+    Ok(state.resource_table.add(BrotliResource {
+        codec: RefCell::new(Some(codec)),
+        output,
+    }))
+}
+
+#[op]
+fn op_brotli_write(
+    state: &mut OpState,
+    rid: ResourceId,
+    input: &[u8],
+) -> Result<ZeroCopyBuf, AnyError> {
+    let resource = state.resource_table.get::<BrotliResource>(rid)?;
+    let mut codec = resource.codec.borrow_mut();
+    codec
+        .as_mut()
+        .ok_or_else(|| type_error("the stream is already finished"))?
+        .write(input)?;
+    Ok(resource.output.take())
+}
+
+#[op]
+fn op_brotli_finish(state: &mut OpState, rid: ResourceId) -> Result<ZeroCopyBuf, AnyError> {
+    let resource = state.resource_table.take::<BrotliResource>(rid)?;
+    let codec = resource.codec.borrow_mut().take();
+    codec
+        .ok_or_else(|| type_error("the stream is already finished"))?
+        .finish()?;
+    Ok(resource.output.take())
+}
+
+// Another resource (eg: a fetch response's body) read through a codec, so
+// piping it through a compression stream doesn't go through JS for each
+// chunk. Being a resource itself, responding with it stays on this side too.
+struct TransformedResource {
+    source: Rc<dyn Resource>,
+    // `None` once the source is done
+    codec: RefCell<Option<Codec>>,
+    output: Output,
+    cancel: CancelHandle,
+}
+
+impl Resource for TransformedResource {
+    fn name(&self) -> Cow<str> {
+        "transformedStream".into()
+    }
+
+    fn read(self: Rc<Self>, limit: usize) -> AsyncResult<BufView> {
+        Box::pin(async move {
+            loop {
+                let pending = self.output.take_up_to(limit);
+                if !pending.is_empty() {
+                    return Ok(pending.into());
+                }
+                if self.codec.borrow().is_none() {
+                    return Ok(BufView::empty());
+                }
+
+                let cancel = RcRef::map(&self, |r| &r.cancel);
+                let chunk = self
+                    .source
+                    .clone()
+                    .read(READ_SIZE)
+                    .try_or_cancel(cancel)
+                    .await?;
+                let mut codec = self.codec.borrow_mut();
+                if chunk.is_empty() {
+                    if let Some(codec) = codec.take() {
+                        codec.finish()?;
+                    }
+                } else if let Some(codec) = codec.as_mut() {
+                    codec.write(&chunk)?;
+                }
+            }
+        })
+    }
+
+    fn close(self: Rc<Self>) {
+        self.cancel.cancel();
+        self.source.clone().close();
+    }
+}
+
+// takes over the resource `rid`, its stream was piped through a
+// `CompressionStream` or `DecompressionStream` of `format`
+#[op]
+fn op_transform_resource(
+    state: &mut OpState,
+    rid: ResourceId,
+    format: String,
+    is_decoder: bool,
+) -> Result<ResourceId, AnyError> {
+    let output = Output::default();
+    let codec = Codec::new(&format, is_decoder, output.clone())?;
+    let source = state.resource_table.take_any(rid)?;
+    Ok(state.resource_table.add(TransformedResource {
+        source,
+        codec: RefCell::new(Some(codec)),
+        output,
+        cancel: CancelHandle::default(),
+    }))
+}
+
+// Decodes UTF-8 text split across chunks, like a `TextDecoder` with
+// `stream: true` and `fatal: false`.
+#[derive(Default)]
+struct Utf8Decoder {
+    // the start of a character cut at the end of the last chunk
+    partial: Vec<u8>,
+    ignore_bom: bool,
+    bom_seen: bool,
+}
+
+impl Utf8Decoder {
+    fn decode(&mut self, chunk: &[u8], done: bool) -> String {
+        let mut bytes = std::mem::take(&mut self.partial);
+        bytes.extend_from_slice(chunk);
+
+        let mut input = bytes.as_slice();
+        if !self.bom_seen && !self.ignore_bom {
+            // wait for the whole BOM
+            if !done && input.len() < 3 && b"\xEF\xBB\xBF".starts_with(input) {
+                self.partial = bytes;
+                return String::new();
+            }
+            input = input.strip_prefix(b"\xEF\xBB\xBF").unwrap_or(input);
+        }
+        self.bom_seen = true;
+
+        let mut text = String::with_capacity(input.len());
+        loop {
+            match std::str::from_utf8(input) {
+                Ok(valid) => {
+                    text.push_str(valid);
+                    return text;
+                }
+                Err(err) => {
+                    let (valid, rest) = input.split_at(err.valid_up_to());
+                    text.push_str(std::str::from_utf8(valid).unwrap_or_default());
+                    match err.error_len() {
+                        Some(len) => {
+                            text.push(char::REPLACEMENT_CHARACTER);
+                            input = &rest[len..];
+                        }
+                        // cut off, unless there's nothing coming after it
+                        None if done => {
+                            text.push(char::REPLACEMENT_CHARACTER);
+                            return text;
+                        }
+                        None => {
+                            self.partial = rest.to_vec();
+                            return text;
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+// A resource read as text, for a stream piped through a UTF-8
+// `TextDecoderStream`.
+struct TextDecoderResource {
+    source: Rc<dyn Resource>,
+    decoder: RefCell<Utf8Decoder>,
+    cancel: CancelHandle,
+}
+
+impl Resource for TextDecoderResource {
+    fn name(&self) -> Cow<str> {
+        "textDecoderStream".into()
+    }
+
+    fn close(self: Rc<Self>) {
+        self.cancel.cancel();
+        self.source.clone().close();
+    }
+}
+
+#[op]
+fn op_text_decode_resource(
+    state: &mut OpState,
+    rid: ResourceId,
+    ignore_bom: bool,
+) -> Result<ResourceId, AnyError> {
+    let source = state.resource_table.take_any(rid)?;
+    Ok(state.resource_table.add(TextDecoderResource {
+        source,
+        decoder: RefCell::new(Utf8Decoder {
+            ignore_bom,
+            ..Default::default()
+        }),
+        cancel: CancelHandle::default(),
+    }))
+}
+
+// the next text of the resource, `None` once it's done
+#[op]
+async fn op_text_decode_read(
+    state: Rc<RefCell<OpState>>,
+    rid: ResourceId,
+) -> Result<Option<String>, AnyError> {
+    let resource = state
+        .borrow()
+        .resource_table
+        .get::<TextDecoderResource>(rid)?;
+    loop {
+        let cancel = RcRef::map(&resource, |r| &r.cancel);
+        let chunk = resource
+            .source
+            .clone()
+            .read(READ_SIZE)
+            .try_or_cancel(cancel)
+            .await?;
+        let done = chunk.is_empty();
+        let text = resource.decoder.borrow_mut().decode(&chunk, done);
+        if done {
+            state.borrow_mut().resource_table.close(rid)?;
+            return Ok((!text.is_empty()).then_some(text));
+        }
+        if !text.is_empty() {
+            return Ok(Some(text));
+        }
+    }
+}
+
+deno_core::extension!(
+    sb_core_compression,
+    ops = [
+        op_brotli_new,
+        op_brotli_write,
+        op_brotli_finish,
+        op_transform_resource,
+        op_text_decode_resource,
+        op_text_decode_read
+    ]
+);
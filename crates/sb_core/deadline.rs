@@ -0,0 +1,68 @@
+use deno_core::error::{custom_error, AnyError};
+use deno_core::op;
+use deno_core::OpState;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::sync::watch;
+
+// When the worker reaches its wall clock limit, watched from the monitor
+// thread. Trusted workers (eg: batch and cron jobs) can push it back, up to
+// `max_extension` in total.
+#[derive(Debug)]
+pub struct WorkerDeadline {
+    // shared with the worker's `WorkerHandle`
+    tx: Arc<watch::Sender<Instant>>,
+    max_extension: Duration,
+    extended: Duration,
+}
+
+impl WorkerDeadline {
+    pub fn new(tx: Arc<watch::Sender<Instant>>, max_extension: Duration) -> Self {
+        Self {
+            tx,
+            max_extension,
+            extended: Duration::ZERO,
+        }
+    }
+
+    // grants what's left of `max_extension` if less is left than asked for,
+    // returns the time left until the new deadline
+    pub fn extend(&mut self, by: Duration) -> Duration {
+        let granted = by.min(self.max_extension.saturating_sub(self.extended));
+        self.extended += granted;
+        self.tx.send_modify(|at| *at += granted);
+        self.tx.borrow().saturating_duration_since(Instant::now())
+    }
+}
+
+// Resolves once the latest deadline passes.
+pub async fn wait_for_deadline(mut rx: watch::Receiver<Instant>) {
+    loop {
+        let at = tokio::time::Instant::from_std(*rx.borrow_and_update());
+        tokio::select! {
+            _ = tokio::time::sleep_until(at) => return,
+            changed = rx.changed() => {
+                // the isolate is gone, its last deadline stands
+                if changed.is_err() {
+                    tokio::time::sleep_until(at).await;
+                    return;
+                }
+            }
+        }
+    }
+}
+
+// pushes back the wall clock limit of the worker by `ms`, returns the ms
+// left until it's reached
+#[op]
+fn op_extend_deadline(state: &mut OpState, ms: u64) -> Result<u64, AnyError> {
+    let Some(deadline) = state.try_borrow_mut::<WorkerDeadline>() else {
+        return Err(custom_error(
+            "PermissionDenied",
+            "the worker is not allowed to extend its wall clock limit (maxWallClockExtensionMs)",
+        ));
+    };
+    Ok(deadline.extend(Duration::from_millis(ms)).as_millis() as u64)
+}
+
+deno_core::extension!(sb_core_deadline, ops = [op_extend_deadline]);
@@ -0,0 +1,48 @@
+use deno_core::op;
+use deno_core::OpState;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+// Tracks the last time the JS side was able to run a heartbeat timer. The
+// monitor thread compares it against the wall clock to find out how long
+// the event loop has been blocked.
+#[derive(Debug, Clone)]
+pub struct EventLoopHeartbeat {
+    start: Instant,
+    last_beat_ms: Arc<AtomicU64>,
+}
+
+impl Default for EventLoopHeartbeat {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl EventLoopHeartbeat {
+    pub fn new() -> Self {
+        Self {
+            start: Instant::now(),
+            last_beat_ms: Arc::new(AtomicU64::new(0)),
+        }
+    }
+
+    pub fn beat(&self) {
+        let elapsed = self.start.elapsed().as_millis() as u64;
+        self.last_beat_ms.store(elapsed, Ordering::Relaxed);
+    }
+
+    pub fn since_last_beat(&self) -> Duration {
+        let last = Duration::from_millis(self.last_beat_ms.load(Ordering::Relaxed));
+        self.start.elapsed().saturating_sub(last)
+    }
+}
+
+#[op]
+fn op_event_loop_heartbeat(state: &mut OpState) {
+    if let Some(heartbeat) = state.try_borrow::<EventLoopHeartbeat>() {
+        heartbeat.beat();
+    }
+}
+
+deno_core::extension!(sb_core_event_loop, ops = [op_event_loop_heartbeat]);
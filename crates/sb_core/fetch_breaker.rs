@@ -0,0 +1,44 @@
+use deno_core::error::{type_error, AnyError};
+use deno_core::op;
+use deno_core::url::Url;
+use deno_core::OpState;
+use sb_worker_context::fetch_breaker::FetchBreakers;
+
+// host and port the breaker of a fetch call is keyed by
+fn breaker_key(url: &str) -> Option<String> {
+    let url = Url::parse(url).ok()?;
+    let host = url.host_str()?;
+    Some(match url.port_or_known_default() {
+        Some(port) => format!("{}:{}", host, port),
+        None => host.to_string(),
+    })
+}
+
+// called before a fetch call, fails while the circuit of its host is open
+#[op]
+fn op_fetch_breaker_check(state: &mut OpState, url: String) -> Result<(), AnyError> {
+    let (Some(breakers), Some(key)) = (state.try_borrow::<FetchBreakers>(), breaker_key(&url))
+    else {
+        return Ok(());
+    };
+    breakers.check(&key).map_err(|retry_in| {
+        type_error(format!(
+            "the circuit of {} is open after failing calls, retry in {}ms",
+            key,
+            retry_in.as_millis()
+        ))
+    })
+}
+
+// called once a fetch call got a response (or failed)
+#[op]
+fn op_fetch_breaker_report(state: &mut OpState, url: String, ok: bool) {
+    if let (Some(breakers), Some(key)) = (state.try_borrow::<FetchBreakers>(), breaker_key(&url)) {
+        breakers.report(&key, ok);
+    }
+}
+
+deno_core::extension!(
+    sb_core_fetch_breaker,
+    ops = [op_fetch_breaker_check, op_fetch_breaker_report]
+);
@@ -0,0 +1,17 @@
+use deno_core::op;
+use deno_core::OpState;
+use sb_worker_context::fetch::{FetchInterception, FetchInterceptor, InterceptedRequest};
+use std::sync::Arc;
+
+#[derive(Debug, Clone)]
+pub struct FetchInterceptorState(pub Arc<dyn FetchInterceptor>);
+
+#[op]
+fn op_intercept_fetch(state: &mut OpState, req: InterceptedRequest) -> FetchInterception {
+    match state.try_borrow::<FetchInterceptorState>() {
+        Some(interceptor) => interceptor.0.intercept(&req),
+        None => FetchInterception::PassThrough,
+    }
+}
+
+deno_core::extension!(sb_core_fetch_intercept, ops = [op_intercept_fetch]);
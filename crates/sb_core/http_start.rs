@@ -1,13 +1,172 @@
+use std::cell::RefCell;
 use std::rc::Rc;
 
 use deno_core::error::bad_resource;
 use deno_core::error::bad_resource_id;
+use deno_core::error::custom_error;
 use deno_core::error::AnyError;
 use deno_core::op;
+use deno_core::serde_v8;
+use deno_core::v8;
+use deno_core::OpDecl;
 use deno_core::OpState;
+use deno_core::RcRef;
 use deno_core::ResourceId;
 use deno_http::http_create_conn_resource;
+use deno_http::{HttpRequestReader, HttpStreamResource};
 use deno_net::io::UnixStreamResource;
+use tokio::sync::Notify;
+
+// Thresholds past which the worker stops taking requests off its connection,
+// until some of the requests it's handling are answered. Requests that aren't
+// accepted yet stay in the connection, instead of piling up in the isolate.
+#[derive(Debug, Default)]
+pub struct HttpBackpressure {
+    max_in_flight: Option<usize>,
+    max_heap_bytes: Option<usize>,
+    in_flight: usize,
+    heap_used: usize,
+    // shared by the waits for capacity, rather than a channel for each one
+    capacity: Rc<Notify>,
+}
+
+impl HttpBackpressure {
+    pub fn new(max_in_flight: Option<usize>, max_heap_bytes: Option<usize>) -> Self {
+        Self {
+            max_in_flight,
+            max_heap_bytes,
+            ..Default::default()
+        }
+    }
+
+    // the heap is only considered while requests are in flight, their answers
+    // are what the worker waits on to take the next ones
+    pub fn saturated(&self) -> bool {
+        self.in_flight > 0
+            && (self
+                .max_in_flight
+                .map_or(false, |max| self.in_flight >= max)
+                || self
+                    .max_heap_bytes
+                    .map_or(false, |max| self.heap_used >= max))
+    }
+
+    fn sample_heap(&mut self, scope: &mut v8::HandleScope) {
+        if self.max_heap_bytes.is_some() {
+            let mut stats = v8::HeapStatistics::default();
+            scope.get_heap_statistics(&mut stats);
+            self.heap_used = stats.used_heap_size();
+        }
+    }
+
+    fn request_started(&mut self) {
+        self.in_flight += 1;
+    }
+
+    fn request_finished(&mut self) {
+        self.in_flight = self.in_flight.saturating_sub(1);
+        if !self.saturated() {
+            self.capacity.notify_waiters();
+        }
+    }
+}
+
+// Scratch space of the bridge, kept in the op state and reused by every
+// request of the worker instead of being allocated for each one.
+#[derive(Debug, Default)]
+pub struct HttpBufPool {
+    cookies: Vec<u8>,
+    // ids of the requests in flight, copied once as a request is accepted so
+    // the ops tracking it are passed its slot rather than the id. a slot and
+    // its buffer are reused once the request is answered
+    request_ids: Vec<Vec<u8>>,
+    free_slots: Vec<u32>,
+}
+
+impl HttpBufPool {
+    fn acquire_slot(&mut self) -> (u32, &mut Vec<u8>) {
+        let slot = match self.free_slots.pop() {
+            Some(slot) => slot,
+            None => {
+                self.request_ids.push(vec![]);
+                (self.request_ids.len() - 1) as u32
+            }
+        };
+        (slot, &mut self.request_ids[slot as usize])
+    }
+
+    fn release_slot(&mut self, slot: u32) {
+        if let Some(id) = self.request_ids.get_mut(slot as usize) {
+            id.clear();
+            self.free_slots.push(slot);
+        }
+    }
+
+    // empty for a request that came without an id
+    pub fn request_id(&self, slot: u32) -> &str {
+        self.request_ids
+            .get(slot as usize)
+            .and_then(|id| std::str::from_utf8(id).ok())
+            .unwrap_or_default()
+    }
+}
+
+fn header_pair<'a>(
+    scope: &mut v8::HandleScope<'a>,
+    name: &[u8],
+    value: &[u8],
+) -> v8::Local<'a, v8::Value> {
+    // names repeat from a request to the next, v8 keeps a single copy of them
+    let name = v8::String::new_from_one_byte(scope, name, v8::NewStringType::Internalized).unwrap();
+    let value = v8::String::new_from_one_byte(scope, value, v8::NewStringType::Normal).unwrap();
+    v8::Array::new_with_elements(scope, &[name.into(), value.into()]).into()
+}
+
+// Takes the place of deno_http's op_http_headers, which copies the headers of
+// the request into a vector of owned pairs to serialize it. The list is built
+// from hyper's header map directly, the cookies are joined in the pool's
+// buffer, as a single header like deno_http does.
+#[op(v8)]
+fn op_http_headers_pooled<'a>(
+    scope: &mut v8::HandleScope<'a>,
+    state: &mut OpState,
+    rid: ResourceId,
+) -> Result<serde_v8::Value<'a>, AnyError> {
+    let stream = state.resource_table.get::<HttpStreamResource>(rid)?;
+    let rd = RcRef::map(&stream, |r| &r.rd)
+        .try_borrow()
+        .ok_or_else(|| custom_error("Http", "already in use"))?;
+    let headers = match &*rd {
+        HttpRequestReader::Headers(request) => request.headers(),
+        HttpRequestReader::Body(headers, _) => headers,
+        HttpRequestReader::Closed => return Err(bad_resource_id()),
+    };
+
+    let pool = state.borrow_mut::<HttpBufPool>();
+    pool.cookies.clear();
+    let mut has_cookies = false;
+    let list = v8::Array::new(scope, 0);
+    let mut len = 0;
+    for (name, value) in headers {
+        if name == hyper::header::COOKIE {
+            if has_cookies {
+                pool.cookies.extend_from_slice(b"; ");
+            }
+            pool.cookies.extend_from_slice(value.as_bytes());
+            has_cookies = true;
+            continue;
+        }
+        let pair = header_pair(scope, name.as_str().as_bytes(), value.as_bytes());
+        list.set_index(scope, len, pair);
+        len += 1;
+    }
+    if has_cookies {
+        let pair = header_pair(scope, b"cookie", &pool.cookies);
+        list.set_index(scope, len, pair);
+    }
+
+    Ok(v8::Local::<v8::Value>::from(list).into())
+}
 
 #[op]
 fn op_http_start(state: &mut OpState, stream_rid: ResourceId) -> Result<ResourceId, AnyError> {
@@ -28,4 +187,144 @@ fn op_http_start(state: &mut OpState, stream_rid: ResourceId) -> Result<Resource
     Err(bad_resource_id())
 }
 
-deno_core::extension!(sb_core_http, ops = [op_http_start]);
+#[op(v8)]
+fn op_http_backpressure(scope: &mut v8::HandleScope, state: &mut OpState) -> bool {
+    match state.try_borrow_mut::<HttpBackpressure>() {
+        Some(backpressure) => {
+            backpressure.sample_heap(scope);
+            backpressure.saturated()
+        }
+        None => false,
+    }
+}
+
+#[op]
+async fn op_http_wait_for_capacity(state: Rc<RefCell<OpState>>) {
+    let capacity = {
+        let state = state.borrow();
+        let Some(backpressure) = state.try_borrow::<HttpBackpressure>() else {
+            return;
+        };
+        if !backpressure.saturated() {
+            return;
+        }
+        backpressure.capacity.clone()
+    };
+    // registered before any request can finish, the isolate is single threaded
+    capacity.notified().await;
+}
+
+// Returns the slot of the request in the pool, that the other ops tracking
+// it are passed until it's finished.
+#[op(v8)]
+fn op_http_request_started<'a>(
+    scope: &mut v8::HandleScope<'a>,
+    state: &mut OpState,
+    request_id: serde_v8::Value<'a>,
+) -> u32 {
+    if let Some(backpressure) = state.try_borrow_mut::<HttpBackpressure>() {
+        backpressure.request_started();
+    }
+    let (slot, id) = state.borrow_mut::<HttpBufPool>().acquire_slot();
+    if let Ok(request_id) = v8::Local::<v8::String>::try_from(request_id.v8_value) {
+        // written in place, rather than through an owned string
+        id.resize(request_id.utf8_length(scope), 0);
+        request_id.write_utf8(
+            scope,
+            id,
+            None,
+            v8::WriteOptions::NO_NULL_TERMINATION | v8::WriteOptions::REPLACE_INVALID_UTF8,
+        );
+    }
+    slot
+}
+
+#[op(v8)]
+fn op_http_request_finished(scope: &mut v8::HandleScope, state: &mut OpState, slot: u32) {
+    if let Some(backpressure) = state.try_borrow_mut::<HttpBackpressure>() {
+        backpressure.sample_heap(scope);
+        backpressure.request_finished();
+    }
+    state.borrow_mut::<HttpBufPool>().release_slot(slot);
+}
+
+deno_core::extension!(
+    sb_core_http,
+    ops = [
+        op_http_start,
+        op_http_backpressure,
+        op_http_wait_for_capacity,
+        op_http_request_started,
+        op_http_request_finished
+    ],
+    // same name, so deno_http's js calls it
+    middleware = |op| match op.name {
+        "op_http_headers" => OpDecl {
+            name: op.name,
+            ..op_http_headers_pooled::decl()
+        },
+        _ => op,
+    },
+    state = |state| {
+        state.put(HttpBufPool::default());
+    }
+);
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_saturated_in_flight() {
+        let mut backpressure = HttpBackpressure::new(Some(2), None);
+        assert!(!backpressure.saturated());
+        backpressure.request_started();
+        assert!(!backpressure.saturated());
+        backpressure.request_started();
+        assert!(backpressure.saturated());
+        backpressure.request_finished();
+        assert!(!backpressure.saturated());
+    }
+
+    #[test]
+    fn test_saturated_heap() {
+        let mut backpressure = HttpBackpressure::new(None, Some(1024));
+        backpressure.heap_used = 2048;
+        // nothing to wait on while no request is in flight
+        assert!(!backpressure.saturated());
+        backpressure.request_started();
+        assert!(backpressure.saturated());
+    }
+
+    #[tokio::test]
+    async fn test_capacity_notified() {
+        let mut backpressure = HttpBackpressure::new(Some(1), None);
+        backpressure.request_started();
+        let capacity = backpressure.capacity.clone();
+        // created before the request finished, like the op's wait
+        let notified = capacity.notified();
+        backpressure.request_finished();
+        tokio::time::timeout(std::time::Duration::from_secs(1), notified)
+            .await
+            .unwrap();
+    }
+
+    #[test]
+    fn test_request_id_slots() {
+        let mut pool = HttpBufPool::default();
+        let (first, id) = pool.acquire_slot();
+        id.extend_from_slice(b"req-1");
+        let (second, _) = pool.acquire_slot();
+        assert_ne!(first, second);
+        assert_eq!(pool.request_id(first), "req-1");
+        assert_eq!(pool.request_id(second), "");
+
+        pool.release_slot(first);
+        let (reused, id) = pool.acquire_slot();
+        assert_eq!(reused, first);
+        // cleared, but its buffer is kept for the next id
+        assert!(id.is_empty());
+        assert!(id.capacity() >= 5);
+        assert_eq!(pool.request_ids.len(), 2);
+    }
+}
@@ -6,10 +6,12 @@ import DOMException from "ext:deno_web/01_dom_exception.js";
 import * as encoding from "ext:deno_web/08_text_encoding.js";
 import * as event from "ext:deno_web/02_event.js";
 import * as fetch from "ext:deno_fetch/26_fetch.js";
+import * as fetchBody from "ext:deno_fetch/22_body.js";
 import * as file from "ext:deno_web/09_file.js";
 import * as fileReader from "ext:deno_web/10_filereader.js";
 import * as formData from "ext:deno_fetch/21_formdata.js";
 import * as colors from "ext:deno_console/01_colors.js";
+import * as compression from "ext:deno_web/14_compression.js";
 import * as headers from "ext:deno_fetch/20_headers.js";
 import * as streams from "ext:deno_web/06_streams.js";
 import * as timers from "ext:deno_web/02_timers.js";
@@ -31,19 +33,47 @@ const core = globalThis.Deno.core;
 const ops = core.ops;
 
 const {
+  ArrayBufferIsView,
+  ArrayBufferPrototype,
+  ArrayFrom,
+  ArrayPrototypeIncludes,
   ArrayPrototypeIndexOf,
   ArrayPrototypePush,
   ArrayPrototypeShift,
   ArrayPrototypeSplice,
+  BigIntPrototype,
+  DatePrototype,
+  DatePrototypeGetTime,
+  DatePrototypeSetTime,
+  DateUTC,
   Error,
   ErrorPrototype,
+  JSONParse,
+  FunctionPrototypeApply,
+  FunctionPrototypeCall,
+  MathAbs,
+  MathFloor,
+  MathTrunc,
+  NumberIsNaN,
   ObjectDefineProperty,
   ObjectDefineProperties,
   ObjectPrototypeIsPrototypeOf,
   ObjectSetPrototypeOf,
   ObjectFreeze,
+  ObjectGetPrototypeOf,
+  ObjectKeys,
+  NumberParseInt,
+  NumberPrototype,
+  PromiseReject,
+  PromisePrototypeThen,
+  ReflectConstruct,
+  ReflectGet,
+  SafeArrayIterator,
   SafeWeakMap,
+  StringPrototype,
+  StringPrototypePadStart,
   StringPrototypeSplit,
+  StringPrototypeToUpperCase,
   WeakMapPrototypeGet,
   WeakMapPrototypeSet,
   WeakMapPrototypeDelete
@@ -53,7 +83,173 @@ const defineEventHandler = event.defineEventHandler;
 
 function serveHttp(conn) {
   const rid = ops.op_http_start(conn.rid);
-  return new HttpConn(rid, conn.remoteAddr, conn.localAddr);
+  const httpConn = new HttpConn(rid, conn.remoteAddr, conn.localAddr);
+
+  const nextRequest = httpConn.nextRequest;
+  httpConn.nextRequest = async function () {
+    // leave requests in the connection while the worker is saturated
+    while (ops.op_http_backpressure()) {
+      await core.opAsync("op_http_wait_for_capacity");
+    }
+
+    const requestEvent = await FunctionPrototypeCall(nextRequest, httpConn);
+    if (requestEvent) {
+      trackInFlight(requestEvent);
+    }
+    return requestEvent;
+  };
+
+  return httpConn;
+}
+
+// set when what each request costs the isolate is billed
+let meterInvocations = false;
+
+// counts the request as in flight until it's answered
+function trackInFlight(requestEvent) {
+  // uncaught errors are reported with the requests in flight
+  const requestId = requestEvent.request.headers.get("x-request-id");
+  // the id is copied once, the ops below are passed its slot
+  const slot = ops.op_http_request_started(requestId ?? "");
+  if (requestId) {
+    ops.op_uncaught_errors_request_started(slot);
+  }
+  if (requestId && meterInvocations) {
+    ops.op_meter_request_started(slot);
+  }
+  let finished = false;
+  const respondWith = requestEvent.respondWith;
+  requestEvent.respondWith = async function (res) {
+    try {
+      if (requestId && meterInvocations) {
+        // the response may still be in the works
+        try {
+          res = await res;
+        } finally {
+          ops.op_meter_request_responded(slot);
+        }
+      }
+      return await FunctionPrototypeCall(respondWith, requestEvent, res);
+    } finally {
+      if (!finished) {
+        finished = true;
+        if (requestId) {
+          ops.op_uncaught_errors_request_finished(slot);
+        }
+        // last, it frees the slot
+        ops.op_http_request_finished(slot);
+        if (countOpenSockets) {
+          ops.op_open_sockets_refresh();
+        }
+      }
+    }
+  };
+}
+
+// set once a server is started with `Deno.serve`
+let serving = false;
+
+// the client's address derived by the server, the worker's connection is
+// always from the server itself
+function remoteAddrOf(request, conn) {
+  const hostname = request.headers.get("x-edge-runtime-client-ip");
+  if (!hostname) {
+    return conn.remoteAddr;
+  }
+  return { transport: "tcp", hostname, port: 0 };
+}
+
+async function serveConnection(conn, handler, onError) {
+  const httpConn = serveHttp(conn);
+  for await (const requestEvent of httpConn) {
+    (async () => {
+      let res;
+      try {
+        res = await handler(requestEvent.request, {
+          remoteAddr: remoteAddrOf(requestEvent.request, conn),
+        });
+      } catch (error) {
+        if (onError) {
+          res = await onError(error);
+        } else {
+          console.error(error);
+          res = new response.Response("Internal Server Error", { status: 500 });
+        }
+      }
+
+      try {
+        await requestEvent.respondWith(res);
+      } catch {
+        // the connection was closed before the response was sent
+      }
+    })();
+  }
+}
+
+// `Deno.serve(handler)`, `Deno.serve(options, handler)` and
+// `Deno.serve({ ...options, handler })`, served over the worker's connection.
+// The port and hostname are only reported back to `onListen`.
+function serve(arg1, arg2) {
+  let options = {};
+  let handler;
+  if (typeof arg1 === "function") {
+    handler = arg1;
+    options = arg2 ?? {};
+  } else {
+    options = arg1 ?? {};
+    handler = typeof arg2 === "function" ? arg2 : options.handler;
+  }
+  if (typeof handler !== "function") {
+    throw new TypeError("A handler function must be provided.");
+  }
+
+  serving = true;
+  const listener = net.listen({
+    hostname: options.hostname ?? "0.0.0.0",
+    port: options.port ?? 9999,
+  });
+  const finished = (async () => {
+    for await (const conn of listener) {
+      serveConnection(conn, handler, options.onError);
+    }
+  })();
+  options.onListen?.({ hostname: listener.addr.hostname, port: listener.addr.port });
+
+  return {
+    finished,
+    addr: listener.addr,
+    shutdown() {
+      listener.close();
+      return finished;
+    },
+    ref() {},
+    unref() {},
+  };
+}
+
+// Serves `export default { fetch }` of the main module (Cloudflare Workers and
+// Deno Deploy style), unless it started a server itself. Called once the main
+// module is evaluated.
+async function serveDefaultExport() {
+  delete globalThis.serveDefaultExport;
+
+  let main;
+  try {
+    main = await import(ops.op_main_module());
+  } catch {
+    // the evaluation error of the module is reported on its own
+    return;
+  }
+  const fetchHandler = main.default?.fetch;
+  if (serving || typeof fetchHandler !== "function") {
+    return;
+  }
+
+  const ctx = {
+    waitUntil() {},
+    passThroughOnException() {},
+  };
+  serve((req) => FunctionPrototypeCall(fetchHandler, main.default, req, SUPABASE_ENV.toObject(), ctx));
 }
 
 function nonEnumerable(value) {
@@ -92,9 +288,602 @@ function getterOnly(getter) {
   };
 }
 
+let forwardLogs = false;
+let interceptFetch = false;
+let defaultHeaders = [];
+let maxFetchResponseBytes = null;
+let countNetUsage = false;
+let countOpenSockets = false;
+let fetchBreaker = false;
+let fetchPolicy = null;
+
+function addNetUsage(channel, sent, received) {
+  ops.op_net_usage_add(channel, sent, received);
+}
+
+// bytes of a body fetch or a websocket sends
+function byteLength(data) {
+  if (typeof data === "string") {
+    return core.encode(data).byteLength;
+  }
+  if (ObjectPrototypeIsPrototypeOf(file.BlobPrototype, data)) {
+    return data.size;
+  }
+  if (ArrayBufferIsView(data) || ObjectPrototypeIsPrototypeOf(ArrayBufferPrototype, data)) {
+    return data.byteLength;
+  }
+  // eg: FormData, URLSearchParams
+  return fetchBody.extractBody(data).body.length ?? 0;
+}
+
+// passes the chunks of a stream through, calling `count` with their size
+function countingStream(count) {
+  return new streams.TransformStream({
+    transform(chunk, controller) {
+      count(chunk.byteLength);
+      controller.enqueue(chunk);
+    },
+  });
+}
+
+// counts the request body as sent, streamed ones as they're read by fetch
+function countRequestBody(input, init) {
+  const body = init?.body;
+  if (ObjectPrototypeIsPrototypeOf(streams.ReadableStreamPrototype, body)) {
+    return {
+      ...init,
+      body: body.pipeThrough(countingStream((n) => addNetUsage("fetch", n, 0))),
+    };
+  }
+
+  let sent = 0;
+  if (body !== undefined && body !== null) {
+    sent = byteLength(body);
+  } else if (ObjectPrototypeIsPrototypeOf(request.RequestPrototype, input)) {
+    sent = request.toInnerRequest(input).body?.length ?? 0;
+  }
+  addNetUsage("fetch", sent, 0);
+  return init;
+}
+
+function responseTooLarge(url) {
+  return new RangeError(
+    `The response of ${url} is larger than the ${maxFetchResponseBytes} bytes the worker can read (maxFetchResponseBytes).`,
+  );
+}
+
+// counts what's read of the response body, and errors it once it's read past
+// `maxFetchResponseBytes`, instead of letting the worker run out of memory
+function watchResponseBody(res) {
+  const contentLength = res.headers.get("content-length");
+  if (
+    maxFetchResponseBytes !== null && contentLength !== null &&
+    NumberParseInt(contentLength, 10) > maxFetchResponseBytes
+  ) {
+    res.body?.cancel();
+    throw responseTooLarge(res.url);
+  }
+  if (res.body === null) {
+    return res;
+  }
+
+  let read = 0;
+  const watched = res.body.pipeThrough(new streams.TransformStream({
+    transform(chunk, controller) {
+      read += chunk.byteLength;
+      if (maxFetchResponseBytes !== null && read > maxFetchResponseBytes) {
+        controller.error(responseTooLarge(res.url));
+        return;
+      }
+      if (countNetUsage) {
+        addNetUsage("fetch", 0, chunk.byteLength);
+      }
+      controller.enqueue(chunk);
+    },
+  }));
+  const watchedRes = new response.Response(watched, res);
+  ObjectDefineProperties(watchedRes, {
+    url: { value: res.url },
+    redirected: { value: res.redirected },
+  });
+  return watchedRes;
+}
+
+// upstream errors opening the circuit of a host and retried, along with
+// network errors
+const UPSTREAM_FAILURE_STATUSES = [502, 503, 504];
+
+// fails right away while the circuit of the host is open, and reports how the
+// call went, see `op_fetch_breaker_check`
+async function breakerFetch(input, init) {
+  const url = ObjectPrototypeIsPrototypeOf(request.RequestPrototype, input)
+    ? input.url
+    : `${input}`;
+  ops.op_fetch_breaker_check(url);
+
+  let res;
+  try {
+    res = await outboundFetch(input, init);
+  } catch (err) {
+    // calls aborted by the worker don't say anything about the host
+    if (err?.name !== "AbortError") {
+      ops.op_fetch_breaker_report(url, false);
+    }
+    throw err;
+  }
+  ops.op_fetch_breaker_report(url, !ArrayPrototypeIncludes(UPSTREAM_FAILURE_STATUSES, res.status));
+  return res;
+}
+
+function sendFetch(input, init) {
+  return fetchBreaker ? breakerFetch(input, init) : outboundFetch(input, init);
+}
+
+// methods whose calls can be sent again
+const IDEMPOTENT_METHODS = ["GET", "HEAD", "OPTIONS", "PUT", "DELETE"];
+
+function fetchTimeoutError(message) {
+  return new DOMException(message, "TimeoutError");
+}
+
+// errors the body of the response once no chunk was received for `ms`, by
+// aborting the call with `controller`
+function withReadTimeout(res, controller, ms) {
+  const reader = res.body.getReader();
+  let timer;
+  const body = new streams.ReadableStream({
+    async pull(c) {
+      timer = timers.setTimeout(
+        () => controller.abort(fetchTimeoutError(`no data was received from ${res.url} for ${ms}ms (readTimeoutMs)`)),
+        ms,
+      );
+      try {
+        const { value, done } = await reader.read();
+        if (done) {
+          c.close();
+        } else {
+          c.enqueue(value);
+        }
+      } catch (err) {
+        c.error(err);
+      } finally {
+        timers.clearTimeout(timer);
+      }
+    },
+    cancel(reason) {
+      timers.clearTimeout(timer);
+      return reader.cancel(reason);
+    },
+  }, { highWaterMark: 0 });
+
+  const timedRes = new response.Response(body, res);
+  ObjectDefineProperties(timedRes, {
+    url: { value: res.url },
+    redirected: { value: res.redirected },
+  });
+  return timedRes;
+}
+
+// a single attempt of a fetch call, with the timeouts of `fetchPolicy`
+async function timedFetch(input, init) {
+  const { connectTimeoutMs, readTimeoutMs } = fetchPolicy;
+  if (connectTimeoutMs === null && readTimeoutMs === null) {
+    return sendFetch(input, init);
+  }
+
+  // aborted by the worker's signal, or by the timeouts
+  const controller = new abortSignal.AbortController();
+  const signal = init?.signal ??
+    (ObjectPrototypeIsPrototypeOf(request.RequestPrototype, input) ? input.signal : null);
+  if (signal?.aborted) {
+    controller.abort(signal.reason);
+  } else {
+    signal?.addEventListener("abort", () => controller.abort(signal.reason), { once: true });
+  }
+
+  let timer;
+  if (connectTimeoutMs !== null) {
+    timer = timers.setTimeout(
+      () => controller.abort(fetchTimeoutError(`no response was received for ${connectTimeoutMs}ms (connectTimeoutMs)`)),
+      connectTimeoutMs,
+    );
+  }
+  let res;
+  try {
+    res = await sendFetch(input, { ...init, signal: controller.signal });
+  } finally {
+    timers.clearTimeout(timer);
+  }
+
+  if (readTimeoutMs === null || res.body === null) {
+    return res;
+  }
+  return withReadTimeout(res, controller, readTimeoutMs);
+}
+
+// sends the calls again after network errors, timeouts and upstream errors,
+// if they're idempotent and their body can be sent again
+async function retryingFetch(input, init) {
+  const { retries, retryBackoffMs } = fetchPolicy;
+  const isRequest = ObjectPrototypeIsPrototypeOf(request.RequestPrototype, input);
+  const method = StringPrototypeToUpperCase(init?.method ?? (isRequest ? input.method : "GET"));
+  const body = init?.body ?? (isRequest ? input.body : null);
+  const retriable = retries > 0 &&
+    ArrayPrototypeIncludes(IDEMPOTENT_METHODS, method) &&
+    !ObjectPrototypeIsPrototypeOf(streams.ReadableStreamPrototype, body);
+
+  for (let attempt = 0;; attempt++) {
+    const last = !retriable || attempt >= retries;
+    try {
+      const res = await timedFetch(input, init);
+      if (last || !ArrayPrototypeIncludes(UPSTREAM_FAILURE_STATUSES, res.status)) {
+        return res;
+      }
+      await res.body?.cancel();
+    } catch (err) {
+      // calls aborted by the worker aren't sent again
+      if (last || err?.name === "AbortError") {
+        throw err;
+      }
+    }
+    await new Promise((resolve) => timers.setTimeout(resolve, retryBackoffMs * 2 ** attempt));
+  }
+}
+
+function interceptedFetch(input, init) {
+  if (countOpenSockets) {
+    try {
+      ops.op_open_sockets_check();
+    } catch (err) {
+      return PromiseReject(err);
+    }
+  }
+  if (countNetUsage) {
+    init = countRequestBody(input, init);
+  }
+  const res = fetchPolicy === null ? sendFetch(input, init) : retryingFetch(input, init);
+  if (maxFetchResponseBytes === null && !countNetUsage) {
+    return res;
+  }
+  return PromisePrototypeThen(res, watchResponseBody);
+}
+
+// counts what's read from and written to a connection, through its methods
+// and its streams
+function countConn(conn) {
+  const read = conn.read;
+  const write = conn.write;
+  conn.read = async function (p) {
+    const n = await FunctionPrototypeCall(read, conn, p);
+    if (n !== null) {
+      addNetUsage("net", 0, n);
+    }
+    return n;
+  };
+  conn.write = async function (p) {
+    const n = await FunctionPrototypeCall(write, conn, p);
+    addNetUsage("net", n, 0);
+    return n;
+  };
+
+  const proto = ObjectGetPrototypeOf(conn);
+  let readable;
+  let writable;
+  ObjectDefineProperties(conn, {
+    readable: {
+      get() {
+        readable ??= ReflectGet(proto, "readable", conn)
+          .pipeThrough(countingStream((n) => addNetUsage("net", 0, n)));
+        return readable;
+      },
+    },
+    writable: {
+      get() {
+        if (writable === undefined) {
+          const counter = countingStream((n) => addNetUsage("net", n, 0));
+          counter.readable.pipeTo(ReflectGet(proto, "writable", conn));
+          writable = counter.writable;
+        }
+        return writable;
+      },
+    },
+  });
+  return conn;
+}
+
+class CountedWebSocket extends webSocket.WebSocket {
+  constructor(url, protocols) {
+    super(url, protocols);
+    this.addEventListener("message", (e) => addNetUsage("webSocket", 0, byteLength(e.data)));
+  }
+
+  send(data) {
+    super.send(data);
+    addNetUsage("webSocket", byteLength(data), 0);
+  }
+}
+ObjectDefineProperty(CountedWebSocket, "name", { value: "WebSocket" });
+
+// what the worker sends and receives is reported to the host, see `NetUsage`
+function startCountingNetUsage() {
+  countNetUsage = true;
+  Deno.connect = async (opts) => countConn(await net.connect(opts));
+  Deno.connectTls = async (opts) => countConn(await tls.connectTls(opts));
+  Deno.startTls = async (conn, opts) => countConn(await tls.startTls(conn, opts));
+  ObjectDefineProperty(globalThis, "WebSocket", nonEnumerable(CountedWebSocket));
+}
+
+// opening a socket past the worker's `maxOpenSockets` throws, see
+// `SocketUsage`
+function startCountingOpenSockets() {
+  countOpenSockets = true;
+  // wraps the counting ones, see `startCountingNetUsage`
+  const { connect, connectTls, listenDatagram } = Deno;
+  Deno.connect = async (opts) => {
+    ops.op_open_sockets_check();
+    return await connect(opts);
+  };
+  Deno.connectTls = async (opts) => {
+    ops.op_open_sockets_check();
+    return await connectTls(opts);
+  };
+  Deno.listenDatagram = (opts) => {
+    ops.op_open_sockets_check();
+    return listenDatagram(opts);
+  };
+
+  class CappedWebSocket extends globalThis.WebSocket {
+    constructor(url, protocols) {
+      ops.op_open_sockets_check();
+      super(url, protocols);
+    }
+  }
+  ObjectDefineProperty(CappedWebSocket, "name", { value: "WebSocket" });
+  ObjectDefineProperty(globalThis, "WebSocket", nonEnumerable(CappedWebSocket));
+}
+
+// Private and secret keys can't be exported or wrapped, even the extractable
+// ones, so a worker holding a signing key (eg: of webhooks) can't leak its
+// material. Keys don't leave the isolate any other way.
+function restrictKeyExport() {
+  const { exportKey, wrapKey } = crypto.SubtleCrypto.prototype;
+  const check = (key) => {
+    if (ObjectPrototypeIsPrototypeOf(crypto.CryptoKey.prototype, key) && key.type !== "public") {
+      throw new DOMException(
+        `exporting ${key.type} keys is disabled for this worker`,
+        "InvalidAccessError",
+      );
+    }
+  };
+  ObjectDefineProperties(crypto.SubtleCrypto.prototype, {
+    exportKey: nonEnumerable(function exportKey(format, key) {
+      try {
+        check(key);
+      } catch (err) {
+        return PromiseReject(err);
+      }
+      return FunctionPrototypeApply(exportKey, this, arguments);
+    }),
+    wrapKey: nonEnumerable(function wrapKey(format, key, wrappingKey, wrapAlgorithm) {
+      try {
+        check(key);
+      } catch (err) {
+        return PromiseReject(err);
+      }
+      return FunctionPrototypeApply(wrapKey, this, arguments);
+    }),
+  });
+}
+
+// a transform stream of the brotli encoder or decoder, see `op_brotli_new`
+function brotliTransform(isDecoder, prefix) {
+  const rid = ops.op_brotli_new(isDecoder);
+  const enqueue = (controller, output) => {
+    if (output.byteLength > 0) {
+      controller.enqueue(output);
+    }
+  };
+  return new streams.TransformStream({
+    transform(chunk, controller) {
+      chunk = webidl.converters.BufferSource(chunk, { prefix, context: "chunk" });
+      enqueue(controller, ops.op_brotli_write(rid, chunk));
+    },
+    flush(controller) {
+      enqueue(controller, ops.op_brotli_finish(rid));
+    },
+  });
+}
+
+// A transform stream that can also run on this side, when it's piped a
+// resource backed stream (eg: a fetch response's body), rather than going
+// through JS for each chunk. See `pipeThrough` below.
+class ResourceTransform {
+  #transform;
+  #readable = null;
+
+  constructor(transform) {
+    this.#transform = transform;
+  }
+
+  get readable() {
+    return this.#readable ?? this.#transform.readable;
+  }
+
+  get writable() {
+    return this.#transform.writable;
+  }
+
+  canTransformResource() {
+    return true;
+  }
+
+  // takes over the resource `rid`, what's read from it is transformed
+  pipeResource(rid) {
+    // as if something was piped to it
+    this.#transform.writable.getWriter();
+    this.#readable = this.transformResource(rid);
+    return this.#readable;
+  }
+}
+
+// `CompressionStream` and `DecompressionStream` of deno_web ("gzip",
+// "deflate" and "deflate-raw"), which also take "br"
+class CompressionStream extends ResourceTransform {
+  #format;
+
+  constructor(format) {
+    const prefix = "Failed to construct 'CompressionStream'";
+    super(format === "br"
+      ? brotliTransform(false, prefix)
+      : new compression.CompressionStream(format));
+    this.#format = format;
+  }
+
+  transformResource(rid) {
+    return streams.readableStreamForRid(ops.op_transform_resource(rid, this.#format, false));
+  }
+}
+
+class DecompressionStream extends ResourceTransform {
+  #format;
+
+  constructor(format) {
+    const prefix = "Failed to construct 'DecompressionStream'";
+    super(format === "br"
+      ? brotliTransform(true, prefix)
+      : new compression.DecompressionStream(format));
+    this.#format = format;
+  }
+
+  transformResource(rid) {
+    return streams.readableStreamForRid(ops.op_transform_resource(rid, this.#format, true));
+  }
+}
+
+// `TextDecoderStream` of deno_web, UTF-8 without `fatal` is decoded on this
+// side when it can be
+class TextDecoderStream extends ResourceTransform {
+  #decoder;
+
+  constructor(label = "utf-8", options = {}) {
+    const decoder = new encoding.TextDecoderStream(label, options);
+    super(decoder);
+    this.#decoder = decoder;
+  }
+
+  get encoding() {
+    return this.#decoder.encoding;
+  }
+
+  get fatal() {
+    return this.#decoder.fatal;
+  }
+
+  get ignoreBOM() {
+    return this.#decoder.ignoreBOM;
+  }
+
+  canTransformResource() {
+    return this.encoding === "utf-8" && !this.fatal;
+  }
+
+  transformResource(rid) {
+    const decoderRid = ops.op_text_decode_resource(rid, this.ignoreBOM);
+    return new streams.ReadableStream({
+      async pull(controller) {
+        try {
+          const text = await core.opAsync("op_text_decode_read", decoderRid);
+          if (text === null) {
+            controller.close();
+          } else {
+            controller.enqueue(text);
+          }
+        } catch (error) {
+          core.tryClose(decoderRid);
+          controller.error(error);
+        }
+      },
+      cancel() {
+        core.tryClose(decoderRid);
+      },
+    });
+  }
+}
+
+const readableStreamPipeThrough = streams.ReadableStream.prototype.pipeThrough;
+
+// Pipes the resource backed streams given to the transforms above on this
+// side, eg: a fetch response's body through a `CompressionStream`. The stream
+// it returns is resource backed too, so responding with it doesn't go
+// through JS either.
+function pipeThrough(transform, options = undefined) {
+  const backing = streams.getReadableStreamResourceBacking(this);
+  if (
+    backing?.autoClose &&
+    !this.locked &&
+    (options === undefined || ObjectKeys(options).length === 0) &&
+    ObjectPrototypeIsPrototypeOf(ResourceTransform.prototype, transform) &&
+    !transform.writable.locked &&
+    transform.canTransformResource()
+  ) {
+    // the resource is read from the other side now
+    this.getReader();
+    return transform.pipeResource(backing.rid);
+  }
+  return FunctionPrototypeCall(readableStreamPipeThrough, this, transform, options);
+}
+
+// adds the default headers the request doesn't set, and lets the host stub or
+// record outbound requests, see `op_intercept_fetch`
+function outboundFetch(input, init) {
+  if (!interceptFetch && defaultHeaders.length === 0) {
+    return fetch.fetch(input, init);
+  }
+
+  const req = new request.Request(input, init);
+  for (const { 0: name, 1: value } of new SafeArrayIterator(defaultHeaders)) {
+    if (!req.headers.has(name)) {
+      req.headers.set(name, value);
+    }
+  }
+  if (!interceptFetch) {
+    return fetch.fetch(req);
+  }
+
+  return (async () => {
+    const body = req.body === null ? null : await req.clone().text();
+    const interception = ops.op_intercept_fetch({
+      method: req.method,
+      url: req.url,
+      headers: ArrayFrom(req.headers.entries()),
+      body,
+    });
+
+    switch (interception.action) {
+      case "respond":
+        return new response.Response(interception.body, {
+          status: interception.status,
+          headers: interception.headers,
+        });
+      case "reject":
+        throw new TypeError(interception.message);
+      default:
+        return fetch.fetch(req);
+    }
+  })();
+}
+
+function printLog(msg, level) {
+  if (forwardLogs) {
+    ops.op_forward_log(msg, level);
+  } else {
+    core.print(msg, level > 1);
+  }
+}
+
 const globalScope = {
   console: nonEnumerable(
-      new console.Console((msg, level) => core.print(msg, level > 1)),
+      new console.Console(printLog),
   ),
 
   // timers
@@ -107,7 +896,7 @@ const globalScope = {
   Request: nonEnumerable(request.Request),
   Response: nonEnumerable(response.Response),
   Headers: nonEnumerable(headers.Headers),
-  fetch: writable(fetch.fetch),
+  fetch: writable(interceptedFetch),
 
   // base64
   atob: writable(base64.atob),
@@ -116,9 +905,13 @@ const globalScope = {
   // encoding
   TextDecoder: nonEnumerable(encoding.TextDecoder),
   TextEncoder: nonEnumerable(encoding.TextEncoder),
-  TextDecoderStream: nonEnumerable(encoding.TextDecoderStream),
+  TextDecoderStream: nonEnumerable(TextDecoderStream),
   TextEncoderStream: nonEnumerable(encoding.TextEncoderStream),
 
+  // compression
+  CompressionStream: nonEnumerable(CompressionStream),
+  DecompressionStream: nonEnumerable(DecompressionStream),
+
   // url
   URL: nonEnumerable(url.URL),
   URLPattern: nonEnumerable(urlPattern.URLPattern),
@@ -195,6 +988,24 @@ const globalScope = {
 const pendingRejections = [];
 const pendingRejectionsReasons = new SafeWeakMap();
 
+// when set, unhandled rejections are reported to the supervisor instead of
+// terminating the worker
+let reportUnhandledRejections = false;
+
+function reportUnhandledRejection(reason) {
+  if (ObjectPrototypeIsPrototypeOf(ErrorPrototype, reason)) {
+    ops.op_report_unhandled_rejection(
+        `Uncaught (in promise) ${reason.name}: ${reason.message}`,
+        reason.stack ?? null,
+    );
+  } else {
+    ops.op_report_unhandled_rejection(
+        `Uncaught (in promise) ${console.inspectArgs([reason], { colors: false })}`,
+        null,
+    );
+  }
+}
+
 function promiseRejectCallback(type, promise, reason) {
   switch (type) {
     case 0: {
@@ -259,6 +1070,9 @@ function promiseRejectMacrotaskCallback() {
     // throw) we will let Rust side handle it.
     if (rejectionEvent.defaultPrevented) {
       ops.op_remove_pending_promise_rejection(promise);
+    } else if (reportUnhandledRejections) {
+      reportUnhandledRejection(reason);
+      ops.op_remove_pending_promise_rejection(promise);
     }
   }
   return true;
@@ -432,6 +1246,26 @@ function registerErrors() {
   core.registerErrorClass("Http", Http);
   core.registerErrorClass("Busy", Busy);
   core.registerErrorClass("NotSupported", NotSupported);
+  core.registerErrorBuilder(
+      "ModuleResolutionError",
+      function ModuleResolutionError(msg) {
+        const { message, diagnostic } = JSONParse(msg);
+        const err = new Error(message);
+        err.name = "ModuleResolutionError";
+        err.diagnostic = diagnostic;
+        return err;
+      },
+  );
+  core.registerErrorBuilder(
+      "RateLimitError",
+      function RateLimitError(msg) {
+        const { message, retryAfterMs } = JSONParse(msg);
+        const err = new Error(message);
+        err.name = "RateLimitError";
+        err.retryAfterMs = retryAfterMs;
+        return err;
+      },
+  );
   core.registerErrorBuilder(
       "DOMExceptionOperationError",
       function DOMExceptionOperationError(msg) {
@@ -520,6 +1354,16 @@ function opMainModule() {
   return ops.op_main_module();
 }
 
+function startEventLoopHeartbeat(intervalMs) {
+  if (!intervalMs) {
+    return;
+  }
+
+  // unref the timer, so it doesn't keep the event loop alive
+  const id = timers.setInterval(() => ops.op_event_loop_heartbeat(), intervalMs);
+  timers.unrefTimer(id);
+}
+
 function runtimeStart(runtimeOptions, source) {
   core.setMacrotaskCallback(timers.handleTimerMacrotask);
   core.setMacrotaskCallback(promiseRejectMacrotaskCallback);
@@ -543,16 +1387,23 @@ function runtimeStart(runtimeOptions, source) {
 // Deno overrides
 Deno.listen = net.listen;
 Deno.connect = net.connect;
+Deno.listenDatagram = net.createListenDatagram(
+  ops.op_net_listen_udp,
+  ops.op_net_listen_unixpacket,
+);
 Deno.connectTls = tls.connectTls;
 Deno.startTls = tls.startTls;
 Deno.resolveDns = net.resolveDns;
 Deno.serveHttp = serveHttp;
+Deno.serve = serve;
+globalThis.serveDefaultExport = serveDefaultExport;
 
 const __bootstrap = globalThis.__bootstrap;
 delete globalThis.__bootstrap;
 delete globalThis.bootstrap;
 
 ObjectDefineProperties(globalThis, globalScope);
+ObjectDefineProperty(streams.ReadableStream.prototype, "pipeThrough", writable(pipeThrough));
 
 const globalProperties = {
     Window: globalInterfaces.windowConstructorDescriptor,
@@ -586,18 +1437,361 @@ ObjectDefineProperties(Deno, {
   errors,
 });
 
-globalThis.bootstrapSBEdge = (opts, isUserRuntime) => {
+// must match `BOOTSTRAP_OPTIONS_VERSION` (base/src/bootstrap.rs)
+const BOOTSTRAP_OPTIONS_VERSION = 1;
+
+function parseBootstrapOptions(opts) {
+  if (opts?.version !== BOOTSTRAP_OPTIONS_VERSION) {
+    throw new TypeError(
+      `unsupported bootstrap options version ${opts?.version}, expected ${BOOTSTRAP_OPTIONS_VERSION}`,
+    );
+  }
+
+  return {
+    target: opts.target ?? "unknown",
+    debug: !!opts.debug,
+    locale: opts.locale ?? null,
+    timezone: opts.timezone ?? null,
+    isUserWorker: opts.workerKind === "user",
+    defaultHeaders: opts.defaultHeaders ?? [],
+    features: {
+      eventLoopHeartbeatMs: opts.features?.eventLoopHeartbeatMs ?? null,
+      terminateOnUnhandledRejection: !!opts.features?.terminateOnUnhandledRejection,
+      forwardLogs: !!opts.features?.forwardLogs,
+      interceptFetch: !!opts.features?.interceptFetch,
+      maxFetchResponseBytes: opts.features?.maxFetchResponseBytes ?? null,
+      fetchPolicy: opts.features?.fetchPolicy ?? null,
+      countNetUsage: !!opts.features?.countNetUsage,
+      countOpenSockets: !!opts.features?.countOpenSockets,
+      fetchBreaker: !!opts.features?.fetchBreaker,
+      restrictKeyExport: !!opts.features?.restrictKeyExport,
+      meterInvocations: !!opts.features?.meterInvocations,
+      unstable: opts.features?.unstable ?? [],
+    },
+  };
+}
+
+function setLocale(locale) {
+  ObjectDefineProperty(globalThis, "navigator", readOnly(ObjectFreeze({
+    language: locale,
+    languages: ObjectFreeze([locale]),
+  })));
+}
+
+// Intl constructors taking (locales, options)
+const INTL_CONSTRUCTORS = [
+  "Collator",
+  "DateTimeFormat",
+  "DisplayNames",
+  "ListFormat",
+  "NumberFormat",
+  "PluralRules",
+  "RelativeTimeFormat",
+  "Segmenter",
+];
+
+// locale aware methods taking (locales, options), after `skip` other arguments
+const LOCALE_METHODS = [
+  [DatePrototype, "toLocaleString", 0, true],
+  [DatePrototype, "toLocaleDateString", 0, true],
+  [DatePrototype, "toLocaleTimeString", 0, true],
+  [NumberPrototype, "toLocaleString", 0, false],
+  [BigIntPrototype, "toLocaleString", 0, false],
+  [StringPrototype, "localeCompare", 1, false],
+];
+
+// Makes the worker's locale and time zone the defaults of Intl and of the
+// locale aware methods of Date, Number and String, instead of the host's.
+function setIntlDefaults(locale, timeZone) {
+  const defaultLocales = (locales) => locales ?? locale ?? undefined;
+  const withTimeZone = (options) =>
+    timeZone && options?.timeZone === undefined ? { ...options, timeZone } : options;
+
+  for (const name of INTL_CONSTRUCTORS) {
+    const IntlConstructor = Intl[name];
+    if (IntlConstructor === undefined) {
+      continue;
+    }
+
+    const dateTime = name === "DateTimeFormat";
+    // callable with or without `new`, and `instanceof` still holds
+    const Constructor = function (locales, options) {
+      return new IntlConstructor(
+        defaultLocales(locales),
+        dateTime ? withTimeZone(options) : options,
+      );
+    };
+    Constructor.prototype = IntlConstructor.prototype;
+    Constructor.supportedLocalesOf = IntlConstructor.supportedLocalesOf;
+    ObjectDefineProperty(Constructor, "name", { value: name });
+    Intl[name] = Constructor;
+  }
+
+  for (const { 0: proto, 1: name, 2: skip, 3: dateTime } of LOCALE_METHODS) {
+    const method = proto[name];
+    proto[name] = {
+      [name](...args) {
+        args[skip] = defaultLocales(args[skip]);
+        if (dateTime) {
+          args[skip + 1] = withTimeZone(args[skip + 1]);
+        }
+        return FunctionPrototypeApply(method, this, args);
+      },
+    }[name];
+  }
+}
+
+const WEEKDAYS = ["Sun", "Mon", "Tue", "Wed", "Thu", "Fri", "Sat"];
+const MONTHS = [
+  "Jan",
+  "Feb",
+  "Mar",
+  "Apr",
+  "May",
+  "Jun",
+  "Jul",
+  "Aug",
+  "Sep",
+  "Oct",
+  "Nov",
+  "Dec",
+];
+
+// Makes `timeZone` the one of the local time of Date (getters and setters
+// such as getHours, getTimezoneOffset, toString and `new Date(2024, 0, 1)`)
+// instead of the process's (`TZ`). V8 has a single time zone per process,
+// so the local time is converted from and to UTC here. Date strings without
+// an offset are still read in the process's time zone.
+function setDateTimeZone(timeZone) {
+  const OriginalDate = globalThis.Date;
+  const wallClock = new Intl.DateTimeFormat("en-US", {
+    timeZone,
+    hourCycle: "h23",
+    era: "short",
+    year: "numeric",
+    month: "numeric",
+    day: "numeric",
+    hour: "numeric",
+    minute: "numeric",
+    second: "numeric",
+  });
+  const zoneNames = new Intl.DateTimeFormat("en-US", {
+    timeZone,
+    timeZoneName: "long",
+  });
+
+  const partsOf = (format, time) => {
+    const parts = {};
+    for (const { type, value } of new SafeArrayIterator(format.formatToParts(time))) {
+      parts[type] = value;
+    }
+    return parts;
+  };
+  // how far the zone is ahead of UTC at `time`, in ms
+  const offsetAt = (time) => {
+    const { era, year, month, day, hour, minute, second } = partsOf(wallClock, time);
+    // not `Date.UTC`, it takes years 0 to 99 for 1900 to 1999
+    const wall = new OriginalDate(0);
+    wall.setUTCFullYear(era === "BC" ? 1 - year : +year, month - 1, +day);
+    wall.setUTCHours(+hour, +minute, +second);
+    return DatePrototypeGetTime(wall) - MathFloor(time / 1000) * 1000;
+  };
+  const toLocal = (time) => time + offsetAt(time);
+  const toUtc = (local) => {
+    if (NumberIsNaN(local)) {
+      return NaN;
+    }
+    return local - offsetAt(local - offsetAt(local));
+  };
+  // the local time of a date, as the UTC time of another one
+  const localDate = (date) => {
+    const time = DatePrototypeGetTime(date);
+    return new OriginalDate(NumberIsNaN(time) ? NaN : toLocal(time));
+  };
+
+  for (
+    const name of new SafeArrayIterator([
+      "FullYear",
+      "Month",
+      "Date",
+      "Day",
+      "Hours",
+      "Minutes",
+      "Seconds",
+      "Milliseconds",
+    ])
+  ) {
+    const getUtc = DatePrototype[`getUTC${name}`];
+    DatePrototype[`get${name}`] = {
+      [`get${name}`]() {
+        return FunctionPrototypeCall(getUtc, localDate(this));
+      },
+    }[`get${name}`];
+
+    if (name === "Day") {
+      continue;
+    }
+    const setUtc = DatePrototype[`setUTC${name}`];
+    DatePrototype[`set${name}`] = {
+      [`set${name}`](...args) {
+        const time = DatePrototypeGetTime(this);
+        // an invalid date gets a year from the start of the epoch
+        const local = name === "FullYear" && NumberIsNaN(time)
+          ? new OriginalDate(0)
+          : localDate(this);
+        const updated = FunctionPrototypeApply(setUtc, local, args);
+        return DatePrototypeSetTime(this, toUtc(updated));
+      },
+    }[`set${name}`];
+  }
+
+  DatePrototype.getTimezoneOffset = function getTimezoneOffset() {
+    const time = DatePrototypeGetTime(this);
+    return NumberIsNaN(time) ? NaN : MathTrunc(-offsetAt(time) / 60000);
+  };
+
+  const pad = (value, length = 2) => StringPrototypePadStart(`${value}`, length, "0");
+  const dateString = (local) => {
+    const year = local.getUTCFullYear();
+    return `${WEEKDAYS[local.getUTCDay()]} ${MONTHS[local.getUTCMonth()]} ${
+      pad(local.getUTCDate())
+    } ${year < 0 ? "-" : ""}${pad(MathAbs(year), 4)}`;
+  };
+  const timeString = (time, local) => {
+    const offset = MathTrunc(offsetAt(time) / 60000);
+    const { timeZoneName } = partsOf(zoneNames, time);
+    return `${pad(local.getUTCHours())}:${pad(local.getUTCMinutes())}:${
+      pad(local.getUTCSeconds())
+    } GMT${offset < 0 ? "-" : "+"}${pad(MathFloor(MathAbs(offset) / 60))}${
+      pad(MathAbs(offset) % 60)
+    } (${timeZoneName})`;
+  };
+  const toStrings = {
+    toString: (time, local) => `${dateString(local)} ${timeString(time, local)}`,
+    toDateString: (_time, local) => dateString(local),
+    toTimeString: (time, local) => timeString(time, local),
+  };
+  for (const name of ObjectKeys(toStrings)) {
+    const toString = toStrings[name];
+    DatePrototype[name] = {
+      [name]() {
+        const time = DatePrototypeGetTime(this);
+        if (NumberIsNaN(time)) {
+          return "Invalid Date";
+        }
+        return toString(time, new OriginalDate(toLocal(time)));
+      },
+    }[name];
+  }
+
+  // the date and time given to the constructor are the local ones
+  const ZonedDate = function Date(...args) {
+    if (new.target === undefined) {
+      return FunctionPrototypeCall(DatePrototype.toString, new OriginalDate());
+    }
+    if (args.length < 2) {
+      return ReflectConstruct(OriginalDate, args, new.target);
+    }
+    const time = toUtc(FunctionPrototypeApply(DateUTC, null, args));
+    return ReflectConstruct(OriginalDate, [time], new.target);
+  };
+  ObjectDefineProperties(ZonedDate, {
+    length: { value: 7 },
+    prototype: { value: DatePrototype },
+    now: { value: OriginalDate.now, writable: true, configurable: true },
+    parse: { value: OriginalDate.parse, writable: true, configurable: true },
+    UTC: { value: OriginalDate.UTC, writable: true, configurable: true },
+  });
+  ObjectDefineProperty(DatePrototype, "constructor", {
+    value: ZonedDate,
+    writable: true,
+    configurable: true,
+  });
+  ObjectDefineProperty(globalThis, "Date", {
+    value: ZonedDate,
+    writable: true,
+    configurable: true,
+  });
+}
+
+// unstable APIs by the feature enabling them
+const UNSTABLE_APIS = {
+  kv: ["openKv"],
+  cron: ["cron"],
+  ffi: ["dlopen"],
+};
+
+// APIs of disabled features throw, enabled ones are left to whatever
+// defines them (eg: the extensions of an embedder)
+function gateUnstableApis(enabled) {
+  for (const feature of ObjectKeys(UNSTABLE_APIS)) {
+    const isEnabled = ArrayPrototypeIncludes(enabled, feature);
+    for (const name of UNSTABLE_APIS[feature]) {
+      if (isEnabled && Deno[name] !== undefined) {
+        continue;
+      }
+
+      const stub = isEnabled
+        ? () => {
+          throw new NotSupported(`Deno.${name} is not available in this runtime`);
+        }
+        : () => {
+          throw new TypeError(
+            `Deno.${name} is unstable, the worker needs the "${feature}" feature to use it`,
+          );
+        };
+      ObjectDefineProperty(Deno, name, readOnly(stub));
+    }
+  }
+}
+
+globalThis.bootstrapSBEdge = (rawOpts) => {
+  const opts = parseBootstrapOptions(rawOpts);
+
   runtimeStart({
     denoVersion: "NA",
     v8Version: "NA",
     tsVersion: "NA",
     noColor: true,
     isTty: false,
-    ...opts
+    target: opts.target,
   });
 
-  if(isUserRuntime) {
+  if (opts.debug) {
+    Error.stackTraceLimit = Infinity;
+  }
+  if (opts.locale) {
+    setLocale(opts.locale);
+  }
+  if (opts.timezone) {
+    setDateTimeZone(opts.timezone);
+  }
+  if (opts.locale || opts.timezone) {
+    setIntlDefaults(opts.locale, opts.timezone);
+  }
+
+  interceptFetch = opts.features.interceptFetch;
+  defaultHeaders = opts.defaultHeaders;
+  maxFetchResponseBytes = opts.features.maxFetchResponseBytes;
+  fetchPolicy = opts.features.fetchPolicy;
+  fetchBreaker = opts.features.fetchBreaker;
+  meterInvocations = opts.features.meterInvocations;
+  gateUnstableApis(opts.features.unstable);
+  if (opts.features.countNetUsage) {
+    startCountingNetUsage();
+  }
+  if (opts.features.countOpenSockets) {
+    startCountingOpenSockets();
+  }
+  if (opts.features.restrictKeyExport) {
+    restrictKeyExport();
+  }
+
+  if(opts.isUserWorker) {
     loadUserRuntime();
+    startEventLoopHeartbeat(opts.features.eventLoopHeartbeatMs);
+    reportUnhandledRejections = !opts.features.terminateOnUnhandledRejection;
+    forwardLogs = opts.features.forwardLogs;
   }
 
   delete globalThis.bootstrapSBEdge;
@@ -0,0 +1,10 @@
+// the APIs the optional extensions the runtime is built with (eg: `ai`,
+// `mail`) add to `EdgeRuntime`, registered by their modules as they're
+// evaluated
+const EDGE_RUNTIME_APIS = {};
+
+function registerEdgeRuntimeApi(name, api) {
+  EDGE_RUNTIME_APIS[name] = api;
+}
+
+export { EDGE_RUNTIME_APIS, registerEdgeRuntimeApi };
@@ -1,9 +1,17 @@
 import { SUPABASE_USER_WORKERS } from "ext:sb_user_workers/user_workers.js";
+import { EDGE_RUNTIME_APIS } from "ext:sb_core_edge_runtime_apis/js/edge_runtime_apis.js";
+import { SUPABASE_MULTIPART } from "ext:sb_core_main_js/js/multipart.js";
+import { SUPABASE_ROUTER } from "ext:sb_core_main_worker_js/js/router.js";
+import { runtimeInfo } from "ext:sb_core_main_js/js/user_runtime_loader.js";
 
 Object.defineProperty(globalThis, "EdgeRuntime", {
   get() {
     return {
-      userWorkers: SUPABASE_USER_WORKERS
+      userWorkers: SUPABASE_USER_WORKERS,
+      ...EDGE_RUNTIME_APIS,
+      multipart: SUPABASE_MULTIPART,
+      router: SUPABASE_ROUTER,
+      runtimeInfo
     }
   },
   configurable: true
@@ -0,0 +1,285 @@
+import { Headers } from "ext:deno_fetch/20_headers.js";
+import { File } from "ext:deno_web/09_file.js";
+import { ReadableStream } from "ext:deno_web/06_streams.js";
+import { TextDecoder, TextEncoder } from "ext:deno_web/08_text_encoding.js";
+
+// headers of a part, past it the body is unlikely to be multipart
+const MAX_HEADER_BYTES = 16 * 1024;
+// a file is made of blobs of this size, each of them spilled to disk with
+// the `blobSpill` option
+const FILE_SEGMENT_BYTES = 1024 * 1024;
+
+const CRLF = new Uint8Array([13, 10]);
+const HEADERS_END = new Uint8Array([13, 10, 13, 10]);
+
+function concat(a, b) {
+  if (a.byteLength === 0) {
+    return b;
+  }
+  const buf = new Uint8Array(a.byteLength + b.byteLength);
+  buf.set(a, 0);
+  buf.set(b, a.byteLength);
+  return buf;
+}
+
+function indexOf(buf, needle, from = 0) {
+  const first = needle[0];
+  const last = buf.byteLength - needle.byteLength;
+  for (let i = buf.indexOf(first, from); i !== -1 && i <= last; i = buf.indexOf(first, i + 1)) {
+    let j = 1;
+    while (j < needle.byteLength && buf[i + j] === needle[j]) {
+      j++;
+    }
+    if (j === needle.byteLength) {
+      return i;
+    }
+  }
+  return -1;
+}
+
+function boundaryOf(contentType) {
+  const match = /;\s*boundary=(?:"([^"]+)"|([^;\s]+))/i.exec(contentType ?? "");
+  if (!/^multipart\//i.test(contentType ?? "") || !match) {
+    throw new TypeError("the request isn't multipart, or has no boundary");
+  }
+  return match[1] ?? match[2];
+}
+
+// eg: form-data; name="avatar"; filename="me.png"
+function parseContentDisposition(value) {
+  const params = {};
+  const re = /;\s*([^=;\s]+)\s*=\s*("(?:[^"\\]|\\.)*"|[^;]*)/g;
+  for (let match = re.exec(value); match !== null; match = re.exec(value)) {
+    let param = match[2].trim();
+    if (param.startsWith('"')) {
+      param = param.slice(1, -1).replace(/\\(.)/g, "$1");
+    }
+    params[match[1].toLowerCase()] = param;
+  }
+  // filename*=utf-8''na%C3%AFve.txt
+  const extended = /^utf-8''(.*)$/i.exec(params["filename*"] ?? "");
+  if (extended) {
+    try {
+      params.filename = decodeURIComponent(extended[1]);
+    } catch {
+      // keep the plain one
+    }
+  }
+  return params;
+}
+
+// Reads the parts of a multipart body one after the other, as they're
+// streamed in.
+class MultipartReader {
+  #reader;
+  #limits;
+  #delimiter;
+  // the start of the body starts with a delimiter too, without the CRLF
+  #buf = CRLF;
+  #bytes = 0;
+  #parts = 0;
+  #partBytes = 0;
+  // the preamble is read like the body of a part
+  #partEnded = false;
+  #ended = false;
+
+  constructor(body, boundary, limits) {
+    this.#reader = body.getReader();
+    this.#delimiter = new TextEncoder().encode(`\r\n--${boundary}`);
+    this.#limits = limits;
+  }
+
+  // parts read so far
+  get parts() {
+    return this.#parts;
+  }
+
+  async #fill() {
+    const { value, done } = await this.#reader.read();
+    if (done) {
+      throw new TypeError("the multipart body ended early");
+    }
+    this.#bytes += value.byteLength;
+    if (this.#limits.maxBytes !== null && this.#bytes > this.#limits.maxBytes) {
+      throw new RangeError(`the multipart body is larger than ${this.#limits.maxBytes} bytes`);
+    }
+    this.#buf = concat(this.#buf, value);
+  }
+
+  // the next chunk of the current part, null at its end
+  async readBody() {
+    while (!this.#partEnded) {
+      let chunk;
+      const index = indexOf(this.#buf, this.#delimiter);
+      if (index !== -1) {
+        chunk = this.#buf.subarray(0, index);
+        this.#buf = this.#buf.subarray(index + this.#delimiter.byteLength);
+        this.#partEnded = true;
+      } else if (this.#buf.byteLength >= this.#delimiter.byteLength) {
+        // the end may be the start of a delimiter
+        const end = this.#buf.byteLength - this.#delimiter.byteLength + 1;
+        chunk = this.#buf.subarray(0, end);
+        this.#buf = this.#buf.subarray(end);
+      } else {
+        await this.#fill();
+        continue;
+      }
+
+      this.#partBytes += chunk.byteLength;
+      const limit = this.#limits.maxPartBytes;
+      if (limit !== null && this.#parts > 0 && this.#partBytes > limit) {
+        throw new RangeError(`a part is larger than ${limit} bytes`);
+      }
+      if (chunk.byteLength > 0) {
+        return chunk;
+      }
+    }
+    return null;
+  }
+
+  // the headers of the next part, null after the last one
+  async nextPart() {
+    if (this.#ended) {
+      return null;
+    }
+    // skip what's left of the current part, and the preamble
+    while (await this.readBody() !== null);
+
+    while (this.#buf.byteLength < 2) {
+      await this.#fill();
+    }
+    // the closing delimiter ends with --
+    if (this.#buf[0] === 45 && this.#buf[1] === 45) {
+      this.#ended = true;
+      this.#reader.cancel().catch(() => {});
+      return null;
+    }
+
+    let end;
+    while ((end = indexOf(this.#buf, HEADERS_END)) === -1) {
+      if (this.#buf.byteLength > MAX_HEADER_BYTES) {
+        throw new RangeError(`the headers of a part are larger than ${MAX_HEADER_BYTES} bytes`);
+      }
+      await this.#fill();
+    }
+    // what's before the first CRLF is transport padding
+    const start = indexOf(this.#buf, CRLF);
+    const headers = new Headers();
+    const lines = new TextDecoder().decode(this.#buf.subarray(start + 2, end)).split("\r\n");
+    for (const line of lines) {
+      const colon = line.indexOf(":");
+      if (colon > 0) {
+        headers.append(line.slice(0, colon).trim(), line.slice(colon + 1).trim());
+      }
+    }
+    this.#buf = this.#buf.subarray(end + HEADERS_END.byteLength);
+
+    this.#parts++;
+    if (this.#limits.maxParts !== null && this.#parts > this.#limits.maxParts) {
+      throw new RangeError(`the multipart body has more than ${this.#limits.maxParts} parts`);
+    }
+    this.#partBytes = 0;
+    this.#partEnded = false;
+    return headers;
+  }
+
+  cancel(reason) {
+    this.#ended = true;
+    return this.#reader.cancel(reason).catch(() => {});
+  }
+}
+
+// A part of a multipart body. Its body is streamed from the request, and
+// can only be read until the next part is.
+class MultipartPart {
+  constructor(reader, headers) {
+    const index = reader.parts;
+    const disposition = parseContentDisposition(headers.get("content-disposition") ?? "");
+    this.headers = headers;
+    this.name = disposition.name ?? null;
+    // set for files
+    this.filename = disposition.filename ?? null;
+    this.contentType = headers.get("content-type") ?? (this.filename === null ? "text/plain" : "application/octet-stream");
+    this.body = new ReadableStream({
+      pull: async (controller) => {
+        if (reader.parts !== index) {
+          controller.error(new TypeError("the body of a part can't be read after the next part"));
+          return;
+        }
+        try {
+          const chunk = await reader.readBody();
+          if (chunk === null) {
+            controller.close();
+          } else {
+            controller.enqueue(chunk.slice());
+          }
+        } catch (error) {
+          controller.error(error);
+          reader.cancel(error);
+        }
+      },
+    }, { highWaterMark: 0 });
+  }
+
+  async arrayBuffer() {
+    const file = await this.file();
+    return file.arrayBuffer();
+  }
+
+  async text() {
+    const file = await this.file();
+    return file.text();
+  }
+
+  // the part as a `File`, held in segments rather than a single buffer, which
+  // are written to disk with the `blobSpill` option of the worker
+  async file() {
+    const segments = [];
+    let segment = new Uint8Array(0);
+    for await (const chunk of this.body) {
+      segment = concat(segment, chunk);
+      if (segment.byteLength >= FILE_SEGMENT_BYTES) {
+        segments.push(new File([segment], ""));
+        segment = new Uint8Array(0);
+      }
+    }
+    segments.push(segment);
+    return new File(segments, this.filename ?? this.name ?? "", { type: this.contentType });
+  }
+}
+
+// The parts of a multipart request (or response), as it's streamed in, eg:
+//
+//   for await (const part of EdgeRuntime.multipart(req, { maxPartBytes: 10 * 1024 * 1024 })) {
+//     if (part.filename !== null) {
+//       await EdgeRuntime.storage.put(part.filename, part.body);
+//     }
+//   }
+//
+// Going past a limit throws a RangeError.
+async function* multipart(message, opts = {}) {
+  const limits = {
+    maxParts: opts.maxParts ?? null,
+    maxPartBytes: opts.maxPartBytes ?? null,
+    maxBytes: opts.maxBytes ?? null,
+  };
+  const boundary = boundaryOf(message.headers.get("content-type"));
+  if (message.body === null) {
+    throw new TypeError("the multipart body is empty");
+  }
+
+  const reader = new MultipartReader(message.body, boundary, limits);
+  try {
+    let headers;
+    while ((headers = await reader.nextPart()) !== null) {
+      yield new MultipartPart(reader, headers);
+    }
+  } catch (error) {
+    reader.cancel(error);
+    throw error;
+  }
+}
+
+const SUPABASE_MULTIPART = multipart;
+
+export { SUPABASE_MULTIPART };
@@ -0,0 +1,239 @@
+// The connections and listener of deno_net's 01_net.js, for the isolates it
+// isn't in (see `sb_core_no_net`). Only the worker's own connection is ever
+// accepted, the functions opening sockets throw.
+
+const core = globalThis.Deno.core;
+const { BadResourcePrototype, InterruptedPrototype, ops } = core;
+import {
+  readableStreamForRidUnrefable,
+  readableStreamForRidUnrefableRef,
+  readableStreamForRidUnrefableUnref,
+  writableStreamForRid,
+} from "ext:deno_web/06_streams.js";
+const primordials = globalThis.__bootstrap.primordials;
+const {
+  ArrayPrototypeFilter,
+  ArrayPrototypeForEach,
+  ArrayPrototypePush,
+  ObjectPrototypeIsPrototypeOf,
+  PromiseResolve,
+  SymbolAsyncIterator,
+  SymbolFor,
+  TypeError,
+} = primordials;
+
+const promiseIdSymbol = SymbolFor("Deno.core.internalPromiseId");
+
+class Conn {
+  #rid = 0;
+  #remoteAddr = null;
+  #localAddr = null;
+  #unref = false;
+  #pendingReadPromiseIds = [];
+
+  #readable;
+  #writable;
+
+  constructor(rid, remoteAddr, localAddr) {
+    this.#rid = rid;
+    this.#remoteAddr = remoteAddr;
+    this.#localAddr = localAddr;
+  }
+
+  get rid() {
+    return this.#rid;
+  }
+
+  get remoteAddr() {
+    return this.#remoteAddr;
+  }
+
+  get localAddr() {
+    return this.#localAddr;
+  }
+
+  write(p) {
+    return core.write(this.rid, p);
+  }
+
+  async read(buffer) {
+    if (buffer.length === 0) {
+      return 0;
+    }
+    const promise = core.read(this.rid, buffer);
+    const promiseId = promise[promiseIdSymbol];
+    if (this.#unref) core.unrefOp(promiseId);
+    ArrayPrototypePush(this.#pendingReadPromiseIds, promiseId);
+    let nread;
+    try {
+      nread = await promise;
+    } finally {
+      this.#pendingReadPromiseIds = ArrayPrototypeFilter(
+        this.#pendingReadPromiseIds,
+        (id) => id !== promiseId,
+      );
+    }
+    return nread === 0 ? null : nread;
+  }
+
+  close() {
+    core.close(this.rid);
+  }
+
+  closeWrite() {
+    return core.shutdown(this.rid);
+  }
+
+  get readable() {
+    if (this.#readable === undefined) {
+      this.#readable = readableStreamForRidUnrefable(this.rid);
+      if (this.#unref) {
+        readableStreamForRidUnrefableUnref(this.#readable);
+      }
+    }
+    return this.#readable;
+  }
+
+  get writable() {
+    if (this.#writable === undefined) {
+      this.#writable = writableStreamForRid(this.rid);
+    }
+    return this.#writable;
+  }
+
+  ref() {
+    this.#unref = false;
+    if (this.#readable) {
+      readableStreamForRidUnrefableRef(this.#readable);
+    }
+    ArrayPrototypeForEach(this.#pendingReadPromiseIds, (id) => core.refOp(id));
+  }
+
+  unref() {
+    this.#unref = true;
+    if (this.#readable) {
+      readableStreamForRidUnrefableUnref(this.#readable);
+    }
+    ArrayPrototypeForEach(this.#pendingReadPromiseIds, (id) => core.unrefOp(id));
+  }
+}
+
+class TcpConn extends Conn {}
+
+class UnixConn extends Conn {}
+
+// accepts the connection the worker is served on, see `op_net_accept`
+class Listener {
+  #rid = 0;
+  #addr = null;
+  #unref = false;
+  #promiseId = null;
+
+  constructor(rid, addr) {
+    this.#rid = rid;
+    this.#addr = addr;
+  }
+
+  get rid() {
+    return this.#rid;
+  }
+
+  get addr() {
+    return this.#addr;
+  }
+
+  async accept() {
+    const promise = core.opAsync("op_net_accept");
+    this.#promiseId = promise[promiseIdSymbol];
+    if (this.#unref) core.unrefOp(this.#promiseId);
+    const { 0: rid, 1: localAddr, 2: remoteAddr } = await promise;
+    this.#promiseId = null;
+    localAddr.transport = "tcp";
+    remoteAddr.transport = "tcp";
+    return new TcpConn(rid, remoteAddr, localAddr);
+  }
+
+  async next() {
+    let conn;
+    try {
+      conn = await this.accept();
+    } catch (error) {
+      if (
+        ObjectPrototypeIsPrototypeOf(BadResourcePrototype, error) ||
+        ObjectPrototypeIsPrototypeOf(InterruptedPrototype, error)
+      ) {
+        return { value: undefined, done: true };
+      }
+      throw error;
+    }
+    return { value: conn, done: false };
+  }
+
+  return(value) {
+    this.close();
+    return PromiseResolve({ value, done: true });
+  }
+
+  close() {
+    core.close(this.rid);
+  }
+
+  [SymbolAsyncIterator]() {
+    return this;
+  }
+
+  ref() {
+    this.#unref = false;
+    if (typeof this.#promiseId === "number") {
+      core.refOp(this.#promiseId);
+    }
+  }
+
+  unref() {
+    this.#unref = true;
+    if (typeof this.#promiseId === "number") {
+      core.unrefOp(this.#promiseId);
+    }
+  }
+}
+
+function listen(args) {
+  const transport = args.transport ?? "tcp";
+  if (transport !== "tcp") {
+    throw new TypeError(`Unsupported transport: '${transport}'`);
+  }
+  const { 0: rid, 1: addr } = ops.op_net_listen();
+  addr.transport = "tcp";
+  return new Listener(rid, addr);
+}
+
+// throws a `PermissionDenied` error
+function netRemoved() {
+  ops.op_net_removed();
+}
+
+async function connect(_args) {
+  netRemoved();
+}
+
+async function resolveDns(_query, _recordType, _options) {
+  netRemoved();
+}
+
+function createListenDatagram(_udpOpFn, _unixOpFn) {
+  return function listenDatagram(_args) {
+    netRemoved();
+  };
+}
+
+export {
+  Conn,
+  connect,
+  createListenDatagram,
+  listen,
+  Listener,
+  netRemoved,
+  resolveDns,
+  TcpConn,
+  UnixConn,
+};
@@ -0,0 +1,19 @@
+// The TLS connection of deno_net's 02_tls.js, see 01_net.js
+
+import { Conn, netRemoved } from "ext:deno_net/01_net.js";
+
+class TlsConn extends Conn {
+  async handshake() {
+    netRemoved();
+  }
+}
+
+async function connectTls(_args) {
+  netRemoved();
+}
+
+async function startTls(_conn, _args) {
+  netRemoved();
+}
+
+export { connectTls, startTls, TlsConn };
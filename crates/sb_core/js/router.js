@@ -0,0 +1,55 @@
+import { URLPattern } from "ext:deno_url/01_urlpattern.js";
+
+const core = globalThis.Deno.core;
+const ops = core.ops;
+
+// Matches requests to the routes of the main service, in order, eg:
+//
+//   const router = EdgeRuntime.router([
+//     { pattern: "/users/:id", service: "./services/users" },
+//     { pattern: "/hello{/*}?", service: "./services/hello" },
+//   ]);
+//
+//   const route = router.match(req);
+//   if (route === null) {
+//     return new Response("not found", { status: 404 });
+//   }
+//   // route.service, route.params.id
+//
+// The patterns are registered with the runtime, which answers requests to
+// paths none of them can match with a 404 rather than the main worker. A
+// router replaces the routes of the previous one.
+class Router {
+  #routes;
+
+  constructor(routes) {
+    this.#routes = routes.map((route) => {
+      if (typeof route?.pattern !== "string") {
+        throw new TypeError("a route needs a pathname pattern");
+      }
+      return { route, pattern: new URLPattern({ pathname: route.pattern }) };
+    });
+    ops.op_router_register(routes.map((route) => route.pattern));
+  }
+
+  // the first route matching the request (or URL), with the groups of its
+  // pattern as `params`, null if none does
+  match(req) {
+    const url = typeof req === "string" || req instanceof URL ? req : req.url;
+    for (const { route, pattern } of this.#routes) {
+      const result = pattern.exec(url);
+      if (result !== null) {
+        return { ...route, params: result.pathname.groups };
+      }
+    }
+    return null;
+  }
+}
+
+function router(routes) {
+  return new Router(routes);
+}
+
+const SUPABASE_ROUTER = router;
+
+export { SUPABASE_ROUTER };
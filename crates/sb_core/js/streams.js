@@ -0,0 +1,58 @@
+// Byte streams between the ops of an extension and JS, see streams.rs.
+// Reading and writing wait on the other side, a stream is never buffered
+// whole on either.
+import { readableStreamForRid } from "ext:deno_web/06_streams.js";
+
+const core = globalThis.Deno.core;
+const ops = core.ops;
+
+// the chunk as bytes, streams can also carry text
+function chunkBytes(chunk) {
+  if (typeof chunk === "string") {
+    return core.encode(chunk);
+  }
+  if (chunk instanceof Uint8Array) {
+    return chunk;
+  }
+  if (chunk instanceof ArrayBuffer) {
+    return new Uint8Array(chunk);
+  }
+  if (ArrayBuffer.isView(chunk)) {
+    return new Uint8Array(chunk.buffer, chunk.byteOffset, chunk.byteLength);
+  }
+  throw new TypeError("A stream chunk must be a string or a buffer.");
+}
+
+// A `ReadableStream` of what an op gave with `add_readable`, cancelling it
+// lets the op's side know.
+function readableStreamFromRid(rid) {
+  return readableStreamForRid(rid);
+}
+
+// Writes `readable` to a new stream, for an op to read with
+// `take_writable`. The rid is given to the op, `done` settles once
+// `readable` is read, or the op stopped reading it (`readable` is then
+// cancelled).
+function writableRid(readable) {
+  const rid = ops.op_stream_writable_new();
+  const reader = readable.getReader();
+  const done = (async () => {
+    try {
+      while (true) {
+        const { value, done } = await reader.read();
+        if (done) {
+          break;
+        }
+        await core.opAsync("op_stream_write", rid, chunkBytes(value));
+      }
+    } catch (err) {
+      await core.opAsync("op_stream_write_end", rid, String(err?.message ?? err));
+      await reader.cancel(err).catch(() => {});
+      throw err;
+    }
+    await core.opAsync("op_stream_write_end", rid, null);
+  })();
+  return { rid, done };
+}
+
+export { readableStreamFromRid, writableRid };
@@ -1,9 +1,35 @@
+import { EDGE_RUNTIME_APIS } from "ext:sb_core_edge_runtime_apis/js/edge_runtime_apis.js";
+import { SUPABASE_SERVICES } from "ext:sb_service_bindings/service_bindings.js";
+import { SUPABASE_MULTIPART } from "ext:sb_core_main_js/js/multipart.js";
+
+const core = globalThis.Deno.core;
+
 // This file is meant to only have `userRuntimeCleanUp`
 // The code should address any user specific runtime behavior
 // As well as deletions
 
+// pushes back the wall clock limit of the worker by `ms`, up to the
+// maxWallClockExtensionMs it was created with, returns the ms left until it's
+// reached
+function extendDeadline(ms) {
+    if (typeof ms !== "number" || !Number.isFinite(ms) || ms < 0) {
+        throw new TypeError("ms must be a non-negative number");
+    }
+    return core.ops.op_extend_deadline(Math.ceil(ms));
+}
+
+// the version of the runtime, its V8 version, build target, extensions and
+// the features and unstable APIs turned on for the worker
+function runtimeInfo() {
+    return core.ops.op_runtime_info();
+}
+
 function loadUserRuntime() {
-    delete globalThis.EdgeRuntime;
+    // user workers can't create other workers
+    Object.defineProperty(globalThis, "EdgeRuntime", {
+        value: { ...EDGE_RUNTIME_APIS, services: SUPABASE_SERVICES, multipart: SUPABASE_MULTIPART, extendDeadline, runtimeInfo },
+        configurable: true
+    });
 }
 
-export { loadUserRuntime };
\ No newline at end of file
+export { loadUserRuntime, runtimeInfo };
\ No newline at end of file
@@ -0,0 +1,254 @@
+use crate::permissions::Permissions;
+use deno_core::error::AnyError;
+use deno_core::{op, ByteString, OpDecl, OpState, ResourceId, ZeroCopyBuf};
+use deno_fetch::{
+    create_http_client, op_fetch, op_fetch_custom_client, reqwest, CreateHttpClientArgs,
+    FetchReturn,
+};
+use deno_net::ops::IpAddr;
+use deno_net::ops_tls::{op_net_connect_tls, op_tls_start, ConnectTlsArgs, StartTlsArgs};
+use deno_net::DefaultTlsOptions;
+use deno_tls::rustls::RootCertStore;
+use deno_websocket::{op_ws_create, CreateResponse, WsRootStore};
+use std::cell::RefCell;
+use std::rc::Rc;
+
+// Where the root cert store of fetch, websocket and net comes from, it's
+// copied into their op state on the first request or TLS connection of the
+// worker.
+struct LazyRootCertStore(fn() -> RootCertStore);
+
+fn root_cert_store(state: &OpState) -> RootCertStore {
+    (state.borrow::<LazyRootCertStore>().0)()
+}
+
+pub fn init_ws_root_cert_store(state: &mut OpState) {
+    if state.borrow::<WsRootStore>().0.is_none() {
+        let root_cert_store = root_cert_store(state);
+        state.put(WsRootStore(Some(root_cert_store)));
+    }
+}
+
+pub fn init_net_root_cert_store(state: &mut OpState) {
+    if state
+        .borrow::<DefaultTlsOptions>()
+        .root_cert_store
+        .is_none()
+    {
+        let root_cert_store = root_cert_store(state);
+        state.borrow_mut::<DefaultTlsOptions>().root_cert_store = Some(root_cert_store);
+    }
+}
+
+// deno_fetch builds its client when its state is made, from an empty store in
+// user workers, it's built again with the store on the first fetch
+pub fn init_fetch_root_cert_store(state: &mut OpState) -> Result<(), AnyError> {
+    let options = state.borrow::<deno_fetch::Options>();
+    if options
+        .root_cert_store
+        .as_ref()
+        .map_or(false, |store| !store.roots.is_empty())
+    {
+        return Ok(());
+    }
+    let root_cert_store = root_cert_store(state);
+    let options = state.borrow_mut::<deno_fetch::Options>();
+    let client = create_http_client(
+        &options.user_agent,
+        Some(root_cert_store.clone()),
+        vec![],
+        options.proxy.clone(),
+        options.unsafely_ignore_certificate_errors.clone(),
+        options.client_cert_chain_and_key.clone(),
+    )?;
+    // `Deno.createHttpClient` builds its clients from the options
+    options.root_cert_store = Some(root_cert_store);
+    state.put::<reqwest::Client>(client);
+    Ok(())
+}
+
+#[op]
+#[allow(clippy::too_many_arguments)]
+fn op_fetch_lazy(
+    state: &mut OpState,
+    method: ByteString,
+    url: String,
+    headers: Vec<(ByteString, ByteString)>,
+    client_rid: Option<u32>,
+    has_body: bool,
+    body_length: Option<u64>,
+    data: Option<ZeroCopyBuf>,
+) -> Result<FetchReturn, AnyError> {
+    init_fetch_root_cert_store(state)?;
+    op_fetch::call::<Permissions>(
+        state,
+        method,
+        url,
+        headers,
+        client_rid,
+        has_body,
+        body_length,
+        data,
+    )
+}
+
+#[op]
+fn op_fetch_custom_client_lazy(
+    state: &mut OpState,
+    args: CreateHttpClientArgs,
+) -> Result<ResourceId, AnyError> {
+    init_fetch_root_cert_store(state)?;
+    op_fetch_custom_client::call::<Permissions>(state, args)
+}
+
+#[op]
+async fn op_ws_create_lazy(
+    state: Rc<RefCell<OpState>>,
+    api_name: String,
+    url: String,
+    protocols: String,
+    cancel_handle: Option<ResourceId>,
+    headers: Option<Vec<(ByteString, ByteString)>>,
+) -> Result<CreateResponse, AnyError> {
+    init_ws_root_cert_store(&mut state.borrow_mut());
+    op_ws_create::call::<Permissions>(state, api_name, url, protocols, cancel_handle, headers).await
+}
+
+#[op]
+async fn op_tls_start_lazy(
+    state: Rc<RefCell<OpState>>,
+    args: StartTlsArgs,
+) -> Result<(ResourceId, IpAddr, IpAddr), AnyError> {
+    init_net_root_cert_store(&mut state.borrow_mut());
+    op_tls_start::call::<Permissions>(state, args).await
+}
+
+#[op]
+async fn op_net_connect_tls_lazy(
+    state: Rc<RefCell<OpState>>,
+    addr: IpAddr,
+    args: ConnectTlsArgs,
+) -> Result<(ResourceId, IpAddr, IpAddr), AnyError> {
+    init_net_root_cert_store(&mut state.borrow_mut());
+    op_net_connect_tls::call::<Permissions>(state, addr, args).await
+}
+
+// User workers boot without a root cert store in the op state of fetch,
+// websocket and net, many of them never make a request nor open a websocket or
+// a TLS socket. Their ops taking it are swapped for ones copying
+// `root_cert_store` there on first use, so it isn't copied on every boot nor
+// rebuilt on every connection.
+deno_core::extension!(
+    sb_core_lazy_tls,
+    options = {
+        root_cert_store: fn() -> RootCertStore,
+    },
+    middleware = |op| match op.name {
+        "op_fetch" => OpDecl {
+            name: op.name,
+            ..op_fetch_lazy::decl()
+        },
+        "op_fetch_custom_client" => OpDecl {
+            name: op.name,
+            ..op_fetch_custom_client_lazy::decl()
+        },
+        "op_ws_create" => OpDecl {
+            name: op.name,
+            ..op_ws_create_lazy::decl()
+        },
+        "op_tls_start" => OpDecl {
+            name: op.name,
+            ..op_tls_start_lazy::decl()
+        },
+        "op_net_connect_tls" => OpDecl {
+            name: op.name,
+            ..op_net_connect_tls_lazy::decl()
+        },
+        _ => op,
+    },
+    state = |state, options| {
+        state.put(LazyRootCertStore(options.root_cert_store));
+    }
+);
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use deno_core::{JsRuntime, RuntimeOptions};
+    use deno_net::DefaultTlsOptions;
+
+    // the extensions taking a root cert store, as in a user worker
+    fn user_runtime() -> JsRuntime {
+        JsRuntime::new(RuntimeOptions {
+            extensions: vec![
+                deno_webidl::deno_webidl::init_ops(),
+                deno_console::deno_console::init_ops(),
+                deno_url::deno_url::init_ops(),
+                deno_web::deno_web::init_ops::<Permissions>(deno_web::BlobStore::default(), None),
+                deno_fetch::deno_fetch::init_ops::<Permissions>(deno_fetch::Options {
+                    root_cert_store: Some(RootCertStore::empty()),
+                    ..Default::default()
+                }),
+                deno_websocket::deno_websocket::init_ops::<Permissions>(
+                    "test".to_string(),
+                    None,
+                    None,
+                ),
+                deno_net::deno_net::init_ops::<Permissions>(None, true, None),
+                sb_core_lazy_tls::init_ops(deno_tls::create_default_root_cert_store),
+            ],
+            ..Default::default()
+        })
+    }
+
+    #[tokio::test]
+    async fn test_lazy_root_cert_store() {
+        let runtime = user_runtime();
+        let op_state = runtime.op_state();
+        let mut op_state = op_state.borrow_mut();
+        // deno_fetch's client is made with an empty one on boot
+        assert_eq!(
+            op_state
+                .borrow::<deno_fetch::Options>()
+                .root_cert_store
+                .as_ref()
+                .map(|store| store.roots.len()),
+            Some(0)
+        );
+        assert!(op_state
+            .borrow::<DefaultTlsOptions>()
+            .root_cert_store
+            .is_none());
+        assert!(op_state.borrow::<WsRootStore>().0.is_none());
+
+        // copied on the first request or TLS connection
+        init_fetch_root_cert_store(&mut op_state).unwrap();
+        init_net_root_cert_store(&mut op_state);
+        init_ws_root_cert_store(&mut op_state);
+        let roots = Some(deno_tls::create_default_root_cert_store().roots.len());
+        assert_eq!(
+            op_state
+                .borrow::<deno_fetch::Options>()
+                .root_cert_store
+                .as_ref()
+                .map(|store| store.roots.len()),
+            roots
+        );
+        assert_eq!(
+            op_state
+                .borrow::<DefaultTlsOptions>()
+                .root_cert_store
+                .as_ref()
+                .map(|store| store.roots.len()),
+            roots
+        );
+        assert_eq!(
+            op_state
+                .borrow::<WsRootStore>()
+                .0
+                .as_ref()
+                .map(|store| store.roots.len()),
+            roots
+        );
+    }
+}
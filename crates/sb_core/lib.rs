@@ -1,13 +1,40 @@
+pub mod billing;
+pub mod blob;
+pub mod compression;
+pub mod deadline;
+pub mod event_loop;
+pub mod fetch_breaker;
+pub mod fetch_intercept;
 pub mod http_start;
+pub mod lazy_tls;
+pub mod log_files;
+pub mod log_sinks;
+pub mod logs;
 pub mod net;
+pub mod net_usage;
+pub mod no_net;
+pub mod open_sockets;
+pub mod permission_prompt;
 pub mod permissions;
+pub mod router;
 pub mod runtime;
+pub mod streams;
+pub mod uncaught_errors;
+
+// `EdgeRuntime` APIs registered by the optional extensions, loaded before them
+deno_core::extension!(sb_core_edge_runtime_apis, esm = ["js/edge_runtime_apis.js"]);
 
 deno_core::extension!(
     sb_core_main_js,
     esm = [
+        "js/multipart.js",
         "js/user_runtime_loader.js",
-        "js/bootstrap.js",
-        "js/main_worker.js"
+        "js/bootstrap.js"
     ]
 );
+
+// `EdgeRuntime` of the main worker, only in its isolates
+deno_core::extension!(
+    sb_core_main_worker_js,
+    esm = ["js/router.js", "js/main_worker.js"]
+);
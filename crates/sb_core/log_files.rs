@@ -0,0 +1,300 @@
+use crate::log_sinks::{LogRecord, LogSink};
+use anyhow::{bail, Context, Error};
+use chrono::{DateTime, Duration, SecondsFormat, Utc};
+use log::error;
+use sb_worker_context::events::LogLevel;
+use std::collections::HashMap;
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+
+// the time rotated files are named after, eg: `api.20231016T120000000000.log`
+const STAMP_FORMAT: &str = "%Y%m%dT%H%M%S%6f";
+const STAMP_LEN: usize = 21;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum LogRotation {
+    Hourly,
+    Daily,
+}
+
+impl LogRotation {
+    // the period a line written at `at` belongs to
+    fn period(&self, at: DateTime<Utc>) -> String {
+        match self {
+            LogRotation::Hourly => at.format("%Y%m%d%H").to_string(),
+            LogRotation::Daily => at.format("%Y%m%d").to_string(),
+        }
+    }
+}
+
+impl FromStr for LogRotation {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "hourly" => Ok(LogRotation::Hourly),
+            "daily" => Ok(LogRotation::Daily),
+            _ => bail!("invalid log rotation {:?}, expected hourly or daily", s),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct LogFileOpts {
+    pub dir: PathBuf,
+    // a file is rotated before it gets larger
+    pub max_bytes: Option<u64>,
+    pub rotation: Option<LogRotation>,
+    // rotated files kept for each service, the older ones are deleted
+    pub keep: usize,
+}
+
+fn level_name(level: LogLevel) -> &'static str {
+    match level {
+        LogLevel::Debug => "DEBUG",
+        LogLevel::Info => "INFO",
+        LogLevel::Warning => "WARN",
+        LogLevel::Error => "ERROR",
+    }
+}
+
+// the name of the files of a service, eg: `functions_hello` for the service
+// at `./functions/hello`
+pub(crate) fn file_stem(service: &str) -> String {
+    let stem: String = service
+        .chars()
+        .map(|c| match c {
+            'a'..='z' | 'A'..='Z' | '0'..='9' | '-' | '.' => c,
+            _ => '_',
+        })
+        .collect();
+    match stem.trim_matches(|c| c == '_' || c == '.') {
+        "" => "service".to_string(),
+        stem => stem.to_string(),
+    }
+}
+
+#[derive(Debug)]
+struct OpenFile {
+    file: File,
+    size: u64,
+    // of the first line written to the file
+    period: Option<String>,
+}
+
+// The console output of a service, written to `<dir>/<service>.log`. Once
+// the file is too large, or a new hour or day starts, it's renamed with the
+// time it was rotated at and a new one is started.
+#[derive(Debug)]
+pub struct LogFile {
+    path: PathBuf,
+    stem: String,
+    opts: LogFileOpts,
+    open: Mutex<OpenFile>,
+}
+
+impl LogFile {
+    fn open(service: &str, opts: &LogFileOpts) -> Result<Self, Error> {
+        let stem = file_stem(service);
+        let path = opts.dir.join(format!("{}.log", stem));
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&path)
+            .with_context(|| format!("failed to open the log file {}", path.display()))?;
+
+        // a file left by a previous run is rotated if its period is over
+        let metadata = file.metadata()?;
+        let period = opts.rotation.and_then(|rotation| {
+            let modified = metadata.modified().ok()?;
+            Some(rotation.period(modified.into()))
+        });
+
+        Ok(Self {
+            path,
+            stem,
+            opts: opts.clone(),
+            open: Mutex::new(OpenFile {
+                file,
+                size: metadata.len(),
+                period,
+            }),
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn write(&self, level: LogLevel, msg: &str) {
+        let now = Utc::now();
+        let line = format!(
+            "{} {} {}\n",
+            now.to_rfc3339_opts(SecondsFormat::Millis, true),
+            level_name(level),
+            msg.trim_end_matches('\n')
+        );
+        let period = self.opts.rotation.map(|rotation| rotation.period(now));
+
+        let mut open = self.open.lock().unwrap();
+        let too_large = self
+            .opts
+            .max_bytes
+            .map(|max| open.size > 0 && open.size + line.len() as u64 > max)
+            .unwrap_or(false);
+        let period_over = open.size > 0 && open.period.is_some() && open.period != period;
+        if too_large || period_over {
+            if let Err(err) = self.rotate(&mut open, now) {
+                error!("failed to rotate {}: {:#}", self.path.display(), err);
+            }
+        }
+        if open.size == 0 {
+            open.period = period;
+        }
+
+        match open.file.write_all(line.as_bytes()) {
+            Ok(_) => open.size += line.len() as u64,
+            Err(err) => error!("failed to write to {}: {}", self.path.display(), err),
+        }
+    }
+
+    fn rotate(&self, open: &mut OpenFile, now: DateTime<Utc>) -> Result<(), Error> {
+        let mut at = now;
+        let rotated = loop {
+            let path = self
+                .opts
+                .dir
+                .join(format!("{}.{}.log", self.stem, at.format(STAMP_FORMAT)));
+            if !path.exists() {
+                break path;
+            }
+            at += Duration::microseconds(1);
+        };
+        std::fs::rename(&self.path, &rotated)?;
+        open.file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)?;
+        open.size = 0;
+        open.period = None;
+        self.remove_old()
+    }
+
+    // the rotated files past the `keep` most recent ones
+    fn remove_old(&self) -> Result<(), Error> {
+        let prefix = format!("{}.", self.stem);
+        let mut rotated = vec![];
+        for entry in std::fs::read_dir(&self.opts.dir)? {
+            let name = entry?.file_name().to_string_lossy().to_string();
+            let is_rotated = name
+                .strip_prefix(&prefix)
+                .and_then(|rest| rest.strip_suffix(".log"))
+                .map(|stamp| {
+                    stamp.len() == STAMP_LEN
+                        && stamp.chars().all(|c| c.is_ascii_digit() || c == 'T')
+                })
+                .unwrap_or(false);
+            if is_rotated {
+                rotated.push(name);
+            }
+        }
+
+        // the stamps sort as the times they're of
+        rotated.sort();
+        let excess = rotated.len().saturating_sub(self.opts.keep);
+        for name in &rotated[..excess] {
+            std::fs::remove_file(self.opts.dir.join(name))?;
+        }
+        Ok(())
+    }
+}
+
+// Writes the console output of each service to its own file, opened when
+// the service first logs.
+#[derive(Debug)]
+pub struct LogFiles {
+    opts: LogFileOpts,
+    files: Mutex<HashMap<String, Arc<LogFile>>>,
+}
+
+impl LogFiles {
+    pub fn new(opts: LogFileOpts) -> Result<Self, Error> {
+        if opts.max_bytes == Some(0) {
+            bail!("the max size of log files must be greater than 0");
+        }
+        std::fs::create_dir_all(&opts.dir).with_context(|| {
+            format!("failed to create the log directory {}", opts.dir.display())
+        })?;
+        Ok(Self {
+            opts,
+            files: Mutex::new(HashMap::new()),
+        })
+    }
+
+    pub fn get(&self, service: &str) -> Result<Arc<LogFile>, Error> {
+        let mut files = self.files.lock().unwrap();
+        if let Some(file) = files.get(service) {
+            return Ok(file.clone());
+        }
+        let file = Arc::new(LogFile::open(service, &self.opts)?);
+        files.insert(service.to_string(), file.clone());
+        Ok(file)
+    }
+}
+
+impl LogSink for LogFiles {
+    fn write(&self, record: &LogRecord) {
+        match self.get(record.service) {
+            Ok(file) => file.write(record.level, record.msg),
+            Err(err) => error!("{:#}", err),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    // rotated files are named after when they were rotated, and only the
+    // most recent ones are kept
+    #[test]
+    fn test_log_file_rotation() {
+        let dir = std::env::temp_dir().join(uuid::Uuid::new_v4().to_string());
+        let files = LogFiles::new(LogFileOpts {
+            dir: dir.clone(),
+            max_bytes: Some(50),
+            rotation: None,
+            keep: 2,
+        })
+        .unwrap();
+        let log_file = files.get("./functions/hello").unwrap();
+        assert_eq!(log_file.path(), dir.join("functions_hello.log"));
+        // the same file for every worker of the service
+        assert!(Arc::ptr_eq(
+            &log_file,
+            &files.get("./functions/hello").unwrap()
+        ));
+
+        // each line is 37 bytes, so a file holds one
+        for i in 0..5 {
+            log_file.write(LogLevel::Info, &format!("line {}\n", i));
+        }
+
+        let mut names: Vec<String> = std::fs::read_dir(&dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().to_string_lossy().to_string())
+            .collect();
+        names.sort();
+        assert_eq!(names.len(), 3);
+        assert_eq!(names[2], "functions_hello.log");
+        let current = std::fs::read_to_string(dir.join(&names[2])).unwrap();
+        assert!(current.ends_with(" INFO line 4\n"));
+        let last_rotated = std::fs::read_to_string(dir.join(&names[1])).unwrap();
+        assert!(last_rotated.ends_with(" INFO line 3\n"));
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,444 @@
+use crate::log_files::file_stem;
+use anyhow::{bail, Error};
+use chrono::{Local, SecondsFormat, Utc};
+use log::{info, warn};
+use once_cell::sync::OnceCell;
+use sb_worker_context::events::LogLevel;
+use std::fmt::Debug;
+use std::io::{self, Write};
+use std::net::{SocketAddr, TcpStream, ToSocketAddrs, UdpSocket};
+#[cfg(unix)]
+use std::os::unix::net::UnixDatagram;
+#[cfg(unix)]
+use std::path::PathBuf;
+use std::sync::mpsc::{sync_channel, SyncSender};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+static LOG_SINKS: OnceCell<Vec<Arc<dyn LogSink>>> = OnceCell::new();
+
+// messages waiting to be sent by a sink, the ones past it are dropped
+const SINK_QUEUE_SIZE: usize = 4096;
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(5);
+// how long a sink that failed to connect drops messages before trying again
+const RECONNECT_INTERVAL: Duration = Duration::from_secs(1);
+
+const DEFAULT_SYSLOG_SOCKET: &str = "/dev/log";
+const DEFAULT_JOURNALD_SOCKET: &str = "/run/systemd/journal/socket";
+const DEFAULT_SYSLOG_UDP_PORT: u16 = 514;
+const DEFAULT_SYSLOG_TCP_PORT: u16 = 601;
+const DEFAULT_FLUENTD_PORT: u16 = 24224;
+
+// a message written to the console of a worker
+#[derive(Debug)]
+pub struct LogRecord<'a> {
+    pub service: &'a str,
+    pub level: LogLevel,
+    pub msg: &'a str,
+}
+
+// Where the console output of the workers goes instead of stdout. It's
+// written from the worker's thread, so it mustn't block for long.
+pub trait LogSink: Debug + Send + Sync {
+    fn write(&self, record: &LogRecord);
+}
+
+#[derive(Debug)]
+enum Transport {
+    Udp(String),
+    Tcp(String),
+    #[cfg(unix)]
+    Unix(PathBuf),
+}
+
+enum Conn {
+    Udp(UdpSocket),
+    Tcp(TcpStream),
+    #[cfg(unix)]
+    Unix(UnixDatagram),
+}
+
+fn resolve(addr: &str) -> io::Result<SocketAddr> {
+    addr.to_socket_addrs()?
+        .next()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, format!("{} has no address", addr)))
+}
+
+impl Transport {
+    fn connect(&self) -> io::Result<Conn> {
+        match self {
+            Transport::Udp(addr) => {
+                let addr = resolve(addr)?;
+                let socket = match addr {
+                    SocketAddr::V4(_) => UdpSocket::bind("0.0.0.0:0")?,
+                    SocketAddr::V6(_) => UdpSocket::bind("[::]:0")?,
+                };
+                socket.connect(addr)?;
+                Ok(Conn::Udp(socket))
+            }
+            Transport::Tcp(addr) => {
+                let stream = TcpStream::connect_timeout(&resolve(addr)?, CONNECT_TIMEOUT)?;
+                stream.set_write_timeout(Some(CONNECT_TIMEOUT))?;
+                Ok(Conn::Tcp(stream))
+            }
+            #[cfg(unix)]
+            Transport::Unix(path) => {
+                let socket = UnixDatagram::unbound()?;
+                socket.connect(path)?;
+                Ok(Conn::Unix(socket))
+            }
+        }
+    }
+}
+
+impl Conn {
+    fn send(&mut self, frame: &[u8]) -> io::Result<()> {
+        match self {
+            Conn::Udp(socket) => socket.send(frame).map(|_| ()),
+            Conn::Tcp(stream) => stream.write_all(frame),
+            #[cfg(unix)]
+            Conn::Unix(socket) => socket.send(frame).map(|_| ()),
+        }
+    }
+}
+
+// connects first if needed, the connection is dropped if sending fails
+fn send_frame(conn: &mut Option<Conn>, transport: &Transport, frame: &[u8]) -> io::Result<()> {
+    if conn.is_none() {
+        *conn = Some(transport.connect()?);
+    }
+    let sent = conn.as_mut().unwrap().send(frame);
+    if sent.is_err() {
+        *conn = None;
+    }
+    sent
+}
+
+// Sends the frames of a sink from a thread of its own, reconnecting when the
+// connection is lost. Frames are dropped while they can't be sent.
+fn spawn_sender(name: String, transport: Transport) -> Result<SyncSender<Vec<u8>>, Error> {
+    let (tx, rx) = sync_channel::<Vec<u8>>(SINK_QUEUE_SIZE);
+    std::thread::Builder::new()
+        .name(format!("log-sink-{}", name))
+        .spawn(move || {
+            let mut conn = None;
+            let mut retry_at = Instant::now();
+            let mut failing = false;
+            for frame in rx {
+                if conn.is_none() && Instant::now() < retry_at {
+                    continue;
+                }
+                // a connection that was lost is made again once
+                let sent = send_frame(&mut conn, &transport, &frame)
+                    .or_else(|_| send_frame(&mut conn, &transport, &frame));
+                match sent {
+                    Ok(_) if failing => {
+                        info!("sending the console output to {} again", name);
+                        failing = false;
+                    }
+                    Ok(_) => {}
+                    Err(err) => {
+                        if !failing {
+                            warn!("failed to send the console output to {}: {}", name, err);
+                            failing = true;
+                        }
+                        retry_at = Instant::now() + RECONNECT_INTERVAL;
+                    }
+                }
+            }
+        })?;
+    Ok(tx)
+}
+
+// syslog severity, also used by journald
+fn severity(level: LogLevel) -> u8 {
+    match level {
+        LogLevel::Error => 3,
+        LogLevel::Warning => 4,
+        LogLevel::Info => 6,
+        LogLevel::Debug => 7,
+    }
+}
+
+fn level_name(level: LogLevel) -> &'static str {
+    match level {
+        LogLevel::Debug => "debug",
+        LogLevel::Info => "info",
+        LogLevel::Warning => "warn",
+        LogLevel::Error => "error",
+    }
+}
+
+fn hostname() -> String {
+    std::fs::read_to_string("/proc/sys/kernel/hostname")
+        .ok()
+        .or_else(|| std::env::var("HOSTNAME").ok())
+        .map(|hostname| hostname.trim().to_string())
+        .filter(|hostname| !hostname.is_empty())
+        .unwrap_or_else(|| "-".to_string())
+}
+
+fn truncate(s: &str, max_len: usize) -> &str {
+    match s.char_indices().nth(max_len) {
+        Some((end, _)) => &s[..end],
+        None => s,
+    }
+}
+
+// a field of the native journald protocol, in its binary form if the value
+// has line breaks
+fn journald_field(frame: &mut Vec<u8>, name: &str, value: &str) {
+    frame.extend_from_slice(name.as_bytes());
+    if value.contains('\n') {
+        frame.push(b'\n');
+        frame.extend_from_slice(&(value.len() as u64).to_le_bytes());
+    } else {
+        frame.push(b'=');
+    }
+    frame.extend_from_slice(value.as_bytes());
+    frame.push(b'\n');
+}
+
+fn msgpack_str(frame: &mut Vec<u8>, s: &str) {
+    let len = s.len();
+    if len < 32 {
+        frame.push(0xa0 | len as u8);
+    } else if len < 256 {
+        frame.extend_from_slice(&[0xd9, len as u8]);
+    } else if len < 65536 {
+        frame.push(0xda);
+        frame.extend_from_slice(&(len as u16).to_be_bytes());
+    } else {
+        frame.push(0xdb);
+        frame.extend_from_slice(&(len as u32).to_be_bytes());
+    }
+    frame.extend_from_slice(s.as_bytes());
+}
+
+#[derive(Debug)]
+enum Format {
+    // RFC 3164, as the local syslog daemons read it
+    SyslogLocal,
+    // RFC 5424, octet counted when framed (RFC 6587)
+    Syslog { hostname: String, framed: bool },
+    Journald,
+    // the message mode of the forward protocol, tagged `edge-runtime.<service>`
+    Fluentd,
+}
+
+impl Format {
+    fn encode(&self, record: &LogRecord) -> Vec<u8> {
+        let msg = record.msg.trim_end_matches('\n');
+        let stem = file_stem(record.service);
+        // the user facility
+        let pri = 8 + severity(record.level);
+
+        match self {
+            Format::SyslogLocal => format!(
+                "<{}>{} {}[{}]: {}",
+                pri,
+                Local::now().format("%b %e %H:%M:%S"),
+                truncate(&stem, 32),
+                std::process::id(),
+                msg
+            )
+            .into_bytes(),
+            Format::Syslog { hostname, framed } => {
+                let line = format!(
+                    "<{}>1 {} {} {} {} - - {}",
+                    pri,
+                    Utc::now().to_rfc3339_opts(SecondsFormat::Millis, true),
+                    hostname,
+                    truncate(&stem, 48),
+                    std::process::id(),
+                    msg
+                );
+                match framed {
+                    true => format!("{} {}", line.len(), line).into_bytes(),
+                    false => line.into_bytes(),
+                }
+            }
+            Format::Journald => {
+                let mut frame = vec![];
+                journald_field(&mut frame, "MESSAGE", msg);
+                journald_field(&mut frame, "PRIORITY", &severity(record.level).to_string());
+                journald_field(&mut frame, "SYSLOG_IDENTIFIER", &stem);
+                journald_field(&mut frame, "EDGE_RUNTIME_SERVICE", record.service);
+                frame
+            }
+            Format::Fluentd => {
+                // [tag, time, { service, level, message }]
+                let mut frame = vec![0x93];
+                msgpack_str(&mut frame, &format!("edge-runtime.{}", stem));
+                frame.push(0xce);
+                frame.extend_from_slice(&(Utc::now().timestamp() as u32).to_be_bytes());
+                frame.push(0x83);
+                for (key, value) in [
+                    ("service", record.service),
+                    ("level", level_name(record.level)),
+                    ("message", msg),
+                ] {
+                    msgpack_str(&mut frame, key);
+                    msgpack_str(&mut frame, value);
+                }
+                frame
+            }
+        }
+    }
+}
+
+// Forwards the console output to a log daemon or collector.
+#[derive(Debug)]
+struct ForwardSink {
+    format: Format,
+    tx: SyncSender<Vec<u8>>,
+}
+
+impl LogSink for ForwardSink {
+    fn write(&self, record: &LogRecord) {
+        // dropped if the sink can't keep up
+        let _ = self.tx.try_send(self.format.encode(record));
+    }
+}
+
+// `addr` with `port` if it has none
+fn with_default_port(addr: &str, port: u16) -> String {
+    let addr = addr.trim_end_matches('/');
+    match addr.rsplit_once(':') {
+        Some((_, p)) if p.parse::<u16>().is_ok() => addr.to_string(),
+        _ => format!("{}:{}", addr, port),
+    }
+}
+
+#[cfg(unix)]
+fn unix_socket(path: &str) -> Result<Transport, Error> {
+    Ok(Transport::Unix(PathBuf::from(path)))
+}
+
+#[cfg(not(unix))]
+fn unix_socket(_path: &str) -> Result<Transport, Error> {
+    bail!("syslog and journald sockets are only on unix, use syslog+udp:// or syslog+tcp://")
+}
+
+// The sink `spec` names: syslog[:<socket>], syslog+udp://<host[:port]>,
+// syslog+tcp://<host[:port]>, journald[:<socket>] or fluentd://<host[:port]>.
+pub fn parse_log_sink(spec: &str) -> Result<Arc<dyn LogSink>, Error> {
+    let (format, transport) = if let Some(addr) = spec.strip_prefix("syslog+udp://") {
+        (
+            Format::Syslog {
+                hostname: hostname(),
+                framed: false,
+            },
+            Transport::Udp(with_default_port(addr, DEFAULT_SYSLOG_UDP_PORT)),
+        )
+    } else if let Some(addr) = spec.strip_prefix("syslog+tcp://") {
+        (
+            Format::Syslog {
+                hostname: hostname(),
+                framed: true,
+            },
+            Transport::Tcp(with_default_port(addr, DEFAULT_SYSLOG_TCP_PORT)),
+        )
+    } else if let Some(addr) = spec.strip_prefix("fluentd://") {
+        (
+            Format::Fluentd,
+            Transport::Tcp(with_default_port(addr, DEFAULT_FLUENTD_PORT)),
+        )
+    } else if spec == "syslog" || spec.starts_with("syslog:") {
+        let path = spec
+            .strip_prefix("syslog:")
+            .unwrap_or(DEFAULT_SYSLOG_SOCKET);
+        (Format::SyslogLocal, unix_socket(path)?)
+    } else if spec == "journald" || spec.starts_with("journald:") {
+        let path = spec
+            .strip_prefix("journald:")
+            .unwrap_or(DEFAULT_JOURNALD_SOCKET);
+        (Format::Journald, unix_socket(path)?)
+    } else {
+        bail!(
+            "invalid log sink {:?}, expected syslog[:<socket>], syslog+udp://<host>, syslog+tcp://<host>, journald[:<socket>] or fluentd://<host>",
+            spec
+        );
+    };
+
+    let tx = spawn_sender(spec.to_string(), transport)?;
+    Ok(Arc::new(ForwardSink { format, tx }))
+}
+
+// Sends the console output of the workers to `sinks` instead of stdout. Must
+// be called before the first worker is created.
+pub fn init_log_sinks(sinks: Vec<Arc<dyn LogSink>>) -> Result<(), Error> {
+    if LOG_SINKS.set(sinks).is_err() {
+        bail!("log sinks are already set up");
+    }
+    Ok(())
+}
+
+// the sinks of the console output of a worker, with the service it's of
+#[derive(Debug, Clone)]
+pub struct WorkerLogSinks {
+    service: String,
+    sinks: &'static [Arc<dyn LogSink>],
+}
+
+impl WorkerLogSinks {
+    // `None` if the console output isn't sent to sinks
+    pub fn new(service: String) -> Option<Self> {
+        let sinks = LOG_SINKS.get().filter(|sinks| !sinks.is_empty())?;
+        Some(Self { service, sinks })
+    }
+
+    pub fn write(&self, level: LogLevel, msg: &str) {
+        let record = LogRecord {
+            service: &self.service,
+            level,
+            msg,
+        };
+        for sink in self.sinks {
+            sink.write(&record);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_forward_log_sinks() {
+        let record = LogRecord {
+            service: "./functions/hello",
+            level: LogLevel::Info,
+            msg: "hello\n",
+        };
+
+        let syslog = std::net::UdpSocket::bind("127.0.0.1:0").unwrap();
+        syslog
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        let sink =
+            parse_log_sink(&format!("syslog+udp://{}", syslog.local_addr().unwrap())).unwrap();
+        sink.write(&record);
+        let mut buf = [0; 1024];
+        let len = syslog.recv(&mut buf).unwrap();
+        let line = std::str::from_utf8(&buf[..len]).unwrap();
+        // user facility, info severity
+        assert!(line.starts_with("<14>1 "));
+        assert!(line.contains(" functions_hello "));
+        assert!(line.ends_with(" - - hello"));
+
+        let fluentd = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let sink = parse_log_sink(&format!("fluentd://{}", fluentd.local_addr().unwrap())).unwrap();
+        sink.write(&record);
+        let (mut stream, _) = fluentd.accept().unwrap();
+        stream
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        let len = std::io::Read::read(&mut stream, &mut buf).unwrap();
+        let frame = &buf[..len];
+        // [tag, time, record] in msgpack
+        assert_eq!(frame[0], 0x93);
+        assert_eq!(&frame[1..30], b"\xbcedge-runtime.functions_hello");
+        assert!(frame.ends_with(b"\xa7message\xa5hello"));
+    }
+}
@@ -0,0 +1,125 @@
+use crate::log_sinks::WorkerLogSinks;
+use deno_core::op;
+use deno_core::OpState;
+use sb_worker_context::essentials::LogOpts;
+use sb_worker_context::events::{
+    LogEvent, LogLevel, WorkerEventWithMetadata, WorkerEvents, WorkerEventsTx,
+};
+use sb_worker_context::worker_id::WorkerId;
+use std::time::Instant;
+
+// Forwards the console output of a worker to the embedder, instead of
+// printing it to the process' stdout/stderr.
+#[derive(Debug, Clone)]
+pub struct LogForwarder {
+    worker_id: WorkerId,
+    events_tx: WorkerEventsTx,
+}
+
+impl LogForwarder {
+    pub fn new(worker_id: WorkerId, events_tx: WorkerEventsTx) -> Self {
+        Self {
+            worker_id,
+            events_tx,
+        }
+    }
+}
+
+// Drops the console output of a worker below its level, and over its rate
+// (a token bucket). The number of messages dropped for the rate is written
+// before the next one that isn't.
+#[derive(Debug)]
+pub struct LogLimiter {
+    level: LogLevel,
+    rate_per_sec: f64,
+    capacity: f64,
+    tokens: f64,
+    updated_at: Instant,
+    dropped: u64,
+}
+
+impl LogLimiter {
+    pub fn new(opts: &LogOpts) -> Self {
+        let capacity = opts.burst.max(1) as f64;
+        Self {
+            level: opts.level,
+            rate_per_sec: opts.rate_per_sec,
+            capacity,
+            tokens: capacity,
+            updated_at: Instant::now(),
+            dropped: 0,
+        }
+    }
+
+    // the messages dropped since the last one written, `None` if this one is
+    fn admit(&mut self, level: LogLevel, now: Instant) -> Option<u64> {
+        if level < self.level {
+            return None;
+        }
+
+        let elapsed = now.saturating_duration_since(self.updated_at);
+        self.tokens = (self.tokens + elapsed.as_secs_f64() * self.rate_per_sec).min(self.capacity);
+        self.updated_at = now;
+        if self.tokens < 1.0 {
+            self.dropped += 1;
+            return None;
+        }
+        self.tokens -= 1.0;
+        Some(std::mem::take(&mut self.dropped))
+    }
+}
+
+fn write_log(state: &OpState, msg: &str, level: LogLevel) {
+    let log_sinks = state.try_borrow::<WorkerLogSinks>();
+    if let Some(log_sinks) = log_sinks {
+        log_sinks.write(level, msg);
+    }
+
+    let Some(forwarder) = state.try_borrow::<LogForwarder>() else {
+        if log_sinks.is_some() {
+            return;
+        }
+        // forwarding is not set up, fallback to printing
+        if level > LogLevel::Info {
+            eprint!("{}", msg);
+        } else {
+            print!("{}", msg);
+        }
+        return;
+    };
+
+    let _ = forwarder.events_tx.send(WorkerEventWithMetadata {
+        worker_id: forwarder.worker_id.clone(),
+        event: WorkerEvents::Log(LogEvent {
+            msg: msg.trim_end_matches('\n').to_string(),
+            level,
+        }),
+    });
+}
+
+#[op]
+fn op_forward_log(state: &mut OpState, msg: String, level: u8) {
+    // levels used by deno_console
+    let level = match level {
+        0 => LogLevel::Debug,
+        1 => LogLevel::Info,
+        2 => LogLevel::Warning,
+        _ => LogLevel::Error,
+    };
+
+    let admitted = state
+        .try_borrow_mut::<LogLimiter>()
+        .map(|limiter| limiter.admit(level, Instant::now()));
+    match admitted {
+        Some(None) => return,
+        Some(Some(dropped)) if dropped > 0 => write_log(
+            state,
+            &format!("[{} messages dropped]\n", dropped),
+            LogLevel::Warning,
+        ),
+        _ => {}
+    }
+    write_log(state, &msg, level);
+}
+
+deno_core::extension!(sb_core_logs, ops = [op_forward_log]);
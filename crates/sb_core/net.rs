@@ -129,15 +129,19 @@ fn op_net_unsupported(_state: &mut OpState) -> Result<(), AnyError> {
     Err(deno_core::error::not_supported())
 }
 
+// The listener workers are served with. deno_net's ops are swapped for its
+// ones, isolates built without deno_net use them directly (see
+// `sb_core_no_net`).
 deno_core::extension!(
     sb_core_net,
+    ops = [op_net_listen, op_net_accept],
     middleware = |op| match op.name {
         "op_net_listen_tcp" => op_net_listen::decl(),
         "op_net_accept_tcp" => op_net_accept::decl(),
 
-        // disable listening on TLS, UDP and Unix sockets
+        // disable listening on TLS and Unix sockets, UDP sockets are left to
+        // the permissions of the worker
         "op_net_listen_tls" => op_net_unsupported::decl(),
-        "op_net_listen_udp" => op_net_unsupported::decl(),
         "op_node_unstable_net_listen_udp" => op_net_unsupported::decl(),
         "op_net_listen_unix" => op_net_unsupported::decl(),
         "op_net_listen_unixpacket" => op_net_unsupported::decl(),
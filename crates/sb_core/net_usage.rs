@@ -0,0 +1,18 @@
+use deno_core::op;
+use deno_core::OpState;
+use sb_worker_context::net_usage::{NetChannel, NetUsage};
+use std::sync::Arc;
+
+#[derive(Debug, Clone)]
+pub struct NetUsageState(pub Arc<NetUsage>);
+
+// called by the wrappers counting what the worker's fetch calls, connections
+// and websockets send and receive
+#[op]
+fn op_net_usage_add(state: &mut OpState, channel: NetChannel, sent: u64, received: u64) {
+    if let Some(usage) = state.try_borrow::<NetUsageState>() {
+        usage.0.add(channel, sent, received);
+    }
+}
+
+deno_core::extension!(sb_core_net_usage, ops = [op_net_usage_add]);
@@ -0,0 +1,27 @@
+use deno_core::error::{custom_error, AnyError};
+use deno_core::op;
+
+#[op]
+fn op_net_removed() -> Result<(), AnyError> {
+    Err(custom_error(
+        "PermissionDenied",
+        "sockets are not available to this worker, its service's manifest doesn't ask for the \"net\" capability",
+    ))
+}
+
+// Takes the place of deno_net in the user workers that aren't given the net
+// capability in strict mode, they're built from a snapshot of their own. It's
+// named after it as deno_http depends on it and imports its connections, so
+// its js only has these and the listener the worker is served with (see
+// sb_core_net). `Deno.connect`, `Deno.connectTls`, `Deno.startTls`,
+// `Deno.listenDatagram` and `Deno.resolveDns` throw without reaching any op of
+// deno_net, which isn't in the isolate. The runtime has no fs or ffi
+// extension, `Deno.dlopen` is stubbed with the unstable APIs.
+deno_core::extension!(
+    deno_net,
+    deps = [deno_web],
+    ops = [op_net_removed],
+    esm = [dir "js/no_net", "01_net.js", "02_tls.js"]
+);
+
+pub use self::deno_net as sb_core_no_net;
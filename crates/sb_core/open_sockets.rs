@@ -0,0 +1,90 @@
+use deno_core::error::{custom_error, AnyError};
+use deno_core::op;
+use deno_core::{OpState, ResourceTable};
+use sb_worker_context::open_sockets::{OpenSockets, SocketUsage};
+use std::sync::Arc;
+
+// Counts the sockets of the isolate into the worker's `SocketUsage`, and caps
+// them at `max`.
+#[derive(Debug)]
+pub struct SocketUsageState {
+    usage: Arc<SocketUsage>,
+    max: Option<u32>,
+    // what the isolate last added to `usage`
+    counted: OpenSockets,
+}
+
+impl SocketUsageState {
+    pub fn new(usage: Arc<SocketUsage>, max: Option<u32>) -> Self {
+        Self {
+            usage,
+            max,
+            counted: OpenSockets::default(),
+        }
+    }
+
+    fn update(&mut self, now: OpenSockets) {
+        self.usage.update(&self.counted, &now);
+        self.counted = now;
+    }
+}
+
+// the sockets of the isolate are closed along with it
+impl Drop for SocketUsageState {
+    fn drop(&mut self) {
+        self.update(OpenSockets::default());
+    }
+}
+
+// by the names of the resources holding them, unix streams are left out as
+// user workers can't open them, the connection the worker is served on is one
+fn count(table: &ResourceTable) -> OpenSockets {
+    let mut open = OpenSockets::default();
+    for (_, name) in table.names() {
+        match name.as_ref() {
+            "tcpStream" | "tlsStream" => open.connections += 1,
+            "udpSocket" => open.datagrams += 1,
+            "webSocketStream" => open.web_sockets += 1,
+            "fetchRequest" | "fetchResponseBody" => open.fetches += 1,
+            _ => {}
+        }
+    }
+    open
+}
+
+// called before the worker opens a socket, throws once the worker has as
+// many open as it's allowed
+#[op]
+fn op_open_sockets_check(state: &mut OpState) -> Result<(), AnyError> {
+    let now = count(&state.resource_table);
+    let Some(sockets) = state.try_borrow_mut::<SocketUsageState>() else {
+        return Ok(());
+    };
+    sockets.update(now);
+
+    let open = sockets.usage.snapshot().total();
+    match sockets.max {
+        Some(max) if open >= max => Err(custom_error(
+            "DOMExceptionQuotaExceededError",
+            format!(
+                "the worker has {} sockets open, the most it can have (maxOpenSockets)",
+                open
+            ),
+        )),
+        _ => Ok(()),
+    }
+}
+
+// keeps the counts up to date as sockets are closed
+#[op]
+fn op_open_sockets_refresh(state: &mut OpState) {
+    let now = count(&state.resource_table);
+    if let Some(sockets) = state.try_borrow_mut::<SocketUsageState>() {
+        sockets.update(now);
+    }
+}
+
+deno_core::extension!(
+    sb_core_open_sockets,
+    ops = [op_open_sockets_check, op_open_sockets_refresh]
+);
@@ -0,0 +1,147 @@
+use anyhow::{bail, Context, Error};
+use deno_core::serde_json;
+use once_cell::sync::OnceCell;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::io::{BufRead, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+static PERMISSION_PROMPTER: OnceCell<Arc<PermissionPrompter>> = OnceCell::new();
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PermissionKind {
+    Net,
+    Read,
+    Env,
+}
+
+impl PermissionKind {
+    fn name(&self) -> &'static str {
+        match self {
+            PermissionKind::Net => "net",
+            PermissionKind::Read => "read",
+            PermissionKind::Env => "env",
+        }
+    }
+}
+
+// answers given so far, by resource, eg: `{ "net": { "example.com:443": true } }`
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct Decisions {
+    #[serde(default)]
+    net: BTreeMap<String, bool>,
+    #[serde(default)]
+    read: BTreeMap<String, bool>,
+    #[serde(default)]
+    env: BTreeMap<String, bool>,
+}
+
+impl Decisions {
+    fn of(&mut self, kind: PermissionKind) -> &mut BTreeMap<String, bool> {
+        match kind {
+            PermissionKind::Net => &mut self.net,
+            PermissionKind::Read => &mut self.read,
+            PermissionKind::Env => &mut self.env,
+        }
+    }
+}
+
+// Asks on the terminal whether a user worker may use a resource the first
+// time one does, as deno does, and remembers the answers in a file so they
+// survive restarts. For local development only: the isolate waits for the
+// answer.
+#[derive(Debug)]
+pub struct PermissionPrompter {
+    path: PathBuf,
+    // held while prompting so the questions of workers don't interleave
+    decisions: Mutex<Decisions>,
+}
+
+impl PermissionPrompter {
+    pub fn load(path: &Path) -> Result<Self, Error> {
+        let decisions = match std::fs::read_to_string(path) {
+            Ok(json) => serde_json::from_str(&json)
+                .with_context(|| format!("invalid permission decisions in {}", path.display()))?,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => Decisions::default(),
+            Err(err) => return Err(err.into()),
+        };
+        Ok(Self {
+            path: path.to_path_buf(),
+            decisions: Mutex::new(decisions),
+        })
+    }
+
+    // whether `api_name` may use `resource`, asking if it wasn't decided yet
+    pub fn allows(&self, kind: PermissionKind, resource: &str, api_name: &str) -> bool {
+        let mut decisions = self.decisions.lock().unwrap();
+        if let Some(allowed) = decisions.of(kind).get(resource) {
+            return *allowed;
+        }
+        // nobody to ask, eg: when the output is piped
+        if unsafe { libc::isatty(libc::STDIN_FILENO) } != 1 {
+            return false;
+        }
+        let Some(allowed) = ask(kind, resource, api_name) else {
+            return false;
+        };
+        decisions.of(kind).insert(resource.to_string(), allowed);
+        if let Err(err) = self.save(&decisions) {
+            eprintln!(
+                "failed to save the permission decisions to {}: {}",
+                self.path.display(),
+                err
+            );
+        }
+        allowed
+    }
+
+    fn save(&self, decisions: &Decisions) -> Result<(), Error> {
+        if let Some(dir) = self.path.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        std::fs::write(&self.path, serde_json::to_string_pretty(decisions)?)?;
+        Ok(())
+    }
+}
+
+// None if stdin was closed
+fn ask(kind: PermissionKind, resource: &str, api_name: &str) -> Option<bool> {
+    let mut stdin = std::io::stdin().lock();
+    let mut stderr = std::io::stderr().lock();
+    loop {
+        let _ = write!(
+            stderr,
+            "{} wants {} access to {}. Allow? [y/n] ",
+            api_name,
+            kind.name(),
+            resource
+        );
+        let _ = stderr.flush();
+        let mut answer = String::new();
+        match stdin.read_line(&mut answer) {
+            Ok(0) | Err(_) => return None,
+            Ok(_) => {}
+        }
+        match answer.trim().to_lowercase().as_str() {
+            "y" | "yes" => return Some(true),
+            "n" | "no" => return Some(false),
+            _ => {}
+        }
+    }
+}
+
+// Makes user workers ask before using the network, reading files or env vars
+// they aren't given, remembering the answers in `path`. Must be called before
+// the first worker is created.
+pub fn init_permission_prompt(path: &Path) -> Result<(), Error> {
+    let prompter = PermissionPrompter::load(path)?;
+    if PERMISSION_PROMPTER.set(Arc::new(prompter)).is_err() {
+        bail!("the permission prompt is already on");
+    }
+    Ok(())
+}
+
+pub fn permission_prompter() -> Option<Arc<PermissionPrompter>> {
+    PERMISSION_PROMPTER.get().cloned()
+}
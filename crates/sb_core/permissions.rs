@@ -1,8 +1,82 @@
-use deno_core::error::AnyError;
+use crate::permission_prompt::{PermissionKind, PermissionPrompter};
+use deno_core::error::{custom_error, type_error, AnyError};
 use deno_core::url::Url;
+use sb_worker_context::events::{
+    PermissionDeniedEvent, WorkerEventWithMetadata, WorkerEvents, WorkerEventsTx,
+};
+use sb_worker_context::worker_id::WorkerId;
 use std::path::Path;
+use std::str::FromStr;
+use std::sync::Arc;
 
-pub struct Permissions;
+// A host (`example.com`, `*.example.com` for its subdomains) and optionally
+// the only port allowed on it (`example.com:6379`).
+#[derive(Debug, Clone, PartialEq)]
+pub struct NetDescriptor {
+    host: String,
+    port: Option<u16>,
+}
+
+impl FromStr for NetDescriptor {
+    type Err = AnyError;
+
+    fn from_str(s: &str) -> Result<Self, AnyError> {
+        let invalid = || type_error(format!("invalid net allowlist entry {:?}", s));
+        // [::1]:80
+        let (host, port) = match s.strip_prefix('[') {
+            Some(rest) => {
+                let (host, rest) = rest.split_once(']').ok_or_else(invalid)?;
+                match rest {
+                    "" => (host, None),
+                    rest => (host, Some(rest.strip_prefix(':').ok_or_else(invalid)?)),
+                }
+            }
+            None => match s.split_once(':') {
+                Some((host, port)) => (host, Some(port)),
+                None => (s, None),
+            },
+        };
+        if host.is_empty() {
+            return Err(invalid());
+        }
+        let port = port
+            .map(|port| port.parse::<u16>().map_err(|_| invalid()))
+            .transpose()?;
+
+        Ok(Self {
+            host: host.to_lowercase(),
+            port,
+        })
+    }
+}
+
+impl NetDescriptor {
+    fn matches(&self, host: &str, port: Option<u16>) -> bool {
+        let host = host.trim_start_matches('[').trim_end_matches(']');
+        let host_matches = match self.host.strip_prefix("*.") {
+            Some(domain) => host
+                .to_lowercase()
+                .strip_suffix(domain)
+                .map_or(false, |sub| sub.ends_with('.')),
+            None => self.host.eq_ignore_ascii_case(host),
+        };
+        host_matches && (self.port.is_none() || self.port == port)
+    }
+}
+
+pub struct Permissions {
+    // hosts fetch, websockets and Deno.connect can reach, any if unset
+    net_allowlist: Option<Vec<NetDescriptor>>,
+    // Deno.listenDatagram
+    allow_udp: bool,
+    allow_unix_sockets: bool,
+    // (worker id, events channel) the denials are sent to, so operators can
+    // spot functions probing what they can reach or missing an allowlist entry
+    denials_tx: Option<(WorkerId, WorkerEventsTx)>,
+    // asks before the network (outside of the allowlist), files and env vars
+    // are used, in local development
+    prompter: Option<Arc<PermissionPrompter>>,
+}
 
 impl Default for Permissions {
     fn default() -> Self {
@@ -11,25 +85,131 @@ impl Default for Permissions {
 }
 
 impl Permissions {
+    // everything is allowed, as for the main worker
     pub fn new() -> Self {
-        Self
+        Self {
+            net_allowlist: None,
+            allow_udp: true,
+            allow_unix_sockets: true,
+            denials_tx: None,
+            prompter: None,
+        }
+    }
+
+    // user workers only get UDP sockets when they're trusted with them, and
+    // never unix sockets, which would reach the host's services
+    pub fn user_worker(
+        net_allowlist: Option<&[String]>,
+        allow_udp: bool,
+    ) -> Result<Self, AnyError> {
+        let net_allowlist = net_allowlist
+            .map(|allowlist| allowlist.iter().map(|entry| entry.parse()).collect())
+            .transpose()?;
+        Ok(Self {
+            net_allowlist,
+            allow_udp,
+            allow_unix_sockets: false,
+            denials_tx: None,
+            prompter: None,
+        })
+    }
+
+    pub fn with_denials_tx(mut self, worker_id: WorkerId, events_tx: WorkerEventsTx) -> Self {
+        self.denials_tx = Some((worker_id, events_tx));
+        self
+    }
+
+    pub fn with_prompter(mut self, prompter: Arc<PermissionPrompter>) -> Self {
+        self.prompter = Some(prompter);
+        self
+    }
+
+    // allowed unless the answer to the prompt is no
+    fn prompt(
+        &self,
+        kind: PermissionKind,
+        resource: String,
+        api_name: &str,
+    ) -> Result<(), AnyError> {
+        match &self.prompter {
+            Some(prompter) if !prompter.allows(kind, &resource, api_name) => {
+                let msg = format!("{} was denied access to {}", api_name, resource);
+                Err(self.deny(api_name, resource, msg))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    fn deny(&self, api_name: &str, resource: String, msg: String) -> AnyError {
+        if let Some((worker_id, events_tx)) = &self.denials_tx {
+            let _ = events_tx.send(WorkerEventWithMetadata {
+                worker_id: worker_id.clone(),
+                event: WorkerEvents::PermissionDenied(PermissionDeniedEvent {
+                    api_name: api_name.to_string(),
+                    resource,
+                }),
+            });
+        }
+        custom_error("PermissionDenied", msg)
+    }
+
+    fn check_udp(&self, api_name: &str) -> Result<(), AnyError> {
+        if self.allow_udp {
+            return Ok(());
+        }
+        Err(self.deny(
+            api_name,
+            "udp".to_string(),
+            format!("{} is not allowed to use UDP sockets", api_name),
+        ))
+    }
+
+    fn check_host(&self, host: &str, port: Option<u16>, api_name: &str) -> Result<(), AnyError> {
+        let target = || match port {
+            Some(port) => format!("{}:{}", host, port),
+            None => host.to_string(),
+        };
+        match &self.net_allowlist {
+            Some(allowlist) if allowlist.iter().any(|d| d.matches(host, port)) => Ok(()),
+            // the hosts outside of the allowlist are asked for instead
+            _ if self.prompter.is_some() => self.prompt(PermissionKind::Net, target(), api_name),
+            Some(_) => {
+                let target = target();
+                let msg = format!("{} is not allowed to reach {}", api_name, target);
+                Err(self.deny(api_name, target, msg))
+            }
+            None => Ok(()),
+        }
+    }
+
+    fn check_url(&self, url: &Url, api_name: &str) -> Result<(), AnyError> {
+        match url.host_str() {
+            Some(host) => self.check_host(host, url.port_or_known_default(), api_name),
+            // eg: data: and blob: urls
+            None => Ok(()),
+        }
     }
 
-    pub fn check_env(&mut self, _var: &str) -> Result<(), AnyError> {
-        Ok(())
+    pub fn check_env(&mut self, var: &str) -> Result<(), AnyError> {
+        self.prompt(PermissionKind::Env, var.to_string(), "Deno.env.get()")
     }
 
     pub fn check_env_all(&mut self) -> Result<(), AnyError> {
-        Ok(())
+        self.prompt(PermissionKind::Env, "*".to_string(), "Deno.env.toObject()")
     }
 
+    // `display` is shown instead of the path
     pub fn check_read_blind(
         &mut self,
         _path: &Path,
-        _display: &str,
-        _api_name: &str,
+        display: &str,
+        api_name: &str,
     ) -> Result<(), AnyError> {
-        Ok(())
+        self.prompt(PermissionKind::Read, format!("<{}>", display), api_name)
+    }
+
+    fn check_read_path(&self, path: &Path, api_name: &str) -> Result<(), AnyError> {
+        self.prompt(PermissionKind::Read, path.display().to_string(), api_name)
     }
 }
 
@@ -49,35 +229,89 @@ impl deno_web::TimersPermission for Permissions {
 }
 
 impl deno_fetch::FetchPermissions for Permissions {
-    fn check_net_url(&mut self, _url: &Url, _api_name: &str) -> Result<(), AnyError> {
-        Ok(())
+    fn check_net_url(&mut self, url: &Url, api_name: &str) -> Result<(), AnyError> {
+        self.check_url(url, api_name)
     }
 
-    fn check_read(&mut self, _p: &Path, _api_name: &str) -> Result<(), AnyError> {
-        Ok(())
+    fn check_read(&mut self, p: &Path, api_name: &str) -> Result<(), AnyError> {
+        self.check_read_path(p, api_name)
     }
 }
 
 impl deno_net::NetPermissions for Permissions {
     fn check_net<T: AsRef<str>>(
         &mut self,
-        _host: &(T, Option<u16>),
-        _api_name: &str,
+        host: &(T, Option<u16>),
+        api_name: &str,
     ) -> Result<(), AnyError> {
-        Ok(())
+        // binding a datagram socket doesn't reach anything, its sends are checked
+        if api_name.starts_with("Deno.listenDatagram") {
+            return self.check_udp(api_name);
+        }
+        if api_name.starts_with("Deno.DatagramConn") {
+            self.check_udp(api_name)?;
+        }
+        self.check_host(host.0.as_ref(), host.1, api_name)
     }
 
-    fn check_read(&mut self, _path: &Path, _api_name: &str) -> Result<(), AnyError> {
-        Ok(())
+    fn check_read(&mut self, path: &Path, api_name: &str) -> Result<(), AnyError> {
+        self.check_read_path(path, api_name)
     }
 
-    fn check_write(&mut self, _path: &Path, _api_name: &str) -> Result<(), AnyError> {
-        Ok(())
+    // only called for unix sockets
+    fn check_write(&mut self, path: &Path, api_name: &str) -> Result<(), AnyError> {
+        if self.allow_unix_sockets {
+            return Ok(());
+        }
+        Err(self.deny(
+            api_name,
+            path.display().to_string(),
+            format!("{} is not allowed to use unix sockets", api_name),
+        ))
     }
 }
 
 impl deno_websocket::WebSocketPermissions for Permissions {
-    fn check_net_url(&mut self, _url: &Url, _api_name: &str) -> Result<(), AnyError> {
-        Ok(())
+    fn check_net_url(&mut self, url: &Url, api_name: &str) -> Result<(), AnyError> {
+        self.check_url(url, api_name)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use deno_core::error::get_custom_error_class;
+    use deno_net::NetPermissions;
+
+    // the answers saved by the prompt are reused without asking again
+    #[test]
+    fn test_permission_prompt_decisions() {
+        let path = std::env::temp_dir().join(format!("{}.json", uuid::Uuid::new_v4()));
+        std::fs::write(
+            &path,
+            r#"{ "net": { "example.com:443": true, "evil.com:443": false }, "env": { "API_KEY": true, "SECRET": false } }"#,
+        )
+        .unwrap();
+        let prompter = Arc::new(PermissionPrompter::load(&path).unwrap());
+        std::fs::remove_file(&path).unwrap();
+        let allowlist = vec!["*.internal:6379".to_string()];
+        let mut permissions = Permissions::user_worker(Some(&allowlist), false)
+            .unwrap()
+            .with_prompter(prompter);
+
+        assert!(permissions.check_env("API_KEY").is_ok());
+        let err = permissions.check_env("SECRET").unwrap_err();
+        assert_eq!(get_custom_error_class(&err), Some("PermissionDenied"));
+
+        assert!(permissions
+            .check_net(&("example.com", Some(443)), "Deno.connect()")
+            .is_ok());
+        assert!(permissions
+            .check_net(&("evil.com", Some(443)), "Deno.connect()")
+            .is_err());
+        // the allowlist doesn't need answers
+        assert!(permissions
+            .check_net(&("redis.internal", Some(6379)), "Deno.connect()")
+            .is_ok());
     }
 }
@@ -0,0 +1,14 @@
+use deno_core::op;
+use deno_core::OpState;
+use sb_worker_context::routes::RouteTable;
+
+// called by `EdgeRuntime.router` of the main worker with the pathname
+// patterns of its routes
+#[op]
+fn op_router_register(state: &mut OpState, patterns: Vec<String>) {
+    if let Some(routes) = state.try_borrow::<RouteTable>() {
+        routes.register(&patterns);
+    }
+}
+
+deno_core::extension!(sb_core_router, ops = [op_router_register]);
@@ -1,9 +1,27 @@
 use crate::permissions::Permissions;
 use anyhow::Context;
-use deno_core::error::AnyError;
+use deno_core::error::{type_error, AnyError};
 use deno_core::op;
 use deno_core::ModuleSpecifier;
 use deno_core::OpState;
+use serde::Serialize;
+
+// What a worker runs on, so functions can check for the capabilities they
+// rely on instead of failing halfway through a request.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RuntimeInfo {
+    pub version: String,
+    pub v8_version: String,
+    // the target triple the runtime was built for
+    pub target: String,
+    // the deno_core extensions the isolate is made of
+    pub extensions: Vec<String>,
+    // the runtime behaviours turned on for the worker
+    pub features: Vec<String>,
+    // the unstable APIs the worker can use
+    pub unstable: Vec<String>,
+}
 
 #[op]
 fn op_main_module(state: &mut OpState) -> Result<String, AnyError> {
@@ -22,8 +40,16 @@ fn op_main_module(state: &mut OpState) -> Result<String, AnyError> {
     Ok(main)
 }
 
+#[op]
+fn op_runtime_info(state: &mut OpState) -> Result<RuntimeInfo, AnyError> {
+    state
+        .try_borrow::<RuntimeInfo>()
+        .cloned()
+        .ok_or_else(|| type_error("the runtime info of the worker is not set"))
+}
+
 deno_core::extension!(sb_core_runtime,
-    ops = [op_main_module],
+    ops = [op_main_module, op_runtime_info],
     options = {
         main_module: Option<ModuleSpecifier>
     },
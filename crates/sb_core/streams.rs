@@ -0,0 +1,330 @@
+use bytes::Bytes;
+use deno_core::error::{custom_error, type_error, AnyError};
+use deno_core::futures::{Stream, StreamExt};
+use deno_core::op;
+use deno_core::{
+    AsyncRefCell, AsyncResult, BufView, CancelHandle, CancelTryFuture, OpState, RcRef, Resource,
+    ResourceId, ZeroCopyBuf,
+};
+use std::borrow::Cow;
+use std::cell::RefCell;
+use std::io;
+use std::pin::Pin;
+use std::rc::Rc;
+use std::task::{Context, Poll};
+use tokio::io::{AsyncRead, ReadBuf};
+use tokio::sync::mpsc;
+
+// Chunks between a stream's writer and its reader. The writer waits once
+// this many are unread, a side that doesn't keep up holds the other back.
+const STREAM_CHANNEL_SIZE: usize = 4;
+
+type Chunk = Result<Bytes, AnyError>;
+
+// A stream of bytes between the ops of an extension and JS, in either
+// direction, see `add_readable` and `take_writable`. The writing side can be
+// on any thread or runtime (eg: the storage's).
+pub fn byte_stream() -> (ByteStreamSender, ByteStream) {
+    let (tx, rx) = mpsc::channel(STREAM_CHANNEL_SIZE);
+    (
+        ByteStreamSender(tx),
+        ByteStream {
+            rx,
+            pending: Bytes::new(),
+        },
+    )
+}
+
+#[derive(Clone)]
+pub struct ByteStreamSender(mpsc::Sender<Chunk>);
+
+impl ByteStreamSender {
+    // waits until the chunk can be queued, fails once nothing reads the
+    // stream anymore (eg: JS cancelled it)
+    pub async fn send(&self, chunk: Bytes) -> Result<(), AnyError> {
+        // an empty read is the end of the stream
+        if chunk.is_empty() {
+            return Ok(());
+        }
+        self.0
+            .send(Ok(chunk))
+            .await
+            .map_err(|_| custom_error("Interrupted", "the stream is no longer read"))
+    }
+
+    // the reader fails with `err` once it read what was sent before it
+    pub async fn abort(self, err: AnyError) {
+        let _ = self.0.send(Err(err)).await;
+    }
+
+    // sends what `stream` yields until it ends, fails or the reader is gone,
+    // the stream is done once this returns
+    pub async fn pipe<S, E>(self, stream: S)
+    where
+        S: Stream<Item = Result<Bytes, E>>,
+        E: Into<AnyError>,
+    {
+        let mut stream = Box::pin(stream);
+        while let Some(chunk) = stream.next().await {
+            match chunk {
+                Ok(chunk) => {
+                    if self.send(chunk).await.is_err() {
+                        return;
+                    }
+                }
+                Err(err) => return self.abort(err.into()).await,
+            }
+        }
+    }
+}
+
+// The reading side of `byte_stream`, ends once every sender is dropped.
+pub struct ByteStream {
+    rx: mpsc::Receiver<Chunk>,
+    // the rest of a chunk read in parts
+    pending: Bytes,
+}
+
+impl ByteStream {
+    // up to `limit` bytes, `None` once the stream ended
+    pub async fn read(&mut self, limit: usize) -> Result<Option<Bytes>, AnyError> {
+        if self.pending.is_empty() {
+            match self.rx.recv().await {
+                Some(chunk) => self.pending = chunk?,
+                None => return Ok(None),
+            }
+        }
+        let len = limit.min(self.pending.len());
+        Ok(Some(self.pending.split_to(len)))
+    }
+}
+
+impl Stream for ByteStream {
+    type Item = Chunk;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Chunk>> {
+        if !self.pending.is_empty() {
+            return Poll::Ready(Some(Ok(std::mem::take(&mut self.pending))));
+        }
+        self.rx.poll_recv(cx)
+    }
+}
+
+// for the clients that upload from a reader
+impl AsyncRead for ByteStream {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        if self.pending.is_empty() {
+            match self.rx.poll_recv(cx) {
+                Poll::Ready(Some(Ok(chunk))) => self.pending = chunk,
+                Poll::Ready(Some(Err(err))) => {
+                    return Poll::Ready(Err(io::Error::new(io::ErrorKind::Other, err.to_string())))
+                }
+                Poll::Ready(None) => return Poll::Ready(Ok(())),
+                Poll::Pending => return Poll::Pending,
+            }
+        }
+        let len = buf.remaining().min(self.pending.len());
+        buf.put_slice(&self.pending.split_to(len));
+        Poll::Ready(Ok(()))
+    }
+}
+
+// A stream read from JS, `readableStreamFromRid` in js/streams.js.
+struct ReadableResource {
+    stream: AsyncRefCell<ByteStream>,
+    cancel: CancelHandle,
+}
+
+impl Resource for ReadableResource {
+    fn name(&self) -> Cow<str> {
+        "byteStream".into()
+    }
+
+    fn read(self: Rc<Self>, limit: usize) -> AsyncResult<BufView> {
+        Box::pin(async move {
+            let mut stream = RcRef::map(&self, |r| &r.stream).borrow_mut().await;
+            let cancel = RcRef::map(&self, |r| &r.cancel);
+            let chunk = stream.read(limit).try_or_cancel(cancel).await?;
+            Ok(chunk.map(BufView::from).unwrap_or_else(BufView::empty))
+        })
+    }
+
+    // dropping the stream lets its sender know
+    fn close(self: Rc<Self>) {
+        self.cancel.cancel();
+    }
+}
+
+// Gives `stream` to JS, the rid is turned into a `ReadableStream` with
+// `readableStreamFromRid`.
+pub fn add_readable(state: &mut OpState, stream: ByteStream) -> ResourceId {
+    state.resource_table.add(ReadableResource {
+        stream: AsyncRefCell::new(stream),
+        cancel: CancelHandle::default(),
+    })
+}
+
+// A stream written from JS, `writableRid` in js/streams.js. Closed once it's
+// both ended and taken by an op, whichever comes last.
+struct WritableResource {
+    // `None` once JS ended the stream
+    sender: RefCell<Option<ByteStreamSender>>,
+    // `None` once an op took it
+    stream: RefCell<Option<ByteStream>>,
+}
+
+impl Resource for WritableResource {
+    fn name(&self) -> Cow<str> {
+        "byteStreamSink".into()
+    }
+}
+
+// What JS writes to the stream `rid` (given to the op), an op can only take
+// a stream once.
+pub fn take_writable(state: &mut OpState, rid: ResourceId) -> Result<ByteStream, AnyError> {
+    let resource = state.resource_table.get::<WritableResource>(rid)?;
+    let stream = resource
+        .stream
+        .borrow_mut()
+        .take()
+        .ok_or_else(|| type_error("the stream is already read"))?;
+    if resource.sender.borrow().is_none() {
+        state.resource_table.close(rid)?;
+    }
+    Ok(stream)
+}
+
+#[op]
+fn op_stream_writable_new(state: &mut OpState) -> ResourceId {
+    let (sender, stream) = byte_stream();
+    state.resource_table.add(WritableResource {
+        sender: RefCell::new(Some(sender)),
+        stream: RefCell::new(Some(stream)),
+    })
+}
+
+#[op]
+async fn op_stream_write(
+    state: Rc<RefCell<OpState>>,
+    rid: ResourceId,
+    chunk: ZeroCopyBuf,
+) -> Result<(), AnyError> {
+    let resource = state.borrow().resource_table.get::<WritableResource>(rid)?;
+    let sender = resource
+        .sender
+        .borrow()
+        .clone()
+        .ok_or_else(|| type_error("the stream is already ended"))?;
+    // the chunk keeps the JS buffer alive rather than copying it
+    sender.send(Bytes::from(chunk)).await
+}
+
+// ends the stream `rid`, the op reading it fails with `error` if it's set
+#[op]
+async fn op_stream_write_end(
+    state: Rc<RefCell<OpState>>,
+    rid: ResourceId,
+    error: Option<String>,
+) -> Result<(), AnyError> {
+    let resource = state.borrow().resource_table.get::<WritableResource>(rid)?;
+    let sender = resource.sender.borrow_mut().take();
+    if resource.stream.borrow().is_none() {
+        state.borrow_mut().resource_table.close(rid)?;
+    }
+    if let (Some(sender), Some(error)) = (sender, error) {
+        sender.abort(custom_error("Interrupted", error)).await;
+    }
+    Ok(())
+}
+
+deno_core::extension!(
+    sb_core_streams,
+    ops = [op_stream_writable_new, op_stream_write, op_stream_write_end],
+    esm = ["js/streams.js"]
+);
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use deno_core::error::get_custom_error_class;
+    use deno_core::futures::stream;
+    use tokio::io::AsyncReadExt;
+
+    // reads smaller than the chunks take the rest of a chunk first
+    #[tokio::test]
+    async fn test_read_in_parts() {
+        let (sender, mut stream) = byte_stream();
+        tokio::spawn(async move {
+            for chunk in ["1,2", "", ",3"] {
+                sender.send(Bytes::from(chunk)).await.unwrap();
+            }
+        });
+
+        let mut read = vec![];
+        while let Some(chunk) = stream.read(2).await.unwrap() {
+            assert!(chunk.len() <= 2);
+            read.extend_from_slice(&chunk);
+        }
+        assert_eq!(read, b"1,2,3");
+    }
+
+    #[tokio::test]
+    async fn test_abort() {
+        let (sender, mut stream) = byte_stream();
+        sender.send(Bytes::from("a")).await.unwrap();
+        sender
+            .abort(custom_error("Interrupted", "upload failed"))
+            .await;
+
+        // what was sent before is read first
+        assert_eq!(stream.read(16).await.unwrap().unwrap(), "a");
+        let err = stream.read(16).await.unwrap_err();
+        assert_eq!(get_custom_error_class(&err), Some("Interrupted"));
+    }
+
+    #[tokio::test]
+    async fn test_reader_gone() {
+        let (sender, stream) = byte_stream();
+        drop(stream);
+        let err = sender.send(Bytes::from("a")).await.unwrap_err();
+        assert_eq!(get_custom_error_class(&err), Some("Interrupted"));
+    }
+
+    #[tokio::test]
+    async fn test_pipe() {
+        let (sender, reader) = byte_stream();
+        let chunks = stream::iter(vec![
+            Ok::<_, AnyError>(Bytes::from("ab")),
+            Ok(Bytes::from("c")),
+        ]);
+        tokio::spawn(sender.pipe(chunks));
+        let read: Vec<Bytes> = reader.map(|chunk| chunk.unwrap()).collect().await;
+        assert_eq!(read, ["ab", "c"]);
+
+        // the reader fails with the error of the stream, once it read the rest
+        let (sender, mut reader) = byte_stream();
+        let chunks = stream::iter(vec![Ok(Bytes::from("ab")), Err(type_error("bad chunk"))]);
+        tokio::spawn(sender.pipe(chunks));
+        assert_eq!(reader.read(16).await.unwrap().unwrap(), "ab");
+        let err = reader.read(16).await.unwrap_err();
+        assert_eq!(get_custom_error_class(&err), Some("TypeError"));
+    }
+
+    #[tokio::test]
+    async fn test_async_read() {
+        let (sender, mut stream) = byte_stream();
+        tokio::spawn(async move {
+            for chunk in ["abc", "def"] {
+                sender.send(Bytes::from(chunk)).await.unwrap();
+            }
+        });
+
+        let mut read = vec![];
+        stream.read_to_end(&mut read).await.unwrap();
+        assert_eq!(read, b"abcdef");
+    }
+}
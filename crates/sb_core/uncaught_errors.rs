@@ -0,0 +1,102 @@
+use crate::http_start::HttpBufPool;
+use deno_core::op;
+use deno_core::OpState;
+use sb_worker_context::events::{
+    UncaughtExceptionEvent, WorkerEventWithMetadata, WorkerEvents, WorkerEventsTx,
+};
+use sb_worker_context::worker_id::WorkerId;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum UncaughtErrorKind {
+    Exception,
+    UnhandledRejection,
+}
+
+// Forwards errors that escaped user code to the embedder, tagged with the
+// requests the worker was handling at the time. A worker handles several at
+// once, the error came from one of them (or from none, eg: a timer).
+#[derive(Debug, Clone)]
+pub struct UncaughtErrorReporter {
+    worker_id: WorkerId,
+    events_tx: Option<WorkerEventsTx>,
+    // slots of the requests in `HttpBufPool`
+    in_flight: Vec<u32>,
+}
+
+impl UncaughtErrorReporter {
+    pub fn new(worker_id: WorkerId, events_tx: Option<WorkerEventsTx>) -> Self {
+        Self {
+            worker_id,
+            events_tx,
+            in_flight: vec![],
+        }
+    }
+
+    pub fn report(
+        &self,
+        pool: &HttpBufPool,
+        kind: UncaughtErrorKind,
+        message: String,
+        stack: Option<String>,
+    ) {
+        let events_tx = match &self.events_tx {
+            Some(tx) => tx,
+            None => return,
+        };
+
+        let event = UncaughtExceptionEvent {
+            message,
+            stack,
+            request_ids: self
+                .in_flight
+                .iter()
+                .map(|slot| pool.request_id(*slot).to_string())
+                .collect(),
+        };
+
+        let _ = events_tx.send(WorkerEventWithMetadata {
+            worker_id: self.worker_id.clone(),
+            event: match kind {
+                UncaughtErrorKind::Exception => WorkerEvents::UncaughtException(event),
+                UncaughtErrorKind::UnhandledRejection => WorkerEvents::UnhandledRejection(event),
+            },
+        });
+    }
+}
+
+#[op]
+fn op_uncaught_errors_request_started(state: &mut OpState, slot: u32) {
+    if let Some(reporter) = state.try_borrow_mut::<UncaughtErrorReporter>() {
+        reporter.in_flight.push(slot);
+    }
+}
+
+#[op]
+fn op_uncaught_errors_request_finished(state: &mut OpState, slot: u32) {
+    if let Some(reporter) = state.try_borrow_mut::<UncaughtErrorReporter>() {
+        if let Some(i) = reporter.in_flight.iter().position(|s| *s == slot) {
+            reporter.in_flight.swap_remove(i);
+        }
+    }
+}
+
+#[op]
+fn op_report_unhandled_rejection(state: &mut OpState, message: String, stack: Option<String>) {
+    if let Some(reporter) = state.try_borrow::<UncaughtErrorReporter>() {
+        reporter.report(
+            state.borrow::<HttpBufPool>(),
+            UncaughtErrorKind::UnhandledRejection,
+            message,
+            stack,
+        );
+    }
+}
+
+deno_core::extension!(
+    sb_core_uncaught_errors,
+    ops = [
+        op_uncaught_errors_request_started,
+        op_uncaught_errors_request_finished,
+        op_report_unhandled_rejection
+    ]
+);
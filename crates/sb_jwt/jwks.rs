@@ -0,0 +1,128 @@
+use anyhow::{bail, Error};
+use jsonwebtoken::jwk::{Jwk, JwkSet};
+use log::warn;
+use once_cell::sync::Lazy;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+// how long a key set is used before it's fetched again
+const JWKS_TTL: Duration = Duration::from_secs(10 * 60);
+// a token signed by a key missing from the set makes it be fetched again, so
+// rotated keys are picked up, but not more often than this
+const JWKS_MIN_REFRESH: Duration = Duration::from_secs(30);
+const JWKS_FETCH_TIMEOUT: Duration = Duration::from_secs(10);
+
+// The key sets are fetched by this runtime, so a fetch outlives the isolate
+// waiting for it and the other workers of the url can use the result.
+static JWT_RUNTIME: Lazy<tokio::runtime::Runtime> = Lazy::new(|| {
+    tokio::runtime::Builder::new_multi_thread()
+        .worker_threads(1)
+        .thread_name("sb-jwt")
+        .enable_all()
+        .build()
+        .unwrap()
+});
+
+static JWKS_CACHE: Lazy<JwksCache> = Lazy::new(|| JwksCache::new(JWKS_TTL, JWKS_MIN_REFRESH));
+
+pub(crate) fn jwks_cache() -> &'static JwksCache {
+    &JWKS_CACHE
+}
+
+struct CachedJwks {
+    keys: JwkSet,
+    fetched_at: Instant,
+}
+
+// The key sets of the JWKS urls tokens were verified with, shared by every
+// worker rather than fetched on each invocation.
+pub struct JwksCache {
+    ttl: Duration,
+    min_refresh: Duration,
+    client: reqwest::Client,
+    // by url, locked while the set is fetched so it's only fetched once
+    entries: Mutex<HashMap<String, Arc<tokio::sync::Mutex<Option<CachedJwks>>>>>,
+}
+
+// the key of the token, or the only one of the set when the token doesn't
+// say which one it was signed with
+fn find_key(keys: &JwkSet, kid: Option<&str>) -> Option<Jwk> {
+    match kid {
+        Some(kid) => keys.find(kid).cloned(),
+        None if keys.keys.len() == 1 => keys.keys.first().cloned(),
+        None => None,
+    }
+}
+
+impl JwksCache {
+    pub fn new(ttl: Duration, min_refresh: Duration) -> Self {
+        Self {
+            ttl,
+            min_refresh,
+            client: reqwest::Client::builder()
+                .timeout(JWKS_FETCH_TIMEOUT)
+                .build()
+                .unwrap(),
+            entries: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub async fn get(&self, url: &str, kid: Option<&str>) -> Result<Jwk, Error> {
+        let entry = self
+            .entries
+            .lock()
+            .unwrap()
+            .entry(url.to_string())
+            .or_default()
+            .clone();
+        let mut cached = entry.lock().await;
+
+        let now = Instant::now();
+        let (stale, missing, may_refresh) = match cached.as_ref() {
+            Some(cached) => {
+                let age = now.saturating_duration_since(cached.fetched_at);
+                (
+                    age >= self.ttl,
+                    find_key(&cached.keys, kid).is_none(),
+                    age >= self.min_refresh,
+                )
+            }
+            None => (true, true, true),
+        };
+        if stale || (missing && may_refresh) {
+            match self.fetch(url).await {
+                Ok(keys) => {
+                    *cached = Some(CachedJwks {
+                        keys,
+                        fetched_at: now,
+                    })
+                }
+                Err(err) if cached.is_none() => return Err(err),
+                // the keys we have are still used while the server is down
+                Err(err) => warn!("failed to refresh the JWKS of {}: {}", url, err),
+            }
+        }
+
+        let keys = &cached.as_ref().unwrap().keys;
+        match find_key(keys, kid) {
+            Some(key) => Ok(key),
+            None => bail!(
+                "no key of {} matches the token (kid: {})",
+                url,
+                kid.unwrap_or("none")
+            ),
+        }
+    }
+
+    async fn fetch(&self, url: &str) -> Result<JwkSet, Error> {
+        let client = self.client.clone();
+        let url = url.to_string();
+        JWT_RUNTIME
+            .spawn(async move {
+                let res = client.get(&url).send().await?.error_for_status()?;
+                Ok(res.json::<JwkSet>().await?)
+            })
+            .await?
+    }
+}
@@ -0,0 +1,52 @@
+import { registerEdgeRuntimeApi } from "ext:sb_core_edge_runtime_apis/js/edge_runtime_apis.js";
+
+const core = globalThis.Deno.core;
+const ops = core.ops;
+
+// interface VerifyOptions {
+//     jwksUrl?: string; // keys of the issuer, the server's keys if unset
+//     issuer?: string | string[];
+//     audience?: string | string[];
+//     algorithms?: string[];
+//     leewaySecs?: number; // clock skew tolerated on exp and nbf, 60 by default
+// }
+
+function strings(value) {
+  if (value === undefined || value === null) {
+    return [];
+  }
+  return Array.isArray(value) ? value : [value];
+}
+
+// signed with one of the server's keys, which the worker never sees, eg:
+// `EdgeRuntime.jwt.sign({ sub: user.id }, { expiresInSecs: 3600 })`
+function sign(claims, options = {}) {
+  if (typeof claims !== "object" || claims === null || Array.isArray(claims)) {
+    throw new TypeError("The claims of a token must be an object.");
+  }
+  return ops.op_jwt_sign(claims, {
+    kid: options.kid ?? null,
+    expiresInSecs: options.expiresInSecs ?? null,
+  });
+}
+
+// resolves with the { header, payload } of a valid token, rejects with an
+// InvalidData error otherwise. The key sets are cached by the server.
+function verify(token, options = {}) {
+  if (typeof token !== "string") {
+    throw new TypeError("A token must be a string.");
+  }
+  return core.opAsync("op_jwt_verify", token, {
+    jwksUrl: options.jwksUrl ?? null,
+    issuer: strings(options.issuer),
+    audience: strings(options.audience),
+    algorithms: strings(options.algorithms),
+    leewaySecs: options.leewaySecs ?? null,
+  });
+}
+
+const SUPABASE_JWT = { sign, verify };
+
+registerEdgeRuntimeApi("jwt", SUPABASE_JWT);
+
+export { SUPABASE_JWT };
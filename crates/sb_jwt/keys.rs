@@ -0,0 +1,136 @@
+use anyhow::{anyhow, bail, Context, Error};
+use jsonwebtoken::{Algorithm, DecodingKey, EncodingKey};
+use once_cell::sync::OnceCell;
+use ring::signature::{
+    EcdsaKeyPair, Ed25519KeyPair, KeyPair, RsaKeyPair, ECDSA_P256_SHA256_FIXED_SIGNING,
+    ECDSA_P384_SHA384_FIXED_SIGNING,
+};
+use std::path::PathBuf;
+use std::str::FromStr;
+
+static HOST_KEYS: OnceCell<Vec<HostKey>> = OnceCell::new();
+
+// `KID:ALG:PATH`, eg: `main:ES256:./jwt.pem`. The file has the PEM encoded
+// private key, or the secret of the HS algorithms.
+#[derive(Debug, Clone, PartialEq)]
+pub struct JwtKeyOpts {
+    pub kid: String,
+    pub algorithm: Algorithm,
+    pub path: PathBuf,
+}
+
+impl FromStr for JwtKeyOpts {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Error> {
+        let mut parts = s.splitn(3, ':');
+        let (Some(kid), Some(algorithm), Some(path)) = (parts.next(), parts.next(), parts.next())
+        else {
+            bail!("invalid jwt key {:?}, expected KID:ALG:PATH", s);
+        };
+        if kid.is_empty() {
+            bail!("the jwt key {:?} has no id", s);
+        }
+        Ok(Self {
+            kid: kid.to_string(),
+            algorithm: Algorithm::from_str(algorithm)
+                .map_err(|_| anyhow!("unsupported jwt algorithm {:?}", algorithm))?,
+            path: PathBuf::from(path),
+        })
+    }
+}
+
+// A key the runtime holds, so workers can sign tokens without being given
+// the key material, and verify the tokens signed with it.
+pub(crate) struct HostKey {
+    pub kid: String,
+    pub algorithm: Algorithm,
+    pub encoding: EncodingKey,
+    pub decoding: DecodingKey,
+}
+
+// the public key of a private one, in the form jsonwebtoken verifies with
+fn public_key(algorithm: Algorithm, pem: &[u8]) -> Result<DecodingKey, Error> {
+    let pem = pem::parse(pem)?;
+    let der = pem.contents.as_slice();
+    let invalid = |_| anyhow!("invalid {:?} private key", algorithm);
+    Ok(match algorithm {
+        Algorithm::EdDSA => {
+            let pair = Ed25519KeyPair::from_pkcs8_maybe_unchecked(der).map_err(invalid)?;
+            DecodingKey::from_ed_der(pair.public_key().as_ref())
+        }
+        Algorithm::ES256 | Algorithm::ES384 => {
+            let signing = match algorithm {
+                Algorithm::ES256 => &ECDSA_P256_SHA256_FIXED_SIGNING,
+                _ => &ECDSA_P384_SHA384_FIXED_SIGNING,
+            };
+            let pair = EcdsaKeyPair::from_pkcs8(signing, der).map_err(invalid)?;
+            DecodingKey::from_ec_der(pair.public_key().as_ref())
+        }
+        _ => {
+            // PKCS#1 (`RSA PRIVATE KEY`) or PKCS#8
+            let pair = match pem.tag.as_str() {
+                "RSA PRIVATE KEY" => RsaKeyPair::from_der(der),
+                _ => RsaKeyPair::from_pkcs8(der),
+            }
+            .map_err(invalid)?;
+            DecodingKey::from_rsa_der(pair.public_key().as_ref())
+        }
+    })
+}
+
+fn load_key(opts: &JwtKeyOpts) -> Result<HostKey, Error> {
+    let bytes = std::fs::read(&opts.path)
+        .with_context(|| format!("failed to read the jwt key {}", opts.path.display()))?;
+    let (encoding, decoding) = match opts.algorithm {
+        Algorithm::HS256 | Algorithm::HS384 | Algorithm::HS512 => {
+            let secret = String::from_utf8_lossy(&bytes).trim_end().to_string();
+            (
+                EncodingKey::from_secret(secret.as_bytes()),
+                DecodingKey::from_secret(secret.as_bytes()),
+            )
+        }
+        Algorithm::EdDSA => (
+            EncodingKey::from_ed_pem(&bytes)?,
+            public_key(opts.algorithm, &bytes)?,
+        ),
+        Algorithm::ES256 | Algorithm::ES384 => (
+            EncodingKey::from_ec_pem(&bytes)?,
+            public_key(opts.algorithm, &bytes)?,
+        ),
+        _ => (
+            EncodingKey::from_rsa_pem(&bytes)?,
+            public_key(opts.algorithm, &bytes)?,
+        ),
+    };
+    Ok(HostKey {
+        kid: opts.kid.clone(),
+        algorithm: opts.algorithm,
+        encoding,
+        decoding,
+    })
+}
+
+// Lets workers sign tokens with `EdgeRuntime.jwt.sign`. Must be called before
+// the first worker is created.
+pub fn init_jwt_keys(keys: Vec<JwtKeyOpts>) -> Result<(), Error> {
+    let keys = keys.iter().map(load_key).collect::<Result<Vec<_>, _>>()?;
+    if HOST_KEYS.set(keys).is_err() {
+        bail!("the jwt keys are already configured");
+    }
+    Ok(())
+}
+
+pub fn jwt_keys_configured() -> bool {
+    HOST_KEYS.get().map_or(false, |keys| !keys.is_empty())
+}
+
+// the key with this id, or the only one when none is asked for
+pub(crate) fn host_key(kid: Option<&str>) -> Option<&'static HostKey> {
+    let keys = HOST_KEYS.get()?;
+    match kid {
+        Some(kid) => keys.iter().find(|key| key.kid == kid),
+        None if keys.len() == 1 => keys.first(),
+        None => None,
+    }
+}
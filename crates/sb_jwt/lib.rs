@@ -0,0 +1,181 @@
+pub mod jwks;
+pub mod keys;
+
+use deno_core::error::{custom_error, type_error, AnyError};
+use deno_core::op;
+use deno_core::serde_json::{Map, Value};
+use deno_core::url::Url;
+use deno_core::OpState;
+use deno_fetch::FetchPermissions;
+use jsonwebtoken::{decode, decode_header, encode, Algorithm, DecodingKey, Header, Validation};
+use jwks::jwks_cache;
+use keys::host_key;
+use sb_core::permissions::Permissions;
+use serde::{Deserialize, Serialize};
+use std::cell::RefCell;
+use std::rc::Rc;
+use std::str::FromStr;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+// seconds of clock skew tolerated on exp and nbf by default
+const DEFAULT_LEEWAY_SECS: u64 = 60;
+
+deno_core::extension!(sb_jwt, ops = [op_jwt_sign, op_jwt_verify], esm = ["jwt.js"]);
+
+// The host-held keys a worker can sign with, `EdgeRuntime.jwt.sign` throws
+// without it. Any worker can verify tokens.
+pub struct JwtWorkerState {
+    // key ids, all of them if unset
+    sign_keys: Option<Vec<String>>,
+}
+
+impl JwtWorkerState {
+    pub fn new(sign_keys: Vec<String>) -> Self {
+        Self {
+            sign_keys: Some(sign_keys),
+        }
+    }
+
+    // the main worker can sign with every key
+    pub fn unrestricted() -> Self {
+        Self { sign_keys: None }
+    }
+}
+
+// invalid, expired or badly signed tokens
+fn invalid_token(err: impl std::fmt::Display) -> AnyError {
+    custom_error("InvalidData", format!("invalid token: {}", err))
+}
+
+fn now_secs() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+#[derive(Deserialize, Debug, Default)]
+#[serde(rename_all = "camelCase", default)]
+pub struct SignOpts {
+    // the only host key if unset
+    kid: Option<String>,
+    // sets `exp` when the claims don't
+    expires_in_secs: Option<u64>,
+}
+
+#[op]
+fn op_jwt_sign(
+    state: &mut OpState,
+    claims: Map<String, Value>,
+    opts: SignOpts,
+) -> Result<String, AnyError> {
+    let Some(key) = host_key(opts.kid.as_deref()) else {
+        return Err(match opts.kid {
+            Some(kid) => type_error(format!("there is no jwt key {:?} on this server", kid)),
+            None => custom_error(
+                "NotSupported",
+                "signing tokens needs a jwt key on this server, or the kid of one of them",
+            ),
+        });
+    };
+    let Some(worker) = state.try_borrow::<JwtWorkerState>() else {
+        return Err(custom_error(
+            "PermissionDenied",
+            "signing tokens is not enabled for this worker, create it with the `jwt` option",
+        ));
+    };
+    if let Some(sign_keys) = &worker.sign_keys {
+        if !sign_keys.contains(&key.kid) {
+            return Err(custom_error(
+                "PermissionDenied",
+                format!("the worker can't sign tokens with the key {:?}", key.kid),
+            ));
+        }
+    }
+
+    let mut claims = claims;
+    let now = now_secs();
+    claims.entry("iat").or_insert_with(|| now.into());
+    if let Some(expires_in) = opts.expires_in_secs {
+        claims
+            .entry("exp")
+            .or_insert_with(|| (now + expires_in).into());
+    }
+
+    let mut header = Header::new(key.algorithm);
+    header.kid = Some(key.kid.clone());
+    encode(&header, &claims, &key.encoding).map_err(|err| type_error(err.to_string()))
+}
+
+#[derive(Deserialize, Debug, Default)]
+#[serde(rename_all = "camelCase", default)]
+pub struct VerifyOpts {
+    // the host keys are used if unset
+    jwks_url: Option<String>,
+    issuer: Vec<String>,
+    audience: Vec<String>,
+    // the one of the key (or the token's if the key doesn't say) if empty
+    algorithms: Vec<String>,
+    leeway_secs: Option<u64>,
+}
+
+#[derive(Serialize)]
+pub struct VerifiedToken {
+    header: Header,
+    payload: Value,
+}
+
+#[op]
+async fn op_jwt_verify(
+    state: Rc<RefCell<OpState>>,
+    token: String,
+    opts: VerifyOpts,
+) -> Result<VerifiedToken, AnyError> {
+    let header = decode_header(&token).map_err(invalid_token)?;
+
+    let (key, key_algorithm): (DecodingKey, Algorithm) = match &opts.jwks_url {
+        Some(url) => {
+            let url = Url::parse(url)?;
+            // the keys are fetched on the worker's behalf
+            state
+                .borrow_mut()
+                .borrow_mut::<Permissions>()
+                .check_net_url(&url, "EdgeRuntime.jwt.verify()")?;
+            let jwk = jwks_cache()
+                .get(url.as_str(), header.kid.as_deref())
+                .await
+                .map_err(invalid_token)?;
+            let key = DecodingKey::from_jwk(&jwk).map_err(invalid_token)?;
+            (key, jwk.common.algorithm.unwrap_or(header.alg))
+        }
+        None => {
+            let Some(key) = host_key(header.kid.as_deref()) else {
+                return Err(invalid_token("it wasn't signed with a key of this server"));
+            };
+            (key.decoding.clone(), key.algorithm)
+        }
+    };
+
+    let mut validation = Validation::new(key_algorithm);
+    if !opts.algorithms.is_empty() {
+        validation.algorithms = opts
+            .algorithms
+            .iter()
+            .map(|alg| Algorithm::from_str(alg))
+            .collect::<Result<_, _>>()
+            .map_err(|_| type_error("unsupported algorithm"))?;
+    }
+    if !opts.issuer.is_empty() {
+        validation.set_issuer(&opts.issuer);
+    }
+    if !opts.audience.is_empty() {
+        validation.set_audience(&opts.audience);
+    }
+    validation.leeway = opts.leeway_secs.unwrap_or(DEFAULT_LEEWAY_SECS);
+
+    let data = decode::<Value>(&token, &key, &validation).map_err(invalid_token)?;
+    Ok(VerifiedToken {
+        header: data.header,
+        payload: data.claims,
+    })
+}
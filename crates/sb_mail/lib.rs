@@ -0,0 +1,117 @@
+pub mod mailer;
+
+use deno_core::error::{custom_error, type_error, AnyError};
+use deno_core::op;
+use deno_core::OpState;
+use lettre::message::{Mailbox, MultiPart};
+use lettre::Message;
+use serde::Deserialize;
+use std::cell::RefCell;
+use std::rc::Rc;
+use std::time::Instant;
+
+// recipients of a single email, to, cc and bcc together
+const MAX_RECIPIENTS: usize = 50;
+
+deno_core::extension!(sb_mail, ops = [op_send_email], esm = ["mail.js"]);
+
+// The service a worker's emails count against.
+pub struct MailWorkerState {
+    service: String,
+}
+
+impl MailWorkerState {
+    pub fn new(service: String) -> Self {
+        Self { service }
+    }
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct SendEmailArgs {
+    to: Vec<String>,
+    #[serde(default)]
+    cc: Vec<String>,
+    #[serde(default)]
+    bcc: Vec<String>,
+    reply_to: Option<String>,
+    subject: String,
+    text: Option<String>,
+    html: Option<String>,
+}
+
+fn parse_mailbox(address: &str) -> Result<Mailbox, AnyError> {
+    address
+        .parse()
+        .map_err(|_| type_error(format!("invalid email address {:?}", address)))
+}
+
+fn build_message(from: &Mailbox, args: SendEmailArgs) -> Result<Message, AnyError> {
+    if args.to.is_empty() {
+        return Err(type_error("an email needs at least one recipient"));
+    }
+    if args.to.len() + args.cc.len() + args.bcc.len() > MAX_RECIPIENTS {
+        return Err(type_error(format!(
+            "an email can't have more than {} recipients",
+            MAX_RECIPIENTS
+        )));
+    }
+
+    let mut builder = Message::builder().from(from.clone()).subject(args.subject);
+    for address in &args.to {
+        builder = builder.to(parse_mailbox(address)?);
+    }
+    for address in &args.cc {
+        builder = builder.cc(parse_mailbox(address)?);
+    }
+    for address in &args.bcc {
+        builder = builder.bcc(parse_mailbox(address)?);
+    }
+    if let Some(address) = &args.reply_to {
+        builder = builder.reply_to(parse_mailbox(address)?);
+    }
+
+    let message = match (args.text, args.html) {
+        (Some(text), Some(html)) => {
+            builder.multipart(MultiPart::alternative_plain_html(text, html))
+        }
+        (None, Some(html)) => builder
+            .header(lettre::message::header::ContentType::TEXT_HTML)
+            .body(html),
+        (Some(text), None) => builder.body(text),
+        (None, None) => return Err(type_error("an email needs a text or html body")),
+    };
+    message.map_err(|err| type_error(err.to_string()))
+}
+
+#[op]
+async fn op_send_email(state: Rc<RefCell<OpState>>, args: SendEmailArgs) -> Result<(), AnyError> {
+    let Some(mailer) = mailer::mailer() else {
+        return Err(custom_error(
+            "NotSupported",
+            "sending emails isn't configured on this server",
+        ));
+    };
+    let service = state
+        .borrow()
+        .try_borrow::<MailWorkerState>()
+        .map(|state| state.service.clone())
+        .unwrap_or_default();
+
+    let message = build_message(mailer.sender(), args)?;
+
+    // see `RateLimitError` in bootstrap.js
+    if let Err(rate_limited) = mailer.check_rate_limit(&service, Instant::now()) {
+        return Err(custom_error(
+            "RateLimitError",
+            deno_core::serde_json::json!({
+                "message": rate_limited.to_string(),
+                "retryAfterMs": rate_limited.retry_after_ms,
+            })
+            .to_string(),
+        ));
+    }
+
+    mailer.send(message).await?;
+    Ok(())
+}
@@ -0,0 +1,44 @@
+import { registerEdgeRuntimeApi } from "ext:sb_core_edge_runtime_apis/js/edge_runtime_apis.js";
+
+const core = globalThis.Deno.core;
+
+// interface Email {
+//     to: string | string[];
+//     cc?: string | string[];
+//     bcc?: string | string[];
+//     replyTo?: string;
+//     subject: string;
+//     text?: string;
+//     html?: string;
+// }
+
+function addresses(value) {
+  if (value === undefined || value === null) {
+    return [];
+  }
+  return Array.isArray(value) ? value : [value];
+}
+
+// sent from the address configured on the server, throws a RateLimitError
+// when the service sent too many emails
+function sendEmail(email) {
+  if (typeof email?.subject !== "string") {
+    throw new TypeError("An email needs a subject.");
+  }
+
+  return core.opAsync("op_send_email", {
+    to: addresses(email.to),
+    cc: addresses(email.cc),
+    bcc: addresses(email.bcc),
+    replyTo: email.replyTo ?? null,
+    subject: email.subject,
+    text: email.text ?? null,
+    html: email.html ?? null,
+  });
+}
+
+const SUPABASE_MAIL = { sendEmail };
+
+registerEdgeRuntimeApi("mail", SUPABASE_MAIL);
+
+export { SUPABASE_MAIL };
@@ -0,0 +1,97 @@
+pub mod pool;
+pub mod types;
+
+use deno_core::error::{type_error, AnyError};
+use deno_core::op;
+use deno_core::serde_json::{Map, Value};
+use deno_core::OpState;
+use serde::Serialize;
+use std::cell::RefCell;
+use std::rc::Rc;
+use std::sync::Arc;
+use tokio::sync::Semaphore;
+use tokio_postgres::types::ToSql;
+use types::{row_to_json, SqlParam};
+
+deno_core::extension!(sb_postgres, ops = [op_pg_query], esm = ["postgres.js"]);
+
+// Postgres access of a worker, queries fail without it.
+pub struct PgWorkerState {
+    // workers of the same service share their pools
+    service: String,
+    pool_size: usize,
+    // connections the worker can have checked out at once
+    checkouts: Arc<Semaphore>,
+}
+
+impl PgWorkerState {
+    pub fn new(service: String, pool_size: usize, max_checkouts: usize) -> Self {
+        Self {
+            service,
+            pool_size,
+            checkouts: Arc::new(Semaphore::new(max_checkouts.max(1))),
+        }
+    }
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PgQueryResult {
+    rows: Vec<Map<String, Value>>,
+    row_count: u64,
+}
+
+#[op]
+async fn op_pg_query(
+    state: Rc<RefCell<OpState>>,
+    connection_string: String,
+    sql: String,
+    params: Vec<Value>,
+) -> Result<PgQueryResult, AnyError> {
+    let (pool, checkouts) = {
+        let state = state.borrow();
+        let Some(pg) = state.try_borrow::<PgWorkerState>() else {
+            return Err(type_error(
+                "postgres is not enabled for this worker, create it with the `postgres` option",
+            ));
+        };
+        let pool = pool::service_pool(&pg.service, &connection_string, pg.pool_size)?;
+        (pool, pg.checkouts.clone())
+    };
+
+    let _checkout = checkouts.acquire_owned().await?;
+    let client = pool.checkout().await?;
+
+    let statement = client.prepare(&sql).await?;
+    if statement.params().len() != params.len() {
+        return Err(type_error(format!(
+            "expected {} parameters, got {}",
+            statement.params().len(),
+            params.len()
+        )));
+    }
+    let params: Vec<SqlParam> = params.into_iter().map(SqlParam).collect();
+    let params: Vec<&(dyn ToSql + Sync)> =
+        params.iter().map(|p| p as &(dyn ToSql + Sync)).collect();
+
+    // statements without a result (eg: an insert without `returning`) only
+    // report the number of rows they changed
+    if statement.columns().is_empty() {
+        let row_count = client.execute(&statement, &params).await?;
+        return Ok(PgQueryResult {
+            rows: vec![],
+            row_count,
+        });
+    }
+
+    let rows = client
+        .query(&statement, &params)
+        .await?
+        .iter()
+        .map(row_to_json)
+        .collect::<Result<Vec<_>, _>>()?;
+    Ok(PgQueryResult {
+        row_count: rows.len() as u64,
+        rows,
+    })
+}
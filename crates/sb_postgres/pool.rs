@@ -0,0 +1,129 @@
+use deno_core::error::AnyError;
+use deno_tls::rustls::ClientConfig;
+use log::debug;
+use once_cell::sync::Lazy;
+use std::collections::HashMap;
+use std::ops::Deref;
+use std::sync::{Arc, Mutex};
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+use tokio_postgres::{Client, Config};
+use tokio_postgres_rustls::MakeRustlsConnect;
+
+// Isolates run on runtimes of their own and stop with them, the connections
+// are driven by this one so they outlive the workers that opened them.
+static PG_RUNTIME: Lazy<tokio::runtime::Runtime> = Lazy::new(|| {
+    tokio::runtime::Builder::new_multi_thread()
+        .worker_threads(1)
+        .thread_name("sb-postgres")
+        .enable_all()
+        .build()
+        .unwrap()
+});
+
+static PG_TLS: Lazy<MakeRustlsConnect> = Lazy::new(|| {
+    let config = ClientConfig::builder()
+        .with_safe_defaults()
+        .with_root_certificates(deno_tls::create_default_root_cert_store())
+        .with_no_client_auth();
+    MakeRustlsConnect::new(config)
+});
+
+// pools by service and connection string
+static PG_POOLS: Lazy<Mutex<HashMap<(String, String), Arc<PgPool>>>> = Lazy::new(Default::default);
+
+// The pool of a service's database, created by the first of its workers to
+// query it. `size` is the number of connections it keeps open at most.
+pub fn service_pool(
+    service: &str,
+    connection_string: &str,
+    size: usize,
+) -> Result<Arc<PgPool>, AnyError> {
+    let mut pools = PG_POOLS.lock().unwrap();
+    let key = (service.to_string(), connection_string.to_string());
+    if let Some(pool) = pools.get(&key) {
+        return Ok(pool.clone());
+    }
+
+    let pool = Arc::new(PgPool::new(connection_string.parse()?, size));
+    pools.insert(key, pool.clone());
+    Ok(pool)
+}
+
+pub struct PgPool {
+    config: Config,
+    idle: Mutex<Vec<Client>>,
+    slots: Arc<Semaphore>,
+}
+
+impl PgPool {
+    fn new(config: Config, size: usize) -> Self {
+        Self {
+            config,
+            idle: Mutex::new(vec![]),
+            slots: Arc::new(Semaphore::new(size.max(1))),
+        }
+    }
+
+    // waits for a connection of the pool to be free, or opens a new one while
+    // the pool isn't full
+    pub async fn checkout(self: &Arc<Self>) -> Result<PooledClient, AnyError> {
+        let permit = self.slots.clone().acquire_owned().await?;
+
+        let idle = {
+            let mut idle = self.idle.lock().unwrap();
+            idle.retain(|client| !client.is_closed());
+            idle.pop()
+        };
+        let client = match idle {
+            Some(client) => client,
+            None => self.connect().await?,
+        };
+
+        Ok(PooledClient {
+            client: Some(client),
+            pool: self.clone(),
+            _permit: permit,
+        })
+    }
+
+    async fn connect(&self) -> Result<Client, AnyError> {
+        let config = self.config.clone();
+        let client = PG_RUNTIME
+            .spawn(async move {
+                let (client, connection) = config.connect(PG_TLS.clone()).await?;
+                tokio::spawn(async move {
+                    if let Err(err) = connection.await {
+                        debug!("postgres connection closed: {}", err);
+                    }
+                });
+                Ok::<_, tokio_postgres::Error>(client)
+            })
+            .await??;
+        Ok(client)
+    }
+}
+
+// A connection checked out of a pool, it goes back to the pool once dropped.
+pub struct PooledClient {
+    client: Option<Client>,
+    pool: Arc<PgPool>,
+    _permit: OwnedSemaphorePermit,
+}
+
+impl Deref for PooledClient {
+    type Target = Client;
+
+    fn deref(&self) -> &Client {
+        self.client.as_ref().unwrap()
+    }
+}
+
+impl Drop for PooledClient {
+    fn drop(&mut self) {
+        if let Some(client) = self.client.take() {
+            if !client.is_closed() {
+                self.pool.idle.lock().unwrap().push(client);
+            }
+        }
+    }
+}
@@ -0,0 +1,33 @@
+import { registerEdgeRuntimeApi } from "ext:sb_core_edge_runtime_apis/js/edge_runtime_apis.js";
+
+const core = globalThis.Deno.core;
+
+class PostgresClient {
+  #connectionString;
+
+  constructor(connectionString) {
+    this.#connectionString = connectionString;
+  }
+
+  // resolves to { rows, rowCount }, parameters are passed as $1, $2...
+  query(sql, params = []) {
+    if (typeof sql !== "string") {
+      throw new TypeError("The query must be a string.");
+    }
+    return core.opAsync("op_pg_query", this.#connectionString, sql, params);
+  }
+}
+
+const SUPABASE_POSTGRES = {
+  // connections are pooled by the runtime, there's nothing to close
+  connect(connectionString) {
+    if (typeof connectionString !== "string") {
+      throw new TypeError("A connection string must be provided.");
+    }
+    return new PostgresClient(connectionString);
+  },
+};
+
+registerEdgeRuntimeApi("postgres", SUPABASE_POSTGRES);
+
+export { SUPABASE_POSTGRES };
@@ -0,0 +1,87 @@
+use bytes::BytesMut;
+use deno_core::error::{type_error, AnyError};
+use deno_core::serde_json::{Map, Number, Value};
+use std::error::Error;
+use tokio_postgres::types::{to_sql_checked, IsNull, Json, ToSql, Type};
+use tokio_postgres::Row;
+use uuid::Uuid;
+
+type BoxError = Box<dyn Error + Sync + Send>;
+
+// A query parameter passed from JS, encoded for the type postgres infers for
+// it. Other types can be passed as text and cast in the query (eg: `$1::text::date`).
+#[derive(Debug)]
+pub struct SqlParam(pub Value);
+
+impl ToSql for SqlParam {
+    fn to_sql(&self, ty: &Type, out: &mut BytesMut) -> Result<IsNull, BoxError> {
+        let value = &self.0;
+        if value.is_null() {
+            return Ok(IsNull::Yes);
+        }
+
+        match *ty {
+            Type::BOOL => expect(value.as_bool(), ty)?.to_sql(ty, out),
+            Type::INT2 => i16::try_from(expect(value.as_i64(), ty)?)?.to_sql(ty, out),
+            Type::INT4 => i32::try_from(expect(value.as_i64(), ty)?)?.to_sql(ty, out),
+            Type::INT8 => expect(value.as_i64(), ty)?.to_sql(ty, out),
+            Type::FLOAT4 => (expect(value.as_f64(), ty)? as f32).to_sql(ty, out),
+            Type::FLOAT8 => expect(value.as_f64(), ty)?.to_sql(ty, out),
+            Type::JSON | Type::JSONB => Json(value).to_sql(ty, out),
+            Type::UUID => Uuid::parse_str(expect(value.as_str(), ty)?)?.to_sql(ty, out),
+            Type::TEXT | Type::VARCHAR | Type::BPCHAR | Type::NAME | Type::UNKNOWN => match value {
+                Value::String(s) => s.to_sql(ty, out),
+                value => value.to_string().to_sql(ty, out),
+            },
+            _ => Err(format!("unsupported parameter type {}, cast it to text", ty).into()),
+        }
+    }
+
+    fn accepts(_ty: &Type) -> bool {
+        true
+    }
+
+    to_sql_checked!();
+}
+
+fn expect<T>(value: Option<T>, ty: &Type) -> Result<T, BoxError> {
+    value.ok_or_else(|| format!("invalid value for a parameter of type {}", ty).into())
+}
+
+// the row as an object keyed by column name
+pub fn row_to_json(row: &Row) -> Result<Map<String, Value>, AnyError> {
+    let mut object = Map::new();
+    for (idx, column) in row.columns().iter().enumerate() {
+        let ty = column.type_();
+        let value = match *ty {
+            Type::BOOL => row.try_get::<_, Option<bool>>(idx)?.map(Value::Bool),
+            Type::INT2 => row.try_get::<_, Option<i16>>(idx)?.map(Value::from),
+            Type::INT4 => row.try_get::<_, Option<i32>>(idx)?.map(Value::from),
+            Type::INT8 => row.try_get::<_, Option<i64>>(idx)?.map(Value::from),
+            Type::FLOAT4 => row
+                .try_get::<_, Option<f32>>(idx)?
+                .and_then(|f| Number::from_f64(f as f64))
+                .map(Value::Number),
+            Type::FLOAT8 => row
+                .try_get::<_, Option<f64>>(idx)?
+                .and_then(Number::from_f64)
+                .map(Value::Number),
+            Type::JSON | Type::JSONB => row.try_get::<_, Option<Json<Value>>>(idx)?.map(|j| j.0),
+            Type::UUID => row
+                .try_get::<_, Option<Uuid>>(idx)?
+                .map(|uuid| Value::String(uuid.to_string())),
+            Type::TEXT | Type::VARCHAR | Type::BPCHAR | Type::NAME | Type::UNKNOWN => {
+                row.try_get::<_, Option<String>>(idx)?.map(Value::String)
+            }
+            _ => {
+                return Err(type_error(format!(
+                    "column {} has the unsupported type {}, cast it to text",
+                    column.name(),
+                    ty
+                )))
+            }
+        };
+        object.insert(column.name().to_string(), value.unwrap_or(Value::Null));
+    }
+    Ok(object)
+}
@@ -0,0 +1,102 @@
+use crate::memory::InMemoryBackend;
+use anyhow::{bail, Error};
+use async_trait::async_trait;
+use deno_core::serde_json::Value;
+use once_cell::sync::{Lazy, OnceCell};
+use serde::Serialize;
+use std::fmt::Debug;
+use std::future::Future;
+use std::sync::Arc;
+use std::time::Duration;
+
+static QUEUE_BACKEND: OnceCell<Arc<dyn QueueBackend>> = OnceCell::new();
+
+// Consumers wait for messages on this runtime, so the backend's connections
+// outlive the isolates using them.
+static QUEUE_RUNTIME: Lazy<tokio::runtime::Runtime> = Lazy::new(|| {
+    tokio::runtime::Builder::new_multi_thread()
+        .worker_threads(1)
+        .thread_name("sb-queue")
+        .enable_all()
+        .build()
+        .unwrap()
+});
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct QueueMessage {
+    pub id: String,
+    pub payload: Value,
+    // deliveries of the message so far, this one included
+    pub attempts: u64,
+}
+
+// Where `EdgeRuntime.queue` keeps its messages. A received message is hidden
+// from other consumers for its visibility timeout, and delivered again if it
+// isn't acked by then (eg: its worker was terminated).
+#[async_trait]
+pub trait QueueBackend: Send + Sync + Debug {
+    // Returns the message's id, it's delivered after `delay`. A message sent
+    // with the `dedupe_key` of one still in the queue isn't sent again, the
+    // id of that one is returned instead.
+    async fn send(
+        &self,
+        queue: &str,
+        payload: Value,
+        delay: Duration,
+        dedupe_key: Option<String>,
+    ) -> Result<String, Error>;
+
+    // the next visible message, waiting up to `wait` for one
+    async fn receive(
+        &self,
+        queue: &str,
+        visibility_timeout: Duration,
+        wait: Duration,
+    ) -> Result<Option<QueueMessage>, Error>;
+
+    async fn ack(&self, queue: &str, id: &str) -> Result<(), Error>;
+
+    // makes a received message visible again after `delay`
+    async fn nack(&self, queue: &str, id: &str, delay: Duration) -> Result<(), Error>;
+
+    // moves a message that failed too many times to the `<queue>.dead` queue
+    async fn dead_letter(
+        &self,
+        queue: &str,
+        message: QueueMessage,
+        _error: String,
+    ) -> Result<(), Error> {
+        self.send(
+            &format!("{}.dead", queue),
+            message.payload,
+            Duration::ZERO,
+            None,
+        )
+        .await?;
+        self.ack(queue, &message.id).await
+    }
+}
+
+// Must be called before the first worker is created. Messages stay in the
+// process otherwise, which is enough to develop with.
+pub fn init_queue_backend(backend: Arc<dyn QueueBackend>) -> Result<(), Error> {
+    if QUEUE_BACKEND.set(backend).is_err() {
+        bail!("the queue backend is already configured");
+    }
+    Ok(())
+}
+
+pub(crate) fn queue_backend() -> Arc<dyn QueueBackend> {
+    QUEUE_BACKEND
+        .get_or_init(|| Arc::new(InMemoryBackend::default()))
+        .clone()
+}
+
+pub(crate) async fn spawn<F, T>(fut: F) -> Result<T, Error>
+where
+    F: Future<Output = Result<T, Error>> + Send + 'static,
+    T: Send + 'static,
+{
+    QUEUE_RUNTIME.spawn(fut).await?
+}
@@ -0,0 +1,148 @@
+pub mod backend;
+pub mod memory;
+pub mod redis;
+pub mod sqlite;
+
+use backend::{queue_backend, spawn, QueueMessage};
+use deno_core::error::{type_error, AnyError};
+use deno_core::op;
+use deno_core::serde_json::Value;
+use deno_core::OpState;
+use std::cell::RefCell;
+use std::rc::Rc;
+use std::time::Duration;
+
+// a receive returns empty handed after it, so consumers can be stopped
+const MAX_RECEIVE_WAIT: Duration = Duration::from_secs(20);
+const MAX_VISIBILITY_TIMEOUT: Duration = Duration::from_secs(12 * 60 * 60);
+const MAX_QUEUE_NAME_LEN: usize = 128;
+
+deno_core::extension!(
+    sb_queue,
+    ops = [
+        op_queue_send,
+        op_queue_receive,
+        op_queue_ack,
+        op_queue_nack,
+        op_queue_dead_letter
+    ],
+    esm = ["queue.js"]
+);
+
+// The service a worker's queues belong to, services can't reach each other's.
+pub struct QueueWorkerState {
+    service: String,
+}
+
+impl QueueWorkerState {
+    pub fn new(service: String) -> Self {
+        Self { service }
+    }
+}
+
+// the queue's name in the backend
+fn queue_name(state: &Rc<RefCell<OpState>>, name: &str) -> Result<String, AnyError> {
+    if name.is_empty()
+        || name.len() > MAX_QUEUE_NAME_LEN
+        || !name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.'))
+    {
+        return Err(type_error(format!(
+            "invalid queue name {:?}, it can have up to {} letters, digits, '-', '_' and '.'",
+            name, MAX_QUEUE_NAME_LEN
+        )));
+    }
+    // without a service, the queue would be shared by every such worker
+    let state = state.borrow();
+    let Some(worker_state) = state.try_borrow::<QueueWorkerState>() else {
+        return Err(type_error("queues aren't available to this worker"));
+    };
+    Ok(format!("{}/{}", worker_state.service, name))
+}
+
+#[op]
+async fn op_queue_send(
+    state: Rc<RefCell<OpState>>,
+    name: String,
+    payload: Value,
+    delay_ms: u64,
+    dedupe_key: Option<String>,
+) -> Result<String, AnyError> {
+    let queue = queue_name(&state, &name)?;
+    let backend = queue_backend();
+    spawn(async move {
+        backend
+            .send(&queue, payload, Duration::from_millis(delay_ms), dedupe_key)
+            .await
+    })
+    .await
+}
+
+#[op]
+async fn op_queue_receive(
+    state: Rc<RefCell<OpState>>,
+    name: String,
+    visibility_timeout_ms: u64,
+    wait_ms: u64,
+) -> Result<Option<QueueMessage>, AnyError> {
+    let queue = queue_name(&state, &name)?;
+    let visibility_timeout = Duration::from_millis(visibility_timeout_ms);
+    if visibility_timeout.is_zero() || visibility_timeout > MAX_VISIBILITY_TIMEOUT {
+        return Err(type_error(format!(
+            "the visibility timeout must be between 1ms and {}s",
+            MAX_VISIBILITY_TIMEOUT.as_secs()
+        )));
+    }
+    let wait = Duration::from_millis(wait_ms).min(MAX_RECEIVE_WAIT);
+
+    let backend = queue_backend();
+    spawn(async move { backend.receive(&queue, visibility_timeout, wait).await }).await
+}
+
+#[op]
+async fn op_queue_ack(
+    state: Rc<RefCell<OpState>>,
+    name: String,
+    id: String,
+) -> Result<(), AnyError> {
+    let queue = queue_name(&state, &name)?;
+    let backend = queue_backend();
+    spawn(async move { backend.ack(&queue, &id).await }).await
+}
+
+#[op]
+async fn op_queue_nack(
+    state: Rc<RefCell<OpState>>,
+    name: String,
+    id: String,
+    delay_ms: u64,
+) -> Result<(), AnyError> {
+    let queue = queue_name(&state, &name)?;
+    let backend = queue_backend();
+    spawn(async move {
+        backend
+            .nack(&queue, &id, Duration::from_millis(delay_ms))
+            .await
+    })
+    .await
+}
+
+#[op]
+async fn op_queue_dead_letter(
+    state: Rc<RefCell<OpState>>,
+    name: String,
+    id: String,
+    payload: Value,
+    attempts: u64,
+    error: String,
+) -> Result<(), AnyError> {
+    let queue = queue_name(&state, &name)?;
+    let message = QueueMessage {
+        id,
+        payload,
+        attempts,
+    };
+    let backend = queue_backend();
+    spawn(async move { backend.dead_letter(&queue, message, error).await }).await
+}
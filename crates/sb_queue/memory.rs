@@ -0,0 +1,328 @@
+use crate::backend::{QueueBackend, QueueMessage};
+use anyhow::{bail, Error};
+use async_trait::async_trait;
+use deno_core::serde_json::Value;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::Notify;
+use tokio::time::Instant;
+
+// Keeps the messages in the process, they're lost when it exits.
+#[derive(Debug, Default)]
+pub struct InMemoryBackend {
+    queues: Mutex<HashMap<String, Arc<MemoryQueue>>>,
+}
+
+#[derive(Debug, Default)]
+struct MemoryQueue {
+    // in the order they were sent
+    messages: Mutex<Vec<MemoryMessage>>,
+    // a message was sent or made visible again
+    notify: Notify,
+}
+
+#[derive(Debug)]
+struct MemoryMessage {
+    id: String,
+    payload: Value,
+    attempts: u64,
+    visible_at: Instant,
+    dedupe_key: Option<String>,
+}
+
+impl InMemoryBackend {
+    fn queue(&self, name: &str) -> Arc<MemoryQueue> {
+        self.queues
+            .lock()
+            .unwrap()
+            .entry(name.to_string())
+            .or_default()
+            .clone()
+    }
+}
+
+impl MemoryQueue {
+    // takes the first visible message, or tells when the next one will be
+    fn take(&self, visibility_timeout: Duration) -> Result<QueueMessage, Option<Instant>> {
+        let now = Instant::now();
+        let mut messages = self.messages.lock().unwrap();
+        let Some(message) = messages.iter_mut().find(|m| m.visible_at <= now) else {
+            return Err(messages.iter().map(|m| m.visible_at).min());
+        };
+
+        message.attempts += 1;
+        message.visible_at = now + visibility_timeout;
+        Ok(QueueMessage {
+            id: message.id.clone(),
+            payload: message.payload.clone(),
+            attempts: message.attempts,
+        })
+    }
+}
+
+#[async_trait]
+impl QueueBackend for InMemoryBackend {
+    async fn send(
+        &self,
+        queue: &str,
+        payload: Value,
+        delay: Duration,
+        dedupe_key: Option<String>,
+    ) -> Result<String, Error> {
+        let queue = self.queue(queue);
+        let id = uuid::Uuid::new_v4().to_string();
+        {
+            let mut messages = queue.messages.lock().unwrap();
+            if let Some(existing) = dedupe_key
+                .as_ref()
+                .and_then(|key| messages.iter().find(|m| m.dedupe_key.as_ref() == Some(key)))
+            {
+                return Ok(existing.id.clone());
+            }
+            messages.push(MemoryMessage {
+                id: id.clone(),
+                payload,
+                attempts: 0,
+                visible_at: Instant::now() + delay,
+                dedupe_key,
+            });
+        }
+        queue.notify.notify_waiters();
+        Ok(id)
+    }
+
+    async fn receive(
+        &self,
+        queue: &str,
+        visibility_timeout: Duration,
+        wait: Duration,
+    ) -> Result<Option<QueueMessage>, Error> {
+        let queue = self.queue(queue);
+        let deadline = Instant::now() + wait;
+        loop {
+            // created before looking, so a message sent meanwhile wakes it
+            let notified = queue.notify.notified();
+            let next_visible = match queue.take(visibility_timeout) {
+                Ok(message) => return Ok(Some(message)),
+                Err(next_visible) => next_visible,
+            };
+            if Instant::now() >= deadline {
+                return Ok(None);
+            }
+
+            let wake_at = next_visible.map_or(deadline, |at| at.min(deadline));
+            tokio::select! {
+                _ = notified => {}
+                _ = tokio::time::sleep_until(wake_at) => {}
+            }
+        }
+    }
+
+    async fn ack(&self, queue: &str, id: &str) -> Result<(), Error> {
+        self.queue(queue)
+            .messages
+            .lock()
+            .unwrap()
+            .retain(|m| m.id != id);
+        Ok(())
+    }
+
+    async fn nack(&self, queue: &str, id: &str, delay: Duration) -> Result<(), Error> {
+        let queue = self.queue(queue);
+        {
+            let mut messages = queue.messages.lock().unwrap();
+            let Some(message) = messages.iter_mut().find(|m| m.id == id) else {
+                bail!("message {} isn't in the queue", id);
+            };
+            message.visible_at = Instant::now() + delay;
+        }
+        queue.notify.notify_waiters();
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use deno_core::serde_json::json;
+
+    const VISIBILITY: Duration = Duration::from_millis(100);
+
+    #[tokio::test]
+    async fn test_send_receive_ack() {
+        let backend = InMemoryBackend::default();
+        let id = backend
+            .send("svc/jobs", json!({ "n": 1 }), Duration::ZERO, None)
+            .await
+            .unwrap();
+
+        let message = backend
+            .receive("svc/jobs", VISIBILITY, Duration::ZERO)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(message.id, id);
+        assert_eq!(message.payload, json!({ "n": 1 }));
+        assert_eq!(message.attempts, 1);
+
+        // queues are separate
+        let other = backend
+            .receive("other/jobs", VISIBILITY, Duration::ZERO)
+            .await
+            .unwrap();
+        assert!(other.is_none());
+
+        backend.ack("svc/jobs", &id).await.unwrap();
+        tokio::time::sleep(VISIBILITY * 2).await;
+        let message = backend
+            .receive("svc/jobs", VISIBILITY, Duration::ZERO)
+            .await
+            .unwrap();
+        assert!(message.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_visibility_timeout() {
+        let backend = InMemoryBackend::default();
+        let id = backend
+            .send("svc/jobs", json!(1), Duration::ZERO, None)
+            .await
+            .unwrap();
+        backend
+            .receive("svc/jobs", VISIBILITY, Duration::ZERO)
+            .await
+            .unwrap()
+            .unwrap();
+
+        // hidden from other consumers while it's handled
+        let message = backend
+            .receive("svc/jobs", VISIBILITY, Duration::ZERO)
+            .await
+            .unwrap();
+        assert!(message.is_none());
+
+        // and delivered again once it wasn't acked in time
+        let message = backend
+            .receive("svc/jobs", VISIBILITY, VISIBILITY * 5)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(message.id, id);
+        assert_eq!(message.attempts, 2);
+    }
+
+    #[tokio::test]
+    async fn test_nack() {
+        let backend = InMemoryBackend::default();
+        let id = backend
+            .send("svc/jobs", json!(1), Duration::ZERO, None)
+            .await
+            .unwrap();
+        backend
+            .receive("svc/jobs", Duration::from_secs(60), Duration::ZERO)
+            .await
+            .unwrap()
+            .unwrap();
+
+        // visible again after the delay, rather than the visibility timeout
+        backend
+            .nack("svc/jobs", &id, Duration::from_millis(50))
+            .await
+            .unwrap();
+        let start = Instant::now();
+        let message = backend
+            .receive("svc/jobs", VISIBILITY, Duration::from_secs(1))
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(message.id, id);
+        assert!(start.elapsed() >= Duration::from_millis(50));
+        assert!(start.elapsed() < Duration::from_secs(1));
+
+        assert!(backend
+            .nack("svc/jobs", "missing", Duration::ZERO)
+            .await
+            .is_err());
+    }
+
+    #[tokio::test]
+    async fn test_delayed_send() {
+        let backend = InMemoryBackend::default();
+        backend
+            .send("svc/jobs", json!(1), Duration::from_millis(100), None)
+            .await
+            .unwrap();
+        let message = backend
+            .receive("svc/jobs", VISIBILITY, Duration::ZERO)
+            .await
+            .unwrap();
+        assert!(message.is_none());
+
+        // a waiting receive gets it once it's due
+        let message = backend
+            .receive("svc/jobs", VISIBILITY, Duration::from_secs(1))
+            .await
+            .unwrap();
+        assert!(message.is_some());
+    }
+
+    #[tokio::test]
+    async fn test_receive_woken_by_send() {
+        let backend = Arc::new(InMemoryBackend::default());
+        let receiver = backend.clone();
+        let received = tokio::spawn(async move {
+            receiver
+                .receive("svc/jobs", VISIBILITY, Duration::from_secs(5))
+                .await
+        });
+        tokio::time::sleep(Duration::from_millis(50)).await;
+
+        let start = Instant::now();
+        backend
+            .send("svc/jobs", json!(1), Duration::ZERO, None)
+            .await
+            .unwrap();
+        let message = received.await.unwrap().unwrap();
+        assert!(message.is_some());
+        assert!(start.elapsed() < Duration::from_secs(1));
+    }
+
+    #[tokio::test]
+    async fn test_dedupe_and_dead_letter() {
+        let backend = InMemoryBackend::default();
+        let key = Some("job-1".to_string());
+        let id = backend
+            .send("svc/jobs", json!(1), Duration::ZERO, key.clone())
+            .await
+            .unwrap();
+        let again = backend
+            .send("svc/jobs", json!(2), Duration::ZERO, key)
+            .await
+            .unwrap();
+        assert_eq!(id, again);
+
+        let message = backend
+            .receive("svc/jobs", VISIBILITY, Duration::ZERO)
+            .await
+            .unwrap()
+            .unwrap();
+        backend
+            .dead_letter("svc/jobs", message, "failed".to_string())
+            .await
+            .unwrap();
+
+        let dead = backend
+            .receive("svc/jobs.dead", VISIBILITY, Duration::ZERO)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(dead.payload, json!(1));
+        tokio::time::sleep(VISIBILITY * 2).await;
+        let message = backend
+            .receive("svc/jobs", VISIBILITY, Duration::ZERO)
+            .await
+            .unwrap();
+        assert!(message.is_none());
+    }
+}
@@ -0,0 +1,98 @@
+import { registerEdgeRuntimeApi } from "ext:sb_core_edge_runtime_apis/js/edge_runtime_apis.js";
+
+const core = globalThis.Deno.core;
+
+// interface SendOptions {
+//     delayMs?: number; // the message is delivered after it
+//     dedupeKey?: string; // not sent again while a message with this key is in the queue
+// }
+
+// interface ConsumeOptions {
+//     visibilityTimeoutMs?: number; // a message is delivered again if not handled by then
+//     maxAttempts?: number; // then it's moved to the `<name>.dead` queue
+//     concurrency?: number; // messages handled at once
+// }
+
+const MAX_RETRY_DELAY_MS = 60_000;
+
+function send(name, payload, options = {}) {
+  return core.opAsync(
+    "op_queue_send",
+    name,
+    payload ?? null,
+    options.delayMs ?? 0,
+    // a message with the key of one still in the queue isn't sent again
+    options.dedupeKey ?? null,
+  );
+}
+
+async function handle(name, handler, message, maxAttempts) {
+  try {
+    await handler(message.payload, {
+      id: message.id,
+      attempts: message.attempts,
+    });
+    await core.opAsync("op_queue_ack", name, message.id);
+  } catch (err) {
+    if (message.attempts >= maxAttempts) {
+      console.error(
+        `message ${message.id} of ${name} failed ${message.attempts} times, moving it to ${name}.dead:`,
+        err,
+      );
+      await core.opAsync(
+        "op_queue_dead_letter",
+        name,
+        message.id,
+        message.payload,
+        message.attempts,
+        String(err?.stack ?? err),
+      );
+      return;
+    }
+    // retried with an exponential backoff
+    const delay = Math.min(1000 * 2 ** (message.attempts - 1), MAX_RETRY_DELAY_MS);
+    await core.opAsync("op_queue_nack", name, message.id, delay);
+  }
+}
+
+// calls `handler(payload, { id, attempts })` for each message of the queue
+// until `stop()` is called, a message is acked once its handler resolves
+function consume(name, handler, options = {}) {
+  if (typeof handler !== "function") {
+    throw new TypeError("A consumer needs a handler function.");
+  }
+  const visibilityTimeoutMs = options.visibilityTimeoutMs ?? 30_000;
+  const maxAttempts = options.maxAttempts ?? 5;
+  const concurrency = options.concurrency ?? 1;
+
+  let stopped = false;
+  const loop = async () => {
+    while (!stopped) {
+      const message = await core.opAsync(
+        "op_queue_receive",
+        name,
+        visibilityTimeoutMs,
+        // wait as long as the backend allows
+        Number.MAX_SAFE_INTEGER,
+      );
+      if (message !== null) {
+        await handle(name, handler, message, maxAttempts);
+      }
+    }
+  };
+  const loops = Array.from({ length: Math.max(1, concurrency) }, loop);
+
+  return {
+    // resolves once the messages being handled are done
+    async stop() {
+      stopped = true;
+      await Promise.allSettled(loops);
+    },
+  };
+}
+
+const SUPABASE_QUEUE = { send, consume };
+
+registerEdgeRuntimeApi("queue", SUPABASE_QUEUE);
+
+export { SUPABASE_QUEUE };
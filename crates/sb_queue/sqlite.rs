@@ -0,0 +1,523 @@
+use crate::backend::{QueueBackend, QueueMessage};
+use anyhow::{bail, Context, Error};
+use async_trait::async_trait;
+use deno_core::serde_json::{self, Value};
+use log::{info, warn};
+use rusqlite::{params, Connection, OptionalExtension};
+use std::path::Path;
+use std::sync::{Arc, Mutex, Weak};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::sync::Notify;
+
+// how often the file is looked at again while receiving, in case a message
+// became visible without this process being told (eg: it was nacked)
+const POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+// A run holds a lease on the file while it's up, renewed a few times per
+// lease. The messages received by runs whose lease ran out are delivered
+// again by the next run to open it.
+const RUN_LEASE: Duration = Duration::from_secs(30);
+
+const SCHEMA: &str = r"
+    CREATE TABLE IF NOT EXISTS messages (
+        id TEXT PRIMARY KEY,
+        queue TEXT NOT NULL,
+        payload TEXT NOT NULL,
+        attempts INTEGER NOT NULL DEFAULT 0,
+        -- unix time in ms
+        visible_at INTEGER NOT NULL,
+        dedupe_key TEXT,
+        -- the run of the runtime that received it, while it's being handled
+        run_id TEXT
+    );
+    CREATE INDEX IF NOT EXISTS messages_visible ON messages (queue, visible_at);
+    CREATE UNIQUE INDEX IF NOT EXISTS messages_dedupe ON messages (queue, dedupe_key);
+    CREATE TABLE IF NOT EXISTS runs (
+        run_id TEXT PRIMARY KEY,
+        -- unix time in ms
+        lease_until INTEGER NOT NULL
+    );
+    CREATE TABLE IF NOT EXISTS dead_letters (
+        id TEXT NOT NULL,
+        queue TEXT NOT NULL,
+        payload TEXT NOT NULL,
+        attempts INTEGER NOT NULL,
+        error TEXT NOT NULL,
+        failed_at INTEGER NOT NULL
+    );
+";
+
+fn now_ms() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_millis() as u64
+}
+
+// keeps the run's lease until the backend is dropped
+fn renew_lease(conn: Weak<Mutex<Connection>>, run_id: String) {
+    std::thread::spawn(move || loop {
+        std::thread::sleep(RUN_LEASE / 3);
+        let Some(conn) = conn.upgrade() else {
+            return;
+        };
+        let renewed = conn.lock().unwrap().execute(
+            "UPDATE runs SET lease_until = ?1 WHERE run_id = ?2",
+            params![now_ms() + RUN_LEASE.as_millis() as u64, run_id],
+        );
+        if let Err(err) = renewed {
+            warn!("failed to renew the lease of the queue database: {}", err);
+        }
+    });
+}
+
+// Keeps the messages in a sqlite file, so the delayed and unacked ones
+// survive restarts of the runtime. Messages received by a run that stopped
+// (its lease ran out) and not acked are delivered again as soon as another
+// one starts, with their attempts kept so handlers can tell. The ones of runs
+// still up, eg: other processes sharing the file, are left to them. Messages moved to the `<name>.dead` queue are
+// also recorded in the `dead_letters` table, with the error they failed with.
+#[derive(Debug)]
+pub struct SqliteBackend {
+    conn: Arc<Mutex<Connection>>,
+    run_id: String,
+    // a message was sent or made visible again
+    notify: Notify,
+}
+
+impl SqliteBackend {
+    pub fn open(path: &Path) -> Result<Self, Error> {
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        let conn = Connection::open(path)
+            .with_context(|| format!("failed to open the queue database {}", path.display()))?;
+        conn.pragma_update(None, "journal_mode", "wal")?;
+        // a sent message is on disk once `send` resolves
+        conn.pragma_update(None, "synchronous", "full")?;
+        conn.busy_timeout(Duration::from_secs(5))?;
+        conn.execute_batch(SCHEMA)?;
+
+        let run_id = uuid::Uuid::new_v4().to_string();
+        let now = now_ms();
+        let tx = conn.transaction()?;
+        tx.execute("DELETE FROM runs WHERE lease_until <= ?1", params![now])?;
+        tx.execute(
+            "INSERT INTO runs (run_id, lease_until) VALUES (?1, ?2)",
+            params![run_id, now + RUN_LEASE.as_millis() as u64],
+        )?;
+        let replayed = tx.execute(
+            "UPDATE messages SET visible_at = MIN(visible_at, ?1), run_id = NULL
+             WHERE run_id IS NOT NULL AND run_id NOT IN (SELECT run_id FROM runs)",
+            params![now],
+        )?;
+        tx.commit()?;
+        if replayed > 0 {
+            info!(
+                "delivering again {} queue message(s) that weren't acked before the restart",
+                replayed
+            );
+        }
+
+        let conn = Arc::new(Mutex::new(conn));
+        renew_lease(Arc::downgrade(&conn), run_id.clone());
+        Ok(Self {
+            conn,
+            run_id,
+            notify: Notify::new(),
+        })
+    }
+
+    // sqlite blocks, so it's used off the queue runtime's thread
+    async fn with_conn<T, F>(&self, f: F) -> Result<T, Error>
+    where
+        F: FnOnce(&mut Connection) -> Result<T, Error> + Send + 'static,
+        T: Send + 'static,
+    {
+        let conn = self.conn.clone();
+        tokio::task::spawn_blocking(move || f(&mut *conn.lock().unwrap())).await?
+    }
+
+    // takes the first visible message, or tells when the next one will be
+    async fn take(
+        &self,
+        queue: &str,
+        visibility_timeout: Duration,
+    ) -> Result<Result<QueueMessage, Option<u64>>, Error> {
+        let queue = queue.to_string();
+        let run_id = self.run_id.clone();
+        self.with_conn(move |conn| {
+            let now = now_ms();
+            let tx = conn.transaction()?;
+            let message = tx
+                .query_row(
+                    "SELECT id, payload, attempts FROM messages
+                     WHERE queue = ?1 AND visible_at <= ?2 ORDER BY rowid LIMIT 1",
+                    params![queue, now],
+                    |row| {
+                        Ok((
+                            row.get::<_, String>(0)?,
+                            row.get::<_, String>(1)?,
+                            row.get::<_, u64>(2)?,
+                        ))
+                    },
+                )
+                .optional()?;
+            let Some((id, payload, attempts)) = message else {
+                let next_visible = tx.query_row(
+                    "SELECT MIN(visible_at) FROM messages WHERE queue = ?1",
+                    params![queue],
+                    |row| row.get::<_, Option<u64>>(0),
+                )?;
+                return Ok(Err(next_visible));
+            };
+
+            let attempts = attempts + 1;
+            tx.execute(
+                "UPDATE messages SET attempts = ?1, visible_at = ?2, run_id = ?3 WHERE id = ?4",
+                params![
+                    attempts,
+                    now + visibility_timeout.as_millis() as u64,
+                    run_id,
+                    id
+                ],
+            )?;
+            tx.commit()?;
+            Ok(Ok(QueueMessage {
+                id,
+                payload: serde_json::from_str(&payload)?,
+                attempts,
+            }))
+        })
+        .await
+    }
+}
+
+// a run that stops cleanly gives its messages to the next one right away
+impl Drop for SqliteBackend {
+    fn drop(&mut self) {
+        if let Ok(conn) = self.conn.lock() {
+            let _ = conn.execute("DELETE FROM runs WHERE run_id = ?1", params![self.run_id]);
+        }
+    }
+}
+
+#[async_trait]
+impl QueueBackend for SqliteBackend {
+    async fn send(
+        &self,
+        queue: &str,
+        payload: Value,
+        delay: Duration,
+        dedupe_key: Option<String>,
+    ) -> Result<String, Error> {
+        let queue = queue.to_string();
+        let payload = serde_json::to_string(&payload)?;
+        let id = self
+            .with_conn(move |conn| {
+                let tx = conn.transaction()?;
+                if let Some(dedupe_key) = &dedupe_key {
+                    let existing = tx
+                        .query_row(
+                            "SELECT id FROM messages WHERE queue = ?1 AND dedupe_key = ?2",
+                            params![queue, dedupe_key],
+                            |row| row.get::<_, String>(0),
+                        )
+                        .optional()?;
+                    if let Some(id) = existing {
+                        return Ok(id);
+                    }
+                }
+                let id = uuid::Uuid::new_v4().to_string();
+                tx.execute(
+                    "INSERT INTO messages (id, queue, payload, visible_at, dedupe_key)
+                     VALUES (?1, ?2, ?3, ?4, ?5)",
+                    params![
+                        id,
+                        queue,
+                        payload,
+                        now_ms() + delay.as_millis() as u64,
+                        dedupe_key
+                    ],
+                )?;
+                tx.commit()?;
+                Ok(id)
+            })
+            .await?;
+        self.notify.notify_waiters();
+        Ok(id)
+    }
+
+    async fn receive(
+        &self,
+        queue: &str,
+        visibility_timeout: Duration,
+        wait: Duration,
+    ) -> Result<Option<QueueMessage>, Error> {
+        let deadline = tokio::time::Instant::now() + wait;
+        loop {
+            // created before looking, so a message sent meanwhile wakes it
+            let notified = self.notify.notified();
+            let next_visible = match self.take(queue, visibility_timeout).await? {
+                Ok(message) => return Ok(Some(message)),
+                Err(next_visible) => next_visible,
+            };
+            let now = tokio::time::Instant::now();
+            if now >= deadline {
+                return Ok(None);
+            }
+
+            let mut wake_at = deadline.min(now + POLL_INTERVAL);
+            if let Some(at) = next_visible {
+                wake_at = wake_at.min(now + Duration::from_millis(at.saturating_sub(now_ms())));
+            }
+            tokio::select! {
+                _ = notified => {}
+                _ = tokio::time::sleep_until(wake_at) => {}
+            }
+        }
+    }
+
+    // only the run the message was delivered to can ack it, it may have
+    // been delivered again since its visibility timeout ran out
+    async fn ack(&self, queue: &str, id: &str) -> Result<(), Error> {
+        let (queue, id) = (queue.to_string(), id.to_string());
+        let run_id = self.run_id.clone();
+        self.with_conn(move |conn| {
+            conn.execute(
+                "DELETE FROM messages WHERE queue = ?1 AND id = ?2 AND run_id = ?3",
+                params![queue, id, run_id],
+            )?;
+            Ok(())
+        })
+        .await
+    }
+
+    async fn nack(&self, queue: &str, id: &str, delay: Duration) -> Result<(), Error> {
+        let (queue, id) = (queue.to_string(), id.to_string());
+        let run_id = self.run_id.clone();
+        self.with_conn(move |conn| {
+            let updated = conn.execute(
+                "UPDATE messages SET visible_at = ?1, run_id = NULL
+                 WHERE queue = ?2 AND id = ?3 AND run_id = ?4",
+                params![now_ms() + delay.as_millis() as u64, queue, id, run_id],
+            )?;
+            if updated == 0 {
+                bail!("message {} isn't in the queue, or was received again", id);
+            }
+            Ok(())
+        })
+        .await?;
+        self.notify.notify_waiters();
+        Ok(())
+    }
+
+    // moves the message and records it at once, so it's neither lost nor
+    // dead lettered twice if the runtime stops meanwhile
+    async fn dead_letter(
+        &self,
+        queue: &str,
+        message: QueueMessage,
+        error: String,
+    ) -> Result<(), Error> {
+        let queue = queue.to_string();
+        let run_id = self.run_id.clone();
+        self.with_conn(move |conn| {
+            let payload = serde_json::to_string(&message.payload)?;
+            let now = now_ms();
+            let tx = conn.transaction()?;
+            let removed = tx.execute(
+                "DELETE FROM messages WHERE queue = ?1 AND id = ?2 AND run_id = ?3",
+                params![queue, message.id, run_id],
+            )?;
+            // acked, dead lettered or received again by another consumer
+            if removed == 0 {
+                return Ok(());
+            }
+            tx.execute(
+                "INSERT INTO messages (id, queue, payload, visible_at) VALUES (?1, ?2, ?3, ?4)",
+                params![
+                    uuid::Uuid::new_v4().to_string(),
+                    format!("{}.dead", queue),
+                    payload,
+                    now
+                ],
+            )?;
+            tx.execute(
+                "INSERT INTO dead_letters (id, queue, payload, attempts, error, failed_at)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+                params![message.id, queue, payload, message.attempts, error, now],
+            )?;
+            tx.commit()?;
+            Ok(())
+        })
+        .await?;
+        self.notify.notify_waiters();
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use deno_core::serde_json::json;
+    use std::path::PathBuf;
+
+    const VISIBILITY: Duration = Duration::from_secs(60);
+
+    fn db_path() -> PathBuf {
+        std::env::temp_dir()
+            .join(uuid::Uuid::new_v4().to_string())
+            .join("queue.db")
+    }
+
+    async fn receive_now(backend: &SqliteBackend, queue: &str) -> Option<QueueMessage> {
+        backend
+            .receive(queue, VISIBILITY, Duration::ZERO)
+            .await
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_persisted_across_reopen() {
+        let path = db_path();
+        let backend = SqliteBackend::open(&path).unwrap();
+        let id = backend
+            .send("svc/jobs", json!({ "n": 1 }), Duration::ZERO, None)
+            .await
+            .unwrap();
+        drop(backend);
+
+        let backend = SqliteBackend::open(&path).unwrap();
+        let message = receive_now(&backend, "svc/jobs").await.unwrap();
+        assert_eq!(message.id, id);
+        assert_eq!(message.payload, json!({ "n": 1 }));
+        assert_eq!(message.attempts, 1);
+        backend.ack("svc/jobs", &id).await.unwrap();
+        drop(backend);
+
+        let backend = SqliteBackend::open(&path).unwrap();
+        assert!(receive_now(&backend, "svc/jobs").await.is_none());
+
+        let _ = std::fs::remove_dir_all(path.parent().unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_replay_of_stopped_runs_only() {
+        let path = db_path();
+        let first = SqliteBackend::open(&path).unwrap();
+        let id = first
+            .send("svc/jobs", json!(1), Duration::ZERO, None)
+            .await
+            .unwrap();
+        receive_now(&first, "svc/jobs").await.unwrap();
+
+        // the first run is still up, its message is left to it
+        let second = SqliteBackend::open(&path).unwrap();
+        assert!(receive_now(&second, "svc/jobs").await.is_none());
+        // and only the run it was delivered to can ack it
+        second.ack("svc/jobs", &id).await.unwrap();
+        assert!(second.nack("svc/jobs", &id, Duration::ZERO).await.is_err());
+        drop(second);
+
+        // delivered again once its run stopped, without waiting for its
+        // visibility timeout
+        drop(first);
+        let third = SqliteBackend::open(&path).unwrap();
+        let message = receive_now(&third, "svc/jobs").await.unwrap();
+        assert_eq!(message.id, id);
+        assert_eq!(message.attempts, 2);
+
+        let _ = std::fs::remove_dir_all(path.parent().unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_replay_of_expired_leases() {
+        let path = db_path();
+        let backend = SqliteBackend::open(&path).unwrap();
+        backend
+            .send("svc/jobs", json!(1), Duration::ZERO, None)
+            .await
+            .unwrap();
+        receive_now(&backend, "svc/jobs").await.unwrap();
+
+        // like a run that crashed, without releasing its lease
+        backend
+            .with_conn(|conn| {
+                conn.execute("UPDATE runs SET lease_until = 0", [])?;
+                Ok(())
+            })
+            .await
+            .unwrap();
+        let other = SqliteBackend::open(&path).unwrap();
+        let message = receive_now(&other, "svc/jobs").await.unwrap();
+        assert_eq!(message.attempts, 2);
+
+        let _ = std::fs::remove_dir_all(path.parent().unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_delayed_delivery() {
+        let path = db_path();
+        let backend = SqliteBackend::open(&path).unwrap();
+        backend
+            .send("svc/jobs", json!(1), Duration::from_millis(300), None)
+            .await
+            .unwrap();
+        assert!(receive_now(&backend, "svc/jobs").await.is_none());
+        drop(backend);
+
+        // still delayed after a restart, and delivered once it's due
+        let backend = SqliteBackend::open(&path).unwrap();
+        assert!(receive_now(&backend, "svc/jobs").await.is_none());
+        let message = backend
+            .receive("svc/jobs", VISIBILITY, Duration::from_secs(2))
+            .await
+            .unwrap();
+        assert!(message.is_some());
+
+        let _ = std::fs::remove_dir_all(path.parent().unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_dead_letter() {
+        let path = db_path();
+        let backend = SqliteBackend::open(&path).unwrap();
+        backend
+            .send("svc/jobs", json!({ "n": 1 }), Duration::ZERO, None)
+            .await
+            .unwrap();
+        let message = receive_now(&backend, "svc/jobs").await.unwrap();
+        let id = message.id.clone();
+        backend
+            .dead_letter("svc/jobs", message, "boom".to_string())
+            .await
+            .unwrap();
+        drop(backend);
+
+        let backend = SqliteBackend::open(&path).unwrap();
+        assert!(receive_now(&backend, "svc/jobs").await.is_none());
+        let dead = receive_now(&backend, "svc/jobs.dead").await.unwrap();
+        assert_eq!(dead.payload, json!({ "n": 1 }));
+
+        let logged = backend
+            .with_conn(move |conn| {
+                Ok(conn.query_row(
+                    "SELECT queue, attempts, error FROM dead_letters WHERE id = ?1",
+                    params![id],
+                    |row| {
+                        Ok((
+                            row.get::<_, String>(0)?,
+                            row.get::<_, u64>(1)?,
+                            row.get::<_, String>(2)?,
+                        ))
+                    },
+                )?)
+            })
+            .await
+            .unwrap();
+        assert_eq!(logged, ("svc/jobs".to_string(), 1, "boom".to_string()));
+
+        let _ = std::fs::remove_dir_all(path.parent().unwrap());
+    }
+}
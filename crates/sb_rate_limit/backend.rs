@@ -0,0 +1,63 @@
+use crate::memory::InMemoryBackend;
+use anyhow::{bail, Error};
+use async_trait::async_trait;
+use once_cell::sync::{Lazy, OnceCell};
+use serde::Serialize;
+use std::fmt::Debug;
+use std::future::Future;
+use std::sync::Arc;
+use std::time::Duration;
+
+static RATE_LIMIT_BACKEND: OnceCell<Arc<dyn RateLimitBackend>> = OnceCell::new();
+
+// The counters are updated from this runtime, so the backend's connections
+// outlive the isolates using them.
+static RATE_LIMIT_RUNTIME: Lazy<tokio::runtime::Runtime> = Lazy::new(|| {
+    tokio::runtime::Builder::new_multi_thread()
+        .worker_threads(1)
+        .thread_name("sb-rate-limit")
+        .enable_all()
+        .build()
+        .unwrap()
+});
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RateLimitResult {
+    pub allowed: bool,
+    // hits left in the current window
+    pub remaining: u64,
+    // until the window ends and the count starts over
+    pub reset_ms: u64,
+}
+
+// Where `EdgeRuntime.rateLimit` counts the hits of a key, in fixed windows
+// starting with the first hit.
+#[async_trait]
+pub trait RateLimitBackend: Send + Sync + Debug {
+    // counts a hit, which is allowed if the window has at most `limit` of them
+    async fn hit(&self, key: &str, limit: u64, window: Duration) -> Result<RateLimitResult, Error>;
+}
+
+// Must be called before the first worker is created. The counters stay in the
+// process otherwise, so they aren't shared with other instances of the server.
+pub fn init_rate_limit_backend(backend: Arc<dyn RateLimitBackend>) -> Result<(), Error> {
+    if RATE_LIMIT_BACKEND.set(backend).is_err() {
+        bail!("the rate limit backend is already configured");
+    }
+    Ok(())
+}
+
+pub(crate) fn rate_limit_backend() -> Arc<dyn RateLimitBackend> {
+    RATE_LIMIT_BACKEND
+        .get_or_init(|| Arc::new(InMemoryBackend::default()))
+        .clone()
+}
+
+pub(crate) async fn spawn<F, T>(fut: F) -> Result<T, Error>
+where
+    F: Future<Output = Result<T, Error>> + Send + 'static,
+    T: Send + 'static,
+{
+    RATE_LIMIT_RUNTIME.spawn(fut).await?
+}
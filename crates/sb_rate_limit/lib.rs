@@ -0,0 +1,68 @@
+pub mod backend;
+pub mod memory;
+pub mod redis;
+
+use backend::{rate_limit_backend, spawn, RateLimitResult};
+use deno_core::error::{type_error, AnyError};
+use deno_core::op;
+use deno_core::OpState;
+use std::cell::RefCell;
+use std::rc::Rc;
+use std::time::Duration;
+
+const MAX_WINDOW: Duration = Duration::from_secs(24 * 60 * 60);
+const MAX_KEY_LEN: usize = 256;
+
+deno_core::extension!(
+    sb_rate_limit,
+    ops = [op_rate_limit],
+    esm = ["rate_limit.js"]
+);
+
+// The service a worker's limits belong to, services can't use up each
+// other's.
+pub struct RateLimitWorkerState {
+    service: String,
+}
+
+impl RateLimitWorkerState {
+    pub fn new(service: String) -> Self {
+        Self { service }
+    }
+}
+
+#[op]
+async fn op_rate_limit(
+    state: Rc<RefCell<OpState>>,
+    key: String,
+    limit: u64,
+    window_ms: u64,
+) -> Result<RateLimitResult, AnyError> {
+    if key.is_empty() || key.len() > MAX_KEY_LEN {
+        return Err(type_error(format!(
+            "a rate limit key must have 1 to {} bytes",
+            MAX_KEY_LEN
+        )));
+    }
+    if limit == 0 {
+        return Err(type_error("the limit must be at least 1"));
+    }
+    let window = Duration::from_millis(window_ms);
+    if window.is_zero() || window > MAX_WINDOW {
+        return Err(type_error(format!(
+            "the window must be between 1ms and {}s",
+            MAX_WINDOW.as_secs()
+        )));
+    }
+
+    let service = state
+        .borrow()
+        .try_borrow::<RateLimitWorkerState>()
+        .map(|state| state.service.clone())
+        .unwrap_or_default();
+    // the same key counts separately for windows of another length
+    let key = format!("{}/{}/{}", service, window_ms, key);
+
+    let backend = rate_limit_backend();
+    spawn(async move { backend.hit(&key, limit, window).await }).await
+}
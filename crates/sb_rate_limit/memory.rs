@@ -0,0 +1,50 @@
+use crate::backend::{RateLimitBackend, RateLimitResult};
+use anyhow::Error;
+use async_trait::async_trait;
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+// windows kept before the ended ones are dropped
+const MAX_WINDOWS: usize = 10_000;
+
+// Keeps the counters in the process, they're lost when it exits.
+#[derive(Debug, Default)]
+pub struct InMemoryBackend {
+    windows: Mutex<HashMap<String, Window>>,
+}
+
+#[derive(Debug)]
+struct Window {
+    ends_at: Instant,
+    hits: u64,
+}
+
+#[async_trait]
+impl RateLimitBackend for InMemoryBackend {
+    async fn hit(&self, key: &str, limit: u64, window: Duration) -> Result<RateLimitResult, Error> {
+        let now = Instant::now();
+        let mut windows = self.windows.lock().unwrap();
+        if windows.len() >= MAX_WINDOWS {
+            windows.retain(|_, window| window.ends_at > now);
+        }
+
+        let current = windows.entry(key.to_string()).or_insert(Window {
+            ends_at: now + window,
+            hits: 0,
+        });
+        if current.ends_at <= now {
+            *current = Window {
+                ends_at: now + window,
+                hits: 0,
+            };
+        }
+        current.hits += 1;
+
+        Ok(RateLimitResult {
+            allowed: current.hits <= limit,
+            remaining: limit.saturating_sub(current.hits),
+            reset_ms: current.ends_at.saturating_duration_since(now).as_millis() as u64,
+        })
+    }
+}
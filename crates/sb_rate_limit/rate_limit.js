@@ -0,0 +1,22 @@
+import { registerEdgeRuntimeApi } from "ext:sb_core_edge_runtime_apis/js/edge_runtime_apis.js";
+
+const core = globalThis.Deno.core;
+
+// counts a hit of `key` (eg: a user id) in a window of `windowMs` shared by
+// every worker of the service, resolves with { allowed, remaining, resetMs }:
+// `if (!(await EdgeRuntime.rateLimit(userId, 10, 60_000)).allowed) { ... }`
+function rateLimit(key, limit, windowMs) {
+  if (typeof key !== "string") {
+    throw new TypeError("A rate limit key must be a string.");
+  }
+  if (!Number.isInteger(limit) || !Number.isInteger(windowMs)) {
+    throw new TypeError("The limit and the window must be integers.");
+  }
+  return core.opAsync("op_rate_limit", key, limit, windowMs);
+}
+
+const SUPABASE_RATE_LIMIT = rateLimit;
+
+registerEdgeRuntimeApi("rateLimit", SUPABASE_RATE_LIMIT);
+
+export { SUPABASE_RATE_LIMIT };
@@ -0,0 +1,268 @@
+pub mod store;
+
+use bytes::Bytes;
+use deno_core::error::{custom_error, type_error, AnyError};
+use deno_core::futures::StreamExt;
+use deno_core::op;
+use deno_core::OpState;
+use deno_core::ResourceId;
+use deno_core::ZeroCopyBuf;
+use s3::Bucket;
+use sb_core::streams::{add_readable, byte_stream, take_writable};
+use serde::Serialize;
+use std::cell::RefCell;
+use std::future::Future;
+use std::rc::Rc;
+use store::STORAGE_RUNTIME;
+
+// a week, the longest S3 accepts
+const MAX_SIGNED_URL_EXPIRY_SECS: u32 = 7 * 24 * 60 * 60;
+
+deno_core::extension!(
+    sb_storage,
+    ops = [
+        op_storage_get,
+        op_storage_put,
+        op_storage_get_stream,
+        op_storage_put_stream,
+        op_storage_list,
+        op_storage_signed_url
+    ],
+    esm = ["storage.js"]
+);
+
+// The part of the storage a worker can use, `EdgeRuntime.storage` throws
+// without it.
+pub struct StorageWorkerState {
+    // the configured bucket if unset
+    bucket: Option<String>,
+    // prepended to the keys the worker uses, it can't reach other objects
+    prefix: String,
+}
+
+impl StorageWorkerState {
+    pub fn new(bucket: Option<String>, prefix: String) -> Self {
+        Self { bucket, prefix }
+    }
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct StorageObject {
+    key: String,
+    size: u64,
+    last_modified: String,
+}
+
+fn validate_key(key: &str) -> Result<(), AnyError> {
+    if key.is_empty()
+        || key.starts_with('/')
+        || key
+            .split('/')
+            .any(|segment| segment == ".." || segment == ".")
+    {
+        return Err(type_error(format!("invalid object key {:?}", key)));
+    }
+    Ok(())
+}
+
+// the worker's bucket and its prefix
+fn scope(state: &Rc<RefCell<OpState>>) -> Result<(Bucket, String), AnyError> {
+    let Some(store) = store::store() else {
+        return Err(custom_error(
+            "NotSupported",
+            "storage isn't configured on this server",
+        ));
+    };
+    let state = state.borrow();
+    let Some(storage) = state.try_borrow::<StorageWorkerState>() else {
+        return Err(custom_error(
+            "PermissionDenied",
+            "storage is not enabled for this worker, create it with the `storage` option",
+        ));
+    };
+    let bucket = store.bucket(storage.bucket.as_deref())?;
+    Ok((bucket, storage.prefix.clone()))
+}
+
+async fn spawn<F, T>(fut: F) -> Result<T, AnyError>
+where
+    F: Future<Output = Result<T, AnyError>> + Send + 'static,
+    T: Send + 'static,
+{
+    STORAGE_RUNTIME.spawn(fut).await?
+}
+
+#[op]
+async fn op_storage_get(
+    state: Rc<RefCell<OpState>>,
+    key: String,
+) -> Result<Option<ZeroCopyBuf>, AnyError> {
+    validate_key(&key)?;
+    let (bucket, prefix) = scope(&state)?;
+
+    let res =
+        spawn(async move { Ok(bucket.get_object(format!("{}{}", prefix, key)).await?) }).await?;
+    match res.status_code() {
+        404 => Ok(None),
+        200..=299 => Ok(Some(res.bytes().to_vec().into())),
+        status => Err(custom_error(
+            "Http",
+            format!("the storage answered {}", status),
+        )),
+    }
+}
+
+#[op]
+async fn op_storage_put(
+    state: Rc<RefCell<OpState>>,
+    key: String,
+    body: ZeroCopyBuf,
+    content_type: Option<String>,
+) -> Result<(), AnyError> {
+    validate_key(&key)?;
+    let (bucket, prefix) = scope(&state)?;
+    // sent from the JS buffer, without a copy
+    let body = Bytes::from(body);
+    let content_type = content_type.unwrap_or_else(|| "application/octet-stream".to_string());
+
+    let res = spawn(async move {
+        Ok(bucket
+            .put_object_with_content_type(format!("{}{}", prefix, key), &body, &content_type)
+            .await?)
+    })
+    .await?;
+    if !(200..300).contains(&res.status_code()) {
+        return Err(custom_error(
+            "Http",
+            format!("the storage answered {}", res.status_code()),
+        ));
+    }
+    Ok(())
+}
+
+// Like `op_storage_get`, the content is read as JS reads the stream rather
+// than at once.
+#[op]
+async fn op_storage_get_stream(
+    state: Rc<RefCell<OpState>>,
+    key: String,
+) -> Result<Option<ResourceId>, AnyError> {
+    validate_key(&key)?;
+    let (bucket, prefix) = scope(&state)?;
+
+    let res = spawn(async move {
+        Ok(bucket
+            .get_object_stream(format!("{}{}", prefix, key))
+            .await?)
+    })
+    .await?;
+    match res.status_code {
+        404 => Ok(None),
+        200..=299 => {
+            let (sender, stream) = byte_stream();
+            // the body can only be read on the storage's runtime
+            STORAGE_RUNTIME.spawn(sender.pipe(res.bytes.map(Ok::<_, AnyError>)));
+            Ok(Some(add_readable(&mut state.borrow_mut(), stream)))
+        }
+        status => Err(custom_error(
+            "Http",
+            format!("the storage answered {}", status),
+        )),
+    }
+}
+
+// Like `op_storage_put`, with the body JS writes to the stream `rid`.
+#[op]
+async fn op_storage_put_stream(
+    state: Rc<RefCell<OpState>>,
+    key: String,
+    rid: ResourceId,
+    content_type: Option<String>,
+) -> Result<(), AnyError> {
+    // taken first, failing below lets JS know it's no longer read
+    let mut body = take_writable(&mut state.borrow_mut(), rid)?;
+    validate_key(&key)?;
+    let (bucket, prefix) = scope(&state)?;
+    let content_type = content_type.unwrap_or_else(|| "application/octet-stream".to_string());
+
+    let status = spawn(async move {
+        Ok(bucket
+            .put_object_stream_with_content_type(
+                &mut body,
+                format!("{}{}", prefix, key),
+                &content_type,
+            )
+            .await?)
+    })
+    .await?;
+    if !(200..300).contains(&status) {
+        return Err(custom_error(
+            "Http",
+            format!("the storage answered {}", status),
+        ));
+    }
+    Ok(())
+}
+
+#[op]
+async fn op_storage_list(
+    state: Rc<RefCell<OpState>>,
+    key_prefix: String,
+) -> Result<Vec<StorageObject>, AnyError> {
+    if !key_prefix.is_empty() {
+        validate_key(&key_prefix)?;
+    }
+    let (bucket, prefix) = scope(&state)?;
+
+    let pages = {
+        let prefix = prefix.clone();
+        spawn(async move {
+            Ok(bucket
+                .list(format!("{}{}", prefix, key_prefix), None)
+                .await?)
+        })
+        .await?
+    };
+    Ok(pages
+        .into_iter()
+        .flat_map(|page| page.contents)
+        .map(|object| StorageObject {
+            // keys are relative to the worker's prefix
+            key: object.key[prefix.len()..].to_string(),
+            size: object.size,
+            last_modified: object.last_modified,
+        })
+        .collect())
+}
+
+#[op]
+async fn op_storage_signed_url(
+    state: Rc<RefCell<OpState>>,
+    key: String,
+    method: String,
+    expires_in_secs: u32,
+) -> Result<String, AnyError> {
+    validate_key(&key)?;
+    if expires_in_secs == 0 || expires_in_secs > MAX_SIGNED_URL_EXPIRY_SECS {
+        return Err(type_error(format!(
+            "signed urls must expire within 1 to {} seconds",
+            MAX_SIGNED_URL_EXPIRY_SECS
+        )));
+    }
+    let (bucket, prefix) = scope(&state)?;
+    let path = format!("{}{}", prefix, key);
+
+    // presigning is local, there's no request to drive
+    let url = match method.as_str() {
+        "GET" => bucket.presign_get(path, expires_in_secs, None)?,
+        "PUT" => bucket.presign_put(path, expires_in_secs, None)?,
+        _ => {
+            return Err(type_error(format!(
+                "signed urls can't be used with {}, only GET and PUT",
+                method
+            )))
+        }
+    };
+    Ok(url)
+}
@@ -0,0 +1,74 @@
+import { registerEdgeRuntimeApi } from "ext:sb_core_edge_runtime_apis/js/edge_runtime_apis.js";
+import { readableStreamFromRid, writableRid } from "ext:sb_core_streams/js/streams.js";
+
+const core = globalThis.Deno.core;
+
+async function bytes(body) {
+  if (typeof body === "string") {
+    return core.encode(body);
+  }
+  if (body instanceof Uint8Array) {
+    return body;
+  }
+  if (body instanceof ArrayBuffer) {
+    return new Uint8Array(body);
+  }
+  if (ArrayBuffer.isView(body)) {
+    return new Uint8Array(body.buffer, body.byteOffset, body.byteLength);
+  }
+  if (body instanceof Blob) {
+    return new Uint8Array(await body.arrayBuffer());
+  }
+  throw new TypeError("An object body must be a string, a buffer or a Blob.");
+}
+
+// the object's content, null if it doesn't exist
+function get(key) {
+  return core.opAsync("op_storage_get", key);
+}
+
+async function put(key, body, options = {}) {
+  await core.opAsync(
+    "op_storage_put",
+    key,
+    await bytes(body),
+    options.contentType ?? null,
+  );
+}
+
+// the object's content as a `ReadableStream`, null if it doesn't exist
+async function getStream(key) {
+  const rid = await core.opAsync("op_storage_get_stream", key);
+  return rid === null ? null : readableStreamFromRid(rid);
+}
+
+// uploads what's read from the `ReadableStream` `body` as it's read
+async function putStream(key, body, options = {}) {
+  const { rid, done } = writableRid(body);
+  await Promise.all([
+    core.opAsync("op_storage_put_stream", key, rid, options.contentType ?? null),
+    done,
+  ]);
+}
+
+// objects whose key starts with `prefix`, as { key, size, lastModified }
+function list(prefix = "") {
+  return core.opAsync("op_storage_list", prefix);
+}
+
+// a url anyone can get (or put, with `method: "PUT"`) the object with, until
+// it expires
+function signedUrl(key, options = {}) {
+  return core.opAsync(
+    "op_storage_signed_url",
+    key,
+    options.method ?? "GET",
+    options.expiresIn ?? 3600,
+  );
+}
+
+const SUPABASE_STORAGE = { get, put, getStream, putStream, list, signedUrl };
+
+registerEdgeRuntimeApi("storage", SUPABASE_STORAGE);
+
+export { SUPABASE_STORAGE };
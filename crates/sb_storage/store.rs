@@ -0,0 +1,105 @@
+use anyhow::{bail, Error};
+use once_cell::sync::{Lazy, OnceCell};
+use s3::creds::Credentials;
+use s3::{Bucket, Region};
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+static STORE: OnceCell<Store> = OnceCell::new();
+
+// Requests are driven by this runtime, so the connections to the storage
+// outlive the isolates that opened them.
+pub(crate) static STORAGE_RUNTIME: Lazy<tokio::runtime::Runtime> = Lazy::new(|| {
+    tokio::runtime::Builder::new_multi_thread()
+        .worker_threads(1)
+        .thread_name("sb-storage")
+        .enable_all()
+        .build()
+        .unwrap()
+});
+
+#[derive(Clone)]
+pub struct S3Opts {
+    // eg: https://s3.us-east-1.amazonaws.com or http://localhost:9000
+    pub endpoint: String,
+    pub region: String,
+    // bucket of the services that aren't given one
+    pub bucket: String,
+    pub access_key: String,
+    pub secret_key: String,
+    // `<endpoint>/<bucket>` urls instead of `<bucket>.<endpoint>` (eg: minio)
+    pub path_style: bool,
+}
+
+// credentials are never printed
+impl std::fmt::Debug for S3Opts {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("S3Opts")
+            .field("endpoint", &self.endpoint)
+            .field("region", &self.region)
+            .field("bucket", &self.bucket)
+            .field("path_style", &self.path_style)
+            .finish_non_exhaustive()
+    }
+}
+
+// Lets workers use `EdgeRuntime.storage`. Must be called before the first
+// worker is created, its functions throw otherwise.
+pub fn init_storage(opts: S3Opts) -> Result<(), Error> {
+    let store = Store {
+        region: Region::Custom {
+            region: opts.region.clone(),
+            endpoint: opts.endpoint.clone(),
+        },
+        credentials: Credentials::new(
+            Some(&opts.access_key),
+            Some(&opts.secret_key),
+            None,
+            None,
+            None,
+        )?,
+        opts,
+        buckets: Mutex::new(HashMap::new()),
+    };
+    // fails early on an invalid endpoint or bucket name
+    store.bucket(None)?;
+
+    if STORE.set(store).is_err() {
+        bail!("the storage is already configured");
+    }
+    Ok(())
+}
+
+pub(crate) fn store() -> Option<&'static Store> {
+    STORE.get()
+}
+
+pub fn storage_configured() -> bool {
+    STORE.get().is_some()
+}
+
+pub(crate) struct Store {
+    opts: S3Opts,
+    region: Region,
+    credentials: Credentials,
+    // by name
+    buckets: Mutex<HashMap<String, Bucket>>,
+}
+
+impl Store {
+    // the configured bucket if `name` isn't set
+    pub fn bucket(&self, name: Option<&str>) -> Result<Bucket, Error> {
+        let name = name.unwrap_or(&self.opts.bucket);
+        let mut buckets = self.buckets.lock().unwrap();
+        if let Some(bucket) = buckets.get(name) {
+            return Ok(bucket.clone());
+        }
+
+        let mut bucket = Bucket::new(name, self.region.clone(), self.credentials.clone())?;
+        if self.opts.path_style {
+            bucket = bucket.with_path_style();
+        }
+        buckets.insert(name.to_string(), bucket.clone());
+        Ok(bucket)
+    }
+}
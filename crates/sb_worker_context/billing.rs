@@ -0,0 +1,35 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+// past this, usages nobody took (eg: of requests that timed out) are dropped
+const MAX_PENDING_USAGES: usize = 10_000;
+
+// What an isolate spent on a request, measured from when it took the request
+// until it responded.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct IsolateUsage {
+    // the isolate's cpu time, split between the requests in flight
+    pub cpu_ms: f64,
+    pub peak_heap_bytes: u64,
+}
+
+// The usages reported by the isolates of a worker, by request id, until the
+// pool takes them to bill the requests.
+#[derive(Debug, Default)]
+pub struct InvocationUsages {
+    usages: Mutex<HashMap<String, IsolateUsage>>,
+}
+
+impl InvocationUsages {
+    pub fn insert(&self, request_id: String, usage: IsolateUsage) {
+        let mut usages = self.usages.lock().unwrap();
+        if usages.len() >= MAX_PENDING_USAGES {
+            usages.clear();
+        }
+        usages.insert(request_id, usage);
+    }
+
+    pub fn take(&self, request_id: &str) -> Option<IsolateUsage> {
+        self.usages.lock().unwrap().remove(request_id)
+    }
+}
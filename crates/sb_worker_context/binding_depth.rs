@@ -0,0 +1,39 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+// How deep in service calls made with `EdgeRuntime.services` a request is,
+// attached to it by the isolate that made the call.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BindingDepth(pub u32);
+
+// The depths of the service calls a worker is handling, by request id. The
+// pool records them as it hands the calls to the worker, its isolates read
+// them when they call the services bound to them.
+#[derive(Debug, Default)]
+pub struct BindingDepths {
+    depths: Mutex<HashMap<String, u32>>,
+}
+
+impl BindingDepths {
+    pub fn insert(&self, request_id: String, depth: u32) {
+        self.depths.lock().unwrap().insert(request_id, depth);
+    }
+
+    pub fn remove(&self, request_id: &str) {
+        self.depths.lock().unwrap().remove(request_id);
+    }
+
+    // the deepest of the calls being handled, 0 if the worker only handles
+    // requests that didn't come from another service. JS doesn't say which
+    // request a call is made for, so an isolate handling several requests
+    // counts from the deepest of them
+    pub fn deepest(&self) -> u32 {
+        self.depths
+            .lock()
+            .unwrap()
+            .values()
+            .copied()
+            .max()
+            .unwrap_or(0)
+    }
+}
@@ -1,20 +1,410 @@
+use crate::billing::InvocationUsages;
+use crate::binding_depth::BindingDepths;
+use crate::events::{LogLevel, WorkerEventsTx};
+use crate::extensions::WorkerExtensions;
+use crate::fetch::FetchInterceptor;
+use crate::fetch_breaker::FetchBreakers;
+use crate::net_usage::{NetUsage, NetUsageSnapshot};
+use crate::open_sockets::{OpenSockets, SocketUsage};
+use crate::routes::RouteTable;
 use anyhow::Error;
 use hyper::{Body, Request, Response};
+use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 use std::path::PathBuf;
+use std::sync::Arc;
 use tokio::sync::{mpsc, oneshot};
 use uuid::Uuid;
 
+// Bounds and thresholds for scaling the isolates serving a user worker.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct AutoscaleOpts {
+    pub min_workers: usize,
+    pub max_workers: usize,
+    // requests waiting for a slot of a busy isolate (see
+    // `max_concurrent_requests`) before another one is spawned
+    pub max_queue_depth: usize,
+    // p95 of the recent response times before another isolate is spawned
+    pub max_p95_latency_ms: Option<u64>,
+    // how long an extra isolate can stay idle before it's stopped
+    pub idle_timeout_ms: u64,
+}
+
+// Thresholds past which a user worker stops accepting requests, until some of
+// the ones it's handling are answered.
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct BackpressureOpts {
+    pub max_in_flight: Option<usize>,
+    // share of the worker's memory limit in use
+    pub max_heap_usage_pct: Option<u8>,
+}
+
+// Blobs of a user worker written to disk rather than kept in its memory,
+// eg: the files of multipart uploads.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct BlobSpillOpts {
+    // blobs at least this large are spilled
+    pub threshold_bytes: usize,
+    // disk the worker's blobs can take, creating more throws a RangeError
+    pub max_bytes: u64,
+}
+
+impl Default for BlobSpillOpts {
+    fn default() -> BlobSpillOpts {
+        BlobSpillOpts {
+            threshold_bytes: 1024 * 1024,
+            max_bytes: 512 * 1024 * 1024,
+        }
+    }
+}
+
+// Identical GET requests sent to a user worker while one of them is being
+// served wait for its response rather than invoking the worker again.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct CoalesceOpts {
+    // requests are identical if they have the same path, query, origin and
+    // values of these headers. ones with credentials are never coalesced
+    pub vary_headers: Vec<String>,
+    // larger responses aren't shared, the waiting requests are sent to the
+    // worker once it's read
+    pub max_body_bytes: usize,
+}
+
+impl Default for CoalesceOpts {
+    fn default() -> CoalesceOpts {
+        CoalesceOpts {
+            vary_headers: vec!["accept".to_string(), "accept-encoding".to_string()],
+            max_body_bytes: 1024 * 1024,
+        }
+    }
+}
+
+// Pins the requests with the same session key to one of the isolates of an
+// autoscaled user worker. The key is the first of the header, the cookie and
+// the claim of the bearer token (which isn't verified) the request has.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct StickyOpts {
+    pub header: Option<String>,
+    pub cookie: Option<String>,
+    pub jwt_claim: Option<String>,
+    // sessions without requests for this long are forgotten
+    pub session_ttl_ms: u64,
+}
+
+impl Default for StickyOpts {
+    fn default() -> StickyOpts {
+        StickyOpts {
+            header: None,
+            cookie: None,
+            jwt_claim: None,
+            session_ttl_ms: 30 * 60 * 1000,
+        }
+    }
+}
+
+// Filters the console output of a user worker and limits its rate, so a
+// chatty worker can't flood the logs of the node.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct LogOpts {
+    // less severe messages are left out
+    pub level: LogLevel,
+    // messages written per second, after a burst of up to `burst` of them
+    pub rate_per_sec: f64,
+    pub burst: u32,
+}
+
+impl Default for LogOpts {
+    fn default() -> LogOpts {
+        LogOpts {
+            level: LogLevel::Debug,
+            rate_per_sec: 100.0,
+            burst: 200,
+        }
+    }
+}
+
+// Copies the requests of a user worker and its responses to its events
+// channel, for audit logs.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct AuditOpts {
+    // share of the requests recorded, between 0 and 1
+    pub sample_rate: f64,
+    // bytes kept of each body, the rest is still streamed to the worker or client
+    pub max_body_bytes: usize,
+    // regexes matched against the lowercased header names, the values of the
+    // matching headers are redacted
+    pub redact_headers: Vec<String>,
+}
+
+impl Default for AuditOpts {
+    fn default() -> AuditOpts {
+        AuditOpts {
+            sample_rate: 1.0,
+            max_body_bytes: 16 * 1024,
+            redact_headers: vec![
+                "^authorization$".to_string(),
+                "^proxy-authorization$".to_string(),
+                "^cookie$".to_string(),
+                "^set-cookie$".to_string(),
+                "^x-api-key$".to_string(),
+            ],
+        }
+    }
+}
+
+impl Default for AutoscaleOpts {
+    fn default() -> AutoscaleOpts {
+        AutoscaleOpts {
+            min_workers: 1,
+            max_workers: 1,
+            max_queue_depth: 0,
+            max_p95_latency_ms: None,
+            idle_timeout_ms: 30000,
+        }
+    }
+}
+
+// Scheduling class of a user worker, declared from the lowest priority up.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum WorkerPriority {
+    // preempted when memory is needed by the other classes
+    Batch,
+    #[default]
+    Normal,
+    High,
+    System,
+}
+
+// What a user worker can do past serving requests and making fetch calls. In
+// strict mode, the extensions of the ones its service's manifest doesn't ask
+// for are left out of its isolates.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Capability {
+    // sockets: `Deno.connect`, `Deno.connectTls`, `Deno.startTls`,
+    // `Deno.listenDatagram` and `Deno.resolveDns`
+    Net,
+}
+
+// Behaviour changes a service opts into with the compatibility date of its
+// manifest, so the functions written before them keep working unchanged.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CompatFlag {
+    // unhandled promise rejections terminate the worker, as with
+    // `terminate_on_unhandled_rejection`
+    UnhandledRejectionTerminates,
+    // private and secret keys can't be exported, as without `allow_key_export`
+    RestrictKeyExport,
+}
+
+// Unstable APIs a worker can be given access to, they throw otherwise.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum UnstableFeature {
+    // `Deno.openKv`
+    Kv,
+    // `Deno.cron`
+    Cron,
+    // `Deno.dlopen`
+    Ffi,
+}
+
+// Connections a user worker gets to the databases it queries with
+// `EdgeRuntime.postgres`.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct PostgresOpts {
+    // connections kept to a database, shared by the workers of the service
+    pub pool_size: usize,
+    // connections the worker can use at once
+    pub max_checkouts: usize,
+}
+
+impl Default for PostgresOpts {
+    fn default() -> PostgresOpts {
+        PostgresOpts {
+            pool_size: 10,
+            max_checkouts: 3,
+        }
+    }
+}
+
+// Inference a user worker can run with `EdgeRuntime.ai`.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct AiOpts {
+    // embed and run calls over the worker's lifetime, any if unset
+    pub max_calls: Option<u64>,
+    // calls running at once
+    pub max_concurrent: usize,
+}
+
+impl Default for AiOpts {
+    fn default() -> AiOpts {
+        AiOpts {
+            max_calls: None,
+            max_concurrent: 2,
+        }
+    }
+}
+
+// Host-held keys a user worker can sign tokens with, see `EdgeRuntime.jwt`.
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct JwtOpts {
+    // ids of the keys, as given to `--jwt-key`
+    pub sign_keys: Vec<String>,
+}
+
+// Objects a user worker can use with `EdgeRuntime.storage`.
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct StorageOpts {
+    // the server's bucket if unset
+    pub bucket: Option<String>,
+    // the worker can only use keys under it, its service name if unset
+    pub prefix: Option<String>,
+}
+
+// PEM encoded certificate chain and private key fetch presents to servers
+// asking for a client certificate (mTLS).
+#[derive(Clone, PartialEq, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ClientCertOpts {
+    pub cert_chain: String,
+    pub private_key: String,
+}
+
+// keeps the private key out of logs
+impl std::fmt::Debug for ClientCertOpts {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ClientCertOpts")
+            .field("cert_chain", &self.cert_chain)
+            .finish_non_exhaustive()
+    }
+}
+
+// User workers a worker can call with `EdgeRuntime.services`, by binding name.
+// Requests go to them through the pool instead of the public listener.
+#[derive(Debug, Clone)]
+pub struct ServiceBindings {
+    pub worker_pool_tx: mpsc::UnboundedSender<UserWorkerMsgs>,
+    pub workers: HashMap<String, Uuid>,
+}
+
 #[derive(Debug, Clone)]
 pub struct EdgeUserRuntimeOpts {
     pub memory_limit_mb: u64,
     pub worker_timeout_ms: u64,
+    // deadline for a single request, the worker may outlive it (up to `worker_timeout_ms`)
+    pub request_timeout_ms: Option<u64>,
+    // requests an isolate handles at once, interleaved on its event loop. unlimited if unset
+    pub max_concurrent_requests: Option<usize>,
+    pub backpressure: Option<BackpressureOpts>,
+    // number of times a worker that failed to boot is replaced before giving up
+    pub boot_retries: u32,
+    pub boot_retry_backoff_ms: u64,
     pub id: String,
+    // name of the service the worker belongs to, used to pick one of its deployed versions
+    pub service_name: Option<String>,
+    // deployed version of the service the worker was routed to, set by the pool
+    pub deployment: Option<String>,
+    // request headers considered when routing between deployed versions, and
+    // to identify the caller when rate limiting by ip or token (lowercase names)
+    pub routing_headers: HashMap<String, String>,
+    // sha256 of a remote service source, checked before the worker boots from it
+    pub service_checksum: Option<String>,
+    // how long a cached remote service source is used before it's fetched again
+    pub source_max_age_ms: Option<u64>,
+    // global import map, the service's own import map is merged on top of it
+    pub base_import_map_path: Option<String>,
+    // type check the service before booting it (meant for development)
+    pub type_check: bool,
+    // spawn more isolates for the worker under load, a single isolate if unset
+    pub autoscale: Option<AutoscaleOpts>,
+    pub priority: WorkerPriority,
+    // default locale of Intl in the worker, the host's otherwise (eg: en-US)
+    pub locale: Option<String>,
+    // default time zone of Intl and `Date.prototype.toLocale*String` in the
+    // worker, the process's (`TZ`) otherwise (eg: Europe/Paris)
+    pub timezone: Option<String>,
+    pub client_cert: Option<ClientCertOpts>,
+    // seeds the random values of `crypto` (getRandomValues, randomUUID, generated
+    // keys) so tests get the same ones on every run. unset in production, where
+    // each isolate draws from its thread's generator, seeded by the OS
+    pub crypto_seed: Option<u64>,
+    // `crypto.subtle.exportKey` and `wrapKey` of private and secret keys, only
+    // public keys can be exported otherwise
+    pub allow_key_export: bool,
+    // hosts (`host`, `host:port` or `*.domain`) fetch, websockets and
+    // Deno.connect can reach, any if unset
+    pub net_allowlist: Option<Vec<String>>,
+    // Deno.listenDatagram, only the main worker can use it otherwise
+    pub allow_udp: bool,
+    // the only capabilities built into the worker in strict mode, all of
+    // them (behind the permissions) if unset
+    pub capabilities: Option<Vec<Capability>>,
+    // behaviour changes of the service's compatibility date, set by the pool
+    pub compat_flags: Vec<CompatFlag>,
+    pub postgres: Option<PostgresOpts>,
+    pub ai: Option<AiOpts>,
+    pub jwt: Option<JwtOpts>,
+    pub storage: Option<StorageOpts>,
+    pub service_bindings: Option<ServiceBindings>,
+    // how deep the service calls the worker handles are, set by the pool
+    pub binding_depths: Arc<BindingDepths>,
+    pub event_loop_lag_threshold_ms: Option<u64>,
+    pub terminate_on_blocked_event_loop: bool,
+    pub terminate_on_unhandled_rejection: bool,
+    // grace period given to the ops the main module left pending once it
+    // evaluated (eg: streaming response bodies) before the worker is
+    // terminated on reaching its wall clock limit
+    pub drain_timeout_ms: Option<u64>,
+    // how much the worker can push back its wall clock limit with
+    // `EdgeRuntime.extendDeadline`, it can't if unset
+    pub max_wall_clock_extension_ms: Option<u64>,
+    // bytes a fetch response body can have, reading past it throws
+    pub max_fetch_response_bytes: Option<u64>,
+    pub fetch_policy: FetchPolicyOpts,
+    // bytes the worker sends and receives, counted by its isolates
+    pub net_usage: Arc<NetUsage>,
+    // sockets the worker can have open at once, fetch calls included
+    pub max_open_sockets: Option<u32>,
+    // the sockets the worker has open, counted by its isolates
+    pub socket_usage: Arc<SocketUsage>,
+    pub audit: Option<AuditOpts>,
+    pub blob_spill: Option<BlobSpillOpts>,
+    pub coalesce: Option<CoalesceOpts>,
+    pub sticky: Option<StickyOpts>,
+    // set by the pool when fetch calls are broken per host
+    pub fetch_breakers: Option<FetchBreakers>,
+    // set by the pool when invocations are billed, the isolates report what
+    // they spent on each request in it
+    pub invocation_usages: Option<Arc<InvocationUsages>>,
+    pub events_tx: Option<WorkerEventsTx>,
+    // send console output to `events_tx` instead of printing it
+    pub forward_logs: bool,
+    pub logs: Option<LogOpts>,
 }
 
 #[derive(Debug, Clone)]
 pub struct EdgeMainRuntimeOpts {
     pub worker_pool_tx: mpsc::UnboundedSender<UserWorkerMsgs>,
+    // modules fetched and compiled before the main module is loaded, so
+    // user workers importing them are served from the module cache
+    pub warmup_specifiers: Vec<String>,
+    // the paths its `EdgeRuntime.router` serves
+    pub routes: RouteTable,
+    pub fetch_breakers: Option<FetchBreakers>,
 }
 
 #[derive(Debug, Clone)]
@@ -23,12 +413,107 @@ pub enum EdgeContextOpts {
     MainWorker(EdgeMainRuntimeOpts),
 }
 
+// Timeouts and retries of the fetch calls of a user worker, so a hung
+// upstream doesn't hold the worker until it's killed.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct FetchPolicyOpts {
+    // until the response headers are received, connecting included
+    pub connect_timeout_ms: Option<u64>,
+    // between two chunks of the response body
+    pub read_timeout_ms: Option<u64>,
+    // of the calls with an idempotent method and a body that can be sent
+    // again, after network errors, timeouts, 502, 503 and 504 responses
+    pub retries: u32,
+    // doubled after each retry
+    pub retry_backoff_ms: u64,
+}
+
+impl Default for FetchPolicyOpts {
+    fn default() -> FetchPolicyOpts {
+        FetchPolicyOpts {
+            connect_timeout_ms: Some(30000),
+            read_timeout_ms: Some(30000),
+            retries: 0,
+            retry_backoff_ms: 100,
+        }
+    }
+}
+
+// When the fetch calls of workers to a failing host fail fast, see
+// `FetchBreakers`.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct FetchBreakerOpts {
+    // failures in a row (network errors, 502, 503 and 504) opening the circuit
+    pub failure_threshold: u32,
+    // how long calls fail fast before the host is tried again
+    pub cooldown_ms: u64,
+}
+
+impl Default for FetchBreakerOpts {
+    fn default() -> FetchBreakerOpts {
+        FetchBreakerOpts {
+            failure_threshold: 5,
+            cooldown_ms: 30000,
+        }
+    }
+}
+
+// How the requests a worker makes with fetch and WebSocket identify themselves.
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct OutboundOpts {
+    // `supabase-edge-runtime` if unset
+    pub user_agent: Option<String>,
+    // added to the fetch requests that don't set them (eg: X-Deployment-Id)
+    pub default_headers: Vec<(String, String)>,
+    // set for the whole pool, the breakers are shared by its workers
+    pub fetch_breaker: Option<FetchBreakerOpts>,
+}
+
+impl OutboundOpts {
+    // `self`, with what it doesn't set taken from `parent`
+    pub fn inherit(self, parent: &OutboundOpts) -> OutboundOpts {
+        let mut default_headers: Vec<(String, String)> = parent
+            .default_headers
+            .iter()
+            .filter(|(name, _)| {
+                !self
+                    .default_headers
+                    .iter()
+                    .any(|(own, _)| own.eq_ignore_ascii_case(name))
+            })
+            .cloned()
+            .collect();
+        default_headers.extend(self.default_headers);
+
+        OutboundOpts {
+            user_agent: self.user_agent.or_else(|| parent.user_agent.clone()),
+            default_headers,
+            fetch_breaker: self.fetch_breaker.or_else(|| parent.fetch_breaker.clone()),
+        }
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct EdgeContextInitOpts {
     pub service_path: PathBuf,
     pub no_module_cache: bool,
+    // only load remote modules from the module cache, failing the boot otherwise
+    pub offline: bool,
     pub import_map_path: Option<String>,
+    // `token@host` pairs separated by `;` (same format as `DENO_AUTH_TOKENS`),
+    // used to authenticate requests for remote modules
+    pub auth_tokens: Option<String>,
     pub env_vars: HashMap<String, String>,
+    // stubs or records the outbound fetch calls of the worker (eg: in tests)
+    pub fetch_interceptor: Option<Arc<dyn FetchInterceptor>>,
+    // user workers inherit the main worker's
+    pub outbound: OutboundOpts,
+    // ops and state of the embedder, inherited by the user workers
+    pub extensions: Option<Arc<dyn WorkerExtensions>>,
+    pub unstable_features: Vec<UnstableFeature>,
     pub conf: EdgeContextOpts,
 }
 
@@ -37,7 +522,55 @@ impl Default for EdgeUserRuntimeOpts {
         EdgeUserRuntimeOpts {
             memory_limit_mb: 150,
             worker_timeout_ms: 60000,
+            request_timeout_ms: None,
+            max_concurrent_requests: None,
+            backpressure: None,
+            boot_retries: 0,
+            boot_retry_backoff_ms: 100,
             id: String::from("Unknown"),
+            service_name: None,
+            deployment: None,
+            routing_headers: HashMap::new(),
+            service_checksum: None,
+            source_max_age_ms: None,
+            base_import_map_path: None,
+            type_check: false,
+            autoscale: None,
+            priority: WorkerPriority::Normal,
+            locale: None,
+            timezone: None,
+            client_cert: None,
+            crypto_seed: None,
+            allow_key_export: true,
+            net_allowlist: None,
+            allow_udp: false,
+            capabilities: None,
+            compat_flags: vec![],
+            postgres: None,
+            ai: None,
+            jwt: None,
+            storage: None,
+            service_bindings: None,
+            binding_depths: Arc::default(),
+            event_loop_lag_threshold_ms: None,
+            terminate_on_blocked_event_loop: false,
+            terminate_on_unhandled_rejection: false,
+            drain_timeout_ms: None,
+            max_wall_clock_extension_ms: None,
+            max_fetch_response_bytes: None,
+            fetch_policy: FetchPolicyOpts::default(),
+            net_usage: Arc::default(),
+            max_open_sockets: None,
+            socket_usage: Arc::default(),
+            audit: None,
+            blob_spill: None,
+            coalesce: None,
+            sticky: None,
+            fetch_breakers: None,
+            invocation_usages: None,
+            events_tx: None,
+            forward_logs: false,
+            logs: None,
         }
     }
 }
@@ -59,6 +592,17 @@ pub enum UserWorkerMsgs {
         oneshot::Sender<Result<CreateUserWorkerResult, Error>>,
     ),
     SendRequest(Uuid, Request<Body>, oneshot::Sender<Response<Body>>),
+    // what the worker sent and received so far, `None` once it's gone
+    NetUsage(Uuid, oneshot::Sender<Option<NetUsageSnapshot>>),
+    // the sockets the worker has open, `None` once it's gone
+    OpenSockets(Uuid, oneshot::Sender<Option<OpenSockets>>),
+    // the response the runtime gives to a request to a service without
+    // booting a worker (CORS preflights, 404s and 405s), `None` if it doesn't
+    Preflight(
+        String,
+        Request<Body>,
+        oneshot::Sender<Result<Option<Response<Body>>, Error>>,
+    ),
 }
 
 #[derive(Debug)]
@@ -0,0 +1,118 @@
+use crate::net_usage::NetUsageSnapshot;
+use crate::worker_id::WorkerId;
+use serde::{Deserialize, Serialize};
+use tokio::sync::mpsc;
+
+#[derive(Debug, Clone)]
+pub struct EventLoopBlockedEvent {
+    pub lag_ms: u64,
+    pub js_stack: Option<String>,
+}
+
+#[derive(Debug, Clone)]
+pub struct UncaughtExceptionEvent {
+    pub message: String,
+    pub stack: Option<String>,
+    // the requests the worker was handling, the error came from one of them
+    pub request_ids: Vec<String>,
+}
+
+// from the least to the most severe
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LogLevel {
+    Debug,
+    Info,
+    #[serde(alias = "warn")]
+    Warning,
+    Error,
+}
+
+#[derive(Debug, Clone)]
+pub struct LogEvent {
+    pub msg: String,
+    pub level: LogLevel,
+}
+
+// Up to `AuditOpts.max_body_bytes` of a body, `size` is the whole body's.
+#[derive(Debug, Clone, Default)]
+pub struct CapturedBody {
+    pub data: Vec<u8>,
+    pub size: u64,
+}
+
+impl CapturedBody {
+    pub fn truncated(&self) -> bool {
+        (self.data.len() as u64) < self.size
+    }
+}
+
+// A request handled by a user worker and its response, the values of the
+// sensitive headers are redacted.
+#[derive(Debug, Clone)]
+pub struct AuditEvent {
+    pub request_id: Option<String>,
+    pub method: String,
+    pub url: String,
+    pub request_headers: Vec<(String, String)>,
+    pub request_body: CapturedBody,
+    pub status: u16,
+    pub response_headers: Vec<(String, String)>,
+    pub response_body: CapturedBody,
+}
+
+// A user worker was refused something by its permissions, eg: a host out of
+// its allowlist.
+#[derive(Debug, Clone)]
+pub struct PermissionDeniedEvent {
+    // eg: `Deno.connect()`, `fetch()`
+    pub api_name: String,
+    // the host (and port), path or kind of socket it was refused
+    pub resource: String,
+}
+
+// What a request handled by a user worker is billed for, sent once its
+// response body is streamed.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BillingEvent {
+    pub request_id: Option<String>,
+    pub service: String,
+    // the deployed version the request went to
+    pub deployment: Option<String>,
+    // unix time in ms
+    pub started_at: u64,
+    // until the response body was streamed
+    pub duration_ms: u64,
+    // 0 if the isolate didn't respond, eg: the request timed out
+    pub cpu_ms: f64,
+    // the memory limit of the worker's isolates
+    pub memory_mb: u64,
+    // `memory_mb` for `duration_ms`
+    pub memory_mb_s: f64,
+    pub peak_memory_bytes: u64,
+    // bytes of the response body
+    pub egress_bytes: u64,
+    pub status: u16,
+}
+
+#[derive(Debug, Clone)]
+pub enum WorkerEvents {
+    EventLoopBlocked(EventLoopBlockedEvent),
+    UncaughtException(UncaughtExceptionEvent),
+    UnhandledRejection(UncaughtExceptionEvent),
+    Log(LogEvent),
+    // sent once the worker is gone, with what its isolates sent and received
+    NetUsage(NetUsageSnapshot),
+    Audit(AuditEvent),
+    PermissionDenied(PermissionDeniedEvent),
+    Billing(BillingEvent),
+}
+
+#[derive(Debug, Clone)]
+pub struct WorkerEventWithMetadata {
+    pub worker_id: WorkerId,
+    pub event: WorkerEvents,
+}
+
+pub type WorkerEventsTx = mpsc::UnboundedSender<WorkerEventWithMetadata>;
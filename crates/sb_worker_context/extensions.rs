@@ -0,0 +1,19 @@
+use deno_core::{Extension, OpState};
+use std::fmt::Debug;
+use std::sync::Arc;
+
+// Ops and state an embedder adds to its workers (eg: billing or storage),
+// without patching the runtime.
+pub trait WorkerExtensions: Send + Sync + Debug {
+    // Appended after the runtime's extensions. `with_esm` is set when isolates
+    // start without a snapshot, their js has to be loaded with them then.
+    fn extensions(&self, with_esm: bool) -> Vec<Extension>;
+
+    // Called once the isolate is created, before its main module is loaded.
+    fn init_state(&self, _state: &mut OpState) {}
+}
+
+// Kept in the op state of a worker with extensions, so the user workers it
+// creates get them too.
+#[derive(Debug, Clone)]
+pub struct WorkerExtensionsState(pub Arc<dyn WorkerExtensions>);
@@ -0,0 +1,82 @@
+use serde::{Deserialize, Serialize};
+use std::fmt::Debug;
+use std::sync::Mutex;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct InterceptedRequest {
+    pub method: String,
+    pub url: String,
+    pub headers: Vec<(String, String)>,
+    pub body: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct StubResponse {
+    pub status: u16,
+    pub headers: Vec<(String, String)>,
+    pub body: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "action", rename_all = "camelCase")]
+pub enum FetchInterception {
+    // let the request reach the network
+    PassThrough,
+    Respond(StubResponse),
+    // fail the fetch call with the given message
+    Reject { message: String },
+}
+
+// Host-side hook called for every outbound fetch made by a worker.
+pub trait FetchInterceptor: Send + Sync + Debug {
+    fn intercept(&self, req: &InterceptedRequest) -> FetchInterception;
+}
+
+// Stubs requests by url prefix and records every request it sees. Requests
+// that don't match any stub are rejected, unless `allow_passthrough` is set.
+#[derive(Debug, Default)]
+pub struct MockFetchLayer {
+    stubs: Vec<(String, StubResponse)>,
+    allow_passthrough: bool,
+    recorded: Mutex<Vec<InterceptedRequest>>,
+}
+
+impl MockFetchLayer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn stub(mut self, url_prefix: &str, response: StubResponse) -> Self {
+        self.stubs.push((url_prefix.to_string(), response));
+        self
+    }
+
+    pub fn allow_passthrough(mut self, allow: bool) -> Self {
+        self.allow_passthrough = allow;
+        self
+    }
+
+    pub fn recorded(&self) -> Vec<InterceptedRequest> {
+        self.recorded.lock().unwrap().clone()
+    }
+}
+
+impl FetchInterceptor for MockFetchLayer {
+    fn intercept(&self, req: &InterceptedRequest) -> FetchInterception {
+        self.recorded.lock().unwrap().push(req.clone());
+
+        let stub = self
+            .stubs
+            .iter()
+            .find(|(prefix, _)| req.url.starts_with(prefix));
+        match stub {
+            Some((_, response)) => FetchInterception::Respond(response.clone()),
+            None if self.allow_passthrough => FetchInterception::PassThrough,
+            None => FetchInterception::Reject {
+                message: format!("fetch to {} is not stubbed", req.url),
+            },
+        }
+    }
+}
@@ -0,0 +1,122 @@
+use crate::essentials::FetchBreakerOpts;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+// hosts tracked at once, the failures of the ones past it aren't counted
+const MAX_HOSTS: usize = 10_000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum BreakerState {
+    Closed,
+    // fetch calls to the host fail right away
+    Open,
+    // the cool-down is over, the next call is let through to try the host
+    HalfOpen,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BreakerSnapshot {
+    pub host: String,
+    pub state: BreakerState,
+    pub consecutive_failures: u32,
+}
+
+#[derive(Debug, Default)]
+struct Breaker {
+    consecutive_failures: u32,
+    opened_at: Option<Instant>,
+    // when the last call was let through to try the host again
+    trial_at: Option<Instant>,
+}
+
+// Circuit breakers of the hosts workers fetch from, shared by all the workers
+// of the pool. After `failure_threshold` failures in a row, the calls to a
+// host fail without reaching it until the cool-down is over, rather than
+// piling up on a dead upstream until the workers time out.
+#[derive(Debug, Clone)]
+pub struct FetchBreakers {
+    opts: FetchBreakerOpts,
+    hosts: Arc<Mutex<HashMap<String, Breaker>>>,
+}
+
+impl FetchBreakers {
+    pub fn new(opts: FetchBreakerOpts) -> Self {
+        Self {
+            opts,
+            hosts: Arc::default(),
+        }
+    }
+
+    fn cooldown(&self) -> Duration {
+        Duration::from_millis(self.opts.cooldown_ms)
+    }
+
+    // `Err` with the time left until the host is tried again while its
+    // circuit is open
+    pub fn check(&self, host: &str) -> Result<(), Duration> {
+        let mut hosts = self.hosts.lock().unwrap();
+        let Some(breaker) = hosts.get_mut(host) else {
+            return Ok(());
+        };
+        let Some(opened_at) = breaker.opened_at else {
+            return Ok(());
+        };
+
+        // a single call is let through per cool-down
+        let elapsed = breaker.trial_at.unwrap_or(opened_at).elapsed();
+        if elapsed < self.cooldown() {
+            return Err(self.cooldown() - elapsed);
+        }
+        breaker.trial_at = Some(Instant::now());
+        Ok(())
+    }
+
+    // the outcome of a call to the host, a success closes its circuit
+    pub fn report(&self, host: &str, ok: bool) {
+        let mut hosts = self.hosts.lock().unwrap();
+        if ok {
+            hosts.remove(host);
+            return;
+        }
+        if hosts.len() >= MAX_HOSTS && !hosts.contains_key(host) {
+            return;
+        }
+
+        let breaker = hosts.entry(host.to_string()).or_default();
+        breaker.consecutive_failures += 1;
+        if breaker.consecutive_failures >= self.opts.failure_threshold.max(1) {
+            breaker.opened_at = Some(Instant::now());
+            breaker.trial_at = None;
+        }
+    }
+
+    // the hosts whose last calls failed
+    pub fn snapshot(&self) -> Vec<BreakerSnapshot> {
+        let hosts = self.hosts.lock().unwrap();
+        let mut snapshot: Vec<BreakerSnapshot> = hosts
+            .iter()
+            .map(|(host, breaker)| {
+                let state = match breaker.opened_at {
+                    None => BreakerState::Closed,
+                    Some(opened_at)
+                        if breaker.trial_at.is_none() && opened_at.elapsed() < self.cooldown() =>
+                    {
+                        BreakerState::Open
+                    }
+                    Some(_) => BreakerState::HalfOpen,
+                };
+                BreakerSnapshot {
+                    host: host.clone(),
+                    state,
+                    consecutive_failures: breaker.consecutive_failures,
+                }
+            })
+            .collect();
+        snapshot.sort_by(|a, b| a.host.cmp(&b.host));
+        snapshot
+    }
+}
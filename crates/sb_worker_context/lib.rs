@@ -1 +1,13 @@
+pub mod billing;
+pub mod binding_depth;
 pub mod essentials;
+pub mod events;
+pub mod extensions;
+pub mod fetch;
+pub mod fetch_breaker;
+pub mod net_usage;
+pub mod open_sockets;
+pub mod rate_limit;
+pub mod resolution;
+pub mod routes;
+pub mod worker_id;
@@ -0,0 +1,84 @@
+use serde::{Deserialize, Serialize};
+use std::sync::atomic::{AtomicU64, Ordering};
+
+// What a worker's bytes went through.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum NetChannel {
+    Fetch,
+    // connections opened with `Deno.connect`, `Deno.connectTls` and `Deno.startTls`
+    Net,
+    WebSocket,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Traffic {
+    pub sent: u64,
+    pub received: u64,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct NetUsageSnapshot {
+    pub fetch: Traffic,
+    pub net: Traffic,
+    pub web_socket: Traffic,
+}
+
+impl NetUsageSnapshot {
+    pub fn total(&self) -> Traffic {
+        Traffic {
+            sent: self.fetch.sent + self.net.sent + self.web_socket.sent,
+            received: self.fetch.received + self.net.received + self.web_socket.received,
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+struct TrafficCounter {
+    sent: AtomicU64,
+    received: AtomicU64,
+}
+
+impl TrafficCounter {
+    fn add(&self, sent: u64, received: u64) {
+        self.sent.fetch_add(sent, Ordering::Relaxed);
+        self.received.fetch_add(received, Ordering::Relaxed);
+    }
+
+    fn get(&self) -> Traffic {
+        Traffic {
+            sent: self.sent.load(Ordering::Relaxed),
+            received: self.received.load(Ordering::Relaxed),
+        }
+    }
+}
+
+// Bytes a worker sent and received over the network, shared by the isolates
+// of an autoscaled worker. Only the bodies of fetch requests and responses
+// and the data of websocket messages are counted, not their headers.
+#[derive(Debug, Default)]
+pub struct NetUsage {
+    fetch: TrafficCounter,
+    net: TrafficCounter,
+    web_socket: TrafficCounter,
+}
+
+impl NetUsage {
+    pub fn add(&self, channel: NetChannel, sent: u64, received: u64) {
+        match channel {
+            NetChannel::Fetch => self.fetch.add(sent, received),
+            NetChannel::Net => self.net.add(sent, received),
+            NetChannel::WebSocket => self.web_socket.add(sent, received),
+        }
+    }
+
+    pub fn snapshot(&self) -> NetUsageSnapshot {
+        NetUsageSnapshot {
+            fetch: self.fetch.get(),
+            net: self.net.get(),
+            web_socket: self.web_socket.get(),
+        }
+    }
+}
@@ -0,0 +1,56 @@
+use serde::Serialize;
+use std::sync::atomic::{AtomicU32, Ordering};
+
+// The sockets a worker has open, each holding a file descriptor of the
+// process. Workers can't open files, sockets are all they can leak.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct OpenSockets {
+    // opened with `Deno.connect`, `Deno.connectTls` and `Deno.startTls`
+    pub connections: u32,
+    // `Deno.listenDatagram`
+    pub datagrams: u32,
+    pub web_sockets: u32,
+    // fetch calls waiting for a response or reading its body
+    pub fetches: u32,
+}
+
+impl OpenSockets {
+    pub fn total(&self) -> u32 {
+        self.connections + self.datagrams + self.web_sockets + self.fetches
+    }
+}
+
+// The sockets open in the isolates of a worker, as of the last time each of
+// them counted its own.
+#[derive(Debug, Default)]
+pub struct SocketUsage {
+    connections: AtomicU32,
+    datagrams: AtomicU32,
+    web_sockets: AtomicU32,
+    fetches: AtomicU32,
+}
+
+impl SocketUsage {
+    // replaces what an isolate counted before with what it counts now
+    pub fn update(&self, before: &OpenSockets, now: &OpenSockets) {
+        for (counter, before, now) in [
+            (&self.connections, before.connections, now.connections),
+            (&self.datagrams, before.datagrams, now.datagrams),
+            (&self.web_sockets, before.web_sockets, now.web_sockets),
+            (&self.fetches, before.fetches, now.fetches),
+        ] {
+            // wraps around to subtract
+            counter.fetch_add(now.wrapping_sub(before), Ordering::Relaxed);
+        }
+    }
+
+    pub fn snapshot(&self) -> OpenSockets {
+        OpenSockets {
+            connections: self.connections.load(Ordering::Relaxed),
+            datagrams: self.datagrams.load(Ordering::Relaxed),
+            web_sockets: self.web_sockets.load(Ordering::Relaxed),
+            fetches: self.fetches.load(Ordering::Relaxed),
+        }
+    }
+}
@@ -0,0 +1,21 @@
+use std::fmt;
+
+// Returned when creating a user worker would go over the rate limit of its
+// service (or caller), so the main worker can answer with a 429.
+#[derive(Debug, Clone)]
+pub struct RateLimited {
+    pub key: String,
+    pub retry_after_ms: u64,
+}
+
+impl fmt::Display for RateLimited {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "rate limit exceeded for {}, retry in {}ms",
+            self.key, self.retry_after_ms
+        )
+    }
+}
+
+impl std::error::Error for RateLimited {}
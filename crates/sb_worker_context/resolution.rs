@@ -0,0 +1,35 @@
+use serde::Serialize;
+use std::fmt;
+
+// Describes why a module specifier couldn't be resolved, returned by the
+// module loader so the failure can be reported to the caller as is.
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ResolutionDiagnostic {
+    pub specifier: String,
+    pub referrer: String,
+    pub reason: String,
+    // import map keys close to the specifier, best match first
+    pub suggestions: Vec<String>,
+    // modules that led to the import, from the referrer up to the entrypoint
+    pub chain: Vec<String>,
+}
+
+impl fmt::Display for ResolutionDiagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "failed to resolve \"{}\": {}",
+            self.specifier, self.reason
+        )?;
+        if !self.suggestions.is_empty() {
+            write!(f, "\n  did you mean: {}", self.suggestions.join(", "))?;
+        }
+        for module in &self.chain {
+            write!(f, "\n    imported from {}", module)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for ResolutionDiagnostic {}
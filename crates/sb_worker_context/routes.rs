@@ -0,0 +1,96 @@
+use std::sync::{Arc, RwLock};
+
+// The paths the main worker routes to user workers, registered by its
+// `EdgeRuntime.router`. Requests to paths none of them can match are answered
+// with a 404 by the server, without reaching the main worker.
+#[derive(Debug, Clone, Default)]
+pub struct RouteTable {
+    // the literal start of each pattern, nothing is filtered until some are
+    // registered
+    prefixes: Arc<RwLock<Option<Vec<String>>>>,
+}
+
+// The start of a URLPattern pathname before anything it doesn't match
+// literally, eg: `/users/` for `/users/:id`.
+fn static_prefix(pattern: &str) -> &str {
+    let end = pattern
+        .find(|c| matches!(c, ':' | '*' | '(' | '{' | '?' | '+' | '\\'))
+        .unwrap_or(pattern.len());
+    &pattern[..end]
+}
+
+impl RouteTable {
+    // replaces the registered patterns (URLPattern pathnames)
+    pub fn register(&self, patterns: &[String]) {
+        let prefixes = patterns
+            .iter()
+            .map(|pattern| static_prefix(pattern).to_string())
+            .collect();
+        *self.prefixes.write().unwrap() = Some(prefixes);
+    }
+
+    // false only if `path` can't match any of the patterns
+    pub fn may_match(&self, path: &str) -> bool {
+        match self.prefixes.read().unwrap().as_ref() {
+            None => true,
+            Some(prefixes) => prefixes.iter().any(|prefix| path.starts_with(prefix)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_may_match_before_register() {
+        let routes = RouteTable::default();
+        assert!(routes.may_match("/"));
+        assert!(routes.may_match("/anything/at/all"));
+    }
+
+    #[test]
+    fn test_may_match_static() {
+        let routes = RouteTable::default();
+        routes.register(&["/health".to_string(), "/api/v1/users".to_string()]);
+        assert!(routes.may_match("/health"));
+        assert!(routes.may_match("/api/v1/users"));
+        assert!(!routes.may_match("/"));
+        assert!(!routes.may_match("/api/v2/users"));
+    }
+
+    #[test]
+    fn test_may_match_params() {
+        let routes = RouteTable::default();
+        routes.register(&["/users/:id".to_string(), "/posts/:id(\\d+)".to_string()]);
+        assert!(routes.may_match("/users/1"));
+        assert!(routes.may_match("/posts/42"));
+        assert!(!routes.may_match("/user/1"));
+        assert!(!routes.may_match("/comments/1"));
+    }
+
+    #[test]
+    fn test_may_match_wildcards() {
+        let routes = RouteTable::default();
+        routes.register(&["/static/*".to_string()]);
+        assert!(routes.may_match("/static/app.js"));
+        assert!(!routes.may_match("/app.js"));
+
+        // a pattern matching any path lets everything through
+        routes.register(&["*".to_string()]);
+        assert!(routes.may_match("/app.js"));
+    }
+
+    #[test]
+    fn test_register_replaces() {
+        let routes = RouteTable::default();
+        routes.register(&["/old".to_string()]);
+        routes.register(&["/new".to_string()]);
+        assert!(routes.may_match("/new"));
+        assert!(!routes.may_match("/old"));
+
+        // no routes at all, nothing can match
+        routes.register(&[]);
+        assert!(!routes.may_match("/new"));
+    }
+}
@@ -0,0 +1,43 @@
+use std::fmt;
+
+// Identifies a worker in logs, events and metrics. `id` is unique to the
+// worker (the uuid the pool gave it), `label` is what humans know it by: its
+// service, and the deployed version it was routed to.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct WorkerId {
+    pub id: String,
+    pub label: String,
+}
+
+impl WorkerId {
+    pub fn new(id: String, service: &str, deployment: Option<&str>) -> Self {
+        let service = service.strip_prefix("./").unwrap_or(service);
+        let label = match deployment {
+            Some(version) => format!("{}@{}", service, version),
+            None => service.to_string(),
+        };
+        Self { id, label }
+    }
+
+    pub fn main() -> Self {
+        Self {
+            id: "main".to_string(),
+            label: "main".to_string(),
+        }
+    }
+
+    // the first block of the uuid, enough to tell the workers of a service apart
+    fn short_id(&self) -> &str {
+        self.id.split('-').next().unwrap_or(&self.id)
+    }
+}
+
+// `hello@v2 (1b4e28ba)`
+impl fmt::Display for WorkerId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.label == self.id || self.label.is_empty() {
+            return write!(f, "{}", self.id);
+        }
+        write!(f, "{} ({})", self.label, self.short_id())
+    }
+}
@@ -1,5 +1,5 @@
 use anyhow::Error;
-use deno_core::error::{custom_error, type_error, AnyError};
+use deno_core::error::{custom_error, range_error, type_error, AnyError};
 use deno_core::futures::stream::Peekable;
 use deno_core::futures::{Stream, StreamExt};
 use deno_core::op;
@@ -10,10 +10,18 @@ use deno_core::{
 use hyper::body::HttpBody;
 use hyper::header::{HeaderName, HeaderValue};
 use hyper::{Body, Request, Response};
+use sb_worker_context::binding_depth::{BindingDepth, BindingDepths};
 use sb_worker_context::essentials::{
-    CreateUserWorkerResult, EdgeContextInitOpts, EdgeContextOpts, EdgeUserRuntimeOpts,
-    UserWorkerMsgs,
+    AiOpts, AuditOpts, AutoscaleOpts, BackpressureOpts, BlobSpillOpts, ClientCertOpts,
+    CoalesceOpts, CreateUserWorkerResult, EdgeContextInitOpts, EdgeContextOpts,
+    EdgeUserRuntimeOpts, FetchPolicyOpts, JwtOpts, LogOpts, OutboundOpts, PostgresOpts,
+    ServiceBindings, StickyOpts, StorageOpts, UnstableFeature, UserWorkerMsgs, WorkerPriority,
 };
+use sb_worker_context::extensions::WorkerExtensionsState;
+use sb_worker_context::net_usage::NetUsageSnapshot;
+use sb_worker_context::open_sockets::OpenSockets;
+use sb_worker_context::rate_limit::RateLimited;
+use sb_worker_context::resolution::ResolutionDiagnostic;
 use serde::{Deserialize, Serialize};
 use std::borrow::Cow;
 use std::cell::RefCell;
@@ -21,29 +29,88 @@ use std::collections::HashMap;
 use std::path::PathBuf;
 use std::pin::Pin;
 use std::rc::Rc;
+use std::sync::Arc;
 use std::task::{Context, Poll};
 use tokio::sync::{mpsc, oneshot};
 use uuid::Uuid;
 
+// only in the main worker's isolates, user workers can't reach the pool
 deno_core::extension!(
     sb_user_workers,
     ops = [
         op_user_worker_create,
-        op_user_worker_fetch_build,
-        op_user_worker_fetch_send
+        op_user_worker_fetch_send,
+        op_user_worker_net_usage,
+        op_user_worker_open_sockets,
+        op_user_worker_preflight
     ],
     esm = ["user_workers.js"]
 );
 
+// the calls of user workers to the ones bound to them
+deno_core::extension!(
+    sb_service_bindings,
+    ops = [op_user_worker_fetch_build, op_service_binding_send],
+    esm = ["service_bindings.js"]
+);
+
+// how deep service calls made with `EdgeRuntime.services` can be nested
+const MAX_BINDING_DEPTH: u32 = 16;
+
+// the depths of the service calls a user worker handles, shared with the pool
+pub struct BindingDepthsState(pub Arc<BindingDepths>);
+
 #[derive(Deserialize, Default, Debug)]
 #[serde(rename_all = "camelCase")]
 pub struct UserWorkerCreateOptions {
     service_path: String,
     memory_limit_mb: u64,
     worker_timeout_ms: u64,
+    request_timeout_ms: Option<u64>,
+    max_concurrent_requests: Option<usize>,
+    backpressure: Option<BackpressureOpts>,
+    boot_retries: u32,
+    boot_retry_backoff_ms: u64,
     no_module_cache: bool,
     import_map_path: Option<String>,
+    auth_tokens: Option<String>,
     env_vars: Vec<(String, String)>,
+    service_name: Option<String>,
+    routing_headers: Vec<(String, String)>,
+    service_checksum: Option<String>,
+    source_max_age_ms: Option<u64>,
+    type_check: bool,
+    autoscale: Option<AutoscaleOpts>,
+    priority: WorkerPriority,
+    locale: Option<String>,
+    timezone: Option<String>,
+    client_cert: Option<ClientCertOpts>,
+    crypto_seed: Option<u64>,
+    allow_key_export: bool,
+    net_allow: Option<Vec<String>>,
+    allow_udp: bool,
+    postgres: Option<PostgresOpts>,
+    ai: Option<AiOpts>,
+    jwt: Option<JwtOpts>,
+    storage: Option<StorageOpts>,
+    // binding name and key of a user worker
+    bindings: Vec<(String, String)>,
+    user_agent: Option<String>,
+    default_headers: Vec<(String, String)>,
+    unstable: Vec<UnstableFeature>,
+    event_loop_lag_threshold_ms: Option<u64>,
+    terminate_on_blocked_event_loop: bool,
+    terminate_on_unhandled_rejection: bool,
+    drain_timeout_ms: Option<u64>,
+    max_wall_clock_extension_ms: Option<u64>,
+    max_fetch_response_bytes: Option<u64>,
+    fetch_policy: Option<FetchPolicyOpts>,
+    max_open_sockets: Option<u32>,
+    audit: Option<AuditOpts>,
+    blob_spill: Option<BlobSpillOpts>,
+    coalesce: Option<CoalesceOpts>,
+    sticky: Option<StickyOpts>,
+    logs: Option<LogOpts>,
 }
 
 #[op]
@@ -60,9 +127,50 @@ pub async fn op_user_worker_create(
             service_path,
             memory_limit_mb,
             worker_timeout_ms,
+            request_timeout_ms,
+            max_concurrent_requests,
+            backpressure,
+            boot_retries,
+            boot_retry_backoff_ms,
             no_module_cache,
             import_map_path,
+            auth_tokens,
             env_vars,
+            service_name,
+            routing_headers,
+            service_checksum,
+            source_max_age_ms,
+            type_check,
+            autoscale,
+            priority,
+            locale,
+            timezone,
+            client_cert,
+            crypto_seed,
+            allow_key_export,
+            net_allow,
+            allow_udp,
+            postgres,
+            ai,
+            jwt,
+            storage,
+            bindings,
+            user_agent,
+            default_headers,
+            unstable,
+            event_loop_lag_threshold_ms,
+            terminate_on_blocked_event_loop,
+            terminate_on_unhandled_rejection,
+            drain_timeout_ms,
+            max_wall_clock_extension_ms,
+            max_fetch_response_bytes,
+            fetch_policy,
+            max_open_sockets,
+            audit,
+            blob_spill,
+            coalesce,
+            sticky,
+            logs,
         } = opts;
 
         let mut env_vars_map = HashMap::new();
@@ -70,15 +178,149 @@ pub async fn op_user_worker_create(
             env_vars_map.insert(key, value);
         }
 
+        let service_bindings = if bindings.is_empty() {
+            None
+        } else {
+            let mut workers = HashMap::new();
+            for (name, key) in bindings {
+                let key = Uuid::parse_str(&key)
+                    .map_err(|_| type_error(format!("invalid worker bound to {}", name)))?;
+                workers.insert(name, key);
+            }
+            Some(ServiceBindings {
+                worker_pool_tx: tx.clone(),
+                workers,
+            })
+        };
+
+        for (name, value) in &default_headers {
+            if HeaderName::try_from(name.as_str()).is_err()
+                || HeaderValue::try_from(value.as_str()).is_err()
+            {
+                return Err(type_error(format!("invalid default header {}", name)));
+            }
+        }
+
+        if let Some(audit) = &audit {
+            if !(0.0..=1.0).contains(&audit.sample_rate) {
+                return Err(range_error("the audit sample rate must be between 0 and 1"));
+            }
+            for pattern in &audit.redact_headers {
+                if let Err(err) = regex::Regex::new(pattern) {
+                    return Err(type_error(format!(
+                        "invalid header pattern {}: {}",
+                        pattern, err
+                    )));
+                }
+            }
+        }
+        for name in coalesce.iter().flat_map(|opts| &opts.vary_headers) {
+            if HeaderName::try_from(name.as_str()).is_err() {
+                return Err(type_error(format!("invalid vary header {}", name)));
+            }
+        }
+        if let Some(logs) = &logs {
+            if logs.rate_per_sec <= 0.0 || logs.burst == 0 {
+                return Err(type_error(
+                    "logs.ratePerSec and logs.burst must be greater than 0",
+                ));
+            }
+        }
+        if let Some(sticky) = &sticky {
+            if sticky.header.is_none() && sticky.cookie.is_none() && sticky.jwt_claim.is_none() {
+                return Err(type_error(
+                    "sticky sessions need a header, a cookie or a jwtClaim",
+                ));
+            }
+            if let Some(name) = &sticky.header {
+                if HeaderName::try_from(name.as_str()).is_err() {
+                    return Err(type_error(format!("invalid sticky header {}", name)));
+                }
+            }
+        }
+        let outbound = OutboundOpts {
+            user_agent,
+            default_headers,
+            fetch_breaker: None,
+        }
+        .inherit(
+            &op_state
+                .try_borrow::<OutboundOpts>()
+                .cloned()
+                .unwrap_or_default(),
+        );
+
+        let routing_headers = routing_headers
+            .into_iter()
+            .map(|(key, value)| (key.to_lowercase(), value))
+            .collect();
+
         let user_worker_options = EdgeContextInitOpts {
             service_path: PathBuf::from(service_path),
             no_module_cache,
+            offline: false,
             import_map_path,
+            auth_tokens,
             env_vars: env_vars_map,
+            fetch_interceptor: None,
+            outbound,
+            extensions: op_state
+                .try_borrow::<WorkerExtensionsState>()
+                .map(|state| state.0.clone()),
+            unstable_features: unstable,
             conf: EdgeContextOpts::UserWorker(EdgeUserRuntimeOpts {
                 memory_limit_mb,
                 worker_timeout_ms,
+                request_timeout_ms,
+                max_concurrent_requests,
+                backpressure,
+                boot_retries,
+                boot_retry_backoff_ms,
                 id: "".to_string(),
+                service_name,
+                deployment: None,
+                routing_headers,
+                service_checksum,
+                source_max_age_ms,
+                base_import_map_path: None,
+                type_check,
+                autoscale,
+                priority,
+                locale,
+                timezone,
+                client_cert,
+                crypto_seed,
+                allow_key_export,
+                net_allowlist: net_allow,
+                allow_udp,
+                // from the service's manifest in strict mode, see `Capability`
+                capabilities: None,
+                compat_flags: vec![],
+                postgres,
+                ai,
+                jwt,
+                storage,
+                service_bindings,
+                binding_depths: Default::default(),
+                event_loop_lag_threshold_ms,
+                terminate_on_blocked_event_loop,
+                terminate_on_unhandled_rejection,
+                drain_timeout_ms,
+                max_wall_clock_extension_ms,
+                max_fetch_response_bytes,
+                fetch_policy: fetch_policy.unwrap_or_default(),
+                net_usage: Default::default(),
+                max_open_sockets,
+                socket_usage: Default::default(),
+                audit,
+                blob_spill,
+                coalesce,
+                sticky,
+                fetch_breakers: None,
+                invocation_usages: None,
+                events_tx: None,
+                forward_logs: false,
+                logs,
             }),
         };
 
@@ -96,11 +338,35 @@ pub async fn op_user_worker_create(
 
     // channel returns a Result<T, E>, we need to unwrap it first;
     let result = result.unwrap();
-    if result.is_err() {
-        return Err(custom_error(
-            "create_user_worker_error",
-            result.unwrap_err().to_string(),
-        ));
+    if let Err(err) = result {
+        // the diagnostic is passed along as json, see `ModuleResolutionError` in bootstrap.js
+        if let Some(diagnostic) = err
+            .chain()
+            .find_map(|e| e.downcast_ref::<ResolutionDiagnostic>())
+        {
+            return Err(custom_error(
+                "ModuleResolutionError",
+                deno_core::serde_json::json!({
+                    "message": diagnostic.to_string(),
+                    "diagnostic": diagnostic,
+                })
+                .to_string(),
+            ));
+        }
+
+        // see `RateLimitError` in bootstrap.js
+        if let Some(rate_limited) = err.chain().find_map(|e| e.downcast_ref::<RateLimited>()) {
+            return Err(custom_error(
+                "RateLimitError",
+                deno_core::serde_json::json!({
+                    "message": rate_limited.to_string(),
+                    "retryAfterMs": rate_limited.retry_after_ms,
+                })
+                .to_string(),
+            ));
+        }
+
+        return Err(custom_error("create_user_worker_error", err.to_string()));
     }
     Ok(result.unwrap().key.to_string())
 }
@@ -293,25 +559,130 @@ pub async fn op_user_worker_fetch_send(
     key: String,
     rid: ResourceId,
 ) -> Result<UserWorkerResponse, AnyError> {
-    let (tx, request) = {
-        let mut op_state = state.borrow_mut();
-        let tx = op_state
-            .borrow::<mpsc::UnboundedSender<UserWorkerMsgs>>()
-            .clone();
+    let tx = state
+        .borrow()
+        .borrow::<mpsc::UnboundedSender<UserWorkerMsgs>>()
+        .clone();
+    let request = take_request(&state, rid)?;
+    let key = Uuid::parse_str(key.as_str())?;
+    send_user_worker_request(state, tx, key, request).await
+}
 
-        let request = op_state
-            .resource_table
-            .take::<UserWorkerRequestResource>(rid)?;
+// What the user worker sent and received over the network so far, `null`
+// once it's gone.
+#[op]
+pub async fn op_user_worker_net_usage(
+    state: Rc<RefCell<OpState>>,
+    key: String,
+) -> Result<Option<NetUsageSnapshot>, AnyError> {
+    let tx = state
+        .borrow()
+        .borrow::<mpsc::UnboundedSender<UserWorkerMsgs>>()
+        .clone();
+    let key = Uuid::parse_str(key.as_str())?;
+    let (result_tx, result_rx) = oneshot::channel();
+    tx.send(UserWorkerMsgs::NetUsage(key, result_tx))?;
+    Ok(result_rx.await.unwrap_or_default())
+}
+
+// The sockets the user worker has open, `null` once it's gone.
+#[op]
+pub async fn op_user_worker_open_sockets(
+    state: Rc<RefCell<OpState>>,
+    key: String,
+) -> Result<Option<OpenSockets>, AnyError> {
+    let tx = state
+        .borrow()
+        .borrow::<mpsc::UnboundedSender<UserWorkerMsgs>>()
+        .clone();
+    let key = Uuid::parse_str(key.as_str())?;
+    let (result_tx, result_rx) = oneshot::channel();
+    tx.send(UserWorkerMsgs::OpenSockets(key, result_tx))?;
+    Ok(result_rx.await.unwrap_or_default())
+}
 
-        (tx, request)
+// Answers a request to the service at `service_path` without booting a
+// worker: 403 for clients its manifest refuses, CORS preflights, 404 if
+// there's no such service and 405 for methods its manifest doesn't list. `null` if the request goes to a worker.
+#[op]
+pub async fn op_user_worker_preflight(
+    state: Rc<RefCell<OpState>>,
+    service_path: String,
+    rid: ResourceId,
+) -> Result<Option<UserWorkerResponse>, AnyError> {
+    let tx = state
+        .borrow()
+        .borrow::<mpsc::UnboundedSender<UserWorkerMsgs>>()
+        .clone();
+    let request = take_request(&state, rid)?;
+    let (result_tx, result_rx) = oneshot::channel();
+    tx.send(UserWorkerMsgs::Preflight(service_path, request, result_tx))?;
+
+    let res = result_rx.await.map_err(|_| {
+        custom_error(
+            "user_worker_fetch",
+            "failed to answer the preflight request",
+        )
+    })??;
+    Ok(res.map(|res| into_user_worker_response(&state, res)))
+}
+
+// Calls the user worker bound to `name`, through the pool.
+#[op]
+pub async fn op_service_binding_send(
+    state: Rc<RefCell<OpState>>,
+    name: String,
+    rid: ResourceId,
+) -> Result<UserWorkerResponse, AnyError> {
+    let mut request = take_request(&state, rid)?;
+    let Some((tx, key)) = state
+        .borrow()
+        .try_borrow::<ServiceBindings>()
+        .and_then(|bindings| {
+            let key = bindings.workers.get(&name)?;
+            Some((bindings.worker_pool_tx.clone(), *key))
+        })
+    else {
+        return Err(type_error(format!("no service is bound to {}", name)));
     };
 
+    // a call made while handling a call goes one level deeper, past the limit
+    // the services are likely calling each other in a loop. The depth is
+    // kept by the pool, the headers of the request are the caller's to set
+    let depth = state
+        .borrow()
+        .try_borrow::<BindingDepthsState>()
+        .map_or(0, |depths| depths.0.deepest());
+    if depth >= MAX_BINDING_DEPTH {
+        return Err(range_error(format!(
+            "calling {} would nest service calls more than {} levels deep, they may be calling each other in a loop",
+            name, MAX_BINDING_DEPTH
+        )));
+    }
+    request.extensions_mut().insert(BindingDepth(depth + 1));
+
+    send_user_worker_request(state, tx, key, request).await
+}
+
+fn take_request(state: &Rc<RefCell<OpState>>, rid: ResourceId) -> Result<Request<Body>, AnyError> {
+    let request = state
+        .borrow_mut()
+        .resource_table
+        .take::<UserWorkerRequestResource>(rid)?;
     let request = Rc::try_unwrap(request)
         .ok()
         .expect("multiple op_user_worker_fetch_send ongoing");
+    Ok(request.0)
+}
+
+async fn send_user_worker_request(
+    state: Rc<RefCell<OpState>>,
+    tx: mpsc::UnboundedSender<UserWorkerMsgs>,
+    key: Uuid,
+    request: Request<Body>,
+) -> Result<UserWorkerResponse, AnyError> {
     let (result_tx, result_rx) = oneshot::channel::<Response<Body>>();
-    let uuid = Uuid::parse_str(key.as_str())?;
-    tx.send(UserWorkerMsgs::SendRequest(uuid, request.0, result_tx))?;
+    tx.send(UserWorkerMsgs::SendRequest(key, request, result_tx))?;
 
     let result = result_rx.await;
     if result.is_err() {
@@ -321,8 +692,14 @@ pub async fn op_user_worker_fetch_send(
         ));
     }
 
-    let result = result.unwrap();
+    Ok(into_user_worker_response(&state, result.unwrap()))
+}
 
+// hands the response's body to JS as a resource
+fn into_user_worker_response(
+    state: &Rc<RefCell<OpState>>,
+    result: Response<Body>,
+) -> UserWorkerResponse {
     let mut headers = vec![];
     for (key, value) in result.headers().iter() {
         headers.push((
@@ -352,13 +729,12 @@ pub async fn op_user_worker_fetch_send(
         size,
     });
 
-    let response = UserWorkerResponse {
+    UserWorkerResponse {
         status,
         status_text,
         headers,
         body_rid,
-    };
-    Ok(response)
+    }
 }
 
 // [copied from https://github.com/denoland/deno/blob/v1.31.3/ext/fetch/byte_stream.rs]
@@ -0,0 +1,63 @@
+import {
+    readableStreamForRid,
+    writableStreamForRid,
+} from "ext:deno_web/06_streams.js";
+const core = globalThis.Deno.core;
+const ops = core.ops;
+
+// sends the request with `send(requestRid)`, which returns the response
+async function sendRequest(req, send) {
+    const { method, url, headers, body, bodyUsed } = req;
+
+    const headersArray = Array.from(headers.entries());
+    const hasBody = body !== null && !(method === "GET" || method === "HEAD" || bodyUsed);
+
+    const userWorkerReq = {
+        method,
+        url,
+        headers: headersArray,
+        hasBody,
+    };
+
+    const { requestRid, requestBodyRid } = await ops.op_user_worker_fetch_build(userWorkerReq);
+
+    // stream the request body
+    if (hasBody) {
+        let writableStream = writableStreamForRid(requestBodyRid);
+        body.pipeTo(writableStream);
+    }
+
+    const res = await send(requestRid);
+    if (res === null) {
+        return null;
+    }
+
+    const bodyStream = readableStreamForRid(res.bodyRid);
+    return new Response(bodyStream, {
+        headers: res.headers,
+        status: res.status,
+        statusText: res.statusText
+    });
+}
+
+// A user worker bound to this one, called through the pool rather than the
+// public listener.
+class ServiceBinding {
+    constructor(name) {
+        this.name = name;
+    }
+
+    fetch(input, init) {
+        const req = new Request(input, init);
+        return sendRequest(req, (rid) => core.opAsync("op_service_binding_send", this.name, rid));
+    }
+}
+
+// eg: `await EdgeRuntime.services.get("AUTH").fetch("http://auth/verify", { headers })`
+const SUPABASE_SERVICES = {
+    get(name) {
+        return new ServiceBinding(name);
+    },
+};
+
+export { SUPABASE_SERVICES, sendRequest };
@@ -2,20 +2,57 @@ const primordials = globalThis.__bootstrap.primordials;
 const {
     TypeError
 } = primordials;
-import {
-    readableStreamForRid,
-    writableStreamForRid,
-} from "ext:deno_web/06_streams.js";
+import { sendRequest } from "ext:sb_service_bindings/service_bindings.js";
 const core = globalThis.Deno.core;
-const ops = core.ops;
 
 // interface WorkerOptions {
 //     servicePath: string;
 //     memoryLimitMb?: number;
 //     workerTimeoutMs?: number;
+//     requestTimeoutMs?: number;
+//     maxConcurrentRequests?: number;
+//     backpressure?: { maxInFlight?: number, maxHeapUsagePct?: number };
+//     bootRetries?: number;
+//     bootRetryBackoffMs?: number;
 //     noModuleCache?: boolean;
-//     importMapPath?: string;
+//     importMapPath?: string; // relative to servicePath, defaults to its import_map.json
+//     authTokens?: string; // `token@host` pairs separated by `;`, for private remote modules
 //     envVars?: Array<any>
+//     serviceName?: string;
+//     routingHeaders?: Array<[string, string]>;
+//     serviceChecksum?: string;
+//     sourceMaxAgeMs?: number;
+//     typeCheck?: boolean;
+//     priority?: "system" | "high" | "normal" | "batch";
+//     locale?: string; // default locale of Intl and navigator.language, eg: en-US
+//     timezone?: string; // default time zone of Intl and Date's toLocale*String, eg: Europe/Paris
+//     postgres?: { poolSize?: number, maxCheckouts?: number }; // enables EdgeRuntime.postgres
+//     ai?: { maxCalls?: number, maxConcurrent?: number }; // enables EdgeRuntime.ai
+//     jwt?: { signKeys?: string[] }; // keys EdgeRuntime.jwt.sign can use, verifying is always available
+//     storage?: { bucket?: string, prefix?: string }; // enables EdgeRuntime.storage, under the service name by default
+//     bindings?: { [name: string]: UserWorker }; // workers it can call with EdgeRuntime.services.get(name)
+//     userAgent?: string; // of fetch and WebSocket, the main worker's by default
+//     defaultHeaders?: Array<[string, string]>; // added to fetch requests not setting them, on top of the main worker's
+//     netAllow?: string[]; // hosts the worker can reach (eg: "redis.internal:6379", "*.example.com"), any if unset
+//     allowUdp?: boolean; // Deno.listenDatagram
+//     clientCert?: { certChain: string, privateKey: string }; // PEM, presented by fetch to mTLS servers
+//     cryptoSeed?: number; // makes the random values of crypto the same on every run, for tests
+//     allowKeyExport?: boolean; // crypto.subtle.exportKey and wrapKey of private and secret keys, true by default
+//     unstable?: Array<"kv" | "cron" | "ffi">; // unstable APIs the worker can use
+//     autoscale?: { minWorkers?: number, maxWorkers?: number, maxQueueDepth?: number, maxP95LatencyMs?: number, idleTimeoutMs?: number };
+//     eventLoopLagThresholdMs?: number;
+//     terminateOnBlockedEventLoop?: boolean;
+//     terminateOnUnhandledRejection?: boolean;
+//     drainTimeoutMs?: number;
+//     maxWallClockExtensionMs?: number; // lets the worker push back workerTimeoutMs by up to this much with EdgeRuntime.extendDeadline
+//     maxFetchResponseBytes?: number; // reading a larger fetch response throws a RangeError
+//     maxOpenSockets?: number | null; // 256 by default, opening more throws a QuotaExceededError
+//     fetchPolicy?: { connectTimeoutMs?: number | null, readTimeoutMs?: number | null, retries?: number, retryBackoffMs?: number }; // 30s timeouts and no retries by default
+//     audit?: { sampleRate?: number, maxBodyBytes?: number, redactHeaders?: string[] }; // copies requests and responses to the events channel
+//     blobSpill?: { thresholdBytes?: number, maxBytes?: number }; // writes large blobs to disk rather than memory
+//     coalesce?: { varyHeaders?: string[], maxBodyBytes?: number }; // identical concurrent GETs share a response
+//     logs?: { level?: "debug" | "info" | "warn" | "error", ratePerSec?: number, burst?: number }; // console output over the rate is dropped
+//     sticky?: { header?: string, cookie?: string, jwtClaim?: string, sessionTtlMs?: number }; // pins a session to one isolate of an autoscaled worker
 // }
 
 class UserWorker {
@@ -23,44 +60,84 @@ class UserWorker {
         this.key = key;
     }
 
-    async fetch(req) {
-        const { method, url, headers, body, bodyUsed } = req;
-
-        const headersArray = Array.from(headers.entries());
-        const hasBody = !(method === "GET" || method === "HEAD" || bodyUsed);
+    fetch(req) {
+        return sendRequest(req, (rid) => core.opAsync("op_user_worker_fetch_send", this.key, rid));
+    }
 
-        const userWorkerReq = {
-            method,
-            url,
-            headers: headersArray,
-            hasBody,
-        };
+    // bytes sent and received by fetch, Deno.connect and WebSocket, by each
+    // of them: { fetch: { sent, received }, net, webSocket }, null once the
+    // worker is gone
+    netUsage() {
+        return core.opAsync("op_user_worker_net_usage", this.key);
+    }
 
-        const { requestRid, requestBodyRid } = await ops.op_user_worker_fetch_build(userWorkerReq);
+    // sockets the worker has open, by kind:
+    // { connections, datagrams, webSockets, fetches }, null once the worker
+    // is gone
+    openSockets() {
+        return core.opAsync("op_user_worker_open_sockets", this.key);
+    }
 
-        // stream the request body
-        if (hasBody) {
-            let writableStream = writableStreamForRid(requestBodyRid);
-            body.pipeTo(writableStream);
+    // The response to a request to the service at `servicePath` given without
+    // booting a worker: a 403 for the clients its `clientIps` refuse, CORS
+    // preflights from the `cors` settings of its edge-runtime.json, a 404 if
+    // there's no such service, and a 405 for the methods not in its `methods`.
+    // null if the request goes to a worker.
+    static preflight(servicePath, req) {
+        if (!servicePath || servicePath === "") {
+            throw new TypeError("service path must be defined");
         }
-
-        const res = await core.opAsync("op_user_worker_fetch_send", this.key, requestRid);
-
-        const bodyStream = readableStreamForRid(res.bodyRid);
-        return new Response(bodyStream, {
-            headers: res.headers,
-            status: res.status,
-            statusText: res.statusText
-        });
+        return sendRequest(req, (rid) => core.opAsync("op_user_worker_preflight", servicePath, rid));
     }
 
     static async create(opts) {
         const readyOptions = {
             memoryLimitMb: 150,
             workerTimeoutMs: 60 * 1000,
+            requestTimeoutMs: null,
+            maxConcurrentRequests: null,
+            backpressure: null,
+            bootRetries: 0,
+            bootRetryBackoffMs: 100,
             noModuleCache: false,
             importMapPath: null,
+            authTokens: null,
             envVars: [],
+            serviceName: null,
+            routingHeaders: [],
+            serviceChecksum: null,
+            sourceMaxAgeMs: null,
+            typeCheck: false,
+            autoscale: null,
+            priority: "normal",
+            locale: null,
+            timezone: null,
+            clientCert: null,
+            cryptoSeed: null,
+            allowKeyExport: true,
+            netAllow: null,
+            allowUdp: false,
+            postgres: null,
+            ai: null,
+            jwt: null,
+            storage: null,
+            bindings: {},
+            userAgent: null,
+            defaultHeaders: [],
+            unstable: [],
+            eventLoopLagThresholdMs: null,
+            terminateOnBlockedEventLoop: false,
+            terminateOnUnhandledRejection: false,
+            drainTimeoutMs: null,
+            maxWallClockExtensionMs: null,
+            maxFetchResponseBytes: null,
+            maxOpenSockets: 256,
+            fetchPolicy: null,
+            audit: null,
+            blobSpill: null,
+            coalesce: null,
+            sticky: null,
+            logs: null,
             ...opts
         }
 
@@ -70,7 +147,20 @@ class UserWorker {
             throw new TypeError("service path must be defined");
         }
 
-        const key = await core.opAsync("op_user_worker_create", readyOptions);
+        // fail here on unknown locales and time zones, rather than in the worker
+        const { locale, timezone } = readyOptions;
+        if (locale !== null || timezone !== null) {
+            new Intl.DateTimeFormat(locale ?? undefined, { timeZone: timezone ?? undefined });
+        }
+
+        const bindings = Object.entries(readyOptions.bindings ?? {}).map(([name, worker]) => {
+            if (!(worker instanceof UserWorker)) {
+                throw new TypeError(`${name} must be bound to a user worker`);
+            }
+            return [name, worker.key];
+        });
+
+        const key = await core.opAsync("op_user_worker_create", { ...readyOptions, bindings });
 
         return new UserWorker(key);
     }
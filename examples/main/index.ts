@@ -26,18 +26,40 @@ serve(async (req: Request) => {
   const envVarsObj = Deno.env.toObject();
   const envVars = Object.keys(envVarsObj).map(k => [k, envVarsObj[k]]);
   try {
+    // preflights, unknown functions and methods they don't handle are
+    // answered without booting a worker
+    const preflight = await EdgeRuntime.userWorkers.preflight(servicePath, req);
+    if (preflight) {
+      return preflight;
+    }
+
     const worker = await EdgeRuntime.userWorkers.create({
       servicePath,
       memoryLimitMb,
       workerTimeoutMs,
       noModuleCache,
       importMapPath,
-      envVars
+      envVars,
+      routingHeaders: [...req.headers.entries()],
     });
     return worker.fetch(req);
   } catch (e) {
     console.error(e);
-    const error = { msg: e.toString() }
+    if (e.name === "RateLimitError") {
+      const error = { msg: e.message }
+      return new Response(
+          JSON.stringify(error),
+          {
+            status: 429,
+            headers: {
+              "Content-Type": "application/json",
+              "Retry-After": String(Math.ceil(e.retryAfterMs / 1000)),
+            },
+          },
+      )
+    }
+    // module resolution failures come with suggestions and the import chain
+    const error = { msg: e.toString(), diagnostic: e.diagnostic }
     return new Response(
         JSON.stringify(error),
         { status: 500, headers: { "Content-Type": "application/json" } },